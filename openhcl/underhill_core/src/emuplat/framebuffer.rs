@@ -1,7 +1,9 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT License.
 
+use guestmem::GuestMemory;
 use std::convert::Infallible;
+use std::io;
 use std::sync::Arc;
 use video_core::FramebufferControl;
 use video_core::FramebufferFormat;
@@ -39,6 +41,11 @@ async fn unmap(&mut self) {
     async fn set_format(&mut self, format: FramebufferFormat) {
         self.format_send.send(format);
     }
+
+    fn memory(&self) -> io::Result<GuestMemory> {
+        // The framebuffer is owned by the host.
+        Err(io::ErrorKind::Unsupported.into())
+    }
 }
 
 impl ResolveResource<FramebufferHandleKind, SharedFramebufferHandle> for FramebufferRemoteControl {
@@ -345,8 +345,10 @@ async fn launch_workers(
                     vnc_worker_defs::VNC_WORKER_VMSOCKET,
                     VncParameters {
                         listener,
+                        websocket_listener: None,
                         framebuffer,
                         input_send,
+                        auth: Default::default(),
                     },
                 )
                 .await?,
@@ -267,8 +267,12 @@ fn load_linux(params: LoadLinuxParams<'_>) -> Result<VpContext, Error> {
         with_pic: false,
         with_pit: false,
         with_psp: platform_config.general.psp_enabled,
+        with_hpet: false,
         pm_base: crate::worker::PM_BASE,
         acpi_irq: crate::worker::SYSTEM_IRQ_ACPI,
+        pcie_ecam: None,
+        present_vps: None,
+        numa_distances: None,
     };
 
     let acpi_tables = acpi_builder.build_acpi_tables(ACPI_BASE, |mem_layout, dsdt| {
@@ -424,8 +428,12 @@ pub fn write_uefi_config(
         with_pic: false,                          // uefi never runs with pic or pit
         with_pit: false,
         with_psp: platform_config.general.psp_enabled,
+        with_hpet: false,
         pm_base: crate::worker::PM_BASE,
         acpi_irq: crate::worker::SYSTEM_IRQ_ACPI,
+        pcie_ecam: None,
+        present_vps: None,
+        numa_distances: None,
     };
 
     // Build the ACPI tables as specified.
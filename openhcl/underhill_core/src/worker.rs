@@ -1954,8 +1954,12 @@ async fn new_underhill_vm(
                 with_pic: true,    // pcat always runs with pic and pit
                 with_pit: true,
                 with_psp: dps.general.psp_enabled,
+                with_hpet: false,
                 pm_base: PM_BASE,
                 acpi_irq: SYSTEM_IRQ_ACPI,
+                pcie_ecam: None,
+                present_vps: None,
+                numa_distances: None,
             };
 
             let config = firmware_pcat::config::PcatBiosConfig {
@@ -2206,6 +2210,7 @@ async fn new_underhill_vm(
                                 device,
                                 ResolveScsiDeviceHandleParams {
                                     driver_source: &driver_source,
+                                    copy_domain: None,
                                 },
                             )
                             .await?;
@@ -2551,6 +2556,7 @@ async fn new_underhill_vm(
         deps_generic_isa_dma,
         deps_generic_isa_floppy: None,
         deps_generic_pci_bus: None,
+        deps_generic_pcie_root_complex: None,
         deps_generic_pic,
         deps_generic_pit,
         deps_hyperv_firmware_pcat,
@@ -2600,6 +2606,7 @@ async fn new_underhill_vm(
             vmtime: &vmtime_source,
             vmtime_unit: vmtime.handle(),
             doorbell_registration: None,
+            msi_target: None,
             power_event_handler: halt_vps.clone(),
             debug_event_handler: halt_vps.clone(),
         },
@@ -2790,6 +2797,7 @@ async fn new_underhill_vm(
                 &mut chipset_builder,
                 None,
                 None,
+                None,
                 |device_id| {
                     let device = partition
                         .new_virtual_device()
@@ -2962,6 +2970,7 @@ async fn new_underhill_vm(
             client_notify_send: halt_notify_send,
             vtl_guest_memory: [Some(gm.vtl0()), gm.vtl1(), None],
             debugger_rpc,
+            vp_presence_recv: None,
         },
     )
     .context("failed to create partition unit")?;
@@ -3247,6 +3256,10 @@ enum HaltRequest {
                 tracing::info!(vp, "hardware breakpoint");
                 HaltRequest::None
             }
+            HaltReason::GuestPanic { crash_loaded } => {
+                tracing::info!(crash_loaded, "guest panic");
+                HaltRequest::None
+            }
         };
 
         if halt_on_guest_halt {
@@ -18,6 +18,8 @@
 use inspect::Inspect;
 use inspect::InspectMut;
 use memory_range::MemoryRange;
+#[cfg(guest_arch = "x86_64")]
+use pci_core::msi::MsiControl;
 use pci_core::msi::MsiInterruptTarget;
 use std::convert::Infallible;
 use std::sync::Arc;
@@ -290,6 +292,50 @@ fn target(self: Arc<Self>) -> Arc<dyn MsiInterruptTarget> {
     }
 }
 
+/// An [`MsiInterruptTarget`] that delivers MSIs directly to a VTL of the
+/// partition, for devices that are not behind a VPCI bus.
+#[cfg(guest_arch = "x86_64")]
+pub struct PartitionMsiTarget {
+    partition: Arc<dyn HvlitePartition>,
+    vtl: Vtl,
+}
+
+#[cfg(guest_arch = "x86_64")]
+impl PartitionMsiTarget {
+    /// Returns a new target delivering MSIs to `vtl`.
+    pub fn new(partition: Arc<dyn HvlitePartition>, vtl: Vtl) -> Self {
+        Self { partition, vtl }
+    }
+}
+
+#[cfg(guest_arch = "x86_64")]
+impl MsiInterruptTarget for PartitionMsiTarget {
+    fn new_interrupt(&self) -> Box<dyn MsiControl> {
+        Box::new(PartitionMsi {
+            partition: self.partition.clone(),
+            vtl: self.vtl,
+        })
+    }
+}
+
+#[cfg(guest_arch = "x86_64")]
+struct PartitionMsi {
+    partition: Arc<dyn HvlitePartition>,
+    vtl: Vtl,
+}
+
+#[cfg(guest_arch = "x86_64")]
+impl MsiControl for PartitionMsi {
+    fn enable(&mut self, _address: u64, _data: u32) {}
+
+    fn disable(&mut self) {}
+
+    fn signal(&mut self, address: u64, data: u32) {
+        self.partition
+            .request_msi(self.vtl, MsiRequest { address, data });
+    }
+}
+
 struct WrappedVp<'a, T>(&'a mut T);
 
 impl<T: InspectMut> InspectMut for WrappedVp<'_, T> {
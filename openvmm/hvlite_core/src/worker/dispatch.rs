@@ -10,6 +10,8 @@
 use anyhow::Context;
 use cfg_if::cfg_if;
 use chipset_device_resources::IRQ_LINE_SET;
+use chipset_resources::hpet::HpetDeviceHandle;
+use closeable_mutex::CloseableMutex;
 use debug_ptr::DebugPtr;
 use disk_backend::resolve::ResolveDiskParameters;
 use disk_backend::Disk;
@@ -20,6 +22,13 @@
 use futures::FutureExt;
 use futures::StreamExt;
 use futures_concurrency::prelude::*;
+use fw_cfg_resources::FwCfgDeviceHandle;
+use fw_cfg_resources::FwCfgPlatform;
+use fw_cfg_resources::FwCfgRegisterLayout;
+use fw_cfg_resources::FW_CFG_IO_LEN;
+use fw_cfg_resources::FW_CFG_IO_PORT;
+use fw_cfg_resources::FW_CFG_MMIO_BASE;
+use fw_cfg_resources::FW_CFG_MMIO_LEN;
 use guestmem::GuestMemory;
 use guid::Guid;
 use hvdef::Vtl;
@@ -32,8 +41,12 @@
 use hvlite_defs::config::HypervisorConfig;
 use hvlite_defs::config::LoadMode;
 use hvlite_defs::config::MemoryConfig;
+use hvlite_defs::config::NumaConfig;
+use hvlite_defs::config::PcieDeviceConfig;
+use hvlite_defs::config::PcieRootPortConfig;
 use hvlite_defs::config::ProcessorTopologyConfig;
 use hvlite_defs::config::SerialPipes;
+use hvlite_defs::config::SmbiosConfig;
 use hvlite_defs::config::VirtioBus;
 use hvlite_defs::config::VmbusConfig;
 use hvlite_defs::config::VpciDeviceConfig;
@@ -68,10 +81,13 @@
 use pal_async::local::block_with_io;
 use pal_async::task::Spawn;
 use pal_async::task::Task;
+use pal_async::timer::PolledTimer;
 use pal_async::DefaultDriver;
 use pal_async::DefaultPool;
 use pci_core::msi::MsiInterruptSet;
 use pci_core::PciInterruptPin;
+use pvpanic_resources::PvPanicIsaDeviceHandle;
+use pvpanic_resources::PVPANIC_ISA_PORT;
 use scsi_core::ResolveScsiDeviceHandleParams;
 use scsidisk::atapi_scsi::AtapiScsiDisk;
 use scsidisk::SimpleScsiDisk;
@@ -79,12 +95,15 @@
 use state_unit::SavedStateUnit;
 use state_unit::SpawnedUnit;
 use state_unit::StateUnits;
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::io::Read;
 use std::io::Write;
 use std::sync::Arc;
 use std::thread;
 use std::thread::JoinHandle;
+use std::time::Duration;
+use std::time::Instant;
 use storvsp::ScsiControllerDisk;
 use tracing_helpers::ErrorValueExt;
 use virt::ProtoPartition;
@@ -96,12 +115,14 @@
 use virtio::VirtioPciDevice;
 use virtio_serial::VirtioSerialDevice;
 use vm_loader::initial_regs::initial_regs;
+use vm_resource::kind::ChipsetDeviceHandleKind;
 use vm_resource::kind::DiskHandleKind;
 use vm_resource::kind::KeyboardInputHandleKind;
 use vm_resource::kind::MouseInputHandleKind;
 use vm_resource::kind::VirtioDeviceHandle;
 use vm_resource::kind::VmbusDeviceHandleKind;
 use vm_resource::Resource;
+use vm_resource::ResourceId;
 use vm_resource::ResourceResolver;
 use vm_topology::memory::MemoryLayout;
 use vm_topology::processor::aarch64::Aarch64Topology;
@@ -115,6 +136,7 @@
 use vmbus_server::hvsock::HvsockRelay;
 use vmbus_server::HvsockRelayChannel;
 use vmbus_server::VmbusServer;
+use vmcore::device_state::ChangeDeviceState;
 use vmcore::save_restore::SavedStateRoot;
 use vmcore::vm_task::thread::ThreadDriverBackend;
 use vmcore::vm_task::VmTaskDriverSource;
@@ -123,16 +145,20 @@
 use vmcore::vmtime::VmTimeSource;
 use vmgs_broker::resolver::VmgsFileResolver;
 use vmm_core::acpi_builder::AcpiTablesBuilder;
+use vmm_core::acpi_builder::PcieEcamRange;
 use vmm_core::input_distributor::InputDistributor;
 use vmm_core::partition_unit::block_on_vp;
 use vmm_core::partition_unit::Halt;
 use vmm_core::partition_unit::PartitionUnit;
 use vmm_core::partition_unit::PartitionUnitParams;
+use vmm_core::partition_unit::VpPresence;
 use vmm_core::synic::SynicPorts;
 use vmm_core::vmbus_unit::offer_channel_unit;
 use vmm_core::vmbus_unit::offer_vmbus_device_handle_unit;
 use vmm_core::vmbus_unit::ChannelUnit;
 use vmm_core::vmbus_unit::VmbusServerHandle;
+use vmm_core_defs::debug_rpc::DebugRequest;
+use vmm_core_defs::debug_rpc::DebuggerVpState;
 use vmm_core_defs::HaltReason;
 use vmotherboard::options::BaseChipsetDevices;
 use vmotherboard::options::BaseChipsetFoundation;
@@ -148,6 +174,11 @@
 
 const WDAT_PORT: u16 = 0x30;
 
+/// How long to wait for the guest to release a PCIe device before
+/// surprise-removing it. Guests typically wait 5 seconds after an attention
+/// button press before acting on it, to allow the press to be cancelled.
+const PCIE_HOT_REMOVE_TIMEOUT: Duration = Duration::from_secs(30);
+
 /// Creates a thread to run low-performance devices on.
 pub fn new_device_thread() -> (JoinHandle<()>, DefaultDriver) {
     let pool = DefaultPool::new();
@@ -166,9 +197,12 @@ fn from_config(config: Config) -> Self {
             floppy_disks: config.floppy_disks,
             ide_disks: config.ide_disks,
             vpci_devices: config.vpci_devices,
+            pcie_root_ports: config.pcie_root_ports,
+            pcie_devices: config.pcie_devices,
             hypervisor: config.hypervisor,
             memory: config.memory,
             processor_topology: config.processor_topology,
+            numa: config.numa,
             chipset: config.chipset,
             #[cfg(windows)]
             kernel_vmnics: config.kernel_vmnics,
@@ -187,6 +221,7 @@ fn from_config(config: Config) -> Self {
             vmgs_disk: config.vmgs_disk,
             secure_boot_enabled: config.secure_boot_enabled,
             custom_uefi_vars: config.custom_uefi_vars,
+            smbios: config.smbios,
             firmware_event_send: config.firmware_event_send,
             debugger_rpc: config.debugger_rpc,
             vmbus_devices: config.vmbus_devices,
@@ -206,8 +241,11 @@ pub struct Manifest {
     floppy_disks: Vec<FloppyDiskConfig>,
     ide_disks: Vec<IdeDeviceConfig>,
     vpci_devices: Vec<VpciDeviceConfig>,
+    pcie_root_ports: Vec<PcieRootPortConfig>,
+    pcie_devices: Vec<PcieDeviceConfig>,
     memory: MemoryConfig,
     processor_topology: ProcessorTopologyConfig,
+    numa: Option<NumaConfig>,
     hypervisor: HypervisorConfig,
     chipset: BaseChipsetManifest,
     #[cfg(windows)]
@@ -227,6 +265,7 @@ pub struct Manifest {
     vmgs_disk: Option<Resource<DiskHandleKind>>,
     secure_boot_enabled: bool,
     custom_uefi_vars: firmware_uefi_custom_vars::CustomVars,
+    smbios: SmbiosConfig,
     firmware_event_send: Option<mesh::MpscSender<get_resources::ged::FirmwareEvent>>,
     debugger_rpc: Option<mesh::Receiver<vmm_core_defs::debug_rpc::DebugRequest>>,
     vmbus_devices: Vec<(DeviceVtl, Resource<VmbusDeviceHandleKind>)>,
@@ -376,13 +415,16 @@ struct InitializedVm {
     gm: GuestMemory,
     cfg: Manifest,
     mem_layout: MemoryLayout,
+    pcie_layout: Option<PcieLayout>,
     processor_topology: ProcessorTopology,
     igvm_file: Option<IgvmFile>,
     driver_source: VmTaskDriverSource,
 }
 
 trait BuildTopology<T: ArchTopology + Inspect> {
-    fn to_topology(&self) -> anyhow::Result<ProcessorTopology<T>>;
+    /// Builds the topology, assigning VP `n` to NUMA node `vnodes[n]` if
+    /// `vnodes` is set.
+    fn to_topology(&self, vnodes: Option<Vec<u32>>) -> anyhow::Result<ProcessorTopology<T>>;
 }
 
 trait ExtractTopologyConfig {
@@ -408,12 +450,16 @@ fn to_config(&self) -> ProcessorTopologyConfig<X86TopologyConfig> {
                     vm_topology::processor::x86::ApicMode::X2ApicEnabled => X2ApicConfig::Enabled,
                 },
             },
+            max_proc_count: None,
         }
     }
 }
 
 impl BuildTopology<X86Topology> for ProcessorTopologyConfig<X86TopologyConfig> {
-    fn to_topology(&self) -> anyhow::Result<ProcessorTopology<X86Topology>> {
+    fn to_topology(
+        &self,
+        vnodes: Option<Vec<u32>>,
+    ) -> anyhow::Result<ProcessorTopology<X86Topology>> {
         let mut builder = TopologyBuilder::from_host_topology()?;
         builder.apic_id_offset(self.arch.apic_id_offset);
         if let Some(smt) = self.enable_smt {
@@ -432,7 +478,15 @@ fn to_topology(&self) -> anyhow::Result<ProcessorTopology<X86Topology>> {
             X2ApicConfig::Enabled => X2ApicState::Enabled,
         };
         builder.x2apic(x2apic);
-        Ok(builder.build(self.proc_count)?)
+        let vp_count = self.max_proc_count.unwrap_or(self.proc_count);
+        if vp_count < self.proc_count {
+            anyhow::bail!("the maximum processor count is less than the processor count");
+        }
+        if let Some(vnodes) = vnodes {
+            check_vnode_count(&vnodes, vp_count)?;
+            builder.vnodes(vnodes);
+        }
+        Ok(builder.build(vp_count)?)
     }
 }
 
@@ -449,12 +503,16 @@ fn to_config(&self) -> ProcessorTopologyConfig<Aarch64TopologyConfig> {
                     gic_redistributors_base: self.gic_redistributors_base(),
                 }),
             },
+            max_proc_count: None,
         }
     }
 }
 
 impl BuildTopology<Aarch64Topology> for ProcessorTopologyConfig<Aarch64TopologyConfig> {
-    fn to_topology(&self) -> anyhow::Result<ProcessorTopology<Aarch64Topology>> {
+    fn to_topology(
+        &self,
+        vnodes: Option<Vec<u32>>,
+    ) -> anyhow::Result<ProcessorTopology<Aarch64Topology>> {
         let gic = if let Some(gic_config) = &self.arch.gic_config {
             GicInfo {
                 gic_distributor_base: gic_config.gic_distributor_base,
@@ -476,10 +534,86 @@ fn to_topology(&self) -> anyhow::Result<ProcessorTopology<Aarch64Topology>> {
         } else {
             builder.vps_per_socket(self.proc_count);
         }
+        if let Some(vnodes) = vnodes {
+            check_vnode_count(&vnodes, self.proc_count)?;
+            builder.vnodes(vnodes);
+        }
         Ok(builder.build(self.proc_count)?)
     }
 }
 
+fn check_vnode_count(vnodes: &[u32], vp_count: u32) -> anyhow::Result<()> {
+    if vnodes.len() != vp_count as usize {
+        anyhow::bail!(
+            "the numa configuration assigns {} processors to nodes, but there are {vp_count}",
+            vnodes.len()
+        );
+    }
+    Ok(())
+}
+
+/// Returns the NUMA node of each VP, indexed by VP index.
+fn numa_vnodes(numa: &NumaConfig) -> anyhow::Result<Vec<u32>> {
+    let mut vnodes = Vec::new();
+    for (vnode, node) in numa.nodes.iter().enumerate() {
+        for &vp in &node.vps {
+            let vp = vp as usize;
+            if vnodes.len() <= vp {
+                vnodes.resize(vp + 1, None);
+            }
+            if vnodes[vp].replace(vnode as u32).is_some() {
+                anyhow::bail!("processor {vp} is assigned to more than one numa node");
+            }
+        }
+    }
+    vnodes
+        .into_iter()
+        .enumerate()
+        .map(|(vp, vnode)| vnode.with_context(|| format!("processor {vp} has no numa node")))
+        .collect()
+}
+
+/// Returns the matrix of distances between NUMA nodes, where `[i][j]` is the
+/// distance from node `i` to node `j`.
+fn numa_distances(numa: &NumaConfig) -> anyhow::Result<Vec<Vec<u8>>> {
+    use acpi_spec::slit::SLIT_LOCAL_DISTANCE;
+    const DEFAULT_REMOTE_DISTANCE: u8 = 20;
+
+    let count = numa.nodes.len();
+    let mut distances = vec![vec![None; count]; count];
+    for &(from, to, distance) in &numa.distances {
+        let (from, to) = (from as usize, to as usize);
+        if from >= count || to >= count {
+            anyhow::bail!("numa distance from node {from} to node {to} is for a missing node");
+        }
+        if from == to && distance != SLIT_LOCAL_DISTANCE {
+            anyhow::bail!("numa distance from node {from} to itself must be {SLIT_LOCAL_DISTANCE}");
+        }
+        if from != to && distance <= SLIT_LOCAL_DISTANCE {
+            anyhow::bail!(
+                "numa distance from node {from} to node {to} must be greater than {SLIT_LOCAL_DISTANCE}"
+            );
+        }
+        distances[from][to] = Some(distance);
+    }
+
+    Ok((0..count)
+        .map(|from| {
+            (0..count)
+                .map(|to| {
+                    distances[from][to]
+                        .or(distances[to][from])
+                        .unwrap_or(if from == to {
+                            SLIT_LOCAL_DISTANCE
+                        } else {
+                            DEFAULT_REMOTE_DISTANCE
+                        })
+                })
+                .collect()
+        })
+        .collect())
+}
+
 /// A VM that has been loaded and can be run.
 ///
 /// Most new state should be added to [`LoadedVmInner`].
@@ -513,6 +647,9 @@ struct LoadedVmInner {
     memory_cfg: MemoryConfig,
     mem_layout: MemoryLayout,
     processor_topology: ProcessorTopology,
+    numa_cfg: Option<NumaConfig>,
+    /// The distances between NUMA nodes, if a NUMA topology is configured.
+    numa_distances: Option<Vec<Vec<u8>>>,
     hypervisor_cfg: HypervisorConfig,
     vmbus_redirect: bool,
     vmbus_devices: Vec<SpawnedUnit<ChannelUnit<dyn VmbusDevice>>>,
@@ -531,6 +668,22 @@ struct LoadedVmInner {
     /// ((device, function), interrupt)
     #[cfg_attr(not(guest_arch = "x86_64"), allow(dead_code))]
     pci_legacy_interrupts: Vec<((u8, Option<u8>), u32)>,
+    #[cfg_attr(not(guest_arch = "x86_64"), allow(dead_code))]
+    pcie_layout: Option<PcieLayout>,
+    with_hpet: bool,
+    #[cfg_attr(not(guest_arch = "x86_64"), allow(dead_code))]
+    with_pvpanic: bool,
+    smbios: SmbiosConfig,
+    pcie_root_complex: Option<Arc<CloseableMutex<pcie::GenericPcieRootComplex>>>,
+    /// The processor hotplug controller, if processors can be hot-added.
+    cpu_hotplug: Option<Arc<CloseableMutex<chipset::cpu_hotplug::CpuHotplugDevice>>>,
+    /// The number of processors present at boot.
+    boot_vp_count: u32,
+    /// The memory hotplug controller, if RAM can be hot-added.
+    memory_hotplug: Option<Arc<CloseableMutex<chipset::memory_hotplug::MemoryHotplugDevice>>>,
+    /// The VMM's fw_cfg support, if there is a fw_cfg device. Its generated
+    /// files are updated when the firmware is loaded.
+    fw_cfg: Option<FwCfgPlatform>,
     firmware_event_send: Option<mesh::MpscSender<get_resources::ged::FirmwareEvent>>,
 
     load_mode: LoadMode,
@@ -538,6 +691,9 @@ struct LoadedVmInner {
     next_igvm_file: Option<IgvmFile>,
     _vmgs_task: Option<Task<()>>,
     vmgs_client_inspect_handle: Option<vmgs_broker::VmgsClient>,
+    /// Debugger requests, and the channel to forward the ones that don't need
+    /// the rest of the VM to the partition unit.
+    debugger_rpc: Option<(mesh::Receiver<DebugRequest>, mesh::Sender<DebugRequest>)>,
 }
 
 fn choose_hypervisor() -> anyhow::Result<Hypervisor> {
@@ -756,7 +912,23 @@ async fn new_with_hypervisor<P, H>(
             None
         };
 
-        let processor_topology = cfg.processor_topology.to_topology()?;
+        let processor_topology = cfg
+            .processor_topology
+            .to_topology(cfg.numa.as_ref().map(numa_vnodes).transpose()?)?;
+        let boot_vp_count = cfg.processor_topology.proc_count;
+        let with_cpu_hotplug = processor_topology.vp_count() > boot_vp_count;
+        if with_cpu_hotplug
+            && !(cfg!(guest_arch = "x86_64")
+                && matches!(
+                    cfg.load_mode,
+                    LoadMode::Linux {
+                        custom_dsdt: None,
+                        ..
+                    }
+                ))
+        {
+            anyhow::bail!("processor hotplug is only supported for x86 Linux direct boot");
+        }
 
         let proto = hypervisor
             .new_partition(virt::ProtoPartitionConfig {
@@ -804,14 +976,72 @@ async fn new_with_hypervisor<P, H>(
         };
 
         // Choose the memory layout of the VM.
-        let mem_layout = MemoryLayout::new(
-            physical_address_size,
-            cfg.memory.mem_size,
-            &cfg.memory.mmio_gaps,
-            vtl2_range,
-        )
+        let mut mem_layout = if let Some(numa) = &cfg.numa {
+            let node_sizes = numa
+                .nodes
+                .iter()
+                .map(|node| node.mem_size)
+                .collect::<Vec<_>>();
+            if node_sizes
+                .iter()
+                .try_fold(0u64, |total, &size| total.checked_add(size))
+                != Some(cfg.memory.mem_size)
+            {
+                anyhow::bail!("the memory of the numa nodes must add up to the memory size");
+            }
+            MemoryLayout::new_numa(
+                physical_address_size,
+                &node_sizes,
+                &cfg.memory.mmio_gaps,
+                vtl2_range,
+            )
+        } else {
+            MemoryLayout::new(
+                physical_address_size,
+                cfg.memory.mem_size,
+                &cfg.memory.mmio_gaps,
+                vtl2_range,
+            )
+        }
         .context("invalid memory configuration")?;
 
+        // Reserve address space above everything else for hot-added RAM.
+        if let Some(max_mem_size) = cfg.memory.max_mem_size {
+            let hotplug_size = max_mem_size
+                .checked_sub(cfg.memory.mem_size)
+                .context("the maximum memory size is less than the memory size")?;
+            if hotplug_size != 0 {
+                if !(cfg!(guest_arch = "x86_64")
+                    && matches!(
+                        cfg.load_mode,
+                        LoadMode::Linux {
+                            custom_dsdt: None,
+                            ..
+                        }
+                    ))
+                {
+                    anyhow::bail!("memory hot-add is only supported for x86 Linux direct boot");
+                }
+                let slot_size = chipset::memory_hotplug::slot_size(hotplug_size);
+                mem_layout = mem_layout
+                    .with_hotplug_range(hotplug_size.next_multiple_of(slot_size), slot_size)
+                    .context("invalid memory hotplug configuration")?;
+            }
+        }
+
+        let pcie_layout = if cfg.chipset.with_generic_pcie_root_complex {
+            Some(PcieLayout::new(
+                &mem_layout,
+                cfg.pcie_root_ports.len(),
+                cfg.chipset.with_generic_pci_bus,
+            )?)
+        } else {
+            if !cfg.pcie_root_ports.is_empty() || !cfg.pcie_devices.is_empty() {
+                anyhow::bail!("pcie root ports require the pcie root complex");
+            }
+            None
+        };
+
         let mut memory_builder = GuestMemoryBuilder::new();
         memory_builder = memory_builder
             .existing_backing(shared_memory)
@@ -827,6 +1057,11 @@ async fn new_with_hypervisor<P, H>(
                 matches!(cfg.load_mode, LoadMode::Pcat { .. }) || cfg.chipset.with_hyperv_vga,
             );
 
+        if let Some(numa) = &cfg.numa {
+            memory_builder = memory_builder
+                .host_numa_nodes(numa.nodes.iter().map(|node| node.host_node).collect());
+        }
+
         #[cfg(all(windows, feature = "virt_whp"))]
         if !cfg.vpci_resources.is_empty() {
             memory_builder = memory_builder.pin_mappings(true);
@@ -913,6 +1148,7 @@ async fn new_with_hypervisor<P, H>(
             gm,
             cfg,
             mem_layout,
+            pcie_layout,
             processor_topology,
             igvm_file,
             driver_source,
@@ -940,11 +1176,14 @@ async fn load(
             gm,
             cfg,
             mem_layout,
+            pcie_layout,
             processor_topology,
             igvm_file,
             driver_source,
         } = self;
 
+        let numa_distances = cfg.numa.as_ref().map(numa_distances).transpose()?;
+
         let mut resolver = ResourceResolver::new();
 
         let (vmgs_client, vmgs_task) = if let Some(vmgs_file) = cfg.vmgs_disk {
@@ -983,6 +1222,45 @@ async fn load(
 
         resolver.add_resolver(vmm_core::platform_resolvers::HaltResolver(halt_vps.clone()));
 
+        let with_hpet = cfg.chipset_devices.iter().any(|dev| {
+            dev.resource.id() == <HpetDeviceHandle as ResourceId<ChipsetDeviceHandleKind>>::ID
+        });
+        let with_pvpanic = cfg.chipset_devices.iter().any(|dev| {
+            dev.resource.id() == <PvPanicIsaDeviceHandle as ResourceId<ChipsetDeviceHandleKind>>::ID
+        });
+
+        // The fw_cfg device must be described in the ACPI tables or device
+        // tree, which the VMM only provides for Linux direct boot and UEFI.
+        let with_fw_cfg = cfg.chipset_devices.iter().any(|dev| {
+            dev.resource.id() == <FwCfgDeviceHandle as ResourceId<ChipsetDeviceHandleKind>>::ID
+        });
+        if with_fw_cfg
+            && !matches!(
+                cfg.load_mode,
+                LoadMode::Linux {
+                    custom_dsdt: None,
+                    ..
+                } | LoadMode::Uefi { .. }
+            )
+        {
+            anyhow::bail!("fw_cfg is only supported for Linux direct boot and UEFI");
+        }
+
+        // The fw_cfg device must also use the register layout that the VMM
+        // describes, and exposes the files that the VMM generates.
+        let fw_cfg = with_fw_cfg.then(|| {
+            let fw_cfg = FwCfgPlatform {
+                register_layout: if cfg!(guest_arch = "x86_64") {
+                    FwCfgRegisterLayout::IoPort
+                } else {
+                    FwCfgRegisterLayout::Mmio
+                },
+                firmware_files: Default::default(),
+            };
+            resolver.add_resolver(fw_cfg.clone());
+            fw_cfg
+        });
+
         // Save the serial handles for restart.
         //
         // TODO: instead, take the handles back from the serial device and input threads.
@@ -1000,6 +1278,7 @@ async fn load(
         ));
 
         let mapper = memory_manager.device_memory_mapper();
+        let shared_guest_ram = memory_manager.shared_guest_ram();
 
         #[cfg_attr(not(guest_arch = "x86_64"), allow(unused_mut))]
         let mut deps_hyperv_firmware_pcat = None;
@@ -1102,8 +1381,12 @@ async fn load(
                             with_pic: cfg.chipset.with_generic_pic,
                             with_pit: cfg.chipset.with_generic_pit,
                             with_psp: cfg.chipset.with_generic_psp,
+                            with_hpet,
                             pm_base: PM_BASE,
                             acpi_irq: SYSTEM_IRQ_ACPI,
+                            pcie_ecam: None,
+                            present_vps: None,
+                            numa_distances: None,
                         };
                         let srat = acpi_tables_builder.build_srat();
                         firmware_pcat::config::PcatBiosConfig {
@@ -1133,22 +1416,44 @@ async fn load(
                                 })
                             },
                             num_lock_enabled: false,
-                            // TODO: these are all very bogus values, and need to be swapped out with something better
-                            smbios: firmware_pcat::config::SmbiosConstants {
-                                bios_guid: Guid {
-                                    data1: 0xC4066C45,
-                                    data2: 0x503D,
-                                    data3: 0x40E8,
-                                    data4: [0xB1, 0x5C, 0x31, 0x26, 0x4E, 0x5F, 0xE1, 0xD9],
-                                },
-                                system_serial_number: "9583-9572-9874-4843-7295-1653-92".into(),
-                                base_board_serial_number: "9583-9572-9874-4843-7295-1653-92".into(),
-                                chassis_serial_number: "9583-9572-9874-4843-7295-1653-92".into(),
-                                chassis_asset_tag: "9583-9572-9874-4843-7295-1653-92".into(),
-                                bios_lock_string: "00000000000000000000000000000000".into(),
-                                processor_manufacturer: b"\0".to_vec(),
-                                processor_version: b"\0".to_vec(),
-                                cpu_info_bundle: None,
+                            // TODO: the defaults are all very bogus values, and need to be swapped out with something better
+                            smbios: {
+                                let smbios = &cfg.smbios;
+                                let string = |s: &Option<String>, default: &[u8]| {
+                                    s.as_ref()
+                                        .map_or_else(|| default.to_vec(), |s| s.as_bytes().to_vec())
+                                };
+                                firmware_pcat::config::SmbiosConstants {
+                                    bios_guid: smbios.system_uuid.unwrap_or(Guid {
+                                        data1: 0xC4066C45,
+                                        data2: 0x503D,
+                                        data3: 0x40E8,
+                                        data4: [0xB1, 0x5C, 0x31, 0x26, 0x4E, 0x5F, 0xE1, 0xD9],
+                                    }),
+                                    system_serial_number: string(
+                                        &smbios.system_serial_number,
+                                        b"9583-9572-9874-4843-7295-1653-92",
+                                    ),
+                                    base_board_serial_number: string(
+                                        &smbios.baseboard_serial_number,
+                                        b"9583-9572-9874-4843-7295-1653-92",
+                                    ),
+                                    chassis_serial_number: string(
+                                        &smbios.chassis_serial_number,
+                                        b"9583-9572-9874-4843-7295-1653-92",
+                                    ),
+                                    chassis_asset_tag: string(
+                                        &smbios.chassis_asset_tag,
+                                        b"9583-9572-9874-4843-7295-1653-92",
+                                    ),
+                                    bios_lock_string: "00000000000000000000000000000000".into(),
+                                    processor_manufacturer: string(
+                                        &smbios.processor_manufacturer,
+                                        b"\0",
+                                    ),
+                                    processor_version: string(&smbios.processor_version, b"\0"),
+                                    cpu_info_bundle: None,
+                                }
                             },
                         }
                     },
@@ -1227,6 +1532,7 @@ async fn load(
                                 disk_type,
                                 ResolveScsiDeviceHandleParams {
                                     driver_source: &driver_source,
+                                    copy_domain: None,
                                 },
                             )
                             .await
@@ -1409,6 +1715,29 @@ async fn load(
                 pio_data: pci_bus::standard_x86_io_ports::DATA_START,
             });
 
+        // The PCIe root complex is only supported on x86 (see `PcieLayout`).
+        #[cfg(guest_arch = "x86_64")]
+        let deps_generic_pcie_root_complex =
+            pcie_layout.map(|layout| dev::GenericPcieRootComplexDeps {
+                ecam_base: layout.ecam.start(),
+                start_bus: 0,
+                end_bus: layout.end_bus,
+                root_ports: cfg
+                    .pcie_root_ports
+                    .iter()
+                    .map(|port| dev::GenericPcieRootPortDeps {
+                        name: port.name.clone(),
+                        bus_id: vmotherboard::BusId::new(&port.name),
+                    })
+                    .collect(),
+                msi_target: Box::new(crate::partition::PartitionMsiTarget::new(
+                    partition.clone(),
+                    Vtl::Vtl0,
+                )),
+            });
+        #[cfg(not(guest_arch = "x86_64"))]
+        let deps_generic_pcie_root_complex = None;
+
         let deps_generic_pic = (cfg.chipset.with_generic_pic).then_some(dev::GenericPicDeps {});
 
         let deps_generic_pit = (cfg.chipset.with_generic_pit).then_some(dev::GenericPitDeps {});
@@ -1494,6 +1823,7 @@ async fn load(
                 deps_generic_isa_dma,
                 deps_generic_isa_floppy,
                 deps_generic_pci_bus,
+                deps_generic_pcie_root_complex,
                 deps_generic_pic,
                 deps_generic_pit,
                 deps_generic_psp,
@@ -1534,6 +1864,13 @@ async fn load(
                 vmtime: &vmtime_source,
                 vmtime_unit: vmtime.handle(),
                 doorbell_registration: partition.clone().into_doorbell_registration(Vtl::Vtl0),
+                #[cfg(guest_arch = "x86_64")]
+                msi_target: Some(Arc::new(crate::partition::PartitionMsiTarget::new(
+                    partition.clone(),
+                    Vtl::Vtl0,
+                ))),
+                #[cfg(not(guest_arch = "x86_64"))]
+                msi_target: None,
             },
             base_chipset_devices,
         )
@@ -1560,6 +1897,57 @@ async fn load(
                 })?;
         }
 
+        // Processors beyond those present at boot can be hot-added.
+        let boot_vp_count = cfg.processor_topology.proc_count;
+        let (cpu_hotplug, vp_presence_recv) = if processor_topology.vp_count() > boot_vp_count {
+            // Keep the processors that are not present at boot from running
+            // until they are hot-added.
+            let (vp_presence, vp_presence_recv) = VpPresence::new();
+            for vp in boot_vp_count..processor_topology.vp_count() {
+                vp_presence.set_present(VpIndex::new(vp), false);
+            }
+            let cpu_hotplug = chipset_builder
+                .arc_mutex_device("cpu_hotplug")
+                .add(|services| {
+                    chipset::cpu_hotplug::CpuHotplugDevice::new(
+                        chipset::cpu_hotplug::CPU_HOTPLUG_IO_PORT,
+                        processor_topology.vp_count(),
+                        boot_vp_count,
+                        services.new_line(
+                            chipset_device_resources::GPE0_LINE_SET,
+                            "notify",
+                            chipset::cpu_hotplug::CPU_HOTPLUG_GPE0_LINE,
+                        ),
+                        Box::new(move |vp, present| {
+                            vp_presence.set_present(VpIndex::new(vp), present)
+                        }),
+                    )
+                })?;
+            (Some(cpu_hotplug), Some(vp_presence_recv))
+        } else {
+            (None, None)
+        };
+
+        let memory_hotplug = if let Some(range) = mem_layout.hotplug_range() {
+            Some(
+                chipset_builder
+                    .arc_mutex_device("memory_hotplug")
+                    .add(|services| {
+                        chipset::memory_hotplug::MemoryHotplugDevice::new(
+                            chipset::memory_hotplug::MEMORY_HOTPLUG_IO_PORT,
+                            range,
+                            services.new_line(
+                                chipset_device_resources::GPE0_LINE_SET,
+                                "notify",
+                                chipset::memory_hotplug::MEMORY_HOTPLUG_GPE0_LINE,
+                            ),
+                        )
+                    })?,
+            )
+        } else {
+            None
+        };
+
         // Add the GIC.
         #[cfg(guest_arch = "aarch64")]
         chipset_builder.add_external_line_target(
@@ -1586,6 +1974,8 @@ async fn load(
             resolver.add_resolver(framebuffer);
         }
 
+        let pcie_root_complex = base_chipset_device_interfaces.pcie_root_complex;
+
         let pci_inta_line = {
             const PCI_LEGACY_INTA_IRQ: u32 = 11;
             const PCI_INTA_IRQ: u32 = 16;
@@ -1865,6 +2255,7 @@ async fn add_virtio_vpci(
                         &mut chipset_builder,
                         partition.clone().into_doorbell_registration(vtl),
                         Some(&mapper),
+                        shared_guest_ram.as_ref().map(|ram| ram as _),
                         |device_id| {
                             let hv_device = partition.new_virtual_device(
                                 match dev_cfg.vtl {
@@ -1931,6 +2322,28 @@ async fn add_virtio_vpci(
             }
         }
 
+        // Add devices behind PCIe root ports.
+        #[cfg(guest_arch = "x86_64")]
+        if !cfg.pcie_devices.is_empty() {
+            let msi_target =
+                crate::partition::PartitionMsiTarget::new(partition.clone(), Vtl::Vtl0);
+            for dev_cfg in cfg.pcie_devices {
+                vmm_core::device_builder::build_pcie_device(
+                    &driver_source,
+                    &resolver,
+                    &gm,
+                    &dev_cfg.port_name,
+                    dev_cfg.resource,
+                    &mut chipset_builder,
+                    partition.clone().into_doorbell_registration(Vtl::Vtl0),
+                    Some(&mapper),
+                    shared_guest_ram.as_ref().map(|ram| ram as _),
+                    &msi_target,
+                )
+                .await?;
+            }
+        }
+
         // Add vmbus devices.
         let mut vmbus_devices = Vec::new();
         for (vtl, resource) in cfg.vmbus_devices {
@@ -2130,6 +2543,16 @@ async fn add_virtio_vpci(
         let (chipset, devices) = chipset_builder.build()?;
         let chipset = vmm_core::vmotherboard_adapter::ChipsetPlusSynic::new(synic.clone(), chipset);
 
+        // Debugger requests go to the partition unit, except for the ones that
+        // need the rest of the VM, which are handled by `LoadedVm::run`.
+        let (debugger_rpc, partition_debugger_rpc) = match cfg.debugger_rpc {
+            Some(recv) => {
+                let (send, partition_recv) = mesh::channel();
+                (Some((recv, send)), Some(partition_recv))
+            }
+            None => (None, None),
+        };
+
         let (partition_unit, vp_runners) = PartitionUnit::new(
             driver_source.simple(),
             state_units
@@ -2147,7 +2570,8 @@ async fn add_virtio_vpci(
                     None,
                     cfg.hypervisor.with_vtl2.is_some().then_some(&gm),
                 ],
-                debugger_rpc: cfg.debugger_rpc,
+                debugger_rpc: partition_debugger_rpc,
+                vp_presence_recv,
             },
         )
         .context("failed to create partition unit")?;
@@ -2206,6 +2630,8 @@ async fn add_virtio_vpci(
                 memory_cfg: cfg.memory,
                 mem_layout,
                 processor_topology,
+                numa_cfg: cfg.numa,
+                numa_distances,
                 vmbus_redirect,
                 input_distributor,
                 vtl2_framebuffer_gpa_base,
@@ -2221,10 +2647,25 @@ async fn add_virtio_vpci(
                 virtio_mmio_count,
                 virtio_mmio_irq,
                 pci_legacy_interrupts,
+                pcie_layout,
+                with_hpet,
+                with_pvpanic,
+                // Fix the UUID for the life of the VM so that it is stable
+                // across resets.
+                smbios: SmbiosConfig {
+                    system_uuid: Some(cfg.smbios.system_uuid.unwrap_or_else(Guid::new_random)),
+                    ..cfg.smbios
+                },
+                pcie_root_complex,
+                cpu_hotplug,
+                boot_vp_count,
+                memory_hotplug,
+                fw_cfg,
                 igvm_file,
                 next_igvm_file: None,
                 _vmgs_task: vmgs_task,
                 vmgs_client_inspect_handle,
+                debugger_rpc,
             },
         };
 
@@ -2241,6 +2682,245 @@ async fn add_virtio_vpci(
 }
 
 impl LoadedVmInner {
+    fn inspect_vm(&self, resp: &mut inspect::Response<'_>) {
+        resp.field("memory", &self.memory_manager)
+            .field("memory_layout", &self.mem_layout)
+            .field("resolver", &self.resolver)
+            .field("vmgs", &self.vmgs_client_inspect_handle);
+    }
+
+    /// Hot-adds a device to an empty PCIe root port.
+    async fn add_pcie_device(&mut self, config: PcieDeviceConfig) -> anyhow::Result<()> {
+        let PcieDeviceConfig {
+            port_name,
+            resource,
+        } = config;
+        let root_complex = self
+            .pcie_root_complex
+            .as_ref()
+            .context("no pcie root complex")?;
+
+        #[cfg(guest_arch = "x86_64")]
+        {
+            let msi_target =
+                crate::partition::PartitionMsiTarget::new(self.partition.clone(), Vtl::Vtl0);
+            vmm_core::device_builder::hot_add_pcie_device(
+                &self.driver_source,
+                &self.resolver,
+                &self.gm,
+                root_complex,
+                &port_name,
+                resource,
+                self.partition.clone().into_doorbell_registration(Vtl::Vtl0),
+                Some(&self.memory_manager.device_memory_mapper()),
+                self.memory_manager
+                    .shared_guest_ram()
+                    .as_ref()
+                    .map(|ram| ram as _),
+                &msi_target,
+            )
+            .await
+        }
+        // The root complex is only created on x86 (see `PcieLayout`).
+        #[cfg(not(guest_arch = "x86_64"))]
+        {
+            let _ = (root_complex, port_name, resource);
+            anyhow::bail!("pcie hot-plug is not supported on this architecture")
+        }
+    }
+
+    /// Starts removing a hot-added device from a PCIe root port.
+    ///
+    /// The guest is asked to release the device, and the returned future
+    /// finishes the removal once the guest powers off the slot. If it does not
+    /// do so within [`PCIE_HOT_REMOVE_TIMEOUT`], the device is
+    /// surprise-removed. The future does not borrow `self`, so that the caller
+    /// can wait on it without blocking the worker loop.
+    fn remove_pcie_device(
+        &self,
+        port_name: String,
+    ) -> anyhow::Result<impl std::future::Future<Output = anyhow::Result<()>> + Send + 'static>
+    {
+        let root_complex = self
+            .pcie_root_complex
+            .clone()
+            .context("no pcie root complex")?;
+        root_complex.lock().request_hot_remove(&port_name)?;
+        let mut timer = PolledTimer::new(&self.driver_source.simple());
+        Ok(async move {
+            let deadline = Instant::now() + PCIE_HOT_REMOVE_TIMEOUT;
+            while !root_complex.lock().is_slot_powered_off(&port_name)? {
+                if Instant::now() >= deadline {
+                    tracing::warn!(
+                        port_name,
+                        "guest did not release the pcie device, surprise-removing it"
+                    );
+                    break;
+                }
+                timer.sleep(Duration::from_millis(100)).await;
+            }
+            let mut device = root_complex.lock().hot_remove_device(&port_name)?;
+            device.stop().await;
+            Ok(())
+        })
+    }
+
+    /// Hot-adds processor `vp`.
+    fn add_processor(&mut self, vp: u32) -> anyhow::Result<()> {
+        let cpu_hotplug = self
+            .cpu_hotplug
+            .as_ref()
+            .context("processor hotplug is not enabled")?;
+        cpu_hotplug.lock().add(vp)?;
+        Ok(())
+    }
+
+    /// Requests that the guest eject processor `vp`.
+    ///
+    /// This completes once the request has been sent; the processor is removed
+    /// when the guest ejects it.
+    fn remove_processor(&mut self, vp: u32) -> anyhow::Result<()> {
+        let cpu_hotplug = self
+            .cpu_hotplug
+            .as_ref()
+            .context("processor hotplug is not enabled")?;
+        cpu_hotplug.lock().request_remove(vp)?;
+        Ok(())
+    }
+
+    /// Hot-adds `size` bytes of RAM.
+    async fn add_memory(&mut self, size: u64) -> anyhow::Result<()> {
+        let memory_hotplug = self
+            .memory_hotplug
+            .as_ref()
+            .context("memory hot-add is not enabled")?;
+        let slots = memory_hotplug.lock().free_slots(size)?;
+        for (slot, range) in slots {
+            self.memory_manager
+                .add_ram(range)
+                .await
+                .with_context(|| format!("failed to add ram at {range}"))?;
+            memory_hotplug.lock().add(slot)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the guest RAM ranges, including hot-added RAM.
+    fn ram_ranges(&self) -> Vec<MemoryRange> {
+        let mut ranges = self
+            .mem_layout
+            .ram()
+            .iter()
+            .map(|r| r.range)
+            .collect::<Vec<_>>();
+        if let Some(memory_hotplug) = &self.memory_hotplug {
+            ranges.extend(memory_hotplug.lock().present_ranges());
+        }
+        ranges
+    }
+
+    /// Gets the register state of each VP, for guest memory dumps.
+    async fn vp_states(&self) -> anyhow::Result<Vec<DebuggerVpState>> {
+        #[cfg(feature = "gdb")]
+        {
+            let mut states = Vec::new();
+            for vp in 0..self.processor_topology.vp_count() {
+                states.push(*self.partition_unit.get_vp_state(VpIndex::new(vp)).await?);
+            }
+            Ok(states)
+        }
+        #[cfg(not(feature = "gdb"))]
+        {
+            anyhow::bail!("vp state is only available with the gdb feature")
+        }
+    }
+
+    /// Sends an NMI to `vp`. Returns false if this is not supported.
+    fn nmi(&self, vp: VpIndex) -> bool {
+        // Send an NMI MSI to the processor. We could raise LINT1 instead,
+        // which would allow the guest to reconfigure the LINT to do something
+        // other than an NMI. Since this is for diagnostics, that doesn't seem
+        // like what we want.
+        //
+        // AARCH64-TODO: is there an equivalent?
+        #[cfg(guest_arch = "x86_64")]
+        {
+            self.partition.request_msi(
+                Vtl::Vtl0,
+                virt::irqcon::MsiRequest::new_x86(
+                    virt::irqcon::DeliveryMode::NMI,
+                    self.processor_topology.vp_arch(vp).apic_id,
+                    false,
+                    0,
+                    false,
+                ),
+            );
+            true
+        }
+        #[cfg(not(guest_arch = "x86_64"))]
+        {
+            let _ = vp;
+            false
+        }
+    }
+
+    /// Returns the files that QEMU's x86 machines generate for fw_cfg: the
+    /// e820 memory map, and ACPI tables that firmware installs with the
+    /// table loader.
+    #[cfg(guest_arch = "x86_64")]
+    fn fw_cfg_firmware_files(
+        &self,
+        acpi_builder: &AcpiTablesBuilder<'_, X86Topology>,
+        fw_cfg_layout: Option<FwCfgRegisterLayout>,
+    ) -> Vec<(String, Vec<u8>)> {
+        use vmm_core::acpi_builder::AcpiTableLoaderFiles;
+
+        // Each entry is a 64-bit address, a 64-bit length and a 32-bit type.
+        const E820_RAM: u32 = 1;
+        let mut e820 = Vec::new();
+        for range in self.mem_layout.ram() {
+            e820.extend_from_slice(&range.range.start().to_le_bytes());
+            e820.extend_from_slice(&range.range.len().to_le_bytes());
+            e820.extend_from_slice(&E820_RAM.to_le_bytes());
+        }
+
+        let serial_uarts = match self.load_mode {
+            LoadMode::Linux { enable_serial, .. } | LoadMode::Uefi { enable_serial, .. } => {
+                enable_serial
+            }
+            _ => false,
+        };
+        let memory_hotplug_slots = self
+            .memory_hotplug
+            .as_ref()
+            .map(|memory_hotplug| memory_hotplug.lock().slots().collect::<Vec<_>>());
+        let tables = acpi_builder.build_acpi_table_loader(|mem_layout, dsdt| {
+            add_devices_to_dsdt(
+                mem_layout,
+                dsdt,
+                &self.chipset_cfg,
+                serial_uarts,
+                self.virtio_mmio_count,
+                self.virtio_mmio_irq,
+                &self.pci_legacy_interrupts,
+                self.pcie_layout.as_ref(),
+                self.with_pvpanic,
+                fw_cfg_layout,
+                self.cpu_hotplug
+                    .is_some()
+                    .then_some(&self.processor_topology),
+                memory_hotplug_slots.as_deref(),
+            )
+        });
+
+        vec![
+            ("etc/e820".into(), e820),
+            (AcpiTableLoaderFiles::RSDP_FILE.into(), tables.rsdp),
+            (AcpiTableLoaderFiles::TABLES_FILE.into(), tables.tables),
+            (AcpiTableLoaderFiles::LOADER_FILE.into(), tables.loader),
+        ]
+    }
+
     async fn load_firmware(&mut self, vtl2_only: bool) -> anyhow::Result<()> {
         let cache_topology = if cfg!(guest_arch = "aarch64") {
             Some(
@@ -2250,6 +2930,12 @@ async fn load_firmware(&mut self, vtl2_only: bool) -> anyhow::Result<()> {
         } else {
             None
         };
+        // Describe the current set of present processors, which may differ
+        // from the boot set if processors have been hot-added or removed.
+        let present_vps = self
+            .cpu_hotplug
+            .as_ref()
+            .map(|cpu_hotplug| cpu_hotplug.lock().present().to_vec());
         let acpi_builder = AcpiTablesBuilder {
             processor_topology: &self.processor_topology,
             mem_layout: &self.mem_layout,
@@ -2258,9 +2944,22 @@ async fn load_firmware(&mut self, vtl2_only: bool) -> anyhow::Result<()> {
             with_psp: self.chipset_cfg.with_generic_psp,
             with_pic: self.chipset_cfg.with_generic_pic,
             with_pit: self.chipset_cfg.with_generic_pit,
+            with_hpet: self.with_hpet,
             pm_base: PM_BASE,
             acpi_irq: SYSTEM_IRQ_ACPI,
+            pcie_ecam: self.pcie_layout.map(|layout| layout.ecam_range()),
+            present_vps: present_vps.as_deref(),
+            numa_distances: self.numa_distances.as_deref(),
         };
+        let fw_cfg_layout = self.fw_cfg.as_ref().map(|fw_cfg| fw_cfg.register_layout);
+
+        // Regenerate the fw_cfg files that describe the VM to the firmware.
+        #[cfg(guest_arch = "x86_64")]
+        if let Some(fw_cfg) = &self.fw_cfg {
+            fw_cfg
+                .firmware_files
+                .set(self.fw_cfg_firmware_files(&acpi_builder, fw_cfg_layout));
+        }
 
         if vtl2_only {
             assert!(matches!(self.load_mode, LoadMode::Igvm { .. }));
@@ -2283,8 +2982,16 @@ async fn load_firmware(&mut self, vtl2_only: bool) -> anyhow::Result<()> {
                     cmdline,
                     mem_layout: &self.mem_layout,
                 };
-                let regs =
-                    super::vm_loaders::linux::load_linux_x86(&kernel_config, &self.gm, |gpa| {
+                let smbios =
+                    build_smbios_tables(&self.smbios, &self.processor_topology, &self.mem_layout);
+                let memory_hotplug_slots = self
+                    .memory_hotplug
+                    .as_ref()
+                    .map(|memory_hotplug| memory_hotplug.lock().slots().collect::<Vec<_>>());
+                let regs = super::vm_loaders::linux::load_linux_x86(
+                    &kernel_config,
+                    &self.gm,
+                    |gpa| {
                         let tables = if let Some(dsdt) = custom_dsdt {
                             acpi_builder.build_acpi_tables_custom_dsdt(gpa, dsdt)
                         } else {
@@ -2297,6 +3004,13 @@ async fn load_firmware(&mut self, vtl2_only: bool) -> anyhow::Result<()> {
                                     self.virtio_mmio_count,
                                     self.virtio_mmio_irq,
                                     &self.pci_legacy_interrupts,
+                                    self.pcie_layout.as_ref(),
+                                    self.with_pvpanic,
+                                    fw_cfg_layout,
+                                    self.cpu_hotplug
+                                        .is_some()
+                                        .then_some(&self.processor_topology),
+                                    memory_hotplug_slots.as_deref(),
                                 )
                             })
                         };
@@ -2305,7 +3019,9 @@ async fn load_firmware(&mut self, vtl2_only: bool) -> anyhow::Result<()> {
                             rdsp: tables.rdsp,
                             tables: tables.tables,
                         }
-                    })?;
+                    },
+                    &smbios,
+                )?;
 
                 (regs, Vec::new())
             }
@@ -2327,7 +3043,9 @@ async fn load_firmware(&mut self, vtl2_only: bool) -> anyhow::Result<()> {
                     &kernel_config,
                     &self.gm,
                     enable_serial,
+                    fw_cfg_layout.is_some(),
                     &self.processor_topology,
+                    self.numa_distances.as_deref(),
                 )?;
 
                 (regs, Vec::new())
@@ -2345,7 +3063,18 @@ async fn load_firmware(&mut self, vtl2_only: bool) -> anyhow::Result<()> {
             } => {
                 let madt = acpi_builder.build_madt();
                 let srat = acpi_builder.build_srat();
+                let slit = self
+                    .numa_distances
+                    .is_some()
+                    .then(|| acpi_builder.build_slit());
                 let pptt = cache_topology.is_some().then(|| acpi_builder.build_pptt());
+                // The firmware builds its own DSDT, so describe the fw_cfg
+                // device in an SSDT.
+                let ssdt = fw_cfg_layout.map(|register_layout| {
+                    let mut ssdt = dsdt::Dsdt::new_ssdt();
+                    add_fw_cfg_to_dsdt(&mut ssdt, register_layout);
+                    ssdt.to_bytes()
+                });
                 let load_settings = super::vm_loaders::uefi::UefiLoadSettings {
                     debugging: enable_debugging,
                     memory_protections: enable_memory_protections,
@@ -2363,9 +3092,12 @@ async fn load_firmware(&mut self, vtl2_only: bool) -> anyhow::Result<()> {
                     &self.processor_topology,
                     &self.mem_layout,
                     load_settings,
+                    &self.smbios,
                     &madt,
                     &srat,
+                    slit.as_deref(),
                     pptt.as_deref(),
+                    ssdt.as_deref(),
                 )?;
 
                 (regs, Vec::new())
@@ -2384,6 +3116,10 @@ async fn load_firmware(&mut self, vtl2_only: bool) -> anyhow::Result<()> {
             } => {
                 let madt = acpi_builder.build_madt();
                 let srat = acpi_builder.build_srat();
+                let slit = self
+                    .numa_distances
+                    .is_some()
+                    .then(|| acpi_builder.build_slit());
                 const ENTROPY_SIZE: usize = 64;
                 let mut entropy = [0u8; ENTROPY_SIZE];
                 getrandom::getrandom(&mut entropy).unwrap();
@@ -2397,7 +3133,7 @@ async fn load_firmware(&mut self, vtl2_only: bool) -> anyhow::Result<()> {
                     acpi_tables: super::vm_loaders::igvm::AcpiTables {
                         madt: &madt,
                         srat: &srat,
-                        slit: None,
+                        slit: slit.as_deref(),
                         pptt: None,
                     },
                     vtl2_base_address,
@@ -2480,8 +3216,11 @@ pub async fn run(
         enum Event {
             WorkerRpc(Result<WorkerRpc<RestartState>, mesh::RecvError>),
             VmRpc(Result<VmRpc, mesh::RecvError>),
+            Debug(DebugRequest),
         }
 
+        let state_units = self.state_units.inspector();
+
         // Start a task to handle state unit inspections by filtering the worker
         // RPC requests. This is done so that inspect on state units works even
         // during state transitions.
@@ -2507,7 +3246,15 @@ enum Event {
             let event: Event = {
                 let a = rpc.recv().map(Event::VmRpc);
                 let b = worker_rpc.recv().map(Event::WorkerRpc);
-                (a, b).race().await
+                let c = async {
+                    if let Some((recv, _)) = &mut self.inner.debugger_rpc {
+                        if let Some(req) = recv.next().await {
+                            return Event::Debug(req);
+                        }
+                    }
+                    std::future::pending().await
+                };
+                (a, b, c).race().await
             };
 
             match event {
@@ -2543,12 +3290,9 @@ enum Event {
                             }
                         }
                     }
-                    WorkerRpc::Inspect(deferred) => deferred.respond(|resp| {
-                        resp.field("memory", &self.inner.memory_manager)
-                            .field("memory_layout", &self.inner.mem_layout)
-                            .field("resolver", &self.inner.resolver)
-                            .field("vmgs", &self.inner.vmgs_client_inspect_handle);
-                    }),
+                    WorkerRpc::Inspect(deferred) => {
+                        deferred.respond(|resp| self.inner.inspect_vm(resp))
+                    }
                 },
                 Event::VmRpc(Err(_)) => break,
                 Event::VmRpc(Ok(message)) => match message {
@@ -2567,27 +3311,7 @@ enum Event {
                     }
                     VmRpc::Nmi(rpc) => rpc.handle_sync(|vpindex| {
                         if vpindex < self.inner.processor_topology.vp_count() {
-                            // Send an NMI MSI to the processor. We could raise
-                            // LINT1 instead, which would allow the guest to
-                            // reconfigure the LINT to do something other than
-                            // an NMI. Since this is for diagnostics, that
-                            // doesn't seem like what we want.
-                            //
-                            // AARCH64-TODO: is there an equivalent?
-                            #[cfg(guest_arch = "x86_64")]
-                            self.inner.partition.request_msi(
-                                Vtl::Vtl0,
-                                virt::irqcon::MsiRequest::new_x86(
-                                    virt::irqcon::DeliveryMode::NMI,
-                                    self.inner
-                                        .processor_topology
-                                        .vp_arch(VpIndex::new(vpindex))
-                                        .apic_id,
-                                    false,
-                                    0,
-                                    false,
-                                ),
-                            );
+                            self.inner.nmi(VpIndex::new(vpindex));
                         }
                     }),
                     VmRpc::AddVmbusDevice(rpc) => {
@@ -2615,6 +3339,38 @@ enum Event {
                         })
                         .await
                     }
+                    VmRpc::AddPcieDevice(rpc) => {
+                        rpc.handle_failable(|config| {
+                            let this = &mut self;
+                            async move { this.inner.add_pcie_device(config).await }
+                        })
+                        .await
+                    }
+                    VmRpc::RemovePcieDevice(Rpc(port_name, response)) => {
+                        // The guest may take a while to release the device, so
+                        // wait for it in a separate task.
+                        match self.inner.remove_pcie_device(port_name) {
+                            Ok(fut) => driver
+                                .spawn("vmrpc-pcie-hot-remove", async move {
+                                    response.send(fut.await.map_err(RemoteError::new))
+                                })
+                                .detach(),
+                            Err(err) => response.send(Err(RemoteError::new(err))),
+                        }
+                    }
+                    VmRpc::AddProcessor(rpc) => {
+                        rpc.handle_failable_sync(|vp| self.inner.add_processor(vp))
+                    }
+                    VmRpc::RemoveProcessor(rpc) => {
+                        rpc.handle_failable_sync(|vp| self.inner.remove_processor(vp))
+                    }
+                    VmRpc::AddMemory(rpc) => {
+                        rpc.handle_failable(|size| {
+                            let this = &mut self;
+                            async move { this.inner.add_memory(size).await }
+                        })
+                        .await
+                    }
                     VmRpc::ConnectHvsock(Rpc((mut ctx, service_id, vtl), response)) => {
                         if let Some(relay) = self.hvsock_relay(vtl) {
                             let fut = relay.connect(&mut ctx, service_id);
@@ -2663,6 +3419,34 @@ enum Event {
                     VmRpc::WriteMemory(rpc) => rpc.handle_failable_sync(|(gpa, bytes)| {
                         self.inner.gm.write_at(gpa, bytes.as_slice())
                     }),
+                    VmRpc::GetRamRanges(rpc) => rpc.handle_sync(|()| self.inner.ram_ranges()),
+                    VmRpc::GetVpStates(rpc) => {
+                        rpc.handle_failable(|()| self.inner.vp_states()).await
+                    }
+                },
+                Event::Debug(req) => match req {
+                    DebugRequest::GetRamRanges(rpc) => {
+                        rpc.handle_failable_sync(|()| anyhow::Ok(self.inner.ram_ranges()))
+                    }
+                    DebugRequest::Nmi(rpc) => rpc.handle_failable_sync(|vpindex| {
+                        if vpindex >= self.inner.processor_topology.vp_count() {
+                            anyhow::bail!("invalid vp {vpindex}");
+                        }
+                        if !self.inner.nmi(VpIndex::new(vpindex)) {
+                            anyhow::bail!("nmi is not supported");
+                        }
+                        Ok(())
+                    }),
+                    DebugRequest::Reset(rpc) => rpc.handle_failable(|()| self.reset(true)).await,
+                    DebugRequest::Inspect(deferred) => deferred.respond(|resp| {
+                        resp.merge(&state_units);
+                        self.inner.inspect_vm(resp);
+                    }),
+                    req => {
+                        if let Some((_, send)) = &self.inner.debugger_rpc {
+                            send.send(req);
+                        }
+                    }
                 },
             }
         }
@@ -2793,11 +3577,21 @@ async fn serialize(
 
         let manifest = Manifest {
             load_mode: self.inner.load_mode,
-            floppy_disks: vec![], // TODO
-            ide_disks: vec![],    // TODO
-            vpci_devices: vec![], // TODO
+            floppy_disks: vec![],    // TODO
+            ide_disks: vec![],       // TODO
+            vpci_devices: vec![],    // TODO
+            pcie_root_ports: vec![], // TODO
+            pcie_devices: vec![],    // TODO
             memory: self.inner.memory_cfg,
-            processor_topology: self.inner.processor_topology.to_config(),
+            processor_topology: {
+                let mut config = self.inner.processor_topology.to_config();
+                if self.inner.cpu_hotplug.is_some() {
+                    config.max_proc_count = Some(config.proc_count);
+                    config.proc_count = self.inner.boot_vp_count;
+                }
+                config
+            },
+            numa: self.inner.numa_cfg,
             chipset: self.inner.chipset_cfg,
             vmbus: None,      // TODO
             vtl2_vmbus: None, // TODO
@@ -2817,6 +3611,7 @@ async fn serialize(
             format_vmgs: false,     // TODO
             secure_boot_enabled: false, // TODO
             custom_uefi_vars: Default::default(), // TODO
+            smbios: self.inner.smbios,
             firmware_event_send: self.inner.firmware_event_send,
             debugger_rpc: None,       // TODO
             vmbus_devices: vec![],    // TODO
@@ -2853,6 +3648,88 @@ async fn reset(&mut self, reload_firmware: bool) -> anyhow::Result<()> {
     }
 }
 
+/// The guest physical layout of the PCIe root complex.
+///
+/// The root complex claims the start of both MMIO gaps: the ECAM and a 64-bit
+/// window from the high gap, and a 32-bit window (needed for non-prefetchable
+/// BARs behind the root ports) from the low gap.
+#[derive(Debug, Copy, Clone)]
+struct PcieLayout {
+    segment: u16,
+    end_bus: u8,
+    ecam: MemoryRange,
+    low_mmio: MemoryRange,
+    high_mmio: MemoryRange,
+}
+
+impl PcieLayout {
+    /// Space reserved for the ECAM, enough for the maximum number of root
+    /// ports.
+    const ECAM_RESERVED_SIZE: u64 = 64 * 1024 * 1024;
+    const LOW_MMIO_SIZE: u64 = 64 * 1024 * 1024;
+    const HIGH_MMIO_SIZE: u64 = 256 * 1024 * 1024;
+
+    fn new(
+        mem_layout: &MemoryLayout,
+        root_port_count: usize,
+        with_generic_pci_bus: bool,
+    ) -> anyhow::Result<Self> {
+        if !cfg!(guest_arch = "x86_64") {
+            anyhow::bail!("pcie root complex is only supported on x86_64");
+        }
+        if root_port_count > pcie::MAX_ROOT_PORTS {
+            anyhow::bail!(
+                "too many pcie root ports: {root_port_count} > {}",
+                pcie::MAX_ROOT_PORTS
+            );
+        }
+
+        let [low_gap, high_gap, ..] = mem_layout.mmio() else {
+            anyhow::bail!("pcie root complex requires two mmio gaps");
+        };
+        if low_gap.len() <= Self::LOW_MMIO_SIZE
+            || high_gap.len() <= Self::ECAM_RESERVED_SIZE + Self::HIGH_MMIO_SIZE
+        {
+            anyhow::bail!("mmio gaps too small for the pcie root complex");
+        }
+
+        // Bus 0 holds the root ports, and each root port gets one secondary
+        // bus.
+        let end_bus = root_port_count as u8;
+        let ecam_size = (end_bus as u64 + 1) * pcie::ECAM_BUS_SIZE;
+        let high_mmio_start = high_gap.start() + Self::ECAM_RESERVED_SIZE;
+
+        Ok(Self {
+            // Keep segment 0 for the conventional PCI bus, if present, since it
+            // is accessed via the legacy I/O ports.
+            segment: if with_generic_pci_bus { 1 } else { 0 },
+            end_bus,
+            ecam: MemoryRange::new(high_gap.start()..high_gap.start() + ecam_size),
+            low_mmio: MemoryRange::new(low_gap.start()..low_gap.start() + Self::LOW_MMIO_SIZE),
+            high_mmio: MemoryRange::new(high_mmio_start..high_mmio_start + Self::HIGH_MMIO_SIZE),
+        })
+    }
+
+    fn ecam_range(&self) -> PcieEcamRange {
+        PcieEcamRange {
+            ecam_base: self.ecam.start(),
+            segment: self.segment,
+            start_bus: 0,
+            end_bus: self.end_bus,
+        }
+    }
+}
+
+/// Describes the fw_cfg device in `dsdt`, which may be an SSDT.
+fn add_fw_cfg_to_dsdt(dsdt: &mut dsdt::Dsdt, register_layout: FwCfgRegisterLayout) {
+    match register_layout {
+        FwCfgRegisterLayout::IoPort => dsdt.add_fw_cfg(FW_CFG_IO_PORT, FW_CFG_IO_LEN),
+        FwCfgRegisterLayout::Mmio => {
+            dsdt.add_fw_cfg_mmio(FW_CFG_MMIO_BASE as u32, FW_CFG_MMIO_LEN as u32)
+        }
+    }
+}
+
 #[cfg_attr(not(guest_arch = "x86_64"), allow(dead_code))]
 fn add_devices_to_dsdt(
     mem_layout: &MemoryLayout,
@@ -2862,6 +3739,11 @@ fn add_devices_to_dsdt(
     virtio_mmio_count: usize,
     virtio_mmio_irq: u32,
     pci_legacy_interrupts: &[((u8, Option<u8>), u32)], // ((device, function), interrupt)
+    pcie_layout: Option<&PcieLayout>,
+    with_pvpanic: bool,
+    fw_cfg: Option<FwCfgRegisterLayout>,
+    cpu_hotplug_topology: Option<&ProcessorTopology<X86Topology>>,
+    memory_hotplug_slots: Option<&[MemoryRange]>,
 ) {
     dsdt.add_apic();
 
@@ -2884,8 +3766,22 @@ fn add_devices_to_dsdt(
         mem_layout.mmio().len() >= 2,
         "the DSDT describes two MMIO regions"
     );
-    let low_mmio_gap = mem_layout.mmio()[0];
+    let mut low_mmio_gap = mem_layout.mmio()[0];
     let mut high_mmio_space: std::ops::Range<u64> = mem_layout.mmio()[1].into();
+
+    // The PCIe root complex claims the bottom of each MMIO gap.
+    if let Some(layout) = pcie_layout {
+        dsdt.add_pcie(
+            layout.segment,
+            0,
+            layout.end_bus,
+            layout.ecam,
+            &[layout.low_mmio, layout.high_mmio],
+        );
+        low_mmio_gap = MemoryRange::new(layout.low_mmio.end()..low_mmio_gap.end());
+        high_mmio_space.start = layout.high_mmio.end();
+    }
+
     // Device(\_SB.VI00)
     // {
     //     Name(_HID, "LNRO0005")
@@ -2923,4 +3819,121 @@ fn add_devices_to_dsdt(
 
     dsdt.add_vmbus(cfg.with_generic_pci_bus || cfg.with_i440bx_host_pci_bridge);
     dsdt.add_rtc();
+
+    if with_pvpanic {
+        dsdt.add_pvpanic(PVPANIC_ISA_PORT);
+    }
+
+    if let Some(register_layout) = fw_cfg {
+        add_fw_cfg_to_dsdt(dsdt, register_layout);
+    }
+
+    if let Some(processor_topology) = cpu_hotplug_topology {
+        let processors = processor_topology
+            .vps_arch()
+            .map(|vp| {
+                let index = vp.base.vp_index.index();
+                dsdt::HotplugProcessor {
+                    index,
+                    uid: index + 1,
+                    apic_id: vp.apic_id,
+                    hotpluggable: vp.base.vp_index != VpIndex::BSP,
+                }
+            })
+            .collect::<Vec<_>>();
+        dsdt.add_cpu_hotplug(
+            chipset::cpu_hotplug::CPU_HOTPLUG_IO_PORT,
+            chipset::cpu_hotplug::CPU_HOTPLUG_GPE0_LINE as u8,
+            &processors,
+        );
+    }
+
+    if let Some(slots) = memory_hotplug_slots {
+        dsdt.add_memory_hotplug(
+            chipset::memory_hotplug::MEMORY_HOTPLUG_IO_PORT,
+            chipset::memory_hotplug::MEMORY_HOTPLUG_GPE0_LINE as u8,
+            slots,
+        );
+    }
+}
+
+/// Builds the SMBIOS tables for loaders without firmware to provide them.
+#[cfg_attr(not(guest_arch = "x86_64"), allow(dead_code))]
+fn build_smbios_tables<T: ArchTopology>(
+    smbios: &SmbiosConfig,
+    processor_topology: &ProcessorTopology<T>,
+    mem_layout: &MemoryLayout,
+) -> smbios::SmbiosTables {
+    fn string<'a>(s: &'a Option<String>, default: &'a str) -> &'a str {
+        s.as_deref().unwrap_or(default)
+    }
+
+    let mut builder = smbios::SmbiosBuilder::new();
+
+    builder.add_bios(&smbios::BiosInfo {
+        vendor: string(&smbios.bios_vendor, "Microsoft Corporation"),
+        version: string(&smbios.bios_version, "OpenVMM"),
+        release_date: string(&smbios.bios_release_date, ""),
+    });
+    builder.add_system(&smbios::SystemInfo {
+        manufacturer: string(&smbios.system_manufacturer, "Microsoft Corporation"),
+        product_name: string(&smbios.system_product_name, "OpenVMM"),
+        version: string(&smbios.system_version, ""),
+        serial_number: string(&smbios.system_serial_number, ""),
+        uuid: smbios.system_uuid.unwrap_or_default(),
+        sku_number: string(&smbios.system_sku_number, ""),
+        family: string(&smbios.system_family, ""),
+    });
+    let chassis = builder.add_chassis(&smbios::ChassisInfo {
+        manufacturer: string(&smbios.chassis_manufacturer, "Microsoft Corporation"),
+        version: string(&smbios.chassis_version, ""),
+        serial_number: string(&smbios.chassis_serial_number, ""),
+        asset_tag: string(&smbios.chassis_asset_tag, ""),
+        sku_number: string(&smbios.chassis_sku_number, ""),
+    });
+    builder.add_baseboard(
+        &smbios::BaseboardInfo {
+            manufacturer: string(&smbios.baseboard_manufacturer, "Microsoft Corporation"),
+            product: string(&smbios.baseboard_product, "OpenVMM"),
+            version: string(&smbios.baseboard_version, ""),
+            serial_number: string(&smbios.baseboard_serial_number, ""),
+            asset_tag: string(&smbios.baseboard_asset_tag, ""),
+        },
+        chassis,
+    );
+
+    // (cores, threads) per socket.
+    let mut sockets = BTreeMap::<u32, (u16, u16)>::new();
+    for vp in processor_topology.vps() {
+        let topology = processor_topology.vp_topology(vp.vp_index);
+        let (cores, threads) = sockets.entry(topology.socket).or_default();
+        if topology.thread == 0 {
+            *cores += 1;
+        }
+        *threads += 1;
+    }
+    for (socket, (core_count, thread_count)) in sockets {
+        builder.add_processor(&smbios::ProcessorInfo {
+            socket_designation: &format!("CPU {socket}"),
+            manufacturer: string(&smbios.processor_manufacturer, ""),
+            version: string(&smbios.processor_version, ""),
+            family: if cfg!(guest_arch = "aarch64") {
+                smbios::spec::PROCESSOR_FAMILY_ARMV8
+            } else {
+                smbios::spec::PROCESSOR_FAMILY_OTHER
+            },
+            processor_id: 0,
+            core_count,
+            thread_count,
+        });
+    }
+
+    if !smbios.oem_strings.is_empty() {
+        builder.add_oem_strings(&smbios.oem_strings);
+    }
+
+    let ram: Vec<_> = mem_layout.ram().iter().map(|ram| ram.range).collect();
+    builder.add_memory(&ram);
+    builder.add_system_boot();
+    builder.build()
 }
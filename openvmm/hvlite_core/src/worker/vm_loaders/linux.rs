@@ -1,8 +1,13 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT License.
 
+use fw_cfg_resources::FW_CFG_MMIO_BASE;
+use fw_cfg_resources::FW_CFG_MMIO_LEN;
 use guestmem::GuestMemory;
+use hvdef::HV_PAGE_SIZE;
 use loader::importer::Aarch64Register;
+use loader::importer::BootPageAcceptance;
+use loader::importer::ImageLoad;
 use loader::importer::X86Register;
 use loader::linux::AcpiConfig;
 use loader::linux::CommandLineConfig;
@@ -10,6 +15,7 @@
 use loader::linux::InitrdConfig;
 use loader::linux::RegisterConfig;
 use loader::linux::ZeroPageConfig;
+use smbios::SmbiosTables;
 use std::ffi::CString;
 use std::io::Read;
 use std::io::Seek;
@@ -18,6 +24,8 @@
 use vm_topology::memory::MemoryLayout;
 use vm_topology::processor::aarch64::Aarch64Topology;
 use vm_topology::processor::ProcessorTopology;
+use vm_topology::processor::VpIndex;
+use zerocopy::AsBytes;
 
 #[derive(Debug, Error)]
 #[error("device tree error: {0:?}")]
@@ -31,6 +39,10 @@ pub enum Error {
     Loader(#[source] loader::linux::Error),
     #[error("device tree error")]
     Dt(#[source] DtError),
+    #[error("acpi and smbios tables do not fit below 1MB")]
+    TablesTooLarge,
+    #[error("failed to import smbios tables")]
+    Smbios(#[source] anyhow::Error),
 }
 
 #[derive(Debug)]
@@ -53,12 +65,17 @@ pub fn load_linux_x86(
     cfg: &KernelConfig<'_>,
     gm: &GuestMemory,
     acpi_at_gpa: impl FnOnce(u64) -> AcpiTables,
+    smbios: &SmbiosTables,
 ) -> Result<Vec<X86Register>, Error> {
     const GDT_BASE: u64 = 0x1000;
     const CR3_BASE: u64 = 0x4000;
     const ZERO_PAGE_BASE: u64 = 0x2000;
     const CMDLINE_BASE: u64 = 0x3000;
     const ACPI_BASE: u64 = 0xe0000;
+    // Without EFI, Linux finds the SMBIOS entry point by scanning
+    // 0xf0000-0xfffff. Put it in the last page of the BIOS area, which Linux
+    // always reserves, and the tables between the ACPI tables and it.
+    const SMBIOS_ENTRY_POINT_BASE: u64 = 0xff000;
 
     let kaddr: u64 = 2 * 1024 * 1024;
     let mut kernel_file = cfg.kernel;
@@ -98,6 +115,11 @@ pub fn load_linux_x86(
         tables: &acpi_tables.tables,
     };
 
+    let smbios_base = ACPI_BASE + (acpi_len as u64).next_multiple_of(HV_PAGE_SIZE);
+    if smbios_base + smbios.tables().len() as u64 > SMBIOS_ENTRY_POINT_BASE {
+        return Err(Error::TablesTooLarge);
+    }
+
     let zero_page_config = ZeroPageConfig {
         address: ZERO_PAGE_BASE,
         mem_layout: cfg.mem_layout,
@@ -123,6 +145,25 @@ pub fn load_linux_x86(
     )
     .map_err(Error::Loader)?;
 
+    loader
+        .import_pages(
+            SMBIOS_ENTRY_POINT_BASE / HV_PAGE_SIZE,
+            1,
+            "linux-smbios-entry-point",
+            BootPageAcceptance::Exclusive,
+            smbios.entry_point(smbios_base).as_bytes(),
+        )
+        .map_err(Error::Smbios)?;
+    loader
+        .import_pages(
+            smbios_base / HV_PAGE_SIZE,
+            (smbios.tables().len() as u64).div_ceil(HV_PAGE_SIZE),
+            "linux-smbios-tables",
+            BootPageAcceptance::Exclusive,
+            smbios.tables(),
+        )
+        .map_err(Error::Smbios)?;
+
     Ok(loader.initial_regs())
 }
 
@@ -136,7 +177,9 @@ fn build_dt(
     cfg: &KernelConfig<'_>,
     _gm: &GuestMemory,
     enable_serial: bool,
+    with_fw_cfg: bool,
     processor_topology: &ProcessorTopology<Aarch64Topology>,
+    numa_distances: Option<&[Vec<u8>]>,
     initrd_start: u64,
     initrd_end: u64,
 ) -> Result<Vec<u8>, fdt::builder::Error> {
@@ -200,6 +243,9 @@ fn build_dt(
     let p_clock_names = builder.add_string("clock-names")?;
     let p_current_speed = builder.add_string("current-speed")?;
     let p_arm_periph_id = builder.add_string("arm,primecell-periphid")?;
+    let p_numa_node_id = builder.add_string("numa-node-id")?;
+    let p_distance_matrix = builder.add_string("distance-matrix")?;
+    let p_dma_coherent = builder.add_string("dma-coherent")?;
 
     // Property handle values.
     const PHANDLE_GIC: u32 = 1;
@@ -242,6 +288,13 @@ fn build_dt(
             cpu = cpu.add_str(p_status, "disabled")?;
         }
 
+        if numa_distances.is_some() {
+            cpu = cpu.add_u32(
+                p_numa_node_id,
+                processor_topology.vp(VpIndex::new(vp_index as u32)).vnode,
+            )?;
+        }
+
         cpu_builder = cpu.end_node()?;
     }
     root_builder = cpu_builder.end_node()?;
@@ -260,9 +313,31 @@ fn build_dt(
         let mut mem = root_builder.start_node(&name)?;
         mem = mem.add_str(p_device_type, "memory")?;
         mem = mem.add_u64_array(p_reg, &[start, len])?;
+        if numa_distances.is_some() {
+            mem = mem.add_u32(p_numa_node_id, mem_entry.vnode)?;
+        }
         root_builder = mem.end_node()?;
     }
 
+    // Describe the distances between NUMA nodes as (from, to, distance)
+    // triples.
+    if let Some(numa_distances) = numa_distances {
+        let matrix = numa_distances
+            .iter()
+            .enumerate()
+            .flat_map(|(from, row)| {
+                row.iter()
+                    .enumerate()
+                    .flat_map(move |(to, &distance)| [from as u32, to as u32, distance.into()])
+            })
+            .collect::<Vec<_>>();
+        root_builder = root_builder
+            .start_node("distance-map")?
+            .add_str(p_compatible, "numa-distance-map-v1")?
+            .add_u32_array(p_distance_matrix, &matrix)?
+            .end_node()?;
+    }
+
     // Advanced Bus Peripheral Clock.
     root_builder = root_builder
         .start_node("apb-pclk")?
@@ -341,6 +416,15 @@ fn build_dt(
         }
     }
 
+    if with_fw_cfg {
+        soc = soc
+            .start_node(format!("fw-cfg@{FW_CFG_MMIO_BASE:x}").as_str())?
+            .add_str(p_compatible, "qemu,fw-cfg-mmio")?
+            .add_u64_array(p_reg, &[FW_CFG_MMIO_BASE, FW_CFG_MMIO_LEN])?
+            .add_null(p_dma_coherent)?
+            .end_node()?;
+    }
+
     root_builder = soc.end_node()?;
 
     let mut chosen = root_builder
@@ -368,7 +452,9 @@ pub fn load_linux_arm64(
     cfg: &KernelConfig<'_>,
     gm: &GuestMemory,
     enable_serial: bool,
+    with_fw_cfg: bool,
     processor_topology: &ProcessorTopology<Aarch64Topology>,
+    numa_distances: Option<&[Vec<u8>]>,
 ) -> Result<Vec<Aarch64Register>, Error> {
     let mut loader = Loader::new(gm.clone(), cfg.mem_layout, hvdef::Vtl::Vtl0);
     let mut kernel_file = cfg.kernel;
@@ -398,7 +484,9 @@ pub fn load_linux_arm64(
         cfg,
         gm,
         enable_serial,
+        with_fw_cfg,
         processor_topology,
+        numa_distances,
         initrd_start,
         initrd_end,
     )
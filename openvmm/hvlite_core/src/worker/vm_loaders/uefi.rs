@@ -4,6 +4,7 @@
 use guestmem::GuestMemory;
 use guid::Guid;
 use hvdef::HV_PAGE_SIZE;
+use hvlite_defs::config::SmbiosConfig;
 use hvlite_defs::config::UefiConsoleMode;
 use loader::importer::Register;
 use loader::uefi::config;
@@ -45,9 +46,12 @@ pub fn load_uefi(
     processor_topology: &ProcessorTopology,
     mem_layout: &MemoryLayout,
     load_settings: UefiLoadSettings,
+    smbios: &SmbiosConfig,
     madt: &[u8],
     srat: &[u8],
+    slit: Option<&[u8]>,
     pptt: Option<&[u8]>,
+    ssdt: Option<&[u8]>,
 ) -> Result<Vec<Register>, Error> {
     assert!(mem_layout.mmio().len() >= 2, "UEFI expects 2 MMIO gaps");
 
@@ -117,7 +121,9 @@ pub fn load_uefi(
     .add_raw(config::BlobStructureType::Madt, madt)
     .add_raw(config::BlobStructureType::Srat, srat)
     .add_raw(config::BlobStructureType::MemoryMap, memory_map.as_bytes())
-    .add(&config::BiosGuid(Guid::new_random()))
+    .add(&config::BiosGuid(
+        smbios.system_uuid.unwrap_or_else(Guid::new_random),
+    ))
     .add(&config::Entropy(entropy))
     .add(&config::MmioRanges([
         config::Mmio {
@@ -141,6 +147,56 @@ pub fn load_uefi(
     })
     .add(&flags);
 
+    // The firmware uses its own defaults for any fields left empty.
+    for (structure_type, value) in [
+        (
+            config::BlobStructureType::SmbiosSystemManufacturer,
+            &smbios.system_manufacturer,
+        ),
+        (
+            config::BlobStructureType::SmbiosSystemProductName,
+            &smbios.system_product_name,
+        ),
+        (
+            config::BlobStructureType::SmbiosSystemVersion,
+            &smbios.system_version,
+        ),
+        (
+            config::BlobStructureType::SmbiosSystemSerialNumber,
+            &smbios.system_serial_number,
+        ),
+        (
+            config::BlobStructureType::SmbiosSystemSkuNumber,
+            &smbios.system_sku_number,
+        ),
+        (
+            config::BlobStructureType::SmbiosSystemFamily,
+            &smbios.system_family,
+        ),
+        (
+            config::BlobStructureType::SmbiosBaseSerialNumber,
+            &smbios.baseboard_serial_number,
+        ),
+        (
+            config::BlobStructureType::SmbiosChassisSerialNumber,
+            &smbios.chassis_serial_number,
+        ),
+        (
+            config::BlobStructureType::SmbiosChassisAssetTag,
+            &smbios.chassis_asset_tag,
+        ),
+        (
+            config::BlobStructureType::SmbiosProcessorManufacturer,
+            &smbios.processor_manufacturer,
+        ),
+        (
+            config::BlobStructureType::SmbiosProcessorVersion,
+            &smbios.processor_version,
+        ),
+    ] {
+        cfg.add_cstring(structure_type, value.as_deref().unwrap_or("").as_bytes());
+    }
+
     #[cfg(guest_arch = "aarch64")]
     {
         cfg.add(&config::Gic {
@@ -149,10 +205,18 @@ pub fn load_uefi(
         });
     }
 
+    if let Some(slit) = slit {
+        cfg.add_raw(config::BlobStructureType::Slit, slit);
+    }
+
     if let Some(pptt) = pptt {
         cfg.add_raw(config::BlobStructureType::Pptt, pptt);
     }
 
+    if let Some(ssdt) = ssdt {
+        cfg.add_raw(config::BlobStructureType::Ssdt, ssdt);
+    }
+
     let mut loader = Loader::new(gm.clone(), mem_layout, hvdef::Vtl::Vtl0);
 
     loader::uefi::load(
@@ -26,8 +26,11 @@ pub struct Config {
     pub floppy_disks: Vec<floppy_resources::FloppyDiskConfig>,
     pub ide_disks: Vec<ide_resources::IdeDeviceConfig>,
     pub vpci_devices: Vec<VpciDeviceConfig>,
+    pub pcie_root_ports: Vec<PcieRootPortConfig>,
+    pub pcie_devices: Vec<PcieDeviceConfig>,
     pub memory: MemoryConfig,
     pub processor_topology: ProcessorTopologyConfig,
+    pub numa: Option<NumaConfig>,
     pub hypervisor: HypervisorConfig,
     pub chipset: BaseChipsetManifest,
     pub vmbus: Option<VmbusConfig>,
@@ -47,6 +50,7 @@ pub struct Config {
     pub vmgs_disk: Option<Resource<DiskHandleKind>>,
     pub secure_boot_enabled: bool,
     pub custom_uefi_vars: firmware_uefi_custom_vars::CustomVars,
+    pub smbios: SmbiosConfig,
     // TODO: move FirmwareEvent somewhere not GED-specific.
     pub firmware_event_send: Option<mesh::MpscSender<get_resources::ged::FirmwareEvent>>,
     pub debugger_rpc: Option<mesh::Receiver<vmm_core_defs::debug_rpc::DebugRequest>>,
@@ -118,6 +122,39 @@ pub enum LoadMode {
     None,
 }
 
+/// SMBIOS fields to present to the guest. Unset fields get the VMM's
+/// defaults.
+///
+/// The UEFI and PCAT firmware only consume a subset of these fields. The
+/// Linux direct boot loader builds the full tables.
+#[derive(Debug, Default, Clone, MeshPayload)]
+pub struct SmbiosConfig {
+    pub bios_vendor: Option<String>,
+    pub bios_version: Option<String>,
+    pub bios_release_date: Option<String>,
+    pub system_manufacturer: Option<String>,
+    pub system_product_name: Option<String>,
+    pub system_version: Option<String>,
+    pub system_serial_number: Option<String>,
+    pub system_uuid: Option<Guid>,
+    pub system_sku_number: Option<String>,
+    pub system_family: Option<String>,
+    pub baseboard_manufacturer: Option<String>,
+    pub baseboard_product: Option<String>,
+    pub baseboard_version: Option<String>,
+    pub baseboard_serial_number: Option<String>,
+    pub baseboard_asset_tag: Option<String>,
+    pub chassis_manufacturer: Option<String>,
+    pub chassis_version: Option<String>,
+    pub chassis_serial_number: Option<String>,
+    pub chassis_asset_tag: Option<String>,
+    pub chassis_sku_number: Option<String>,
+    pub processor_manufacturer: Option<String>,
+    pub processor_version: Option<String>,
+    /// Type 11 OEM strings.
+    pub oem_strings: Vec<String>,
+}
+
 #[derive(Debug, Clone, Copy, MeshPayload)]
 pub struct SerialInformation {
     pub io_port: u16,
@@ -159,12 +196,28 @@ pub struct VpciDeviceConfig {
     pub resource: Resource<PciDeviceHandleKind>,
 }
 
+/// A root port on the PCI Express root complex.
+#[derive(Debug, MeshPayload)]
+pub struct PcieRootPortConfig {
+    pub name: String,
+}
+
+/// A device attached to the downstream link of a PCI Express root port.
+#[derive(Debug, MeshPayload)]
+pub struct PcieDeviceConfig {
+    pub port_name: String,
+    pub resource: Resource<PciDeviceHandleKind>,
+}
+
 #[derive(Debug, Protobuf)]
 pub struct ProcessorTopologyConfig<T = TargetTopologyConfig> {
     pub proc_count: u32,
     pub vps_per_socket: Option<u32>,
     pub enable_smt: Option<bool>,
     pub arch: T,
+    /// The maximum number of processors, including ones that can be hot-added
+    /// later. If set above `proc_count`, processor hotplug is enabled.
+    pub max_proc_count: Option<u32>,
 }
 
 #[derive(Debug, Protobuf, Default)]
@@ -209,6 +262,33 @@ pub struct MemoryConfig {
     pub mem_size: u64,
     pub mmio_gaps: Vec<MemoryRange>,
     pub prefetch_memory: bool,
+    /// The maximum memory size, including memory that can be hot-added later.
+    /// If set above `mem_size`, memory hot-add is enabled.
+    pub max_mem_size: Option<u64>,
+}
+
+/// The guest's virtual NUMA topology.
+#[derive(Debug, MeshPayload)]
+pub struct NumaConfig {
+    /// The nodes, indexed by node number.
+    pub nodes: Vec<NumaNodeConfig>,
+    /// The distances between pairs of nodes, as `(from, to, distance)`.
+    ///
+    /// A distance specified in only one direction applies in both directions.
+    /// Unspecified distances default to 10 within a node and 20 between
+    /// nodes.
+    pub distances: Vec<(u32, u32, u8)>,
+}
+
+#[derive(Debug, MeshPayload)]
+pub struct NumaNodeConfig {
+    /// The VP indices of the processors in the node.
+    pub vps: Vec<u32>,
+    /// The amount of RAM in the node. The sum of all nodes' RAM must equal
+    /// the VM's memory size.
+    pub mem_size: u64,
+    /// The host NUMA node to allocate the node's RAM from.
+    pub host_node: Option<u32>,
 }
 
 #[derive(Debug, MeshPayload, Default)]
@@ -4,7 +4,9 @@
 //! RPC types for communicating with the VM worker.
 
 use crate::config::DeviceVtl;
+use crate::config::PcieDeviceConfig;
 use guid::Guid;
+use memory_range::MemoryRange;
 use mesh::error::RemoteError;
 use mesh::payload::message::ProtobufMessage;
 use mesh::rpc::FailableRpc;
@@ -15,6 +17,7 @@
 use std::fs::File;
 use vm_resource::kind::VmbusDeviceHandleKind;
 use vm_resource::Resource;
+use vmm_core_defs::debug_rpc::DebuggerVpState;
 
 #[derive(MeshPayload)]
 pub enum VmRpc {
@@ -25,12 +28,19 @@ pub enum VmRpc {
     Reset(FailableRpc<(), ()>),
     Nmi(Rpc<u32, ()>),
     AddVmbusDevice(FailableRpc<(DeviceVtl, Resource<VmbusDeviceHandleKind>), ()>),
+    AddPcieDevice(FailableRpc<PcieDeviceConfig, ()>),
+    RemovePcieDevice(FailableRpc<String, ()>),
+    AddProcessor(FailableRpc<u32, ()>),
+    RemoveProcessor(FailableRpc<u32, ()>),
+    AddMemory(FailableRpc<u64, ()>),
     ConnectHvsock(FailableRpc<(CancelContext, Guid, DeviceVtl), unix_socket::UnixStream>),
     PulseSaveRestore(Rpc<(), Result<(), PulseSaveRestoreError>>),
     StartReloadIgvm(FailableRpc<File, ()>),
     CompleteReloadIgvm(FailableRpc<bool, ()>),
     ReadMemory(FailableRpc<(u64, usize), Vec<u8>>),
     WriteMemory(FailableRpc<(u64, Vec<u8>), ()>),
+    GetRamRanges(Rpc<(), Vec<MemoryRange>>),
+    GetVpStates(FailableRpc<(), Vec<DebuggerVpState>>),
 }
 
 #[derive(Debug, MeshPayload, thiserror::Error)]
@@ -57,12 +67,19 @@ fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
             VmRpc::ClearHalt(_) => "ClearHalt",
             VmRpc::Nmi(_) => "Nmi",
             VmRpc::AddVmbusDevice(_) => "AddVmbusDevice",
+            VmRpc::AddPcieDevice(_) => "AddPcieDevice",
+            VmRpc::RemovePcieDevice(_) => "RemovePcieDevice",
+            VmRpc::AddProcessor(_) => "AddProcessor",
+            VmRpc::RemoveProcessor(_) => "RemoveProcessor",
+            VmRpc::AddMemory(_) => "AddMemory",
             VmRpc::ConnectHvsock(_) => "ConnectHvsock",
             VmRpc::PulseSaveRestore(_) => "PulseSaveRestore",
             VmRpc::StartReloadIgvm(_) => "StartReloadIgvm",
             VmRpc::CompleteReloadIgvm(_) => "CompleteReloadIgvm",
             VmRpc::ReadMemory(_) => "ReadMemory",
             VmRpc::WriteMemory(_) => "WriteMemory",
+            VmRpc::GetRamRanges(_) => "GetRamRanges",
+            VmRpc::GetVpStates(_) => "GetVpStates",
         };
         f.pad(s)
     }
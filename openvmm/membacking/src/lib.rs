@@ -68,6 +68,7 @@
 
 mod mapping_manager;
 mod memory_manager;
+mod numa;
 mod partition_mapper;
 mod region_manager;
 
@@ -90,6 +91,7 @@ pub enum RemoteProcess {}
 /// On Unix, this is an empty (uninhabitable) enum.
 pub type RemoteProcess = sys::RemoteProcess;
 
+pub use memory_manager::AddRamError;
 pub use memory_manager::DeviceMemoryMapper;
 pub use memory_manager::GuestMemoryBuilder;
 pub use memory_manager::GuestMemoryClient;
@@ -98,4 +100,5 @@ pub enum RemoteProcess {}
 pub use memory_manager::PartitionAttachError;
 pub use memory_manager::RamVisibility;
 pub use memory_manager::RamVisibilityControl;
+pub use memory_manager::SharedGuestRamRanges;
 pub use memory_manager::SharedMemoryBacking;
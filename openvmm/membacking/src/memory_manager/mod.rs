@@ -13,6 +13,7 @@
 use crate::mapping_manager::VaMapper;
 use crate::mapping_manager::VaMapperError;
 use crate::partition_mapper::PartitionMapper;
+use crate::region_manager::AddRegionError;
 use crate::region_manager::MapParams;
 use crate::region_manager::RegionHandle;
 use crate::region_manager::RegionManager;
@@ -38,6 +39,12 @@ pub struct GuestMemoryManager {
     #[inspect(skip)]
     ram_regions: Arc<Vec<RamRegion>>,
 
+    /// Hot-added RAM, each with its own allocation.
+    #[inspect(skip)]
+    hotplug_ram: Vec<HotplugRamRegion>,
+    #[inspect(skip)]
+    hotplug_range: Option<MemoryRange>,
+
     #[inspect(flatten)]
     mapping_manager: MappingManager,
 
@@ -52,6 +59,7 @@ pub struct GuestMemoryManager {
 
     vtl0_alias_map_offset: Option<u64>,
     pin_mappings: bool,
+    prefetch_ram: bool,
 }
 
 #[derive(Debug)]
@@ -60,6 +68,19 @@ struct RamRegion {
     handle: RegionHandle,
 }
 
+#[derive(Debug)]
+struct HotplugRamRegion {
+    memory: HotplugRam,
+    _handle: RegionHandle,
+}
+
+/// A hot-added RAM range and its backing allocation.
+#[derive(Debug, Clone, MeshPayload)]
+struct HotplugRam {
+    range: MemoryRange,
+    memory: Mappable,
+}
+
 /// Errors when attaching a partition to a [`GuestMemoryManager`].
 #[derive(Error, Debug)]
 pub enum PartitionAttachError {
@@ -89,6 +110,23 @@ pub enum MemoryBuildError {
     /// Memory layout incompatible with x86 legacy support.
     #[error("x86 support requires RAM to start at 0 and contain at least 1MB")]
     InvalidRamForX86,
+    /// Existing hot-added RAM does not fit the memory layout's hotplug range.
+    #[error("hot-added ram {0} does not fit in the hotplug range")]
+    InvalidHotplugRam(MemoryRange),
+}
+
+/// Errors hot-adding RAM with [`GuestMemoryManager::add_ram`].
+#[derive(Error, Debug)]
+pub enum AddRamError {
+    /// The range is outside the memory layout's hotplug range.
+    #[error("range {0} is outside of the hotplug range")]
+    OutsideHotplugRange(MemoryRange),
+    /// Couldn't allocate RAM.
+    #[error("failed to allocate memory")]
+    AllocationFailed(#[source] std::io::Error),
+    /// The range overlaps existing memory.
+    #[error("failed to add memory region")]
+    Region(#[source] AddRegionError),
 }
 
 /// A builder for [`GuestMemoryManager`].
@@ -98,6 +136,7 @@ pub struct GuestMemoryBuilder {
     prefetch_ram: bool,
     pin_mappings: bool,
     x86_legacy_support: bool,
+    host_numa_nodes: Vec<Option<u32>>,
 }
 
 impl GuestMemoryBuilder {
@@ -109,6 +148,7 @@ pub fn new() -> Self {
             pin_mappings: false,
             prefetch_ram: false,
             x86_legacy_support: false,
+            host_numa_nodes: Vec::new(),
         }
     }
 
@@ -158,6 +198,16 @@ pub fn x86_legacy_support(mut self, enable: bool) -> Self {
         self
     }
 
+    /// Specifies the host NUMA node to allocate each virtual NUMA node's RAM
+    /// from, indexed by virtual NUMA node.
+    ///
+    /// This only applies to newly allocated memory, and is only supported on
+    /// Linux.
+    pub fn host_numa_nodes(mut self, nodes: Vec<Option<u32>>) -> Self {
+        self.host_numa_nodes = nodes;
+        self
+    }
+
     /// Builds the memory backing, allocating memory if existing memory was not
     /// provided by [`existing_backing`](Self::existing_backing).
     pub async fn build(
@@ -166,16 +216,18 @@ pub async fn build(
     ) -> Result<GuestMemoryManager, MemoryBuildError> {
         let ram_size = mem_layout.ram_size() + mem_layout.vtl2_range().map_or(0, |r| r.len());
 
-        let memory = if let Some(memory) = self.existing_mapping {
-            memory.guest_ram
+        let (memory, hotplug_ram) = if let Some(memory) = self.existing_mapping {
+            (memory.guest_ram, memory.hotplug_ram)
         } else {
-            sparse_mmap::alloc_shared_memory(
+            let memory = sparse_mmap::alloc_shared_memory(
                 ram_size
                     .try_into()
                     .map_err(|_| MemoryBuildError::RamTooLarge(ram_size))?,
             )
             .map_err(MemoryBuildError::AllocationFailed)?
-            .into()
+            .into();
+            bind_host_numa_nodes(&memory, mem_layout, &self.host_numa_nodes)?;
+            (memory, Vec::new())
         };
 
         // Spawn a thread to handle memory requests.
@@ -188,8 +240,9 @@ pub async fn build(
             .spawn(move || pool.run())
             .unwrap();
 
-        let max_addr =
-            (mem_layout.end_of_ram_or_mmio()).max(mem_layout.vtl2_range().map_or(0, |r| r.end()));
+        let max_addr = (mem_layout.end_of_ram_or_mmio())
+            .max(mem_layout.vtl2_range().map_or(0, |r| r.end()))
+            .max(mem_layout.hotplug_range().map_or(0, |r| r.end()));
 
         let vtl0_alias_map_mask = if self.vtl0_alias_map {
             let mask = 1 << (mem_layout.physical_address_size() - 1);
@@ -285,16 +338,28 @@ pub async fn build(
             start += range.len();
         }
 
-        let gm = GuestMemoryManager {
+        let mut gm = GuestMemoryManager {
             guest_ram: memory,
             _thread: thread,
             ram_regions: Arc::new(ram_regions),
+            hotplug_ram: Vec::new(),
+            hotplug_range: mem_layout.hotplug_range(),
             mapping_manager,
             region_manager,
             va_mapper,
             vtl0_alias_map_offset: vtl0_alias_map_mask,
             pin_mappings: self.pin_mappings,
+            prefetch_ram: self.prefetch_ram,
         };
+
+        // Restore any RAM that was hot-added to the previous instance.
+        for ram in hotplug_ram {
+            let range = ram.range;
+            gm.map_hotplug_ram(ram)
+                .await
+                .map_err(|_| MemoryBuildError::InvalidHotplugRam(range))?;
+        }
+
         Ok(gm)
     }
 }
@@ -303,6 +368,71 @@ pub async fn build(
 #[derive(Debug, MeshPayload)]
 pub struct SharedMemoryBacking {
     guest_ram: Mappable,
+    hotplug_ram: Vec<HotplugRam>,
+}
+
+/// Binds the RAM of each virtual NUMA node to its host NUMA node, if any.
+///
+/// RAM ranges are allocated contiguously in `memory`, in address order.
+fn bind_host_numa_nodes(
+    memory: &Mappable,
+    mem_layout: &MemoryLayout,
+    host_numa_nodes: &[Option<u32>],
+) -> Result<(), MemoryBuildError> {
+    let mut offset = 0;
+    for range in mem_layout.ram() {
+        if let Some(&Some(host_node)) = host_numa_nodes.get(range.vnode as usize) {
+            #[cfg(target_os = "linux")]
+            let r = crate::numa::bind_to_host_node(memory, offset, range.range.len(), host_node);
+            #[cfg(not(target_os = "linux"))]
+            let r = {
+                let _ = memory;
+                Err(std::io::ErrorKind::Unsupported.into())
+            };
+            r.map_err(|err| MemoryBuildError::HostNumaBind {
+                vnode: range.vnode,
+                host_node,
+                err,
+            })?;
+        }
+        offset += range.range.len();
+    }
+    Ok(())
+}
+
+/// The guest RAM ranges of a [`GuestMemoryManager`] and their backing
+/// allocations, returned by [`GuestMemoryManager::shared_guest_ram`].
+#[derive(Debug)]
+pub struct SharedGuestRamRanges {
+    ranges: Vec<SharedRange>,
+}
+
+#[derive(Debug)]
+struct SharedRange {
+    range: MemoryRange,
+    memory: Mappable,
+    file_offset: u64,
+}
+
+impl guestmem::SharedGuestRam for SharedGuestRamRanges {
+    fn shared_ram_ranges(&self) -> std::io::Result<Vec<guestmem::SharedRamRange>> {
+        self.ranges
+            .iter()
+            .map(|r| {
+                #[cfg(unix)]
+                let mappable = std::os::fd::AsFd::as_fd(&r.memory).try_clone_to_owned()?;
+                #[cfg(windows)]
+                let mappable =
+                    std::os::windows::io::AsHandle::as_handle(&r.memory).try_clone_to_owned()?;
+                Ok(guestmem::SharedRamRange {
+                    gpa: r.range.start(),
+                    len: r.range.len(),
+                    mappable,
+                    file_offset: r.file_offset,
+                })
+            })
+            .collect()
+    }
 }
 
 /// A mesh-serializable object for providing access to guest memory.
@@ -345,6 +475,37 @@ pub fn device_memory_mapper(&self) -> DeviceMemoryMapper {
         DeviceMemoryMapper::new(self.region_manager.client().clone())
     }
 
+    /// Returns an object for sharing guest RAM with other processes.
+    ///
+    /// This describes the RAM present at the time of the call, and other
+    /// processes are not told about RAM hot-added later. So this returns
+    /// `None` if the memory layout has a hotplug range.
+    pub fn shared_guest_ram(&self) -> Option<SharedGuestRamRanges> {
+        if self.hotplug_range.is_some() {
+            return None;
+        }
+        let mut ranges = Vec::<SharedRange>::new();
+        let mut file_offset = 0;
+        for region in self.ram_regions.iter() {
+            // Merge the ranges split for x86 legacy support back together.
+            match ranges.last_mut() {
+                Some(last)
+                    if last.range.end() == region.range.start()
+                        && last.file_offset + last.range.len() == file_offset =>
+                {
+                    last.range = MemoryRange::new(last.range.start()..region.range.end());
+                }
+                _ => ranges.push(SharedRange {
+                    range: region.range,
+                    memory: self.guest_ram.clone(),
+                    file_offset,
+                }),
+            }
+            file_offset += region.range.len();
+        }
+        Some(SharedGuestRamRanges { ranges })
+    }
+
     /// Returns an object for manipulating the visibility state of different RAM
     /// regions.
     pub fn ram_visibility_control(&self) -> RamVisibilityControl {
@@ -362,7 +523,72 @@ pub fn ram_visibility_control(&self) -> RamVisibilityControl {
     /// guest may see unpredictable results.
     pub fn shared_memory_backing(&self) -> SharedMemoryBacking {
         let guest_ram = self.guest_ram.clone();
-        SharedMemoryBacking { guest_ram }
+        let hotplug_ram = self
+            .hotplug_ram
+            .iter()
+            .map(|region| region.memory.clone())
+            .collect();
+        SharedMemoryBacking {
+            guest_ram,
+            hotplug_ram,
+        }
+    }
+
+    /// Hot-adds RAM at `range`, which must be within the memory layout's
+    /// hotplug range, and maps it into the VM.
+    ///
+    /// The new RAM is backed by a separate allocation, which is included in
+    /// [`shared_memory_backing`](Self::shared_memory_backing).
+    pub async fn add_ram(&mut self, range: MemoryRange) -> Result<(), AddRamError> {
+        let memory = sparse_mmap::alloc_shared_memory(
+            range
+                .len()
+                .try_into()
+                .map_err(|_| AddRamError::OutsideHotplugRange(range))?,
+        )
+        .map_err(AddRamError::AllocationFailed)?
+        .into();
+
+        self.map_hotplug_ram(HotplugRam { range, memory }).await
+    }
+
+    async fn map_hotplug_ram(&mut self, ram: HotplugRam) -> Result<(), AddRamError> {
+        if !self
+            .hotplug_range
+            .is_some_and(|hotplug_range| hotplug_range.contains(&ram.range))
+        {
+            return Err(AddRamError::OutsideHotplugRange(ram.range));
+        }
+
+        let region = self
+            .region_manager
+            .client()
+            .new_region("ram-hotplug".into(), ram.range, RAM_PRIORITY)
+            .await
+            .map_err(AddRamError::Region)?;
+
+        region
+            .add_mapping(
+                MemoryRange::new(0..ram.range.len()),
+                ram.memory.clone(),
+                0,
+                true,
+            )
+            .await;
+
+        region
+            .map(MapParams {
+                writable: true,
+                executable: true,
+                prefetch: self.prefetch_ram,
+            })
+            .await;
+
+        self.hotplug_ram.push(HotplugRamRegion {
+            memory: ram,
+            _handle: region,
+        });
+        Ok(())
     }
 
     /// Attaches the guest memory to a partition, mapping it to the guest
@@ -0,0 +1,51 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Host NUMA binding for guest RAM.
+
+#![cfg(target_os = "linux")]
+// UNSAFETY: Calling mbind on a temporary mapping.
+#![expect(unsafe_code)]
+
+use crate::mapping_manager::Mappable;
+use sparse_mmap::SparseMapping;
+use std::io;
+
+const MPOL_BIND: libc::c_int = 2;
+
+/// Sets the memory policy of `len` bytes at `offset` within `memory` to only
+/// allocate pages from host NUMA node `node`.
+///
+/// This must be called before the memory is touched, since it does not move
+/// pages that have already been allocated.
+pub fn bind_to_host_node(memory: &Mappable, offset: u64, len: u64, node: u32) -> io::Result<()> {
+    let len = usize::try_from(len).map_err(|_| io::ErrorKind::InvalidInput)?;
+    let node = node as usize;
+    let mut nodemask = vec![0u64; node / 64 + 1];
+    nodemask[node / 64] |= 1 << (node % 64);
+
+    // The policy of a shared memory object is stored with the object rather
+    // than with the mapping, so it still applies after this temporary mapping
+    // is removed.
+    let mapping = SparseMapping::new(len)?;
+    mapping.map_file(0, len, memory, offset, true)?;
+
+    // SAFETY: the address range is owned by `mapping`, and `nodemask` is a
+    // valid bitmask of the specified number of bits.
+    let r = unsafe {
+        libc::syscall(
+            libc::SYS_mbind,
+            mapping.as_ptr(),
+            len,
+            MPOL_BIND,
+            nodemask.as_ptr(),
+            // The kernel ignores the last bit.
+            nodemask.len() * 64 + 1,
+            0,
+        )
+    };
+    if r < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
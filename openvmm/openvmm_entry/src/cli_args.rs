@@ -21,9 +21,11 @@
 use anyhow::Context;
 use clap::Parser;
 use clap::ValueEnum;
+use guid::Guid;
 use hvlite_defs::config::DeviceVtl;
 use hvlite_defs::config::Hypervisor;
 use hvlite_defs::config::PcatBootDevice;
+use hvlite_defs::config::SmbiosConfig;
 use hvlite_defs::config::Vtl2BaseAddressType;
 use hvlite_defs::config::X2ApicConfig;
 use hvlite_defs::config::DEFAULT_PCAT_BOOT_ORDER;
@@ -43,15 +45,15 @@ pub struct Options {
     #[clap(short = 'p', long, value_name = "COUNT", default_value = "1")]
     pub processors: u32,
 
-    /// guest RAM size
-    #[clap(
-        short = 'm',
-        long,
-        value_name = "SIZE",
-        default_value = "1GB",
-        value_parser = parse_memory
-    )]
-    pub memory: u64,
+    /// guest RAM size [default: 1GB]
+    #[clap(short = 'm', long, value_name = "SIZE", value_parser = parse_memory)]
+    pub memory: Option<u64>,
+
+    /// maximum guest RAM size, allowing RAM to be hot-added up to this size
+    /// (x86 Linux direct boot only)
+    #[cfg(guest_arch = "x86_64")]
+    #[clap(long, value_name = "SIZE", value_parser = parse_memory)]
+    pub max_memory: Option<u64>,
 
     /// use shared memory segment
     #[clap(short = 'M', long)]
@@ -144,6 +146,17 @@ pub struct Options {
         <disk>: lower disk, e.g.: `file:base.img`
     `file:\<path\>`                  file-backed disk
         \<path\>: path to file
+    `iso-dir:\<path\>[;label=<id>]`  read-only ISO9660 image of a host directory
+        \<path\>: path to directory
+        <id>: volume label, e.g.: `cidata`
+    `fat-dir:\<path\>[;opts]`        read-only FAT image of a host directory
+        \<path\>: path to directory
+        opts: `;`-separated `fat=12|16|32`, `size=<len>`, `label=<id>`
+        use with `memdiff:` or `sqldiff:` for a writable disk
+    `prwrap:<disk>`                emulate persistent reservations in memory
+    `prwrap-shared:\<path\>:<disk>` emulate persistent reservations, shared
+                                   with other VMs using the same store file
+        \<path\>: path to reservation store, created if missing
 
 flags:
     `ro`                           open disk as read-only
@@ -171,10 +184,26 @@ pub struct Options {
 flags:
     `ro`                           open disk as read-only
     `vtl2`                         assign this disk to VTL2
+    `pcie_port=<name>`             attach the controller to the named PCIe root port
 "#)]
     #[clap(long)]
     pub nvme: Vec<DiskCli>,
 
+    /// add a PCIe root port with the given name
+    ///
+    /// Any root ports enable the PCIe root complex. Devices are attached to a
+    /// root port by name, e.g. `--nvme file:disk.vhd,pcie_port=rp0`.
+    #[clap(long, value_name = "NAME")]
+    pub pcie_root_port: Vec<String>,
+
+    /// attach a PCI device implemented by a vfio-user server
+    ///
+    /// The server must already be listening on the given Unix socket. The
+    /// device is attached to the named PCIe root port.
+    #[cfg(target_os = "linux")]
+    #[clap(long, value_name = "PATH,pcie_port=NAME")]
+    pub vfio_user: Vec<VfioUserCli>,
+
     /// number of sub-channels for the SCSI controller
     #[clap(long, value_name = "COUNT", default_value = "0")]
     pub scsi_sub_channels: u16,
@@ -212,6 +241,39 @@ pub struct Options {
     #[clap(long, value_name = "PORT", default_value = "5900")]
     pub vnc_port: u16,
 
+    /// also listen for VNC connections over WebSocket (e.g. from noVNC) on
+    /// this port
+    #[clap(long, value_name = "PORT")]
+    pub vnc_websocket_port: Option<u16>,
+
+    /// allow browser connections over WebSocket from web pages with this
+    /// origin (e.g. http://localhost:6080). may be specified multiple times.
+    /// connections from other origins are rejected.
+    #[clap(long, value_name = "ORIGIN", requires("vnc_websocket_port"))]
+    pub vnc_websocket_origin: Vec<String>,
+
+    /// require VNC clients to authenticate with the password in this file
+    #[clap(long, value_name = "PATH")]
+    pub vnc_password_file: Option<PathBuf>,
+
+    /// require VNC clients to use VeNCrypt TLS with this PEM certificate chain
+    #[clap(long, value_name = "PATH", requires("vnc_tls_key"))]
+    pub vnc_tls_cert: Option<PathBuf>,
+
+    /// the PEM private key for --vnc-tls-cert
+    #[clap(long, value_name = "PATH", requires("vnc_tls_cert"))]
+    pub vnc_tls_key: Option<PathBuf>,
+
+    /// require VNC clients to authenticate with this username and the
+    /// --vnc-password-file password, over TLS
+    #[clap(
+        long,
+        value_name = "NAME",
+        requires("vnc_password_file"),
+        requires("vnc_tls_cert")
+    )]
+    pub vnc_username: Option<String>,
+
     /// set the APIC ID offset, for testing APIC IDs that don't match VP index
     #[cfg(guest_arch = "x86_64")]
     #[clap(long, default_value_t)]
@@ -221,6 +283,27 @@ pub struct Options {
     #[clap(long)]
     pub vps_per_socket: Option<u32>,
 
+    /// the maximum processor count, allowing processors to be hot-added up to
+    /// this count (x86 Linux direct boot only)
+    #[cfg(guest_arch = "x86_64")]
+    #[clap(long, value_name = "COUNT")]
+    pub max_processors: Option<u32>,
+
+    /// configure a virtual NUMA node or the distance between two nodes
+    ///
+    /// `node=<n>,cpus=<list>,mem=<size>` describes node `n`, where `cpus` is a
+    /// processor index or range (e.g. `0-3`) and can be repeated. Add
+    /// `host-node=<n>` to allocate the node's memory from host NUMA node `n`
+    /// (Linux only). Nodes must be numbered from 0 and together include every
+    /// processor. The guest RAM size is the total of the nodes' memory, which
+    /// must match `--memory` if that is also given.
+    ///
+    /// `dist,src=<n>,dst=<n>,val=<distance>` sets the distance between two
+    /// nodes, in both directions unless also set in the other direction. The
+    /// distance within a node is 10, and the default between nodes is 20.
+    #[clap(long, value_name = "node=<n>,cpus=<list>,mem=<size>,...")]
+    pub numa: Vec<NumaCli>,
+
     /// enable or disable SMT (hyperthreading) (auto | force | off)
     #[clap(long, default_value = "auto")]
     pub smt: SmtConfigCli,
@@ -238,35 +321,35 @@ pub struct Options {
     #[clap(long, conflicts_with("virtio_console"))]
     pub virtio_console_pci: bool,
 
-    /// COM1 binding (console | stderr | listen=\<path\> | listen=tcp:\<ip\>:\<port\> | term[=\<program\>] | none)
+    /// COM1 binding (console | stderr | listen=\<path\> | listen=tcp:\<ip\>:\<port\> | term[=\<program\>] | file=\<path\>[,timestamps][,rotate=\<size\>] | tee=\<serial\>,\<serial\>[,...] | none)
     #[clap(long, value_name = "SERIAL")]
     pub com1: Option<SerialConfigCli>,
 
-    /// COM2 binding (console | stderr | listen=\<path\> | listen=tcp:\<ip\>:\<port\> | term[=\<program\>] | none)
+    /// COM2 binding (console | stderr | listen=\<path\> | listen=tcp:\<ip\>:\<port\> | term[=\<program\>] | file=\<path\>[,timestamps][,rotate=\<size\>] | tee=\<serial\>,\<serial\>[,...] | none)
     #[clap(long, value_name = "SERIAL")]
     pub com2: Option<SerialConfigCli>,
 
-    /// COM3 binding (console | stderr | listen=\<path\> | listen=tcp:\<ip\>:\<port\> | term[=\<program\>] | none)
+    /// COM3 binding (console | stderr | listen=\<path\> | listen=tcp:\<ip\>:\<port\> | term[=\<program\>] | file=\<path\>[,timestamps][,rotate=\<size\>] | tee=\<serial\>,\<serial\>[,...] | none)
     #[clap(long, value_name = "SERIAL")]
     pub com3: Option<SerialConfigCli>,
 
-    /// COM4 binding (console | stderr | listen=\<path\> | listen=tcp:\<ip\>:\<port\> | term[=\<program\>] | none)
+    /// COM4 binding (console | stderr | listen=\<path\> | listen=tcp:\<ip\>:\<port\> | term[=\<program\>] | file=\<path\>[,timestamps][,rotate=\<size\>] | tee=\<serial\>,\<serial\>[,...] | none)
     #[clap(long, value_name = "SERIAL")]
     pub com4: Option<SerialConfigCli>,
 
-    /// virtio serial binding (console | stderr | listen=\<path\> | listen=tcp:\<ip\>:\<port\> | term[=\<program\>] | none)
+    /// virtio serial binding (console | stderr | listen=\<path\> | listen=tcp:\<ip\>:\<port\> | term[=\<program\>] | file=\<path\>[,timestamps][,rotate=\<size\>] | tee=\<serial\>,\<serial\>[,...] | none)
     #[clap(long, value_name = "SERIAL")]
     pub virtio_serial: Option<SerialConfigCli>,
 
-    /// vmbus com1 serial binding (console | stderr | listen=\<path\> | listen=tcp:\<ip\>:\<port\> | term[=\<program\>] | none)
+    /// vmbus com1 serial binding (console | stderr | listen=\<path\> | listen=tcp:\<ip\>:\<port\> | term[=\<program\>] | file=\<path\>[,timestamps][,rotate=\<size\>] | tee=\<serial\>,\<serial\>[,...] | none)
     #[structopt(long, value_name = "SERIAL")]
     pub vmbus_com1_serial: Option<SerialConfigCli>,
 
-    /// vmbus com2 serial binding (console | stderr | listen=\<path\> | listen=tcp:\<ip\>:\<port\> | term[=\<program\>] | none)
+    /// vmbus com2 serial binding (console | stderr | listen=\<path\> | listen=tcp:\<ip\>:\<port\> | term[=\<program\>] | file=\<path\>[,timestamps][,rotate=\<size\>] | tee=\<serial\>,\<serial\>[,...] | none)
     #[structopt(long, value_name = "SERIAL")]
     pub vmbus_com2_serial: Option<SerialConfigCli>,
 
-    /// debugcon binding (port:serial, where port is a u16, and serial is (console | stderr | listen=\<path\> | listen=tcp:\<ip\>:\<port\> | term[=\<program\>] | none))
+    /// debugcon binding (port:serial, where port is a u16, and serial is (console | stderr | listen=\<path\> | listen=tcp:\<ip\>:\<port\> | term[=\<program\>] | file=\<path\>[,timestamps][,rotate=\<size\>] | tee=\<serial\>,\<serial\>[,...] | none))
     #[clap(long, value_name = "SERIAL")]
     pub debugcon: Option<DebugconSerialConfigCli>,
 
@@ -332,7 +415,7 @@ pub struct Options {
     #[clap(long, value_name = "tag,root_path")]
     pub virtio_fs_shmem: Vec<FsArgs>,
 
-    /// add a virtio_fs device under either the PCI or MMIO bus, or whatever the hypervisor supports (pci | mmio | auto)
+    /// add a virtio_fs device under either the PCI or MMIO bus, a dedicated PCIe root port, or whatever the hypervisor supports (pci | mmio | pcie | auto)
     #[clap(long, value_name = "BUS", default_value = "auto")]
     pub virtio_fs_bus: VirtioBusCli,
 
@@ -340,6 +423,15 @@ pub struct Options {
     #[clap(long, value_name = "PATH")]
     pub virtio_pmem: Option<String>,
 
+    /// add virtio input devices receiving input from the graphical console
+    /// (keyboard | mouse | tablet)
+    #[clap(long, value_name = "DEVICE", value_delimiter = ',')]
+    pub virtio_input: Vec<VirtioInputCli>,
+
+    /// add a virtio-gpu display device rendering into the graphical console
+    #[clap(long, conflicts_with_all(&["gfx", "pcat", "vtl2_gfx"]))]
+    pub virtio_gpu: bool,
+
     /// expose a virtio network with the given backend (dio | vmnic | tap |
     /// none)
     ///
@@ -462,6 +554,13 @@ pub struct Options {
         <disk>: lower disk, e.g.: `file:base.img`
     `file:\<path\>`                  file-backed disk
         \<path\>: path to file
+    `iso-dir:\<path\>[;label=<id>]`  read-only ISO9660 image of a host directory
+        \<path\>: path to directory
+        <id>: volume label, e.g.: `cidata`
+    `fat-dir:\<path\>[;opts]`        read-only FAT image of a host directory
+        \<path\>: path to directory
+        opts: `;`-separated `fat=12|16|32`, `size=<len>`, `label=<id>`
+        use with `memdiff:` or `sqldiff:` for a writable disk
 
 flags:
     `ro`                           open disk as read-only
@@ -485,6 +584,10 @@ pub struct Options {
         <disk>: lower disk, e.g.: `file:base.img`
     `file:\<path\>`                  file-backed disk
         \<path\>: path to file
+    `fat-dir:\<path\>[;opts]`        read-only FAT image of a host directory
+        \<path\>: path to directory
+        opts: `;`-separated `fat=12|16|32`, `size=<len>`, `label=<id>`
+        e.g.: `memdiff:fat-dir:out;size=1440K` for a writable floppy
 
 flags:
     `ro`                           open disk as read-only
@@ -520,9 +623,70 @@ pub struct Options {
     #[clap(long)]
     pub battery: bool,
 
+    /// expose an HPET (High Precision Event Timer)
+    #[clap(long)]
+    pub hpet: bool,
+
+    /// expose a pvpanic device, which the guest uses to report kernel panics
+    ///
+    /// `isa` (the default) places the device at I/O port 0x505, described in
+    /// the DSDT. `pcie_port=<name>` attaches a PCI pvpanic device to the
+    /// named PCIe root port.
+    #[clap(
+        long,
+        value_name = "isa|pcie_port=NAME",
+        num_args = 0..=1,
+        default_missing_value = "isa"
+    )]
+    pub pvpanic: Option<PvPanicCli>,
+
+    /// action to take when the guest reports a panic via pvpanic
+    ///
+    /// One of `pause` (leave the VM halted), `continue`, `reset`,
+    /// `dump=<path>` (write a guest core dump, then leave the VM halted), or
+    /// `exit[=<code>]` (exit OpenVMM with the given code, 1 by default).
+    #[clap(long, value_name = "ACTION", default_value = "pause")]
+    pub on_guest_panic: GuestPanicActionCli,
+
+    /// action to take when the guest reports a panic via pvpanic, but has a
+    /// crash kernel loaded to handle it
+    ///
+    /// Takes the same values as `--on-guest-panic`.
+    #[clap(long, value_name = "ACTION", default_value = "continue")]
+    pub on_guest_crash_loaded: GuestPanicActionCli,
+
+    /// expose a file to the guest firmware via the QEMU fw_cfg interface
+    ///
+    /// `name` is the fw_cfg file name, such as `opt/com.example/config`. The
+    /// contents are read from the host file `path`, or given inline with
+    /// `string`. Can be specified multiple times. Only supported with Linux
+    /// direct boot and UEFI.
+    #[clap(long, value_name = "name=<key>,file=<path>|string=<s>")]
+    pub fw_cfg: Vec<FwCfgFileCli>,
+
+    /// expose `--kernel`, `--initrd` and `--cmdline` to the firmware via the
+    /// QEMU fw_cfg interface, so that firmware such as OVMF can direct-boot
+    /// Linux
+    #[clap(long, requires("uefi"))]
+    pub fw_cfg_kernel: bool,
+
     /// set the uefi console mode
     #[clap(long)]
     pub uefi_console_mode: Option<UefiConsoleModeCli>,
+
+    /// set SMBIOS fields presented to the guest
+    ///
+    /// Keys by type: 0: `vendor`, `version`, `date`; 1: `manufacturer`,
+    /// `product`, `version`, `serial`, `uuid`, `sku`, `family`; 2:
+    /// `manufacturer`, `product`, `version`, `serial`, `asset`; 3:
+    /// `manufacturer`, `version`, `serial`, `asset`, `sku`; 4:
+    /// `manufacturer`, `version`. For type 11, `value=<s>` adds an OEM string,
+    /// and takes the rest of the argument, including any commas. Can be
+    /// specified multiple times.
+    ///
+    /// UEFI and PCAT firmware only use some of these fields.
+    #[clap(long, value_name = "type=<n>,<key>=<value>,...")]
+    pub smbios: Vec<SmbiosCli>,
 }
 
 #[derive(Clone)]
@@ -579,6 +743,14 @@ pub enum VirtioBusCli {
     Mmio,
     Pci,
     Vpci,
+    Pcie,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, clap::ValueEnum)]
+pub enum VirtioInputCli {
+    Keyboard,
+    Mouse,
+    Tablet,
 }
 
 #[derive(clap::ValueEnum, Clone, Copy)]
@@ -587,7 +759,7 @@ pub enum SecureBootTemplateCli {
     UefiCa,
 }
 
-fn parse_memory(s: &str) -> anyhow::Result<u64> {
+pub fn parse_memory(s: &str) -> anyhow::Result<u64> {
     || -> Option<u64> {
         let mut b = s.as_bytes();
         if s.ends_with('B') {
@@ -638,9 +810,25 @@ pub enum DiskCliKind {
         disk: Box<DiskCliKind>,
     },
     // prwrap:<kind>
-    PersistentReservationsWrapper(Box<DiskCliKind>),
+    // prwrap-shared:<path>:<kind>
+    PersistentReservationsWrapper {
+        disk: Box<DiskCliKind>,
+        store: Option<PathBuf>,
+    },
     // file:<path>
     File(PathBuf),
+    // iso-dir:<path>[;label=<id>]
+    IsoDir {
+        path: PathBuf,
+        volume_id: Option<String>,
+    },
+    // fat-dir:<path>[;fat=<12|16|32>][;size=<len>][;label=<id>]
+    FatDir {
+        path: PathBuf,
+        fat_type: Option<FatType>,
+        size: Option<u64>,
+        volume_label: Option<String>,
+    },
     // blob:<type>:<url>
     Blob {
         kind: BlobKind,
@@ -660,6 +848,13 @@ pub enum DiskCipher {
     XtsAes256,
 }
 
+#[derive(Copy, Clone)]
+pub enum FatType {
+    Fat12,
+    Fat16,
+    Fat32,
+}
+
 #[derive(Copy, Clone)]
 pub enum BlobKind {
     Flat,
@@ -715,8 +910,64 @@ fn from_str(s: &str) -> anyhow::Result<Self> {
                         },
                     }
                 }
-                "prwrap" => DiskCliKind::PersistentReservationsWrapper(Box::new(arg.parse()?)),
+                "prwrap" => DiskCliKind::PersistentReservationsWrapper {
+                    disk: Box::new(arg.parse()?),
+                    store: None,
+                },
+                "prwrap-shared" => {
+                    let (path, kind) = arg.split_once(':').context("expected path:kind")?;
+                    DiskCliKind::PersistentReservationsWrapper {
+                        disk: Box::new(kind.parse()?),
+                        store: Some(path.into()),
+                    }
+                }
                 "file" => DiskCliKind::File(PathBuf::from(arg)),
+                "iso-dir" => match arg.split_once(';') {
+                    Some((path, label)) => {
+                        let Some(label) = label.strip_prefix("label=") else {
+                            anyhow::bail!("invalid syntax after ';', expected 'label=<id>'")
+                        };
+                        DiskCliKind::IsoDir {
+                            path: path.into(),
+                            volume_id: Some(label.into()),
+                        }
+                    }
+                    None => DiskCliKind::IsoDir {
+                        path: arg.into(),
+                        volume_id: None,
+                    },
+                },
+                "fat-dir" => {
+                    let mut opts = arg.split(';');
+                    let path = opts.next().unwrap().into();
+                    let mut fat_type = None;
+                    let mut size = None;
+                    let mut volume_label = None;
+                    for opt in opts {
+                        let (name, value) = opt
+                            .split_once('=')
+                            .with_context(|| format!("expected <opt>=<value>, got '{opt}'"))?;
+                        match name {
+                            "fat" => {
+                                fat_type = Some(match value {
+                                    "12" => FatType::Fat12,
+                                    "16" => FatType::Fat16,
+                                    "32" => FatType::Fat32,
+                                    _ => anyhow::bail!("unknown fat type {value}"),
+                                })
+                            }
+                            "size" => size = Some(parse_memory(value)?),
+                            "label" => volume_label = Some(value.into()),
+                            _ => anyhow::bail!("unknown fat-dir option {name}"),
+                        }
+                    }
+                    DiskCliKind::FatDir {
+                        path,
+                        fat_type,
+                        size,
+                        volume_label,
+                    }
+                }
                 "blob" => {
                     let (blob_kind, url) = arg.split_once(':').context("expected kind:url")?;
                     let blob_kind = match blob_kind {
@@ -767,6 +1018,7 @@ pub struct DiskCli {
     pub read_only: bool,
     pub is_dvd: bool,
     pub underhill: Option<UnderhillDiskSource>,
+    pub pcie_port: Option<String>,
 }
 
 #[derive(Copy, Clone)]
@@ -786,6 +1038,7 @@ fn from_str(s: &str) -> anyhow::Result<Self> {
         let mut is_dvd = false;
         let mut underhill = None;
         let mut vtl = DeviceVtl::Vtl0;
+        let mut pcie_port = None;
         for opt in opts {
             let mut s = opt.split('=');
             let opt = s.next().unwrap();
@@ -800,6 +1053,10 @@ fn from_str(s: &str) -> anyhow::Result<Self> {
                 }
                 "uh" => underhill = Some(UnderhillDiskSource::Scsi),
                 "uh-nvme" => underhill = Some(UnderhillDiskSource::Nvme),
+                "pcie_port" => {
+                    let port = s.next().context("missing pcie_port name")?;
+                    pcie_port = Some(port.to_string());
+                }
                 opt => anyhow::bail!("unknown option: '{opt}'"),
             }
         }
@@ -808,12 +1065,17 @@ fn from_str(s: &str) -> anyhow::Result<Self> {
             anyhow::bail!("`uh` is incompatible with `vtl2`");
         }
 
+        if pcie_port.is_some() && (underhill.is_some() || vtl != DeviceVtl::Vtl0) {
+            anyhow::bail!("`pcie_port` is incompatible with `uh` and `vtl2`");
+        }
+
         Ok(DiskCli {
             vtl,
             kind,
             read_only,
             is_dvd,
             underhill,
+            pcie_port,
         })
     }
 }
@@ -918,7 +1180,7 @@ fn from_str(s: &str) -> Result<Self, Self::Err> {
     }
 }
 
-/// (console | stderr | listen=\<path\> | listen=tcp:\<ip\>:\<port\> | none)
+/// (console | stderr | listen=\<path\> | listen=tcp:\<ip\>:\<port\> | file=\<path\>[,timestamps][,rotate=\<size\>] | tee=\<serial\>,\<serial\>[,...] | none)
 #[derive(Clone)]
 pub enum SerialConfigCli {
     None,
@@ -927,6 +1189,8 @@ pub enum SerialConfigCli {
     Stderr,
     Pipe(PathBuf),
     Tcp(SocketAddr),
+    File(SerialFileCli),
+    Tee(Vec<SerialConfigCli>),
 }
 
 impl FromStr for SerialConfigCli {
@@ -952,6 +1216,33 @@ fn from_str(s: &str) -> Result<Self, Self::Err> {
                     SerialConfigCli::Pipe(s.into())
                 }
             }
+            s if s.starts_with("file=") => {
+                SerialConfigCli::File(s.strip_prefix("file=").unwrap().parse()?)
+            }
+            s if s.starts_with("tee=") => {
+                // Split on commas, keeping options with the file they follow.
+                let mut targets = Vec::<String>::new();
+                for part in s.strip_prefix("tee=").unwrap().split(',') {
+                    match targets.last_mut() {
+                        Some(last)
+                            if last.starts_with("file=")
+                                && (part == "timestamps" || part.starts_with("rotate=")) =>
+                        {
+                            last.push(',');
+                            last.push_str(part);
+                        }
+                        _ => targets.push(part.to_owned()),
+                    }
+                }
+                let targets = targets
+                    .iter()
+                    .map(|target| match target.parse()? {
+                        SerialConfigCli::Tee(_) => Err("tee cannot be nested".to_owned()),
+                        cfg => Ok(cfg),
+                    })
+                    .collect::<Result<_, _>>()?;
+                SerialConfigCli::Tee(targets)
+            }
             _ => return Err("invalid serial configuration".into()),
         };
 
@@ -959,6 +1250,48 @@ fn from_str(s: &str) -> Result<Self, Self::Err> {
     }
 }
 
+/// \<path\>[,timestamps][,rotate=\<size\>]
+#[derive(Clone)]
+pub struct SerialFileCli {
+    pub path: PathBuf,
+    /// Prefix each line with the time it was written.
+    pub timestamps: bool,
+    /// Start a new file once the current one reaches this many bytes.
+    pub rotate: Option<u64>,
+}
+
+impl FromStr for SerialFileCli {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut opts = s.split(',');
+        let path = opts.next().unwrap();
+        if path.is_empty() {
+            return Err("missing file path".into());
+        }
+        let mut timestamps = false;
+        let mut rotate = None;
+        for opt in opts {
+            match opt.split_once('=') {
+                None if opt == "timestamps" => timestamps = true,
+                Some(("rotate", size)) => {
+                    let size = parse_memory(size).map_err(|err| format!("{err:#}"))?;
+                    if size == 0 {
+                        return Err("rotate size must be non-zero".into());
+                    }
+                    rotate = Some(size);
+                }
+                _ => return Err(format!("unknown file option: '{opt}'")),
+            }
+        }
+        Ok(Self {
+            path: path.into(),
+            timestamps,
+            rotate,
+        })
+    }
+}
+
 #[derive(Clone)]
 pub enum EndpointConfigCli {
     None,
@@ -1186,6 +1519,297 @@ fn from_str(s: &str) -> Result<Self, Self::Err> {
     }
 }
 
+/// isa | pcie_port=\<name\>
+#[derive(Clone, Debug)]
+pub enum PvPanicCli {
+    Isa,
+    Pcie(String),
+}
+
+impl FromStr for PvPanicCli {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.split_once('=') {
+            None if s == "isa" => Ok(PvPanicCli::Isa),
+            Some(("pcie_port", port)) if !port.is_empty() => Ok(PvPanicCli::Pcie(port.into())),
+            _ => anyhow::bail!("expected `isa` or `pcie_port=<name>`"),
+        }
+    }
+}
+
+/// pause | continue | reset | dump=\<path\> | exit[=\<code\>]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GuestPanicActionCli {
+    Pause,
+    Continue,
+    Reset,
+    Dump(PathBuf),
+    Exit(i32),
+}
+
+impl FromStr for GuestPanicActionCli {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let action = match s.split_once('=') {
+            None => match s {
+                "pause" => GuestPanicActionCli::Pause,
+                "continue" => GuestPanicActionCli::Continue,
+                "reset" => GuestPanicActionCli::Reset,
+                "exit" => GuestPanicActionCli::Exit(1),
+                _ => anyhow::bail!("unknown action: '{s}'"),
+            },
+            Some(("dump", path)) if !path.is_empty() => GuestPanicActionCli::Dump(path.into()),
+            Some(("exit", code)) => {
+                GuestPanicActionCli::Exit(code.parse().context("invalid exit code")?)
+            }
+            _ => anyhow::bail!("unknown action: '{s}'"),
+        };
+        Ok(action)
+    }
+}
+
+/// name=\<key\>,file=\<path\> | name=\<key\>,string=\<s\>
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FwCfgFileCli {
+    pub name: String,
+    pub data: FwCfgFileDataCli,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FwCfgFileDataCli {
+    File(PathBuf),
+    String(String),
+}
+
+impl FromStr for FwCfgFileCli {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let (name, data) = s
+            .strip_prefix("name=")
+            .and_then(|s| s.split_once(','))
+            .context("expected `name=<key>,file=<path>` or `name=<key>,string=<s>`")?;
+        if name.is_empty() {
+            anyhow::bail!("empty fw_cfg file name");
+        }
+        let data = match data.split_once('=') {
+            Some(("file", path)) if !path.is_empty() => FwCfgFileDataCli::File(path.into()),
+            Some(("string", string)) => FwCfgFileDataCli::String(string.into()),
+            _ => anyhow::bail!("expected `file=<path>` or `string=<s>`, got '{data}'"),
+        };
+        Ok(FwCfgFileCli {
+            name: name.into(),
+            data,
+        })
+    }
+}
+
+/// type=\<n\>,\<key\>=\<value\>,...
+#[derive(Clone, Debug)]
+pub struct SmbiosCli(Vec<SmbiosFieldCli>);
+
+#[derive(Clone, Debug)]
+enum SmbiosFieldCli {
+    String(fn(&mut SmbiosConfig) -> &mut Option<String>, String),
+    Uuid(Guid),
+    OemString(String),
+}
+
+impl SmbiosCli {
+    /// Sets the fields in `config`, replacing any previous values.
+    pub fn apply(&self, config: &mut SmbiosConfig) {
+        for field in &self.0 {
+            match field {
+                SmbiosFieldCli::String(get, value) => *get(config) = Some(value.clone()),
+                SmbiosFieldCli::Uuid(uuid) => config.system_uuid = Some(*uuid),
+                SmbiosFieldCli::OemString(value) => config.oem_strings.push(value.clone()),
+            }
+        }
+    }
+}
+
+impl FromStr for SmbiosCli {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        type Field = fn(&mut SmbiosConfig) -> &mut Option<String>;
+
+        let (typ, rest) = s
+            .strip_prefix("type=")
+            .and_then(|s| s.split_once(','))
+            .context("expected `type=<n>,<key>=<value>,...`")?;
+
+        if typ == "11" {
+            let value = rest
+                .strip_prefix("value=")
+                .context("expected `value=<s>` for type 11")?;
+            return Ok(SmbiosCli(vec![SmbiosFieldCli::OemString(value.into())]));
+        }
+
+        let keys: &[(&str, Field)] = match typ {
+            "0" => &[
+                ("vendor", |c| &mut c.bios_vendor),
+                ("version", |c| &mut c.bios_version),
+                ("date", |c| &mut c.bios_release_date),
+            ],
+            "1" => &[
+                ("manufacturer", |c| &mut c.system_manufacturer),
+                ("product", |c| &mut c.system_product_name),
+                ("version", |c| &mut c.system_version),
+                ("serial", |c| &mut c.system_serial_number),
+                ("sku", |c| &mut c.system_sku_number),
+                ("family", |c| &mut c.system_family),
+            ],
+            "2" => &[
+                ("manufacturer", |c| &mut c.baseboard_manufacturer),
+                ("product", |c| &mut c.baseboard_product),
+                ("version", |c| &mut c.baseboard_version),
+                ("serial", |c| &mut c.baseboard_serial_number),
+                ("asset", |c| &mut c.baseboard_asset_tag),
+            ],
+            "3" => &[
+                ("manufacturer", |c| &mut c.chassis_manufacturer),
+                ("version", |c| &mut c.chassis_version),
+                ("serial", |c| &mut c.chassis_serial_number),
+                ("asset", |c| &mut c.chassis_asset_tag),
+                ("sku", |c| &mut c.chassis_sku_number),
+            ],
+            "4" => &[
+                ("manufacturer", |c| &mut c.processor_manufacturer),
+                ("version", |c| &mut c.processor_version),
+            ],
+            _ => anyhow::bail!("unsupported SMBIOS type '{typ}'"),
+        };
+
+        let fields = rest
+            .split(',')
+            .map(|field| {
+                let (key, value) = field
+                    .split_once('=')
+                    .with_context(|| format!("expected `<key>=<value>`, got '{field}'"))?;
+                if typ == "1" && key == "uuid" {
+                    return Ok(SmbiosFieldCli::Uuid(
+                        value.parse().context("invalid SMBIOS uuid")?,
+                    ));
+                }
+                let &(_, get) = keys
+                    .iter()
+                    .find(|&&(k, _)| k == key)
+                    .with_context(|| format!("unknown key '{key}' for SMBIOS type {typ}"))?;
+                Ok(SmbiosFieldCli::String(get, value.into()))
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(SmbiosCli(fields))
+    }
+}
+
+/// node=\<n\>,cpus=\<list\>,mem=\<size\>\[,host-node=\<n\>\] |
+/// dist,src=\<n\>,dst=\<n\>,val=\<distance\>
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NumaCli {
+    Node {
+        node: u32,
+        vps: Vec<u32>,
+        mem_size: u64,
+        host_node: Option<u32>,
+    },
+    Distance {
+        src: u32,
+        dst: u32,
+        distance: u8,
+    },
+}
+
+impl FromStr for NumaCli {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let (is_distance, rest) = match s.strip_prefix("dist,") {
+            Some(rest) => (true, rest),
+            None => (false, s),
+        };
+
+        let mut node = None;
+        let mut vps = Vec::new();
+        let mut mem_size = None;
+        let mut host_node = None;
+        let mut src = None;
+        let mut dst = None;
+        let mut distance = None;
+        for field in rest.split(',') {
+            let (key, value) = field
+                .split_once('=')
+                .with_context(|| format!("expected `<key>=<value>`, got '{field}'"))?;
+            let number = || {
+                value
+                    .parse::<u32>()
+                    .with_context(|| format!("invalid number for '{key}'"))
+            };
+            match (is_distance, key) {
+                (false, "node") => node = Some(number()?),
+                (false, "cpus") => {
+                    let (first, last) = value.split_once('-').unwrap_or((value, value));
+                    let first: u32 = first.parse().context("invalid cpu index")?;
+                    let last: u32 = last.parse().context("invalid cpu index")?;
+                    if last < first {
+                        anyhow::bail!("invalid cpu range '{value}'");
+                    }
+                    vps.extend(first..=last);
+                }
+                (false, "mem") => mem_size = Some(parse_memory(value)?),
+                (false, "host-node") => host_node = Some(number()?),
+                (true, "src") => src = Some(number()?),
+                (true, "dst") => dst = Some(number()?),
+                (true, "val") => {
+                    distance = Some(value.parse().context("invalid distance")?);
+                }
+                _ => anyhow::bail!("unknown key '{key}'"),
+            }
+        }
+
+        if is_distance {
+            Ok(NumaCli::Distance {
+                src: src.context("missing `src`")?,
+                dst: dst.context("missing `dst`")?,
+                distance: distance.context("missing `val`")?,
+            })
+        } else {
+            Ok(NumaCli::Node {
+                node: node.context("missing `node`")?,
+                vps,
+                mem_size: mem_size.context("missing `mem`")?,
+                host_node,
+            })
+        }
+    }
+}
+
+/// \<path\>,pcie_port=\<name\>
+#[derive(Clone, Debug)]
+pub struct VfioUserCli {
+    pub socket_path: String,
+    pub pcie_port: String,
+}
+
+impl FromStr for VfioUserCli {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let (socket_path, port) = s
+            .split_once(',')
+            .and_then(|(path, opt)| Some((path, opt.strip_prefix("pcie_port=")?)))
+            .filter(|(path, port)| !path.is_empty() && !port.is_empty())
+            .context("expected `<path>,pcie_port=<name>`")?;
+        Ok(Self {
+            socket_path: socket_path.into(),
+            pcie_port: port.into(),
+        })
+    }
+}
+
 #[derive(Copy, Clone, Debug, ValueEnum)]
 pub enum UefiConsoleModeCli {
     Default,
@@ -1224,3 +1848,96 @@ fn from(s: &std::ffi::OsStr) -> Self {
         OptionalPathBuf(if s.is_empty() { None } else { Some(s.into()) })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn test_serial_file() {
+        let file: SerialFileCli = "out.log".parse().unwrap();
+        assert_eq!(file.path, Path::new("out.log"));
+        assert!(!file.timestamps);
+        assert_eq!(file.rotate, None);
+
+        let file: SerialFileCli = "out.log,timestamps".parse().unwrap();
+        assert!(file.timestamps);
+        assert_eq!(file.rotate, None);
+
+        let file: SerialFileCli = "out.log,rotate=1M,timestamps".parse().unwrap();
+        assert!(file.timestamps);
+        assert_eq!(file.rotate, Some(1024 * 1024));
+
+        let file: SerialFileCli = "out.log,rotate=4096".parse().unwrap();
+        assert!(!file.timestamps);
+        assert_eq!(file.rotate, Some(4096));
+
+        for (s, err) in [
+            ("", "missing file path"),
+            (",timestamps", "missing file path"),
+            ("out.log,rotate=0", "rotate size must be non-zero"),
+            ("out.log,rotate=big", "invalid memory size 'big'"),
+            (
+                "out.log,timestamps=1",
+                "unknown file option: 'timestamps=1'",
+            ),
+            ("out.log,append", "unknown file option: 'append'"),
+        ] {
+            assert_eq!(
+                s.parse::<SerialFileCli>().err().as_deref(),
+                Some(err),
+                "{s}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_serial_tee() {
+        let SerialConfigCli::File(file) = "file=out.log,timestamps"
+            .parse::<SerialConfigCli>()
+            .unwrap()
+        else {
+            panic!("expected file");
+        };
+        assert_eq!(file.path, Path::new("out.log"));
+        assert!(file.timestamps);
+
+        // File options stay with the file they follow.
+        let SerialConfigCli::Tee(targets) =
+            "tee=console,file=a.log,timestamps,rotate=1K,stderr,file=b.log"
+                .parse::<SerialConfigCli>()
+                .unwrap()
+        else {
+            panic!("expected tee");
+        };
+        assert_eq!(targets.len(), 4);
+        assert!(matches!(targets[0], SerialConfigCli::Console));
+        let SerialConfigCli::File(a) = &targets[1] else {
+            panic!("expected file");
+        };
+        assert!(matches!(targets[2], SerialConfigCli::Stderr));
+        let SerialConfigCli::File(b) = &targets[3] else {
+            panic!("expected file");
+        };
+        assert_eq!(a.path, Path::new("a.log"));
+        assert!(a.timestamps);
+        assert_eq!(a.rotate, Some(1024));
+        assert_eq!(b.path, Path::new("b.log"));
+        assert!(!b.timestamps);
+        assert_eq!(b.rotate, None);
+
+        for (s, err) in [
+            ("tee=console,timestamps", "invalid serial configuration"),
+            ("tee=console,tee=stderr", "tee cannot be nested"),
+            ("tee=file=a.log,bogus", "invalid serial configuration"),
+            ("file=a.log,rotate=0", "rotate size must be non-zero"),
+        ] {
+            assert_eq!(
+                s.parse::<SerialConfigCli>().err().as_deref(),
+                Some(err),
+                "{s}"
+            );
+        }
+    }
+}
@@ -0,0 +1,538 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Guest memory dumps.
+//!
+//! Dumps are written as ELF core files in the same layout as QEMU's
+//! `dump-guest-memory` command, so they can be loaded with `crash` or `gdb`:
+//! a `PT_NOTE` segment with a `NT_PRSTATUS` note per VP (plus a `QEMU` note
+//! with the system registers on x86_64), followed by a `PT_LOAD` segment per
+//! guest RAM range, with physical addresses in `p_paddr`.
+
+use anyhow::Context;
+use futures::AsyncWriteExt;
+use hvlite_defs::rpc::VmRpc;
+use memory_range::MemoryRange;
+use mesh::rpc::RpcSend;
+use object::elf;
+use object::endian::LittleEndian as LE;
+use object::endian::U16;
+use object::endian::U32;
+use object::endian::U64;
+use std::path::Path;
+use vmm_core_defs::debug_rpc::DebuggerVpState;
+use vmm_core_defs::debug_rpc::X86VpState;
+use zerocopy::AsBytes;
+use zerocopy::FromBytes;
+use zerocopy::FromZeroes;
+
+const PAGE_SIZE: u64 = 4096;
+
+/// The amount of memory to read from the VM at once.
+const CHUNK_SIZE: usize = 1024 * 1024;
+
+/// The part of the Linux `elf_prstatus` structure before the registers.
+#[repr(C)]
+#[derive(AsBytes, FromBytes, FromZeroes)]
+struct PrStatusPrefix {
+    si_signo: i32,
+    si_code: i32,
+    si_errno: i32,
+    pr_cursig: i16,
+    _pad: u16,
+    pr_sigpend: u64,
+    pr_sighold: u64,
+    pr_pid: i32,
+    pr_ppid: i32,
+    pr_pgrp: i32,
+    pr_sid: i32,
+    /// User, system, and cumulative user and system times, as `timeval`s.
+    pr_times: [u64; 8],
+}
+
+/// QEMU's x86 segment register format.
+#[repr(C)]
+#[derive(AsBytes, FromBytes, FromZeroes)]
+struct QemuSegment {
+    selector: u32,
+    limit: u32,
+    flags: u32,
+    _pad: u32,
+    base: u64,
+}
+
+/// QEMU's x86 CPU state note (`QEMUCPUState`), used by `crash` to find the
+/// kernel's page tables.
+#[repr(C)]
+#[derive(AsBytes, FromBytes, FromZeroes)]
+struct QemuCpuState {
+    version: u32,
+    size: u32,
+    rax: u64,
+    rbx: u64,
+    rcx: u64,
+    rdx: u64,
+    rsi: u64,
+    rdi: u64,
+    rsp: u64,
+    rbp: u64,
+    r8: u64,
+    r9: u64,
+    r10: u64,
+    r11: u64,
+    r12: u64,
+    r13: u64,
+    r14: u64,
+    r15: u64,
+    rip: u64,
+    rflags: u64,
+    cs: QemuSegment,
+    ds: QemuSegment,
+    es: QemuSegment,
+    fs: QemuSegment,
+    gs: QemuSegment,
+    ss: QemuSegment,
+    ldt: QemuSegment,
+    tr: QemuSegment,
+    gdt: QemuSegment,
+    idt: QemuSegment,
+    cr: [u64; 5],
+    kernel_gs_base: u64,
+}
+
+fn qemu_segment(reg: &virt::x86::SegmentRegister) -> QemuSegment {
+    QemuSegment {
+        selector: reg.selector.into(),
+        limit: reg.limit,
+        // QEMU stores the attributes as they appear in the descriptor.
+        flags: u32::from(reg.attributes) << 8,
+        _pad: 0,
+        base: reg.base,
+    }
+}
+
+fn qemu_table(reg: &virt::x86::TableRegister) -> QemuSegment {
+    QemuSegment {
+        limit: reg.limit.into(),
+        base: reg.base,
+        ..FromZeroes::new_zeroed()
+    }
+}
+
+fn qemu_cpu_state(state: &X86VpState) -> QemuCpuState {
+    let [rax, rcx, rdx, rbx, rsp, rbp, rsi, rdi, r8, r9, r10, r11, r12, r13, r14, r15] = state.gp;
+    QemuCpuState {
+        version: 1,
+        size: size_of::<QemuCpuState>() as u32,
+        rax,
+        rbx,
+        rcx,
+        rdx,
+        rsi,
+        rdi,
+        rsp,
+        rbp,
+        r8,
+        r9,
+        r10,
+        r11,
+        r12,
+        r13,
+        r14,
+        r15,
+        rip: state.rip,
+        rflags: state.rflags,
+        cs: qemu_segment(&state.cs),
+        ds: qemu_segment(&state.ds),
+        es: qemu_segment(&state.es),
+        fs: qemu_segment(&state.fs),
+        gs: qemu_segment(&state.gs),
+        ss: qemu_segment(&state.ss),
+        ldt: qemu_segment(&state.ldtr),
+        tr: qemu_segment(&state.tr),
+        gdt: qemu_table(&state.gdtr),
+        idt: qemu_table(&state.idtr),
+        cr: [state.cr0, 0, state.cr2, state.cr3, state.cr4],
+        kernel_gs_base: state.kernel_gs_base,
+    }
+}
+
+/// Returns the registers in the order of the Linux `user_regs_struct` for
+/// the VP's architecture.
+fn prstatus_regs(state: &DebuggerVpState) -> Vec<u64> {
+    match state {
+        DebuggerVpState::X86_64(state) => {
+            let [rax, rcx, rdx, rbx, rsp, rbp, rsi, rdi, r8, r9, r10, r11, r12, r13, r14, r15] =
+                state.gp;
+            vec![
+                r15,
+                r14,
+                r13,
+                r12,
+                rbp,
+                rbx,
+                r11,
+                r10,
+                r9,
+                r8,
+                rax,
+                rcx,
+                rdx,
+                rsi,
+                rdi,
+                rax, // orig_rax
+                state.rip,
+                state.cs.selector.into(),
+                state.rflags,
+                rsp,
+                state.ss.selector.into(),
+                state.fs.base,
+                state.gs.base,
+                state.ds.selector.into(),
+                state.es.selector.into(),
+                state.fs.selector.into(),
+                state.gs.selector.into(),
+            ]
+        }
+        DebuggerVpState::Aarch64(state) => {
+            // PSTATE.SP selects between SP_EL0 and SP_ELx.
+            let sp = if state.cpsr & 1 != 0 {
+                state.sp_el1
+            } else {
+                state.sp_el0
+            };
+            let mut regs = state.x.to_vec();
+            regs.extend([sp, state.pc, state.cpsr]);
+            regs
+        }
+    }
+}
+
+/// Appends an ELF note to `notes`.
+fn push_note(notes: &mut Vec<u8>, name: &[u8], ty: u32, desc: &[u8]) {
+    let header = elf::NoteHeader64::<LE> {
+        n_namesz: U32::new(LE, name.len() as u32 + 1),
+        n_descsz: U32::new(LE, desc.len() as u32),
+        n_type: U32::new(LE, ty),
+    };
+    notes.extend_from_slice(object::bytes_of(&header));
+    notes.extend_from_slice(name);
+    notes.push(0);
+    notes.resize(notes.len().next_multiple_of(4), 0);
+    notes.extend_from_slice(desc);
+    notes.resize(notes.len().next_multiple_of(4), 0);
+}
+
+/// Builds the per-VP notes.
+fn vp_notes(vps: &[DebuggerVpState]) -> Vec<u8> {
+    let mut notes = Vec::new();
+    for (i, state) in vps.iter().enumerate() {
+        let prefix = PrStatusPrefix {
+            pr_pid: i as i32 + 1,
+            ..FromZeroes::new_zeroed()
+        };
+        let mut desc = prefix.as_bytes().to_vec();
+        desc.extend_from_slice(prstatus_regs(state).as_bytes());
+        // pr_fpvalid and padding.
+        desc.extend_from_slice(&[0; 8]);
+        push_note(&mut notes, b"CORE", elf::NT_PRSTATUS, &desc);
+    }
+    for state in vps {
+        if let DebuggerVpState::X86_64(state) = state {
+            push_note(&mut notes, b"QEMU", 0, qemu_cpu_state(state).as_bytes());
+        }
+    }
+    notes
+}
+
+/// The layout of an ELF core file.
+struct ElfCore {
+    /// The ELF header, program headers, and notes.
+    headers: Vec<u8>,
+    /// The RAM ranges and their offsets in the file, in file order.
+    segments: Vec<(MemoryRange, u64)>,
+}
+
+fn elf_core(machine: u16, ram: &[MemoryRange], notes: &[u8]) -> ElfCore {
+    let ehdr_size = size_of::<elf::FileHeader64<LE>>() as u64;
+    let phdr_size = size_of::<elf::ProgramHeader64<LE>>() as u64;
+    let phnum = ram.len() as u64 + 1;
+    let notes_offset = ehdr_size + phdr_size * phnum;
+    let mut offset = (notes_offset + notes.len() as u64).next_multiple_of(PAGE_SIZE);
+
+    let ehdr = elf::FileHeader64::<LE> {
+        e_ident: elf::Ident {
+            magic: elf::ELFMAG,
+            class: elf::ELFCLASS64,
+            data: elf::ELFDATA2LSB,
+            version: elf::EV_CURRENT,
+            os_abi: elf::ELFOSABI_NONE,
+            abi_version: 0,
+            padding: [0; 7],
+        },
+        e_type: U16::new(LE, elf::ET_CORE),
+        e_machine: U16::new(LE, machine),
+        e_version: U32::new(LE, elf::EV_CURRENT.into()),
+        e_entry: U64::new(LE, 0),
+        e_phoff: U64::new(LE, ehdr_size),
+        e_shoff: U64::new(LE, 0),
+        e_flags: U32::new(LE, 0),
+        e_ehsize: U16::new(LE, ehdr_size as u16),
+        e_phentsize: U16::new(LE, phdr_size as u16),
+        e_phnum: U16::new(LE, phnum as u16),
+        e_shentsize: U16::new(LE, 0),
+        e_shnum: U16::new(LE, 0),
+        e_shstrndx: U16::new(LE, 0),
+    };
+
+    let mut headers = object::bytes_of(&ehdr).to_vec();
+    let note_phdr = elf::ProgramHeader64::<LE> {
+        p_type: U32::new(LE, elf::PT_NOTE),
+        p_flags: U32::new(LE, 0),
+        p_offset: U64::new(LE, notes_offset),
+        p_vaddr: U64::new(LE, 0),
+        p_paddr: U64::new(LE, 0),
+        p_filesz: U64::new(LE, notes.len() as u64),
+        p_memsz: U64::new(LE, notes.len() as u64),
+        p_align: U64::new(LE, 0),
+    };
+    headers.extend_from_slice(object::bytes_of(&note_phdr));
+
+    let mut segments = Vec::new();
+    for range in ram {
+        let phdr = elf::ProgramHeader64::<LE> {
+            p_type: U32::new(LE, elf::PT_LOAD),
+            p_flags: U32::new(LE, elf::PF_R | elf::PF_W | elf::PF_X),
+            p_offset: U64::new(LE, offset),
+            // Like QEMU without paging, only the physical address is known.
+            p_vaddr: U64::new(LE, 0),
+            p_paddr: U64::new(LE, range.start()),
+            p_filesz: U64::new(LE, range.len()),
+            p_memsz: U64::new(LE, range.len()),
+            p_align: U64::new(LE, 0),
+        };
+        headers.extend_from_slice(object::bytes_of(&phdr));
+        segments.push((*range, offset));
+        offset += range.len();
+    }
+
+    headers.extend_from_slice(notes);
+    ElfCore { headers, segments }
+}
+
+/// Writes an ELF core dump of the guest to `path`.
+///
+/// The VM should be paused first to get a consistent dump.
+pub async fn dump_guest(vm_rpc: &mesh::Sender<VmRpc>, path: &Path) -> anyhow::Result<()> {
+    let ram = vm_rpc
+        .call(VmRpc::GetRamRanges, ())
+        .await
+        .context("failed to get ram ranges")?;
+
+    let vps = vm_rpc
+        .call_failable(VmRpc::GetVpStates, ())
+        .await
+        .context("failed to get vp state")?;
+
+    let machine = if cfg!(guest_arch = "aarch64") {
+        elf::EM_AARCH64
+    } else {
+        elf::EM_X86_64
+    };
+    let core = elf_core(machine, &ram, &vp_notes(&vps));
+
+    let file = blocking::unblock({
+        let path = path.to_owned();
+        move || fs_err::File::create(path)
+    })
+    .await?;
+    // Write from a blocking thread so that slow storage does not stall the
+    // console.
+    let mut file = blocking::Unblock::with_capacity(CHUNK_SIZE, file);
+    file.write_all(&core.headers).await?;
+    let mut written = core.headers.len() as u64;
+    for (range, offset) in core.segments {
+        file.write_all(&vec![0; (offset - written) as usize])
+            .await?;
+        for gpa in (range.start()..range.end()).step_by(CHUNK_SIZE) {
+            let len = (range.end() - gpa).min(CHUNK_SIZE as u64) as usize;
+            let data = vm_rpc
+                .call_failable(VmRpc::ReadMemory, (gpa, len))
+                .await
+                .with_context(|| format!("failed to read guest memory at {gpa:#x}"))?;
+            file.write_all(&data).await?;
+        }
+        written = offset + range.len();
+    }
+    file.flush().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object::read::elf::FileHeader;
+    use object::read::elf::ProgramHeader;
+    use vmm_core_defs::debug_rpc::Aarch64VpState;
+
+    fn x86_state() -> X86VpState {
+        let segment = |selector: u16| virt::x86::SegmentRegister {
+            selector,
+            base: 0x1000 * u64::from(selector),
+            limit: 0xffff,
+            attributes: 0x93,
+        };
+        X86VpState {
+            gp: std::array::from_fn(|i| i as u64),
+            rip: 0xffff_8000_0000_1234,
+            rflags: 0x202,
+            cr0: 0x8005_0033,
+            cr2: 0x2000,
+            cr3: 0x1_0000,
+            cr4: 0x6f0,
+            cr8: 0,
+            efer: 0xd01,
+            kernel_gs_base: 0x5000,
+            es: segment(0x18),
+            cs: segment(0x10),
+            ss: segment(0x18),
+            ds: segment(0x18),
+            fs: segment(0),
+            gs: segment(0),
+            tr: segment(0x40),
+            ldtr: segment(0),
+            gdtr: virt::x86::TableRegister {
+                base: 0x3000,
+                limit: 0x7f,
+            },
+            idtr: virt::x86::TableRegister {
+                base: 0x4000,
+                limit: 0xfff,
+            },
+        }
+    }
+
+    /// Returns the notes in the `PT_NOTE` segment of an ELF core.
+    fn parse_notes(data: &[u8]) -> Vec<object::read::elf::Note<'_, elf::FileHeader64<LE>>> {
+        let header = elf::FileHeader64::<LE>::parse(data).unwrap();
+        let phdrs = header.program_headers(LE, data).unwrap();
+        phdrs[0]
+            .notes(LE, data)
+            .unwrap()
+            .unwrap()
+            .map(|note| note.unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_push_note() {
+        let mut notes = Vec::new();
+        push_note(&mut notes, b"CORE", elf::NT_PRSTATUS, &[1, 2, 3, 4, 5]);
+        // The header, then the name and descriptor, each padded to a multiple
+        // of 4 bytes.
+        assert_eq!(notes.len(), 12 + 8 + 8);
+        assert_eq!(&notes[..4], 5u32.to_le_bytes());
+        assert_eq!(&notes[4..8], 5u32.to_le_bytes());
+        assert_eq!(&notes[8..12], elf::NT_PRSTATUS.to_le_bytes());
+        assert_eq!(&notes[12..20], b"CORE\0\0\0\0");
+        assert_eq!(&notes[20..], [1, 2, 3, 4, 5, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_vp_notes() {
+        let state = x86_state();
+        let core = elf_core(
+            elf::EM_X86_64,
+            &[],
+            &vp_notes(&[DebuggerVpState::X86_64(x86_state())]),
+        );
+        let notes = parse_notes(&core.headers);
+        assert_eq!(notes.len(), 2);
+
+        let prstatus = &notes[0];
+        assert_eq!(prstatus.name(), b"CORE");
+        assert_eq!(prstatus.n_type(LE), elf::NT_PRSTATUS);
+        // The size of the Linux x86_64 `elf_prstatus`.
+        assert_eq!(prstatus.desc().len(), 336);
+        let prefix = PrStatusPrefix::read_from_prefix(prstatus.desc()).unwrap();
+        assert_eq!(prefix.pr_pid, 1);
+        let regs = prstatus.desc()[size_of::<PrStatusPrefix>()..][..27 * 8]
+            .chunks_exact(8)
+            .map(|b| u64::from_le_bytes(b.try_into().unwrap()))
+            .collect::<Vec<_>>();
+        // rax, rip, and rsp.
+        assert_eq!(regs[10], 0);
+        assert_eq!(regs[16], state.rip);
+        assert_eq!(regs[19], state.gp[4]);
+
+        let qemu = &notes[1];
+        assert_eq!(qemu.name(), b"QEMU");
+        let cpu = QemuCpuState::read_from(qemu.desc()).unwrap();
+        assert_eq!(cpu.size as usize, size_of::<QemuCpuState>());
+        assert_eq!(cpu.rip, state.rip);
+        assert_eq!(cpu.cr, [state.cr0, 0, state.cr2, state.cr3, state.cr4]);
+        assert_eq!((cpu.cs.selector, cpu.cs.base), (0x10, 0x10000));
+        assert_eq!(cpu.cs.flags, 0x93 << 8);
+        assert_eq!((cpu.tr.selector, cpu.tr.base), (0x40, 0x40000));
+        assert_eq!((cpu.gdt.base, cpu.gdt.limit), (0x3000, 0x7f));
+        assert_eq!((cpu.idt.base, cpu.idt.limit), (0x4000, 0xfff));
+    }
+
+    #[test]
+    fn test_aarch64_prstatus() {
+        let state = Aarch64VpState {
+            x: std::array::from_fn(|i| i as u64),
+            sp_el0: 0x1000,
+            sp_el1: 0x2000,
+            pc: 0x3000,
+            cpsr: 0x3c5,
+            sctlr_el1: 0,
+            tcr_el1: 0,
+            ttbr0_el1: 0,
+            ttbr1_el1: 0,
+        };
+        let regs = prstatus_regs(&DebuggerVpState::Aarch64(state));
+        // x0-x30, sp, pc, and pstate, with sp from SP_EL1 since PSTATE.SP is
+        // set.
+        assert_eq!(regs.len(), 34);
+        assert_eq!(regs[30], 30);
+        assert_eq!(regs[31..], [0x2000, 0x3000, 0x3c5]);
+    }
+
+    #[test]
+    fn test_elf_core() {
+        let ram = [
+            MemoryRange::new(0..0x2000),
+            MemoryRange::new(0x10_0000..0x10_1000),
+        ];
+        let notes = vp_notes(&[DebuggerVpState::X86_64(x86_state())]);
+        let core = elf_core(elf::EM_X86_64, &ram, &notes);
+
+        let header = elf::FileHeader64::<LE>::parse(core.headers.as_slice()).unwrap();
+        assert_eq!(header.e_type(LE), elf::ET_CORE);
+        assert_eq!(header.e_machine(LE), elf::EM_X86_64);
+        let phdrs = header.program_headers(LE, core.headers.as_slice()).unwrap();
+        assert_eq!(phdrs.len(), 3);
+
+        let note_phdr = &phdrs[0];
+        assert_eq!(note_phdr.p_type(LE), elf::PT_NOTE);
+        assert_eq!(
+            note_phdr.data(LE, core.headers.as_slice()).unwrap(),
+            notes.as_slice()
+        );
+        assert_eq!(parse_notes(&core.headers).len(), 2);
+
+        let first = (core.headers.len() as u64).next_multiple_of(PAGE_SIZE);
+        assert_eq!(
+            core.segments,
+            [(ram[0], first), (ram[1], first + ram[0].len())]
+        );
+        for (phdr, (range, offset)) in phdrs[1..].iter().zip(&core.segments) {
+            assert_eq!(phdr.p_type(LE), elf::PT_LOAD);
+            assert_eq!(phdr.p_paddr(LE), range.start());
+            assert_eq!(phdr.p_offset(LE), *offset);
+            assert_eq!(phdr.p_filesz(LE), range.len());
+            assert_eq!(phdr.p_memsz(LE), range.len());
+        }
+    }
+}
@@ -5,7 +5,9 @@
 //! for the worker process.
 
 mod cli_args;
+mod guest_dump;
 mod meshworker;
+mod screenshot;
 mod serial_io;
 mod storage_builder;
 mod tracing_init;
@@ -28,6 +30,7 @@
 use cli_args::SerialConfigCli;
 use cli_args::UefiConsoleModeCli;
 use cli_args::VirtioBusCli;
+use cli_args::VirtioInputCli;
 use disk_backend_resources::layer::DiskLayerHandle;
 use disk_backend_resources::layer::RamDiskLayerHandle;
 use disk_backend_resources::layer::SqliteDiskLayerHandle;
@@ -42,6 +45,10 @@
 use futures::FutureExt;
 use futures::StreamExt;
 use futures_concurrency::stream::Merge;
+use fw_cfg_resources::FwCfgDeviceHandle;
+use fw_cfg_resources::FwCfgFile;
+use fw_cfg_resources::FwCfgFileData;
+use fw_cfg_resources::FwCfgRegisterLayout;
 use gdma_resources::GdmaDeviceHandle;
 use gdma_resources::VportDefinition;
 use guid::Guid;
@@ -51,8 +58,13 @@
 use hvlite_defs::config::LateMapVtl0MemoryPolicy;
 use hvlite_defs::config::LoadMode;
 use hvlite_defs::config::MemoryConfig;
+use hvlite_defs::config::NumaConfig;
+use hvlite_defs::config::NumaNodeConfig;
+use hvlite_defs::config::PcieDeviceConfig;
+use hvlite_defs::config::PcieRootPortConfig;
 use hvlite_defs::config::ProcessorTopologyConfig;
 use hvlite_defs::config::SerialInformation;
+use hvlite_defs::config::SmbiosConfig;
 use hvlite_defs::config::VirtioBus;
 use hvlite_defs::config::VmbusConfig;
 use hvlite_defs::config::VpciDeviceConfig;
@@ -88,13 +100,17 @@
 use pal_async::timer::PolledTimer;
 use pal_async::DefaultDriver;
 use pal_async::DefaultPool;
+use pvpanic_resources::PvPanicPciDeviceHandle;
 use scsidisk_resources::SimpleScsiDiskHandle;
 use scsidisk_resources::SimpleScsiDvdHandle;
 use serial_16550_resources::ComPort;
+use serial_core::expect::Expect;
 use serial_core::resources::DisconnectedSerialBackendHandle;
+use serial_io::ConsoleTap;
 use serial_io::SerialIo;
 use sparse_mmap::alloc_shared_memory;
 use std::cell::RefCell;
+use std::collections::BTreeMap;
 use std::fmt::Write as _;
 use std::future::pending;
 use std::io;
@@ -136,7 +152,9 @@
 use vmcore::non_volatile_store::resources::EphemeralNonVolatileStoreHandle;
 use vmgs_resources::VmgsFileHandle;
 use vmotherboard::ChipsetDeviceHandle;
+use vnc_worker_defs::VncAuth;
 use vnc_worker_defs::VncParameters;
+use vnc_worker_defs::VncTls;
 
 pub fn hvlite_main() {
     // Save the current state of the terminal so we can restore it back to
@@ -147,8 +165,12 @@ pub fn hvlite_main() {
     let exit_code = match do_main() {
         Ok(_) => 0,
         Err(err) => {
-            eprintln!("fatal error: {:?}", err);
-            1
+            if let Some(GuestPanicExit(code)) = err.downcast_ref() {
+                *code
+            } else {
+                eprintln!("fatal error: {:?}", err);
+                1
+            }
         }
     };
 
@@ -167,9 +189,16 @@ pub fn hvlite_main() {
     pal::process::terminate(exit_code);
 }
 
+/// Returned by [`run_control`] when the VM was stopped due to a guest panic,
+/// to exit the process with the configured exit code.
+#[derive(Debug, thiserror::Error)]
+#[error("guest panicked")]
+struct GuestPanicExit(i32);
+
 #[derive(Default)]
 struct VmResources {
     console_in: Option<Box<dyn AsyncWrite + Send + Unpin>>,
+    console_tap: ConsoleTap,
     framebuffer_access: Option<FramebufferAccess>,
     shutdown_ic: Option<mesh::Sender<hyperv_ic_resources::shutdown::ShutdownRpc>>,
     scsi_rpc: Option<mesh::Sender<ScsiControllerRequest>>,
@@ -203,6 +232,7 @@ fn vm_config_from_command_line(
     };
 
     let console_state: RefCell<Option<ConsoleState<'_>>> = RefCell::new(None);
+    let console_tap = ConsoleTap::default();
     let setup_serial = |name: &str, cli_cfg, device| -> anyhow::Result<_> {
         Ok(match cli_cfg {
             SerialConfigCli::Console => {
@@ -215,12 +245,13 @@ fn vm_config_from_command_line(
                     device,
                     input: Box::new(serial_write),
                 });
+                let console_tap = console_tap.clone();
                 thread::Builder::new()
                     .name(name.to_owned())
                     .spawn(move || {
                         let _ = block_on(futures::io::copy(
                             serial_read,
-                            &mut AllowStdIo::new(term::raw_stdout()),
+                            &mut AllowStdIo::new(console_tap.writer(term::raw_stdout())),
                         ));
                     })
                     .unwrap();
@@ -255,6 +286,29 @@ fn vm_config_from_command_line(
 
                 Some(config)
             }
+            cfg @ (SerialConfigCli::File(_) | SerialConfigCli::Tee(_)) => {
+                let (config, serial) = serial_io::anonymous_serial_pair(&serial_driver)?;
+                let (serial_read, serial_write) = AsyncReadExt::split(serial);
+                let mut serial_write = Some(serial_write);
+                let output = serial_io::tee_output(cfg, &console_tap, || {
+                    if let Some(console_state) = console_state.borrow().as_ref() {
+                        bail!("console already set by {}", console_state.device);
+                    }
+                    *console_state.borrow_mut() = Some(ConsoleState {
+                        device,
+                        input: Box::new(serial_write.take().unwrap()),
+                    });
+                    Ok(())
+                })?;
+                thread::Builder::new()
+                    .name(name.to_owned())
+                    .spawn(move || {
+                        let _ =
+                            block_on(futures::io::copy(serial_read, &mut AllowStdIo::new(output)));
+                    })
+                    .unwrap();
+                Some(config)
+            }
         })
     };
 
@@ -266,7 +320,7 @@ fn vm_config_from_command_line(
                     bail!("console already set");
                 }
                 let mut io = SerialIo::new().context("creating serial IO")?;
-                io.spawn_copy_out(name, term::raw_stdout());
+                io.spawn_copy_out(name, console_tap.writer(term::raw_stdout()));
                 *console_state.borrow_mut() = Some(ConsoleState {
                     device,
                     input: Box::new(PolledPipe::new(&serial_driver, io.input.unwrap())?),
@@ -302,6 +356,27 @@ fn vm_config_from_command_line(
                     .context("failed to launch console")?;
                 Some(io.config)
             }
+            cfg @ (SerialConfigCli::File(_) | SerialConfigCli::Tee(_)) => {
+                let mut io = SerialIo::new().context("creating serial IO")?;
+                let mut input = io.input.take();
+                let output = serial_io::tee_output(cfg, &console_tap, || {
+                    if console_state.borrow().is_some() {
+                        bail!("console already set");
+                    }
+                    *console_state.borrow_mut() = Some(ConsoleState {
+                        device,
+                        input: Box::new(PolledPipe::new(&serial_driver, input.take().unwrap())?),
+                    });
+                    Ok(())
+                })?;
+                io.spawn_copy_out(name, output);
+                if input.is_some() {
+                    // Ensure there is no input so that the serial devices don't
+                    // see EOF and think the port is disconnected.
+                    io.config.input = None;
+                }
+                Some(io.config)
+            }
         })
     };
 
@@ -416,6 +491,7 @@ fn vm_config_from_command_line(
     let mut console_str = "";
     if let Some(ConsoleState { device, input }) = console_state.into_inner() {
         resources.console_in = Some(input);
+        resources.console_tap = console_tap;
         console_str = device;
     }
 
@@ -448,8 +524,12 @@ fn vm_config_from_command_line(
         read_only,
         is_dvd,
         underhill,
+        ref pcie_port,
     } in &opt.disk
     {
+        if pcie_port.is_some() {
+            anyhow::bail!("`pcie_port` is only supported for NVMe disks");
+        }
         storage.add(
             vtl,
             underhill,
@@ -484,8 +564,13 @@ fn vm_config_from_command_line(
         read_only,
         is_dvd,
         underhill,
+        ref pcie_port,
     } in &opt.nvme
     {
+        if let Some(port) = pcie_port {
+            storage.add_pcie_nvme(port, kind, is_dvd, read_only)?;
+            continue;
+        }
         storage.add(
             vtl,
             underhill,
@@ -650,7 +735,7 @@ fn vm_config_from_command_line(
         None
     };
 
-    let framebuffer = if opt.gfx || opt.vtl2_gfx || opt.vnc || opt.pcat {
+    let framebuffer = if opt.gfx || opt.vtl2_gfx || opt.vnc || opt.pcat || opt.virtio_gpu {
         let vram = alloc_shared_memory(FRAMEBUFFER_SIZE)?;
         let (fb, fba) =
             framebuffer::framebuffer(vram, FRAMEBUFFER_SIZE, 0).context("creating framebuffer")?;
@@ -706,6 +791,15 @@ fn vm_config_from_command_line(
         tx.send(HostBatteryUpdate::default_present());
         chipset = chipset.with_battery(rx);
     }
+    if !opt.pcie_root_port.is_empty() || matches!(opt.virtio_fs_bus, VirtioBusCli::Pcie) {
+        chipset = chipset.with_pcie_root_complex();
+    }
+    if opt.hpet {
+        chipset = chipset.with_hpet();
+    }
+    if let Some(cli_args::PvPanicCli::Isa) = opt.pvpanic {
+        chipset = chipset.with_pvpanic();
+    }
     if let Some(cfg) = &opt.debugcon {
         chipset = chipset.with_debugcon(
             debugcon_cfg.unwrap_or_else(|| DisconnectedSerialBackendHandle.into_resource()),
@@ -960,6 +1054,73 @@ fn vm_config_from_command_line(
         });
     }
 
+    if !opt.fw_cfg.is_empty() || opt.fw_cfg_kernel {
+        let register_layout = if cfg!(guest_arch = "x86_64") {
+            FwCfgRegisterLayout::IoPort
+        } else {
+            FwCfgRegisterLayout::Mmio
+        };
+
+        let (kernel, initrd, cmdline) = if opt.fw_cfg_kernel {
+            let kernel = fs_err::File::open(
+                (opt.kernel.0)
+                    .as_ref()
+                    .context("must provide kernel with --fw-cfg-kernel")?,
+            )
+            .context("failed to open kernel")?;
+            let initrd = (opt.initrd.0)
+                .as_ref()
+                .map(fs_err::File::open)
+                .transpose()
+                .context("failed to open initrd")?;
+            (
+                Some(kernel.into()),
+                initrd.map(Into::into),
+                Some(opt.cmdline.join(" ")),
+            )
+        } else {
+            (None, None, None)
+        };
+
+        let files = opt
+            .fw_cfg
+            .iter()
+            .map(|file| -> anyhow::Result<_> {
+                let data = match &file.data {
+                    cli_args::FwCfgFileDataCli::File(path) => FwCfgFileData::File(
+                        fs_err::File::open(path)
+                            .context("failed to open fw_cfg file")?
+                            .into(),
+                    ),
+                    cli_args::FwCfgFileDataCli::String(s) => {
+                        FwCfgFileData::Bytes(s.as_bytes().to_vec())
+                    }
+                };
+                Ok(FwCfgFile {
+                    name: file.name.clone(),
+                    data,
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        chipset_devices.push(ChipsetDeviceHandle {
+            name: "fw_cfg".to_string(),
+            resource: FwCfgDeviceHandle {
+                register_layout,
+                kernel,
+                initrd,
+                cmdline,
+                files,
+            }
+            .into_resource(),
+        });
+    }
+
+    let mut smbios = SmbiosConfig::default();
+    for fields in &opt.smbios {
+        fields.apply(&mut smbios);
+    }
+
     let custom_uefi_vars = {
         use firmware_uefi_custom_vars::CustomVars;
 
@@ -1094,6 +1255,11 @@ fn vm_config_from_command_line(
         x2apic: opt.x2apic,
     };
 
+    #[cfg(guest_arch = "aarch64")]
+    let (max_proc_count, max_mem_size) = (None, None);
+    #[cfg(guest_arch = "x86_64")]
+    let (max_proc_count, max_mem_size) = (opt.max_processors, opt.max_memory);
+
     let with_isolation = if let Some(isolation) = &opt.isolation {
         // TODO: For now, isolation is only supported with VTL2.
         if !opt.vtl2 {
@@ -1129,8 +1295,26 @@ fn vm_config_from_command_line(
         ));
     }
 
+    let mut pcie_root_ports = opt
+        .pcie_root_port
+        .iter()
+        .map(|name| PcieRootPortConfig { name: name.clone() })
+        .collect::<Vec<_>>();
+    let mut pcie_devices = Vec::new();
     let mut virtio_devices = Vec::new();
     let mut add_virtio_device = |bus, resource: Resource<VirtioDeviceHandle>| {
+        if let VirtioBusCli::Pcie = bus {
+            // Give each device its own root port.
+            let port_name = format!("virtio{}", pcie_devices.len());
+            pcie_root_ports.push(PcieRootPortConfig {
+                name: port_name.clone(),
+            });
+            pcie_devices.push(PcieDeviceConfig {
+                port_name,
+                resource: VirtioPciDeviceHandle(resource).into_resource(),
+            });
+            return;
+        }
         let bus = match bus {
             VirtioBusCli::Auto => {
                 // Use VPCI when possible (currently only on Windows and macOS due
@@ -1144,6 +1328,7 @@ fn vm_config_from_command_line(
             VirtioBusCli::Mmio => Some(VirtioBus::Mmio),
             VirtioBusCli::Pci => Some(VirtioBus::Pci),
             VirtioBusCli::Vpci => None,
+            VirtioBusCli::Pcie => unreachable!(),
         };
         if let Some(bus) = bus {
             virtio_devices.push((bus, resource));
@@ -1218,6 +1403,58 @@ fn vm_config_from_command_line(
         );
     }
 
+    for &device in &opt.virtio_input {
+        // Save 0 for PS/2 and 1 for the synthetic devices, and stack the
+        // virtio devices above them.
+        let resource = match device {
+            VirtioInputCli::Keyboard => virtio_resources::input::VirtioKeyboardHandle {
+                source: MultiplexedInputHandle { elevation: 2 }.into_resource(),
+            }
+            .into_resource(),
+            VirtioInputCli::Mouse => virtio_resources::input::VirtioMouseHandle {
+                source: MultiplexedInputHandle { elevation: 2 }.into_resource(),
+            }
+            .into_resource(),
+            VirtioInputCli::Tablet => virtio_resources::input::VirtioTabletHandle {
+                source: MultiplexedInputHandle { elevation: 3 }.into_resource(),
+            }
+            .into_resource(),
+        };
+        add_virtio_device(VirtioBusCli::Auto, resource);
+    }
+
+    if opt.virtio_gpu {
+        add_virtio_device(
+            VirtioBusCli::Auto,
+            virtio_resources::gpu::VirtioGpuHandle {
+                framebuffer: SharedFramebufferHandle.into_resource(),
+            }
+            .into_resource(),
+        );
+    }
+
+    if let Some(cli_args::PvPanicCli::Pcie(port_name)) = &opt.pvpanic {
+        pcie_devices.push(PcieDeviceConfig {
+            port_name: port_name.clone(),
+            resource: PvPanicPciDeviceHandle.into_resource(),
+        });
+    }
+
+    #[cfg(target_os = "linux")]
+    for cli_args::VfioUserCli {
+        socket_path,
+        pcie_port,
+    } in &opt.vfio_user
+    {
+        pcie_devices.push(PcieDeviceConfig {
+            port_name: pcie_port.clone(),
+            resource: vfio_user_resources::VfioUserDeviceHandle {
+                socket_path: socket_path.clone(),
+            }
+            .into_resource(),
+        });
+    }
+
     let (vmgs_disk, format_vmgs) = if let Some(path) = &opt.vmgs_file {
         let file = fs_err::OpenOptions::new()
             .create(true)
@@ -1239,6 +1476,24 @@ fn vm_config_from_command_line(
         (None, false)
     };
 
+    let numa = (!opt.numa.is_empty())
+        .then(|| numa_config(&opt.numa))
+        .transpose()?;
+    let mem_size = match &numa {
+        Some(numa) => {
+            let total = numa.nodes.iter().map(|node| node.mem_size).sum();
+            if let Some(memory) = opt.memory {
+                if memory != total {
+                    anyhow::bail!(
+                        "--memory ({memory:#x}) does not match the total memory of the --numa nodes ({total:#x})"
+                    );
+                }
+            }
+            total
+        }
+        None => opt.memory.unwrap_or(DEFAULT_MEMORY_SIZE),
+    };
+
     let mut cfg = Config {
         chipset,
         load_mode,
@@ -1246,9 +1501,10 @@ fn vm_config_from_command_line(
         vpci_devices,
         ide_disks: Vec::new(),
         memory: MemoryConfig {
-            mem_size: opt.memory,
+            mem_size,
             mmio_gaps,
             prefetch_memory: opt.prefetch,
+            max_mem_size,
         },
         processor_topology: ProcessorTopologyConfig {
             proc_count: opt.processors,
@@ -1259,7 +1515,9 @@ fn vm_config_from_command_line(
                 cli_args::SmtConfigCli::Off => Some(false),
             },
             arch: topology_arch,
+            max_proc_count,
         },
+        numa,
         hypervisor: HypervisorConfig {
             with_hv,
             with_vtl2: opt.vtl2.then_some(Vtl2Config {
@@ -1308,6 +1566,7 @@ fn vm_config_from_command_line(
         format_vmgs,
         secure_boot_enabled: opt.secure_boot,
         custom_uefi_vars,
+        smbios,
         firmware_event_send: None,
         debugger_rpc: None,
         generation_id_recv: None,
@@ -1317,6 +1576,41 @@ fn vm_config_from_command_line(
     Ok((cfg, resources))
 }
 
+/// Builds the NUMA configuration from the `--numa` options.
+fn numa_config(numa: &[cli_args::NumaCli]) -> anyhow::Result<NumaConfig> {
+    let mut nodes = BTreeMap::new();
+    let mut distances = Vec::new();
+    for entry in numa {
+        match entry {
+            cli_args::NumaCli::Node {
+                node,
+                vps,
+                mem_size,
+                host_node,
+            } => {
+                let config = NumaNodeConfig {
+                    vps: vps.clone(),
+                    mem_size: *mem_size,
+                    host_node: *host_node,
+                };
+                if nodes.insert(*node, config).is_some() {
+                    anyhow::bail!("numa node {node} is specified more than once");
+                }
+            }
+            &cli_args::NumaCli::Distance { src, dst, distance } => {
+                distances.push((src, dst, distance));
+            }
+        }
+    }
+    if nodes.keys().copied().ne(0..nodes.len() as u32) {
+        anyhow::bail!("numa nodes must be numbered consecutively from 0");
+    }
+    Ok(NumaConfig {
+        nodes: nodes.into_values().collect(),
+        distances,
+    })
+}
+
 /// Gets the terminal to use for externally launched console windows.
 fn openvmm_terminal_app() -> Option<PathBuf> {
     std::env::var_os("OPENVMM_TERM")
@@ -1338,6 +1632,9 @@ fn cleanup_socket(path: &Path) {
     }
 }
 
+/// The guest RAM size when `--memory` is not given.
+const DEFAULT_MEMORY_SIZE: u64 = 1 << 30;
+
 #[cfg(windows)]
 const DEFAULT_SWITCH: &str = "C08CB7B8-9B3C-408E-8E30-5E16A3AEB444";
 
@@ -1454,6 +1751,27 @@ fn disk_open(disk_cli: &DiskCliKind, read_only: bool) -> anyhow::Result<Resource
         }
         DiskCliKind::File(path) => open_disk_type(path, read_only)
             .with_context(|| format!("failed to open {}", path.display()))?,
+        DiskCliKind::IsoDir { path, volume_id } => {
+            Resource::new(disk_backend_resources::IsoDirDiskHandle {
+                path: path.display().to_string(),
+                volume_id: volume_id.clone(),
+            })
+        }
+        DiskCliKind::FatDir {
+            path,
+            fat_type,
+            size,
+            volume_label,
+        } => Resource::new(disk_backend_resources::FatDirDiskHandle {
+            path: path.display().to_string(),
+            volume_label: volume_label.clone(),
+            fat_type: fat_type.map(|fat_type| match fat_type {
+                cli_args::FatType::Fat12 => disk_backend_resources::FatType::Fat12,
+                cli_args::FatType::Fat16 => disk_backend_resources::FatType::Fat16,
+                cli_args::FatType::Fat32 => disk_backend_resources::FatType::Fat32,
+            }),
+            size: *size,
+        }),
         DiskCliKind::Blob { kind, url } => Resource::new(disk_backend_resources::BlobDiskHandle {
             url: url.to_owned(),
             format: match kind {
@@ -1471,9 +1789,23 @@ fn disk_open(disk_cli: &DiskCliKind, read_only: bool) -> anyhow::Result<Resource
                 ],
             })
         }
-        DiskCliKind::PersistentReservationsWrapper(inner) => Resource::new(
-            disk_backend_resources::DiskWithReservationsHandle(disk_open(inner, read_only)?),
-        ),
+        DiskCliKind::PersistentReservationsWrapper { disk, store } => {
+            let store = store
+                .as_ref()
+                .map(|path| {
+                    fs_err::OpenOptions::new()
+                        .create(true)
+                        .read(true)
+                        .write(true)
+                        .open(path)
+                        .context("failed to create or open reservation store")
+                })
+                .transpose()?;
+            Resource::new(disk_backend_resources::DiskWithReservationsHandle {
+                disk: disk_open(disk, read_only)?,
+                store: store.map(Into::into),
+            })
+        }
         DiskCliKind::Crypt {
             disk,
             cipher,
@@ -1701,6 +2033,45 @@ enum InteractiveCommand {
         lun: u8,
     },
 
+    /// Hot add an NVMe controller to an empty PCIe root port.
+    AddPcieNvme {
+        /// The name of the root port to attach the controller to.
+        port: String,
+        #[clap(long = "ro")]
+        read_only: bool,
+        #[clap(long)]
+        ram: Option<u64>,
+        file_path: Option<PathBuf>,
+    },
+
+    /// Hot remove the device attached to a PCIe root port.
+    ///
+    /// The guest is asked to release the device first, and the device is
+    /// surprise-removed if the guest does not respond within 30 seconds.
+    RmPcie {
+        /// The name of the root port.
+        port: String,
+    },
+
+    /// Hot add a processor (requires --max-processors).
+    AddCpu {
+        /// The VP index of the processor.
+        vp: u32,
+    },
+
+    /// Request that the guest eject a hot-added processor.
+    RmCpu {
+        /// The VP index of the processor.
+        vp: u32,
+    },
+
+    /// Hot add RAM (requires --max-memory).
+    AddMem {
+        /// The amount of RAM to add.
+        #[clap(value_parser = cli_args::parse_memory)]
+        size: u64,
+    },
+
     /// Inspect program state.
     #[clap(visible_alias = "x")]
     Inspect {
@@ -1724,6 +2095,25 @@ enum InteractiveCommand {
     #[clap(visible_alias = "V")]
     RestartVnc,
 
+    /// Save a screenshot of the framebuffer as a PNG.
+    Screenshot {
+        /// The file to write.
+        file: PathBuf,
+    },
+
+    /// Record the framebuffer to a directory, or stop the current recording.
+    ///
+    /// Each distinct frame is written as a PNG image, with a log of frame
+    /// durations that can be converted to a video with, for example,
+    /// `ffmpeg -f concat -i <dir>/frames.ffconcat out.mp4`.
+    Record {
+        /// The directory to write. If omitted, stops the current recording.
+        dir: Option<PathBuf>,
+        /// The number of times to sample the framebuffer per second.
+        #[clap(long, default_value_t = 10, value_parser = clap::value_parser!(u32).range(1..=60))]
+        fps: u32,
+    },
+
     /// Start an hvsocket terminal window.
     #[clap(visible_alias = "v")]
     Hvsock {
@@ -1751,6 +2141,21 @@ enum InteractiveCommand {
     #[clap(visible_alias = "I")]
     InputMode,
 
+    /// Wait for console output matching a regular expression.
+    ///
+    /// Only output written after the command is entered is matched.
+    Expect {
+        /// The regular expression to wait for.
+        #[clap(value_parser = regex::bytes::Regex::new)]
+        pattern: regex::bytes::Regex,
+        /// The number of seconds to wait before failing.
+        #[clap(short, long, default_value_t = 30)]
+        timeout: u64,
+        /// A line to send to the console once the output matches.
+        #[clap(short, long)]
+        send: Option<String>,
+    },
+
     /// Reset the VM.
     Reset,
 
@@ -1811,6 +2216,15 @@ enum InteractiveCommand {
         file: Option<PathBuf>,
     },
 
+    /// Write an ELF core dump of guest memory and VP registers, which can be
+    /// loaded with `crash` or `gdb`.
+    ///
+    /// Pause the VM first to get a consistent dump.
+    DumpGuest {
+        /// The file to write.
+        file: PathBuf,
+    },
+
     /// Inject an artificial panic into OpenVMM
     Panic,
 }
@@ -1851,13 +2265,54 @@ fn new_hvsock_service_id(port: u32) -> Guid {
 async fn run_control(driver: &DefaultDriver, mesh: &VmmMesh, opt: Options) -> anyhow::Result<()> {
     let (mut vm_config, mut resources) = vm_config_from_command_line(driver, &opt)?;
 
+    let mut framebuffer_access = resources.framebuffer_access.take();
     let mut vnc_worker = None;
     if opt.gfx || opt.vnc {
         let listener = TcpListener::bind(format!("127.0.0.1:{}", opt.vnc_port))
             .with_context(|| format!("binding to VNC port {}", opt.vnc_port))?;
+        let websocket_listener = opt
+            .vnc_websocket_port
+            .map(|port| {
+                TcpListener::bind(format!("127.0.0.1:{}", port))
+                    .with_context(|| format!("binding to VNC WebSocket port {}", port))
+            })
+            .transpose()?;
 
         let input_send = vm_config.input.sender();
-        let framebuffer = resources.framebuffer_access.expect("synth video enabled");
+        let (access, framebuffer) = framebuffer_access
+            .take()
+            .expect("synth video enabled")
+            .split(driver)
+            .context("failed to share framebuffer")?;
+        framebuffer_access = Some(access);
+
+        let password = opt
+            .vnc_password_file
+            .as_ref()
+            .map(|path| {
+                // Ignore a trailing newline from editors.
+                fs_err::read_to_string(path)
+                    .map(|s| s.trim_end_matches(['\r', '\n']).to_owned())
+                    .context("failed to read VNC password file")
+            })
+            .transpose()?;
+        let tls = opt
+            .vnc_tls_cert
+            .as_ref()
+            .zip(opt.vnc_tls_key.as_ref())
+            .map(|(cert, key)| -> anyhow::Result<_> {
+                Ok(VncTls {
+                    cert_chain_pem: fs_err::read(cert).context("failed to read VNC certificate")?,
+                    key_pem: fs_err::read(key).context("failed to read VNC private key")?,
+                })
+            })
+            .transpose()?;
+        let auth = VncAuth {
+            password,
+            username: opt.vnc_username.clone(),
+            tls,
+            allowed_origins: opt.vnc_websocket_origin.clone(),
+        };
 
         let vnc_host = mesh
             .make_host("vnc", None)
@@ -1870,14 +2325,23 @@ async fn run_control(driver: &DefaultDriver, mesh: &VmmMesh, opt: Options) -> an
                     vnc_worker_defs::VNC_WORKER_TCP,
                     VncParameters {
                         listener,
+                        websocket_listener,
                         framebuffer,
                         input_send,
+                        auth,
                     },
                 )
                 .await?,
         )
     }
 
+    // Keep a view of the framebuffer for screenshots and recordings.
+    let screen = framebuffer_access
+        .map(|access| access.view())
+        .transpose()
+        .context("failed to map framebuffer")?
+        .map(|view| Arc::new(parking_lot::Mutex::new(view)));
+
     // spin up the debug worker
     let gdb_worker = if let Some(port) = opt.gdb {
         let listener = TcpListener::bind(format!("127.0.0.1:{}", port))
@@ -1956,6 +2420,7 @@ async fn run_control(driver: &DefaultDriver, mesh: &VmmMesh, opt: Options) -> an
     let (inspect_completion_engine_send, inspect_completion_engine_recv) = mesh::channel();
 
     let mut console_in = resources.console_in;
+    let console_tap = resources.console_tap;
     thread::Builder::new()
         .name("stdio-thread".to_string())
         .spawn(move || {
@@ -2071,6 +2536,28 @@ async fn run_control(driver: &DefaultDriver, mesh: &VmmMesh, opt: Options) -> an
                                 }
                             }
                             InteractiveCommand::InputMode => break,
+                            InteractiveCommand::Expect {
+                                pattern,
+                                timeout,
+                                send,
+                            } => {
+                                if let Some(input) = console_in.as_mut() {
+                                    let mut expect = Expect::new(console_tap.capture(), input)
+                                        .with_timeout(Duration::from_secs(timeout));
+                                    let r = block_on(async {
+                                        expect.expect(&pattern).await?;
+                                        if let Some(line) = &send {
+                                            expect.send_line(line).await?;
+                                        }
+                                        anyhow::Ok(())
+                                    });
+                                    if let Err(err) = r {
+                                        eprintln!("error: {:#}", err);
+                                    }
+                                } else {
+                                    eprintln!("error: no console");
+                                }
+                            }
                             cmd => {
                                 // Send the command to the main thread for processing.
                                 let (processing_done_send, processing_done_recv) =
@@ -2093,6 +2580,7 @@ async fn run_control(driver: &DefaultDriver, mesh: &VmmMesh, opt: Options) -> an
         .unwrap();
 
     let mut state_change_task = None::<Task<Result<StateChange, RecvError>>>;
+    let mut recording = None::<screenshot::Recording>;
     let mut pulse_save_restore_interval: Option<Duration> = None;
     let mut pending_shutdown = None;
 
@@ -2127,6 +2615,7 @@ enum Event {
         inspect_completion_engine_recv.map(Event::InspectRequestFromCompletionEngine);
 
     let mut quit = false;
+    let mut guest_panic_exit = None;
     loop {
         let event = {
             let pulse_save_restore = pin!(async {
@@ -2208,6 +2697,53 @@ enum Event {
                             StateChange::Reset,
                         );
                     }
+                    vmm_core_defs::HaltReason::GuestPanic { crash_loaded } => {
+                        let action = if crash_loaded {
+                            &opt.on_guest_crash_loaded
+                        } else {
+                            &opt.on_guest_panic
+                        };
+                        tracing::warn!(crash_loaded, ?action, "guest panicked");
+                        match action {
+                            cli_args::GuestPanicActionCli::Pause => {}
+                            cli_args::GuestPanicActionCli::Continue => {
+                                vm_rpc.call(VmRpc::ClearHalt, ()).await.ok();
+                            }
+                            cli_args::GuestPanicActionCli::Reset => {
+                                if state_change_task.is_none() {
+                                    state_change(
+                                        driver,
+                                        &vm_rpc,
+                                        &mut state_change_task,
+                                        VmRpc::Reset,
+                                        StateChange::Reset,
+                                    );
+                                } else {
+                                    tracing::error!("state change in progress, not resetting");
+                                }
+                            }
+                            cli_args::GuestPanicActionCli::Dump(path) => {
+                                match guest_dump::dump_guest(&vm_rpc, path).await {
+                                    Ok(()) => tracing::info!(
+                                        path = %path.display(),
+                                        "dumped guest after panic"
+                                    ),
+                                    Err(err) => tracing::error!(
+                                        error = err.as_ref() as &dyn std::error::Error,
+                                        "failed to dump guest after panic"
+                                    ),
+                                }
+                            }
+                            cli_args::GuestPanicActionCli::Exit(code) => {
+                                // Work around the detached SCSI task holding up
+                                // worker stop, as for the quit command.
+                                resources.scsi_rpc = None;
+                                vm_worker.stop();
+                                quit = true;
+                                guest_panic_exit = Some(*code);
+                            }
+                        }
+                    }
                     _ => {
                         tracing::info!(?reason, "guest halted");
                     }
@@ -2527,6 +3063,89 @@ fn state_change<U: 'static + Send>(
                     tracing::error!(error = error.as_error(), "error removing disk")
                 }
             }
+            InteractiveCommand::AddPcieNvme {
+                port,
+                read_only,
+                ram,
+                file_path,
+            } => {
+                let action = async {
+                    let disk = match ram {
+                        None => {
+                            let path = file_path.context("no filename passed")?;
+                            open_disk_type(path.as_ref(), read_only)
+                                .with_context(|| format!("failed to open {}", path.display()))?
+                        }
+                        Some(size) => {
+                            Resource::new(disk_backend_resources::LayeredDiskHandle::single_layer(
+                                RamDiskLayerHandle { len: Some(size) },
+                            ))
+                        }
+                    };
+
+                    let cfg = PcieDeviceConfig {
+                        port_name: port,
+                        resource: nvme_resources::NvmeControllerHandle {
+                            subsystem_id: Guid::new_random(),
+                            namespaces: vec![nvme_resources::NamespaceDefinition {
+                                nsid: 1,
+                                disk,
+                                read_only,
+                            }],
+                            max_io_queues: 64,
+                            msix_count: 64,
+                        }
+                        .into_resource(),
+                    };
+
+                    vm_rpc.call_failable(VmRpc::AddPcieDevice, cfg).await?;
+                    anyhow::Ok(())
+                };
+
+                if let Err(error) = action.await {
+                    tracing::error!(error = error.as_error(), "error adding pcie device")
+                }
+            }
+            InteractiveCommand::RmPcie { port } => {
+                let action = async {
+                    vm_rpc.call_failable(VmRpc::RemovePcieDevice, port).await?;
+                    anyhow::Ok(())
+                };
+
+                if let Err(error) = action.await {
+                    tracing::error!(error = error.as_error(), "error removing pcie device")
+                }
+            }
+            InteractiveCommand::AddCpu { vp } => {
+                let action = async {
+                    vm_rpc.call_failable(VmRpc::AddProcessor, vp).await?;
+                    anyhow::Ok(())
+                };
+
+                if let Err(error) = action.await {
+                    tracing::error!(error = error.as_error(), "error adding processor")
+                }
+            }
+            InteractiveCommand::RmCpu { vp } => {
+                let action = async {
+                    vm_rpc.call_failable(VmRpc::RemoveProcessor, vp).await?;
+                    anyhow::Ok(())
+                };
+
+                if let Err(error) = action.await {
+                    tracing::error!(error = error.as_error(), "error removing processor")
+                }
+            }
+            InteractiveCommand::AddMem { size } => {
+                let action = async {
+                    vm_rpc.call_failable(VmRpc::AddMemory, size).await?;
+                    anyhow::Ok(())
+                };
+
+                if let Err(error) = action.await {
+                    tracing::error!(error = error.as_error(), "error adding memory")
+                }
+            }
             InteractiveCommand::Inspect {
                 recursive,
                 limit,
@@ -2601,6 +3220,49 @@ fn state_change<U: 'static + Send>(
                     eprintln!("ERROR: no VNC server running");
                 }
             }
+            InteractiveCommand::Screenshot { file } => {
+                if let Some(screen) = &screen {
+                    match screenshot::screenshot(screen.clone(), file.clone()).await {
+                        Ok(()) => println!("saved screenshot to {}", file.display()),
+                        Err(err) => eprintln!("error: {:#}", err),
+                    }
+                } else {
+                    eprintln!("error: no framebuffer");
+                }
+            }
+            InteractiveCommand::Record { dir, fps } => {
+                if let Some(dir) = dir {
+                    if let Some(screen) = &screen {
+                        if recording.is_some() {
+                            eprintln!("error: already recording");
+                        } else {
+                            match screenshot::Recording::start(
+                                driver,
+                                screen.clone(),
+                                dir.clone(),
+                                fps,
+                            )
+                            .await
+                            {
+                                Ok(r) => {
+                                    recording = Some(r);
+                                    println!("recording to {}", dir.display());
+                                }
+                                Err(err) => eprintln!("error: {:#}", err),
+                            }
+                        }
+                    } else {
+                        eprintln!("error: no framebuffer");
+                    }
+                } else if let Some(r) = recording.take() {
+                    match r.stop().await {
+                        Ok(frames) => println!("recording stopped after {frames} frames"),
+                        Err(err) => eprintln!("error: {:#}", err),
+                    }
+                } else {
+                    eprintln!("error: not recording");
+                }
+            }
             InteractiveCommand::Hvsock { term, port } => {
                 let vm_rpc = &vm_rpc;
                 let action = || async move {
@@ -2775,12 +3437,32 @@ fn state_change<U: 'static + Send>(
                     eprintln!("error: {err:?}");
                 }
             }
-            InteractiveCommand::Input { .. } | InteractiveCommand::InputMode => unreachable!(),
+            InteractiveCommand::DumpGuest { file } => {
+                match guest_dump::dump_guest(&vm_rpc, &file).await {
+                    Ok(()) => println!("dumped guest to {}", file.display()),
+                    Err(err) => eprintln!("error: {:#}", err),
+                }
+            }
+            InteractiveCommand::Input { .. }
+            | InteractiveCommand::InputMode
+            | InteractiveCommand::Expect { .. } => unreachable!(),
+        }
+    }
+
+    if let Some(recording) = recording {
+        if let Err(err) = recording.stop().await {
+            tracing::error!(
+                error = err.as_ref() as &dyn std::error::Error,
+                "recording failed"
+            );
         }
     }
 
     vm_worker.stop();
     vm_worker.join().await?;
+    if let Some(code) = guest_panic_exit {
+        return Err(GuestPanicExit(code).into());
+    }
     Ok(())
 }
 
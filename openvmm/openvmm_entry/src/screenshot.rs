@@ -0,0 +1,339 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Screenshots and screen recordings of the framebuffer.
+
+use anyhow::Context;
+use framebuffer::View;
+use futures::future::Either;
+use image::codecs::png::PngEncoder;
+use image::ColorType;
+use image::ImageEncoder;
+use pal_async::task::Spawn;
+use pal_async::task::Task;
+use pal_async::timer::Instant;
+use pal_async::timer::PolledTimer;
+use pal_async::DefaultDriver;
+use parking_lot::Mutex;
+use std::io::Write;
+use std::path::PathBuf;
+use std::pin::pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The name of the recording's frame log within the recording directory.
+pub const RECORDING_LOG: &str = "frames.ffconcat";
+
+/// A frame read from the framebuffer.
+#[derive(PartialEq, Eq)]
+struct Frame {
+    width: u32,
+    height: u32,
+    rgba: Vec<u8>,
+}
+
+/// Reads the framebuffer at its current resolution.
+fn capture(view: &mut View) -> Frame {
+    // The framebuffer uses 4 bytes per pixel in BGRX order. Swap to RGB and
+    // make the image opaque.
+    const BYTES_PER_PIXEL: usize = 4;
+    let (width, height) = view.resolution();
+    let stride = width as usize * BYTES_PER_PIXEL;
+    let mut rgba = vec![0; stride * height as usize];
+    for (y, line) in (0..height).zip(rgba.chunks_exact_mut(stride)) {
+        view.read_line(y, line);
+        for pixel in line.chunks_exact_mut(BYTES_PER_PIXEL) {
+            pixel.swap(0, 2);
+            pixel[3] = 0xff;
+        }
+    }
+    Frame {
+        width: width.into(),
+        height: height.into(),
+        rgba,
+    }
+}
+
+fn encode_png(frame: &Frame) -> anyhow::Result<Vec<u8>> {
+    let mut png = Vec::new();
+    PngEncoder::new(&mut png)
+        .write_image(&frame.rgba, frame.width, frame.height, ColorType::Rgba8)
+        .context("failed to encode PNG")?;
+    Ok(png)
+}
+
+/// A PNG image of the framebuffer.
+pub struct Screenshot {
+    /// The width of the image, in pixels.
+    pub width: u32,
+    /// The height of the image, in pixels.
+    pub height: u32,
+    /// The PNG-encoded image.
+    pub png: Vec<u8>,
+}
+
+/// Captures the current contents of the framebuffer as a PNG.
+///
+/// Encoding is done on a blocking thread.
+pub async fn capture_png(view: Arc<Mutex<View>>) -> anyhow::Result<Screenshot> {
+    blocking::unblock(move || {
+        let frame = capture(&mut view.lock());
+        Ok(Screenshot {
+            width: frame.width,
+            height: frame.height,
+            png: encode_png(&frame)?,
+        })
+    })
+    .await
+}
+
+/// Saves the current contents of the framebuffer to `path` as a PNG.
+pub async fn screenshot(view: Arc<Mutex<View>>, path: PathBuf) -> anyhow::Result<()> {
+    let screenshot = capture_png(view).await?;
+    blocking::unblock(move || {
+        fs_err::write(&path, screenshot.png)
+            .with_context(|| format!("failed to write screenshot to {}", path.display()))
+    })
+    .await
+}
+
+/// An in-progress recording of the framebuffer.
+///
+/// The recording is a directory of PNG images, one for each distinct frame,
+/// and a log of how long each frame was shown, in the ffmpeg concat format.
+/// Each image carries its own dimensions, so resolution changes during the
+/// recording are preserved.
+pub struct Recording {
+    stop: mesh::OneshotSender<()>,
+    task: Task<anyhow::Result<u64>>,
+}
+
+impl Recording {
+    /// Starts recording the framebuffer to the directory `path`, sampling it
+    /// `fps` times per second.
+    pub async fn start(
+        driver: &DefaultDriver,
+        view: Arc<Mutex<View>>,
+        path: PathBuf,
+        fps: u32,
+    ) -> anyhow::Result<Self> {
+        let recorder = blocking::unblock(move || Recorder::new(path)).await?;
+        let (stop_send, stop_recv) = mesh::oneshot();
+        let mut timer = PolledTimer::new(driver);
+        let task = driver.spawn("framebuffer-record", async move {
+            let r = record(&mut timer, view, recorder, fps, stop_recv).await;
+            if let Err(err) = &r {
+                tracing::error!(
+                    error = err.as_ref() as &dyn std::error::Error,
+                    "recording failed"
+                );
+            }
+            r
+        });
+        Ok(Self {
+            stop: stop_send,
+            task,
+        })
+    }
+
+    /// Stops the recording, returning the number of distinct frames written.
+    pub async fn stop(self) -> anyhow::Result<u64> {
+        self.stop.send(());
+        self.task.await
+    }
+}
+
+async fn record(
+    timer: &mut PolledTimer,
+    view: Arc<Mutex<View>>,
+    mut recorder: Recorder,
+    fps: u32,
+    stop: mesh::OneshotReceiver<()>,
+) -> anyhow::Result<u64> {
+    let period = Duration::from_secs(1) / fps.max(1);
+    let start = Instant::now();
+    let mut stop = pin!(stop);
+    let mut deadline = start;
+    loop {
+        // Capture, encode, and write the frame off the async thread.
+        recorder = blocking::unblock({
+            let view = view.clone();
+            move || {
+                let frame = capture(&mut view.lock());
+                recorder.push(frame, Instant::now() - start)?;
+                anyhow::Result::<_>::Ok(recorder)
+            }
+        })
+        .await?;
+
+        // Skip any sample periods that passed while the frame was being
+        // written. The log records when each frame actually changed.
+        let now = Instant::now();
+        while deadline <= now {
+            deadline = deadline + period;
+        }
+        match futures::future::select(pin!(timer.sleep_until(deadline)), stop.as_mut()).await {
+            Either::Left(_) => {}
+            Either::Right(_) => break,
+        }
+    }
+    blocking::unblock(move || recorder.finish(Instant::now() - start)).await
+}
+
+/// Writes the frames of a recording to a directory.
+struct Recorder {
+    dir: PathBuf,
+    log: std::io::BufWriter<fs_err::File>,
+    /// The last frame written and when it was captured.
+    last: Option<(Frame, Duration)>,
+    count: u64,
+}
+
+impl Recorder {
+    fn new(dir: PathBuf) -> anyhow::Result<Self> {
+        fs_err::create_dir_all(&dir)?;
+        let mut log = std::io::BufWriter::new(fs_err::File::create(dir.join(RECORDING_LOG))?);
+        writeln!(log, "ffconcat version 1.0")?;
+        Ok(Self {
+            dir,
+            log,
+            last: None,
+            count: 0,
+        })
+    }
+
+    fn file_name(index: u64) -> String {
+        format!("frame-{index:06}.png")
+    }
+
+    /// Adds a frame captured at `time`. The frame is only written if it
+    /// differs from the previous one.
+    fn push(&mut self, frame: Frame, time: Duration) -> anyhow::Result<()> {
+        if let Some((last, last_time)) = &self.last {
+            if *last == frame {
+                return Ok(());
+            }
+            writeln!(
+                self.log,
+                "duration {:.3}",
+                (time - *last_time).as_secs_f64()
+            )?;
+        }
+        let name = Self::file_name(self.count);
+        let path = self.dir.join(&name);
+        fs_err::write(&path, encode_png(&frame)?)?;
+        writeln!(self.log, "file '{name}'")?;
+        writeln!(self.log, "# {:.3}", time.as_secs_f64())?;
+        self.count += 1;
+        self.last = Some((frame, time));
+        Ok(())
+    }
+
+    /// Finishes the log at `time`, returning the number of frames written.
+    fn finish(mut self, time: Duration) -> anyhow::Result<u64> {
+        if let Some((_, last_time)) = &self.last {
+            writeln!(
+                self.log,
+                "duration {:.3}",
+                (time - *last_time).as_secs_f64()
+            )?;
+            // The concat demuxer ignores the duration of the final entry, so
+            // repeat the last frame to end the recording at `time`.
+            writeln!(self.log, "file '{}'", Self::file_name(self.count - 1))?;
+        }
+        self.log.flush().context("failed to write recording log")?;
+        Ok(self.count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sparse_mmap::alloc_shared_memory;
+    use sparse_mmap::SparseMapping;
+    use video_core::FramebufferFormat;
+
+    /// Returns a view of a 2x2 framebuffer and a writable mapping of it.
+    fn test_view() -> (View, SparseMapping, framebuffer::Framebuffer) {
+        let len = framebuffer::FRAMEBUFFER_SIZE;
+        let vram = alloc_shared_memory(len).unwrap();
+        let mapping = SparseMapping::new(len).unwrap();
+        mapping.map_file(0, len, &vram, 0, true).unwrap();
+        let (fb, access) = framebuffer::framebuffer(vram, len, 0).unwrap();
+        (access.view().unwrap(), mapping, fb)
+    }
+
+    fn set_format(fb: framebuffer::Framebuffer, width: usize, height: usize) {
+        fb.format_send().send(FramebufferFormat {
+            width,
+            height,
+            bytes_per_line: 16,
+            offset: 0,
+        });
+    }
+
+    #[test]
+    fn test_capture() {
+        let (mut view, mapping, fb) = test_view();
+        // Two BGRX pixels per line, with a stride of 4 pixels.
+        mapping.write_at(0, &[1, 2, 3, 0, 4, 5, 6, 0]).unwrap();
+        mapping.write_at(16, &[7, 8, 9, 0, 10, 11, 12, 0]).unwrap();
+        set_format(fb, 2, 2);
+
+        let frame = capture(&mut view);
+        assert_eq!((frame.width, frame.height), (2, 2));
+        assert_eq!(
+            frame.rgba,
+            [3, 2, 1, 255, 6, 5, 4, 255, 9, 8, 7, 255, 12, 11, 10, 255]
+        );
+    }
+
+    #[test]
+    fn test_encode_png() {
+        let frame = Frame {
+            width: 2,
+            height: 1,
+            rgba: vec![1, 2, 3, 255, 4, 5, 6, 255],
+        };
+        let png = encode_png(&frame).unwrap();
+        let image = image::load_from_memory_with_format(&png, image::ImageFormat::Png)
+            .unwrap()
+            .into_rgba8();
+        assert_eq!(image.dimensions(), (2, 1));
+        assert_eq!(image.into_raw(), frame.rgba);
+    }
+
+    #[test]
+    fn test_recorder() {
+        let dir = tempfile::tempdir().unwrap();
+        let frame = |v: u8| Frame {
+            width: 1,
+            height: 1,
+            rgba: vec![v, v, v, 255],
+        };
+        let mut recorder = Recorder::new(dir.path().to_owned()).unwrap();
+        recorder.push(frame(0), Duration::ZERO).unwrap();
+        // Unchanged frames are not written again.
+        recorder.push(frame(0), Duration::from_millis(100)).unwrap();
+        recorder.push(frame(1), Duration::from_millis(200)).unwrap();
+        let count = recorder.finish(Duration::from_millis(500)).unwrap();
+        assert_eq!(count, 2);
+
+        let log = fs_err::read_to_string(dir.path().join(RECORDING_LOG)).unwrap();
+        assert_eq!(
+            log,
+            "ffconcat version 1.0\n\
+            file 'frame-000000.png'\n\
+            # 0.000\n\
+            duration 0.200\n\
+            file 'frame-000001.png'\n\
+            # 0.200\n\
+            duration 0.300\n\
+            file 'frame-000001.png'\n"
+        );
+        let image = image::open(dir.path().join("frame-000001.png"))
+            .unwrap()
+            .into_rgba8();
+        assert_eq!(image.into_raw(), [1, 1, 1, 255]);
+    }
+}
@@ -2,9 +2,13 @@
 // Licensed under the MIT License.
 
 use crate::cleanup_socket;
+use crate::cli_args::SerialConfigCli;
+use crate::cli_args::SerialFileCli;
 use anyhow::Context;
 use futures::stream;
+use futures::AsyncRead;
 use futures::StreamExt;
+use futures::TryStreamExt;
 use futures_concurrency::prelude::*;
 use hvlite_defs::config::SerialPipes;
 use io::ErrorKind;
@@ -13,12 +17,17 @@
 use pal_async::driver::SpawnDriver;
 use pal_async::pipe::PolledPipe;
 use pal_async::task::Task;
+use parking_lot::Mutex;
 use serial_socket::net::OpenSocketSerialConfig;
 use std::fs::File;
 use std::io;
 use std::io::Write;
 use std::net::SocketAddr;
 use std::path::Path;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::Poll;
 use std::thread;
 use unix_socket::UnixListener;
 use vm_resource::kind::SerialBackendHandle;
@@ -254,3 +263,375 @@ pub fn bind_tcp_serial(addr: &SocketAddr) -> anyhow::Result<Resource<SerialBacke
         .with_context(|| format!("failed to bind tcp address {addr}"))?;
     Ok(OpenSocketSerialConfig::from(listener).into_resource())
 }
+
+/// Builds the output side of a `file=` or `tee=` serial configuration.
+///
+/// `set_console` is called if the output includes the console, to connect the
+/// console's input to the serial port.
+pub fn tee_output(
+    cfg: SerialConfigCli,
+    console_tap: &ConsoleTap,
+    mut set_console: impl FnMut() -> anyhow::Result<()>,
+) -> anyhow::Result<TeeWriter> {
+    let targets = match cfg {
+        SerialConfigCli::Tee(targets) => targets,
+        cfg => vec![cfg],
+    };
+    let mut writers = Vec::<Box<dyn Write + Send>>::new();
+    for target in targets {
+        match target {
+            SerialConfigCli::None => {}
+            SerialConfigCli::Console => {
+                set_console()?;
+                writers.push(Box::new(console_tap.writer(term::raw_stdout())));
+            }
+            SerialConfigCli::Stderr => writers.push(Box::new(term::raw_stderr())),
+            SerialConfigCli::File(file) => writers
+                .push(Box::new(SerialLogFile::create(&file).with_context(
+                    || format!("failed to create {}", file.path.display()),
+                )?)),
+            SerialConfigCli::NewConsole(_)
+            | SerialConfigCli::Pipe(_)
+            | SerialConfigCli::Tcp(_)
+            | SerialConfigCli::Tee(_) => {
+                anyhow::bail!("tee only supports console, stderr, file, and none")
+            }
+        }
+    }
+    Ok(TeeWriter(writers))
+}
+
+/// A copy of the console's serial output, for the interactive `expect`
+/// command.
+#[derive(Clone, Default)]
+pub struct ConsoleTap(Arc<Mutex<Option<Arc<mesh::Sender<Vec<u8>>>>>>);
+
+impl ConsoleTap {
+    /// Wraps `inner`, the console's output, so that output written to it is
+    /// also sent to the tap.
+    pub fn writer<W: Write>(&self, inner: W) -> TapWriter<W> {
+        TapWriter {
+            inner,
+            tap: self.clone(),
+        }
+    }
+
+    /// Returns a reader for console output written from now on, until the
+    /// reader is dropped or the next call.
+    pub fn capture(&self) -> Capture {
+        let (send, recv) = mesh::channel();
+        let send = Arc::new(send);
+        *self.0.lock() = Some(send.clone());
+        Capture {
+            read: Box::new(recv.map(Ok::<_, io::Error>).into_async_read()),
+            tap: self.clone(),
+            send,
+        }
+    }
+}
+
+/// A reader for console output, from [`ConsoleTap::capture`].
+///
+/// Output stops being copied to the tap when this is dropped.
+pub struct Capture {
+    read: Box<dyn AsyncRead + Send + Unpin>,
+    tap: ConsoleTap,
+    send: Arc<mesh::Sender<Vec<u8>>>,
+}
+
+impl AsyncRead for Capture {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().read).poll_read(cx, buf)
+    }
+}
+
+impl Drop for Capture {
+    fn drop(&mut self) {
+        // Only uninstall this capture, not a newer one.
+        let mut current = self.tap.0.lock();
+        if current
+            .as_ref()
+            .is_some_and(|send| Arc::ptr_eq(send, &self.send))
+        {
+            *current = None;
+        }
+    }
+}
+
+/// A writer that copies its output to a [`ConsoleTap`].
+pub struct TapWriter<W> {
+    inner: W,
+    tap: ConsoleTap,
+}
+
+impl<W: Write> Write for TapWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        if let Some(send) = &*self.tap.0.lock() {
+            send.send(buf[..n].to_vec());
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Writes serial output to multiple destinations.
+pub struct TeeWriter(Vec<Box<dyn Write + Send>>);
+
+impl Write for TeeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // Drop a failing destination rather than stopping output to the
+        // others.
+        self.0
+            .retain_mut(|w| match w.write_all(buf).and_then(|()| w.flush()) {
+                Ok(()) => true,
+                Err(err) => {
+                    tracing::error!(
+                        error = &err as &dyn std::error::Error,
+                        "failed to write serial output"
+                    );
+                    false
+                }
+            });
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The number of rotated serial log files to keep, as `<path>.1` (the most
+/// recent) through `<path>.N`.
+const ROTATED_LOG_COUNT: usize = 5;
+
+/// A serial log file, with optional timestamps and size-based rotation.
+struct SerialLogFile {
+    path: PathBuf,
+    file: Option<fs_err::File>,
+    timestamps: bool,
+    rotate: Option<u64>,
+    len: u64,
+    line_start: bool,
+}
+
+impl SerialLogFile {
+    fn create(cfg: &SerialFileCli) -> io::Result<Self> {
+        Ok(Self {
+            path: cfg.path.clone(),
+            file: Some(fs_err::File::create(&cfg.path)?),
+            timestamps: cfg.timestamps,
+            rotate: cfg.rotate,
+            len: 0,
+            line_start: true,
+        })
+    }
+
+    fn rotated_path(&self, n: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{n}"));
+        path.into()
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        // Close the file first so that it can be renamed on Windows.
+        self.file = None;
+        for n in (1..ROTATED_LOG_COUNT).rev() {
+            let _ = std::fs::rename(self.rotated_path(n), self.rotated_path(n + 1));
+        }
+        fs_err::rename(&self.path, self.rotated_path(1))?;
+        self.file = Some(fs_err::File::create(&self.path)?);
+        self.len = 0;
+        Ok(())
+    }
+
+    fn write_raw(&mut self, buf: &[u8]) -> io::Result<()> {
+        let file = self
+            .file
+            .as_mut()
+            .ok_or_else(|| io::Error::other("log file closed after a failed rotation"))?;
+        file.write_all(buf)?;
+        self.len += buf.len() as u64;
+        Ok(())
+    }
+}
+
+impl Write for SerialLogFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for line in buf.split_inclusive(|&b| b == b'\n') {
+            if self.line_start {
+                // Only rotate between lines, so that lines are never split
+                // across files.
+                if self.rotate.is_some_and(|limit| self.len >= limit) {
+                    self.rotate()?;
+                }
+                if self.timestamps {
+                    let now = time::OffsetDateTime::now_utc();
+                    let stamp = format!(
+                        "[{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:06}Z] ",
+                        now.year(),
+                        u8::from(now.month()),
+                        now.day(),
+                        now.hour(),
+                        now.minute(),
+                        now.second(),
+                        now.microsecond()
+                    );
+                    self.write_raw(stamp.as_bytes())?;
+                }
+            }
+            self.write_raw(line)?;
+            self.line_start = line.ends_with(b"\n");
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.file {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::AsyncReadExt;
+
+    fn log_file(path: &Path, timestamps: bool, rotate: Option<u64>) -> SerialLogFile {
+        SerialLogFile::create(&SerialFileCli {
+            path: path.to_owned(),
+            timestamps,
+            rotate,
+        })
+        .unwrap()
+    }
+
+    fn read(path: impl AsRef<Path>) -> String {
+        fs_err::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn test_rotate() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("serial.log");
+        let mut file = log_file(&path, false, Some(10));
+        file.write_all(b"0123456789\n").unwrap();
+        // The limit has been reached, but rotation waits for the next line.
+        assert_eq!(read(&path), "0123456789\n");
+        // A line written in pieces is not split across files.
+        file.write_all(b"abcdefghij").unwrap();
+        file.write_all(b"klm\n").unwrap();
+        file.flush().unwrap();
+        assert_eq!(read(file.rotated_path(1)), "0123456789\n");
+        assert_eq!(read(&path), "abcdefghijklm\n");
+
+        // Only the most recent files are kept.
+        for i in 0..ROTATED_LOG_COUNT {
+            writeln!(file, "line {i:04}").unwrap();
+        }
+        file.flush().unwrap();
+        assert_eq!(read(&path), format!("line {:04}\n", ROTATED_LOG_COUNT - 1));
+        assert_eq!(
+            read(file.rotated_path(1)),
+            format!("line {:04}\n", ROTATED_LOG_COUNT - 2)
+        );
+        assert_eq!(
+            read(file.rotated_path(ROTATED_LOG_COUNT)),
+            "abcdefghijklm\n"
+        );
+        assert!(!file.rotated_path(ROTATED_LOG_COUNT + 1).exists());
+    }
+
+    #[test]
+    fn test_timestamps() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("serial.log");
+        let mut file = log_file(&path, true, None);
+        // Each line is stamped once, however it is split across writes.
+        for data in ["a", "b", "c\nd", "e\n\n", "f"] {
+            file.write_all(data.as_bytes()).unwrap();
+        }
+        file.flush().unwrap();
+        let stamp = r"\[\d{4}-\d{2}-\d{2}T\d{2}:\d{2}:\d{2}\.\d{6}Z\] ";
+        let re = regex::Regex::new(&format!("^{stamp}abc\n{stamp}de\n{stamp}\n{stamp}f$")).unwrap();
+        let log = read(&path);
+        assert!(re.is_match(&log), "{log:?}");
+    }
+
+    #[test]
+    fn test_console_tap() {
+        let tap = ConsoleTap::default();
+        let mut writer = tap.writer(Vec::new());
+        writer.write_all(b"before").unwrap();
+
+        let mut capture = tap.capture();
+        writer.write_all(b"during").unwrap();
+        let mut buf = [0; 6];
+        futures::executor::block_on(capture.read_exact(&mut buf)).unwrap();
+        assert_eq!(&buf, b"during");
+
+        // A new capture replaces the old one, which no longer uninstalls it
+        // when dropped.
+        let newer = tap.capture();
+        drop(capture);
+        assert!(tap.0.lock().is_some());
+        drop(newer);
+        assert!(tap.0.lock().is_none());
+
+        writer.write_all(b"after").unwrap();
+        assert_eq!(writer.inner, b"beforeduringafter");
+    }
+
+    #[test]
+    fn test_tee() {
+        #[derive(Clone, Default)]
+        struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+        impl Write for Buffer {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.lock().extend_from_slice(buf);
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        struct Failing;
+
+        impl Write for Failing {
+            fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+                Err(io::ErrorKind::BrokenPipe.into())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let a = Buffer::default();
+        let b = Buffer::default();
+        let mut tee = TeeWriter(vec![
+            Box::new(a.clone()),
+            Box::new(Failing),
+            Box::new(b.clone()),
+        ]);
+        tee.write_all(b"hello ").unwrap();
+        // The failing destination is dropped, and output continues to the
+        // others.
+        assert_eq!(tee.0.len(), 2);
+        tee.write_all(b"world").unwrap();
+        assert_eq!(*a.0.lock(), b"hello world");
+        assert_eq!(*b.0.lock(), b"hello world");
+    }
+}
@@ -12,6 +12,7 @@
 use hvlite_defs::config::Config;
 use hvlite_defs::config::DeviceVtl;
 use hvlite_defs::config::LoadMode;
+use hvlite_defs::config::PcieDeviceConfig;
 use hvlite_defs::config::VpciDeviceConfig;
 use ide_resources::GuestMedia;
 use ide_resources::IdeDeviceConfig;
@@ -34,6 +35,7 @@ pub(super) struct StorageBuilder {
     vtl2_scsi_devices: Vec<ScsiDeviceAndPath>,
     vtl0_nvme_namespaces: Vec<NamespaceDefinition>,
     vtl2_nvme_namespaces: Vec<NamespaceDefinition>,
+    pcie_nvme_namespaces: Vec<(String, Vec<NamespaceDefinition>)>,
     underhill_scsi_luns: Vec<Lun>,
     underhill_nvme_luns: Vec<Lun>,
     openhcl_vtl: Option<DeviceVtl>,
@@ -74,6 +76,7 @@ pub fn new(openhcl_vtl: Option<DeviceVtl>) -> Self {
             vtl2_scsi_devices: Vec::new(),
             vtl0_nvme_namespaces: Vec::new(),
             vtl2_nvme_namespaces: Vec::new(),
+            pcie_nvme_namespaces: Vec::new(),
             underhill_scsi_luns: Vec::new(),
             underhill_nvme_luns: Vec::new(),
             openhcl_vtl,
@@ -104,6 +107,40 @@ pub fn add(
         Ok(())
     }
 
+    /// Adds an NVMe namespace to the controller behind the PCIe root port
+    /// `port`, creating the controller if necessary.
+    pub fn add_pcie_nvme(
+        &mut self,
+        port: &str,
+        kind: &DiskCliKind,
+        is_dvd: bool,
+        read_only: bool,
+    ) -> anyhow::Result<()> {
+        if is_dvd {
+            anyhow::bail!("dvd not supported with nvme");
+        }
+        let disk = disk_open(kind, read_only)?;
+        let index = match self
+            .pcie_nvme_namespaces
+            .iter()
+            .position(|(name, _)| name == port)
+        {
+            Some(index) => index,
+            None => {
+                self.pcie_nvme_namespaces
+                    .push((port.to_string(), Vec::new()));
+                self.pcie_nvme_namespaces.len() - 1
+            }
+        };
+        let namespaces = &mut self.pcie_nvme_namespaces[index].1;
+        namespaces.push(NamespaceDefinition {
+            nsid: namespaces.len() as u32 + 1,
+            disk,
+            read_only,
+        });
+        Ok(())
+    }
+
     /// Returns the "sub device path" for assigning this into Underhill, or
     /// `None` if Underhill can't use this device as a source.
     fn add_inner(
@@ -354,6 +391,19 @@ pub fn build_config(
             }
         }
 
+        for (port_name, namespaces) in self.pcie_nvme_namespaces.drain(..) {
+            config.pcie_devices.push(PcieDeviceConfig {
+                port_name,
+                resource: NvmeControllerHandle {
+                    subsystem_id: Guid::new_random(),
+                    namespaces,
+                    max_io_queues: 64,
+                    msix_count: 64,
+                }
+                .into_resource(),
+            });
+        }
+
         if !self.vtl2_nvme_namespaces.is_empty() {
             if config
                 .hypervisor
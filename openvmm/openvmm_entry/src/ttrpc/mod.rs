@@ -4,12 +4,15 @@
 //! Worker for the prototype gRPC/ttrpc management endpoint.
 
 use self::vmservice::nic_config::Backend;
+use crate::screenshot;
 use crate::serial_io::bind_serial;
 use crate::DEFAULT_MMIO_GAPS;
 use anyhow::anyhow;
 use anyhow::bail;
 use anyhow::Context;
 use awaitgroup::WaitGroup;
+use framebuffer::View;
+use framebuffer::FRAMEBUFFER_SIZE;
 use futures::FutureExt;
 use futures::StreamExt;
 use guid::Guid;
@@ -49,6 +52,7 @@
 use pal_async::DefaultPool;
 use parking_lot::Mutex;
 use scsidisk_resources::SimpleScsiDiskHandle;
+use sparse_mmap::alloc_shared_memory;
 use std::fs::File;
 use std::future::Future;
 use std::sync::Arc;
@@ -56,7 +60,9 @@
 use storvsp_resources::ScsiControllerHandle;
 use storvsp_resources::ScsiControllerRequest;
 use storvsp_resources::ScsiDeviceAndPath;
+use uidevices_resources::SynthVideoHandle;
 use unix_socket::UnixListener;
+use video_core::SharedFramebufferHandle;
 use virtio_resources::VirtioPciDeviceHandle;
 use vm_manifest_builder::VmManifestBuilder;
 use vm_resource::kind::VmbusDeviceHandleKind;
@@ -261,6 +267,7 @@ struct Vm {
     worker_rpc: mesh::Sender<VmRpc>,
     scsi_rpc: Option<mesh::Sender<ScsiControllerRequest>>,
     notify_recv: Mutex<Option<mesh::Receiver<HaltReason>>>,
+    screen: Option<Arc<Mutex<View>>>,
 }
 
 struct VmService {
@@ -335,6 +342,10 @@ async fn handle(&mut self, ctx: mesh::CancelContext, request: vmservice::Vm) ->
                         let r = self.modify_resource(&vm, request);
                         self.start_rpc(response, r);
                     }
+                    vmservice::Vm::ScreenshotVm((), response) => {
+                        let r = self.screenshot_vm(&vm);
+                        self.start_rpc(response, r);
+                    }
 
                     r @ vmservice::Vm::CapabilitiesVm(_, _)
                     | r @ vmservice::Vm::PropertiesVm(_, _) => {
@@ -445,13 +456,32 @@ async fn create_vm(&mut self, request: vmservice::CreateVmRequest) -> anyhow::Re
             })?);
         }
 
-        let chipset = VmManifestBuilder::new(
+        let mut chipset = VmManifestBuilder::new(
             vm_manifest_builder::BaseChipsetType::HyperVGen2LinuxDirect,
             vm_manifest_builder::MachineArch::X86_64,
         )
-        .with_serial(ports)
-        .build()
-        .context("failed to build vm configuration")?;
+        .with_serial(ports);
+
+        let synthetic_video = req_config
+            .devices_config
+            .as_ref()
+            .is_some_and(|c| c.synthetic_video);
+        let mut framebuffer = None;
+        let mut screen = None;
+        if synthetic_video {
+            let vram = alloc_shared_memory(FRAMEBUFFER_SIZE)?;
+            let (fb, access) = framebuffer::framebuffer(vram, FRAMEBUFFER_SIZE, 0)
+                .context("creating framebuffer")?;
+            framebuffer = Some(fb);
+            screen = Some(Arc::new(Mutex::new(
+                access.view().context("failed to map framebuffer")?,
+            )));
+            chipset = chipset.with_framebuffer();
+        }
+
+        let chipset = chipset
+            .build()
+            .context("failed to build vm configuration")?;
 
         let mut config = Config {
             // TODO: devices, other stuff
@@ -459,6 +489,8 @@ async fn create_vm(&mut self, request: vmservice::CreateVmRequest) -> anyhow::Re
             ide_disks: vec![],
             floppy_disks: vec![],
             vpci_devices: vec![],
+            pcie_root_ports: vec![],
+            pcie_devices: vec![],
             memory: MemoryConfig {
                 mem_size: req_config
                     .memory_config
@@ -469,6 +501,16 @@ async fn create_vm(&mut self, request: vmservice::CreateVmRequest) -> anyhow::Re
                     .context("invalid memory configuration")?,
                 mmio_gaps: DEFAULT_MMIO_GAPS.into(),
                 prefetch_memory: false,
+                max_mem_size: req_config
+                    .memory_config
+                    .as_ref()
+                    .map(|c| c.max_memory_mb)
+                    .filter(|&mb| mb != 0)
+                    .map(|mb| {
+                        mb.checked_mul(0x100000)
+                            .context("invalid maximum memory size")
+                    })
+                    .transpose()?,
             },
             chipset: chipset.chipset,
             processor_topology: ProcessorTopologyConfig {
@@ -480,7 +522,13 @@ async fn create_vm(&mut self, request: vmservice::CreateVmRequest) -> anyhow::Re
                 vps_per_socket: None,
                 enable_smt: None,
                 arch: Default::default(),
+                max_proc_count: req_config
+                    .processor_config
+                    .as_ref()
+                    .map(|c| c.max_processor_count)
+                    .filter(|&count| count != 0),
             },
+            numa: None,
             hypervisor: HypervisorConfig {
                 with_hv: true,
                 ..Default::default()
@@ -488,7 +536,7 @@ async fn create_vm(&mut self, request: vmservice::CreateVmRequest) -> anyhow::Re
             #[cfg(windows)]
             kernel_vmnics: vec![],
             input: mesh::MpscReceiver::new(),
-            framebuffer: None,
+            framebuffer,
             vga_firmware: None,
             vtl2_gfx: false,
             virtio_console_pci: false,
@@ -503,12 +551,23 @@ async fn create_vm(&mut self, request: vmservice::CreateVmRequest) -> anyhow::Re
             format_vmgs: false,
             secure_boot_enabled: false,
             custom_uefi_vars: Default::default(),
+            smbios: Default::default(),
             firmware_event_send: None,
             debugger_rpc: None,
             chipset_devices: chipset.chipset_devices,
             generation_id_recv: None,
         };
 
+        if synthetic_video {
+            config.vmbus_devices.push((
+                DeviceVtl::Vtl0,
+                SynthVideoHandle {
+                    framebuffer: SharedFramebufferHandle.into_resource(),
+                }
+                .into_resource(),
+            ));
+        }
+
         let mut scsi_rpc = None;
         if let Some(devices_config) = req_config.devices_config {
             if !devices_config.scsi_disks.is_empty() {
@@ -592,6 +651,7 @@ async fn create_vm(&mut self, request: vmservice::CreateVmRequest) -> anyhow::Re
             scsi_rpc,
             notify_recv: Mutex::new(Some(notify_recv)),
             worker_rpc: send,
+            screen,
         }));
         Ok(())
     }
@@ -610,6 +670,25 @@ fn pause_vm(&mut self, vm: &Vm) -> impl Future<Output = anyhow::Result<()>> {
         async move { recv.await.map(drop).context("pause failed") }
     }
 
+    fn screenshot_vm(
+        &self,
+        vm: &Vm,
+    ) -> anyhow::Result<impl Future<Output = anyhow::Result<vmservice::ScreenshotVmResponse>>> {
+        let screen = vm
+            .screen
+            .clone()
+            .ok_or(Code::FailedPrecondition)
+            .context("VM has no synthetic video device")?;
+        Ok(async move {
+            let screenshot = screenshot::capture_png(screen).await?;
+            Ok(vmservice::ScreenshotVmResponse {
+                width: screenshot.width,
+                height: screenshot.height,
+                png: screenshot.png,
+            })
+        })
+    }
+
     fn resume_vm(&mut self, vm: &Vm) -> impl Future<Output = anyhow::Result<()>> {
         let (send, recv) = mesh::oneshot();
         vm.worker_rpc.send(VmRpc::Resume(Rpc((), send)));
@@ -687,8 +766,31 @@ fn modify_resource(
             }
             Resource::VpmemDisk(_) => anyhow::bail!("vpmem not supported"),
             Resource::WindowsDevice(_) => anyhow::bail!("device assignment not supported"),
-            Resource::Processor(_) | Resource::ProcessorConfig(_) | Resource::Memory(_) => {
-                anyhow::bail!("processor and memory resources not supported")
+            Resource::Processor(processor) => {
+                let recv = if request.r#type == vmservice::ModifyType::Add as i32 {
+                    vm.worker_rpc
+                        .call_failable(VmRpc::AddProcessor, processor.processor_index)
+                } else if request.r#type == vmservice::ModifyType::Remove as i32 {
+                    vm.worker_rpc
+                        .call_failable(VmRpc::RemoveProcessor, processor.processor_index)
+                } else {
+                    anyhow::bail!("unsupported request type {}", request.r#type);
+                };
+                Ok(async move { recv.await.map_err(anyhow::Error::from) }.boxed())
+            }
+            Resource::Memory(memory) => {
+                if request.r#type != vmservice::ModifyType::Add as i32 {
+                    anyhow::bail!("only memory hot-add is supported");
+                }
+                let size = memory
+                    .memory_mb
+                    .checked_mul(0x100000)
+                    .context("invalid memory size")?;
+                let recv = vm.worker_rpc.call_failable(VmRpc::AddMemory, size);
+                Ok(async move { recv.await.map_err(anyhow::Error::from) }.boxed())
+            }
+            Resource::ProcessorConfig(_) => {
+                anyhow::bail!("processor config resources not supported")
             }
         }
     }
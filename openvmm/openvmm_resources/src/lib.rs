@@ -12,6 +12,8 @@
     // Chipset devices
     #[cfg(guest_arch = "x86_64")]
     chipset::i8042::resolver::I8042Resolver,
+    #[cfg(guest_arch = "x86_64")]
+    chipset::hpet::resolver::HpetResolver,
     missing_dev::resolver::MissingDevResolver,
     #[cfg(feature = "tpm")]
     tpm::resolver::TpmDeviceResolver,
@@ -22,6 +24,8 @@
     #[cfg(guest_arch = "aarch64")]
     serial_pl011::resolver::SerialPl011Resolver,
     chipset::battery::resolver::BatteryResolver,
+    pvpanic::resolver::PvPanicResolver,
+    fw_cfg::resolver::FwCfgResolver,
 
     // Non-volatile stores
     vmcore::non_volatile_store::resources::EphemeralNonVolatileStoreResolver,
@@ -46,7 +50,9 @@
     #[cfg(feature = "disk_crypt")]
     disk_crypt::resolver::DiskCryptResolver,
     disk_file::FileDiskResolver,
+    disk_iso::resolver::IsoDirDiskResolver,
     disk_prwrap::DiskWithReservationsResolver,
+    disk_vfat::resolver::FatDirDiskResolver,
     disk_vhd1::Vhd1Resolver,
     #[cfg(windows)]
     disk_vhdmp::VhdmpDiskResolver,
@@ -61,6 +67,8 @@
     // PCI devices
     gdma::resolver::GdmaDeviceResolver,
     nvme::resolver::NvmeControllerResolver,
+    #[cfg(target_os = "linux")]
+    vfio_user_client::resolver::VfioUserResolver,
     virtio::resolver::VirtioPciResolver,
 
     // SCSI
@@ -71,6 +79,8 @@
     virtiofs::resolver::VirtioFsResolver,
     #[cfg(any(windows, target_os = "linux"))]
     virtio_p9::resolver::VirtioPlan9Resolver,
+    virtio_gpu::resolver::VirtioGpuResolver,
+    virtio_input::resolver::VirtioInputResolver,
     virtio_net::resolver::VirtioNetResolver,
     virtio_pmem::resolver::VirtioPmemResolver,
 
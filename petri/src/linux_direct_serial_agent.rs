@@ -1,20 +1,20 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT License.
 
-use futures::AsyncReadExt;
-use futures::AsyncWriteExt;
 use pal_async::socket::ReadHalf;
 use pal_async::socket::WriteHalf;
+use regex::bytes::Regex;
+use serial_core::expect::Expect;
 use unix_socket::UnixStream;
 
 const BUSYBOX_INIT: &str =
     "/bin/busybox --install /bin && mount none /dev -t devtmpfs && mount none /proc -t proc && mount none /sys -t sysfs";
 
 pub(crate) struct LinuxDirectSerialAgent {
-    /// Writer to serial 0, the console we define in our kernel commandline
-    write: WriteHalf<UnixStream>,
-    /// Reader on serial 1, not serial 0, to avoid reading the commands we just sent
-    read: ReadHalf<UnixStream>,
+    /// Writes to serial 0, the console we define in our kernel commandline,
+    /// and reads from serial 1, not serial 0, to avoid reading the commands
+    /// we just sent
+    expect: Expect<ReadHalf<UnixStream>, WriteHalf<UnixStream>>,
     /// Delayed initialization so new can be synchronous
     init: bool,
 }
@@ -25,8 +25,8 @@ pub(crate) fn new(
         serial0_write: WriteHalf<UnixStream>,
     ) -> Self {
         Self {
-            read: serial1_read,
-            write: serial0_write,
+            // Command output is returned in full, however large.
+            expect: Expect::new(serial1_read, serial0_write).with_max_buffer(None),
             init: false,
         }
     }
@@ -55,19 +55,11 @@ async fn run_command_core(&mut self, command: &str) -> anyhow::Result<Vec<u8>> {
         // When reading the output there will be a trailing newline.
         const COMMAND_END_SIGNAL_READ: &str = "== Petri Command Complete ==\r\n";
 
-        self.write.write_all(command.as_bytes()).await?;
+        let end_signal = Regex::new(&regex::escape(COMMAND_END_SIGNAL_READ)).unwrap();
 
-        let mut output = Vec::new();
-        let mut buf = [0u8; 1024];
-        loop {
-            let n = self.read.read(&mut buf).await?;
-            tracing::debug!(buf = ?&buf[..n], "read serial bytes from guest");
-            output.extend_from_slice(&buf[..n]);
-            if output.ends_with(COMMAND_END_SIGNAL_READ.as_bytes()) {
-                output.truncate(output.len() - COMMAND_END_SIGNAL_READ.len());
-                break;
-            }
-        }
+        self.expect.send(command.as_bytes()).await?;
+        let output = self.expect.expect(&end_signal).await?.before;
+        tracing::debug!(output = ?output, "read serial bytes from guest");
 
         Ok(output)
     }
@@ -288,13 +288,16 @@ pub fn new(
                     DEFAULT_MMIO_GAPS.into()
                 },
                 prefetch_memory: false,
+                max_mem_size: None,
             },
             processor_topology: ProcessorTopologyConfig {
                 proc_count: 2,
                 vps_per_socket: None,
                 enable_smt: None,
                 arch: Default::default(),
+                max_proc_count: None,
             },
+            numa: None,
 
             // Base chipset
             chipset: chipset.chipset,
@@ -322,6 +325,8 @@ pub fn new(
             floppy_disks,
             ide_disks,
             vpci_devices,
+            pcie_root_ports: vec![],
+            pcie_devices: vec![],
             vmbus_devices,
 
             // Video support
@@ -330,6 +335,7 @@ pub fn new(
 
             // Reasonable defaults
             custom_uefi_vars: Default::default(),
+            smbios: Default::default(),
 
             // Disabled for VMM tests by default
             #[cfg(windows)]
@@ -115,6 +115,147 @@ fn append_to_vec(&self, byte_stream: &mut Vec<u8>) {
     }
 }
 
+pub struct Scope {
+    name: Vec<u8>,
+    objects: Vec<u8>,
+}
+
+impl Scope {
+    pub fn new(name: &[u8]) -> Self {
+        Self {
+            name: encode_name(name),
+            objects: vec![],
+        }
+    }
+
+    pub fn add_object(&mut self, obj: &impl DsdtObject) {
+        obj.append_to_vec(&mut self.objects);
+    }
+}
+
+impl DsdtObject for Scope {
+    // A scope object consists of the identifier (0x10) followed by the length, the name and then the contained
+    // objects.
+    fn append_to_vec(&self, byte_stream: &mut Vec<u8>) {
+        byte_stream.push(0x10);
+        let length = self.name.len() + self.objects.len();
+        byte_stream.extend_from_slice(&encode_package_len(length));
+        byte_stream.extend_from_slice(&self.name);
+        byte_stream.extend_from_slice(&self.objects);
+    }
+}
+
+#[repr(u8)]
+#[derive(Copy, Clone, Debug)]
+pub enum RegionSpace {
+    SystemMemory = 0,
+    SystemIo = 1,
+}
+
+pub struct OperationRegion {
+    name: Vec<u8>,
+    space: RegionSpace,
+    offset: u64,
+    length: u64,
+}
+
+impl OperationRegion {
+    pub fn new(name: &[u8], space: RegionSpace, offset: u64, length: u64) -> Self {
+        Self {
+            name: encode_name(name),
+            space,
+            offset,
+            length,
+        }
+    }
+}
+
+impl DsdtObject for OperationRegion {
+    // An operation region consists of the extended identifier (0x5b 0x80) followed by the name, the address space,
+    // and then the offset and length of the region.
+    fn append_to_vec(&self, byte_stream: &mut Vec<u8>) {
+        byte_stream.push(0x5b);
+        byte_stream.push(0x80);
+        byte_stream.extend_from_slice(&self.name);
+        byte_stream.push(self.space as u8);
+        byte_stream.extend_from_slice(&encode_integer(self.offset));
+        byte_stream.extend_from_slice(&encode_integer(self.length));
+    }
+}
+
+#[repr(u8)]
+#[derive(Copy, Clone, Debug)]
+pub enum FieldAccessType {
+    Any = 0,
+    Byte = 1,
+    Word = 2,
+    DWord = 3,
+    QWord = 4,
+}
+
+#[repr(u8)]
+#[derive(Copy, Clone, Debug)]
+pub enum FieldUpdateRule {
+    Preserve = 0,
+    WriteAsOnes = 1,
+    WriteAsZeros = 2,
+}
+
+pub struct Field {
+    region: Vec<u8>,
+    access_type: FieldAccessType,
+    update_rule: FieldUpdateRule,
+    elements: Vec<u8>,
+}
+
+impl Field {
+    pub fn new(region: &[u8], access_type: FieldAccessType, update_rule: FieldUpdateRule) -> Self {
+        Self {
+            region: encode_name(region),
+            access_type,
+            update_rule,
+            elements: vec![],
+        }
+    }
+
+    /// Adds a named field of `bits` bits, following the previous element.
+    pub fn add_named(&mut self, name: &[u8; 4], bits: usize) {
+        self.elements.extend_from_slice(name);
+        self.elements.extend_from_slice(&Self::encode_bits(bits));
+    }
+
+    /// Skips `bits` bits of the region.
+    pub fn add_reserved(&mut self, bits: usize) {
+        self.elements.push(0);
+        self.elements.extend_from_slice(&Self::encode_bits(bits));
+    }
+
+    // Field sizes use the package length encoding, but without counting the
+    // bytes of the encoding itself.
+    fn encode_bits(bits: usize) -> Vec<u8> {
+        assert!(bits < 1 << 12);
+        if bits < 0x40 {
+            vec![bits as u8]
+        } else {
+            vec![(1 << 6) | (bits & 0xf) as u8, (bits >> 4) as u8]
+        }
+    }
+}
+
+impl DsdtObject for Field {
+    // A field consists of the extended identifier (0x5b 0x81) followed by the length, the region name, the flags and
+    // then the field elements. Field elements encode their size in bits using the package length encoding.
+    fn append_to_vec(&self, byte_stream: &mut Vec<u8>) {
+        byte_stream.push(0x5b);
+        byte_stream.push(0x81);
+        let length = self.region.len() + 1 + self.elements.len();
+        byte_stream.extend_from_slice(&encode_package_len(length));
+        byte_stream.extend_from_slice(&self.region);
+        byte_stream.push(self.access_type as u8 | (self.update_rule as u8) << 5);
+        byte_stream.extend_from_slice(&self.elements);
+    }
+}
+
 pub struct PciRoutingTableEntry {
     pub address: u32,
     pub pin: u8,
@@ -173,6 +314,18 @@ fn append_to_vec(&self, byte_stream: &mut Vec<u8>) {
     }
 }
 
+/// A processor described by [`Dsdt::add_cpu_hotplug`].
+pub struct HotplugProcessor {
+    /// The index used to select the processor in the hotplug register block.
+    pub index: u32,
+    /// The ACPI processor UID, which must match the processor's MADT entry.
+    pub uid: u32,
+    /// The processor's APIC ID.
+    pub apic_id: u32,
+    /// Whether the processor can be hot-added and hot-removed.
+    pub hotpluggable: bool,
+}
+
 pub struct Dsdt {
     description_header: DescriptionHeader,
     objects: Vec<u8>,
@@ -196,6 +349,15 @@ pub fn new() -> Self {
         }
     }
 
+    /// Returns an empty secondary system description table (SSDT), which
+    /// extends the namespace defined by the DSDT using the same encoding.
+    pub fn new_ssdt() -> Self {
+        let mut ssdt = Self::new();
+        ssdt.description_header.signature = u32::from_le_bytes(*b"SSDT");
+        ssdt.description_header.oem_table_id = 0x313054445353; // b'SSDT01'
+        ssdt
+    }
+
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut byte_stream = Vec::new();
         byte_stream.extend_from_slice(self.description_header.as_bytes());
@@ -332,8 +494,8 @@ pub fn add_pci(
     ) {
         let mut pci0 = Device::new(b"\\_SB.PCI0");
         pci0.add_object(&NamedObject::new(b"_HID", &EisaId(*b"PNP0A03")));
-        // FUTURE: when implementing PCIe, switch _HID over to "PNP0A08", and a
-        // _CID for "PNP0A03"
+        // NOTE: this is a conventional PCI bus. PCI Express root complexes are
+        // added separately, via `add_pcie`.
 
         // OS negotiation for control of the bus. See https://uefi.org/specs/ACPI/6.4/06_Device_Configuration/Device_Configuration.html#osc-operating-system-capabilities
         // TODO: Lots of work needed for _OSC.
@@ -362,6 +524,83 @@ pub fn add_pci(
         self.add_object(&pci0);
     }
 
+    /// Adds a PCI Express root complex for the specified segment, decoding
+    /// buses `start_bus..=end_bus` via the given ECAM region, and forwarding
+    /// the specified MMIO ranges.
+    ///
+    /// The ECAM region itself is reserved via a motherboard resources device,
+    /// as required by the PCI Firmware Specification.
+    ///
+    /// ```text
+    /// Device(\_SB.PCIE)
+    /// {
+    ///     Name(_HID, PNP0A08)
+    ///     Name(_CID, PNP0A03)
+    ///     Name(_UID, <segment>)
+    ///     Name(_SEG, <segment>)
+    ///     Name(_BBN, <start_bus>)
+    ///     // Grant the OS control over all requested features.
+    ///     Method(_OSC, 4) { Return(Arg3) }
+    ///     Name(_CRS, ResourceTemplate()
+    ///     {
+    ///         WordBusNumber(...) // start_bus..=end_bus
+    ///         QWordMemory() // for each MMIO range
+    ///     })
+    /// }
+    ///
+    /// Device(\_SB.ECAM)
+    /// {
+    ///     Name(_HID, PNP0C02)
+    ///     Name(_UID, <segment>)
+    ///     Name(_CRS, ResourceTemplate()
+    ///     {
+    ///         QWordMemory() // ECAM
+    ///     })
+    /// }
+    /// ```
+    pub fn add_pcie(
+        &mut self,
+        segment: u16,
+        start_bus: u8,
+        end_bus: u8,
+        ecam: MemoryRange,
+        mmio: &[MemoryRange],
+    ) {
+        let mut pcie = Device::new(b"\\_SB.PCIE");
+        pcie.add_object(&NamedObject::new(b"_HID", &EisaId(*b"PNP0A08")));
+        pcie.add_object(&NamedObject::new(b"_CID", &EisaId(*b"PNP0A03")));
+        pcie.add_object(&NamedInteger::new(b"_UID", segment.into()));
+        pcie.add_object(&NamedInteger::new(b"_SEG", segment.into()));
+        pcie.add_object(&NamedInteger::new(b"_BBN", start_bus.into()));
+
+        let mut osc = Method::new(b"_OSC");
+        osc.set_arg_count(4);
+        osc.add_operation(&ReturnOp {
+            // Arg3
+            result: vec![0x6b],
+        });
+        pcie.add_object(&osc);
+
+        let mut crs = CurrentResourceSettings::new();
+        crs.add_resource(&BusNumber::new(
+            start_bus.into(),
+            (end_bus - start_bus) as u16 + 1,
+        ));
+        for range in mmio {
+            crs.add_resource(&QwordMemory::new(range.start(), range.len()));
+        }
+        pcie.add_object(&crs);
+        self.add_object(&pcie);
+
+        let mut res = Device::new(b"\\_SB.ECAM");
+        res.add_object(&NamedObject::new(b"_HID", &EisaId(*b"PNP0C02")));
+        res.add_object(&NamedInteger::new(b"_UID", segment.into()));
+        let mut crs = CurrentResourceSettings::new();
+        crs.add_resource(&QwordMemory::new(ecam.start(), ecam.len()));
+        res.add_object(&crs);
+        self.add_object(&res);
+    }
+
     /// Add a VMBUS device to the DSDT.
     ///
     /// If `in_pci`, then enumerate the device under PCI0. Otherwise, enumerate
@@ -452,6 +691,456 @@ pub fn add_rtc(&mut self) {
         rtc.add_object(&rtc_crs);
         self.add_object(&rtc);
     }
+
+    /// Add a pvpanic device with the following ASL code:
+    /// ```text
+    /// Device(\_SB.PEVT)
+    /// {
+    ///     Name(_HID, "QEMU0001")
+    ///     Name(_UID, 0)
+    ///     Name(_CRS, ResourceTemplate()
+    ///     {
+    ///         IO(Decode16, <io_port>, <io_port>, 1, 1)
+    ///     })
+    /// }
+    /// ```
+    pub fn add_pvpanic(&mut self, io_port: u16) {
+        let mut pevt = Device::new(b"\\_SB.PEVT");
+        pevt.add_object(&NamedString::new(b"_HID", b"QEMU0001"));
+        pevt.add_object(&NamedInteger::new(b"_UID", 0));
+        let mut pevt_crs = CurrentResourceSettings::new();
+        pevt_crs.add_resource(&IoPort::new(io_port, io_port, 1));
+        pevt.add_object(&pevt_crs);
+        self.add_object(&pevt);
+    }
+
+    /// Add a fw_cfg device with the following ASL code:
+    /// ```text
+    /// Device(\_SB.FWCF)
+    /// {
+    ///     Name(_HID, "QEMU0002")
+    ///     Name(_UID, 0)
+    ///     Name(_CRS, ResourceTemplate()
+    ///     {
+    ///         IO(Decode16, <io_port>, <io_port>, 1, <len>)
+    ///     })
+    /// }
+    /// ```
+    pub fn add_fw_cfg(&mut self, io_port: u16, len: u8) {
+        let mut fwcf = Device::new(b"\\_SB.FWCF");
+        fwcf.add_object(&NamedString::new(b"_HID", b"QEMU0002"));
+        fwcf.add_object(&NamedInteger::new(b"_UID", 0));
+        let mut fwcf_crs = CurrentResourceSettings::new();
+        fwcf_crs.add_resource(&IoPort::new(io_port, io_port, len));
+        fwcf.add_object(&fwcf_crs);
+        self.add_object(&fwcf);
+    }
+
+    /// Add a fw_cfg device using the MMIO register layout with the following
+    /// ASL code:
+    /// ```text
+    /// Device(\_SB.FWCF)
+    /// {
+    ///     Name(_HID, "QEMU0002")
+    ///     Name(_UID, 0)
+    ///     Name(_CCA, 1)
+    ///     Name(_CRS, ResourceTemplate()
+    ///     {
+    ///         Memory32Fixed(ReadWrite, <base>, <len>)
+    ///     })
+    /// }
+    /// ```
+    pub fn add_fw_cfg_mmio(&mut self, base: u32, len: u32) {
+        let mut fwcf = Device::new(b"\\_SB.FWCF");
+        fwcf.add_object(&NamedString::new(b"_HID", b"QEMU0002"));
+        fwcf.add_object(&NamedInteger::new(b"_UID", 0));
+        // DMA is cache coherent.
+        fwcf.add_object(&NamedInteger::new(b"_CCA", 1));
+        let mut fwcf_crs = CurrentResourceSettings::new();
+        fwcf_crs.add_resource(&Memory32Fixed::new(base, len, true));
+        fwcf.add_object(&fwcf_crs);
+        self.add_object(&fwcf);
+    }
+
+    /// Add processor devices supporting hot-add and hot-remove, backed by a
+    /// CPU hotplug register block at `io_port` which signals events on GPE0
+    /// bit `gpe`, with the following ASL code:
+    /// ```text
+    /// Device(\_SB.CPUS)
+    /// {
+    ///     Name(_HID, "ACPI0010") // processor container
+    ///     OperationRegion(PRST, SystemIO, <io_port>, 8)
+    ///     Field(PRST, DWordAcc, NoLock, Preserve)
+    ///     {
+    ///         CSEL, 32,
+    ///     }
+    ///     Field(PRST, ByteAcc, NoLock, Preserve)
+    ///     {
+    ///         Offset(4),
+    ///         CSTA, 8,
+    ///         CEVT, 8,
+    ///         CEJ0, 8,
+    ///     }
+    ///     Method(CPST, 1, Serialized)
+    ///     {
+    ///         Store(Arg0, CSEL)
+    ///         Return(CSTA)
+    ///     }
+    ///     Method(CPEJ, 1, Serialized)
+    ///     {
+    ///         Store(Arg0, CSEL)
+    ///         Store(One, CEJ0)
+    ///     }
+    ///     Method(CPEV, 1, Serialized)
+    ///     {
+    ///         Store(Arg0, CSEL)
+    ///         Store(CEVT, Local0)
+    ///         Store(Local0, CEVT)
+    ///         Return(Local0)
+    ///     }
+    ///     Method(CSCN, 0, Serialized)
+    ///     {
+    ///         // For each hotpluggable processor:
+    ///         Store(CPEV(<index>), Local0)
+    ///         If (Local0)
+    ///         {
+    ///             Notify(C<index>, Local0)
+    ///         }
+    ///     }
+    ///     // For each processor:
+    ///     Device(C<index>)
+    ///     {
+    ///         Name(_HID, "ACPI0007") // processor device
+    ///         Name(_UID, <uid>)
+    ///         Method(_STA, 0) { Return(CPST(<index>)) }
+    ///         Method(_MAT, 0) { Return(<enabled MADT APIC or X2APIC entry>) }
+    ///         // Only for hotpluggable processors:
+    ///         Method(_EJ0, 1) { CPEJ(<index>) }
+    ///     }
+    /// }
+    /// Scope(\_GPE)
+    /// {
+    ///     Method(_E<gpe>, 0) { \_SB.CPUS.CSCN() }
+    /// }
+    /// ```
+    pub fn add_cpu_hotplug(&mut self, io_port: u16, gpe: u8, processors: &[HotplugProcessor]) {
+        const LOCAL0: u8 = 0x60;
+        const ARG0: u8 = 0x68;
+
+        let processor_name = |index: u32| {
+            assert!(index < 0x1000, "processor index too large");
+            format!("C{:03X}", index).into_bytes()
+        };
+
+        let mut cpus = Device::new(b"\\_SB.CPUS");
+        cpus.add_object(&NamedString::new(b"_HID", b"ACPI0010"));
+        cpus.add_object(&OperationRegion::new(
+            b"PRST",
+            RegionSpace::SystemIo,
+            io_port.into(),
+            8,
+        ));
+        let mut field = Field::new(b"PRST", FieldAccessType::DWord, FieldUpdateRule::Preserve);
+        field.add_named(b"CSEL", 32);
+        cpus.add_object(&field);
+        let mut field = Field::new(b"PRST", FieldAccessType::Byte, FieldUpdateRule::Preserve);
+        field.add_reserved(32);
+        field.add_named(b"CSTA", 8);
+        field.add_named(b"CEVT", 8);
+        field.add_named(b"CEJ0", 8);
+        cpus.add_object(&field);
+
+        let select = StoreOp {
+            operand: vec![ARG0],
+            target_name: b"CSEL".to_vec(),
+        };
+        let mut method = Method::new(b"CPST");
+        method.is_serialized = true;
+        method.set_arg_count(1);
+        method.add_operation(&select);
+        method.add_operation(&ReturnOp {
+            result: b"CSTA".to_vec(),
+        });
+        cpus.add_object(&method);
+
+        let mut method = Method::new(b"CPEJ");
+        method.is_serialized = true;
+        method.set_arg_count(1);
+        method.add_operation(&select);
+        method.add_operation(&StoreOp {
+            operand: encode_integer(1),
+            target_name: b"CEJ0".to_vec(),
+        });
+        cpus.add_object(&method);
+
+        let mut method = Method::new(b"CPEV");
+        method.is_serialized = true;
+        method.set_arg_count(1);
+        method.add_operation(&select);
+        method.add_operation(&StoreOp {
+            operand: b"CEVT".to_vec(),
+            target_name: vec![LOCAL0],
+        });
+        method.add_operation(&StoreOp {
+            operand: vec![LOCAL0],
+            target_name: b"CEVT".to_vec(),
+        });
+        method.add_operation(&ReturnOp {
+            result: vec![LOCAL0],
+        });
+        cpus.add_object(&method);
+
+        let mut scan = Method::new(b"CSCN");
+        scan.is_serialized = true;
+        for processor in processors.iter().filter(|p| p.hotpluggable) {
+            scan.add_operation(&StoreOp {
+                operand: MethodCallOp {
+                    name: b"CPEV".to_vec(),
+                    args: vec![encode_integer(processor.index.into())],
+                }
+                .to_bytes(),
+                target_name: vec![LOCAL0],
+            });
+            let mut notify = IfOp::new(vec![LOCAL0]);
+            notify.add_operation(&NotifyOp {
+                object: processor_name(processor.index),
+                value: vec![LOCAL0],
+            });
+            scan.add_operation(&notify);
+        }
+        cpus.add_object(&scan);
+
+        for processor in processors {
+            let index = encode_integer(processor.index.into());
+            let mut cpu = Device::new(&processor_name(processor.index));
+            cpu.add_object(&NamedString::new(b"_HID", b"ACPI0007"));
+            cpu.add_object(&NamedInteger::new(b"_UID", processor.uid.into()));
+
+            let mut method = Method::new(b"_STA");
+            method.add_operation(&ReturnOp {
+                result: MethodCallOp {
+                    name: b"CPST".to_vec(),
+                    args: vec![index.clone()],
+                }
+                .to_bytes(),
+            });
+            cpu.add_object(&method);
+
+            let mat = if processor.apic_id <= 0xfe && processor.uid <= u8::MAX.into() {
+                acpi_spec::madt::MadtApic {
+                    apic_id: processor.apic_id as u8,
+                    acpi_processor_uid: processor.uid as u8,
+                    flags: acpi_spec::madt::MADT_APIC_ENABLED,
+                    ..acpi_spec::madt::MadtApic::new()
+                }
+                .as_bytes()
+                .to_vec()
+            } else {
+                acpi_spec::madt::MadtX2Apic {
+                    x2_apic_id: processor.apic_id,
+                    acpi_processor_uid: processor.uid,
+                    flags: acpi_spec::madt::MADT_APIC_ENABLED,
+                    ..acpi_spec::madt::MadtX2Apic::new()
+                }
+                .as_bytes()
+                .to_vec()
+            };
+            let mut method = Method::new(b"_MAT");
+            method.add_operation(&ReturnOp {
+                result: Buffer(mat).to_bytes(),
+            });
+            cpu.add_object(&method);
+
+            if processor.hotpluggable {
+                let mut method = Method::new(b"_EJ0");
+                method.set_arg_count(1);
+                method.add_operation(&MethodCallOp {
+                    name: b"CPEJ".to_vec(),
+                    args: vec![index],
+                });
+                cpu.add_object(&method);
+            }
+
+            cpus.add_object(&cpu);
+        }
+        self.add_object(&cpus);
+
+        let mut gpe_scope = Scope::new(b"\\_GPE");
+        let mut method = Method::new(format!("_E{:02X}", gpe).as_bytes().try_into().unwrap());
+        method.add_operation(&MethodCallOp {
+            name: encode_name(b"\\_SB.CPUS.CSCN"),
+            args: vec![],
+        });
+        gpe_scope.add_object(&method);
+        self.add_object(&gpe_scope);
+    }
+
+    /// Add memory devices supporting hot-add, one per slot in `slots`, backed
+    /// by a memory hotplug register block at `io_port` which signals events
+    /// on GPE0 bit `gpe`, with the following ASL code:
+    /// ```text
+    /// Device(\_SB.MHPC)
+    /// {
+    ///     Name(_HID, EISAID("PNP0A06")) // generic container
+    ///     Name(_UID, "MHPC")
+    ///     OperationRegion(MRST, SystemIO, <io_port>, 8)
+    ///     Field(MRST, DWordAcc, NoLock, Preserve)
+    ///     {
+    ///         MSEL, 32,
+    ///     }
+    ///     Field(MRST, ByteAcc, NoLock, Preserve)
+    ///     {
+    ///         Offset(4),
+    ///         MSTA, 8,
+    ///         MEVT, 8,
+    ///     }
+    ///     Method(MEST, 1, Serialized)
+    ///     {
+    ///         Store(Arg0, MSEL)
+    ///         Return(MSTA)
+    ///     }
+    ///     Method(MEEV, 1, Serialized)
+    ///     {
+    ///         Store(Arg0, MSEL)
+    ///         Store(MEVT, Local0)
+    ///         Store(Local0, MEVT)
+    ///         Return(Local0)
+    ///     }
+    ///     Method(MSCN, 0, Serialized)
+    ///     {
+    ///         // For each slot:
+    ///         Store(MEEV(<index>), Local0)
+    ///         If (Local0)
+    ///         {
+    ///             Notify(M<index>, Local0)
+    ///         }
+    ///     }
+    ///     // For each slot:
+    ///     Device(M<index>)
+    ///     {
+    ///         Name(_HID, EISAID("PNP0C80")) // memory device
+    ///         Name(_UID, <index>)
+    ///         Method(_STA, 0) { Return(MEST(<index>)) }
+    ///         Name(_CRS, ResourceTemplate()
+    ///         {
+    ///             QWordMemory(..., <slot start>, <slot end>, ...)
+    ///         })
+    ///     }
+    /// }
+    /// Scope(\_GPE)
+    /// {
+    ///     Method(_E<gpe>, 0) { \_SB.MHPC.MSCN() }
+    /// }
+    /// ```
+    pub fn add_memory_hotplug(&mut self, io_port: u16, gpe: u8, slots: &[MemoryRange]) {
+        const LOCAL0: u8 = 0x60;
+        const ARG0: u8 = 0x68;
+
+        let slot_name = |index: usize| {
+            assert!(index < 0x1000, "too many memory slots");
+            format!("M{:03X}", index).into_bytes()
+        };
+
+        let mut mhpc = Device::new(b"\\_SB.MHPC");
+        mhpc.add_object(&NamedObject::new(b"_HID", &EisaId(*b"PNP0A06")));
+        mhpc.add_object(&NamedString::new(b"_UID", b"MHPC"));
+        mhpc.add_object(&OperationRegion::new(
+            b"MRST",
+            RegionSpace::SystemIo,
+            io_port.into(),
+            8,
+        ));
+        let mut field = Field::new(b"MRST", FieldAccessType::DWord, FieldUpdateRule::Preserve);
+        field.add_named(b"MSEL", 32);
+        mhpc.add_object(&field);
+        let mut field = Field::new(b"MRST", FieldAccessType::Byte, FieldUpdateRule::Preserve);
+        field.add_reserved(32);
+        field.add_named(b"MSTA", 8);
+        field.add_named(b"MEVT", 8);
+        mhpc.add_object(&field);
+
+        let select = StoreOp {
+            operand: vec![ARG0],
+            target_name: b"MSEL".to_vec(),
+        };
+        let mut method = Method::new(b"MEST");
+        method.is_serialized = true;
+        method.set_arg_count(1);
+        method.add_operation(&select);
+        method.add_operation(&ReturnOp {
+            result: b"MSTA".to_vec(),
+        });
+        mhpc.add_object(&method);
+
+        let mut method = Method::new(b"MEEV");
+        method.is_serialized = true;
+        method.set_arg_count(1);
+        method.add_operation(&select);
+        method.add_operation(&StoreOp {
+            operand: b"MEVT".to_vec(),
+            target_name: vec![LOCAL0],
+        });
+        method.add_operation(&StoreOp {
+            operand: vec![LOCAL0],
+            target_name: b"MEVT".to_vec(),
+        });
+        method.add_operation(&ReturnOp {
+            result: vec![LOCAL0],
+        });
+        mhpc.add_object(&method);
+
+        let mut scan = Method::new(b"MSCN");
+        scan.is_serialized = true;
+        for index in 0..slots.len() {
+            scan.add_operation(&StoreOp {
+                operand: MethodCallOp {
+                    name: b"MEEV".to_vec(),
+                    args: vec![encode_integer(index as u64)],
+                }
+                .to_bytes(),
+                target_name: vec![LOCAL0],
+            });
+            let mut notify = IfOp::new(vec![LOCAL0]);
+            notify.add_operation(&NotifyOp {
+                object: slot_name(index),
+                value: vec![LOCAL0],
+            });
+            scan.add_operation(&notify);
+        }
+        mhpc.add_object(&scan);
+
+        for (index, range) in slots.iter().enumerate() {
+            let mut mem = Device::new(&slot_name(index));
+            mem.add_object(&NamedObject::new(b"_HID", &EisaId(*b"PNP0C80")));
+            mem.add_object(&NamedInteger::new(b"_UID", index as u64));
+
+            let mut method = Method::new(b"_STA");
+            method.add_operation(&ReturnOp {
+                result: MethodCallOp {
+                    name: b"MEST".to_vec(),
+                    args: vec![encode_integer(index as u64)],
+                }
+                .to_bytes(),
+            });
+            mem.add_object(&method);
+
+            let mut crs = CurrentResourceSettings::new();
+            crs.add_resource(&QwordMemory::new(range.start(), range.len()));
+            mem.add_object(&crs);
+
+            mhpc.add_object(&mem);
+        }
+        self.add_object(&mhpc);
+
+        let mut gpe_scope = Scope::new(b"\\_GPE");
+        let mut method = Method::new(format!("_E{:02X}", gpe).as_bytes().try_into().unwrap());
+        method.add_operation(&MethodCallOp {
+            name: encode_name(b"\\_SB.MHPC.MSCN"),
+            args: vec![],
+        });
+        gpe_scope.add_object(&method);
+        self.add_object(&gpe_scope);
+    }
 }
 
 #[cfg(test)]
@@ -710,4 +1399,176 @@ fn verify_table() {
             ],
         );
     }
+
+    #[test]
+    fn verify_scope() {
+        let mut scope = Scope::new(b"\\_GPE");
+        scope.add_object(&NamedInteger::new(b"FOO", 1));
+        let bytes = scope.to_bytes();
+        verify_expected_bytes(
+            &bytes,
+            &[
+                0x10, 0x0c, b'\\', b'_', b'G', b'P', b'E', 8, b'F', b'O', b'O', b'_', 1,
+            ],
+        );
+    }
+
+    #[test]
+    fn verify_operation_region() {
+        let region = OperationRegion::new(b"PRST", RegionSpace::SystemIo, 0xcd8, 8);
+        let bytes = region.to_bytes();
+        verify_expected_bytes(
+            &bytes,
+            &[
+                0x5b, 0x80, b'P', b'R', b'S', b'T', 0x01, 0x0b, 0xd8, 0x0c, 0x0a, 0x08,
+            ],
+        );
+    }
+
+    #[test]
+    fn verify_field() {
+        let mut field = Field::new(b"PRST", FieldAccessType::Byte, FieldUpdateRule::Preserve);
+        field.add_reserved(32);
+        field.add_named(b"CSTA", 8);
+        let bytes = field.to_bytes();
+        verify_expected_bytes(
+            &bytes,
+            &[
+                0x5b, 0x81, 0x0d, b'P', b'R', b'S', b'T', 0x01, 0x00, 0x20, b'C', b'S', b'T', b'A',
+                0x08,
+            ],
+        );
+
+        let mut field = Field::new(
+            b"PRST",
+            FieldAccessType::DWord,
+            FieldUpdateRule::WriteAsZeros,
+        );
+        field.add_named(b"WIDE", 0x100);
+        let bytes = field.to_bytes();
+        verify_expected_bytes(
+            &bytes,
+            &[
+                0x5b, 0x81, 0x0c, b'P', b'R', b'S', b'T', 0x43, b'W', b'I', b'D', b'E', 0x40, 0x10,
+            ],
+        );
+    }
+
+    #[test]
+    fn verify_cpu_hotplug() {
+        let mut dsdt = Dsdt::new();
+        dsdt.add_cpu_hotplug(
+            0xcd8,
+            2,
+            &[
+                HotplugProcessor {
+                    index: 0,
+                    uid: 1,
+                    apic_id: 0,
+                    hotpluggable: false,
+                },
+                HotplugProcessor {
+                    index: 1,
+                    uid: 2,
+                    apic_id: 0x100,
+                    hotpluggable: true,
+                },
+            ],
+        );
+        let bytes = dsdt.to_bytes();
+        verify_header(&bytes);
+
+        let find = |pattern: &[u8]| {
+            bytes
+                .windows(pattern.len())
+                .filter(|w| *w == pattern)
+                .count()
+        };
+
+        // Both processors report their status, but only the second can be
+        // ejected or generate events.
+        assert_eq!(find(b"C000"), 1);
+        assert_eq!(find(b"C001"), 2);
+        assert_eq!(find(&[0xa4, b'C', b'P', b'S', b'T', 0x00]), 1);
+        assert_eq!(find(&[0xa4, b'C', b'P', b'S', b'T', 0x01]), 1);
+        assert_eq!(find(b"_EJ0"), 1);
+        assert_eq!(find(&[b'C', b'P', b'E', b'J', 0x01]), 1);
+        assert_eq!(find(&[0x70, b'C', b'P', b'E', b'V', 0x01, 0x60]), 1);
+        assert_eq!(find(&[0x86, b'C', b'0', b'0', b'1', 0x60]), 1);
+
+        // The second processor needs an x2apic entry.
+        assert_eq!(
+            find(&[0x11, 0x13, 0x0a, 0x10, 0x09, 0x10, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00]),
+            1
+        );
+
+        // The GPE handler calls the scan method.
+        assert_eq!(
+            find(&[
+                0x10, 0x1c, b'\\', b'_', b'G', b'P', b'E', 0x14, 0x15, b'_', b'E', b'0', b'2',
+                0x00, b'\\', 0x2f, 0x03, b'_', b'S', b'B', b'_', b'C', b'P', b'U', b'S', b'C',
+                b'S', b'C', b'N',
+            ]),
+            1
+        );
+    }
+
+    #[test]
+    fn verify_memory_hotplug() {
+        let mut dsdt = Dsdt::new();
+        dsdt.add_memory_hotplug(
+            0xce0,
+            3,
+            &[
+                MemoryRange::new(0x1_0000_0000..0x1_0800_0000),
+                MemoryRange::new(0x1_0800_0000..0x1_1000_0000),
+            ],
+        );
+        let bytes = dsdt.to_bytes();
+        verify_header(&bytes);
+
+        let find = |pattern: &[u8]| {
+            bytes
+                .windows(pattern.len())
+                .filter(|w| *w == pattern)
+                .count()
+        };
+
+        // Each slot is a memory device that reports its status and generates
+        // events.
+        assert_eq!(find(&[0x41, 0xd0, 0x0c, 0x80]), 2);
+        assert_eq!(find(&[0xa4, b'M', b'E', b'S', b'T', 0x00]), 1);
+        assert_eq!(find(&[0xa4, b'M', b'E', b'S', b'T', 0x01]), 1);
+        assert_eq!(find(&[0x70, b'M', b'E', b'E', b'V', 0x01, 0x60]), 1);
+        assert_eq!(find(&[0x86, b'M', b'0', b'0', b'1', 0x60]), 1);
+
+        // The second slot's resources.
+        assert_eq!(find(&0x1_0800_0000u64.to_le_bytes()), 1);
+        assert_eq!(find(&0x1_0fff_ffffu64.to_le_bytes()), 1);
+
+        // The GPE handler calls the scan method.
+        assert_eq!(
+            find(&[
+                0x10, 0x1c, b'\\', b'_', b'G', b'P', b'E', 0x14, 0x15, b'_', b'E', b'0', b'3',
+                0x00, b'\\', 0x2f, 0x03, b'_', b'S', b'B', b'_', b'M', b'H', b'P', b'C', b'M',
+                b'S', b'C', b'N',
+            ]),
+            1
+        );
+    }
+
+    #[test]
+    fn verify_ssdt_fw_cfg_mmio() {
+        let mut ssdt = Dsdt::new_ssdt();
+        ssdt.add_fw_cfg_mmio(0xeffe9000, 0x18);
+        let bytes = ssdt.to_bytes();
+        assert_eq!(&bytes[0..4], b"SSDT");
+        assert_eq!(&bytes[16..24], b"SSDT01\0\0");
+        assert_eq!(bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)), 0);
+
+        // The register block is described with a Memory32Fixed descriptor.
+        assert!(bytes
+            .windows(12)
+            .any(|w| w == [0x86, 9, 0, 1, 0x00, 0x90, 0xfe, 0xef, 0x18, 0, 0, 0]));
+    }
 }
@@ -1,6 +1,8 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT License.
 
+use super::helpers::encode_package_len;
+
 pub trait OperationObject {
     fn append_to_vec(&self, byte_stream: &mut Vec<u8>);
 
@@ -52,6 +54,79 @@ fn append_to_vec(&self, byte_stream: &mut Vec<u8>) {
     }
 }
 
+pub struct StoreOp {
+    pub operand: Vec<u8>,
+    pub target_name: Vec<u8>,
+}
+
+impl OperationObject for StoreOp {
+    fn append_to_vec(&self, byte_stream: &mut Vec<u8>) {
+        byte_stream.push(0x70);
+        byte_stream.extend_from_slice(&self.operand);
+        byte_stream.extend_from_slice(&self.target_name);
+    }
+}
+
+pub struct NotifyOp {
+    pub object: Vec<u8>,
+    pub value: Vec<u8>,
+}
+
+impl OperationObject for NotifyOp {
+    fn append_to_vec(&self, byte_stream: &mut Vec<u8>) {
+        byte_stream.push(0x86);
+        byte_stream.extend_from_slice(&self.object);
+        byte_stream.extend_from_slice(&self.value);
+    }
+}
+
+pub struct IfOp {
+    pub predicate: Vec<u8>,
+    operations: Vec<u8>,
+}
+
+impl IfOp {
+    pub fn new(predicate: Vec<u8>) -> Self {
+        Self {
+            predicate,
+            operations: vec![],
+        }
+    }
+
+    pub fn add_operation(&mut self, op: &impl OperationObject) {
+        op.append_to_vec(&mut self.operations);
+    }
+}
+
+impl OperationObject for IfOp {
+    // An if operation consists of the identifier (0xa0) followed by the length, the predicate and then the
+    // operations to execute if the predicate is non-zero.
+    fn append_to_vec(&self, byte_stream: &mut Vec<u8>) {
+        byte_stream.push(0xa0);
+        byte_stream.extend_from_slice(&encode_package_len(
+            self.predicate.len() + self.operations.len(),
+        ));
+        byte_stream.extend_from_slice(&self.predicate);
+        byte_stream.extend_from_slice(&self.operations);
+    }
+}
+
+/// An invocation of a method, which can be used both as an operation and as
+/// an operand to another operation (via [`OperationObject::to_bytes`]).
+pub struct MethodCallOp {
+    pub name: Vec<u8>,
+    pub args: Vec<Vec<u8>>,
+}
+
+impl OperationObject for MethodCallOp {
+    fn append_to_vec(&self, byte_stream: &mut Vec<u8>) {
+        byte_stream.extend_from_slice(&self.name);
+        for arg in &self.args {
+            byte_stream.extend_from_slice(arg);
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -98,4 +173,48 @@ fn verify_return_operation() {
         let bytes = op.to_bytes();
         verify_expected_bytes(&bytes, &[0xa4, b'S', b'T', b'A', b'_']);
     }
+
+    #[test]
+    fn verify_store_operation() {
+        let op = StoreOp {
+            operand: encode_integer(2),
+            target_name: vec![b'C', b'S', b'E', b'L'],
+        };
+        let bytes = op.to_bytes();
+        verify_expected_bytes(&bytes, &[0x70, 0x0a, 0x02, b'C', b'S', b'E', b'L']);
+    }
+
+    #[test]
+    fn verify_notify_operation() {
+        let op = NotifyOp {
+            object: vec![b'C', b'0', b'0', b'1'],
+            value: vec![0x60],
+        };
+        let bytes = op.to_bytes();
+        verify_expected_bytes(&bytes, &[0x86, b'C', b'0', b'0', b'1', 0x60]);
+    }
+
+    #[test]
+    fn verify_if_operation() {
+        let mut op = IfOp::new(vec![0x60]);
+        op.add_operation(&ReturnOp { result: vec![0x60] });
+        let bytes = op.to_bytes();
+        verify_expected_bytes(&bytes, &[0xa0, 0x04, 0x60, 0xa4, 0x60]);
+    }
+
+    #[test]
+    fn verify_method_call_operation() {
+        let op = MethodCallOp {
+            name: vec![b'C', b'P', b'S', b'T'],
+            args: vec![encode_integer(1)],
+        };
+        let bytes = op.to_bytes();
+        verify_expected_bytes(&bytes, &[b'C', b'P', b'S', b'T', 0x01]);
+
+        let op = ReturnOp {
+            result: op.to_bytes(),
+        };
+        let bytes = op.to_bytes();
+        verify_expected_bytes(&bytes, &[0xa4, b'C', b'P', b'S', b'T', 0x01]);
+    }
 }
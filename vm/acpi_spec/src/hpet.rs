@@ -0,0 +1,41 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+// ACPI definitions for the IA-PC High Precision Event Timer description table
+// (HPET).
+
+use super::Table;
+use crate::fadt::GenericAddress;
+use core::mem::size_of;
+use static_assertions::const_assert_eq;
+use zerocopy::AsBytes;
+use zerocopy::Unaligned;
+
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, Default, AsBytes, Unaligned)]
+pub struct Hpet {
+    /// The low 32 bits of the event timer block's general capabilities and
+    /// ID register.
+    pub event_timer_block_id: u32,
+    pub base_address: GenericAddress,
+    pub hpet_number: u8,
+    /// The minimum main counter tick count that can be programmed as a
+    /// periodic interrupt period without losing interrupts.
+    pub min_clock_tick: u16,
+    pub page_protection: u8,
+}
+
+const_assert_eq!(size_of::<Hpet>(), 20);
+
+impl Table for Hpet {
+    const SIGNATURE: [u8; 4] = *b"HPET";
+}
+
+pub const HPET_REVISION: u8 = 1;
+
+/// No guarantee for page protection.
+pub const HPET_PAGE_PROTECTION_NONE: u8 = 0;
+/// The event timer block has a 4KB page of its own.
+pub const HPET_PAGE_PROTECTION_4K: u8 = 1;
+/// The event timer block has a 64KB page of its own.
+pub const HPET_PAGE_PROTECTION_64K: u8 = 2;
@@ -10,8 +10,11 @@
 
 pub mod aspt;
 pub mod fadt;
+pub mod hpet;
 pub mod madt;
+pub mod mcfg;
 pub mod pptt;
+pub mod slit;
 pub mod srat;
 
 #[allow(non_camel_case_types)]
@@ -0,0 +1,40 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+// ACPI definitions for the system locality information table (SLIT).
+
+use super::Table;
+use crate::packed_nums::*;
+use zerocopy::AsBytes;
+use zerocopy::FromBytes;
+use zerocopy::FromZeroes;
+use zerocopy::Unaligned;
+
+/// The SLIT header. It is followed by a matrix of `locality_count` by
+/// `locality_count` one-byte distances, where the entry at row `i` and column
+/// `j` is the relative distance from locality (proximity domain) `i` to
+/// locality `j`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, AsBytes, FromBytes, FromZeroes, Unaligned)]
+pub struct SlitHeader {
+    pub locality_count: u64_ne,
+}
+
+impl SlitHeader {
+    pub fn new(locality_count: u64) -> SlitHeader {
+        SlitHeader {
+            locality_count: locality_count.into(),
+        }
+    }
+}
+
+impl Table for SlitHeader {
+    const SIGNATURE: [u8; 4] = *b"SLIT";
+}
+
+pub const SLIT_REVISION: u8 = 1;
+
+/// The distance from a locality to itself.
+pub const SLIT_LOCAL_DISTANCE: u8 = 10;
+/// The distance to an unreachable locality.
+pub const SLIT_UNREACHABLE_DISTANCE: u8 = 0xff;
@@ -71,6 +71,9 @@ pub struct ResolveChipsetDeviceHandleParams<'a> {
     pub register_mmio: &'a mut (dyn chipset_device::mmio::RegisterMmioIntercept + Send),
     /// Object to register for PIO intercepts.
     pub register_pio: &'a mut (dyn chipset_device::pio::RegisterPortIoIntercept + Send),
+    /// Object to register message-signaled interrupts, if the platform
+    /// supports MSIs from chipset devices.
+    pub register_msi: Option<&'a mut dyn pci_core::msi::RegisterMsi>,
 }
 
 /// A trait for configuring a chipset device's connection to the platform.
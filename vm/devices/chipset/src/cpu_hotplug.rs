@@ -0,0 +1,505 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! ACPI processor hotplug controller.
+//!
+//! This device tracks which of the VM's processors are present, and notifies
+//! the guest of processor hot-add and hot-remove requests via a GPE0 line. It
+//! is a paravirtual design driven by AML generated by
+//! `acpi::dsdt::Dsdt::add_cpu_hotplug`, which must be kept in sync with the
+//! register layout below.
+//!
+//! The guest selects a processor by writing its VP index to the selector
+//! register, and then:
+//!
+//! - reads the status register to get the processor's `_STA` value,
+//! - reads the event register to get the pending ACPI notification value for
+//!   the processor (0 if none), and writes it back to acknowledge it,
+//! - writes 1 to the eject register once it has offlined the processor in
+//!   response to an eject request.
+//!
+//! The processors themselves are not created or destroyed by this device; all
+//! possible processors exist for the lifetime of the VM. Instead, the device
+//! reports each change in presence through a callback, so that the VMM can
+//! keep processors that are not present from running, and stop processors
+//! that the guest ejects.
+
+use chipset_device::io::IoError;
+use chipset_device::io::IoResult;
+use chipset_device::pio::PortIoIntercept;
+use chipset_device::ChipsetDevice;
+use inspect::InspectMut;
+use open_enum::open_enum;
+use std::ops::RangeInclusive;
+use thiserror::Error;
+use vmcore::device_state::ChangeDeviceState;
+use vmcore::line_interrupt::LineInterrupt;
+
+/// The I/O port of the register block.
+pub const CPU_HOTPLUG_IO_PORT: u16 = 0xcd8;
+/// The length of the register block.
+pub const CPU_HOTPLUG_IO_LEN: u16 = 8;
+/// The GPE0 line used to notify the guest of pending events.
+pub const CPU_HOTPLUG_GPE0_LINE: u32 = 2;
+
+open_enum! {
+    enum RegisterOffset: u16 {
+        SELECTOR = 0,
+        STATUS = 4,
+        EVENT = 5,
+        EJECT = 6,
+    }
+}
+
+/// The `_STA` value of a present processor: present, enabled, shown in the
+/// UI, and functioning.
+const STATUS_PRESENT: u8 = 0xf;
+
+/// ACPI notification values reported through the event register.
+mod event {
+    pub const NONE: u8 = 0;
+    pub const DEVICE_CHECK: u8 = 1;
+    pub const EJECT_REQUEST: u8 = 3;
+}
+
+/// Callback invoked with a VP index whenever that processor is added or
+/// removed.
+pub type SetPresentFn = Box<dyn Fn(u32, bool) + Send + Sync>;
+
+/// An error returned when a hot-add or hot-remove request is invalid.
+#[derive(Debug, Error)]
+pub enum CpuHotplugError {
+    /// The VP index is out of range.
+    #[error("processor {0} does not exist")]
+    InvalidProcessor(u32),
+    /// The processor is already present.
+    #[error("processor {0} is already present")]
+    AlreadyPresent(u32),
+    /// The processor is not present.
+    #[error("processor {0} is not present")]
+    NotPresent(u32),
+    /// The boot processor cannot be removed.
+    #[error("the boot processor cannot be removed")]
+    BootProcessor,
+}
+
+/// ACPI processor hotplug controller.
+#[derive(InspectMut)]
+pub struct CpuHotplugDevice {
+    // Static configuration
+    #[inspect(hex)]
+    io_port: u16,
+    #[inspect(skip)]
+    io_region: (&'static str, RangeInclusive<u16>),
+
+    // Runtime glue
+    #[inspect(skip)]
+    notify_interrupt: LineInterrupt,
+    #[inspect(skip)]
+    set_present: SetPresentFn,
+
+    // Volatile state
+    selector: u32,
+    #[inspect(iter_by_index)]
+    present: Vec<bool>,
+    #[inspect(iter_by_index)]
+    events: Vec<u8>,
+}
+
+impl CpuHotplugDevice {
+    /// Returns a new device for `vp_count` processors, of which the first
+    /// `present_count` are initially present.
+    ///
+    /// `set_present` is called whenever the presence of a processor changes
+    /// after this, including on restore. It is not called for the initial
+    /// state.
+    pub fn new(
+        io_port: u16,
+        vp_count: u32,
+        present_count: u32,
+        notify_interrupt: LineInterrupt,
+        set_present: SetPresentFn,
+    ) -> Self {
+        assert!(present_count >= 1 && present_count <= vp_count);
+        Self {
+            io_port,
+            io_region: ("cpu_hotplug", io_port..=io_port + (CPU_HOTPLUG_IO_LEN - 1)),
+            notify_interrupt,
+            set_present,
+            selector: 0,
+            present: (0..vp_count).map(|vp| vp < present_count).collect(),
+            events: vec![event::NONE; vp_count as usize],
+        }
+    }
+
+    /// Returns whether each processor is present, indexed by VP index.
+    pub fn present(&self) -> &[bool] {
+        &self.present
+    }
+
+    /// Makes processor `vp` present and notifies the guest.
+    pub fn add(&mut self, vp: u32) -> Result<(), CpuHotplugError> {
+        let present = self
+            .present
+            .get_mut(vp as usize)
+            .ok_or(CpuHotplugError::InvalidProcessor(vp))?;
+        if *present {
+            return Err(CpuHotplugError::AlreadyPresent(vp));
+        }
+        *present = true;
+        (self.set_present)(vp, true);
+        self.events[vp as usize] = event::DEVICE_CHECK;
+        self.update_interrupt();
+        Ok(())
+    }
+
+    /// Asks the guest to eject processor `vp`.
+    ///
+    /// The processor remains present until the guest ejects it, which it may
+    /// never do.
+    pub fn request_remove(&mut self, vp: u32) -> Result<(), CpuHotplugError> {
+        let present = *self
+            .present
+            .get(vp as usize)
+            .ok_or(CpuHotplugError::InvalidProcessor(vp))?;
+        if vp == 0 {
+            return Err(CpuHotplugError::BootProcessor);
+        }
+        if !present {
+            return Err(CpuHotplugError::NotPresent(vp));
+        }
+        self.events[vp as usize] = event::EJECT_REQUEST;
+        self.update_interrupt();
+        Ok(())
+    }
+
+    fn update_interrupt(&self) {
+        self.notify_interrupt
+            .set_level(self.events.iter().any(|&event| event != event::NONE));
+    }
+
+    fn read_register(&self, offset: RegisterOffset) -> u8 {
+        let vp = self.selector as usize;
+        match offset {
+            RegisterOffset::STATUS => {
+                if self.present.get(vp).copied().unwrap_or(false) {
+                    STATUS_PRESENT
+                } else {
+                    0
+                }
+            }
+            RegisterOffset::EVENT => self.events.get(vp).copied().unwrap_or(event::NONE),
+            _ => 0,
+        }
+    }
+
+    fn write_register(&mut self, offset: RegisterOffset, value: u8) {
+        let vp = self.selector as usize;
+        if vp >= self.present.len() {
+            tracelimit::warn_ratelimited!(vp, "invalid cpu hotplug processor selected");
+            return;
+        }
+        match offset {
+            RegisterOffset::EVENT => {
+                self.events[vp] = event::NONE;
+                self.update_interrupt();
+            }
+            RegisterOffset::EJECT => {
+                if value & 1 != 0 {
+                    if vp == 0 || !self.present[vp] {
+                        tracelimit::warn_ratelimited!(vp, "ignoring invalid processor eject");
+                        return;
+                    }
+                    tracing::info!(vp, "guest ejected processor");
+                    self.present[vp] = false;
+                    (self.set_present)(vp as u32, false);
+                    self.events[vp] = event::NONE;
+                    self.update_interrupt();
+                }
+            }
+            _ => {
+                tracelimit::warn_ratelimited!(?offset, "invalid cpu hotplug register write");
+            }
+        }
+    }
+}
+
+impl ChangeDeviceState for CpuHotplugDevice {
+    fn start(&mut self) {}
+
+    async fn stop(&mut self) {}
+
+    async fn reset(&mut self) {
+        // Presence is preserved across reset, like on a physical machine.
+        self.selector = 0;
+        self.events.fill(event::NONE);
+        self.update_interrupt();
+    }
+}
+
+impl ChipsetDevice for CpuHotplugDevice {
+    fn supports_pio(&mut self) -> Option<&mut dyn PortIoIntercept> {
+        Some(self)
+    }
+}
+
+impl PortIoIntercept for CpuHotplugDevice {
+    fn io_read(&mut self, io_port: u16, data: &mut [u8]) -> IoResult {
+        let offset = RegisterOffset(io_port.wrapping_sub(self.io_port));
+        match (offset, data.len()) {
+            (RegisterOffset::SELECTOR, 4) => data.copy_from_slice(&self.selector.to_le_bytes()),
+            (RegisterOffset::SELECTOR, _) => return IoResult::Err(IoError::InvalidAccessSize),
+            (_, 1) => data[0] = self.read_register(offset),
+            _ => return IoResult::Err(IoError::InvalidAccessSize),
+        }
+        IoResult::Ok
+    }
+
+    fn io_write(&mut self, io_port: u16, data: &[u8]) -> IoResult {
+        let offset = RegisterOffset(io_port.wrapping_sub(self.io_port));
+        match (offset, data.len()) {
+            (RegisterOffset::SELECTOR, 4) => {
+                self.selector = u32::from_le_bytes(data.try_into().unwrap())
+            }
+            (RegisterOffset::SELECTOR, _) => return IoResult::Err(IoError::InvalidAccessSize),
+            (_, 1) => self.write_register(offset, data[0]),
+            _ => return IoResult::Err(IoError::InvalidAccessSize),
+        }
+        IoResult::Ok
+    }
+
+    fn get_static_regions(&mut self) -> &[(&str, RangeInclusive<u16>)] {
+        std::slice::from_ref(&self.io_region)
+    }
+}
+
+mod save_restore {
+    use super::*;
+    use vmcore::save_restore::RestoreError;
+    use vmcore::save_restore::SaveError;
+    use vmcore::save_restore::SaveRestore;
+
+    mod state {
+        use mesh::payload::Protobuf;
+        use vmcore::save_restore::SavedStateRoot;
+
+        #[derive(Protobuf, SavedStateRoot)]
+        #[mesh(package = "chipset.cpu_hotplug")]
+        pub struct SavedState {
+            #[mesh(1)]
+            pub selector: u32,
+            #[mesh(2)]
+            pub present: Vec<bool>,
+            #[mesh(3)]
+            pub events: Vec<u8>,
+        }
+    }
+
+    #[derive(Debug, Error)]
+    enum CpuHotplugRestoreError {
+        #[error("saved state has {0} processors, expected {1}")]
+        ProcessorCount(usize, usize),
+        #[error("the boot processor is not present")]
+        BootProcessorNotPresent,
+    }
+
+    impl SaveRestore for CpuHotplugDevice {
+        type SavedState = state::SavedState;
+
+        fn save(&mut self) -> Result<Self::SavedState, SaveError> {
+            let Self {
+                io_port: _,
+                io_region: _,
+                notify_interrupt: _,
+                set_present: _,
+                selector,
+                present,
+                events,
+            } = self;
+
+            Ok(state::SavedState {
+                selector: *selector,
+                present: present.clone(),
+                events: events.clone(),
+            })
+        }
+
+        fn restore(&mut self, state: Self::SavedState) -> Result<(), RestoreError> {
+            let state::SavedState {
+                selector,
+                present,
+                events,
+            } = state;
+
+            if present.len() != self.present.len() || events.len() != self.events.len() {
+                return Err(RestoreError::InvalidSavedState(
+                    CpuHotplugRestoreError::ProcessorCount(present.len(), self.present.len())
+                        .into(),
+                ));
+            }
+            if !present[0] {
+                return Err(RestoreError::InvalidSavedState(
+                    CpuHotplugRestoreError::BootProcessorNotPresent.into(),
+                ));
+            }
+
+            for (vp, &present) in present.iter().enumerate() {
+                (self.set_present)(vp as u32, present);
+            }
+            self.selector = selector;
+            self.present = present;
+            self.events = events;
+            self.update_interrupt();
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parking_lot::Mutex;
+    use std::sync::Arc;
+    use vmcore::line_interrupt::test_helpers::TestLineInterruptTarget;
+    use vmcore::save_restore::SaveRestore;
+
+    const PORT: u16 = CPU_HOTPLUG_IO_PORT;
+
+    /// The presence changes reported by the device.
+    type Changes = Arc<Mutex<Vec<(u32, bool)>>>;
+
+    fn new_device() -> (CpuHotplugDevice, Arc<TestLineInterruptTarget>, Changes) {
+        let target = TestLineInterruptTarget::new_arc();
+        let interrupt = LineInterrupt::new_with_target("cpu_hotplug", target.clone(), 2);
+        let changes = Changes::default();
+        let dev = CpuHotplugDevice::new(
+            PORT,
+            4,
+            2,
+            interrupt,
+            Box::new({
+                let changes = changes.clone();
+                move |vp, present| changes.lock().push((vp, present))
+            }),
+        );
+        (dev, target, changes)
+    }
+
+    fn select(dev: &mut CpuHotplugDevice, vp: u32) {
+        dev.io_write(PORT, &vp.to_le_bytes()).unwrap();
+    }
+
+    fn read(dev: &mut CpuHotplugDevice, offset: RegisterOffset) -> u8 {
+        let mut data = [0];
+        dev.io_read(PORT + offset.0, &mut data).unwrap();
+        data[0]
+    }
+
+    fn write(dev: &mut CpuHotplugDevice, offset: RegisterOffset, value: u8) {
+        dev.io_write(PORT + offset.0, &[value]).unwrap();
+    }
+
+    #[test]
+    fn status() {
+        let (mut dev, _, _) = new_device();
+        for (vp, status) in [
+            (0, STATUS_PRESENT),
+            (1, STATUS_PRESENT),
+            (2, 0),
+            (3, 0),
+            (4, 0),
+        ] {
+            select(&mut dev, vp);
+            assert_eq!(read(&mut dev, RegisterOffset::STATUS), status);
+        }
+
+        assert!(matches!(
+            dev.io_write(PORT, &[0]),
+            IoResult::Err(IoError::InvalidAccessSize)
+        ));
+    }
+
+    #[test]
+    fn hot_add() {
+        let (mut dev, target, changes) = new_device();
+        assert!(matches!(
+            dev.add(1),
+            Err(CpuHotplugError::AlreadyPresent(1))
+        ));
+        assert!(matches!(
+            dev.add(4),
+            Err(CpuHotplugError::InvalidProcessor(4))
+        ));
+        assert!(!target.is_high(2));
+
+        assert!(changes.lock().is_empty());
+
+        dev.add(3).unwrap();
+        assert!(target.is_high(2));
+        assert_eq!(dev.present(), &[true, true, false, true]);
+        assert_eq!(*changes.lock(), [(3, true)]);
+
+        select(&mut dev, 2);
+        assert_eq!(read(&mut dev, RegisterOffset::EVENT), event::NONE);
+        select(&mut dev, 3);
+        assert_eq!(read(&mut dev, RegisterOffset::STATUS), STATUS_PRESENT);
+        assert_eq!(read(&mut dev, RegisterOffset::EVENT), event::DEVICE_CHECK);
+        write(&mut dev, RegisterOffset::EVENT, event::DEVICE_CHECK);
+        assert_eq!(read(&mut dev, RegisterOffset::EVENT), event::NONE);
+        assert!(!target.is_high(2));
+    }
+
+    #[test]
+    fn hot_remove() {
+        let (mut dev, target, changes) = new_device();
+        assert!(matches!(
+            dev.request_remove(0),
+            Err(CpuHotplugError::BootProcessor)
+        ));
+        assert!(matches!(
+            dev.request_remove(2),
+            Err(CpuHotplugError::NotPresent(2))
+        ));
+
+        dev.request_remove(1).unwrap();
+        assert!(target.is_high(2));
+        select(&mut dev, 1);
+        assert_eq!(read(&mut dev, RegisterOffset::EVENT), event::EJECT_REQUEST);
+        write(&mut dev, RegisterOffset::EVENT, event::EJECT_REQUEST);
+        assert!(!target.is_high(2));
+
+        // The processor stays present until the guest ejects it.
+        assert_eq!(read(&mut dev, RegisterOffset::STATUS), STATUS_PRESENT);
+        assert!(changes.lock().is_empty());
+        write(&mut dev, RegisterOffset::EJECT, 1);
+        assert_eq!(read(&mut dev, RegisterOffset::STATUS), 0);
+        assert_eq!(dev.present(), &[true, false, false, false]);
+        assert_eq!(*changes.lock(), [(1, false)]);
+
+        // The boot processor cannot be ejected, and ejecting a processor that
+        // is not present does nothing.
+        select(&mut dev, 0);
+        write(&mut dev, RegisterOffset::EJECT, 1);
+        assert_eq!(read(&mut dev, RegisterOffset::STATUS), STATUS_PRESENT);
+        select(&mut dev, 1);
+        write(&mut dev, RegisterOffset::EJECT, 1);
+        assert_eq!(*changes.lock(), [(1, false)]);
+    }
+
+    #[test]
+    fn restore() {
+        let (mut dev, _, changes) = new_device();
+        dev.add(2).unwrap();
+        let state = dev.save().unwrap();
+
+        let (mut dev, _, changes2) = new_device();
+        dev.restore(state).unwrap();
+        assert_eq!(dev.present(), &[true, true, true, false]);
+        // The restored presence of every processor is reported.
+        assert_eq!(
+            *changes2.lock(),
+            [(0, true), (1, true), (2, true), (3, false)]
+        );
+        assert_eq!(*changes.lock(), [(2, true)]);
+    }
+}
@@ -0,0 +1,954 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! High Precision Event Timer (HPET).
+//!
+//! Emulates a single HPET event timer block, as described by the IA-PC HPET
+//! Specification (rev 1.0a), with a 64-bit main counter and [`NUM_TIMERS`]
+//! comparators.
+//!
+//! Each comparator supports one-shot and periodic modes, 32-bit mode, edge and
+//! level triggered interrupts, and can deliver its interrupt via:
+//!
+//! - legacy replacement routing (timer 0 to IRQ0, timer 1 to IRQ8),
+//! - one of the I/O APIC inputs in [`HPET_ROUTABLE_IRQS`], or
+//! - an FSB (MSI) message, if the platform supports MSIs from chipset devices.
+//!
+//! The main counter runs at 10MHz, matching the resolution of [`VmTime`].
+//!
+//! Note that enabling legacy replacement routing does not disconnect the PIT
+//! or RTC from IRQ0/IRQ8; guests are expected to quiesce those devices before
+//! switching over to the HPET.
+
+pub mod resolver;
+mod spec;
+
+use self::spec::Capabilities;
+use self::spec::GeneralConfig;
+use self::spec::Register;
+use self::spec::TimerConfig;
+use self::spec::TimerRegister;
+use chipset_device::io::IoError;
+use chipset_device::io::IoResult;
+use chipset_device::mmio::MmioIntercept;
+use chipset_device::poll_device::PollDevice;
+use chipset_device::ChipsetDevice;
+use inspect::Inspect;
+use inspect::InspectMut;
+use pci_core::msi::MsiInterrupt;
+use pci_core::msi::RegisterMsi;
+use std::ops::RangeInclusive;
+use std::task::Context;
+use std::task::Poll;
+use std::time::Duration;
+use thiserror::Error;
+use vmcore::device_state::ChangeDeviceState;
+use vmcore::interrupt::Interrupt;
+use vmcore::line_interrupt::LineInterrupt;
+use vmcore::vmtime::VmTime;
+use vmcore::vmtime::VmTimeAccess;
+
+/// The guest physical address of the HPET's MMIO region.
+pub const HPET_MMIO_BASE: u64 = 0xfed0_0000;
+const HPET_MMIO_LEN: u64 = 0x400;
+
+/// The number of comparators (timers) in the event timer block.
+pub const NUM_TIMERS: usize = 3;
+
+/// The I/O APIC inputs that timers can be routed to when not using legacy
+/// replacement routing or FSB delivery.
+pub const HPET_ROUTABLE_IRQS: RangeInclusive<u32> = 20..=23;
+
+/// The IRQ used by timer 0 in legacy replacement mode (the I/O APIC input that
+/// the PIT's IRQ0 is wired to).
+const LEGACY_TIMER0_IRQ: u32 = 2;
+/// The IRQ used by timer 1 in legacy replacement mode.
+const LEGACY_TIMER1_IRQ: u32 = 8;
+
+const VENDOR_ID: u16 = 0x8086;
+/// The main counter period, in femtoseconds (100ns, i.e. 10MHz).
+const COUNTER_PERIOD_FS: u32 = 100_000_000;
+const NANOS_PER_TICK: u64 = 100;
+
+/// The smallest periodic timer period that the HPET promises to deliver
+/// without losing interrupts, in main counter ticks.
+pub const MIN_PERIODIC_TICKS: u16 = 100;
+
+/// Returns the capabilities register of the event timer block.
+fn capabilities() -> Capabilities {
+    Capabilities::new()
+        .with_rev_id(1)
+        .with_num_tim_cap(NUM_TIMERS as u8 - 1)
+        .with_count_size_cap(true)
+        .with_leg_rt_cap(true)
+        .with_vendor_id(VENDOR_ID)
+        .with_counter_clk_period(COUNTER_PERIOD_FS)
+}
+
+/// Returns the ID reported in the ACPI HPET table's event timer block ID
+/// field, which mirrors the low 32 bits of the capabilities register.
+pub fn event_timer_block_id() -> u32 {
+    u64::from(capabilities()) as u32
+}
+
+/// The destination of a timer's interrupt.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Route {
+    Legacy,
+    Fsb,
+    Irq(usize),
+    None,
+}
+
+#[derive(Inspect)]
+struct Timer {
+    // Static configuration
+    index: usize,
+
+    // Runtime glue
+    #[inspect(skip)]
+    legacy_line: Option<LineInterrupt>,
+    #[inspect(skip)]
+    irq_lines: Vec<LineInterrupt>,
+    #[inspect(skip)]
+    msi: Option<(MsiInterrupt, Interrupt)>,
+
+    // Volatile state
+    #[inspect(flatten)]
+    state: TimerState,
+}
+
+#[derive(Copy, Clone, Debug, Inspect)]
+struct TimerState {
+    #[inspect(with = "|x| inspect::AsHex(u64::from(*x))")]
+    config: TimerConfig,
+    #[inspect(hex)]
+    comparator: u64,
+    #[inspect(hex)]
+    period: u64,
+    #[inspect(hex)]
+    fsb_route: u64,
+}
+
+impl TimerState {
+    fn new() -> Self {
+        Self {
+            config: TimerConfig::new(),
+            comparator: !0,
+            period: 0,
+            fsb_route: 0,
+        }
+    }
+
+    /// The mask of valid comparator bits.
+    fn mask(&self) -> u64 {
+        if self.config.mode_32bit() {
+            u32::MAX.into()
+        } else {
+            u64::MAX
+        }
+    }
+
+    /// Returns whether the main counter passed the comparator when moving
+    /// from `prev` (exclusive) to `counter` (inclusive), updating the
+    /// comparator of a periodic timer.
+    fn check_fired(&mut self, prev: u64, counter: u64) -> bool {
+        let mask = self.mask();
+        let elapsed = counter.wrapping_sub(prev);
+        let until = self.comparator.wrapping_sub(prev) & mask;
+        let fired = (mask != u64::MAX && elapsed > mask) || (until != 0 && until <= elapsed);
+        if fired && self.config.periodic() && self.period != 0 {
+            // Move the comparator past the counter, skipping any periods that
+            // were missed entirely.
+            let behind = counter.wrapping_sub(self.comparator) & mask;
+            let periods = behind / self.period + 1;
+            self.comparator = self
+                .comparator
+                .wrapping_add(periods.wrapping_mul(self.period))
+                & mask;
+        }
+        fired
+    }
+
+    /// Returns the number of ticks from `counter` until the timer next fires.
+    fn ticks_until_fire(&self, counter: u64) -> u64 {
+        let mask = self.mask();
+        match self.comparator.wrapping_sub(counter) & mask {
+            // The comparator just matched, so the next match is after the
+            // counter wraps.
+            0 => mask,
+            n => n,
+        }
+    }
+}
+
+impl Timer {
+    fn new(
+        index: usize,
+        legacy_line: Option<LineInterrupt>,
+        irq_lines: Vec<LineInterrupt>,
+        msi: Option<MsiInterrupt>,
+    ) -> Self {
+        Self {
+            index,
+            legacy_line,
+            irq_lines,
+            msi: msi.map(|mut msi| {
+                let interrupt = msi.interrupt();
+                (msi, interrupt)
+            }),
+            state: TimerState::new(),
+        }
+    }
+
+    fn config(&self) -> TimerConfig {
+        let route_capability = HPET_ROUTABLE_IRQS.fold(0, |mask, irq| mask | 1 << irq);
+        self.state
+            .config
+            .with_periodic_capable(true)
+            .with_size_64bit(true)
+            .with_fsb_capable(self.msi.is_some())
+            .with_interrupt_route_capability(route_capability)
+    }
+
+    fn route(&self, legacy_replacement: bool) -> Route {
+        if legacy_replacement && self.legacy_line.is_some() {
+            Route::Legacy
+        } else if self.state.config.fsb_enable() && self.msi.is_some() {
+            Route::Fsb
+        } else if let Some(index) = (self.state.config.interrupt_route() as u32)
+            .checked_sub(*HPET_ROUTABLE_IRQS.start())
+            .filter(|&i| (i as usize) < self.irq_lines.len())
+        {
+            Route::Irq(index as usize)
+        } else {
+            Route::None
+        }
+    }
+
+    /// Updates the interrupt lines and MSI to match the current state.
+    fn sync_interrupts(&mut self, legacy_replacement: bool, status: u64) {
+        let route = self.route(legacy_replacement);
+        let config = self.state.config;
+        let asserted =
+            config.level_triggered() && config.interrupt_enable() && status & self.bit() != 0;
+
+        if let Some(line) = &self.legacy_line {
+            line.set_level(asserted && route == Route::Legacy);
+        }
+        for (i, line) in self.irq_lines.iter().enumerate() {
+            line.set_level(asserted && route == Route::Irq(i));
+        }
+        if let Some((msi, _)) = &mut self.msi {
+            if route == Route::Fsb && config.interrupt_enable() {
+                msi.enable(
+                    self.state.fsb_route >> 32,
+                    self.state.fsb_route as u32,
+                    false,
+                );
+            } else {
+                msi.disable();
+            }
+        }
+    }
+
+    /// Signals an edge-triggered interrupt (or an FSB message) on the
+    /// current route.
+    fn pulse(&self, legacy_replacement: bool) {
+        if !self.state.config.interrupt_enable() {
+            return;
+        }
+        let line = match self.route(legacy_replacement) {
+            Route::Legacy => self.legacy_line.as_ref(),
+            Route::Irq(i) => Some(&self.irq_lines[i]),
+            Route::Fsb => {
+                self.msi.as_ref().unwrap().1.deliver();
+                return;
+            }
+            Route::None => None,
+        };
+        if let Some(line) = line {
+            if !self.state.config.level_triggered() {
+                line.set_level(true);
+                line.set_level(false);
+            }
+        }
+    }
+
+    fn bit(&self) -> u64 {
+        1 << self.index
+    }
+}
+
+/// An HPET event timer block.
+#[derive(InspectMut)]
+pub struct HpetDevice {
+    // Runtime glue
+    vmtime: VmTimeAccess,
+
+    // Sub-emulators
+    #[inspect(iter_by_index)]
+    timers: Vec<Timer>,
+
+    // Volatile state
+    #[inspect(with = "|x| inspect::AsHex(u64::from(*x))")]
+    config: GeneralConfig,
+    #[inspect(hex)]
+    interrupt_status: u64,
+    /// The main counter value at `last`.
+    #[inspect(hex)]
+    counter: u64,
+    last: VmTime,
+}
+
+impl HpetDevice {
+    /// Returns a new HPET.
+    ///
+    /// `new_line` is called to create each interrupt line the HPET can drive,
+    /// with a debug name and the IRQ number. If `register_msi` is provided,
+    /// then the timers support FSB interrupt delivery.
+    pub fn new(
+        vmtime: VmTimeAccess,
+        mut new_line: impl FnMut(&str, u32) -> LineInterrupt,
+        mut register_msi: Option<&mut dyn RegisterMsi>,
+    ) -> Self {
+        let timers = (0..NUM_TIMERS)
+            .map(|index| {
+                let legacy_line = match index {
+                    0 => Some(new_line("timer0_legacy", LEGACY_TIMER0_IRQ)),
+                    1 => Some(new_line("timer1_legacy", LEGACY_TIMER1_IRQ)),
+                    _ => None,
+                };
+                let irq_lines = HPET_ROUTABLE_IRQS
+                    .map(|irq| new_line(&format!("timer{index}"), irq))
+                    .collect();
+                let msi = register_msi.as_mut().map(|r| r.new_msi());
+                Timer::new(index, legacy_line, irq_lines, msi)
+            })
+            .collect();
+
+        Self {
+            timers,
+            config: GeneralConfig::new(),
+            interrupt_status: 0,
+            counter: 0,
+            last: vmtime.now(),
+            vmtime,
+        }
+    }
+
+    /// Returns the main counter value at `now`.
+    fn counter_at(&self, now: VmTime) -> u64 {
+        if self.config.enable() {
+            let elapsed = now.checked_sub(self.last).unwrap_or(Duration::ZERO);
+            self.counter
+                .wrapping_add(elapsed.as_nanos() as u64 / NANOS_PER_TICK)
+        } else {
+            self.counter
+        }
+    }
+
+    /// Advances the main counter to `now`, firing any timers whose
+    /// comparators it passes.
+    fn evaluate(&mut self, now: VmTime) {
+        if !self.config.enable() {
+            return;
+        }
+        let prev = self.counter;
+        let counter = self.counter_at(now);
+        self.counter = counter;
+        self.last = now;
+
+        let legacy_replacement = self.config.legacy_replacement();
+        for timer in &mut self.timers {
+            if timer.state.check_fired(prev, counter) {
+                tracing::trace!(timer = timer.index, counter, "hpet timer fired");
+                if timer.state.config.level_triggered() {
+                    self.interrupt_status |= timer.bit();
+                    timer.sync_interrupts(legacy_replacement, self.interrupt_status);
+                }
+                timer.pulse(legacy_replacement);
+            }
+        }
+    }
+
+    fn arm_wakeup(&mut self) {
+        if !self.config.enable() {
+            return;
+        }
+        let next = self
+            .timers
+            .iter()
+            .filter(|timer| timer.state.config.interrupt_enable())
+            .map(|timer| timer.state.ticks_until_fire(self.counter))
+            .min();
+
+        if let Some(next) = next {
+            self.vmtime.set_timeout_if_before(
+                self.last
+                    .wrapping_add(Duration::from_nanos(next.saturating_mul(NANOS_PER_TICK))),
+            );
+        }
+    }
+
+    fn sync_interrupts(&mut self) {
+        for timer in &mut self.timers {
+            timer.sync_interrupts(self.config.legacy_replacement(), self.interrupt_status);
+        }
+    }
+
+    fn read_register(&mut self, offset: u64) -> u64 {
+        match Register(offset) {
+            Register::CAPABILITIES => capabilities().into(),
+            Register::CONFIG => self.config.into(),
+            Register::INTERRUPT_STATUS => self.interrupt_status,
+            Register::MAIN_COUNTER => self.counter,
+            _ => {
+                if let Some((timer, reg)) = self.timer_register(offset) {
+                    let timer = &self.timers[timer];
+                    match reg {
+                        TimerRegister::CONFIG => timer.config().with_value_set(false).into(),
+                        TimerRegister::COMPARATOR => timer.state.comparator,
+                        TimerRegister::FSB_ROUTE => timer.state.fsb_route,
+                        _ => 0,
+                    }
+                } else {
+                    tracelimit::warn_ratelimited!(offset, "unknown hpet register read");
+                    0
+                }
+            }
+        }
+    }
+
+    /// Writes the bits in `mask` of the register at `offset`.
+    fn write_register(&mut self, offset: u64, value: u64, mask: u64) {
+        let now = self.vmtime.now();
+        let merge = |old: u64| (old & !mask) | (value & mask);
+        match Register(offset) {
+            Register::CAPABILITIES => {}
+            Register::CONFIG => {
+                let config = GeneralConfig::from(merge(self.config.into()));
+                let config = GeneralConfig::new()
+                    .with_enable(config.enable())
+                    .with_legacy_replacement(config.legacy_replacement());
+                if config.enable() != self.config.enable() {
+                    if config.enable() {
+                        // Restart the counter from its halted value.
+                        self.last = now;
+                    } else {
+                        self.evaluate(now);
+                        self.vmtime.cancel_timeout();
+                    }
+                }
+                self.config = config;
+                self.sync_interrupts();
+            }
+            Register::INTERRUPT_STATUS => {
+                self.interrupt_status &= !(value & mask);
+                self.sync_interrupts();
+            }
+            Register::MAIN_COUNTER => {
+                if self.config.enable() {
+                    tracelimit::warn_ratelimited!("main counter written while running");
+                }
+                self.counter = merge(self.counter_at(now));
+                self.last = now;
+            }
+            _ => {
+                let Some((index, reg)) = self.timer_register(offset) else {
+                    tracelimit::warn_ratelimited!(offset, value, "unknown hpet register write");
+                    return;
+                };
+                let timer = &mut self.timers[index];
+                match reg {
+                    TimerRegister::CONFIG => {
+                        let config = TimerConfig::from(merge(timer.state.config.into()));
+                        timer.state.config =
+                            (u64::from(config) & u64::from(TimerConfig::WRITABLE)).into();
+                        if timer.state.config.mode_32bit() {
+                            timer.state.comparator &= u32::MAX as u64;
+                            timer.state.period &= u32::MAX as u64;
+                        }
+                    }
+                    TimerRegister::COMPARATOR => {
+                        let value = value & timer.state.mask();
+                        let config = timer.state.config;
+                        if !config.periodic() || config.value_set() {
+                            timer.state.comparator =
+                                (timer.state.comparator & !mask) | (value & mask);
+                        }
+                        if config.periodic() {
+                            timer.state.period = (timer.state.period & !mask) | (value & mask);
+                        }
+                        timer.state.config.set_value_set(false);
+                    }
+                    TimerRegister::FSB_ROUTE => {
+                        timer.state.fsb_route = merge(timer.state.fsb_route);
+                    }
+                    _ => {}
+                }
+                timer.sync_interrupts(self.config.legacy_replacement(), self.interrupt_status);
+            }
+        }
+    }
+
+    /// Decodes `offset` as a timer index and register.
+    fn timer_register(&self, offset: u64) -> Option<(usize, TimerRegister)> {
+        let offset = offset.checked_sub(spec::TIMER_BLOCK_START)?;
+        let index = (offset / spec::TIMER_BLOCK_SIZE) as usize;
+        (index < self.timers.len())
+            .then_some((index, TimerRegister(offset % spec::TIMER_BLOCK_SIZE)))
+    }
+}
+
+impl ChangeDeviceState for HpetDevice {
+    fn start(&mut self) {}
+
+    async fn stop(&mut self) {}
+
+    async fn reset(&mut self) {
+        self.config = GeneralConfig::new();
+        self.interrupt_status = 0;
+        self.counter = 0;
+        self.last = self.vmtime.now();
+        for timer in &mut self.timers {
+            timer.state = TimerState::new();
+            if let Some((msi, _)) = &mut timer.msi {
+                msi.drain_pending();
+            }
+        }
+        self.sync_interrupts();
+        self.vmtime.cancel_timeout();
+    }
+}
+
+impl ChipsetDevice for HpetDevice {
+    fn supports_poll_device(&mut self) -> Option<&mut dyn PollDevice> {
+        Some(self)
+    }
+
+    fn supports_mmio(&mut self) -> Option<&mut dyn MmioIntercept> {
+        Some(self)
+    }
+}
+
+impl PollDevice for HpetDevice {
+    fn poll_device(&mut self, cx: &mut Context<'_>) {
+        if let Poll::Ready(now) = self.vmtime.poll_timeout(cx) {
+            self.evaluate(now);
+            // Re-register the poll before arming the next wakeup, as in the
+            // PIT, so that a very short wakeup does not cause livelock.
+            assert!(self.vmtime.poll_timeout(cx).is_pending());
+            self.arm_wakeup();
+        }
+    }
+}
+
+impl MmioIntercept for HpetDevice {
+    fn mmio_read(&mut self, address: u64, data: &mut [u8]) -> IoResult {
+        let offset = address - HPET_MMIO_BASE;
+        if !matches!(data.len(), 4 | 8) || offset % data.len() as u64 != 0 {
+            return IoResult::Err(IoError::InvalidAccessSize);
+        }
+
+        self.evaluate(self.vmtime.now());
+        let value = self.read_register(offset & !7);
+        if data.len() == 8 {
+            data.copy_from_slice(&value.to_ne_bytes());
+        } else {
+            let value = (value >> ((offset & 4) * 8)) as u32;
+            data.copy_from_slice(&value.to_ne_bytes());
+        }
+        IoResult::Ok
+    }
+
+    fn mmio_write(&mut self, address: u64, data: &[u8]) -> IoResult {
+        let offset = address - HPET_MMIO_BASE;
+        let (value, mask) = match *data {
+            [..] if offset % data.len() as u64 != 0 => {
+                return IoResult::Err(IoError::InvalidAccessSize)
+            }
+            [a, b, c, d] => {
+                let shift = (offset & 4) * 8;
+                (
+                    (u32::from_ne_bytes([a, b, c, d]) as u64) << shift,
+                    (u32::MAX as u64) << shift,
+                )
+            }
+            [a, b, c, d, e, f, g, h] => (u64::from_ne_bytes([a, b, c, d, e, f, g, h]), !0),
+            _ => return IoResult::Err(IoError::InvalidAccessSize),
+        };
+
+        self.evaluate(self.vmtime.now());
+        self.write_register(offset & !7, value, mask);
+        self.arm_wakeup();
+        IoResult::Ok
+    }
+
+    fn get_static_regions(&mut self) -> &[(&str, RangeInclusive<u64>)] {
+        &[("mmio", HPET_MMIO_BASE..=HPET_MMIO_BASE + HPET_MMIO_LEN - 1)]
+    }
+}
+
+mod save_restore {
+    use super::*;
+    use vmcore::save_restore::RestoreError;
+    use vmcore::save_restore::SaveError;
+    use vmcore::save_restore::SaveRestore;
+
+    mod state {
+        use mesh::payload::Protobuf;
+        use vmcore::save_restore::SavedStateRoot;
+        use vmcore::vmtime::VmTime;
+
+        #[derive(Protobuf)]
+        #[mesh(package = "chipset.hpet")]
+        pub struct SavedTimerState {
+            #[mesh(1)]
+            pub config: u64,
+            #[mesh(2)]
+            pub comparator: u64,
+            #[mesh(3)]
+            pub period: u64,
+            #[mesh(4)]
+            pub fsb_route: u64,
+        }
+
+        #[derive(Protobuf, SavedStateRoot)]
+        #[mesh(package = "chipset.hpet")]
+        pub struct SavedState {
+            #[mesh(1)]
+            pub config: u64,
+            #[mesh(2)]
+            pub interrupt_status: u64,
+            #[mesh(3)]
+            pub counter: u64,
+            #[mesh(4)]
+            pub last: VmTime,
+            #[mesh(5)]
+            pub timers: Vec<SavedTimerState>,
+        }
+    }
+
+    #[derive(Debug, Error)]
+    enum HpetRestoreError {
+        #[error("saved state has {0} timers, expected {NUM_TIMERS}")]
+        TimerCount(usize),
+        #[error("last tick time is after current time")]
+        InvalidLastTick,
+    }
+
+    impl SaveRestore for HpetDevice {
+        type SavedState = state::SavedState;
+
+        fn save(&mut self) -> Result<Self::SavedState, SaveError> {
+            let Self {
+                vmtime: _,
+                timers,
+                config,
+                interrupt_status,
+                counter,
+                last,
+            } = self;
+
+            Ok(state::SavedState {
+                config: (*config).into(),
+                interrupt_status: *interrupt_status,
+                counter: *counter,
+                last: *last,
+                timers: timers
+                    .iter()
+                    .map(|timer| {
+                        let TimerState {
+                            config,
+                            comparator,
+                            period,
+                            fsb_route,
+                        } = timer.state;
+                        state::SavedTimerState {
+                            config: config.into(),
+                            comparator,
+                            period,
+                            fsb_route,
+                        }
+                    })
+                    .collect(),
+            })
+        }
+
+        fn restore(&mut self, state: Self::SavedState) -> Result<(), RestoreError> {
+            let state::SavedState {
+                config,
+                interrupt_status,
+                counter,
+                last,
+                timers,
+            } = state;
+
+            if timers.len() != self.timers.len() {
+                return Err(RestoreError::InvalidSavedState(
+                    HpetRestoreError::TimerCount(timers.len()).into(),
+                ));
+            }
+            let config = GeneralConfig::from(config);
+            if config.enable() && last.is_after(self.vmtime.now()) {
+                return Err(RestoreError::InvalidSavedState(
+                    HpetRestoreError::InvalidLastTick.into(),
+                ));
+            }
+
+            for (timer, state) in self.timers.iter_mut().zip(timers) {
+                let state::SavedTimerState {
+                    config,
+                    comparator,
+                    period,
+                    fsb_route,
+                } = state;
+
+                timer.state = TimerState {
+                    config: (config & u64::from(TimerConfig::WRITABLE)).into(),
+                    comparator,
+                    period,
+                    fsb_route,
+                };
+            }
+
+            self.config = config;
+            self.interrupt_status = interrupt_status & ((1 << NUM_TIMERS) - 1);
+            self.counter = counter;
+            self.last = last;
+            self.sync_interrupts();
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vmcore::line_interrupt::test_helpers::TestLineInterruptTarget;
+    use vmcore::line_interrupt::LineSet;
+
+    fn new_test_hpet(
+        msi: Option<&mut dyn RegisterMsi>,
+    ) -> (
+        pal_async::DefaultPool,
+        vmcore::vmtime::VmTimeKeeper,
+        std::sync::Arc<TestLineInterruptTarget>,
+        HpetDevice,
+    ) {
+        let mut pool = pal_async::DefaultPool::new();
+        let driver = pool.driver();
+        let vm_time_keeper =
+            vmcore::vmtime::VmTimeKeeper::new(&pool.driver(), VmTime::from_100ns(0));
+        let vm_time_source = pool
+            .run_until(vm_time_keeper.builder().build(&driver))
+            .unwrap();
+
+        let target = TestLineInterruptTarget::new_arc();
+        let line_set = LineSet::new();
+        line_set.add_target(0..=23, 0, "target", target.clone());
+        let hpet = HpetDevice::new(
+            vm_time_source.access("hpet"),
+            |name, irq| line_set.new_line(irq, name.to_owned()).unwrap(),
+            msi,
+        );
+
+        (pool, vm_time_keeper, target, hpet)
+    }
+
+    fn read(hpet: &mut HpetDevice, offset: u64) -> u64 {
+        let mut data = [0; 8];
+        hpet.mmio_read(HPET_MMIO_BASE + offset, &mut data).unwrap();
+        u64::from_ne_bytes(data)
+    }
+
+    fn write(hpet: &mut HpetDevice, offset: u64, value: u64) {
+        hpet.mmio_write(HPET_MMIO_BASE + offset, &value.to_ne_bytes())
+            .unwrap();
+    }
+
+    fn timer_offset(index: u64, reg: TimerRegister) -> u64 {
+        spec::TIMER_BLOCK_START + index * spec::TIMER_BLOCK_SIZE + reg.0
+    }
+
+    /// Runs the device forward to `ticks` main counter ticks after `last`.
+    fn advance(hpet: &mut HpetDevice, ticks: u64) {
+        let now = hpet
+            .last
+            .wrapping_add(Duration::from_nanos(ticks * NANOS_PER_TICK));
+        hpet.evaluate(now);
+    }
+
+    #[test]
+    fn test_capabilities() {
+        let (_pool, _keeper, _target, mut hpet) = new_test_hpet(None);
+        let caps = Capabilities::from(read(&mut hpet, Register::CAPABILITIES.0));
+        assert_eq!(caps.num_tim_cap() as usize, NUM_TIMERS - 1);
+        assert!(caps.count_size_cap());
+        assert!(caps.leg_rt_cap());
+        assert_eq!(caps.counter_clk_period(), COUNTER_PERIOD_FS);
+
+        let mut data = [0; 4];
+        hpet.mmio_read(HPET_MMIO_BASE + 4, &mut data).unwrap();
+        assert_eq!(u32::from_ne_bytes(data), COUNTER_PERIOD_FS);
+
+        let config = TimerConfig::from(read(&mut hpet, timer_offset(1, TimerRegister::CONFIG)));
+        assert!(config.periodic_capable());
+        assert!(!config.fsb_capable());
+        assert_eq!(config.interrupt_route_capability(), 0xf0_0000);
+    }
+
+    #[test]
+    fn test_one_shot_level() {
+        let (_pool, _keeper, target, mut hpet) = new_test_hpet(None);
+        write(
+            &mut hpet,
+            timer_offset(2, TimerRegister::CONFIG),
+            TimerConfig::new()
+                .with_level_triggered(true)
+                .with_interrupt_enable(true)
+                .with_interrupt_route(21)
+                .into(),
+        );
+        write(&mut hpet, timer_offset(2, TimerRegister::COMPARATOR), 1000);
+        write(
+            &mut hpet,
+            Register::CONFIG.0,
+            GeneralConfig::new().with_enable(true).into(),
+        );
+
+        advance(&mut hpet, 999);
+        assert!(!target.is_high(21));
+        assert_eq!(hpet.interrupt_status, 0);
+        advance(&mut hpet, 1);
+        assert!(target.is_high(21));
+        assert_eq!(hpet.interrupt_status, 1 << 2);
+
+        // One-shot timers don't fire again until the counter wraps.
+        write(&mut hpet, Register::INTERRUPT_STATUS.0, 1 << 2);
+        assert!(!target.is_high(21));
+        advance(&mut hpet, 100_000);
+        assert!(!target.is_high(21));
+    }
+
+    #[test]
+    fn test_periodic() {
+        let (_pool, _keeper, _target, mut hpet) = new_test_hpet(None);
+        let cmp = timer_offset(0, TimerRegister::COMPARATOR);
+        write(
+            &mut hpet,
+            timer_offset(0, TimerRegister::CONFIG),
+            TimerConfig::new()
+                .with_level_triggered(true)
+                .with_periodic(true)
+                .with_value_set(true)
+                .into(),
+        );
+        // As programmed by Linux: the first write sets the comparator and the
+        // second just the period.
+        write(&mut hpet, cmp, 500);
+        write(&mut hpet, cmp, 200);
+        assert_eq!(hpet.timers[0].state.comparator, 500);
+        assert_eq!(hpet.timers[0].state.period, 200);
+
+        write(
+            &mut hpet,
+            Register::CONFIG.0,
+            GeneralConfig::new().with_enable(true).into(),
+        );
+        advance(&mut hpet, 500);
+        assert_eq!(hpet.interrupt_status, 1);
+        assert_eq!(read(&mut hpet, cmp), 700);
+
+        // Missed periods are skipped.
+        advance(&mut hpet, 1000);
+        assert_eq!(hpet.timers[0].state.comparator, 1700);
+    }
+
+    #[test]
+    fn test_legacy_replacement() {
+        let (_pool, _keeper, target, mut hpet) = new_test_hpet(None);
+        write(
+            &mut hpet,
+            timer_offset(1, TimerRegister::CONFIG),
+            TimerConfig::new()
+                .with_level_triggered(true)
+                .with_interrupt_enable(true)
+                .with_interrupt_route(20)
+                .into(),
+        );
+        write(&mut hpet, timer_offset(1, TimerRegister::COMPARATOR), 10);
+        write(
+            &mut hpet,
+            Register::CONFIG.0,
+            GeneralConfig::new()
+                .with_enable(true)
+                .with_legacy_replacement(true)
+                .into(),
+        );
+        advance(&mut hpet, 10);
+        assert!(target.is_high(LEGACY_TIMER1_IRQ));
+        assert!(!target.is_high(20));
+
+        // Leaving legacy replacement mode moves the asserted interrupt.
+        write(
+            &mut hpet,
+            Register::CONFIG.0,
+            GeneralConfig::new().with_enable(true).into(),
+        );
+        assert!(!target.is_high(LEGACY_TIMER1_IRQ));
+        assert!(target.is_high(20));
+    }
+
+    #[test]
+    fn test_fsb() {
+        let mut msi_set = pci_core::msi::MsiInterruptSet::new();
+        let (_pool, _keeper, _target, mut hpet) = new_test_hpet(Some(&mut msi_set));
+        let delivered = std::sync::Arc::new(parking_lot::Mutex::new(Vec::new()));
+        msi_set.connect(&TestMsiTarget(delivered.clone()));
+
+        let config = TimerConfig::from(read(&mut hpet, timer_offset(0, TimerRegister::CONFIG)));
+        assert!(config.fsb_capable());
+        write(
+            &mut hpet,
+            timer_offset(0, TimerRegister::FSB_ROUTE),
+            (0xfee0_0000 << 32) | 0x41,
+        );
+        write(
+            &mut hpet,
+            timer_offset(0, TimerRegister::CONFIG),
+            TimerConfig::new()
+                .with_interrupt_enable(true)
+                .with_fsb_enable(true)
+                .into(),
+        );
+        write(&mut hpet, timer_offset(0, TimerRegister::COMPARATOR), 5);
+        write(
+            &mut hpet,
+            Register::CONFIG.0,
+            GeneralConfig::new().with_enable(true).into(),
+        );
+        advance(&mut hpet, 5);
+        assert_eq!(*delivered.lock(), [(0xfee0_0000, 0x41)]);
+    }
+
+    struct TestMsiTarget(std::sync::Arc<parking_lot::Mutex<Vec<(u64, u32)>>>);
+
+    impl pci_core::msi::MsiInterruptTarget for TestMsiTarget {
+        fn new_interrupt(&self) -> Box<dyn pci_core::msi::MsiControl> {
+            let delivered = self.0.clone();
+            Box::new(move |address, data| delivered.lock().push((address, data)))
+        }
+    }
+
+    #[test]
+    fn test_32bit_mode() {
+        let mut state = TimerState::new();
+        state.config = state.config.with_mode_32bit(true);
+        state.comparator = 0x10;
+        // The comparator matches after the low 32 bits of the counter wrap.
+        assert!(state.check_fired(0x1_ffff_fff0, 0x2_0000_0010));
+        assert!(!state.check_fired(0x2_0000_0010, 0x2_0000_0020));
+    }
+}
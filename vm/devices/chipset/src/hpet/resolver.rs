@@ -0,0 +1,41 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Resolver for HPET devices.
+
+use super::HpetDevice;
+use chipset_device_resources::ResolveChipsetDeviceHandleParams;
+use chipset_device_resources::ResolvedChipsetDevice;
+use chipset_device_resources::IRQ_LINE_SET;
+use chipset_resources::hpet::HpetDeviceHandle;
+use std::convert::Infallible;
+use vm_resource::declare_static_resolver;
+use vm_resource::kind::ChipsetDeviceHandleKind;
+use vm_resource::ResolveResource;
+
+/// A resolver for HPET devices.
+pub struct HpetResolver;
+
+declare_static_resolver! {
+    HpetResolver,
+    (ChipsetDeviceHandleKind, HpetDeviceHandle),
+}
+
+impl ResolveResource<ChipsetDeviceHandleKind, HpetDeviceHandle> for HpetResolver {
+    type Output = ResolvedChipsetDevice;
+    type Error = Infallible;
+
+    fn resolve(
+        &self,
+        _resource: HpetDeviceHandle,
+        input: ResolveChipsetDeviceHandleParams<'_>,
+    ) -> Result<Self::Output, Self::Error> {
+        let configure = input.configure;
+        Ok(HpetDevice::new(
+            input.vmtime.access("hpet"),
+            |name, irq| configure.new_line(IRQ_LINE_SET, name, irq),
+            input.register_msi,
+        )
+        .into())
+    }
+}
@@ -0,0 +1,105 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! HPET register definitions, as per the IA-PC HPET (High Precision Event
+//! Timers) Specification, revision 1.0a.
+
+use bitfield_struct::bitfield;
+use open_enum::open_enum;
+
+open_enum! {
+    /// General registers, as offsets into the HPET's MMIO region.
+    pub enum Register: u64 {
+        CAPABILITIES = 0x000,
+        CONFIG = 0x010,
+        INTERRUPT_STATUS = 0x020,
+        MAIN_COUNTER = 0x0f0,
+    }
+}
+
+/// Offset of the first timer's register block.
+pub const TIMER_BLOCK_START: u64 = 0x100;
+/// Size of each timer's register block.
+pub const TIMER_BLOCK_SIZE: u64 = 0x20;
+
+open_enum! {
+    /// Per-timer registers, as offsets into the timer's register block.
+    pub enum TimerRegister: u64 {
+        CONFIG = 0x00,
+        COMPARATOR = 0x08,
+        FSB_ROUTE = 0x10,
+    }
+}
+
+/// General Capabilities and ID Register.
+#[bitfield(u64)]
+pub struct Capabilities {
+    pub rev_id: u8,
+    /// The index of the last timer.
+    #[bits(5)]
+    pub num_tim_cap: u8,
+    /// The main counter is 64 bits wide.
+    pub count_size_cap: bool,
+    _reserved: bool,
+    /// Legacy replacement routing is supported.
+    pub leg_rt_cap: bool,
+    pub vendor_id: u16,
+    /// The main counter tick period, in femtoseconds.
+    pub counter_clk_period: u32,
+}
+
+/// General Configuration Register.
+#[bitfield(u64)]
+pub struct GeneralConfig {
+    /// Allow the main counter to run and timers to generate interrupts.
+    pub enable: bool,
+    /// Route timers 0 and 1 to IRQ0/IRQ8 instead of their configured routes.
+    pub legacy_replacement: bool,
+    #[bits(62)]
+    _reserved: u64,
+}
+
+/// Timer N Configuration and Capability Register.
+#[bitfield(u64)]
+pub struct TimerConfig {
+    _reserved0: bool,
+    /// Tn_INT_TYPE_CNF: the interrupt is level triggered (vs. edge).
+    pub level_triggered: bool,
+    /// Tn_INT_ENB_CNF
+    pub interrupt_enable: bool,
+    /// Tn_TYPE_CNF: the timer is in periodic mode (vs. one-shot).
+    pub periodic: bool,
+    /// Tn_PER_INT_CAP
+    pub periodic_capable: bool,
+    /// Tn_SIZE_CAP: the comparator is 64 bits wide.
+    pub size_64bit: bool,
+    /// Tn_VAL_SET_CNF: the next comparator write sets the comparator value
+    /// of a periodic timer (and not just its period).
+    pub value_set: bool,
+    _reserved1: bool,
+    /// Tn_32MODE_CNF: force the timer into 32-bit mode.
+    pub mode_32bit: bool,
+    /// Tn_INT_ROUTE_CNF: the I/O APIC input to route the interrupt to.
+    #[bits(5)]
+    pub interrupt_route: u8,
+    /// Tn_FSB_EN_CNF: deliver interrupts as FSB (MSI) messages.
+    pub fsb_enable: bool,
+    /// Tn_FSB_INT_DEL_CAP
+    pub fsb_capable: bool,
+    _reserved2: u16,
+    /// Tn_INT_ROUTE_CAP: bitmap of the I/O APIC inputs the timer can be
+    /// routed to.
+    pub interrupt_route_capability: u32,
+}
+
+impl TimerConfig {
+    /// The bits that are writable by the guest.
+    pub const WRITABLE: Self = Self::new()
+        .with_level_triggered(true)
+        .with_interrupt_enable(true)
+        .with_periodic(true)
+        .with_value_set(true)
+        .with_mode_32bit(true)
+        .with_interrupt_route(0x1f)
+        .with_fsb_enable(true);
+}
@@ -17,9 +17,12 @@
 
 pub mod battery;
 pub mod cmos_rtc;
+pub mod cpu_hotplug;
 pub mod dma;
+pub mod hpet;
 pub mod i8042;
 pub mod ioapic;
+pub mod memory_hotplug;
 pub mod pic;
 pub mod pit;
 pub mod pm;
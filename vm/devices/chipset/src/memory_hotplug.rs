@@ -0,0 +1,452 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! ACPI memory hotplug controller.
+//!
+//! This device divides a reserved range of guest physical address space into
+//! equally sized slots, tracks which slots are populated with RAM, and
+//! notifies the guest of hot-added slots via a GPE0 line. It is a paravirtual
+//! design driven by AML generated by `acpi::dsdt::Dsdt::add_memory_hotplug`,
+//! which must be kept in sync with the register layout below.
+//!
+//! The guest selects a slot by writing its index to the selector register,
+//! and then:
+//!
+//! - reads the status register to get the slot's `_STA` value,
+//! - reads the event register to get the pending ACPI notification value for
+//!   the slot (0 if none), and writes it back to acknowledge it.
+//!
+//! The device does not map any memory itself. The VMM must map RAM for a slot
+//! before marking it present with [`MemoryHotplugDevice::add`]. Hot-removal is
+//! not supported.
+
+use chipset_device::io::IoError;
+use chipset_device::io::IoResult;
+use chipset_device::pio::PortIoIntercept;
+use chipset_device::ChipsetDevice;
+use inspect::InspectMut;
+use memory_range::MemoryRange;
+use open_enum::open_enum;
+use std::ops::RangeInclusive;
+use thiserror::Error;
+use vmcore::device_state::ChangeDeviceState;
+use vmcore::line_interrupt::LineInterrupt;
+
+/// The I/O port of the register block.
+pub const MEMORY_HOTPLUG_IO_PORT: u16 = 0xce0;
+/// The length of the register block.
+pub const MEMORY_HOTPLUG_IO_LEN: u16 = 8;
+/// The GPE0 line used to notify the guest of pending events.
+pub const MEMORY_HOTPLUG_GPE0_LINE: u32 = 3;
+
+/// The smallest slot size, matching the x86-64 Linux memory block size.
+const MIN_SLOT_SIZE: u64 = 128 * 1024 * 1024;
+/// The largest number of slots, to bound the size of the generated DSDT.
+const MAX_SLOTS: u64 = 256;
+
+/// Returns the slot size to use for a hotplug range of `len` bytes.
+///
+/// This is a power of two of at least 128MB. The hotplug range should be
+/// aligned to, and a multiple of, this size.
+pub fn slot_size(len: u64) -> u64 {
+    len.div_ceil(MAX_SLOTS)
+        .next_power_of_two()
+        .max(MIN_SLOT_SIZE)
+}
+
+open_enum! {
+    enum RegisterOffset: u16 {
+        SELECTOR = 0,
+        STATUS = 4,
+        EVENT = 5,
+    }
+}
+
+/// The `_STA` value of a present memory device: present, enabled, shown in
+/// the UI, and functioning.
+const STATUS_PRESENT: u8 = 0xf;
+
+/// ACPI notification values reported through the event register.
+mod event {
+    pub const NONE: u8 = 0;
+    pub const DEVICE_CHECK: u8 = 1;
+}
+
+/// An error returned when a hot-add request is invalid.
+#[derive(Debug, Error)]
+pub enum MemoryHotplugError {
+    /// The size is zero or not a multiple of the slot size.
+    #[error("memory size {size:#x} is not a non-zero multiple of {slot_size:#x}")]
+    InvalidSize {
+        /// The requested size.
+        size: u64,
+        /// The slot size.
+        slot_size: u64,
+    },
+    /// There are not enough free slots.
+    #[error("not enough hotplug memory space for {0:#x} bytes")]
+    OutOfSpace(u64),
+    /// The slot index is out of range.
+    #[error("memory slot {0} does not exist")]
+    InvalidSlot(u32),
+    /// The slot is already present.
+    #[error("memory slot {0} is already present")]
+    AlreadyPresent(u32),
+}
+
+/// ACPI memory hotplug controller.
+#[derive(InspectMut)]
+pub struct MemoryHotplugDevice {
+    // Static configuration
+    #[inspect(hex)]
+    io_port: u16,
+    #[inspect(skip)]
+    io_region: (&'static str, RangeInclusive<u16>),
+    #[inspect(display)]
+    range: MemoryRange,
+    #[inspect(hex)]
+    slot_size: u64,
+
+    // Runtime glue
+    #[inspect(skip)]
+    notify_interrupt: LineInterrupt,
+
+    // Volatile state
+    selector: u32,
+    #[inspect(iter_by_index)]
+    present: Vec<bool>,
+    #[inspect(iter_by_index)]
+    events: Vec<u8>,
+}
+
+impl MemoryHotplugDevice {
+    /// Returns a new device managing the hotplug memory range `range`, which
+    /// must be aligned to and a multiple of [`slot_size`]`(range.len())`.
+    ///
+    /// All slots are initially empty.
+    pub fn new(io_port: u16, range: MemoryRange, notify_interrupt: LineInterrupt) -> Self {
+        let slot_size = slot_size(range.len());
+        assert!(
+            range.start() % slot_size == 0 && range.len() % slot_size == 0,
+            "misaligned hotplug range"
+        );
+        let slot_count = (range.len() / slot_size) as usize;
+        Self {
+            io_port,
+            io_region: (
+                "memory_hotplug",
+                io_port..=io_port + (MEMORY_HOTPLUG_IO_LEN - 1),
+            ),
+            range,
+            slot_size,
+            notify_interrupt,
+            selector: 0,
+            present: vec![false; slot_count],
+            events: vec![event::NONE; slot_count],
+        }
+    }
+
+    /// Returns the guest physical address range of each slot, indexed by
+    /// slot.
+    pub fn slots(&self) -> impl '_ + Iterator<Item = MemoryRange> {
+        (0..self.present.len() as u64).map(|slot| {
+            let start = self.range.start() + slot * self.slot_size;
+            MemoryRange::new(start..start + self.slot_size)
+        })
+    }
+
+    /// Returns the ranges of the slots that are present.
+    pub fn present_ranges(&self) -> impl '_ + Iterator<Item = MemoryRange> {
+        self.slots()
+            .zip(&self.present)
+            .filter_map(|(range, &present)| present.then_some(range))
+    }
+
+    /// Returns the empty slots to populate to add `size` bytes of memory, in
+    /// order, along with their ranges.
+    ///
+    /// This does not change the state of the device. Once the memory has been
+    /// mapped, call [`add`](Self::add) for each slot.
+    pub fn free_slots(&self, size: u64) -> Result<Vec<(u32, MemoryRange)>, MemoryHotplugError> {
+        if size == 0 || size % self.slot_size != 0 {
+            return Err(MemoryHotplugError::InvalidSize {
+                size,
+                slot_size: self.slot_size,
+            });
+        }
+        let count = (size / self.slot_size) as usize;
+        let slots = self
+            .slots()
+            .zip(&self.present)
+            .enumerate()
+            .filter(|(_, (_, &present))| !present)
+            .map(|(slot, (range, _))| (slot as u32, range))
+            .take(count)
+            .collect::<Vec<_>>();
+        if slots.len() < count {
+            return Err(MemoryHotplugError::OutOfSpace(size));
+        }
+        Ok(slots)
+    }
+
+    /// Marks slot `slot` present and notifies the guest.
+    pub fn add(&mut self, slot: u32) -> Result<(), MemoryHotplugError> {
+        let present = self
+            .present
+            .get_mut(slot as usize)
+            .ok_or(MemoryHotplugError::InvalidSlot(slot))?;
+        if *present {
+            return Err(MemoryHotplugError::AlreadyPresent(slot));
+        }
+        *present = true;
+        self.events[slot as usize] = event::DEVICE_CHECK;
+        self.update_interrupt();
+        Ok(())
+    }
+
+    fn update_interrupt(&self) {
+        self.notify_interrupt
+            .set_level(self.events.iter().any(|&event| event != event::NONE));
+    }
+
+    fn read_register(&self, offset: RegisterOffset) -> u8 {
+        let slot = self.selector as usize;
+        match offset {
+            RegisterOffset::STATUS => {
+                if self.present.get(slot).copied().unwrap_or(false) {
+                    STATUS_PRESENT
+                } else {
+                    0
+                }
+            }
+            RegisterOffset::EVENT => self.events.get(slot).copied().unwrap_or(event::NONE),
+            _ => 0,
+        }
+    }
+
+    fn write_register(&mut self, offset: RegisterOffset) {
+        let slot = self.selector as usize;
+        if slot >= self.present.len() {
+            tracelimit::warn_ratelimited!(slot, "invalid memory hotplug slot selected");
+            return;
+        }
+        match offset {
+            RegisterOffset::EVENT => {
+                self.events[slot] = event::NONE;
+                self.update_interrupt();
+            }
+            _ => {
+                tracelimit::warn_ratelimited!(?offset, "invalid memory hotplug register write");
+            }
+        }
+    }
+}
+
+impl ChangeDeviceState for MemoryHotplugDevice {
+    fn start(&mut self) {}
+
+    async fn stop(&mut self) {}
+
+    async fn reset(&mut self) {
+        // Hot-added memory stays populated across reset.
+        self.selector = 0;
+        self.events.fill(event::NONE);
+        self.update_interrupt();
+    }
+}
+
+impl ChipsetDevice for MemoryHotplugDevice {
+    fn supports_pio(&mut self) -> Option<&mut dyn PortIoIntercept> {
+        Some(self)
+    }
+}
+
+impl PortIoIntercept for MemoryHotplugDevice {
+    fn io_read(&mut self, io_port: u16, data: &mut [u8]) -> IoResult {
+        let offset = RegisterOffset(io_port.wrapping_sub(self.io_port));
+        match (offset, data.len()) {
+            (RegisterOffset::SELECTOR, 4) => data.copy_from_slice(&self.selector.to_le_bytes()),
+            (RegisterOffset::SELECTOR, _) => return IoResult::Err(IoError::InvalidAccessSize),
+            (_, 1) => data[0] = self.read_register(offset),
+            _ => return IoResult::Err(IoError::InvalidAccessSize),
+        }
+        IoResult::Ok
+    }
+
+    fn io_write(&mut self, io_port: u16, data: &[u8]) -> IoResult {
+        let offset = RegisterOffset(io_port.wrapping_sub(self.io_port));
+        match (offset, data.len()) {
+            (RegisterOffset::SELECTOR, 4) => {
+                self.selector = u32::from_le_bytes(data.try_into().unwrap())
+            }
+            (RegisterOffset::SELECTOR, _) => return IoResult::Err(IoError::InvalidAccessSize),
+            (_, 1) => self.write_register(offset),
+            _ => return IoResult::Err(IoError::InvalidAccessSize),
+        }
+        IoResult::Ok
+    }
+
+    fn get_static_regions(&mut self) -> &[(&str, RangeInclusive<u16>)] {
+        std::slice::from_ref(&self.io_region)
+    }
+}
+
+mod save_restore {
+    use super::*;
+    use vmcore::save_restore::RestoreError;
+    use vmcore::save_restore::SaveError;
+    use vmcore::save_restore::SaveRestore;
+
+    mod state {
+        use mesh::payload::Protobuf;
+        use vmcore::save_restore::SavedStateRoot;
+
+        #[derive(Protobuf, SavedStateRoot)]
+        #[mesh(package = "chipset.memory_hotplug")]
+        pub struct SavedState {
+            #[mesh(1)]
+            pub selector: u32,
+            #[mesh(2)]
+            pub present: Vec<bool>,
+            #[mesh(3)]
+            pub events: Vec<u8>,
+        }
+    }
+
+    #[derive(Debug, Error)]
+    #[error("saved state has {0} memory slots, expected {1}")]
+    struct SlotCountMismatch(usize, usize);
+
+    impl SaveRestore for MemoryHotplugDevice {
+        type SavedState = state::SavedState;
+
+        fn save(&mut self) -> Result<Self::SavedState, SaveError> {
+            let Self {
+                io_port: _,
+                io_region: _,
+                range: _,
+                slot_size: _,
+                notify_interrupt: _,
+                selector,
+                present,
+                events,
+            } = self;
+
+            Ok(state::SavedState {
+                selector: *selector,
+                present: present.clone(),
+                events: events.clone(),
+            })
+        }
+
+        fn restore(&mut self, state: Self::SavedState) -> Result<(), RestoreError> {
+            let state::SavedState {
+                selector,
+                present,
+                events,
+            } = state;
+
+            if present.len() != self.present.len() || events.len() != self.events.len() {
+                return Err(RestoreError::InvalidSavedState(
+                    SlotCountMismatch(present.len(), self.present.len()).into(),
+                ));
+            }
+
+            self.selector = selector;
+            self.present = present;
+            self.events = events;
+            self.update_interrupt();
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use vmcore::line_interrupt::test_helpers::TestLineInterruptTarget;
+
+    const PORT: u16 = MEMORY_HOTPLUG_IO_PORT;
+    const MB: u64 = 1024 * 1024;
+    const GB: u64 = 1024 * MB;
+
+    fn new_device() -> (MemoryHotplugDevice, Arc<TestLineInterruptTarget>) {
+        let target = TestLineInterruptTarget::new_arc();
+        let interrupt = LineInterrupt::new_with_target("memory_hotplug", target.clone(), 3);
+        (
+            MemoryHotplugDevice::new(PORT, MemoryRange::new(4 * GB..5 * GB), interrupt),
+            target,
+        )
+    }
+
+    fn select(dev: &mut MemoryHotplugDevice, slot: u32) {
+        dev.io_write(PORT, &slot.to_le_bytes()).unwrap();
+    }
+
+    fn read(dev: &mut MemoryHotplugDevice, offset: RegisterOffset) -> u8 {
+        let mut data = [0];
+        dev.io_read(PORT + offset.0, &mut data).unwrap();
+        data[0]
+    }
+
+    #[test]
+    fn slot_sizes() {
+        assert_eq!(slot_size(GB), 128 * MB);
+        assert_eq!(slot_size(32 * GB), 128 * MB);
+        assert_eq!(slot_size(33 * GB), 256 * MB);
+        assert_eq!(slot_size(1024 * GB), 4 * GB);
+    }
+
+    #[test]
+    fn hot_add() {
+        let (mut dev, target) = new_device();
+        assert_eq!(dev.slots().count(), 8);
+        assert!(matches!(
+            dev.free_slots(64 * MB),
+            Err(MemoryHotplugError::InvalidSize { .. })
+        ));
+        assert!(matches!(
+            dev.free_slots(2 * GB),
+            Err(MemoryHotplugError::OutOfSpace(_))
+        ));
+
+        let slots = dev.free_slots(256 * MB).unwrap();
+        assert_eq!(
+            slots,
+            [
+                (0, MemoryRange::new(4 * GB..4 * GB + 128 * MB)),
+                (1, MemoryRange::new(4 * GB + 128 * MB..4 * GB + 256 * MB)),
+            ]
+        );
+        for (slot, _) in slots {
+            dev.add(slot).unwrap();
+        }
+        assert!(target.is_high(3));
+        assert!(matches!(
+            dev.add(1),
+            Err(MemoryHotplugError::AlreadyPresent(1))
+        ));
+        assert_eq!(
+            dev.present_ranges().collect::<Vec<_>>(),
+            [
+                MemoryRange::new(4 * GB..4 * GB + 128 * MB),
+                MemoryRange::new(4 * GB + 128 * MB..4 * GB + 256 * MB),
+            ]
+        );
+        assert_eq!(dev.free_slots(128 * MB).unwrap()[0].0, 2);
+
+        for slot in 0..2 {
+            select(&mut dev, slot);
+            assert_eq!(read(&mut dev, RegisterOffset::STATUS), STATUS_PRESENT);
+            assert_eq!(read(&mut dev, RegisterOffset::EVENT), event::DEVICE_CHECK);
+            dev.io_write(PORT + RegisterOffset::EVENT.0, &[event::DEVICE_CHECK])
+                .unwrap();
+            assert_eq!(read(&mut dev, RegisterOffset::EVENT), event::NONE);
+        }
+        assert!(!target.is_high(3));
+
+        select(&mut dev, 2);
+        assert_eq!(read(&mut dev, RegisterOffset::STATUS), 0);
+    }
+}
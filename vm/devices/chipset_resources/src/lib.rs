@@ -27,6 +27,22 @@ impl ResourceId<ChipsetDeviceHandleKind> for I8042DeviceHandle {
     }
 }
 
+pub mod hpet {
+    //! Resource definitions for the HPET (High Precision Event Timer).
+
+    use mesh::MeshPayload;
+    use vm_resource::kind::ChipsetDeviceHandleKind;
+    use vm_resource::ResourceId;
+
+    /// A handle to an HPET device.
+    #[derive(MeshPayload)]
+    pub struct HpetDeviceHandle;
+
+    impl ResourceId<ChipsetDeviceHandleKind> for HpetDeviceHandle {
+        const ID: &'static str = "hpet";
+    }
+}
+
 pub mod battery {
     //! Resource definitions for the battery device
 
@@ -0,0 +1,782 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Implementation of the QEMU fw_cfg device.
+//!
+//! fw_cfg is a simple selector/data interface that QEMU uses to pass
+//! configuration blobs to guest firmware. Supporting it allows stock firmware
+//! and guest tooling (SeaBIOS, OVMF, the Linux `qemu_fw_cfg` driver) to read
+//! host-provided `opt/` and `etc/` files, and allows OVMF to direct-boot a
+//! Linux kernel provided by the host.
+//!
+//! The device supports the traditional data register and the DMA interface,
+//! in either the x86 I/O port layout or the Arm MMIO layout. The write
+//! interface is not supported; all items are read-only.
+//!
+//! Besides the files provided by the user, the VMM can expose files that it
+//! generates, such as `etc/e820` and the ACPI table loader files (see
+//! [`FwCfgDevice::with_firmware_files`]).
+
+#![forbid(unsafe_code)]
+#![warn(missing_docs)]
+
+pub mod resolver;
+mod spec;
+
+use chipset_device::io::IoError;
+use chipset_device::io::IoResult;
+use chipset_device::mmio::MmioIntercept;
+use chipset_device::pio::PortIoIntercept;
+use chipset_device::ChipsetDevice;
+use fw_cfg_resources::FwCfgFirmwareFiles;
+use fw_cfg_resources::FwCfgRegisterLayout;
+use fw_cfg_resources::FW_CFG_IO_LEN;
+use fw_cfg_resources::FW_CFG_MMIO_LEN;
+use guestmem::GuestMemory;
+use guestmem::GuestMemoryError;
+use inspect::InspectMut;
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+use thiserror::Error;
+use vmcore::device_state::ChangeDeviceState;
+
+/// The contents to expose through a [`FwCfgDevice`].
+#[derive(Debug, Default)]
+pub struct FwCfgConfig {
+    /// The kernel image for firmware-driven direct boot.
+    ///
+    /// x86 bzImage kernels are split into their setup and protected-mode
+    /// parts, as firmware expects.
+    pub kernel: Option<Vec<u8>>,
+    /// The initrd for firmware-driven direct boot.
+    pub initrd: Option<Vec<u8>>,
+    /// The kernel command line for firmware-driven direct boot.
+    pub cmdline: Option<String>,
+    /// Named files to expose in the file directory.
+    pub files: Vec<(String, Vec<u8>)>,
+}
+
+/// An error creating a [`FwCfgDevice`].
+#[derive(Debug, Error)]
+pub enum FwCfgError {
+    /// A file name is empty or too long.
+    #[error("invalid fw_cfg file name: '{0}'")]
+    InvalidFileName(String),
+    /// Two files have the same name.
+    #[error("duplicate fw_cfg file name: '{0}'")]
+    DuplicateFile(String),
+    /// There are more files than there are selector keys.
+    #[error("too many fw_cfg files")]
+    TooManyFiles,
+    /// An item is larger than the 32-bit size the interface can describe.
+    #[error("fw_cfg item is too large: {0} bytes")]
+    ItemTooLarge(usize),
+}
+
+/// A file in the file directory.
+#[derive(Clone)]
+struct NamedFile {
+    name: String,
+    data: Vec<u8>,
+    /// Whether the VMM generated the file, as opposed to the user providing
+    /// it.
+    generated: bool,
+}
+
+#[derive(Debug, Error)]
+enum DmaError {
+    #[error("guest memory access failed")]
+    GuestMemory(#[source] GuestMemoryError),
+    #[error("write access is not supported")]
+    WriteUnsupported,
+}
+
+/// A fw_cfg device.
+#[derive(InspectMut)]
+pub struct FwCfgDevice {
+    // Static configuration
+    register_layout: FwCfgRegisterLayout,
+    #[inspect(hex)]
+    base: u64,
+    #[inspect(skip)]
+    pio_region: (&'static str, RangeInclusive<u16>),
+    #[inspect(skip)]
+    mmio_region: (&'static str, RangeInclusive<u64>),
+    #[inspect(iter_by_key)]
+    file_sizes: BTreeMap<String, u32>,
+    #[inspect(skip)]
+    items: BTreeMap<u16, Vec<u8>>,
+    /// The files in the directory, sorted by name. The file at index `i` has
+    /// key `FILE_FIRST + i`.
+    #[inspect(skip)]
+    files: Vec<NamedFile>,
+
+    // Runtime glue
+    #[inspect(skip)]
+    gm: GuestMemory,
+    #[inspect(skip)]
+    firmware_files: Option<FwCfgFirmwareFiles>,
+
+    // Volatile state
+    #[inspect(hex)]
+    selector: u16,
+    offset: u32,
+    #[inspect(hex)]
+    dma_address: u64,
+}
+
+impl FwCfgDevice {
+    /// Returns a new device at `base` (an I/O port or MMIO address, depending
+    /// on `register_layout`), exposing the contents of `config`.
+    ///
+    /// DMA transfers are performed to and from `gm`.
+    pub fn new(
+        register_layout: FwCfgRegisterLayout,
+        base: u64,
+        gm: GuestMemory,
+        config: FwCfgConfig,
+    ) -> Result<Self, FwCfgError> {
+        let FwCfgConfig {
+            kernel,
+            initrd,
+            cmdline,
+            files,
+        } = config;
+
+        let mut items = BTreeMap::new();
+        items.insert(spec::key::SIGNATURE, spec::SIGNATURE.to_vec());
+        items.insert(
+            spec::key::ID,
+            (spec::FEATURE_TRADITIONAL | spec::FEATURE_DMA)
+                .to_le_bytes()
+                .to_vec(),
+        );
+
+        let mut add_blob = |size_key: u16, data_key: u16, data: Vec<u8>| {
+            let len =
+                u32::try_from(data.len()).map_err(|_| FwCfgError::ItemTooLarge(data.len()))?;
+            items.insert(size_key, len.to_le_bytes().to_vec());
+            items.insert(data_key, data);
+            Ok(())
+        };
+
+        if let Some(mut kernel) = kernel {
+            let setup = split_linux_setup(&mut kernel).unwrap_or_default();
+            add_blob(spec::key::SETUP_SIZE, spec::key::SETUP_DATA, setup)?;
+            add_blob(spec::key::KERNEL_SIZE, spec::key::KERNEL_DATA, kernel)?;
+        }
+        if let Some(initrd) = initrd {
+            add_blob(spec::key::INITRD_SIZE, spec::key::INITRD_DATA, initrd)?;
+        }
+        if let Some(cmdline) = cmdline {
+            let mut cmdline = cmdline.into_bytes();
+            cmdline.push(0);
+            add_blob(spec::key::CMDLINE_SIZE, spec::key::CMDLINE_DATA, cmdline)?;
+        }
+
+        let mut this = Self {
+            register_layout,
+            base,
+            pio_region: (
+                "fw_cfg",
+                base as u16..=(base as u16).wrapping_add(u16::from(FW_CFG_IO_LEN) - 1),
+            ),
+            mmio_region: ("fw_cfg", base..=base.wrapping_add(FW_CFG_MMIO_LEN - 1)),
+            file_sizes: BTreeMap::new(),
+            items,
+            files: Vec::new(),
+            gm,
+            firmware_files: None,
+            selector: 0,
+            offset: 0,
+            dma_address: 0,
+        };
+        this.set_files(
+            files
+                .into_iter()
+                .map(|(name, data)| NamedFile {
+                    name,
+                    data,
+                    generated: false,
+                })
+                .collect(),
+        )?;
+        Ok(this)
+    }
+
+    /// Takes the files generated by the VMM from `firmware_files` each time
+    /// the device starts, replacing those from the previous start.
+    pub fn with_firmware_files(mut self, firmware_files: FwCfgFirmwareFiles) -> Self {
+        self.firmware_files = Some(firmware_files);
+        self
+    }
+
+    /// Replaces the files generated by the VMM, such as `etc/e820` and the
+    /// ACPI table loader files.
+    ///
+    /// The VMM regenerates these when it reloads the firmware, since their
+    /// contents depend on the current VM configuration.
+    fn set_firmware_files(&mut self, files: Vec<(String, Vec<u8>)>) -> Result<(), FwCfgError> {
+        let files = self
+            .files
+            .iter()
+            .filter(|file| !file.generated)
+            .cloned()
+            .chain(files.into_iter().map(|(name, data)| NamedFile {
+                name,
+                data,
+                generated: true,
+            }))
+            .collect();
+        self.set_files(files)
+    }
+
+    /// Validates `files` and replaces the file directory with them.
+    fn set_files(&mut self, mut files: Vec<NamedFile>) -> Result<(), FwCfgError> {
+        for file in &files {
+            let name = &file.name;
+            if name.is_empty() || name.len() >= spec::FILE_NAME_SIZE || name.contains('\0') {
+                return Err(FwCfgError::InvalidFileName(name.clone()));
+            }
+            if file.data.len() > u32::MAX as usize {
+                return Err(FwCfgError::ItemTooLarge(file.data.len()));
+            }
+        }
+        files.sort_by(|a, b| a.name.cmp(&b.name));
+        if let Some(w) = files.windows(2).find(|w| w[0].name == w[1].name) {
+            return Err(FwCfgError::DuplicateFile(w[0].name.clone()));
+        }
+        if files.len() > (spec::SELECTOR_KEY_MASK - spec::key::FILE_FIRST + 1).into() {
+            return Err(FwCfgError::TooManyFiles);
+        }
+
+        // The file directory is sorted by name, with keys assigned in order.
+        let mut dir = (files.len() as u32).to_be_bytes().to_vec();
+        for (file, key) in files.iter().zip(spec::key::FILE_FIRST..) {
+            dir.extend_from_slice(&(file.data.len() as u32).to_be_bytes());
+            dir.extend_from_slice(&key.to_be_bytes());
+            dir.extend_from_slice(&[0; 2]);
+            let mut file_name = [0; spec::FILE_NAME_SIZE];
+            file_name[..file.name.len()].copy_from_slice(file.name.as_bytes());
+            dir.extend_from_slice(&file_name);
+        }
+        self.items.insert(spec::key::FILE_DIR, dir);
+        self.file_sizes = files
+            .iter()
+            .map(|file| (file.name.clone(), file.data.len() as u32))
+            .collect();
+        self.files = files;
+        Ok(())
+    }
+
+    fn select(&mut self, selector: u16) {
+        self.selector = selector;
+        self.offset = 0;
+    }
+
+    /// Returns the currently selected item, or an empty slice if there is no
+    /// item with the selected key.
+    fn item(&self) -> &[u8] {
+        if self.selector & spec::SELECTOR_ARCH_LOCAL != 0 {
+            return &[];
+        }
+        let key = self.selector & spec::SELECTOR_KEY_MASK;
+        if let Some(index) = key.checked_sub(spec::key::FILE_FIRST) {
+            return self
+                .files
+                .get(usize::from(index))
+                .map_or(&[], |file| file.data.as_slice());
+        }
+        self.items.get(&key).map_or(&[], |data| data.as_slice())
+    }
+
+    /// Reads from the selected item via the data register, filling past the
+    /// end of the item with zeroes.
+    fn read_data(&mut self, data: &mut [u8]) {
+        let item = self.item();
+        let offset = (self.offset as usize).min(item.len());
+        let len = data.len().min(item.len() - offset);
+        data[..len].copy_from_slice(&item[offset..offset + len]);
+        data[len..].fill(0);
+        self.offset += len as u32;
+    }
+
+    /// Writes `data` at `offset` within the DMA address register, starting a
+    /// DMA transfer when the low half of the address is written.
+    fn write_dma_address(&mut self, offset: u64, data: &[u8]) -> IoResult {
+        match (offset, data.len()) {
+            (0, 4) => {
+                let high = u32::from_be_bytes(data.try_into().unwrap());
+                self.dma_address = u64::from(high) << 32;
+            }
+            (4, 4) => {
+                let low = u32::from_be_bytes(data.try_into().unwrap());
+                self.dma_address |= u64::from(low);
+                self.dma();
+            }
+            (0, 8) => {
+                self.dma_address = u64::from_be_bytes(data.try_into().unwrap());
+                self.dma();
+            }
+            _ => return IoResult::Err(IoError::InvalidAccessSize),
+        }
+        IoResult::Ok
+    }
+
+    fn read_dma_address(&self, offset: u64, data: &mut [u8]) -> IoResult {
+        let signature = spec::DMA_SIGNATURE.to_be_bytes();
+        match signature.get(offset as usize..offset as usize + data.len()) {
+            Some(v) => {
+                data.copy_from_slice(v);
+                IoResult::Ok
+            }
+            None => IoResult::Err(IoError::InvalidAccessSize),
+        }
+    }
+
+    /// Performs the DMA access described by the structure at the programmed
+    /// DMA address, then reports the result in its control field.
+    fn dma(&mut self) {
+        let address = std::mem::take(&mut self.dma_address);
+        let mut access = [0; spec::DMA_ACCESS_SIZE];
+        if let Err(err) = self.gm.read_at(address, &mut access) {
+            tracelimit::warn_ratelimited!(
+                address,
+                error = &err as &dyn std::error::Error,
+                "failed to read fw_cfg dma access"
+            );
+            return;
+        }
+
+        let control = u32::from_be_bytes(access[0..4].try_into().unwrap());
+        let len = u32::from_be_bytes(access[4..8].try_into().unwrap());
+        let data_address = u64::from_be_bytes(access[8..16].try_into().unwrap());
+
+        let status = match self.dma_access(control, len, data_address) {
+            Ok(()) => 0,
+            Err(err) => {
+                tracelimit::warn_ratelimited!(
+                    control,
+                    len,
+                    data_address,
+                    error = &err as &dyn std::error::Error,
+                    "fw_cfg dma access failed"
+                );
+                spec::dma_control::ERROR
+            }
+        };
+
+        if let Err(err) = self.gm.write_at(address, &status.to_be_bytes()) {
+            tracelimit::warn_ratelimited!(
+                address,
+                error = &err as &dyn std::error::Error,
+                "failed to complete fw_cfg dma access"
+            );
+        }
+    }
+
+    fn dma_access(&mut self, control: u32, len: u32, address: u64) -> Result<(), DmaError> {
+        if control & spec::dma_control::SELECT != 0 {
+            self.select((control >> 16) as u16);
+        }
+
+        let item = self.item();
+        let offset = (self.offset as usize).min(item.len());
+        let count = (len as usize).min(item.len() - offset);
+        if control & spec::dma_control::READ != 0 {
+            // Reads past the end of the item are filled with zeroes.
+            self.gm
+                .write_at(address, &item[offset..offset + count])
+                .map_err(DmaError::GuestMemory)?;
+            self.gm
+                .fill_at(address.wrapping_add(count as u64), 0, len as usize - count)
+                .map_err(DmaError::GuestMemory)?;
+        } else if control & spec::dma_control::WRITE != 0 {
+            return Err(DmaError::WriteUnsupported);
+        } else if control & spec::dma_control::SKIP == 0 {
+            return Ok(());
+        }
+        self.offset += count as u32;
+        Ok(())
+    }
+}
+
+/// Splits the real-mode setup code off an x86 bzImage kernel, returning it.
+///
+/// Returns `None` and leaves `kernel` intact if it is not a bzImage.
+fn split_linux_setup(kernel: &mut Vec<u8>) -> Option<Vec<u8>> {
+    let magic = kernel.get(
+        spec::LINUX_HEADER_MAGIC_OFFSET
+            ..spec::LINUX_HEADER_MAGIC_OFFSET + spec::LINUX_HEADER_MAGIC.len(),
+    )?;
+    if magic != spec::LINUX_HEADER_MAGIC {
+        return None;
+    }
+    let setup_sects = match kernel[spec::LINUX_SETUP_SECTS_OFFSET] {
+        // Zero means the legacy default of four sectors.
+        0 => 4,
+        n => n as usize,
+    };
+    let setup_len = (setup_sects + 1) * 512;
+    if setup_len > kernel.len() {
+        return None;
+    }
+    let rest = kernel.split_off(setup_len);
+    Some(std::mem::replace(kernel, rest))
+}
+
+impl ChangeDeviceState for FwCfgDevice {
+    fn start(&mut self) {
+        if let Some(files) = self.firmware_files.as_ref().and_then(|files| files.take()) {
+            if let Err(err) = self.set_firmware_files(files) {
+                tracing::error!(
+                    error = &err as &dyn std::error::Error,
+                    "failed to set the fw_cfg firmware files"
+                );
+            }
+        }
+    }
+
+    async fn stop(&mut self) {}
+
+    async fn reset(&mut self) {
+        self.selector = 0;
+        self.offset = 0;
+        self.dma_address = 0;
+    }
+}
+
+impl ChipsetDevice for FwCfgDevice {
+    fn supports_pio(&mut self) -> Option<&mut dyn PortIoIntercept> {
+        (self.register_layout == FwCfgRegisterLayout::IoPort).then_some(self)
+    }
+
+    fn supports_mmio(&mut self) -> Option<&mut dyn MmioIntercept> {
+        (self.register_layout == FwCfgRegisterLayout::Mmio).then_some(self)
+    }
+}
+
+impl PortIoIntercept for FwCfgDevice {
+    fn io_read(&mut self, io_port: u16, data: &mut [u8]) -> IoResult {
+        match io_port.wrapping_sub(self.base as u16) {
+            spec::io::SELECTOR => data.fill(0),
+            spec::io::DATA => self.read_data(data),
+            offset @ spec::io::DMA_ADDRESS.. => {
+                return self.read_dma_address((offset - spec::io::DMA_ADDRESS).into(), data)
+            }
+            _ => return IoResult::Err(IoError::InvalidRegister),
+        }
+        IoResult::Ok
+    }
+
+    fn io_write(&mut self, io_port: u16, data: &[u8]) -> IoResult {
+        match io_port.wrapping_sub(self.base as u16) {
+            spec::io::SELECTOR => {
+                let Ok(selector) = data.try_into().map(u16::from_le_bytes) else {
+                    return IoResult::Err(IoError::InvalidAccessSize);
+                };
+                self.select(selector);
+            }
+            spec::io::DATA => {
+                tracelimit::warn_ratelimited!("fw_cfg data register writes are not supported");
+            }
+            offset @ spec::io::DMA_ADDRESS.. => {
+                return self.write_dma_address((offset - spec::io::DMA_ADDRESS).into(), data)
+            }
+            _ => return IoResult::Err(IoError::InvalidRegister),
+        }
+        IoResult::Ok
+    }
+
+    fn get_static_regions(&mut self) -> &[(&str, RangeInclusive<u16>)] {
+        if self.register_layout == FwCfgRegisterLayout::IoPort {
+            std::slice::from_ref(&self.pio_region)
+        } else {
+            &[]
+        }
+    }
+}
+
+impl MmioIntercept for FwCfgDevice {
+    fn mmio_read(&mut self, address: u64, data: &mut [u8]) -> IoResult {
+        match address.wrapping_sub(self.base) {
+            spec::mmio::DATA..spec::mmio::SELECTOR => self.read_data(data),
+            spec::mmio::SELECTOR..spec::mmio::DMA_ADDRESS => data.fill(0),
+            offset @ spec::mmio::DMA_ADDRESS.. => {
+                return self.read_dma_address(offset - spec::mmio::DMA_ADDRESS, data)
+            }
+        }
+        IoResult::Ok
+    }
+
+    fn mmio_write(&mut self, address: u64, data: &[u8]) -> IoResult {
+        match address.wrapping_sub(self.base) {
+            spec::mmio::DATA..spec::mmio::SELECTOR => {
+                tracelimit::warn_ratelimited!("fw_cfg data register writes are not supported");
+            }
+            spec::mmio::SELECTOR => {
+                let Ok(selector) = data.try_into().map(u16::from_be_bytes) else {
+                    return IoResult::Err(IoError::InvalidAccessSize);
+                };
+                self.select(selector);
+            }
+            offset @ spec::mmio::DMA_ADDRESS.. => {
+                return self.write_dma_address(offset - spec::mmio::DMA_ADDRESS, data)
+            }
+            _ => return IoResult::Err(IoError::InvalidRegister),
+        }
+        IoResult::Ok
+    }
+
+    fn get_static_regions(&mut self) -> &[(&str, RangeInclusive<u64>)] {
+        if self.register_layout == FwCfgRegisterLayout::Mmio {
+            std::slice::from_ref(&self.mmio_region)
+        } else {
+            &[]
+        }
+    }
+}
+
+mod save_restore {
+    use super::*;
+    use vmcore::save_restore::RestoreError;
+    use vmcore::save_restore::SaveError;
+    use vmcore::save_restore::SaveRestore;
+
+    mod state {
+        use mesh::payload::Protobuf;
+        use vmcore::save_restore::SavedStateRoot;
+
+        #[derive(Protobuf, SavedStateRoot)]
+        #[mesh(package = "firmware.fw_cfg")]
+        pub struct SavedState {
+            #[mesh(1)]
+            pub selector: u16,
+            #[mesh(2)]
+            pub offset: u32,
+            #[mesh(3)]
+            pub dma_address: u64,
+        }
+    }
+
+    impl SaveRestore for FwCfgDevice {
+        type SavedState = state::SavedState;
+
+        fn save(&mut self) -> Result<Self::SavedState, SaveError> {
+            let Self {
+                register_layout: _,
+                base: _,
+                pio_region: _,
+                mmio_region: _,
+                file_sizes: _,
+                items: _,
+                files: _,
+                gm: _,
+                firmware_files: _,
+                selector,
+                offset,
+                dma_address,
+            } = *self;
+
+            Ok(state::SavedState {
+                selector,
+                offset,
+                dma_address,
+            })
+        }
+
+        fn restore(&mut self, state: Self::SavedState) -> Result<(), RestoreError> {
+            let state::SavedState {
+                selector,
+                offset,
+                dma_address,
+            } = state;
+
+            self.selector = selector;
+            self.offset = offset;
+            self.dma_address = dma_address;
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PORT: u16 = 0x510;
+
+    fn new_device(gm: &GuestMemory, config: FwCfgConfig) -> FwCfgDevice {
+        FwCfgDevice::new(FwCfgRegisterLayout::IoPort, PORT.into(), gm.clone(), config).unwrap()
+    }
+
+    fn read_item(dev: &mut FwCfgDevice, key: u16, len: usize) -> Vec<u8> {
+        dev.io_write(PORT + spec::io::SELECTOR, &key.to_le_bytes())
+            .unwrap();
+        let mut data = vec![0; len];
+        for b in &mut data {
+            dev.io_read(PORT + spec::io::DATA, std::slice::from_mut(b))
+                .unwrap();
+        }
+        data
+    }
+
+    #[test]
+    fn signature_and_features() {
+        let gm = GuestMemory::allocate(0x1000);
+        let mut dev = new_device(&gm, FwCfgConfig::default());
+        assert_eq!(read_item(&mut dev, spec::key::SIGNATURE, 6), b"QEMU\0\0");
+        assert_eq!(
+            read_item(&mut dev, spec::key::ID, 4),
+            (spec::FEATURE_TRADITIONAL | spec::FEATURE_DMA).to_le_bytes()
+        );
+
+        let mut sig = [0; 4];
+        dev.io_read(PORT + spec::io::DMA_ADDRESS, &mut sig).unwrap();
+        assert_eq!(&sig, b"QEMU");
+        dev.io_read(PORT + spec::io::DMA_ADDRESS + 4, &mut sig)
+            .unwrap();
+        assert_eq!(&sig, b" CFG");
+    }
+
+    #[test]
+    fn file_dir() {
+        let gm = GuestMemory::allocate(0x1000);
+        let mut dev = new_device(
+            &gm,
+            FwCfgConfig {
+                files: vec![
+                    ("opt/b".into(), b"bbb".to_vec()),
+                    ("opt/a".into(), b"a".to_vec()),
+                ],
+                ..Default::default()
+            },
+        );
+
+        let dir = read_item(&mut dev, spec::key::FILE_DIR, 4 + 2 * 64);
+        assert_eq!(dir[..4], 2u32.to_be_bytes());
+        let entry = |i: usize| &dir[4 + i * 64..4 + (i + 1) * 64];
+        assert_eq!(entry(0)[..4], 1u32.to_be_bytes());
+        assert_eq!(entry(0)[4..6], spec::key::FILE_FIRST.to_be_bytes());
+        assert_eq!(&entry(0)[8..14], b"opt/a\0");
+        assert_eq!(entry(1)[..4], 3u32.to_be_bytes());
+        assert_eq!(entry(1)[4..6], (spec::key::FILE_FIRST + 1).to_be_bytes());
+        assert_eq!(&entry(1)[8..14], b"opt/b\0");
+
+        assert_eq!(read_item(&mut dev, spec::key::FILE_FIRST + 1, 4), b"bbb\0");
+
+        assert!(matches!(
+            FwCfgDevice::new(
+                FwCfgRegisterLayout::IoPort,
+                PORT.into(),
+                gm.clone(),
+                FwCfgConfig {
+                    files: vec![("x".into(), Vec::new()), ("x".into(), Vec::new())],
+                    ..Default::default()
+                },
+            ),
+            Err(FwCfgError::DuplicateFile(_))
+        ));
+    }
+
+    #[test]
+    fn firmware_files() {
+        let gm = GuestMemory::allocate(0x1000);
+        let firmware_files = FwCfgFirmwareFiles::default();
+        let mut dev = new_device(
+            &gm,
+            FwCfgConfig {
+                files: vec![("opt/a".into(), b"a".to_vec())],
+                ..Default::default()
+            },
+        )
+        .with_firmware_files(firmware_files.clone());
+
+        // Generated files are picked up on start, and are sorted in with the
+        // user's files.
+        firmware_files.set(vec![("etc/e820".into(), b"e820".to_vec())]);
+        dev.start();
+        assert!(firmware_files.take().is_none());
+        let dir = read_item(&mut dev, spec::key::FILE_DIR, 4 + 2 * 64);
+        assert_eq!(dir[..4], 2u32.to_be_bytes());
+        assert_eq!(&dir[4 + 8..4 + 17], b"etc/e820\0");
+        assert_eq!(&dir[4 + 64 + 8..4 + 64 + 14], b"opt/a\0");
+        assert_eq!(read_item(&mut dev, spec::key::FILE_FIRST, 4), b"e820");
+        assert_eq!(read_item(&mut dev, spec::key::FILE_FIRST + 1, 1), b"a");
+
+        // Replacing the generated files keeps the user's.
+        dev.set_firmware_files(Vec::new()).unwrap();
+        let dir = read_item(&mut dev, spec::key::FILE_DIR, 4 + 64);
+        assert_eq!(dir[..4], 1u32.to_be_bytes());
+        assert_eq!(&dir[4 + 8..4 + 14], b"opt/a\0");
+        assert_eq!(read_item(&mut dev, spec::key::FILE_FIRST + 1, 1), b"\0");
+
+        assert!(matches!(
+            dev.set_firmware_files(vec![("opt/a".into(), Vec::new())]),
+            Err(FwCfgError::DuplicateFile(_))
+        ));
+    }
+
+    #[test]
+    fn dma() {
+        let gm = GuestMemory::allocate(0x1000);
+        let mut dev = new_device(
+            &gm,
+            FwCfgConfig {
+                cmdline: Some("console=ttyS0".into()),
+                ..Default::default()
+            },
+        );
+
+        let access = |control: u32, len: u32, address: u64| {
+            let mut v = [0; spec::DMA_ACCESS_SIZE];
+            v[0..4].copy_from_slice(&control.to_be_bytes());
+            v[4..8].copy_from_slice(&len.to_be_bytes());
+            v[8..16].copy_from_slice(&address.to_be_bytes());
+            gm.write_at(0x100, &v).unwrap();
+        };
+        let start = |dev: &mut FwCfgDevice| {
+            dev.io_write(PORT + spec::io::DMA_ADDRESS, &0u32.to_be_bytes())
+                .unwrap();
+            dev.io_write(PORT + spec::io::DMA_ADDRESS + 4, &0x100u32.to_be_bytes())
+                .unwrap();
+            let mut control = [0; 4];
+            gm.read_at(0x100, &mut control).unwrap();
+            u32::from_be_bytes(control)
+        };
+
+        // Select and skip past "console=", then read the rest, past the end.
+        gm.fill_at(0x200, 0xff, 0x10).unwrap();
+        access(
+            u32::from(spec::key::CMDLINE_DATA) << 16
+                | spec::dma_control::SELECT
+                | spec::dma_control::SKIP,
+            8,
+            0,
+        );
+        assert_eq!(start(&mut dev), 0);
+        access(spec::dma_control::READ, 0x10, 0x200);
+        assert_eq!(start(&mut dev), 0);
+        let mut data = [0; 0x10];
+        gm.read_at(0x200, &mut data).unwrap();
+        assert_eq!(&data, b"ttyS0\0\0\0\0\0\0\0\0\0\0\0");
+
+        // Writes are not supported.
+        access(spec::dma_control::WRITE, 1, 0x200);
+        assert_eq!(start(&mut dev), spec::dma_control::ERROR);
+    }
+
+    #[test]
+    fn linux_setup_split() {
+        let mut kernel = vec![0; 0x2000];
+        kernel[spec::LINUX_SETUP_SECTS_OFFSET] = 3;
+        kernel[spec::LINUX_HEADER_MAGIC_OFFSET..][..4].copy_from_slice(spec::LINUX_HEADER_MAGIC);
+        let setup = split_linux_setup(&mut kernel).unwrap();
+        assert_eq!(setup.len(), 0x800);
+        assert_eq!(kernel.len(), 0x1800);
+
+        let mut not_bzimage = vec![0; 0x2000];
+        assert!(split_linux_setup(&mut not_bzimage).is_none());
+        assert_eq!(not_bzimage.len(), 0x2000);
+    }
+}
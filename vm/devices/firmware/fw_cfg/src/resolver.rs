@@ -0,0 +1,126 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Resource resolver for the fw_cfg device.
+
+use crate::FwCfgConfig;
+use crate::FwCfgDevice;
+use crate::FwCfgError;
+use async_trait::async_trait;
+use chipset_device_resources::ResolveChipsetDeviceHandleParams;
+use chipset_device_resources::ResolvedChipsetDevice;
+use fw_cfg_resources::FwCfgDeviceHandle;
+use fw_cfg_resources::FwCfgFile;
+use fw_cfg_resources::FwCfgFileData;
+use fw_cfg_resources::FwCfgPlatformKind;
+use fw_cfg_resources::FwCfgRegisterLayout;
+use fw_cfg_resources::FW_CFG_IO_PORT;
+use fw_cfg_resources::FW_CFG_MMIO_BASE;
+use std::fs::File;
+use std::io::Read;
+use thiserror::Error;
+use vm_resource::declare_static_async_resolver;
+use vm_resource::kind::ChipsetDeviceHandleKind;
+use vm_resource::AsyncResolveResource;
+use vm_resource::IntoResource;
+use vm_resource::PlatformResource;
+use vm_resource::ResolveError;
+use vm_resource::ResourceResolver;
+
+/// The resource resolver for the fw_cfg device.
+pub struct FwCfgResolver;
+
+declare_static_async_resolver! {
+    FwCfgResolver,
+    (ChipsetDeviceHandleKind, FwCfgDeviceHandle),
+}
+
+/// An error resolving a fw_cfg device handle.
+#[allow(missing_docs)]
+#[derive(Debug, Error)]
+pub enum ResolveFwCfgError {
+    #[error("fw_cfg is not supported by this VM")]
+    Platform(#[source] ResolveError),
+    #[error("the fw_cfg {0:?} register layout is not supported by this VM")]
+    RegisterLayout(FwCfgRegisterLayout),
+    #[error("failed to read the fw_cfg {0}")]
+    Read(String, #[source] std::io::Error),
+    #[error("invalid fw_cfg configuration")]
+    Device(#[source] FwCfgError),
+}
+
+fn read_file(mut file: File, what: impl FnOnce() -> String) -> Result<Vec<u8>, ResolveFwCfgError> {
+    let mut data = Vec::new();
+    file.read_to_end(&mut data)
+        .map_err(|err| ResolveFwCfgError::Read(what(), err))?;
+    Ok(data)
+}
+
+#[async_trait]
+impl AsyncResolveResource<ChipsetDeviceHandleKind, FwCfgDeviceHandle> for FwCfgResolver {
+    type Output = ResolvedChipsetDevice;
+    type Error = ResolveFwCfgError;
+
+    async fn resolve(
+        &self,
+        resolver: &ResourceResolver,
+        resource: FwCfgDeviceHandle,
+        input: ResolveChipsetDeviceHandleParams<'_>,
+    ) -> Result<Self::Output, Self::Error> {
+        let FwCfgDeviceHandle {
+            register_layout,
+            kernel,
+            initrd,
+            cmdline,
+            files,
+        } = resource;
+
+        // The VMM describes the device to the guest and generates some of its
+        // files, so it must know about it.
+        let platform = resolver
+            .resolve::<FwCfgPlatformKind, _>(PlatformResource.into_resource(), ())
+            .await
+            .map_err(ResolveFwCfgError::Platform)?;
+        if register_layout != platform.register_layout {
+            return Err(ResolveFwCfgError::RegisterLayout(register_layout));
+        }
+
+        let kernel = kernel
+            .map(|file| read_file(file, || "kernel".into()))
+            .transpose()?;
+        let initrd = initrd
+            .map(|file| read_file(file, || "initrd".into()))
+            .transpose()?;
+        let files = files
+            .into_iter()
+            .map(|FwCfgFile { name, data }| {
+                let data = match data {
+                    FwCfgFileData::Bytes(data) => data,
+                    FwCfgFileData::File(file) => read_file(file, || format!("file '{name}'"))?,
+                };
+                Ok((name, data))
+            })
+            .collect::<Result<_, ResolveFwCfgError>>()?;
+
+        let base = match register_layout {
+            FwCfgRegisterLayout::IoPort => FW_CFG_IO_PORT.into(),
+            FwCfgRegisterLayout::Mmio => FW_CFG_MMIO_BASE,
+        };
+
+        let device = FwCfgDevice::new(
+            register_layout,
+            base,
+            input.guest_memory.clone(),
+            FwCfgConfig {
+                kernel,
+                initrd,
+                cmdline,
+                files,
+            },
+        )
+        .map_err(ResolveFwCfgError::Device)?
+        .with_firmware_files(platform.firmware_files);
+
+        Ok(device.into())
+    }
+}
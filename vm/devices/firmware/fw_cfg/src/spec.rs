@@ -0,0 +1,85 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Definitions from the QEMU fw_cfg interface specification
+//! (`docs/specs/fw_cfg.rst` in the QEMU tree).
+
+/// Selector keys for the fixed-purpose items.
+pub mod key {
+    pub const SIGNATURE: u16 = 0x00;
+    pub const ID: u16 = 0x01;
+    pub const KERNEL_SIZE: u16 = 0x08;
+    pub const INITRD_SIZE: u16 = 0x0b;
+    pub const KERNEL_DATA: u16 = 0x11;
+    pub const INITRD_DATA: u16 = 0x12;
+    pub const CMDLINE_SIZE: u16 = 0x14;
+    pub const CMDLINE_DATA: u16 = 0x15;
+    pub const SETUP_SIZE: u16 = 0x17;
+    pub const SETUP_DATA: u16 = 0x18;
+    pub const FILE_DIR: u16 = 0x19;
+    /// The key of the first entry in the file directory.
+    pub const FILE_FIRST: u16 = 0x20;
+}
+
+/// Selector bit requesting write access to the item.
+pub const SELECTOR_WRITE: u16 = 0x4000;
+/// Selector bit selecting an architecture-specific item.
+pub const SELECTOR_ARCH_LOCAL: u16 = 0x8000;
+/// The mask of the item key in a selector.
+pub const SELECTOR_KEY_MASK: u16 = !(SELECTOR_WRITE | SELECTOR_ARCH_LOCAL);
+
+/// The contents of the [`key::SIGNATURE`] item.
+pub const SIGNATURE: &[u8; 4] = b"QEMU";
+
+/// The traditional (data register) interface is supported.
+pub const FEATURE_TRADITIONAL: u32 = 1 << 0;
+/// The DMA interface is supported.
+pub const FEATURE_DMA: u32 = 1 << 1;
+
+/// The value read from the DMA address register, "QEMU CFG" in big-endian.
+pub const DMA_SIGNATURE: u64 = 0x51454d5520434647;
+
+/// DMA control bits, in the first (big-endian) field of a DMA access.
+pub mod dma_control {
+    pub const ERROR: u32 = 1 << 0;
+    pub const READ: u32 = 1 << 1;
+    pub const SKIP: u32 = 1 << 2;
+    pub const SELECT: u32 = 1 << 3;
+    pub const WRITE: u32 = 1 << 4;
+}
+
+/// The size of a DMA access structure in guest memory: a big-endian control
+/// `u32`, length `u32` and address `u64`.
+pub const DMA_ACCESS_SIZE: usize = 16;
+
+/// The size of a file name in a file directory entry, including the NUL
+/// terminator.
+pub const FILE_NAME_SIZE: usize = 56;
+
+/// Register offsets for the I/O port layout.
+pub mod io {
+    /// Selector register, 16 bits, little-endian.
+    pub const SELECTOR: u16 = 0;
+    /// Data register, 8 bits.
+    pub const DATA: u16 = 1;
+    /// DMA address register, 64 bits big-endian, written as two 32-bit
+    /// halves.
+    pub const DMA_ADDRESS: u16 = 4;
+}
+
+/// Register offsets for the MMIO layout.
+pub mod mmio {
+    /// Data register, up to 64 bits.
+    pub const DATA: u64 = 0;
+    /// Selector register, 16 bits, big-endian.
+    pub const SELECTOR: u64 = 8;
+    /// DMA address register, 64 bits big-endian.
+    pub const DMA_ADDRESS: u64 = 16;
+}
+
+/// The offset of the `setup_sects` field in an x86 Linux kernel image.
+pub const LINUX_SETUP_SECTS_OFFSET: usize = 0x1f1;
+/// The offset of the setup header magic in an x86 Linux kernel image.
+pub const LINUX_HEADER_MAGIC_OFFSET: usize = 0x202;
+/// The setup header magic, "HdrS".
+pub const LINUX_HEADER_MAGIC: &[u8; 4] = b"HdrS";
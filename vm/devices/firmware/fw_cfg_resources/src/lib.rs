@@ -0,0 +1,140 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Resource definitions for the QEMU fw_cfg device.
+
+#![forbid(unsafe_code)]
+#![warn(missing_docs)]
+
+use inspect::Inspect;
+use mesh::MeshPayload;
+use parking_lot::Mutex;
+use std::convert::Infallible;
+use std::fs::File;
+use std::sync::Arc;
+use vm_resource::kind::ChipsetDeviceHandleKind;
+use vm_resource::CanResolveTo;
+use vm_resource::PlatformResource;
+use vm_resource::ResolveResource;
+use vm_resource::ResourceId;
+use vm_resource::ResourceKind;
+
+/// The base I/O port of the fw_cfg device when using
+/// [`FwCfgRegisterLayout::IoPort`].
+///
+/// This matches QEMU's x86 machines, which is where firmware expects to find
+/// the device.
+pub const FW_CFG_IO_PORT: u16 = 0x510;
+
+/// The number of I/O ports used by the fw_cfg device, starting at
+/// [`FW_CFG_IO_PORT`].
+pub const FW_CFG_IO_LEN: u8 = 12;
+
+/// The base address of the fw_cfg device when using
+/// [`FwCfgRegisterLayout::Mmio`].
+pub const FW_CFG_MMIO_BASE: u64 = 0xEFFE9000;
+
+/// The size of the fw_cfg register block when using
+/// [`FwCfgRegisterLayout::Mmio`].
+pub const FW_CFG_MMIO_LEN: u64 = 0x18;
+
+/// A handle to a fw_cfg device.
+#[derive(MeshPayload)]
+pub struct FwCfgDeviceHandle {
+    /// The register layout (IO port or MMIO).
+    pub register_layout: FwCfgRegisterLayout,
+    /// The kernel to expose for firmware-driven direct boot.
+    pub kernel: Option<File>,
+    /// The initrd to expose for firmware-driven direct boot.
+    pub initrd: Option<File>,
+    /// The kernel command line to expose for firmware-driven direct boot.
+    pub cmdline: Option<String>,
+    /// Named files to expose in the fw_cfg file directory.
+    pub files: Vec<FwCfgFile>,
+}
+
+impl ResourceId<ChipsetDeviceHandleKind> for FwCfgDeviceHandle {
+    const ID: &'static str = "fw_cfg";
+}
+
+/// The fw_cfg register layout.
+#[derive(Inspect, MeshPayload, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FwCfgRegisterLayout {
+    /// Registers at [`FW_CFG_IO_PORT`], as on QEMU's x86 machines.
+    IoPort,
+    /// Registers at [`FW_CFG_MMIO_BASE`], as on QEMU's Arm machines.
+    Mmio,
+}
+
+/// A named file in the fw_cfg file directory.
+#[derive(MeshPayload)]
+pub struct FwCfgFile {
+    /// The file name, such as `opt/com.example/config`.
+    pub name: String,
+    /// The file contents.
+    pub data: FwCfgFileData,
+}
+
+/// The contents of a fw_cfg file.
+#[derive(MeshPayload)]
+pub enum FwCfgFileData {
+    /// Inline contents.
+    Bytes(Vec<u8>),
+    /// Contents read from a host file when the device is created.
+    File(File),
+}
+
+/// Resource kind for the VMM's fw_cfg support, resolved from
+/// [`PlatformResource`] by the fw_cfg device.
+pub enum FwCfgPlatformKind {}
+
+impl ResourceKind for FwCfgPlatformKind {
+    const NAME: &'static str = "fw_cfg_platform";
+}
+
+impl CanResolveTo<FwCfgPlatform> for FwCfgPlatformKind {
+    type Input<'a> = ();
+}
+
+/// The VMM's fw_cfg support: how it describes the device to the guest, and
+/// the files it generates for the device.
+#[derive(Clone)]
+pub struct FwCfgPlatform {
+    /// The register layout that the VMM describes to the guest.
+    pub register_layout: FwCfgRegisterLayout,
+    /// The files generated by the VMM.
+    pub firmware_files: FwCfgFirmwareFiles,
+}
+
+impl ResolveResource<FwCfgPlatformKind, PlatformResource> for FwCfgPlatform {
+    type Output = FwCfgPlatform;
+    type Error = Infallible;
+
+    fn resolve(
+        &self,
+        PlatformResource: PlatformResource,
+        (): (),
+    ) -> Result<Self::Output, Self::Error> {
+        Ok(self.clone())
+    }
+}
+
+/// Files generated by the VMM for the fw_cfg device, such as `etc/e820` and
+/// the ACPI table loader files.
+///
+/// The VMM regenerates the files each time it loads the firmware, and the
+/// device picks them up the next time it starts.
+#[derive(Clone, Default)]
+pub struct FwCfgFirmwareFiles(Arc<Mutex<Option<Vec<(String, Vec<u8>)>>>>);
+
+impl FwCfgFirmwareFiles {
+    /// Replaces the generated files.
+    pub fn set(&self, files: Vec<(String, Vec<u8>)>) {
+        *self.0.lock() = Some(files);
+    }
+
+    /// Takes the files set since the last call, if any.
+    pub fn take(&self) -> Option<Vec<(String, Vec<u8>)>> {
+        self.0.lock().take()
+    }
+}
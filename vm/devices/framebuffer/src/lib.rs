@@ -31,6 +31,7 @@
 use memory_range::MemoryRange;
 use mesh::payload::Protobuf;
 use mesh::MeshPayload;
+use pal_async::task::Spawn;
 use parking_lot::Mutex;
 use sparse_mmap::Mappable;
 use sparse_mmap::SparseMapping;
@@ -143,6 +144,39 @@ pub fn view(self) -> io::Result<View> {
             offset: self.offset,
         })
     }
+
+    /// Splits the accessor into two accessors for the same framebuffer, so
+    /// that more than one component can read it.
+    ///
+    /// Format updates are forwarded to both accessors by a task spawned on
+    /// `spawner`.
+    pub fn split(self, spawner: &impl Spawn) -> io::Result<(Self, Self)> {
+        let vram = self.vram.try_clone()?;
+        let (send_a, recv_a) = mesh::channel();
+        let (send_b, recv_b) = mesh::channel();
+        let mut format_recv = self.format_recv;
+        spawner
+            .spawn("framebuffer-format", async move {
+                while let Ok(format) = format_recv.recv().await {
+                    send_a.send(format);
+                    send_b.send(format);
+                }
+            })
+            .detach();
+        let a = Self {
+            vram,
+            len: self.len,
+            format_recv: recv_a,
+            offset: self.offset,
+        };
+        let b = Self {
+            vram: self.vram,
+            len: self.len,
+            format_recv: recv_b,
+            offset: self.offset,
+        };
+        Ok((a, b))
+    }
 }
 
 /// A mapped view of the framebuffer.
@@ -489,6 +523,9 @@ async fn unmap(&mut self) {
     async fn set_format(&mut self, format: FramebufferFormat) {
         self.set_format(format);
     }
+    fn memory(&self) -> io::Result<GuestMemory> {
+        self.memory()
+    }
 }
 
 impl ResolveResource<FramebufferHandleKind, SharedFramebufferHandle> for FramebufferLocalControl {
@@ -8,7 +8,9 @@
 use inspect::Inspect;
 use vmcore::save_restore::ProtobufSaveRestore;
 
+pub mod msi;
 pub mod msix;
+pub mod pci_express;
 pub mod read_only;
 
 /// A generic PCI configuration space capability structure.
@@ -25,6 +27,16 @@ pub trait PciCapability: Send + Sync + Inspect + ProtobufSaveRestore {
     /// Write a u32 at the given offset
     fn write_u32(&mut self, offset: u16, val: u32);
 
+    /// Write the bytes selected by `mask` of the u32 at the given offset,
+    /// leaving the other bytes unchanged.
+    ///
+    /// The default implementation performs a read-modify-write, which is not
+    /// suitable for registers with write side effects (e.g: RW1C bits).
+    fn write_u32_masked(&mut self, offset: u16, val: u32, mask: u32) {
+        let old = self.read_u32(offset);
+        self.write_u32(offset, (old & !mask) | (val & mask));
+    }
+
     /// Reset the capability
     fn reset(&mut self);
 }
@@ -0,0 +1,267 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! MSI Capability.
+
+use super::PciCapability;
+use crate::msi::MsiInterrupt;
+use crate::msi::RegisterMsi;
+use crate::spec::caps::msi::MsiCapabilityHeader;
+use crate::spec::caps::msi::MsiMessageControl;
+use crate::spec::caps::msi::MSI_CAPABILITY_LEN;
+use crate::spec::caps::CapabilityId;
+use inspect::Inspect;
+use parking_lot::Mutex;
+use std::sync::Arc;
+use vmcore::interrupt::Interrupt;
+
+#[derive(Inspect)]
+struct MsiState {
+    enabled: bool,
+    #[inspect(hex)]
+    address: u64,
+    #[inspect(hex)]
+    data: u16,
+    #[inspect(skip)]
+    msi: MsiInterrupt,
+}
+
+impl MsiState {
+    fn new(msi: MsiInterrupt) -> Self {
+        Self {
+            enabled: false,
+            address: 0,
+            data: 0,
+            msi,
+        }
+    }
+
+    /// Re-targets the underlying interrupt after a change to the enable bit or
+    /// to the message address or data.
+    fn update(&mut self, was_enabled: bool) {
+        if self.enabled {
+            self.msi.enable(self.address, self.data.into(), false);
+        } else if was_enabled {
+            self.msi.disable();
+        }
+    }
+}
+
+#[derive(Inspect)]
+struct MsiCapability {
+    #[inspect(flatten, with = "|x| inspect::adhoc(|req| x.lock().inspect(req))")]
+    state: Arc<Mutex<MsiState>>,
+}
+
+impl PciCapability for MsiCapability {
+    fn label(&self) -> &str {
+        "msi"
+    }
+
+    fn len(&self) -> usize {
+        MSI_CAPABILITY_LEN.into()
+    }
+
+    fn read_u32(&self, offset: u16) -> u32 {
+        let state = self.state.lock();
+        match MsiCapabilityHeader(offset) {
+            MsiCapabilityHeader::CONTROL_CAPS => {
+                let control = MsiMessageControl::new()
+                    .with_enable(state.enabled)
+                    .with_address_64bit_capable(true);
+                CapabilityId::MSI.0 as u32 | (u16::from(control) as u32) << 16
+            }
+            MsiCapabilityHeader::MSG_ADDR_LO => state.address as u32,
+            MsiCapabilityHeader::MSG_ADDR_HI => (state.address >> 32) as u32,
+            MsiCapabilityHeader::MSG_DATA => state.data.into(),
+            _ => panic!("Unreachable read offset {}", offset),
+        }
+    }
+
+    fn write_u32(&mut self, offset: u16, val: u32) {
+        let mut state = self.state.lock();
+        let was_enabled = state.enabled;
+        match MsiCapabilityHeader(offset) {
+            MsiCapabilityHeader::CONTROL_CAPS => {
+                // Only a single vector is supported, so the multiple message
+                // enable field is hardwired to zero.
+                state.enabled = MsiMessageControl::from((val >> 16) as u16).enable();
+            }
+            MsiCapabilityHeader::MSG_ADDR_LO => {
+                state.address = (state.address & 0xffffffff00000000) | (val & !3) as u64
+            }
+            MsiCapabilityHeader::MSG_ADDR_HI => {
+                state.address = (val as u64) << 32 | state.address & 0xffffffff
+            }
+            MsiCapabilityHeader::MSG_DATA => state.data = val as u16,
+            _ => panic!("Unreachable write offset {}", offset),
+        }
+        state.update(was_enabled);
+    }
+
+    fn reset(&mut self) {
+        let mut state = self.state.lock();
+        let was_enabled = state.enabled;
+        state.enabled = false;
+        state.address = 0;
+        state.data = 0;
+        state.update(was_enabled);
+        state.msi.drain_pending();
+    }
+}
+
+/// Emulator for the hardware-level interface required to configure and trigger
+/// a single-vector MSI interrupt on a PCI device.
+///
+/// The capability advertises 64-bit message addresses and no per-vector
+/// masking.
+#[derive(Clone)]
+pub struct MsiEmulator {
+    state: Arc<Mutex<MsiState>>,
+}
+
+impl MsiEmulator {
+    /// Create a new [`MsiEmulator`] instance, along with with its associated
+    /// [`PciCapability`] structure.
+    pub fn new(register_msi: &mut dyn RegisterMsi) -> (Self, impl PciCapability) {
+        let state = Arc::new(Mutex::new(MsiState::new(register_msi.new_msi())));
+        (
+            Self {
+                state: state.clone(),
+            },
+            MsiCapability { state },
+        )
+    }
+
+    /// Return an [`Interrupt`] that signals the MSI vector.
+    ///
+    /// Interrupts raised while MSI is disabled are held pending and delivered
+    /// once the guest enables it.
+    pub fn interrupt(&self) -> Interrupt {
+        self.state.lock().msi.interrupt()
+    }
+}
+
+mod save_restore {
+    use super::*;
+    use vmcore::save_restore::RestoreError;
+    use vmcore::save_restore::SaveError;
+    use vmcore::save_restore::SaveRestore;
+
+    mod state {
+        use mesh::payload::Protobuf;
+        use vmcore::save_restore::SavedStateRoot;
+
+        #[derive(Debug, Protobuf, SavedStateRoot)]
+        #[mesh(package = "pci.caps.msi")]
+        pub struct SavedState {
+            #[mesh(1)]
+            pub enabled: bool,
+            #[mesh(2)]
+            pub address: u64,
+            #[mesh(3)]
+            pub data: u16,
+            #[mesh(4)]
+            pub is_pending: bool,
+        }
+    }
+
+    impl SaveRestore for MsiCapability {
+        type SavedState = state::SavedState;
+
+        fn save(&mut self) -> Result<Self::SavedState, SaveError> {
+            let mut state = self.state.lock();
+            let is_pending = !state.enabled && state.msi.drain_pending();
+            if is_pending {
+                // Saving must not consume the pending interrupt.
+                state.msi.interrupt().deliver();
+            }
+            Ok(state::SavedState {
+                enabled: state.enabled,
+                address: state.address,
+                data: state.data,
+                is_pending,
+            })
+        }
+
+        fn restore(&mut self, state: Self::SavedState) -> Result<(), RestoreError> {
+            let state::SavedState {
+                enabled,
+                address,
+                data,
+                is_pending,
+            } = state;
+
+            let mut state = self.state.lock();
+            state.enabled = enabled;
+            state.address = address;
+            state.data = data;
+            if enabled {
+                state.msi.enable(address, data.into(), is_pending);
+            } else {
+                state.msi.disable();
+                state.msi.drain_pending();
+                if is_pending {
+                    state.msi.interrupt().deliver();
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::msi::MsiInterruptSet;
+    use crate::test_helpers::TestPciInterruptController;
+
+    #[test]
+    fn msi_check() {
+        let mut set = MsiInterruptSet::new();
+        let (msi, mut cap) = MsiEmulator::new(&mut set);
+        let msi_controller = TestPciInterruptController::new();
+        set.connect(&msi_controller);
+        let interrupt = msi.interrupt();
+
+        // 64-bit capable, disabled.
+        assert_eq!(cap.read_u32(0), 0x800005);
+        cap.write_u32(4, 0xfee01003);
+        cap.write_u32(8, 0x1);
+        cap.write_u32(12, 0xffff0041);
+        assert_eq!(cap.read_u32(4), 0xfee01000);
+        assert_eq!(cap.read_u32(8), 0x1);
+        assert_eq!(cap.read_u32(12), 0x41);
+
+        // Interrupts are held pending while disabled.
+        interrupt.deliver();
+        assert_eq!(msi_controller.get_next_interrupt(), None);
+
+        // Multiple message enable is hardwired to zero.
+        cap.write_u32(0, 0x00710000);
+        assert_eq!(cap.read_u32(0), 0x810005);
+        assert_eq!(
+            msi_controller.get_next_interrupt(),
+            Some((0x1_fee01000, 0x41))
+        );
+        interrupt.deliver();
+        assert_eq!(
+            msi_controller.get_next_interrupt(),
+            Some((0x1_fee01000, 0x41))
+        );
+
+        // Retargeting while enabled takes effect immediately.
+        cap.write_u32(12, 0x42);
+        interrupt.deliver();
+        assert_eq!(
+            msi_controller.get_next_interrupt(),
+            Some((0x1_fee01000, 0x42))
+        );
+
+        cap.reset();
+        assert_eq!(cap.read_u32(0), 0x800005);
+        assert_eq!(cap.read_u32(4), 0);
+        interrupt.deliver();
+        assert_eq!(msi_controller.get_next_interrupt(), None);
+    }
+}
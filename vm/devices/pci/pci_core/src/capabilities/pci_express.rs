@@ -0,0 +1,578 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! PCI Express Capability.
+
+use super::PciCapability;
+use crate::spec::caps::pci_express::DevicePortType;
+use crate::spec::caps::pci_express::LinkCapabilities;
+use crate::spec::caps::pci_express::LinkStatus;
+use crate::spec::caps::pci_express::PciExpressCapabilities;
+use crate::spec::caps::pci_express::PciExpressCapabilityHeader;
+use crate::spec::caps::pci_express::SlotCapabilities;
+use crate::spec::caps::pci_express::SlotControl;
+use crate::spec::caps::pci_express::SlotStatus;
+use crate::spec::caps::pci_express::PCI_EXPRESS_CAPABILITY_LEN;
+use crate::spec::caps::CapabilityId;
+use inspect::Inspect;
+use parking_lot::Mutex;
+use std::sync::Arc;
+use vmcore::interrupt::Interrupt;
+
+/// Default Device Control value: relaxed ordering and no snoop enabled, with a
+/// 512 byte max read request size.
+const DEFAULT_DEVICE_CONTROL: u16 = 0x2810;
+/// Default Link Control 2 value: target link speed of 2.5 GT/s.
+const DEFAULT_LINK_CONTROL_2: u16 = 0x1;
+/// Link speed reported for all links (2.5 GT/s).
+const LINK_SPEED_2_5_GT: u8 = 1;
+/// Supported Link Speeds Vector reported in Link Capabilities 2.
+const SUPPORTED_LINK_SPEEDS_2_5_GT: u32 = 1 << 1;
+/// The RW1C event bits of the Slot Status register that are implemented.
+const SLOT_EVENTS: SlotStatus = SlotStatus::new()
+    .with_attention_button_pressed(true)
+    .with_presence_detect_changed(true)
+    .with_command_completed(true)
+    .with_data_link_layer_state_changed(true);
+
+#[derive(Debug, Inspect)]
+struct PciExpressState {
+    #[inspect(hex)]
+    device_control: u16,
+    #[inspect(hex)]
+    link_control: u16,
+    #[inspect(hex)]
+    slot_control: u16,
+    #[inspect(hex)]
+    root_control: u16,
+    #[inspect(hex)]
+    device_control_2: u16,
+    #[inspect(hex)]
+    link_control_2: u16,
+    #[inspect(hex)]
+    slot_events: u16,
+    link_active: bool,
+    hot_plug_interrupt_asserted: bool,
+    #[inspect(skip)]
+    hot_plug_interrupt: Option<Interrupt>,
+}
+
+impl PciExpressState {
+    fn new(link_active: bool, hot_plug_interrupt: Option<Interrupt>) -> Self {
+        Self {
+            device_control: DEFAULT_DEVICE_CONTROL,
+            link_control: 0,
+            slot_control: 0,
+            root_control: 0,
+            device_control_2: 0,
+            link_control_2: DEFAULT_LINK_CONTROL_2,
+            slot_events: 0,
+            link_active,
+            hot_plug_interrupt_asserted: false,
+            hot_plug_interrupt,
+        }
+    }
+
+    /// Whether any slot event is pending that the guest has enabled as a
+    /// hot-plug interrupt source.
+    fn hot_plug_interrupt_pending(&self) -> bool {
+        let control = SlotControl::from_bits(self.slot_control);
+        let events = SlotStatus::from_bits(self.slot_events);
+        control.hot_plug_interrupt_enable()
+            && ((control.attention_button_pressed_enable() && events.attention_button_pressed())
+                || (control.presence_detect_changed_enable() && events.presence_detect_changed())
+                || (control.command_completed_interrupt_enable() && events.command_completed())
+                || (control.data_link_layer_state_changed_enable()
+                    && events.data_link_layer_state_changed()))
+    }
+
+    /// Signals the hot-plug interrupt if the set of pending, enabled slot
+    /// events went from empty to non-empty.
+    ///
+    /// This matches the edge semantics required of MSI: a new message is only
+    /// sent once software has cleared all previously signaled events.
+    fn update_hot_plug_interrupt(&mut self) {
+        let Some(interrupt) = &self.hot_plug_interrupt else {
+            return;
+        };
+        let pending = self.hot_plug_interrupt_pending();
+        if pending && !self.hot_plug_interrupt_asserted {
+            interrupt.deliver();
+        }
+        self.hot_plug_interrupt_asserted = pending;
+    }
+
+    fn set_slot_events(&mut self, events: SlotStatus) {
+        self.slot_events |= events.into_bits();
+        self.update_hot_plug_interrupt();
+    }
+
+    fn write_slot_control_status(&mut self, control: Option<u16>, clear_events: u16) {
+        if let Some(control) = control {
+            self.slot_control = control;
+            // Commands complete immediately.
+            if self.hot_plug_interrupt.is_some() {
+                self.slot_events |= SlotStatus::new().with_command_completed(true).into_bits();
+            }
+        }
+        self.slot_events &= !clear_events;
+        self.update_hot_plug_interrupt();
+    }
+}
+
+/// A PCI Express Capability Structure (version 2).
+///
+/// Models a single-lane 2.5 GT/s link. Downstream ports (e.g: root ports)
+/// report the state of their link via a [`PciExpressLink`] handle.
+///
+/// Slots can optionally support native hot-plug (see
+/// [`PciExpressCapability::with_hot_plug`]), in which case they implement an
+/// attention button, presence detection, and command completion, and signal
+/// slot events via an interrupt.
+#[derive(Inspect)]
+pub struct PciExpressCapability {
+    device_port_type: DevicePortType,
+    slot_number: Option<u16>,
+    hot_plug_capable: bool,
+    #[inspect(with = "|x| inspect::adhoc(|req| x.lock().inspect(req))")]
+    state: Arc<Mutex<PciExpressState>>,
+}
+
+/// A handle to update the link state reported by a [`PciExpressCapability`].
+#[derive(Clone)]
+pub struct PciExpressLink {
+    state: Arc<Mutex<PciExpressState>>,
+}
+
+impl PciExpressLink {
+    /// Sets whether the link is up, i.e: whether a device is present on the
+    /// other side of the link.
+    pub fn set_active(&self, active: bool) {
+        self.state.lock().link_active = active;
+    }
+
+    /// Reports a hot-plug event: sets the link state to `present`, and raises
+    /// the presence detect and data link layer state changed slot events if
+    /// the state changed.
+    ///
+    /// Removal is reported as a surprise removal unless the guest has already
+    /// powered off the slot (see [`Self::press_attention_button`]).
+    pub fn hot_plug(&self, present: bool) {
+        let mut state = self.state.lock();
+        if state.link_active != present {
+            state.link_active = present;
+            state.set_slot_events(
+                SlotStatus::new()
+                    .with_presence_detect_changed(true)
+                    .with_data_link_layer_state_changed(true),
+            );
+        }
+    }
+
+    /// Reports a press of the slot's attention button, which guests typically
+    /// treat as a request to power down the slot for removal.
+    pub fn press_attention_button(&self) {
+        self.state
+            .lock()
+            .set_slot_events(SlotStatus::new().with_attention_button_pressed(true));
+    }
+
+    /// Returns whether the guest has turned off power to the slot, which it
+    /// does once it has released the device in response to an attention
+    /// button press.
+    pub fn is_powered_off(&self) -> bool {
+        SlotControl::from_bits(self.state.lock().slot_control).power_controller_control()
+    }
+}
+
+impl PciExpressCapability {
+    /// Create a new PCI Express capability for a device of the given type.
+    ///
+    /// If `slot_number` is `Some`, the port reports that it is connected to a
+    /// slot with the given physical slot number.
+    pub fn new(device_port_type: DevicePortType, slot_number: Option<u16>) -> Self {
+        // Upstream-facing devices always have an active link. Downstream
+        // ports start with their link down until something is attached.
+        let link_active = !Self::is_downstream_port(device_port_type);
+        Self {
+            device_port_type,
+            slot_number,
+            hot_plug_capable: false,
+            state: Arc::new(Mutex::new(PciExpressState::new(link_active, None))),
+        }
+    }
+
+    /// Enable native hot-plug support on the slot, signaling slot events via
+    /// `interrupt`.
+    ///
+    /// Panics if the capability was not created with a slot.
+    pub fn with_hot_plug(mut self, interrupt: Interrupt) -> Self {
+        assert!(
+            self.slot_number.is_some(),
+            "hot-plug requires a slot implementation"
+        );
+        self.hot_plug_capable = true;
+        self.state.lock().hot_plug_interrupt = Some(interrupt);
+        self
+    }
+
+    /// Returns a handle to update the link state.
+    pub fn link(&self) -> PciExpressLink {
+        PciExpressLink {
+            state: self.state.clone(),
+        }
+    }
+
+    fn is_downstream_port(device_port_type: DevicePortType) -> bool {
+        matches!(
+            device_port_type,
+            DevicePortType::ROOT_PORT | DevicePortType::DOWNSTREAM_SWITCH_PORT
+        )
+    }
+
+    fn is_root_port(&self) -> bool {
+        self.device_port_type == DevicePortType::ROOT_PORT
+    }
+}
+
+impl PciCapability for PciExpressCapability {
+    fn label(&self) -> &str {
+        "pci-express"
+    }
+
+    fn len(&self) -> usize {
+        PCI_EXPRESS_CAPABILITY_LEN.into()
+    }
+
+    fn read_u32(&self, offset: u16) -> u32 {
+        let state = self.state.lock();
+        match PciExpressCapabilityHeader(offset) {
+            PciExpressCapabilityHeader::PCIE_CAPS => {
+                let caps = PciExpressCapabilities::new()
+                    .with_capability_version(2)
+                    .with_device_port_type(self.device_port_type.0)
+                    .with_slot_implemented(self.slot_number.is_some());
+                CapabilityId::PCI_EXPRESS.0 as u32 | (caps.into_bits() as u32) << 16
+            }
+            // Role-based error reporting, 128 byte max payload.
+            PciExpressCapabilityHeader::DEVICE_CAPS => 1 << 15,
+            PciExpressCapabilityHeader::DEVICE_CTL_STS => state.device_control as u32,
+            PciExpressCapabilityHeader::LINK_CAPS => LinkCapabilities::new()
+                .with_max_link_speed(LINK_SPEED_2_5_GT)
+                .with_max_link_width(1)
+                .with_data_link_layer_link_active_reporting(Self::is_downstream_port(
+                    self.device_port_type,
+                ))
+                .into_bits(),
+            PciExpressCapabilityHeader::LINK_CTL_STS => {
+                let status = LinkStatus::new()
+                    .with_current_link_speed(LINK_SPEED_2_5_GT)
+                    .with_negotiated_link_width(if state.link_active { 1 } else { 0 })
+                    .with_slot_clock_configuration(true)
+                    .with_data_link_layer_link_active(
+                        Self::is_downstream_port(self.device_port_type) && state.link_active,
+                    );
+                state.link_control as u32 | (status.into_bits() as u32) << 16
+            }
+            PciExpressCapabilityHeader::SLOT_CAPS => match self.slot_number {
+                Some(slot_number) => SlotCapabilities::new()
+                    .with_attention_button_present(self.hot_plug_capable)
+                    .with_power_controller_present(self.hot_plug_capable)
+                    .with_attention_indicator_present(self.hot_plug_capable)
+                    .with_power_indicator_present(self.hot_plug_capable)
+                    .with_hot_plug_surprise(self.hot_plug_capable)
+                    .with_hot_plug_capable(self.hot_plug_capable)
+                    .with_no_command_completed_support(!self.hot_plug_capable)
+                    .with_physical_slot_number(slot_number)
+                    .into_bits(),
+                None => 0,
+            },
+            PciExpressCapabilityHeader::SLOT_CTL_STS => {
+                if self.slot_number.is_some() {
+                    let status = SlotStatus::from_bits(state.slot_events)
+                        .with_presence_detect_state(state.link_active);
+                    state.slot_control as u32 | (status.into_bits() as u32) << 16
+                } else {
+                    0
+                }
+            }
+            PciExpressCapabilityHeader::ROOT_CTL_CAPS => {
+                if self.is_root_port() {
+                    state.root_control as u32
+                } else {
+                    0
+                }
+            }
+            PciExpressCapabilityHeader::ROOT_STS => 0,
+            PciExpressCapabilityHeader::DEVICE_CAPS_2 => 0,
+            PciExpressCapabilityHeader::DEVICE_CTL_STS_2 => state.device_control_2 as u32,
+            PciExpressCapabilityHeader::LINK_CAPS_2 => SUPPORTED_LINK_SPEEDS_2_5_GT,
+            PciExpressCapabilityHeader::LINK_CTL_STS_2 => state.link_control_2 as u32,
+            PciExpressCapabilityHeader::SLOT_CAPS_2 => 0,
+            PciExpressCapabilityHeader::SLOT_CTL_STS_2 => 0,
+            _ => panic!("Unreachable read offset {}", offset),
+        }
+    }
+
+    fn write_u32(&mut self, offset: u16, val: u32) {
+        let mut state = self.state.lock();
+        match PciExpressCapabilityHeader(offset) {
+            PciExpressCapabilityHeader::DEVICE_CTL_STS => state.device_control = val as u16,
+            PciExpressCapabilityHeader::LINK_CTL_STS => state.link_control = val as u16,
+            PciExpressCapabilityHeader::SLOT_CTL_STS => {
+                if self.slot_number.is_some() {
+                    state.write_slot_control_status(Some(val as u16), (val >> 16) as u16);
+                }
+            }
+            PciExpressCapabilityHeader::ROOT_CTL_CAPS => {
+                if self.is_root_port() {
+                    state.root_control = val as u16;
+                }
+            }
+            PciExpressCapabilityHeader::DEVICE_CTL_STS_2 => state.device_control_2 = val as u16,
+            PciExpressCapabilityHeader::LINK_CTL_STS_2 => state.link_control_2 = val as u16,
+            // The remaining status bits are all read-only or RW1C bits that
+            // are never set.
+            PciExpressCapabilityHeader::PCIE_CAPS
+            | PciExpressCapabilityHeader::DEVICE_CAPS
+            | PciExpressCapabilityHeader::LINK_CAPS
+            | PciExpressCapabilityHeader::SLOT_CAPS
+            | PciExpressCapabilityHeader::ROOT_STS
+            | PciExpressCapabilityHeader::DEVICE_CAPS_2
+            | PciExpressCapabilityHeader::LINK_CAPS_2
+            | PciExpressCapabilityHeader::SLOT_CAPS_2
+            | PciExpressCapabilityHeader::SLOT_CTL_STS_2 => {}
+            _ => panic!("Unreachable write offset {}", offset),
+        }
+    }
+
+    fn write_u32_masked(&mut self, offset: u16, val: u32, mask: u32) {
+        // Slot Status contains RW1C bits, so the Slot Control and Slot Status
+        // registers must be written independently.
+        if PciExpressCapabilityHeader(offset) == PciExpressCapabilityHeader::SLOT_CTL_STS {
+            if self.slot_number.is_some() {
+                let mut state = self.state.lock();
+                let control = (mask & 0xffff != 0).then(|| {
+                    let mask = mask as u16;
+                    (state.slot_control & !mask) | (val as u16 & mask)
+                });
+                state.write_slot_control_status(control, ((val & mask) >> 16) as u16);
+            }
+        } else {
+            let old = self.read_u32(offset);
+            self.write_u32(offset, (old & !mask) | (val & mask));
+        }
+    }
+
+    fn reset(&mut self) {
+        let mut state = self.state.lock();
+        let hot_plug_interrupt = state.hot_plug_interrupt.take();
+        *state = PciExpressState::new(state.link_active, hot_plug_interrupt);
+    }
+}
+
+mod save_restore {
+    use super::*;
+    use vmcore::save_restore::RestoreError;
+    use vmcore::save_restore::SaveError;
+    use vmcore::save_restore::SaveRestore;
+
+    mod state {
+        use mesh::payload::Protobuf;
+        use vmcore::save_restore::SavedStateRoot;
+
+        #[derive(Debug, Protobuf, SavedStateRoot)]
+        #[mesh(package = "pci.caps.pci_express")]
+        pub struct SavedState {
+            #[mesh(1)]
+            pub device_control: u16,
+            #[mesh(2)]
+            pub link_control: u16,
+            #[mesh(3)]
+            pub slot_control: u16,
+            #[mesh(4)]
+            pub root_control: u16,
+            #[mesh(5)]
+            pub device_control_2: u16,
+            #[mesh(6)]
+            pub link_control_2: u16,
+            #[mesh(7)]
+            pub slot_events: u16,
+        }
+    }
+
+    impl SaveRestore for PciExpressCapability {
+        type SavedState = state::SavedState;
+
+        fn save(&mut self) -> Result<Self::SavedState, SaveError> {
+            let PciExpressState {
+                device_control,
+                link_control,
+                slot_control,
+                root_control,
+                device_control_2,
+                link_control_2,
+                slot_events,
+                link_active: _,
+                hot_plug_interrupt_asserted: _,
+                hot_plug_interrupt: _,
+            } = *self.state.lock();
+
+            Ok(state::SavedState {
+                device_control,
+                link_control,
+                slot_control,
+                root_control,
+                device_control_2,
+                link_control_2,
+                slot_events,
+            })
+        }
+
+        fn restore(&mut self, state: Self::SavedState) -> Result<(), RestoreError> {
+            let state::SavedState {
+                device_control,
+                link_control,
+                slot_control,
+                root_control,
+                device_control_2,
+                link_control_2,
+                slot_events,
+            } = state;
+
+            // The link state is determined by the current topology, not the
+            // saved state.
+            let mut state = self.state.lock();
+            let state = &mut *state;
+            state.device_control = device_control;
+            state.link_control = link_control;
+            state.slot_control = slot_control;
+            state.root_control = root_control;
+            state.device_control_2 = device_control_2;
+            state.link_control_2 = link_control_2;
+            state.slot_events = slot_events & SLOT_EVENTS.into_bits();
+            // Any pending interrupt was already signaled before the save.
+            state.hot_plug_interrupt_asserted =
+                state.hot_plug_interrupt.is_some() && state.hot_plug_interrupt_pending();
+
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn root_port_link_state() {
+        let cap = PciExpressCapability::new(DevicePortType::ROOT_PORT, Some(3));
+        // version 2, root port, slot implemented
+        assert_eq!(cap.read_u32(0), 0x1420010);
+        // physical slot 3, no command completed support
+        assert_eq!(cap.read_u32(0x14), (3 << 19) | (1 << 18));
+        // link down: no presence, no negotiated width, DLL inactive
+        assert_eq!(cap.read_u32(0x10) >> 16, 0x1001);
+        assert_eq!(cap.read_u32(0x18) >> 16, 0);
+
+        cap.link().set_active(true);
+        assert_eq!(cap.read_u32(0x10) >> 16, 0x3011);
+        assert_eq!(cap.read_u32(0x18) >> 16, 0x40);
+    }
+
+    #[test]
+    fn root_port_hot_plug() {
+        let interrupts = Arc::new(AtomicUsize::new(0));
+        let mut cap = PciExpressCapability::new(DevicePortType::ROOT_PORT, Some(1)).with_hot_plug(
+            Interrupt::from_fn({
+                let interrupts = interrupts.clone();
+                move || {
+                    interrupts.fetch_add(1, Ordering::SeqCst);
+                }
+            }),
+        );
+        let link = cap.link();
+        let count = || interrupts.load(Ordering::SeqCst);
+
+        // physical slot 1, hot-plug surprise and capable, attention button,
+        // power controller, and indicators present, command completed
+        // supported
+        assert_eq!(cap.read_u32(0x14), (1 << 19) | 0x7b);
+
+        // events latch without an interrupt until they are enabled
+        link.hot_plug(true);
+        assert_eq!(cap.read_u32(0x18) >> 16, 0x148);
+        assert_eq!(count(), 0);
+
+        // enabling the interrupt with events pending completes the command and
+        // signals once
+        let control = SlotControl::new()
+            .with_attention_button_pressed_enable(true)
+            .with_presence_detect_changed_enable(true)
+            .with_command_completed_interrupt_enable(true)
+            .with_hot_plug_interrupt_enable(true)
+            .with_data_link_layer_state_changed_enable(true)
+            .into_bits();
+        cap.write_u32_masked(0x18, control as u32, 0xffff);
+        assert_eq!(cap.read_u32(0x18), 0x0158_0000 | control as u32);
+        assert_eq!(count(), 1);
+
+        // clearing a subset of the events does not re-signal, and a status
+        // write does not complete a command
+        cap.write_u32_masked(0x18, 0x0048_0000, 0xffff_0000);
+        assert_eq!(cap.read_u32(0x18) >> 16, 0x150);
+        link.press_attention_button();
+        assert_eq!(cap.read_u32(0x18) >> 16, 0x151);
+        assert_eq!(count(), 1);
+
+        // once all events are cleared, new events signal again
+        cap.write_u32_masked(0x18, 0x0151_0000, 0xffff_0000);
+        assert_eq!(cap.read_u32(0x18) >> 16, 0x40);
+        link.hot_plug(false);
+        assert_eq!(cap.read_u32(0x18) >> 16, 0x108);
+        assert_eq!(count(), 2);
+
+        // a dword write clears the status bits and completes a command in one
+        // go, so an event remains pending throughout
+        cap.write_u32(0x18, 0x0108_0000 | control as u32);
+        assert_eq!(cap.read_u32(0x18) >> 16, 0x10);
+        assert_eq!(count(), 2);
+
+        // redundant state changes are not reported
+        cap.write_u32_masked(0x18, 0x0010_0000, 0xffff_0000);
+        link.hot_plug(false);
+        assert_eq!(cap.read_u32(0x18) >> 16, 0);
+        assert_eq!(count(), 2);
+
+        // the guest powers off the slot to release the device
+        assert!(!link.is_powered_off());
+        let off = SlotControl::from_bits(control)
+            .with_power_indicator_control(0b11)
+            .with_power_controller_control(true)
+            .into_bits();
+        cap.write_u32_masked(0x18, off as u32, 0xffff);
+        assert!(link.is_powered_off());
+    }
+
+    #[test]
+    fn endpoint_control_registers() {
+        let mut cap = PciExpressCapability::new(DevicePortType::ENDPOINT, None);
+        assert_eq!(cap.read_u32(0), 0x20010);
+        assert_eq!(cap.read_u32(0x8), DEFAULT_DEVICE_CONTROL as u32);
+        // endpoints always have their link up
+        assert_eq!(cap.read_u32(0x10) >> 16, 0x1011);
+
+        cap.write_u32(0x8, 0xffff_1234);
+        assert_eq!(cap.read_u32(0x8), 0x1234);
+        // no slot or root registers on an endpoint
+        cap.write_u32(0x18, 0xffff);
+        cap.write_u32(0x1c, 0xffff);
+        assert_eq!(cap.read_u32(0x18), 0);
+        assert_eq!(cap.read_u32(0x1c), 0);
+
+        cap.reset();
+        assert_eq!(cap.read_u32(0x8), DEFAULT_DEVICE_CONTROL as u32);
+    }
+}
@@ -119,9 +119,6 @@ fn new() -> Self {
 }
 
 /// Emulator for the standard Type 0 PCI configuration space header.
-//
-// TODO: split + share shared registers with other (yet unimplemented)
-// header types
 #[derive(Inspect)]
 pub struct ConfigSpaceType0Emulator {
     // Fixed configuration
@@ -144,6 +141,119 @@ pub struct ConfigSpaceType0Emulator {
     state: ConfigSpaceType0EmulatorState,
 }
 
+/// Capability list handling shared between the various header types.
+///
+/// Capabilities are laid out back-to-back starting at offset 0x40.
+mod capability_list {
+    use super::*;
+    use crate::spec::caps::CapabilityId;
+
+    const CAPABILITIES_START: u16 = 0x40;
+
+    fn find(capabilities: &[Box<dyn PciCapability>], offset: u16) -> Option<(usize, u16)> {
+        let mut cap_offset = CAPABILITIES_START;
+        for (i, cap) in capabilities.iter().enumerate() {
+            let cap_size = cap.len() as u16;
+            if offset < cap_offset + cap_size {
+                return Some((i, offset - cap_offset));
+            }
+            cap_offset += cap_size;
+        }
+        None
+    }
+
+    /// Returns true if the device exposes a PCI Express capability, and
+    /// therefore has an extended configuration space.
+    fn is_pci_express(capabilities: &[Box<dyn PciCapability>]) -> bool {
+        capabilities
+            .iter()
+            .any(|cap| cap.read_u32(0) as u8 == CapabilityId::PCI_EXPRESS.0)
+    }
+
+    /// The offset of the first capability, for use in the capabilities
+    /// pointer register.
+    pub fn pointer(capabilities: &[Box<dyn PciCapability>]) -> u32 {
+        if capabilities.is_empty() {
+            0
+        } else {
+            CAPABILITIES_START.into()
+        }
+    }
+
+    pub fn read_u32(
+        capabilities: &[Box<dyn PciCapability>],
+        offset: u16,
+        value: &mut u32,
+    ) -> IoResult {
+        if offset < 0x100 {
+            if let Some((cap_index, cap_offset)) = find(capabilities, offset) {
+                *value = capabilities[cap_index].read_u32(cap_offset);
+                if cap_offset == 0 {
+                    let next = if cap_index < capabilities.len() - 1 {
+                        offset as u32 + capabilities[cap_index].len() as u32
+                    } else {
+                        0
+                    };
+                    assert!(*value & 0xff00 == 0);
+                    *value |= next << 8;
+                }
+            } else {
+                tracelimit::warn_ratelimited!(offset, "unhandled config space read");
+                return IoResult::Err(IoError::InvalidRegister);
+            }
+        } else if is_pci_express(capabilities) {
+            // No extended capabilities are implemented. An all-zero header
+            // at 0x100 terminates the (empty) extended capability list.
+            *value = 0;
+        } else if offset == 0x100 {
+            tracelimit::warn_ratelimited!(offset, "unexpected pci express probe");
+            *value = 0x000ffff;
+        } else {
+            tracelimit::warn_ratelimited!(offset, "unhandled extended config space read");
+            return IoResult::Err(IoError::InvalidRegister);
+        }
+
+        IoResult::Ok
+    }
+
+    pub fn write_u32(
+        capabilities: &mut [Box<dyn PciCapability>],
+        offset: u16,
+        val: u32,
+    ) -> IoResult {
+        write_u32_masked(capabilities, offset, val, !0)
+    }
+
+    pub fn write_u32_masked(
+        capabilities: &mut [Box<dyn PciCapability>],
+        offset: u16,
+        val: u32,
+        mask: u32,
+    ) -> IoResult {
+        if offset < 0x100 {
+            if let Some((cap_index, cap_offset)) = find(capabilities, offset) {
+                if mask == !0 {
+                    capabilities[cap_index].write_u32(cap_offset, val);
+                } else {
+                    capabilities[cap_index].write_u32_masked(cap_offset, val, mask);
+                }
+            } else {
+                tracelimit::warn_ratelimited!(offset, value = val, "unhandled config space write");
+                return IoResult::Err(IoError::InvalidRegister);
+            }
+        } else if !is_pci_express(capabilities) {
+            tracelimit::warn_ratelimited!(
+                offset,
+                value = val,
+                "unhandled extended config space write"
+            );
+            return IoResult::Err(IoError::InvalidRegister);
+        }
+
+        IoResult::Ok
+    }
+}
+
 mod inspect_helpers {
     use super::*;
 
@@ -326,18 +436,6 @@ pub fn reset(&mut self) {
         }
     }
 
-    fn get_capability_index_and_offset(&self, offset: u16) -> Option<(usize, u16)> {
-        let mut cap_offset = 0;
-        for i in 0..self.capabilities.len() {
-            let cap_size = self.capabilities[i].len() as u16;
-            if offset < cap_offset + cap_size {
-                return Some((i, offset - cap_offset));
-            }
-            cap_offset += cap_size;
-        }
-        None
-    }
-
     /// Read from the config space. `offset` must be 32-bit aligned.
     pub fn read_u32(&self, offset: u16, value: &mut u32) -> IoResult {
         use cfg_space::HeaderType00;
@@ -386,13 +484,7 @@ pub fn read_u32(&self, offset: u16, value: &mut u32) -> IoResult {
                     | self.hardware_ids.type0_sub_vendor_id as u32
             }
             HeaderType00::EXPANSION_ROM_BASE => 0,
-            HeaderType00::RESERVED_CAP_PTR => {
-                if self.capabilities.is_empty() {
-                    0
-                } else {
-                    0x40
-                }
-            }
+            HeaderType00::RESERVED_CAP_PTR => capability_list::pointer(&self.capabilities),
             HeaderType00::RESERVED => 0,
             HeaderType00::LATENCY_INTERRUPT => {
                 let interrupt_pin = if let Some(intx_interrupt) = &self.intx_interrupt {
@@ -408,35 +500,8 @@ pub fn read_u32(&self, offset: u16, value: &mut u32) -> IoResult {
                 self.state.interrupt_line as u32 | (interrupt_pin as u32) << 8
             }
             // rest of the range is reserved for extended device capabilities
-            _ if (0x40..0x100).contains(&offset) => {
-                if let Some((cap_index, cap_offset)) =
-                    self.get_capability_index_and_offset(offset - 0x40)
-                {
-                    let mut value = self.capabilities[cap_index].read_u32(cap_offset);
-                    if cap_offset == 0 {
-                        let next = if cap_index < self.capabilities.len() - 1 {
-                            offset as u32 + self.capabilities[cap_index].len() as u32
-                        } else {
-                            0
-                        };
-                        assert!(value & 0xff00 == 0);
-                        value |= next << 8;
-                    }
-                    value
-                } else {
-                    tracelimit::warn_ratelimited!(offset, "unhandled config space read");
-                    return IoResult::Err(IoError::InvalidRegister);
-                }
-            }
-            _ if (0x100..0x1000).contains(&offset) => {
-                // TODO: properly support extended pci express configuration space
-                if offset == 0x100 {
-                    tracelimit::warn_ratelimited!(offset, "unexpected pci express probe");
-                    0x000ffff
-                } else {
-                    tracelimit::warn_ratelimited!(offset, "unhandled extended config space read");
-                    return IoResult::Err(IoError::InvalidRegister);
-                }
+            _ if (0x40..0x1000).contains(&offset) => {
+                return capability_list::read_u32(&self.capabilities, offset, value)
             }
             _ => {
                 tracelimit::warn_ratelimited!(offset, "unexpected config space read");
@@ -537,28 +602,8 @@ pub fn write_u32(&mut self, offset: u16, val: u32) -> IoResult {
             // all other base regs are noops
             _ if offset < 0x40 && offset % 4 == 0 => (),
             // rest of the range is reserved for extended device capabilities
-            _ if (0x40..0x100).contains(&offset) => {
-                if let Some((cap_index, cap_offset)) =
-                    self.get_capability_index_and_offset(offset - 0x40)
-                {
-                    self.capabilities[cap_index].write_u32(cap_offset, val);
-                } else {
-                    tracelimit::warn_ratelimited!(
-                        offset,
-                        value = val,
-                        "unhandled config space write"
-                    );
-                    return IoResult::Err(IoError::InvalidRegister);
-                }
-            }
-            _ if (0x100..0x1000).contains(&offset) => {
-                // TODO: properly support extended pci express configuration space
-                tracelimit::warn_ratelimited!(
-                    offset,
-                    value = val,
-                    "unhandled extended config space write"
-                );
-                return IoResult::Err(IoError::InvalidRegister);
+            _ if (0x40..0x1000).contains(&offset) => {
+                return capability_list::write_u32(&mut self.capabilities, offset, val)
             }
             _ => {
                 tracelimit::warn_ratelimited!(offset, value = val, "unexpected config space write");
@@ -575,12 +620,255 @@ pub fn find_bar(&self, address: u64) -> Option<(u8, u16)> {
     }
 }
 
+#[derive(Debug, Inspect)]
+struct ConfigSpaceType1EmulatorState {
+    /// The command register
+    command: cfg_space::Command,
+    /// A read/write register that doesn't matter in virtualized contexts
+    latency_timer: u8,
+    /// Bus number of the bus the bridge is on
+    primary_bus_number: u8,
+    /// Bus number of the bus directly downstream of the bridge
+    secondary_bus_number: u8,
+    /// Highest bus number reachable through the bridge
+    subordinate_bus_number: u8,
+    /// Non-prefetchable memory window base (bits 31:20 in bits 15:4)
+    #[inspect(hex)]
+    memory_base: u16,
+    /// Non-prefetchable memory window limit (bits 31:20 in bits 15:4)
+    #[inspect(hex)]
+    memory_limit: u16,
+    /// Prefetchable memory window base (bits 31:20 in bits 15:4)
+    #[inspect(hex)]
+    prefetch_base: u16,
+    /// Prefetchable memory window limit (bits 31:20 in bits 15:4)
+    #[inspect(hex)]
+    prefetch_limit: u16,
+    /// Prefetchable memory window base (bits 63:32)
+    #[inspect(hex)]
+    prefetch_base_upper: u32,
+    /// Prefetchable memory window limit (bits 63:32)
+    #[inspect(hex)]
+    prefetch_limit_upper: u32,
+    /// See [`ConfigSpaceType0EmulatorState::interrupt_line`]
+    interrupt_line: u8,
+    /// The bridge control register
+    #[inspect(hex)]
+    bridge_control: u16,
+}
+
+impl ConfigSpaceType1EmulatorState {
+    fn new() -> Self {
+        Self {
+            command: cfg_space::Command::new(),
+            latency_timer: 0,
+            primary_bus_number: 0,
+            secondary_bus_number: 0,
+            subordinate_bus_number: 0,
+            memory_base: 0,
+            memory_limit: 0,
+            prefetch_base: 0,
+            prefetch_limit: 0,
+            prefetch_base_upper: 0,
+            prefetch_limit_upper: 0,
+            interrupt_line: 0,
+            bridge_control: 0,
+        }
+    }
+}
+
+/// Mask of the writable bits of the memory base/limit registers. The low 4
+/// bits encode the window's addressing capability.
+const WINDOW_ADDRESS_MASK: u16 = 0xfff0;
+/// Prefetchable memory window base/limit encoding for 64-bit decode.
+const WINDOW_64_BIT: u16 = 0x1;
+
+/// Emulator for the standard Type 1 (PCI-to-PCI bridge) PCI configuration
+/// space header.
+///
+/// The bridge has no BARs and no I/O window. Memory windows are tracked for
+/// the guest's benefit only: routing is performed by bus number.
+#[derive(Inspect)]
+pub struct ConfigSpaceType1Emulator {
+    // Fixed configuration
+    hardware_ids: HardwareIds,
+
+    // Runtime glue
+    #[inspect(with = "|x| inspect::iter_by_key(x.iter().map(|cap| (cap.label(), cap)))")]
+    capabilities: Vec<Box<dyn PciCapability>>,
+
+    // Volatile state
+    state: ConfigSpaceType1EmulatorState,
+}
+
+impl ConfigSpaceType1Emulator {
+    /// Create a new [`ConfigSpaceType1Emulator`]
+    pub fn new(hardware_ids: HardwareIds, capabilities: Vec<Box<dyn PciCapability>>) -> Self {
+        Self {
+            hardware_ids,
+            capabilities,
+            state: ConfigSpaceType1EmulatorState::new(),
+        }
+    }
+
+    /// Resets the configuration space state.
+    pub fn reset(&mut self) {
+        self.state = ConfigSpaceType1EmulatorState::new();
+
+        for cap in &mut self.capabilities {
+            cap.reset();
+        }
+    }
+
+    /// The bus number directly downstream of the bridge.
+    pub fn secondary_bus(&self) -> u8 {
+        self.state.secondary_bus_number
+    }
+
+    /// The highest bus number reachable through the bridge.
+    pub fn subordinate_bus(&self) -> u8 {
+        self.state.subordinate_bus_number
+    }
+
+    /// Read from the config space. `offset` must be 32-bit aligned.
+    pub fn read_u32(&self, offset: u16, value: &mut u32) -> IoResult {
+        use cfg_space::HeaderType01;
+
+        *value = match HeaderType01(offset) {
+            HeaderType01::DEVICE_VENDOR => {
+                (self.hardware_ids.device_id as u32) << 16 | self.hardware_ids.vendor_id as u32
+            }
+            HeaderType01::STATUS_COMMAND => {
+                let status =
+                    cfg_space::Status::new().with_capabilities_list(!self.capabilities.is_empty());
+                (status.into_bits() as u32) << 16 | self.state.command.into_bits() as u32
+            }
+            HeaderType01::CLASS_REVISION => {
+                (u8::from(self.hardware_ids.base_class) as u32) << 24
+                    | (u8::from(self.hardware_ids.sub_class) as u32) << 16
+                    | (u8::from(self.hardware_ids.prog_if) as u32) << 8
+                    | self.hardware_ids.revision_id as u32
+            }
+            HeaderType01::BIST_HEADER => {
+                // header type 1
+                (self.state.latency_timer as u32) << 8 | 0x01 << 16
+            }
+            HeaderType01::BAR0 | HeaderType01::BAR1 => 0,
+            HeaderType01::LATENCY_BUS_NUMBERS => {
+                self.state.primary_bus_number as u32
+                    | (self.state.secondary_bus_number as u32) << 8
+                    | (self.state.subordinate_bus_number as u32) << 16
+            }
+            // no I/O window, no secondary status bits
+            HeaderType01::SEC_STATUS_IO_RANGE => 0,
+            HeaderType01::MEMORY_RANGE => {
+                self.state.memory_base as u32 | (self.state.memory_limit as u32) << 16
+            }
+            HeaderType01::PREFETCH_RANGE => {
+                (self.state.prefetch_base | WINDOW_64_BIT) as u32
+                    | ((self.state.prefetch_limit | WINDOW_64_BIT) as u32) << 16
+            }
+            HeaderType01::PREFETCH_BASE_UPPER => self.state.prefetch_base_upper,
+            HeaderType01::PREFETCH_LIMIT_UPPER => self.state.prefetch_limit_upper,
+            HeaderType01::IO_RANGE_UPPER => 0,
+            HeaderType01::RESERVED_CAP_PTR => capability_list::pointer(&self.capabilities),
+            HeaderType01::EXPANSION_ROM_BASE => 0,
+            HeaderType01::BRIDGE_CONTROL_INTERRUPT => {
+                // no interrupt pin
+                self.state.interrupt_line as u32 | (self.state.bridge_control as u32) << 16
+            }
+            _ if (0x40..0x1000).contains(&offset) => {
+                return capability_list::read_u32(&self.capabilities, offset, value)
+            }
+            _ => {
+                tracelimit::warn_ratelimited!(offset, "unexpected config space read");
+                return IoResult::Err(IoError::InvalidRegister);
+            }
+        };
+
+        IoResult::Ok
+    }
+
+    /// Write to the config space. `offset` must be 32-bit aligned.
+    pub fn write_u32(&mut self, offset: u16, val: u32) -> IoResult {
+        use cfg_space::HeaderType01;
+
+        match HeaderType01(offset) {
+            HeaderType01::STATUS_COMMAND => {
+                let mut command = cfg_space::Command::from_bits(val as u16);
+                if command.into_bits() & !SUPPORTED_COMMAND_BITS != 0 {
+                    tracelimit::warn_ratelimited!(offset, val, "setting invalid command bits");
+                    // still do our best
+                    command =
+                        cfg_space::Command::from_bits(command.into_bits() & SUPPORTED_COMMAND_BITS);
+                };
+                self.state.command = command;
+            }
+            HeaderType01::BIST_HEADER => {
+                // allow writes to the latency timer
+                self.state.latency_timer = (val >> 8) as u8;
+            }
+            HeaderType01::LATENCY_BUS_NUMBERS => {
+                // secondary latency timer is hardwired to 0
+                self.state.primary_bus_number = val as u8;
+                self.state.secondary_bus_number = (val >> 8) as u8;
+                self.state.subordinate_bus_number = (val >> 16) as u8;
+            }
+            HeaderType01::MEMORY_RANGE => {
+                self.state.memory_base = val as u16 & WINDOW_ADDRESS_MASK;
+                self.state.memory_limit = (val >> 16) as u16 & WINDOW_ADDRESS_MASK;
+            }
+            HeaderType01::PREFETCH_RANGE => {
+                self.state.prefetch_base = val as u16 & WINDOW_ADDRESS_MASK;
+                self.state.prefetch_limit = (val >> 16) as u16 & WINDOW_ADDRESS_MASK;
+            }
+            HeaderType01::PREFETCH_BASE_UPPER => self.state.prefetch_base_upper = val,
+            HeaderType01::PREFETCH_LIMIT_UPPER => self.state.prefetch_limit_upper = val,
+            HeaderType01::BRIDGE_CONTROL_INTERRUPT => {
+                self.state.interrupt_line = val as u8;
+                self.state.bridge_control = (val >> 16) as u16;
+            }
+            // all other base regs are noops
+            _ if offset < 0x40 && offset % 4 == 0 => (),
+            _ if (0x40..0x1000).contains(&offset) => {
+                return capability_list::write_u32(&mut self.capabilities, offset, val)
+            }
+            _ => {
+                tracelimit::warn_ratelimited!(offset, value = val, "unexpected config space write");
+                return IoResult::Err(IoError::InvalidRegister);
+            }
+        }
+
+        IoResult::Ok
+    }
+
+    /// Write the bytes selected by `mask` to the config space, leaving the
+    /// other bytes of the register unchanged. `offset` must be 32-bit aligned.
+    ///
+    /// Unlike a read-modify-write via [`Self::read_u32`] and
+    /// [`Self::write_u32`], this does not write back RW1C status bits in
+    /// capabilities that are outside of `mask`.
+    pub fn write_u32_masked(&mut self, offset: u16, val: u32, mask: u32) -> IoResult {
+        if mask != !0 && (0x40..0x1000).contains(&offset) {
+            return capability_list::write_u32_masked(&mut self.capabilities, offset, val, mask);
+        }
+        let mut old = 0;
+        if mask != !0 {
+            if let IoResult::Err(e) = self.read_u32(offset, &mut old) {
+                return IoResult::Err(e);
+            }
+        }
+        self.write_u32(offset, (old & !mask) | (val & mask))
+    }
+}
+
 mod save_restore {
     use super::*;
     use thiserror::Error;
     use vmcore::save_restore::RestoreError;
     use vmcore::save_restore::SaveError;
     use vmcore::save_restore::SaveRestore;
+    use vmcore::save_restore::SavedStateBlob;
 
     mod state {
         use mesh::payload::Protobuf;
@@ -601,6 +889,39 @@ pub struct SavedState {
             #[mesh(5)]
             pub capabilities: Vec<(String, SavedStateBlob)>,
         }
+
+        #[derive(Protobuf, SavedStateRoot)]
+        #[mesh(package = "pci.cfg_space_emu.type1")]
+        pub struct Type1SavedState {
+            #[mesh(1)]
+            pub command: u16,
+            #[mesh(2)]
+            pub latency_timer: u8,
+            #[mesh(3)]
+            pub primary_bus_number: u8,
+            #[mesh(4)]
+            pub secondary_bus_number: u8,
+            #[mesh(5)]
+            pub subordinate_bus_number: u8,
+            #[mesh(6)]
+            pub memory_base: u16,
+            #[mesh(7)]
+            pub memory_limit: u16,
+            #[mesh(8)]
+            pub prefetch_base: u16,
+            #[mesh(9)]
+            pub prefetch_limit: u16,
+            #[mesh(10)]
+            pub prefetch_base_upper: u32,
+            #[mesh(11)]
+            pub prefetch_limit_upper: u32,
+            #[mesh(12)]
+            pub interrupt_line: u8,
+            #[mesh(13)]
+            pub bridge_control: u16,
+            #[mesh(14)]
+            pub capabilities: Vec<(String, SavedStateBlob)>,
+        }
     }
 
     #[derive(Debug, Error)]
@@ -627,14 +948,7 @@ fn save(&mut self) -> Result<Self::SavedState, SaveError> {
                 base_addresses,
                 interrupt_line,
                 latency_timer,
-                capabilities: self
-                    .capabilities
-                    .iter_mut()
-                    .map(|cap| {
-                        let id = cap.label().to_owned();
-                        Ok((id, cap.save()?))
-                    })
-                    .collect::<Result<_, _>>()?,
+                capabilities: save_capabilities(&mut self.capabilities)?,
             };
 
             Ok(saved_state)
@@ -663,28 +977,197 @@ fn restore(&mut self, state: Self::SavedState) -> Result<(), RestoreError> {
             }
 
             self.sync_command_register(self.state.command);
-            for (id, entry) in capabilities {
-                tracing::debug!(save_id = id.as_str(), "restoring pci capability");
-
-                // yes, yes, this is O(n^2), but devices never have more than a
-                // handful of caps, so it's totally fine.
-                let mut restored = false;
-                for cap in self.capabilities.iter_mut() {
-                    if cap.label() == id {
-                        cap.restore(entry)?;
-                        restored = true;
-                        break;
-                    }
-                }
+            restore_capabilities(&mut self.capabilities, capabilities)
+        }
+    }
 
-                if !restored {
-                    return Err(RestoreError::InvalidSavedState(
-                        ConfigSpaceRestoreError::InvalidCap(id).into(),
-                    ));
+    fn save_capabilities(
+        capabilities: &mut [Box<dyn PciCapability>],
+    ) -> Result<Vec<(String, SavedStateBlob)>, SaveError> {
+        capabilities
+            .iter_mut()
+            .map(|cap| {
+                let id = cap.label().to_owned();
+                Ok((id, cap.save()?))
+            })
+            .collect()
+    }
+
+    fn restore_capabilities(
+        capabilities: &mut [Box<dyn PciCapability>],
+        saved: Vec<(String, SavedStateBlob)>,
+    ) -> Result<(), RestoreError> {
+        for (id, entry) in saved {
+            tracing::debug!(save_id = id.as_str(), "restoring pci capability");
+
+            // yes, yes, this is O(n^2), but devices never have more than a
+            // handful of caps, so it's totally fine.
+            let mut restored = false;
+            for cap in capabilities.iter_mut() {
+                if cap.label() == id {
+                    cap.restore(entry)?;
+                    restored = true;
+                    break;
                 }
             }
 
-            Ok(())
+            if !restored {
+                return Err(RestoreError::InvalidSavedState(
+                    ConfigSpaceRestoreError::InvalidCap(id).into(),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    impl SaveRestore for ConfigSpaceType1Emulator {
+        type SavedState = state::Type1SavedState;
+
+        fn save(&mut self) -> Result<Self::SavedState, SaveError> {
+            let ConfigSpaceType1EmulatorState {
+                command,
+                latency_timer,
+                primary_bus_number,
+                secondary_bus_number,
+                subordinate_bus_number,
+                memory_base,
+                memory_limit,
+                prefetch_base,
+                prefetch_limit,
+                prefetch_base_upper,
+                prefetch_limit_upper,
+                interrupt_line,
+                bridge_control,
+            } = self.state;
+
+            let saved_state = state::Type1SavedState {
+                command: command.into_bits(),
+                latency_timer,
+                primary_bus_number,
+                secondary_bus_number,
+                subordinate_bus_number,
+                memory_base,
+                memory_limit,
+                prefetch_base,
+                prefetch_limit,
+                prefetch_base_upper,
+                prefetch_limit_upper,
+                interrupt_line,
+                bridge_control,
+                capabilities: save_capabilities(&mut self.capabilities)?,
+            };
+
+            Ok(saved_state)
         }
+
+        fn restore(&mut self, state: Self::SavedState) -> Result<(), RestoreError> {
+            let state::Type1SavedState {
+                command,
+                latency_timer,
+                primary_bus_number,
+                secondary_bus_number,
+                subordinate_bus_number,
+                memory_base,
+                memory_limit,
+                prefetch_base,
+                prefetch_limit,
+                prefetch_base_upper,
+                prefetch_limit_upper,
+                interrupt_line,
+                bridge_control,
+                capabilities,
+            } = state;
+
+            if command & !SUPPORTED_COMMAND_BITS != 0 {
+                return Err(RestoreError::InvalidSavedState(
+                    ConfigSpaceRestoreError::InvalidConfigBits.into(),
+                ));
+            }
+
+            self.state = ConfigSpaceType1EmulatorState {
+                command: cfg_space::Command::from_bits(command),
+                latency_timer,
+                primary_bus_number,
+                secondary_bus_number,
+                subordinate_bus_number,
+                memory_base,
+                memory_limit,
+                prefetch_base,
+                prefetch_limit,
+                prefetch_base_upper,
+                prefetch_limit_upper,
+                interrupt_line,
+                bridge_control,
+            };
+
+            restore_capabilities(&mut self.capabilities, capabilities)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capabilities::pci_express::PciExpressCapability;
+    use crate::spec::caps::pci_express::DevicePortType;
+    use crate::spec::hwid::ClassCode;
+    use crate::spec::hwid::ProgrammingInterface;
+    use crate::spec::hwid::Subclass;
+
+    fn root_port() -> ConfigSpaceType1Emulator {
+        ConfigSpaceType1Emulator::new(
+            HardwareIds {
+                vendor_id: 0x1111,
+                device_id: 0x2222,
+                revision_id: 1,
+                prog_if: ProgrammingInterface::NONE,
+                sub_class: Subclass::BRIDGE_PCI_TO_PCI,
+                base_class: ClassCode::BRIDGE,
+                type0_sub_vendor_id: 0,
+                type0_sub_system_id: 0,
+            },
+            vec![Box::new(PciExpressCapability::new(
+                DevicePortType::ROOT_PORT,
+                None,
+            ))],
+        )
+    }
+
+    fn read(emu: &ConfigSpaceType1Emulator, offset: u16) -> u32 {
+        let mut value = 0;
+        emu.read_u32(offset, &mut value).unwrap();
+        value
+    }
+
+    #[test]
+    fn type1_header() {
+        let mut emu = root_port();
+        assert_eq!(read(&emu, 0), 0x2222_1111);
+        assert_eq!(read(&emu, 0x8), 0x0604_0001);
+        assert_eq!(read(&emu, 0xc), 0x0001_0000);
+        assert_eq!(read(&emu, 0x34), 0x40);
+        // pci express capability, end of list
+        assert_eq!(read(&emu, 0x40) & 0xffff, 0x0010);
+        // empty extended capability list
+        assert_eq!(read(&emu, 0x100), 0);
+
+        emu.write_u32(0x18, 0x00_05_04_00).unwrap();
+        assert_eq!(emu.secondary_bus(), 4);
+        assert_eq!(emu.subordinate_bus(), 5);
+
+        emu.write_u32(0x20, 0xffff_ffff).unwrap();
+        assert_eq!(read(&emu, 0x20), 0xfff0_fff0);
+        emu.write_u32(0x24, 0).unwrap();
+        assert_eq!(read(&emu, 0x24), 0x0001_0001);
+        // no BARs or I/O window
+        emu.write_u32(0x10, 0xffff_ffff).unwrap();
+        emu.write_u32(0x1c, 0xffff_ffff).unwrap();
+        assert_eq!(read(&emu, 0x10), 0);
+        assert_eq!(read(&emu, 0x1c), 0);
+
+        emu.reset();
+        assert_eq!(emu.secondary_bus(), 0);
+        assert_eq!(read(&emu, 0x20), 0);
     }
 }
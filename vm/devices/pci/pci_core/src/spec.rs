@@ -127,6 +127,7 @@ pub enum Subclass: u8 {
             // Other values: 0x02 - 0x0A
             BRIDGE_HOST = 0x00,
             BRIDGE_ISA = 0x01,
+            BRIDGE_PCI_TO_PCI = 0x04,
             BRIDGE_OTHER = 0x80,
 
             // Base System Peripheral (Class code: 0x08)
@@ -237,6 +238,52 @@ pub enum HeaderType00: u16 {
 
     pub const HEADER_TYPE_00_SIZE: u16 = 0x40;
 
+    open_enum::open_enum! {
+        /// Offsets into the type 01h (PCI-to-PCI bridge) configuration space
+        /// header.
+        ///
+        /// Table pulled from <https://wiki.osdev.org/PCI>
+        ///
+        /// | Offset | Bits 31-24                 | Bits 23-16          | Bits 15-8           | Bits 7-0             |
+        /// |--------|----------------------------|---------------------|---------------------|--------------------- |
+        /// | 0x0    | Device ID                  |                     | Vendor ID           |                      |
+        /// | 0x4    | Status                     |                     | Command             |                      |
+        /// | 0x8    | Class code                 |                     |                     | Revision ID          |
+        /// | 0xC    | BIST                       | Header type         | Latency Timer       | Cache Line Size      |
+        /// | 0x10   | Base address #0 (BAR0)     |                     |                     |                      |
+        /// | 0x14   | Base address #1 (BAR1)     |                     |                     |                      |
+        /// | 0x18   | Secondary Latency Timer    | Subordinate Bus     | Secondary Bus       | Primary Bus          |
+        /// | 0x1C   | Secondary Status           |                     | I/O Limit           | I/O Base             |
+        /// | 0x20   | Memory Limit               |                     | Memory Base         |                      |
+        /// | 0x24   | Prefetchable Memory Limit  |                     | Prefetchable Base   |                      |
+        /// | 0x28   | Prefetchable Base Upper 32 |                     |                     |                      |
+        /// | 0x2C   | Prefetchable Limit Upper 32|                     |                     |                      |
+        /// | 0x30   | I/O Limit Upper 16         |                     | I/O Base Upper 16   |                      |
+        /// | 0x34   | Reserved                   |                     |                     | Capabilities Pointer |
+        /// | 0x38   | Expansion ROM base address |                     |                     |                      |
+        /// | 0x3C   | Bridge Control             |                     | Interrupt PIN       | Interrupt Line       |
+        pub enum HeaderType01: u16 {
+            DEVICE_VENDOR       = 0x00,
+            STATUS_COMMAND      = 0x04,
+            CLASS_REVISION      = 0x08,
+            BIST_HEADER         = 0x0C,
+            BAR0                = 0x10,
+            BAR1                = 0x14,
+            LATENCY_BUS_NUMBERS = 0x18,
+            SEC_STATUS_IO_RANGE = 0x1C,
+            MEMORY_RANGE        = 0x20,
+            PREFETCH_RANGE      = 0x24,
+            PREFETCH_BASE_UPPER = 0x28,
+            PREFETCH_LIMIT_UPPER = 0x2C,
+            IO_RANGE_UPPER      = 0x30,
+            RESERVED_CAP_PTR    = 0x34,
+            EXPANSION_ROM_BASE  = 0x38,
+            BRIDGE_CONTROL_INTERRUPT = 0x3C,
+        }
+    }
+
+    pub const HEADER_TYPE_01_SIZE: u16 = 0x40;
+
     /// BAR in-band encoding bits.
     ///
     /// The low bits of the BAR are not actually part of the address.
@@ -338,11 +385,55 @@ pub mod caps {
         /// variants on an as-needed basis!
         pub enum CapabilityId: u8 {
             #![allow(missing_docs)] // self explanatory variants
+            MSI             = 0x05,
             VENDOR_SPECIFIC = 0x09,
+            PCI_EXPRESS     = 0x10,
             MSIX            = 0x11,
         }
     }
 
+    /// MSI
+    #[allow(missing_docs)] // primarily enums/structs with self-explanatory variants
+    pub mod msi {
+        use bitfield_struct::bitfield;
+
+        open_enum::open_enum! {
+            /// Offsets into the 64-bit MSI Capability Structure (without
+            /// per-vector masking)
+            ///
+            /// | Offset    | Bits 31-16            | Bits 15-0              |
+            /// |-----------|-----------------------|------------------------|
+            /// | Cap + 0x0 | Message Control       | Next Pointer, ID (0x5) |
+            /// | Cap + 0x4 | Message Address       |                        |
+            /// | Cap + 0x8 | Message Upper Address |                        |
+            /// | Cap + 0xC | Reserved              | Message Data           |
+            pub enum MsiCapabilityHeader: u16 {
+                CONTROL_CAPS = 0x00,
+                MSG_ADDR_LO  = 0x04,
+                MSG_ADDR_HI  = 0x08,
+                MSG_DATA     = 0x0C,
+            }
+        }
+
+        /// Length of a 64-bit MSI Capability Structure without per-vector
+        /// masking.
+        pub const MSI_CAPABILITY_LEN: u16 = 0x10;
+
+        /// MSI Message Control Register
+        #[bitfield(u16)]
+        pub struct MsiMessageControl {
+            pub enable: bool,
+            #[bits(3)]
+            pub multiple_message_capable: u8,
+            #[bits(3)]
+            pub multiple_message_enable: u8,
+            pub address_64bit_capable: bool,
+            pub per_vector_masking_capable: bool,
+            #[bits(7)]
+            _reserved: u8,
+        }
+    }
+
     /// MSI-X
     #[allow(missing_docs)] // primarily enums/structs with self-explanatory variants
     pub mod msix {
@@ -373,4 +464,182 @@ pub enum MsixTableEntryIdx: u16 {
             }
         }
     }
+
+    /// PCI Express
+    ///
+    /// Sources: PCI Express Base Specification 4.0 - Section 7.5.3
+    #[allow(missing_docs)] // primarily enums/structs with self-explanatory variants
+    pub mod pci_express {
+        use bitfield_struct::bitfield;
+        use inspect::Inspect;
+
+        open_enum::open_enum! {
+            /// Offsets into the PCI Express Capability Structure
+            ///
+            /// | Offset     | Bits 31-16                  | Bits 15-0                  |
+            /// |------------|-----------------------------|----------------------------|
+            /// | Cap + 0x00 | PCI Express Capabilities    | Next Pointer, ID (0x10)    |
+            /// | Cap + 0x04 | Device Capabilities         |                            |
+            /// | Cap + 0x08 | Device Status               | Device Control             |
+            /// | Cap + 0x0C | Link Capabilities           |                            |
+            /// | Cap + 0x10 | Link Status                 | Link Control               |
+            /// | Cap + 0x14 | Slot Capabilities           |                            |
+            /// | Cap + 0x18 | Slot Status                 | Slot Control               |
+            /// | Cap + 0x1C | Root Capabilities           | Root Control               |
+            /// | Cap + 0x20 | Root Status                 |                            |
+            /// | Cap + 0x24 | Device Capabilities 2       |                            |
+            /// | Cap + 0x28 | Device Status 2             | Device Control 2           |
+            /// | Cap + 0x2C | Link Capabilities 2         |                            |
+            /// | Cap + 0x30 | Link Status 2               | Link Control 2             |
+            /// | Cap + 0x34 | Slot Capabilities 2         |                            |
+            /// | Cap + 0x38 | Slot Status 2               | Slot Control 2             |
+            pub enum PciExpressCapabilityHeader: u16 {
+                PCIE_CAPS        = 0x00,
+                DEVICE_CAPS      = 0x04,
+                DEVICE_CTL_STS   = 0x08,
+                LINK_CAPS        = 0x0C,
+                LINK_CTL_STS     = 0x10,
+                SLOT_CAPS        = 0x14,
+                SLOT_CTL_STS     = 0x18,
+                ROOT_CTL_CAPS    = 0x1C,
+                ROOT_STS         = 0x20,
+                DEVICE_CAPS_2    = 0x24,
+                DEVICE_CTL_STS_2 = 0x28,
+                LINK_CAPS_2      = 0x2C,
+                LINK_CTL_STS_2   = 0x30,
+                SLOT_CAPS_2      = 0x34,
+                SLOT_CTL_STS_2   = 0x38,
+            }
+        }
+
+        /// Length of a version 2 PCI Express Capability Structure.
+        pub const PCI_EXPRESS_CAPABILITY_LEN: u16 = 0x3C;
+
+        open_enum::open_enum! {
+            /// The Device/Port Type field of the PCI Express Capabilities
+            /// register.
+            #[derive(Inspect)]
+            #[inspect(debug)]
+            pub enum DevicePortType: u8 {
+                ENDPOINT                         = 0b0000,
+                LEGACY_ENDPOINT                  = 0b0001,
+                ROOT_PORT                        = 0b0100,
+                UPSTREAM_SWITCH_PORT             = 0b0101,
+                DOWNSTREAM_SWITCH_PORT           = 0b0110,
+                PCIE_TO_PCI_BRIDGE               = 0b0111,
+                PCI_TO_PCIE_BRIDGE               = 0b1000,
+                ROOT_COMPLEX_INTEGRATED_ENDPOINT = 0b1001,
+                ROOT_COMPLEX_EVENT_COLLECTOR     = 0b1010,
+            }
+        }
+
+        /// PCI Express Capabilities Register
+        #[bitfield(u16)]
+        pub struct PciExpressCapabilities {
+            #[bits(4)]
+            pub capability_version: u8,
+            #[bits(4)]
+            pub device_port_type: u8,
+            pub slot_implemented: bool,
+            #[bits(5)]
+            pub interrupt_message_number: u8,
+            #[bits(2)]
+            _reserved: u8,
+        }
+
+        /// Link Capabilities Register
+        #[bitfield(u32)]
+        pub struct LinkCapabilities {
+            #[bits(4)]
+            pub max_link_speed: u8,
+            #[bits(6)]
+            pub max_link_width: u8,
+            #[bits(2)]
+            pub aspm_support: u8,
+            #[bits(3)]
+            pub l0s_exit_latency: u8,
+            #[bits(3)]
+            pub l1_exit_latency: u8,
+            pub clock_power_management: bool,
+            pub surprise_down_error_reporting: bool,
+            pub data_link_layer_link_active_reporting: bool,
+            pub link_bandwidth_notification: bool,
+            pub aspm_optionality_compliance: bool,
+            _reserved: bool,
+            #[bits(8)]
+            pub port_number: u8,
+        }
+
+        /// Link Status Register
+        #[bitfield(u16)]
+        pub struct LinkStatus {
+            #[bits(4)]
+            pub current_link_speed: u8,
+            #[bits(6)]
+            pub negotiated_link_width: u8,
+            _undefined: bool,
+            pub link_training: bool,
+            pub slot_clock_configuration: bool,
+            pub data_link_layer_link_active: bool,
+            pub link_bandwidth_management_status: bool,
+            pub link_autonomous_bandwidth_status: bool,
+        }
+
+        /// Slot Capabilities Register
+        #[bitfield(u32)]
+        pub struct SlotCapabilities {
+            pub attention_button_present: bool,
+            pub power_controller_present: bool,
+            pub mrl_sensor_present: bool,
+            pub attention_indicator_present: bool,
+            pub power_indicator_present: bool,
+            pub hot_plug_surprise: bool,
+            pub hot_plug_capable: bool,
+            #[bits(8)]
+            pub slot_power_limit_value: u8,
+            #[bits(2)]
+            pub slot_power_limit_scale: u8,
+            pub electromechanical_interlock_present: bool,
+            pub no_command_completed_support: bool,
+            #[bits(13)]
+            pub physical_slot_number: u16,
+        }
+
+        /// Slot Control Register
+        #[bitfield(u16)]
+        pub struct SlotControl {
+            pub attention_button_pressed_enable: bool,
+            pub power_fault_detected_enable: bool,
+            pub mrl_sensor_changed_enable: bool,
+            pub presence_detect_changed_enable: bool,
+            pub command_completed_interrupt_enable: bool,
+            pub hot_plug_interrupt_enable: bool,
+            #[bits(2)]
+            pub attention_indicator_control: u8,
+            #[bits(2)]
+            pub power_indicator_control: u8,
+            pub power_controller_control: bool,
+            pub electromechanical_interlock_control: bool,
+            pub data_link_layer_state_changed_enable: bool,
+            pub auto_slot_power_limit_disable: bool,
+            #[bits(2)]
+            _reserved: u8,
+        }
+
+        /// Slot Status Register
+        #[bitfield(u16)]
+        pub struct SlotStatus {
+            pub attention_button_pressed: bool,
+            pub power_fault_detected: bool,
+            pub mrl_sensor_changed: bool,
+            pub presence_detect_changed: bool,
+            pub command_completed: bool,
+            pub mrl_sensor_state: bool,
+            pub presence_detect_state: bool,
+            pub electromechanical_interlock_status: bool,
+            pub data_link_layer_state_changed: bool,
+            #[bits(7)]
+            _reserved: u8,
+        }
+    }
 }
@@ -12,6 +12,7 @@
 use guestmem::DoorbellRegistration;
 use guestmem::GuestMemory;
 use guestmem::MemoryMapper;
+use guestmem::SharedGuestRam;
 use pci_core::msi::RegisterMsi;
 use std::sync::Arc;
 use vm_resource::kind::PciDeviceHandleKind;
@@ -45,4 +46,8 @@ pub struct ResolvePciDeviceHandleParams<'a> {
     pub doorbell_registration: Option<Arc<dyn DoorbellRegistration>>,
     /// An object with which to register shared memory regions.
     pub shared_mem_mapper: Option<&'a dyn MemoryMapper>,
+    /// An object providing the OS objects backing guest RAM, for devices
+    /// implemented in another process. `None` if guest RAM cannot be shared,
+    /// such as when RAM can be hot-added.
+    pub shared_guest_ram: Option<&'a dyn SharedGuestRam>,
 }
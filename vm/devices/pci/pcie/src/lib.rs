@@ -0,0 +1,1104 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! PCI Express topology emulation.
+//!
+//! [`GenericPcieRootComplex`] is a [`ChipsetDevice`] that exposes an ECAM
+//! (Enhanced Configuration Access Mechanism) MMIO region, and a set of PCI
+//! Express root ports on its root bus.
+//!
+//! Each root port is a PCI-to-PCI bridge with a single downstream link, which
+//! can be connected to a single [`GenericPciBusDevice`]. Config space accesses
+//! are routed to downstream devices based on the bus numbers the guest assigns
+//! to each root port.
+//!
+//! Root ports support PCI Express native hot-plug. Devices can be hot-added to
+//! an empty root port at runtime (see
+//! [`GenericPcieRootComplex::hot_add_device`]), in which case the root complex
+//! owns the device and forwards MMIO accesses and state changes to it. Slot
+//! events are signaled to the guest via each root port's MSI.
+
+#![warn(missing_docs)]
+
+use chipset_device::io::IoError;
+use chipset_device::io::IoResult;
+use chipset_device::mmio::ControlMmioIntercept;
+use chipset_device::mmio::MmioIntercept;
+use chipset_device::mmio::RegisterMmioIntercept;
+use chipset_device::poll_device::PollDevice;
+use chipset_device::ChipsetDevice;
+use chipset_device_resources::ErasedChipsetDevice;
+use inspect::Inspect;
+use inspect::InspectMut;
+use parking_lot::Mutex;
+use pci_bus::GenericPciBusDevice;
+use pci_core::capabilities::msi::MsiEmulator;
+use pci_core::capabilities::pci_express::PciExpressCapability;
+use pci_core::capabilities::pci_express::PciExpressLink;
+use pci_core::cfg_space_emu::ConfigSpaceType1Emulator;
+use pci_core::msi::RegisterMsi;
+use pci_core::spec::caps::pci_express::DevicePortType;
+use pci_core::spec::hwid::ClassCode;
+use pci_core::spec::hwid::HardwareIds;
+use pci_core::spec::hwid::ProgrammingInterface;
+use pci_core::spec::hwid::Subclass;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+use std::task::Context;
+use std::task::Waker;
+use thiserror::Error;
+use vmcore::device_state::ChangeDeviceState;
+
+/// The size of the ECAM region for a single bus.
+pub const ECAM_BUS_SIZE: u64 = 1 << 20;
+
+/// The maximum number of root ports on the root bus.
+pub const MAX_ROOT_PORTS: usize = 32;
+
+const ROOT_PORT_VENDOR_ID: u16 = 0x1414;
+const ROOT_PORT_DEVICE_ID: u16 = 0xc030;
+
+/// Definition of a root port attached to a [`GenericPcieRootComplex`].
+pub struct GenericPcieRootPortDefinition {
+    /// The name of the root port, used to attach downstream devices.
+    pub name: Arc<str>,
+}
+
+/// An error returned when hot-plugging a device into a
+/// [`GenericPcieRootComplex`].
+#[derive(Debug, Error)]
+#[allow(missing_docs)] // self explanatory variants
+pub enum HotPlugError {
+    #[error("unknown pcie root port {0}")]
+    UnknownPort(String),
+    #[error("pcie root port {port} is already occupied by {device}")]
+    PortOccupied { port: String, device: Arc<str> },
+    #[error("pcie root port {0} is empty")]
+    PortEmpty(String),
+    #[error("device {device} on pcie root port {port} cannot be hot-removed")]
+    NotHotPlugged { port: String, device: Arc<str> },
+}
+
+type HotPlugMmioRegion = Arc<Mutex<Box<dyn ControlMmioIntercept>>>;
+
+/// A [`RegisterMmioIntercept`] implementation for devices that will be
+/// hot-plugged into a [`GenericPcieRootComplex`].
+///
+/// Regions are registered on behalf of the root complex, which routes accesses
+/// to them to the device once it has been added with
+/// [`GenericPcieRootComplex::hot_add_device`].
+pub struct HotPlugMmio {
+    register_mmio: Arc<Mutex<Box<dyn RegisterMmioIntercept + Send>>>,
+    regions: Vec<HotPlugMmioRegion>,
+}
+
+impl RegisterMmioIntercept for HotPlugMmio {
+    fn new_io_region(&mut self, region_name: &str, len: u64) -> Box<dyn ControlMmioIntercept> {
+        let region = Arc::new(Mutex::new(
+            self.register_mmio.lock().new_io_region(region_name, len),
+        ));
+        self.regions.push(region.clone());
+        Box::new(HotPlugMmioControl {
+            region_name: region_name.into(),
+            region,
+        })
+    }
+}
+
+/// The device-facing handle to a [`HotPlugMmio`] region.
+struct HotPlugMmioControl {
+    region_name: Box<str>,
+    region: HotPlugMmioRegion,
+}
+
+impl ControlMmioIntercept for HotPlugMmioControl {
+    fn region_name(&self) -> &str {
+        &self.region_name
+    }
+
+    fn map(&mut self, addr: u64) {
+        self.region.lock().map(addr)
+    }
+
+    fn unmap(&mut self) {
+        self.region.lock().unmap()
+    }
+
+    fn addr(&self) -> Option<u64> {
+        self.region.lock().addr()
+    }
+
+    fn len(&self) -> u64 {
+        self.region.lock().len()
+    }
+
+    fn offset_of(&self, addr: u64) -> Option<u64> {
+        self.region.lock().offset_of(addr)
+    }
+}
+
+impl Drop for HotPlugMmioControl {
+    fn drop(&mut self) {
+        // The region is registered to the root complex, which outlives the
+        // device. Make sure it doesn't keep decoding accesses.
+        self.region.lock().unmap()
+    }
+}
+
+/// A device attached to the downstream link of a root port.
+enum Downstream {
+    /// A device attached at construction time, which is owned by the chipset.
+    Static(Box<dyn GenericPciBusDevice>),
+    /// A hot-plugged device, which is owned by the root complex.
+    HotPlugged {
+        device: ErasedChipsetDevice,
+        regions: Vec<HotPlugMmioRegion>,
+    },
+}
+
+impl Downstream {
+    fn pci_cfg_read(&mut self, offset: u16, value: &mut u32) -> Option<IoResult> {
+        match self {
+            Downstream::Static(dev) => dev.pci_cfg_read(offset, value),
+            Downstream::HotPlugged { device, .. } => {
+                Some(device.supports_pci()?.pci_cfg_read(offset, value))
+            }
+        }
+    }
+
+    fn pci_cfg_write(&mut self, offset: u16, value: u32) -> Option<IoResult> {
+        match self {
+            Downstream::Static(dev) => dev.pci_cfg_write(offset, value),
+            Downstream::HotPlugged { device, .. } => {
+                Some(device.supports_pci()?.pci_cfg_write(offset, value))
+            }
+        }
+    }
+}
+
+#[derive(Inspect)]
+struct RootPort {
+    #[inspect(skip)]
+    name: Arc<str>,
+    cfg_space: ConfigSpaceType1Emulator,
+    #[inspect(skip)]
+    link: PciExpressLink,
+    #[inspect(with = "|x| x.as_ref().map(|(name, _)| name.as_ref())")]
+    downstream: Option<(Arc<str>, Downstream)>,
+}
+
+impl RootPort {
+    fn new(name: Arc<str>, slot_number: u16, register_msi: &mut dyn RegisterMsi) -> Self {
+        let (msi, msi_cap) = MsiEmulator::new(register_msi);
+        let pcie_cap = PciExpressCapability::new(DevicePortType::ROOT_PORT, Some(slot_number))
+            .with_hot_plug(msi.interrupt());
+        let link = pcie_cap.link();
+        let cfg_space = ConfigSpaceType1Emulator::new(
+            HardwareIds {
+                vendor_id: ROOT_PORT_VENDOR_ID,
+                device_id: ROOT_PORT_DEVICE_ID,
+                revision_id: 0,
+                prog_if: ProgrammingInterface::NONE,
+                sub_class: Subclass::BRIDGE_PCI_TO_PCI,
+                base_class: ClassCode::BRIDGE,
+                type0_sub_vendor_id: 0,
+                type0_sub_system_id: 0,
+            },
+            vec![Box::new(pcie_cap), Box::new(msi_cap)],
+        );
+        Self {
+            name,
+            cfg_space,
+            link,
+            downstream: None,
+        }
+    }
+}
+
+/// A generic PCI Express root complex.
+///
+/// Root ports are placed at device numbers `0..n` on bus `start_bus`.
+#[derive(InspectMut)]
+pub struct GenericPcieRootComplex {
+    // Fixed configuration
+    #[inspect(hex)]
+    ecam_base: u64,
+    start_bus: u8,
+    end_bus: u8,
+    #[inspect(skip)]
+    ecam_region: [(&'static str, RangeInclusive<u64>); 1],
+
+    // Runtime glue
+    #[inspect(with = "|x| inspect::iter_by_key(x.iter().map(|port| (port.name.as_ref(), port)))")]
+    ports: Vec<RootPort>,
+    #[inspect(skip)]
+    register_mmio: Arc<Mutex<Box<dyn RegisterMmioIntercept + Send>>>,
+    #[inspect(skip)]
+    waker: Option<Waker>,
+
+    // Volatile state
+    running: bool,
+}
+
+impl GenericPcieRootComplex {
+    /// Create a new root complex decoding buses `start_bus..=end_bus` via an
+    /// ECAM region located at `ecam_base`.
+    ///
+    /// `register_msi` is used to allocate the root ports' MSIs for hot-plug
+    /// events, and `register_mmio` to register the MMIO regions of
+    /// hot-plugged devices.
+    pub fn new(
+        ecam_base: u64,
+        start_bus: u8,
+        end_bus: u8,
+        ports: Vec<GenericPcieRootPortDefinition>,
+        register_msi: &mut dyn RegisterMsi,
+        register_mmio: Box<dyn RegisterMmioIntercept + Send>,
+    ) -> Self {
+        assert!(start_bus <= end_bus);
+        assert!(ports.len() <= MAX_ROOT_PORTS, "too many root ports");
+
+        let ecam_size = (end_bus - start_bus) as u64 * ECAM_BUS_SIZE + ECAM_BUS_SIZE;
+        let ports = ports
+            .into_iter()
+            .enumerate()
+            .map(|(i, def)| RootPort::new(def.name, i as u16, register_msi))
+            .collect();
+
+        Self {
+            ecam_base,
+            start_bus,
+            end_bus,
+            ecam_region: [("ecam", ecam_base..=ecam_base + ecam_size - 1)],
+            ports,
+            register_mmio: Arc::new(Mutex::new(register_mmio)),
+            waker: None,
+            running: false,
+        }
+    }
+
+    /// Try to attach a device to the downstream link of the root port named
+    /// `port`, returning (device, existing_device_name) if the port is already
+    /// occupied.
+    ///
+    /// Panics if no root port named `port` exists.
+    pub fn add_pcie_device<D: GenericPciBusDevice>(
+        &mut self,
+        port: &str,
+        name: impl AsRef<str>,
+        dev: D,
+    ) -> Result<(), (D, Arc<str>)> {
+        let port = self
+            .ports
+            .iter_mut()
+            .find(|p| p.name.as_ref() == port)
+            .unwrap_or_else(|| panic!("unknown pcie root port {port}"));
+
+        if let Some((name, _)) = &port.downstream {
+            return Err((dev, name.clone()));
+        }
+
+        port.downstream = Some((name.as_ref().into(), Downstream::Static(Box::new(dev))));
+        port.link.set_active(true);
+        Ok(())
+    }
+
+    /// Returns an object with which a device that will be hot-plugged into
+    /// this root complex must register its MMIO regions.
+    pub fn hot_plug_mmio(&self) -> HotPlugMmio {
+        HotPlugMmio {
+            register_mmio: self.register_mmio.clone(),
+            regions: Vec::new(),
+        }
+    }
+
+    /// Hot-add `device` to the empty root port named `port`, and notify the
+    /// guest.
+    ///
+    /// The device's MMIO regions must have been registered via `mmio`, which
+    /// was obtained from [`Self::hot_plug_mmio`]. The device is started if the
+    /// root complex is running.
+    pub fn hot_add_device(
+        &mut self,
+        port: &str,
+        name: impl AsRef<str>,
+        mut device: ErasedChipsetDevice,
+        mmio: HotPlugMmio,
+    ) -> Result<(), HotPlugError> {
+        let running = self.running;
+        let root_port = self.port_mut(port)?;
+
+        if let Some((name, _)) = &root_port.downstream {
+            return Err(HotPlugError::PortOccupied {
+                port: port.into(),
+                device: name.clone(),
+            });
+        }
+
+        if running {
+            device.start();
+        }
+        root_port.downstream = Some((
+            name.as_ref().into(),
+            Downstream::HotPlugged {
+                device,
+                regions: mmio.regions,
+            },
+        ));
+        root_port.link.hot_plug(true);
+
+        // Make sure the new device gets polled.
+        if let Some(waker) = &self.waker {
+            waker.wake_by_ref();
+        }
+        Ok(())
+    }
+
+    /// Ask the guest to release the hot-plugged device attached to the root
+    /// port named `port` by pressing the slot's attention button.
+    ///
+    /// The guest powers off the slot once it has quiesced the device (see
+    /// [`Self::is_slot_powered_off`]), after which the device can be removed
+    /// with [`Self::hot_remove_device`].
+    pub fn request_hot_remove(&mut self, port: &str) -> Result<(), HotPlugError> {
+        let root_port = self.hot_plugged_port(port)?;
+        root_port.link.press_attention_button();
+        Ok(())
+    }
+
+    /// Returns whether the guest has powered off the slot of the root port
+    /// named `port`.
+    pub fn is_slot_powered_off(&mut self, port: &str) -> Result<bool, HotPlugError> {
+        Ok(self.port_mut(port)?.link.is_powered_off())
+    }
+
+    /// Remove the hot-plugged device attached to the root port named `port`,
+    /// and notify the guest.
+    ///
+    /// This is a surprise removal unless the guest has already released the
+    /// device (see [`Self::request_hot_remove`]). The device is returned so
+    /// that the caller can stop it before dropping it.
+    pub fn hot_remove_device(&mut self, port: &str) -> Result<ErasedChipsetDevice, HotPlugError> {
+        let root_port = self.port_mut(port)?;
+        match root_port.downstream.take() {
+            Some((_, Downstream::HotPlugged { device, regions: _ })) => {
+                root_port.link.hot_plug(false);
+                Ok(device)
+            }
+            Some((name, downstream @ Downstream::Static(_))) => {
+                root_port.downstream = Some((name.clone(), downstream));
+                Err(HotPlugError::NotHotPlugged {
+                    port: port.into(),
+                    device: name,
+                })
+            }
+            None => Err(HotPlugError::PortEmpty(port.into())),
+        }
+    }
+
+    fn port_mut(&mut self, port: &str) -> Result<&mut RootPort, HotPlugError> {
+        self.ports
+            .iter_mut()
+            .find(|p| p.name.as_ref() == port)
+            .ok_or_else(|| HotPlugError::UnknownPort(port.into()))
+    }
+
+    /// Returns the root port named `port` if it has a hot-plugged device
+    /// attached.
+    fn hot_plugged_port(&mut self, port: &str) -> Result<&mut RootPort, HotPlugError> {
+        let root_port = self.port_mut(port)?;
+        match &root_port.downstream {
+            Some((_, Downstream::HotPlugged { .. })) => Ok(root_port),
+            Some((name, Downstream::Static(_))) => Err(HotPlugError::NotHotPlugged {
+                port: port.into(),
+                device: name.clone(),
+            }),
+            None => Err(HotPlugError::PortEmpty(port.into())),
+        }
+    }
+
+    fn hot_plugged_devices(&mut self) -> impl Iterator<Item = &mut ErasedChipsetDevice> {
+        self.ports
+            .iter_mut()
+            .filter_map(|port| match &mut port.downstream {
+                Some((_, Downstream::HotPlugged { device, .. })) => Some(device),
+                _ => None,
+            })
+    }
+
+    /// Find the hot-plugged device with an MMIO region containing `addr`.
+    fn hot_plugged_mmio(&mut self, addr: u64) -> Option<&mut dyn MmioIntercept> {
+        self.ports
+            .iter_mut()
+            .find_map(|port| match &mut port.downstream {
+                Some((_, Downstream::HotPlugged { device, regions }))
+                    if regions
+                        .iter()
+                        .any(|region| region.lock().offset_of(addr).is_some()) =>
+                {
+                    device.supports_mmio()
+                }
+                _ => None,
+            })
+    }
+
+    /// Returns the names of the root ports, in device number order.
+    pub fn port_names(&self) -> impl Iterator<Item = &Arc<str>> {
+        self.ports.iter().map(|port| &port.name)
+    }
+
+    /// Decode an ECAM address into (bus, device, function, register).
+    fn decode(&self, addr: u64) -> Option<(u8, u8, u8, u16)> {
+        let offset = addr.checked_sub(self.ecam_base)?;
+        let bus = self.start_bus as u64 + (offset >> 20);
+        if bus > self.end_bus as u64 {
+            return None;
+        }
+        Some((
+            bus as u8,
+            ((offset >> 15) & 0x1f) as u8,
+            ((offset >> 12) & 0x7) as u8,
+            (offset & 0xfff) as u16,
+        ))
+    }
+
+    fn cfg_read(
+        &mut self,
+        bus: u8,
+        device: u8,
+        function: u8,
+        offset: u16,
+        value: &mut u32,
+    ) -> IoResult {
+        if bus == self.start_bus {
+            return match self.ports.get(device as usize) {
+                Some(port) if function == 0 => port.cfg_space.read_u32(offset, value),
+                _ => {
+                    *value = !0;
+                    IoResult::Ok
+                }
+            };
+        }
+
+        match self.downstream(bus, device, function) {
+            Some((name, dev)) => match dev.pci_cfg_read(offset, value) {
+                Some(result) => result,
+                None => {
+                    tracelimit::warn_ratelimited!(
+                        device = &**name,
+                        offset,
+                        "cfg space read failed, device went away"
+                    );
+                    *value = !0;
+                    IoResult::Ok
+                }
+            },
+            None => {
+                *value = !0;
+                IoResult::Ok
+            }
+        }
+    }
+
+    fn cfg_write(
+        &mut self,
+        bus: u8,
+        device: u8,
+        function: u8,
+        offset: u16,
+        value: u32,
+        mask: u32,
+    ) -> IoResult {
+        if bus == self.start_bus {
+            // Root ports have registers with RW1C bits, so undersized writes
+            // must not be turned into a read-modify-write.
+            return match self.ports.get_mut(device as usize) {
+                Some(port) if function == 0 => port.cfg_space.write_u32_masked(offset, value, mask),
+                _ => IoResult::Ok,
+            };
+        }
+
+        let value = if mask == !0 {
+            value
+        } else {
+            // As with the generic PCI bus, undersized writes to endpoints are
+            // handled with a read-modify-write of the containing dword.
+            let mut old_value = 0;
+            match self.cfg_read(bus, device, function, offset, &mut old_value) {
+                IoResult::Ok => {}
+                IoResult::Err(_) => old_value = 0,
+                IoResult::Defer(_) => {
+                    tracelimit::warn_ratelimited!(
+                        bus,
+                        device,
+                        function,
+                        offset,
+                        "deferred undersized ecam writes are not supported"
+                    );
+                    return IoResult::Ok;
+                }
+            }
+            (old_value & !mask) | value
+        };
+
+        match self.downstream(bus, device, function) {
+            Some((name, dev)) => match dev.pci_cfg_write(offset, value) {
+                Some(result) => result,
+                None => {
+                    tracelimit::warn_ratelimited!(
+                        device = &**name,
+                        offset,
+                        "cfg space write failed, device went away"
+                    );
+                    IoResult::Ok
+                }
+            },
+            None => IoResult::Ok,
+        }
+    }
+
+    /// Find the device attached to the downstream link of the root port whose
+    /// secondary bus is `bus`.
+    ///
+    /// There are no switches, so the only device on a root port's secondary
+    /// bus is device 0, and nothing lives on subordinate buses.
+    fn downstream(
+        &mut self,
+        bus: u8,
+        device: u8,
+        function: u8,
+    ) -> Option<&mut (Arc<str>, Downstream)> {
+        if device != 0 || function != 0 {
+            return None;
+        }
+        self.ports
+            .iter_mut()
+            .find(|port| port.cfg_space.secondary_bus() == bus)?
+            .downstream
+            .as_mut()
+    }
+}
+
+impl ChangeDeviceState for GenericPcieRootComplex {
+    fn start(&mut self) {
+        self.running = true;
+        for device in self.hot_plugged_devices() {
+            device.start();
+        }
+    }
+
+    async fn stop(&mut self) {
+        for device in self.hot_plugged_devices() {
+            device.stop().await;
+        }
+        self.running = false;
+    }
+
+    async fn reset(&mut self) {
+        // Hot-plugged devices stay plugged in across reset.
+        for device in self.hot_plugged_devices() {
+            device.reset().await;
+        }
+        for port in &mut self.ports {
+            port.cfg_space.reset();
+        }
+    }
+}
+
+impl ChipsetDevice for GenericPcieRootComplex {
+    fn supports_mmio(&mut self) -> Option<&mut dyn MmioIntercept> {
+        Some(self)
+    }
+
+    fn supports_poll_device(&mut self) -> Option<&mut dyn PollDevice> {
+        Some(self)
+    }
+}
+
+impl PollDevice for GenericPcieRootComplex {
+    fn poll_device(&mut self, cx: &mut Context<'_>) {
+        self.waker = Some(cx.waker().clone());
+        for device in self.hot_plugged_devices() {
+            if let Some(poll) = device.supports_poll_device() {
+                poll.poll_device(cx);
+            }
+        }
+    }
+}
+
+fn check_access(addr: u64, len: usize) -> Result<(), IoError> {
+    if !matches!(len, 1 | 2 | 4) {
+        return Err(IoError::InvalidAccessSize);
+    }
+    if addr & (len as u64 - 1) != 0 {
+        return Err(IoError::UnalignedAccess);
+    }
+    Ok(())
+}
+
+impl MmioIntercept for GenericPcieRootComplex {
+    fn mmio_read(&mut self, addr: u64, data: &mut [u8]) -> IoResult {
+        if !self.ecam_region[0].1.contains(&addr) {
+            return match self.hot_plugged_mmio(addr) {
+                Some(mmio) => mmio.mmio_read(addr, data),
+                None => IoResult::Err(IoError::InvalidRegister),
+            };
+        }
+        if let Err(e) = check_access(addr, data.len()) {
+            return IoResult::Err(e);
+        }
+        let Some((bus, device, function, register)) = self.decode(addr) else {
+            return IoResult::Err(IoError::InvalidRegister);
+        };
+
+        let mut value = 0;
+        let value = match self.cfg_read(bus, device, function, register & !3, &mut value) {
+            IoResult::Ok => value,
+            IoResult::Err(e) => {
+                tracelimit::warn_ratelimited!(
+                    bus,
+                    device,
+                    function,
+                    register,
+                    error = ?e,
+                    "ecam read failed"
+                );
+                // Mirror the generic PCI bus, which returns zeros on error.
+                0
+            }
+            IoResult::Defer(token) if data.len() == 4 => return IoResult::Defer(token),
+            IoResult::Defer(_) => {
+                tracelimit::warn_ratelimited!(
+                    bus,
+                    device,
+                    function,
+                    register,
+                    "deferred undersized ecam reads are not supported"
+                );
+                !0
+            }
+        };
+
+        let shift = (register & 3) * 8;
+        data.copy_from_slice(&(value >> shift).to_ne_bytes()[..data.len()]);
+        IoResult::Ok
+    }
+
+    fn mmio_write(&mut self, addr: u64, data: &[u8]) -> IoResult {
+        if !self.ecam_region[0].1.contains(&addr) {
+            return match self.hot_plugged_mmio(addr) {
+                Some(mmio) => mmio.mmio_write(addr, data),
+                None => IoResult::Err(IoError::InvalidRegister),
+            };
+        }
+        if let Err(e) = check_access(addr, data.len()) {
+            return IoResult::Err(e);
+        }
+        let Some((bus, device, function, register)) = self.decode(addr) else {
+            return IoResult::Err(IoError::InvalidRegister);
+        };
+
+        let mut value = [0; 4];
+        value[..data.len()].copy_from_slice(data);
+        let shift = (register & 3) * 8;
+        let value = u32::from_ne_bytes(value) << shift;
+        let mask = (((1u64 << (data.len() * 8)) - 1) as u32) << shift;
+
+        match self.cfg_write(bus, device, function, register & !3, value, mask) {
+            IoResult::Err(e) => {
+                tracelimit::warn_ratelimited!(
+                    bus,
+                    device,
+                    function,
+                    register,
+                    error = ?e,
+                    "ecam write failed"
+                );
+                IoResult::Ok
+            }
+            result @ (IoResult::Ok | IoResult::Defer(_)) => result,
+        }
+    }
+
+    fn get_static_regions(&mut self) -> &[(&str, RangeInclusive<u64>)] {
+        &self.ecam_region
+    }
+}
+
+mod save_restore {
+    use super::*;
+    use thiserror::Error;
+    use vmcore::save_restore::RestoreError;
+    use vmcore::save_restore::SaveError;
+    use vmcore::save_restore::SaveRestore;
+
+    mod state {
+        use mesh::payload::Protobuf;
+        use pci_core::cfg_space_emu::ConfigSpaceType1Emulator;
+        use vmcore::save_restore::SaveRestore;
+        use vmcore::save_restore::SavedStateRoot;
+
+        #[derive(Protobuf, SavedStateRoot)]
+        #[mesh(package = "pcie.root_complex")]
+        pub struct SavedState {
+            #[mesh(1)]
+            pub ports: Vec<(
+                String,
+                <ConfigSpaceType1Emulator as SaveRestore>::SavedState,
+            )>,
+        }
+    }
+
+    #[derive(Debug, Error)]
+    enum GenericPcieRootComplexRestoreError {
+        #[error("found unexpected root port {0}")]
+        InvalidPort(String),
+    }
+
+    impl SaveRestore for GenericPcieRootComplex {
+        type SavedState = state::SavedState;
+
+        fn save(&mut self) -> Result<Self::SavedState, SaveError> {
+            // Hot-plugged devices are not part of the VM's configuration, so
+            // they could not be recreated on restore.
+            if self.hot_plugged_devices().next().is_some() {
+                return Err(SaveError::NotSupported);
+            }
+
+            let saved_state = state::SavedState {
+                ports: self
+                    .ports
+                    .iter_mut()
+                    .map(|port| Ok((port.name.to_string(), port.cfg_space.save()?)))
+                    .collect::<Result<_, _>>()?,
+            };
+
+            Ok(saved_state)
+        }
+
+        fn restore(&mut self, state: Self::SavedState) -> Result<(), RestoreError> {
+            let state::SavedState { ports } = state;
+
+            for (name, cfg_space) in ports {
+                let port = self
+                    .ports
+                    .iter_mut()
+                    .find(|port| port.name.as_ref() == name)
+                    .ok_or_else(|| {
+                        RestoreError::InvalidSavedState(
+                            GenericPcieRootComplexRestoreError::InvalidPort(name).into(),
+                        )
+                    })?;
+                port.cfg_space.restore(cfg_space)?;
+            }
+
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chipset_device::pci::PciConfigSpace;
+    use chipset_device_resources::ResolvedChipsetDevice;
+    use pci_core::msi::MsiInterruptSet;
+    use pci_core::test_helpers::TestPciInterruptController;
+    use vmcore::save_restore::NoSavedState;
+    use vmcore::save_restore::RestoreError;
+    use vmcore::save_restore::SaveError;
+    use vmcore::save_restore::SaveRestore;
+
+    const ECAM_BASE: u64 = 0xe000_0000;
+    const BAR_BASE: u64 = 0xc000_0000;
+
+    struct TestDevice(u32);
+
+    impl GenericPciBusDevice for TestDevice {
+        fn pci_cfg_read(&mut self, offset: u16, value: &mut u32) -> Option<IoResult> {
+            *value = if offset == 0 { self.0 } else { 0 };
+            Some(IoResult::Ok)
+        }
+
+        fn pci_cfg_write(&mut self, _offset: u16, _value: u32) -> Option<IoResult> {
+            Some(IoResult::Ok)
+        }
+    }
+
+    struct TestMmio;
+
+    impl RegisterMmioIntercept for TestMmio {
+        fn new_io_region(&mut self, region_name: &str, len: u64) -> Box<dyn ControlMmioIntercept> {
+            Box::new(TestMmioRegion {
+                name: region_name.into(),
+                len,
+                addr: None,
+            })
+        }
+    }
+
+    struct TestMmioRegion {
+        name: String,
+        len: u64,
+        addr: Option<u64>,
+    }
+
+    impl ControlMmioIntercept for TestMmioRegion {
+        fn region_name(&self) -> &str {
+            &self.name
+        }
+
+        fn map(&mut self, addr: u64) {
+            self.addr = Some(addr);
+        }
+
+        fn unmap(&mut self) {
+            self.addr = None;
+        }
+
+        fn addr(&self) -> Option<u64> {
+            self.addr
+        }
+
+        fn len(&self) -> u64 {
+            self.len
+        }
+
+        fn offset_of(&self, addr: u64) -> Option<u64> {
+            let offset = addr.checked_sub(self.addr?)?;
+            (offset < self.len).then_some(offset)
+        }
+    }
+
+    /// A device with a single MMIO region, mapped at `BAR_BASE` on creation.
+    #[derive(InspectMut)]
+    struct HotPlugTestDevice {
+        id: u32,
+        #[inspect(skip)]
+        region: Box<dyn ControlMmioIntercept>,
+    }
+
+    impl HotPlugTestDevice {
+        fn new(id: u32, register_mmio: &mut dyn RegisterMmioIntercept) -> Self {
+            let mut region = register_mmio.new_io_region("bar0", 0x1000);
+            region.map(BAR_BASE);
+            Self { id, region }
+        }
+    }
+
+    impl ChangeDeviceState for HotPlugTestDevice {
+        fn start(&mut self) {}
+
+        async fn stop(&mut self) {}
+
+        async fn reset(&mut self) {}
+    }
+
+    impl ChipsetDevice for HotPlugTestDevice {
+        fn supports_pci(&mut self) -> Option<&mut dyn PciConfigSpace> {
+            Some(self)
+        }
+
+        fn supports_mmio(&mut self) -> Option<&mut dyn MmioIntercept> {
+            Some(self)
+        }
+    }
+
+    impl PciConfigSpace for HotPlugTestDevice {
+        fn pci_cfg_read(&mut self, offset: u16, value: &mut u32) -> IoResult {
+            *value = if offset == 0 { self.id } else { 0 };
+            IoResult::Ok
+        }
+
+        fn pci_cfg_write(&mut self, _offset: u16, _value: u32) -> IoResult {
+            IoResult::Ok
+        }
+    }
+
+    impl MmioIntercept for HotPlugTestDevice {
+        fn mmio_read(&mut self, addr: u64, data: &mut [u8]) -> IoResult {
+            let offset = self.region.offset_of(addr).unwrap();
+            data.fill(offset as u8);
+            IoResult::Ok
+        }
+
+        fn mmio_write(&mut self, _addr: u64, _data: &[u8]) -> IoResult {
+            IoResult::Ok
+        }
+    }
+
+    impl SaveRestore for HotPlugTestDevice {
+        type SavedState = NoSavedState;
+
+        fn save(&mut self) -> Result<Self::SavedState, SaveError> {
+            Ok(NoSavedState)
+        }
+
+        fn restore(&mut self, NoSavedState: Self::SavedState) -> Result<(), RestoreError> {
+            Ok(())
+        }
+    }
+
+    fn ecam(bus: u8, device: u8, function: u8, register: u16) -> u64 {
+        ECAM_BASE
+            | (bus as u64) << 20
+            | (device as u64) << 15
+            | (function as u64) << 12
+            | register as u64
+    }
+
+    fn read_u32(rc: &mut GenericPcieRootComplex, addr: u64) -> u32 {
+        let mut data = [0; 4];
+        rc.mmio_read(addr, &mut data).unwrap();
+        u32::from_ne_bytes(data)
+    }
+
+    fn write_u32(rc: &mut GenericPcieRootComplex, addr: u64, value: u32) {
+        rc.mmio_write(addr, &value.to_ne_bytes()).unwrap();
+    }
+
+    fn root_complex(set: &mut MsiInterruptSet) -> GenericPcieRootComplex {
+        GenericPcieRootComplex::new(
+            ECAM_BASE,
+            0,
+            3,
+            vec![
+                GenericPcieRootPortDefinition { name: "rp0".into() },
+                GenericPcieRootPortDefinition { name: "rp1".into() },
+            ],
+            set,
+            Box::new(TestMmio),
+        )
+    }
+
+    #[test]
+    fn route_to_downstream_device() {
+        let mut rc = root_complex(&mut MsiInterruptSet::new());
+        rc.add_pcie_device("rp1", "dev", TestDevice(0x1234_5678))
+            .ok()
+            .unwrap();
+        assert!(rc.add_pcie_device("rp1", "dev2", TestDevice(0)).is_err());
+
+        // root ports on the root bus
+        assert_eq!(read_u32(&mut rc, ecam(0, 0, 0, 0)), 0xc030_1414);
+        assert_eq!(read_u32(&mut rc, ecam(0, 1, 0, 0)), 0xc030_1414);
+        assert_eq!(read_u32(&mut rc, ecam(0, 2, 0, 0)), !0);
+        assert_eq!(read_u32(&mut rc, ecam(0, 0, 1, 0)), !0);
+
+        // nothing is reachable until bus numbers are assigned
+        assert_eq!(read_u32(&mut rc, ecam(2, 0, 0, 0)), !0);
+
+        // assign bus 2 to rp1 using a byte write to the secondary bus number
+        rc.mmio_write(ecam(0, 1, 0, 0x18), &[0]).unwrap();
+        rc.mmio_write(ecam(0, 1, 0, 0x19), &[2]).unwrap();
+        rc.mmio_write(ecam(0, 1, 0, 0x1a), &[2]).unwrap();
+        assert_eq!(read_u32(&mut rc, ecam(0, 1, 0, 0x18)), 0x0002_0200);
+
+        assert_eq!(read_u32(&mut rc, ecam(2, 0, 0, 0)), 0x1234_5678);
+        assert_eq!(read_u32(&mut rc, ecam(2, 1, 0, 0)), !0);
+        assert_eq!(read_u32(&mut rc, ecam(2, 0, 1, 0)), !0);
+
+        let mut data = [0; 2];
+        rc.mmio_read(ecam(2, 0, 0, 2), &mut data).unwrap();
+        assert_eq!(u16::from_ne_bytes(data), 0x1234);
+    }
+
+    #[test]
+    fn hot_plug() {
+        let mut set = MsiInterruptSet::new();
+        let mut rc = root_complex(&mut set);
+        let msi_controller = TestPciInterruptController::new();
+        set.connect(&msi_controller);
+
+        // assign bus 1 to rp0, and enable presence detect changed interrupts
+        // and the port's MSI
+        write_u32(&mut rc, ecam(0, 0, 0, 0x18), 0x0001_0100);
+        let slot_control = 0x28u16;
+        rc.mmio_write(ecam(0, 0, 0, 0x58), &slot_control.to_ne_bytes())
+            .unwrap();
+        write_u32(&mut rc, ecam(0, 0, 0, 0x80), 0xfee0_0000);
+        write_u32(&mut rc, ecam(0, 0, 0, 0x88), 0x30);
+        rc.mmio_write(ecam(0, 0, 0, 0x7e), &1u16.to_ne_bytes())
+            .unwrap();
+        assert_eq!(
+            read_u32(&mut rc, ecam(0, 0, 0, 0x7c)) & 0x81_00ff,
+            0x81_0005
+        );
+
+        // the slot control write completed a command; clear it
+        assert_eq!(read_u32(&mut rc, ecam(0, 0, 0, 0x58)) >> 16, 0x10);
+        rc.mmio_write(ecam(0, 0, 0, 0x5a), &0x10u16.to_ne_bytes())
+            .unwrap();
+        assert_eq!(read_u32(&mut rc, ecam(0, 0, 0, 0x58)), slot_control as u32);
+        assert_eq!(msi_controller.get_next_interrupt(), None);
+        assert_eq!(read_u32(&mut rc, ecam(1, 0, 0, 0)), !0);
+
+        let mut mmio = rc.hot_plug_mmio();
+        let dev = HotPlugTestDevice::new(0x1234_5678, &mut mmio);
+        rc.hot_add_device("rp0", "dev", ResolvedChipsetDevice::from(dev).0, mmio)
+            .unwrap();
+        assert_eq!(
+            msi_controller.get_next_interrupt(),
+            Some((0xfee0_0000, 0x30))
+        );
+        // presence detect changed, presence detected
+        assert_eq!(read_u32(&mut rc, ecam(0, 0, 0, 0x58)) >> 16, 0x148);
+        assert_eq!(read_u32(&mut rc, ecam(1, 0, 0, 0)), 0x1234_5678);
+        assert_eq!(read_u32(&mut rc, BAR_BASE + 0x10), 0x1010_1010);
+
+        let mut mmio = rc.hot_plug_mmio();
+        let dev = HotPlugTestDevice::new(0, &mut mmio);
+        assert!(matches!(
+            rc.hot_add_device("rp0", "dev2", ResolvedChipsetDevice::from(dev).0, mmio),
+            Err(HotPlugError::PortOccupied { .. })
+        ));
+        // dropping the rejected device leaves the existing device's region
+        // intact
+        assert_eq!(read_u32(&mut rc, BAR_BASE + 0x10), 0x1010_1010);
+
+        // clear the events, then ask the guest to release the device
+        rc.mmio_write(ecam(0, 0, 0, 0x5a), &0x108u16.to_ne_bytes())
+            .unwrap();
+        rc.request_hot_remove("rp0").unwrap();
+        // attention button pressed, which the guest has not enabled as an
+        // interrupt source
+        assert_eq!(msi_controller.get_next_interrupt(), None);
+        assert_eq!(read_u32(&mut rc, ecam(0, 0, 0, 0x58)) >> 16, 0x41);
+        assert!(!rc.is_slot_powered_off("rp0").unwrap());
+
+        // the guest clears the event and powers off the slot
+        rc.mmio_write(ecam(0, 0, 0, 0x5a), &0x1u16.to_ne_bytes())
+            .unwrap();
+        rc.mmio_write(ecam(0, 0, 0, 0x58), &(slot_control | 0x700).to_ne_bytes())
+            .unwrap();
+        assert!(rc.is_slot_powered_off("rp0").unwrap());
+        rc.mmio_write(ecam(0, 0, 0, 0x5a), &0x10u16.to_ne_bytes())
+            .unwrap();
+
+        drop(rc.hot_remove_device("rp0").unwrap());
+        assert_eq!(
+            msi_controller.get_next_interrupt(),
+            Some((0xfee0_0000, 0x30))
+        );
+        assert_eq!(read_u32(&mut rc, ecam(0, 0, 0, 0x58)) >> 16, 0x108);
+        assert_eq!(read_u32(&mut rc, ecam(1, 0, 0, 0)), !0);
+        let mut data = [0; 4];
+        assert!(matches!(
+            rc.mmio_read(BAR_BASE, &mut data),
+            IoResult::Err(IoError::InvalidRegister)
+        ));
+
+        assert!(matches!(
+            rc.hot_remove_device("rp0"),
+            Err(HotPlugError::PortEmpty(_))
+        ));
+        assert!(matches!(
+            rc.request_hot_remove("rp0"),
+            Err(HotPlugError::PortEmpty(_))
+        ));
+        assert!(matches!(
+            rc.hot_remove_device("rp2"),
+            Err(HotPlugError::UnknownPort(_))
+        ));
+    }
+}
@@ -0,0 +1,17 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Definitions and transport shared by the vfio-user client and server.
+//!
+//! vfio-user is a protocol for emulating a PCI device in a separate process.
+//! It mirrors the Linux VFIO ioctl interface, carried as messages over a Unix
+//! stream socket, with file descriptors (for DMA memory, sparse mmap regions
+//! and interrupt eventfds) passed as `SCM_RIGHTS` ancillary data.
+//!
+//! Specification: <https://github.com/nutanix/libvfio-user/blob/master/docs/vfio-user.rst>
+
+#![warn(missing_docs)]
+
+pub mod protocol;
+#[cfg(target_os = "linux")]
+pub mod socket;
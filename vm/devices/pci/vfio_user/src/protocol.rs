@@ -0,0 +1,382 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! vfio-user wire protocol definitions.
+//!
+//! All fields are in host byte order, since the client and server always run
+//! on the same machine.
+
+use bitfield_struct::bitfield;
+use open_enum::open_enum;
+use zerocopy::AsBytes;
+use zerocopy::FromBytes;
+use zerocopy::FromZeroes;
+
+/// The protocol major version implemented.
+pub const MAJOR_VERSION: u16 = 0;
+/// The protocol minor version implemented.
+pub const MINOR_VERSION: u16 = 1;
+
+/// The maximum number of file descriptors sent with a single message.
+pub const MAX_MSG_FDS: usize = 16;
+/// The maximum number of bytes transferred by a single region read or write.
+pub const MAX_DATA_XFER_SIZE: u32 = 1024 * 1024;
+/// The maximum size of a message, including the header.
+pub const MAX_MESSAGE_SIZE: u32 = MAX_DATA_XFER_SIZE + 4096;
+
+open_enum! {
+    /// A message command.
+    #[derive(AsBytes, FromBytes, FromZeroes)]
+    pub enum Command: u16 {
+        #![allow(missing_docs)] // Self explanatory names
+        VERSION = 1,
+        DMA_MAP = 2,
+        DMA_UNMAP = 3,
+        DEVICE_GET_INFO = 4,
+        DEVICE_GET_REGION_INFO = 5,
+        DEVICE_GET_REGION_IO_FDS = 6,
+        DEVICE_GET_IRQ_INFO = 7,
+        DEVICE_SET_IRQS = 8,
+        REGION_READ = 9,
+        REGION_WRITE = 10,
+        DMA_READ = 11,
+        DMA_WRITE = 12,
+        DEVICE_RESET = 13,
+    }
+}
+
+/// The header at the start of every message.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, AsBytes, FromBytes, FromZeroes)]
+pub struct Header {
+    /// Identifies the message. A reply uses the ID of its command.
+    pub message_id: u16,
+    /// The command.
+    pub command: Command,
+    /// The size of the message, including this header.
+    pub message_size: u32,
+    /// Message flags.
+    pub flags: HeaderFlags,
+    /// On an error reply, the errno value describing the failure.
+    pub error: u32,
+}
+
+impl Header {
+    /// Returns the header for a command. The message size is filled in when
+    /// the message is sent.
+    pub fn new_command(message_id: u16, command: Command) -> Self {
+        Self {
+            message_id,
+            command,
+            message_size: 0,
+            flags: HeaderFlags::new().with_message_type(MESSAGE_TYPE_COMMAND),
+            error: 0,
+        }
+    }
+
+    /// Returns the header for a successful reply to `self`.
+    pub fn reply(&self) -> Self {
+        Self {
+            message_id: self.message_id,
+            command: self.command,
+            message_size: 0,
+            flags: HeaderFlags::new().with_message_type(MESSAGE_TYPE_REPLY),
+            error: 0,
+        }
+    }
+
+    /// Returns the header for a reply to `self` failing with `errno`.
+    pub fn error_reply(&self, errno: i32) -> Self {
+        Self {
+            flags: HeaderFlags::new()
+                .with_message_type(MESSAGE_TYPE_REPLY)
+                .with_error(true),
+            error: errno as u32,
+            ..self.reply()
+        }
+    }
+}
+
+/// [`Header`] flags.
+#[bitfield(u32)]
+#[derive(AsBytes, FromBytes, FromZeroes)]
+pub struct HeaderFlags {
+    /// The message type.
+    #[bits(4)]
+    pub message_type: u8,
+    /// The sender does not expect a reply.
+    pub no_reply: bool,
+    /// The reply indicates a failure, described by [`Header::error`].
+    pub error: bool,
+    #[bits(26)]
+    _reserved: u32,
+}
+
+/// [`HeaderFlags::message_type`] for commands.
+pub const MESSAGE_TYPE_COMMAND: u8 = 0;
+/// [`HeaderFlags::message_type`] for replies.
+pub const MESSAGE_TYPE_REPLY: u8 = 1;
+
+/// The payload of [`Command::VERSION`], followed by a NUL-terminated JSON
+/// string describing the sender's capabilities.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, AsBytes, FromBytes, FromZeroes)]
+pub struct Version {
+    /// The major version.
+    pub major: u16,
+    /// The minor version.
+    pub minor: u16,
+}
+
+/// The payload of [`Command::DMA_MAP`]. The memory is passed as a file
+/// descriptor.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, AsBytes, FromBytes, FromZeroes)]
+pub struct DmaMap {
+    /// The size of this structure.
+    pub argsz: u32,
+    /// Access flags.
+    pub flags: DmaMapFlags,
+    /// The offset of the region in the file descriptor.
+    pub offset: u64,
+    /// The DMA address of the region.
+    pub address: u64,
+    /// The size of the region.
+    pub size: u64,
+}
+
+/// [`DmaMap`] flags.
+#[bitfield(u32)]
+#[derive(AsBytes, FromBytes, FromZeroes)]
+pub struct DmaMapFlags {
+    /// The region is readable by the device.
+    pub read: bool,
+    /// The region is writable by the device.
+    pub write: bool,
+    #[bits(30)]
+    _reserved: u32,
+}
+
+/// The payload of [`Command::DMA_UNMAP`].
+#[repr(C)]
+#[derive(Debug, Copy, Clone, AsBytes, FromBytes, FromZeroes)]
+pub struct DmaUnmap {
+    /// The size of this structure.
+    pub argsz: u32,
+    /// Flags. Must be zero.
+    pub flags: u32,
+    /// The DMA address of the region.
+    pub address: u64,
+    /// The size of the region.
+    pub size: u64,
+}
+
+/// The payload of [`Command::DEVICE_GET_INFO`].
+#[repr(C)]
+#[derive(Debug, Copy, Clone, AsBytes, FromBytes, FromZeroes)]
+pub struct DeviceInfo {
+    /// The size of this structure.
+    pub argsz: u32,
+    /// Device flags.
+    pub flags: DeviceFlags,
+    /// The number of regions.
+    pub num_regions: u32,
+    /// The number of interrupt types.
+    pub num_irqs: u32,
+}
+
+/// [`DeviceInfo`] flags.
+#[bitfield(u32)]
+#[derive(AsBytes, FromBytes, FromZeroes)]
+pub struct DeviceFlags {
+    /// The device supports [`Command::DEVICE_RESET`].
+    pub reset: bool,
+    /// The device is a PCI device.
+    pub pci: bool,
+    #[bits(30)]
+    _reserved: u32,
+}
+
+/// The PCI region indexes.
+pub mod region_index {
+    /// The first BAR. BAR `n` is region `BAR0 + n`.
+    pub const BAR0: u32 = 0;
+    /// The expansion ROM.
+    pub const ROM: u32 = 6;
+    /// PCI configuration space.
+    pub const CONFIG: u32 = 7;
+    /// Legacy VGA memory and ports.
+    pub const VGA: u32 = 8;
+}
+
+/// The payload of [`Command::DEVICE_GET_REGION_INFO`]. In the reply, this is
+/// followed by any capabilities, and a file descriptor if the region is
+/// mappable.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, AsBytes, FromBytes, FromZeroes)]
+pub struct RegionInfo {
+    /// The size of the buffer available for the reply, including
+    /// capabilities. In the reply, the size required for all capabilities.
+    pub argsz: u32,
+    /// Region flags.
+    pub flags: RegionFlags,
+    /// The region index.
+    pub index: u32,
+    /// The offset of the first capability from the start of this structure,
+    /// or zero.
+    pub cap_offset: u32,
+    /// The size of the region.
+    pub size: u64,
+    /// The offset of the region in the file descriptor, if mappable.
+    pub offset: u64,
+}
+
+/// [`RegionInfo`] flags.
+#[bitfield(u32)]
+#[derive(AsBytes, FromBytes, FromZeroes)]
+pub struct RegionFlags {
+    /// The region can be read.
+    pub read: bool,
+    /// The region can be written.
+    pub write: bool,
+    /// The region, or the areas described by a sparse mmap capability, can be
+    /// mapped.
+    pub mmap: bool,
+    /// The reply has capabilities.
+    pub caps: bool,
+    #[bits(28)]
+    _reserved: u32,
+}
+
+/// The header of a region capability.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, AsBytes, FromBytes, FromZeroes)]
+pub struct CapabilityHeader {
+    /// The capability ID.
+    pub id: u16,
+    /// The capability version.
+    pub version: u16,
+    /// The offset of the next capability from the start of the
+    /// [`RegionInfo`], or zero.
+    pub next: u32,
+}
+
+/// The [`CapabilityHeader::id`] of a sparse mmap capability.
+pub const CAP_SPARSE_MMAP: u16 = 1;
+
+/// A sparse mmap capability, followed by `nr_areas` [`SparseMmapArea`]s.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, AsBytes, FromBytes, FromZeroes)]
+pub struct SparseMmap {
+    /// The capability header.
+    pub header: CapabilityHeader,
+    /// The number of areas.
+    pub nr_areas: u32,
+    /// Reserved.
+    pub reserved: u32,
+}
+
+/// An area of a region that can be mapped.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, AsBytes, FromBytes, FromZeroes)]
+pub struct SparseMmapArea {
+    /// The offset of the area in the region.
+    pub offset: u64,
+    /// The size of the area.
+    pub size: u64,
+}
+
+/// The PCI interrupt indexes.
+pub mod irq_index {
+    /// Legacy INTx.
+    pub const INTX: u32 = 0;
+    /// MSI.
+    pub const MSI: u32 = 1;
+    /// MSI-X.
+    pub const MSIX: u32 = 2;
+    /// Error reporting.
+    pub const ERR: u32 = 3;
+    /// Device requests.
+    pub const REQ: u32 = 4;
+}
+
+/// The payload of [`Command::DEVICE_GET_IRQ_INFO`].
+#[repr(C)]
+#[derive(Debug, Copy, Clone, AsBytes, FromBytes, FromZeroes)]
+pub struct IrqInfo {
+    /// The size of this structure.
+    pub argsz: u32,
+    /// Interrupt flags.
+    pub flags: IrqInfoFlags,
+    /// The interrupt type index.
+    pub index: u32,
+    /// The number of vectors of this type.
+    pub count: u32,
+}
+
+/// [`IrqInfo`] flags.
+#[bitfield(u32)]
+#[derive(AsBytes, FromBytes, FromZeroes)]
+pub struct IrqInfoFlags {
+    /// The interrupts can be signaled via eventfd.
+    pub eventfd: bool,
+    /// The interrupts can be masked.
+    pub maskable: bool,
+    /// The interrupts are masked automatically after being signaled.
+    pub automasked: bool,
+    /// The number of vectors cannot be changed.
+    pub noresize: bool,
+    #[bits(28)]
+    _reserved: u32,
+}
+
+/// The payload of [`Command::DEVICE_SET_IRQS`]. For
+/// [`SetIrqsFlags::data_eventfd`], the eventfds are passed as file
+/// descriptors.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, AsBytes, FromBytes, FromZeroes)]
+pub struct SetIrqs {
+    /// The size of this structure.
+    pub argsz: u32,
+    /// The data type and action.
+    pub flags: SetIrqsFlags,
+    /// The interrupt type index.
+    pub index: u32,
+    /// The first vector.
+    pub start: u32,
+    /// The number of vectors.
+    pub count: u32,
+}
+
+/// [`SetIrqs`] flags.
+#[bitfield(u32)]
+#[derive(AsBytes, FromBytes, FromZeroes)]
+pub struct SetIrqsFlags {
+    /// There is no data.
+    pub data_none: bool,
+    /// The data is an array of booleans.
+    pub data_bool: bool,
+    /// The data is an eventfd per vector.
+    pub data_eventfd: bool,
+    /// Mask the vectors.
+    pub action_mask: bool,
+    /// Unmask the vectors.
+    pub action_unmask: bool,
+    /// Set the vectors' trigger eventfds, or trigger them.
+    pub action_trigger: bool,
+    #[bits(26)]
+    _reserved: u32,
+}
+
+/// The payload of [`Command::REGION_READ`] and [`Command::REGION_WRITE`],
+/// followed by the data for writes and read replies.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, AsBytes, FromBytes, FromZeroes)]
+pub struct RegionAccess {
+    /// The offset in the region.
+    pub offset: u64,
+    /// The region index.
+    pub region: u32,
+    /// The number of bytes to access.
+    pub count: u32,
+}
@@ -0,0 +1,163 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Sending and receiving vfio-user messages, with their file descriptors, over
+//! a Unix stream socket.
+
+// UNSAFETY: Taking ownership of file descriptors received via SCM_RIGHTS.
+#![expect(unsafe_code)]
+
+use crate::protocol::Header;
+use crate::protocol::MAX_MESSAGE_SIZE;
+use crate::protocol::MAX_MSG_FDS;
+use nix::sys::socket::recvmsg;
+use nix::sys::socket::sendmsg;
+use nix::sys::socket::ControlMessage;
+use nix::sys::socket::ControlMessageOwned;
+use nix::sys::socket::MsgFlags;
+use std::io;
+use std::io::IoSlice;
+use std::io::IoSliceMut;
+use std::io::Read;
+use std::io::Write;
+use std::os::fd::AsRawFd;
+use std::os::fd::BorrowedFd;
+use std::os::fd::FromRawFd;
+use std::os::fd::OwnedFd;
+use std::os::fd::RawFd;
+use std::os::unix::net::UnixStream;
+use zerocopy::AsBytes;
+use zerocopy::FromBytes;
+use zerocopy::FromZeroes;
+
+/// A received message.
+#[derive(Debug)]
+pub struct Message {
+    /// The message header.
+    pub header: Header,
+    /// The message payload, following the header.
+    pub payload: Vec<u8>,
+    /// The file descriptors sent with the message.
+    pub fds: Vec<OwnedFd>,
+}
+
+impl Message {
+    /// Reads a `T` from the start of the payload.
+    pub fn read_payload<T: FromBytes>(&self) -> io::Result<T> {
+        T::read_from_prefix(&self.payload).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{:?} payload too short: {} bytes",
+                    self.header.command,
+                    self.payload.len()
+                ),
+            )
+        })
+    }
+
+    /// Returns the payload following a `T` at its start.
+    pub fn trailing_payload<T>(&self) -> &[u8] {
+        self.payload.get(size_of::<T>()..).unwrap_or(&[])
+    }
+}
+
+/// Sends a message consisting of `header` followed by `payload`, along with
+/// `fds`.
+///
+/// `header.message_size` is computed from the payload.
+pub fn send_message(
+    socket: &UnixStream,
+    mut header: Header,
+    payload: &[&[u8]],
+    fds: &[BorrowedFd<'_>],
+) -> io::Result<()> {
+    if fds.len() > MAX_MSG_FDS {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "too many file descriptors",
+        ));
+    }
+    let payload_len = payload.iter().map(|p| p.len()).sum::<usize>();
+    header.message_size = (size_of::<Header>() + payload_len)
+        .try_into()
+        .ok()
+        .filter(|&size| size <= MAX_MESSAGE_SIZE)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "message too large"))?;
+
+    let mut buf = Vec::with_capacity(header.message_size as usize);
+    buf.extend_from_slice(header.as_bytes());
+    for p in payload {
+        buf.extend_from_slice(p);
+    }
+
+    // The file descriptors are attached to the first byte sent. If the socket
+    // takes only part of the message, send the rest without them.
+    let raw_fds = fds.iter().map(|fd| fd.as_raw_fd()).collect::<Vec<_>>();
+    let cmsgs = [ControlMessage::ScmRights(&raw_fds)];
+    let n = sendmsg::<()>(
+        socket.as_raw_fd(),
+        &[IoSlice::new(&buf)],
+        if raw_fds.is_empty() { &[] } else { &cmsgs },
+        MsgFlags::MSG_NOSIGNAL,
+        None,
+    )?;
+    (&*socket).write_all(&buf[n..])
+}
+
+/// Receives a message, returning `None` if the socket was closed between
+/// messages.
+pub fn recv_message(socket: &UnixStream) -> io::Result<Option<Message>> {
+    let mut header = Header::new_zeroed();
+    let mut fds = Vec::new();
+    let n = {
+        let mut cmsg = nix::cmsg_space!([RawFd; MAX_MSG_FDS]);
+        let mut iov = [IoSliceMut::new(header.as_bytes_mut())];
+        let msg = recvmsg::<()>(
+            socket.as_raw_fd(),
+            &mut iov,
+            Some(&mut cmsg),
+            MsgFlags::MSG_CMSG_CLOEXEC,
+        )?;
+        for cmsg in msg.cmsgs() {
+            if let ControlMessageOwned::ScmRights(raw_fds) = cmsg {
+                fds.extend(raw_fds.into_iter().map(|fd| {
+                    // SAFETY: the kernel has just installed this fd in this
+                    // process, and nothing else owns it.
+                    unsafe { OwnedFd::from_raw_fd(fd) }
+                }));
+            }
+        }
+        // Check for truncation only after taking ownership of the fds.
+        if msg.flags.contains(MsgFlags::MSG_CTRUNC) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "too many file descriptors",
+            ));
+        }
+        msg.bytes
+    };
+
+    if n == 0 {
+        return Ok(None);
+    }
+    (&*socket).read_exact(&mut header.as_bytes_mut()[n..])?;
+
+    let payload_len = (header.message_size as usize)
+        .checked_sub(size_of::<Header>())
+        .filter(|_| header.message_size <= MAX_MESSAGE_SIZE)
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid message size {}", header.message_size),
+            )
+        })?;
+
+    let mut payload = vec![0; payload_len];
+    (&*socket).read_exact(&mut payload)?;
+    Ok(Some(Message {
+        header,
+        payload,
+        fds,
+    }))
+}
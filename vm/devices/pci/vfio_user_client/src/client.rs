@@ -0,0 +1,457 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! The client side of a vfio-user connection.
+
+use std::io;
+use std::os::fd::BorrowedFd;
+use std::os::fd::OwnedFd;
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+use thiserror::Error;
+use vfio_user::protocol;
+use vfio_user::protocol::Command;
+use vfio_user::protocol::Header;
+use vfio_user::socket::recv_message;
+use vfio_user::socket::send_message;
+use vfio_user::socket::Message;
+use zerocopy::AsBytes;
+use zerocopy::FromBytes;
+use zerocopy::FromZeroes;
+
+/// The capabilities sent to the server during version negotiation.
+const CAPABILITIES: &str = r#"{"capabilities":{"max_msg_fds":16,"max_data_xfer_size":1048576}}"#;
+
+/// How long to wait for the server to accept a request, and then to reply to
+/// it. This bounds how long a stuck server can stall a VP waiting on a BAR
+/// access, or hold the device lock during a configuration space access.
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// An error communicating with a vfio-user server.
+#[derive(Debug, Error)]
+pub enum Error {
+    /// Failed to send or receive a message.
+    #[error("vfio-user socket error")]
+    Io(#[from] io::Error),
+    /// The server closed the connection.
+    #[error("vfio-user server closed the connection")]
+    Closed,
+    /// The server did not accept or reply to a request in time.
+    #[error("vfio-user server timed out")]
+    Timeout,
+    /// An earlier request failed and left the connection unusable.
+    #[error("vfio-user connection has failed")]
+    Failed,
+    /// The server failed a command.
+    #[error("vfio-user command {command:?} failed with errno {errno}")]
+    Server {
+        /// The failed command.
+        command: Command,
+        /// The error returned by the server.
+        errno: i32,
+    },
+    /// The server sent a message other than the expected reply.
+    #[error("unexpected message from vfio-user server: {0:?}")]
+    UnexpectedMessage(Header),
+    /// The server does not support the protocol version.
+    #[error("unsupported vfio-user server version {0}.{1}")]
+    Version(u16, u16),
+}
+
+/// A region's information, with its sparse mmap areas.
+pub struct RegionInfo {
+    /// The region information.
+    pub info: protocol::RegionInfo,
+    /// The areas of the region that can be mapped, relative to the start of
+    /// the region. Empty if the region is not mappable.
+    pub mmap_areas: Vec<protocol::SparseMmapArea>,
+    /// The file descriptor to map the region with, at `info.offset`.
+    pub fd: Option<OwnedFd>,
+}
+
+/// A connection to a vfio-user server.
+///
+/// Requests are issued synchronously: each waits up to [`REQUEST_TIMEOUT`]
+/// for its reply before returning. If a request times out, or the connection
+/// otherwise breaks, the client fails, and all later requests return
+/// [`Error::Failed`] without contacting the server.
+pub struct Client {
+    socket: UnixStream,
+    next_message_id: u16,
+    failed: bool,
+}
+
+impl Client {
+    /// Connects to the server on `socket` and negotiates the protocol version.
+    pub fn new(socket: UnixStream) -> Result<Self, Error> {
+        socket.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+        socket.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+        let mut client = Self {
+            socket,
+            next_message_id: 0,
+            failed: false,
+        };
+
+        let version = protocol::Version {
+            major: protocol::MAJOR_VERSION,
+            minor: protocol::MINOR_VERSION,
+        };
+        let reply = client.request(
+            Command::VERSION,
+            &[version.as_bytes(), CAPABILITIES.as_bytes(), &[0]],
+            &[],
+        )?;
+        let version = reply.read_payload::<protocol::Version>()?;
+        if version.major != protocol::MAJOR_VERSION {
+            return Err(Error::Version(version.major, version.minor));
+        }
+        Ok(client)
+    }
+
+    /// Returns whether the connection has failed.
+    pub fn is_failed(&self) -> bool {
+        self.failed
+    }
+
+    fn request(
+        &mut self,
+        command: Command,
+        payload: &[&[u8]],
+        fds: &[BorrowedFd<'_>],
+    ) -> Result<Message, Error> {
+        if self.failed {
+            return Err(Error::Failed);
+        }
+        let r = self.request_inner(command, payload, fds);
+        // Only a server error leaves the connection in a known state. After
+        // anything else, a late or partial reply could be mistaken for the
+        // reply to a later request.
+        if let Err(err) = &r {
+            if !matches!(err, Error::Server { .. }) {
+                tracing::error!(
+                    error = err as &dyn std::error::Error,
+                    ?command,
+                    "vfio-user connection failed"
+                );
+                self.failed = true;
+            }
+        }
+        r
+    }
+
+    fn request_inner(
+        &mut self,
+        command: Command,
+        payload: &[&[u8]],
+        fds: &[BorrowedFd<'_>],
+    ) -> Result<Message, Error> {
+        let message_id = self.next_message_id;
+        self.next_message_id = self.next_message_id.wrapping_add(1);
+        send_message(
+            &self.socket,
+            Header::new_command(message_id, command),
+            payload,
+            fds,
+        )
+        .map_err(socket_error)?;
+
+        // FUTURE: handle server-initiated DMA_READ/DMA_WRITE commands. These
+        // are only sent for memory that was not shared via DMA_MAP, and all
+        // guest RAM is shared.
+        let reply = recv_message(&self.socket)
+            .map_err(socket_error)?
+            .ok_or(Error::Closed)?;
+        let header = reply.header;
+        if header.message_id != message_id
+            || header.command != command
+            || header.flags.message_type() != protocol::MESSAGE_TYPE_REPLY
+        {
+            return Err(Error::UnexpectedMessage(header));
+        }
+        if header.flags.error() {
+            return Err(Error::Server {
+                command,
+                errno: header.error as i32,
+            });
+        }
+        Ok(reply)
+    }
+
+    /// Gets the device information.
+    pub fn device_info(&mut self) -> Result<protocol::DeviceInfo, Error> {
+        let info = protocol::DeviceInfo {
+            argsz: size_of::<protocol::DeviceInfo>() as u32,
+            ..FromZeroes::new_zeroed()
+        };
+        let reply = self.request(Command::DEVICE_GET_INFO, &[info.as_bytes()], &[])?;
+        Ok(reply.read_payload()?)
+    }
+
+    /// Gets the information for region `index`.
+    pub fn region_info(&mut self, index: u32) -> Result<RegionInfo, Error> {
+        // Leave room for a sparse mmap capability with a reasonable number of
+        // areas. The server reports the required size if this is too small.
+        let mut argsz = 4096;
+        loop {
+            let info = protocol::RegionInfo {
+                argsz,
+                index,
+                ..FromZeroes::new_zeroed()
+            };
+            let mut reply =
+                self.request(Command::DEVICE_GET_REGION_INFO, &[info.as_bytes()], &[])?;
+            let info = reply.read_payload::<protocol::RegionInfo>()?;
+            if info.argsz > argsz {
+                argsz = info.argsz;
+                continue;
+            }
+
+            let mut mmap_areas = Vec::new();
+            if info.flags.mmap() {
+                if info.flags.caps() && info.cap_offset != 0 {
+                    mmap_areas = parse_sparse_mmap(&reply.payload, info.cap_offset)?;
+                } else {
+                    mmap_areas.push(protocol::SparseMmapArea {
+                        offset: 0,
+                        size: info.size,
+                    });
+                }
+            }
+            return Ok(RegionInfo {
+                info,
+                mmap_areas,
+                fd: reply.fds.pop(),
+            });
+        }
+    }
+
+    /// Gets the information for interrupt type `index`.
+    pub fn irq_info(&mut self, index: u32) -> Result<protocol::IrqInfo, Error> {
+        let info = protocol::IrqInfo {
+            argsz: size_of::<protocol::IrqInfo>() as u32,
+            index,
+            ..FromZeroes::new_zeroed()
+        };
+        let reply = self.request(Command::DEVICE_GET_IRQ_INFO, &[info.as_bytes()], &[])?;
+        Ok(reply.read_payload()?)
+    }
+
+    /// Sets the trigger eventfds for vectors `start..start + eventfds.len()`
+    /// of interrupt type `index`.
+    pub fn set_irq_eventfds(
+        &mut self,
+        index: u32,
+        start: u32,
+        eventfds: &[BorrowedFd<'_>],
+    ) -> Result<(), Error> {
+        for (i, eventfds) in eventfds.chunks(protocol::MAX_MSG_FDS).enumerate() {
+            let set = protocol::SetIrqs {
+                argsz: size_of::<protocol::SetIrqs>() as u32,
+                flags: protocol::SetIrqsFlags::new()
+                    .with_data_eventfd(true)
+                    .with_action_trigger(true),
+                index,
+                start: start + (i * protocol::MAX_MSG_FDS) as u32,
+                count: eventfds.len() as u32,
+            };
+            self.request(Command::DEVICE_SET_IRQS, &[set.as_bytes()], eventfds)?;
+        }
+        Ok(())
+    }
+
+    /// Shares the memory at `file_offset` in `fd` with the device for DMA at
+    /// `address`.
+    pub fn dma_map(
+        &mut self,
+        address: u64,
+        size: u64,
+        fd: BorrowedFd<'_>,
+        file_offset: u64,
+    ) -> Result<(), Error> {
+        let map = protocol::DmaMap {
+            argsz: size_of::<protocol::DmaMap>() as u32,
+            flags: protocol::DmaMapFlags::new()
+                .with_read(true)
+                .with_write(true),
+            offset: file_offset,
+            address,
+            size,
+        };
+        self.request(Command::DMA_MAP, &[map.as_bytes()], &[fd])?;
+        Ok(())
+    }
+
+    /// Reads `data.len()` bytes at `offset` in region `region`.
+    pub fn region_read(&mut self, region: u32, offset: u64, data: &mut [u8]) -> Result<(), Error> {
+        let access = protocol::RegionAccess {
+            offset,
+            region,
+            count: data.len() as u32,
+        };
+        let reply = self.request(Command::REGION_READ, &[access.as_bytes()], &[])?;
+        let reply_access = reply.read_payload::<protocol::RegionAccess>()?;
+        let reply_data = reply.trailing_payload::<protocol::RegionAccess>();
+        if reply_access.count as usize != data.len() || reply_data.len() != data.len() {
+            return Err(Error::UnexpectedMessage(reply.header));
+        }
+        data.copy_from_slice(reply_data);
+        Ok(())
+    }
+
+    /// Writes `data` at `offset` in region `region`.
+    pub fn region_write(&mut self, region: u32, offset: u64, data: &[u8]) -> Result<(), Error> {
+        let access = protocol::RegionAccess {
+            offset,
+            region,
+            count: data.len() as u32,
+        };
+        self.request(Command::REGION_WRITE, &[access.as_bytes(), data], &[])?;
+        Ok(())
+    }
+
+    /// Resets the device.
+    pub fn reset(&mut self) -> Result<(), Error> {
+        self.request(Command::DEVICE_RESET, &[], &[])?;
+        Ok(())
+    }
+}
+
+/// Converts a socket error, which is a timeout if the socket timeout expired.
+fn socket_error(err: io::Error) -> Error {
+    match err.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => Error::Timeout,
+        _ => Error::Io(err),
+    }
+}
+
+/// Finds the sparse mmap capability in a region info reply.
+fn parse_sparse_mmap(
+    payload: &[u8],
+    mut cap_offset: u32,
+) -> Result<Vec<protocol::SparseMmapArea>, Error> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid region capability");
+    // Bound the walk in case the capabilities form a loop.
+    for _ in 0..16 {
+        if cap_offset == 0 {
+            break;
+        }
+        let cap = payload.get(cap_offset as usize..).ok_or_else(invalid)?;
+        let header = protocol::CapabilityHeader::read_from_prefix(cap).ok_or_else(invalid)?;
+        if header.id == protocol::CAP_SPARSE_MMAP {
+            let sparse = protocol::SparseMmap::read_from_prefix(cap).ok_or_else(invalid)?;
+            let areas = cap
+                .get(size_of::<protocol::SparseMmap>()..)
+                .and_then(|areas| {
+                    areas.get(..sparse.nr_areas as usize * size_of::<protocol::SparseMmapArea>())
+                })
+                .ok_or_else(invalid)?;
+            return Ok(areas
+                .chunks_exact(size_of::<protocol::SparseMmapArea>())
+                .map(|area| protocol::SparseMmapArea::read_from(area).unwrap())
+                .collect());
+        }
+        cap_offset = header.next;
+    }
+    Ok(Vec::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Client;
+    use super::Error;
+    use guid::Guid;
+    use nvme::NvmeController;
+    use nvme::NvmeControllerCaps;
+    use pal_async::async_test;
+    use pal_async::DefaultDriver;
+    use std::os::unix::net::UnixStream;
+    use std::time::Duration;
+    use vfio_user::protocol;
+    use vfio_user::protocol::irq_index;
+    use vfio_user::protocol::region_index;
+    use vfio_user::socket::recv_message;
+    use vfio_user::socket::send_message;
+    use vfio_user_server::VfioUserServer;
+    use vmcore::vm_task::SingleDriverBackend;
+    use vmcore::vm_task::VmTaskDriverSource;
+    use zerocopy::AsBytes;
+
+    #[async_test]
+    async fn nvme_loopback(driver: DefaultDriver) {
+        let driver_source = VmTaskDriverSource::new(SingleDriverBackend::new(driver));
+        let mut server = VfioUserServer::new(1 << 30, |params| {
+            NvmeController::new(
+                &driver_source,
+                params.guest_memory.clone(),
+                params.register_msi,
+                params.register_mmio,
+                NvmeControllerCaps {
+                    msix_count: 64,
+                    max_io_queues: 64,
+                    subsystem_id: Guid::new_random(),
+                },
+            )
+        })
+        .unwrap();
+
+        let (client_socket, server_socket) = UnixStream::pair().unwrap();
+        let server_thread = std::thread::spawn(move || server.run(&server_socket));
+
+        let mut client = Client::new(client_socket).unwrap();
+        assert!(client.device_info().unwrap().flags.pci());
+
+        let mut value = [0; 4];
+        client
+            .region_read(region_index::CONFIG, 0, &mut value)
+            .unwrap();
+        assert_eq!(u32::from_ne_bytes(value), 0x00a9_1414);
+
+        let bar0 = client.region_info(region_index::BAR0).unwrap();
+        assert_eq!(bar0.info.size, 0x10000);
+        assert!(bar0.mmap_areas.is_empty());
+
+        // The controller capabilities register.
+        client
+            .region_read(region_index::BAR0, 0, &mut value)
+            .unwrap();
+        assert_eq!(u32::from_ne_bytes(value), 0xff01_00ff);
+
+        assert_eq!(client.irq_info(irq_index::MSIX).unwrap().count, 64);
+
+        drop(client);
+        server_thread.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn timeout() {
+        let (client_socket, server_socket) = UnixStream::pair().unwrap();
+        // Negotiate the version, then stop responding without closing the
+        // connection.
+        let server_thread = std::thread::spawn(move || {
+            let message = recv_message(&server_socket).unwrap().unwrap();
+            let version = protocol::Version {
+                major: protocol::MAJOR_VERSION,
+                minor: protocol::MINOR_VERSION,
+            };
+            send_message(
+                &server_socket,
+                message.header.reply(),
+                &[version.as_bytes()],
+                &[],
+            )
+            .unwrap();
+            let _message = recv_message(&server_socket).unwrap().unwrap();
+            server_socket
+        });
+
+        let mut client = Client::new(client_socket).unwrap();
+        client
+            .socket
+            .set_read_timeout(Some(Duration::from_millis(10)))
+            .unwrap();
+        assert!(matches!(client.device_info(), Err(Error::Timeout)));
+        assert!(client.is_failed());
+        assert!(matches!(client.device_info(), Err(Error::Failed)));
+        drop(server_thread.join().unwrap());
+    }
+}
@@ -0,0 +1,752 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A PCI device implemented by a vfio-user server in another process.
+//!
+//! The device's configuration space, BAR accesses, and reset are forwarded to
+//! the server over its Unix socket. The BAR registers and the MSI-X
+//! capability are emulated locally: the BARs so that they can be placed in the
+//! guest's address space, and MSI-X so that the interrupts signaled by the
+//! server (via eventfds) are delivered through the VM's MSI target. BAR areas
+//! that the server allows to be mapped are mapped directly into the guest, so
+//! that accesses to them do not exit to the VMM.
+//!
+//! Forwarded BAR accesses are deferred: the VP waits for the server's reply,
+//! but the device is not locked in the meantime. Configuration space accesses
+//! are rare, and are forwarded synchronously. If the server does not reply
+//! within [`client::REQUEST_TIMEOUT`], the device fails: reads return all ones
+//! and writes are dropped, as for a device that has been surprise removed.
+//!
+//! All of guest RAM is shared with the server at creation time via
+//! `DMA_MAP`, so the server never needs to issue `DMA_READ` or `DMA_WRITE`
+//! requests, and these are not supported. Since RAM hot-added later would not
+//! be shared, the device cannot be used in VMs with memory hot-add. Legacy
+//! INTx and MSI interrupts are also not supported.
+
+#![cfg(target_os = "linux")]
+#![warn(missing_docs)]
+
+pub mod client;
+pub mod resolver;
+
+use chipset_device::io::deferred::defer_read;
+use chipset_device::io::deferred::defer_write;
+use chipset_device::io::IoError;
+use chipset_device::io::IoResult;
+use chipset_device::mmio::ControlMmioIntercept;
+use chipset_device::mmio::MmioIntercept;
+use chipset_device::mmio::RegisterMmioIntercept;
+use chipset_device::pci::PciConfigSpace;
+use chipset_device::ChipsetDevice;
+use client::Client;
+use device_emulators::read_as_u32_chunks;
+use device_emulators::write_as_u32_chunks;
+use device_emulators::ReadWriteRequestType;
+use guestmem::MappableGuestMemory;
+use guestmem::MappedMemoryRegion;
+use guestmem::MemoryMapper;
+use guestmem::SharedGuestRam;
+use inspect::InspectMut;
+use pal_async::task::Spawn;
+use pal_async::task::Task;
+use pal_async::wait::PolledWait;
+use pal_event::Event;
+use parking_lot::Mutex;
+use pci_core::bar_mapping::BarMappings;
+use pci_core::capabilities::msix::MsixEmulator;
+use pci_core::capabilities::PciCapability;
+use pci_core::msi::RegisterMsi;
+use pci_core::spec::caps::msix::MsixCapabilityHeader;
+use pci_core::spec::caps::CapabilityId;
+use pci_core::spec::cfg_space;
+use pci_core::spec::cfg_space::HeaderType00;
+use std::io;
+use std::os::fd::AsFd;
+use std::os::unix::net::UnixStream;
+use std::sync::Arc;
+use thiserror::Error;
+use vfio_user::protocol::irq_index;
+use vfio_user::protocol::region_index;
+use vfio_user::protocol::DeviceFlags;
+use vmcore::device_state::ChangeDeviceState;
+use vmcore::interrupt::Interrupt;
+use vmcore::save_restore::RestoreError;
+use vmcore::save_restore::SaveError;
+use vmcore::save_restore::SaveRestore;
+use vmcore::save_restore::SavedStateNotSupported;
+use vmcore::vm_task::VmTaskDriverSource;
+
+/// An error creating a [`VfioUserDevice`].
+#[derive(Debug, Error)]
+#[allow(missing_docs)]
+pub enum DeviceError {
+    #[error("vfio-user request failed")]
+    Client(#[from] client::Error),
+    #[error("vfio-user device is not a PCI device")]
+    NotPci,
+    #[error("invalid size {size:#x} for bar {index}")]
+    InvalidBarSize { index: u8, size: u64 },
+    #[error("invalid msi-x capability")]
+    InvalidMsix,
+    #[error("vfio-user server does not support msi-x eventfds")]
+    MsixEventfd,
+    #[error("failed to get shared guest ram")]
+    GuestRam(#[source] io::Error),
+    #[error("failed to map bar {0}")]
+    MapBar(u8, #[source] io::Error),
+    #[error("failed to create interrupt event")]
+    Event(#[source] io::Error),
+}
+
+/// A PCI device implemented by a vfio-user server.
+pub struct VfioUserDevice {
+    /// The connection, shared with the threads that forward BAR accesses.
+    client: Arc<Mutex<Client>>,
+    device_flags: DeviceFlags,
+    bars: [Option<Bar>; 6],
+    bar_masks: [u32; 6],
+    base_addresses: [u32; 6],
+    command: cfg_space::Command,
+    msix: Option<Msix>,
+    _interrupt_tasks: Vec<Task<()>>,
+}
+
+struct Bar {
+    /// The encoding bits of the BAR register, reported by the server.
+    encoding: u32,
+    control_mmio: Box<dyn ControlMmioIntercept>,
+    /// The guest mapping of the BAR's mappable areas.
+    mapping: Option<(Box<dyn MappableGuestMemory>, Arc<dyn MappedMemoryRegion>)>,
+    mapped_address: Option<u64>,
+}
+
+/// The location of the MSI-X table and pending bit array.
+#[derive(Copy, Clone)]
+struct MsixLayout {
+    /// The offset of the capability in configuration space.
+    cap_offset: u16,
+    count: u16,
+    table_bar: u8,
+    table_offset: u64,
+    pba_bar: u8,
+    pba_offset: u64,
+}
+
+impl MsixLayout {
+    fn table_len(&self) -> u64 {
+        self.count as u64 * 16
+    }
+
+    /// The PBA is accessed in quadwords.
+    fn pba_len(&self) -> u64 {
+        (self.count as u64).div_ceil(64) * 8
+    }
+
+    /// Returns the offset within the emulator's table layout for `offset` in
+    /// `bar`, if it falls within the MSI-X table or pending bit array.
+    fn emulator_offset(&self, bar: u8, offset: u64) -> Option<u16> {
+        if bar == self.table_bar
+            && (self.table_offset..self.table_offset + self.table_len()).contains(&offset)
+        {
+            Some((offset - self.table_offset) as u16)
+        } else if bar == self.pba_bar
+            && (self.pba_offset..self.pba_offset + self.pba_len()).contains(&offset)
+        {
+            Some((self.table_len() + offset - self.pba_offset) as u16)
+        } else {
+            None
+        }
+    }
+
+    /// Returns whether the area `offset..offset + len` of `bar` shares a page
+    /// with the table or pending bit array.
+    fn overlaps(&self, bar: u8, offset: u64, len: u64) -> bool {
+        let overlaps = |start: u64, end: u64| {
+            offset < end.next_multiple_of(PAGE_SIZE) && start & !(PAGE_SIZE - 1) < offset + len
+        };
+        (bar == self.table_bar && overlaps(self.table_offset, self.table_offset + self.table_len()))
+            || (bar == self.pba_bar && overlaps(self.pba_offset, self.pba_offset + self.pba_len()))
+    }
+}
+
+struct Msix {
+    layout: MsixLayout,
+    emulator: MsixEmulator,
+    capability: Box<dyn PciCapability>,
+    interrupts: Vec<Interrupt>,
+    function_mask: Arc<Mutex<FunctionMask>>,
+}
+
+/// The MSI-X function mask, which the emulator does not implement.
+///
+/// While the function is masked, interrupts signaled by the server are held
+/// here as pending, and delivered when the function is unmasked.
+struct FunctionMask {
+    masked: bool,
+    pending: Vec<bool>,
+}
+
+impl Msix {
+    fn read_u32(&self, offset: u16) -> u32 {
+        // The emulator's PBA is dword granular.
+        if u64::from(offset) >= self.emulator.bar_len() {
+            return 0;
+        }
+        let mut value = self.emulator.read_u32(offset);
+        if u64::from(offset) >= self.layout.table_len() {
+            let start = (u64::from(offset) - self.layout.table_len()) as usize / 4 * 32;
+            let function_mask = self.function_mask.lock();
+            for (i, &pending) in function_mask
+                .pending
+                .iter()
+                .skip(start)
+                .take(32)
+                .enumerate()
+            {
+                if pending {
+                    value |= 1 << i;
+                }
+            }
+        }
+        value
+    }
+
+    fn write_u32(&mut self, offset: u16, value: u32) {
+        if u64::from(offset) < self.emulator.bar_len() {
+            self.emulator.write_u32(offset, value)
+        }
+    }
+
+    fn function_masked(&self) -> bool {
+        self.function_mask.lock().masked
+    }
+
+    fn set_function_mask(&mut self, masked: bool) {
+        let mut function_mask = self.function_mask.lock();
+        function_mask.masked = masked;
+        if !masked {
+            // Deliver the interrupts that were held while the function was
+            // masked. The emulator holds them again if their vectors are
+            // masked.
+            for (interrupt, pending) in self.interrupts.iter().zip(&mut function_mask.pending) {
+                if std::mem::take(pending) {
+                    interrupt.deliver();
+                }
+            }
+        }
+    }
+}
+
+const PAGE_SIZE: u64 = 4096;
+
+impl VfioUserDevice {
+    /// Connects to the vfio-user server listening on `socket` and creates a
+    /// device for it.
+    ///
+    /// All of guest RAM, as reported by `shared_guest_ram`, is shared with the
+    /// server. If `shared_mem_mapper` is provided, then BAR areas the server
+    /// allows to be mapped are mapped into the guest.
+    pub fn new(
+        driver_source: &VmTaskDriverSource,
+        socket: UnixStream,
+        register_msi: &mut dyn RegisterMsi,
+        register_mmio: &mut dyn RegisterMmioIntercept,
+        shared_mem_mapper: Option<&dyn MemoryMapper>,
+        shared_guest_ram: &dyn SharedGuestRam,
+    ) -> Result<Self, DeviceError> {
+        let mut client = Client::new(socket)?;
+        let device_info = client.device_info()?;
+        if !device_info.flags.pci() {
+            return Err(DeviceError::NotPci);
+        }
+
+        for range in shared_guest_ram
+            .shared_ram_ranges()
+            .map_err(DeviceError::GuestRam)?
+        {
+            client.dma_map(
+                range.gpa,
+                range.len,
+                range.mappable.as_fd(),
+                range.file_offset,
+            )?;
+        }
+
+        let msix = Self::find_msix(&mut client)?;
+
+        let mut bars = [const { None }; 6];
+        let mut bar_masks = [0; 6];
+        let mut index = 0;
+        while index < 6 {
+            let info = client.region_info(region_index::BAR0 + index as u32)?;
+            let mut encoding = [0; 4];
+            client.region_read(
+                region_index::CONFIG,
+                HeaderType00::BAR0.0 as u64 + index as u64 * 4,
+                &mut encoding,
+            )?;
+            let encoding = u32::from_ne_bytes(encoding) & 0xf;
+            let bits = cfg_space::BarEncodingBits::from_bits(encoding);
+            let is_64_bit = bits.type_64_bit();
+            let len = info.info.size;
+            if len == 0 {
+                index += 1;
+                continue;
+            }
+            if bits.use_pio() {
+                tracing::warn!(index, "vfio-user io port bars are not supported");
+                index += 1;
+                continue;
+            }
+            if !len.is_power_of_two()
+                || len < 16
+                || (is_64_bit && index == 5)
+                || (!is_64_bit && len > 1 << 31)
+            {
+                return Err(DeviceError::InvalidBarSize { index, size: len });
+            }
+
+            let mask = !(len - 1);
+            bar_masks[index as usize] = mask as u32 | encoding;
+            if is_64_bit {
+                bar_masks[index as usize + 1] = (mask >> 32) as u32;
+            }
+
+            let name = format!("vfio-user-bar{index}");
+            let mapping = match (shared_mem_mapper, &info.fd) {
+                (Some(mapper), Some(fd)) if !info.mmap_areas.is_empty() => {
+                    let (mappable, region) = mapper
+                        .new_region(len as usize, name.clone())
+                        .map_err(|err| DeviceError::MapBar(index, err))?;
+                    for area in &info.mmap_areas {
+                        if msix.is_some_and(|msix| msix.overlaps(index, area.offset, area.size)) {
+                            continue;
+                        }
+                        region
+                            .map(
+                                area.offset as usize,
+                                fd,
+                                info.info.offset + area.offset,
+                                area.size as usize,
+                                true,
+                            )
+                            .map_err(|err| DeviceError::MapBar(index, err))?;
+                    }
+                    Some((mappable, region))
+                }
+                _ => None,
+            };
+
+            bars[index as usize] = Some(Bar {
+                encoding,
+                control_mmio: register_mmio.new_io_region(&name, len),
+                mapping,
+                mapped_address: None,
+            });
+            index += if is_64_bit { 2 } else { 1 };
+        }
+
+        let mut interrupt_tasks = Vec::new();
+        let msix = if let Some(mut layout) = msix {
+            let irq_info = client.irq_info(irq_index::MSIX)?;
+            if !irq_info.flags.eventfd() {
+                return Err(DeviceError::MsixEventfd);
+            }
+            layout.count = layout.count.min(irq_info.count as u16);
+            if layout.count == 0 {
+                return Err(DeviceError::InvalidMsix);
+            }
+            let (emulator, capability) =
+                MsixEmulator::new(layout.table_bar, layout.count, register_msi);
+
+            let function_mask = Arc::new(Mutex::new(FunctionMask {
+                masked: false,
+                pending: vec![false; layout.count.into()],
+            }));
+            let interrupts = (0..layout.count)
+                .map(|vector| emulator.interrupt(vector).unwrap())
+                .collect::<Vec<_>>();
+
+            let driver = driver_source.simple();
+            let mut events = Vec::new();
+            for (vector, interrupt) in interrupts.iter().enumerate() {
+                let event = Event::new();
+                let mut wait =
+                    PolledWait::new(&driver, event.clone()).map_err(DeviceError::Event)?;
+                let interrupt = interrupt.clone();
+                let function_mask = function_mask.clone();
+                interrupt_tasks.push(driver.spawn("vfio-user-msix", async move {
+                    while wait.wait().await.is_ok() {
+                        let mut function_mask = function_mask.lock();
+                        if function_mask.masked {
+                            function_mask.pending[vector] = true;
+                        } else {
+                            interrupt.deliver();
+                        }
+                    }
+                }));
+                events.push(event);
+            }
+            let fds = events.iter().map(|event| event.as_fd()).collect::<Vec<_>>();
+            client.set_irq_eventfds(irq_index::MSIX, 0, &fds)?;
+
+            Some(Msix {
+                layout,
+                emulator,
+                capability: Box::new(capability),
+                interrupts,
+                function_mask,
+            })
+        } else {
+            None
+        };
+
+        Ok(Self {
+            client: Arc::new(Mutex::new(client)),
+            device_flags: device_info.flags,
+            bars,
+            bar_masks,
+            base_addresses: [0; 6],
+            command: cfg_space::Command::new(),
+            msix,
+            _interrupt_tasks: interrupt_tasks,
+        })
+    }
+
+    /// Finds the device's MSI-X capability.
+    fn find_msix(client: &mut Client) -> Result<Option<MsixLayout>, DeviceError> {
+        let read = |client: &mut Client, offset: u16| {
+            let mut value = [0; 4];
+            client.region_read(region_index::CONFIG, offset.into(), &mut value)?;
+            Ok::<_, DeviceError>(u32::from_ne_bytes(value))
+        };
+
+        let mut offset = (read(client, HeaderType00::RESERVED_CAP_PTR.0)? & 0xfc) as u16;
+        // Bound the walk in case the capabilities form a loop.
+        for _ in 0..48 {
+            if offset == 0 {
+                break;
+            }
+            let header = read(client, offset)?;
+            if header as u8 == CapabilityId::MSIX.0 {
+                let table = read(client, offset + MsixCapabilityHeader::OFFSET_TABLE.0)?;
+                let pba = read(client, offset + MsixCapabilityHeader::OFFSET_PBA.0)?;
+                if table & 7 >= 6 || pba & 7 >= 6 {
+                    return Err(DeviceError::InvalidMsix);
+                }
+                return Ok(Some(MsixLayout {
+                    cap_offset: offset,
+                    count: ((header >> 16) as u16 & 0x7ff) + 1,
+                    table_bar: table as u8 & 7,
+                    table_offset: (table & !7).into(),
+                    pba_bar: pba as u8 & 7,
+                    pba_offset: (pba & !7).into(),
+                }));
+            }
+            offset = ((header >> 8) & 0xfc) as u16;
+        }
+        Ok(None)
+    }
+
+    /// Maps or unmaps the BARs according to the current BAR registers and
+    /// command register.
+    fn update_mappings(&mut self) {
+        let mappings = BarMappings::parse(&self.base_addresses, &self.bar_masks);
+        for (index, bar) in self.bars.iter_mut().enumerate() {
+            let Some(bar) = bar else { continue };
+            let address = if self.command.mmio_enabled() {
+                mappings.get(index as u8)
+            } else {
+                None
+            };
+            if address == bar.mapped_address {
+                continue;
+            }
+            bar.control_mmio.unmap();
+            if let Some((mappable, _)) = &mut bar.mapping {
+                mappable.unmap_from_guest();
+            }
+            bar.mapped_address = address;
+            if let Some(address) = address {
+                bar.control_mmio.map(address);
+                if let Some((mappable, _)) = &mut bar.mapping {
+                    if let Err(err) = mappable.map_to_guest(address, true) {
+                        // Accesses will still be forwarded via the MMIO
+                        // intercept.
+                        tracing::error!(
+                            error = &err as &dyn std::error::Error,
+                            index,
+                            address,
+                            "failed to map bar"
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    fn find_bar(&self, address: u64) -> Option<(u8, u64)> {
+        self.bars.iter().enumerate().find_map(|(index, bar)| {
+            Some((index as u8, bar.as_ref()?.control_mmio.offset_of(address)?))
+        })
+    }
+
+    fn forward_config_read(&mut self, offset: u16) -> u32 {
+        let mut value = [0; 4];
+        match self
+            .client
+            .lock()
+            .region_read(region_index::CONFIG, offset.into(), &mut value)
+        {
+            Ok(()) => u32::from_ne_bytes(value),
+            Err(err) => {
+                tracelimit::error_ratelimited!(
+                    error = &err as &dyn std::error::Error,
+                    offset,
+                    "vfio-user config space read failed"
+                );
+                !0
+            }
+        }
+    }
+
+    fn forward_config_write(&mut self, offset: u16, value: u32) {
+        if let Err(err) = self.client.lock().region_write(
+            region_index::CONFIG,
+            offset.into(),
+            &value.to_ne_bytes(),
+        ) {
+            tracelimit::error_ratelimited!(
+                error = &err as &dyn std::error::Error,
+                offset,
+                "vfio-user config space write failed"
+            );
+        }
+    }
+}
+
+impl InspectMut for VfioUserDevice {
+    fn inspect_mut(&mut self, req: inspect::Request<'_>) {
+        let mut resp = req.respond();
+        resp.field("command", self.command)
+            .field(
+                "bars",
+                BarMappings::parse(&self.base_addresses, &self.bar_masks),
+            )
+            .field("reset_supported", self.device_flags.reset())
+            // Skip the field rather than wait for an outstanding request.
+            .field(
+                "failed",
+                self.client.try_lock().map(|client| client.is_failed()),
+            );
+        if let Some(msix) = &self.msix {
+            resp.field("msix_count", msix.layout.count)
+                .field("msix_function_mask", msix.function_masked())
+                .field("msix", &*msix.capability);
+        }
+    }
+}
+
+impl ChangeDeviceState for VfioUserDevice {
+    fn start(&mut self) {}
+
+    async fn stop(&mut self) {}
+
+    async fn reset(&mut self) {
+        if self.device_flags.reset() {
+            let client = self.client.clone();
+            if let Err(err) = blocking::unblock(move || client.lock().reset()).await {
+                tracing::error!(
+                    error = &err as &dyn std::error::Error,
+                    "vfio-user device reset failed"
+                );
+            }
+        }
+        self.base_addresses = [0; 6];
+        self.command = cfg_space::Command::new();
+        self.update_mappings();
+        if let Some(msix) = &mut self.msix {
+            msix.capability.reset();
+            let mut function_mask = msix.function_mask.lock();
+            function_mask.masked = false;
+            function_mask.pending.fill(false);
+        }
+    }
+}
+
+impl ChipsetDevice for VfioUserDevice {
+    fn supports_mmio(&mut self) -> Option<&mut dyn MmioIntercept> {
+        Some(self)
+    }
+
+    fn supports_pci(&mut self) -> Option<&mut dyn PciConfigSpace> {
+        Some(self)
+    }
+}
+
+impl MmioIntercept for VfioUserDevice {
+    fn mmio_read(&mut self, addr: u64, data: &mut [u8]) -> IoResult {
+        let Some((bar, offset)) = self.find_bar(addr) else {
+            return IoResult::Err(IoError::InvalidRegister);
+        };
+        if let Some(msix) = &self.msix {
+            if let Some(offset) = msix.layout.emulator_offset(bar, offset) {
+                read_as_u32_chunks(offset, data, |offset| msix.read_u32(offset));
+                return IoResult::Ok;
+            }
+        }
+        // Deferred reads complete with at most 8 bytes.
+        let len = data.len();
+        if len > 8 {
+            return IoResult::Err(IoError::InvalidAccessSize);
+        }
+        // Wait for the server on another thread, so that the device is not
+        // locked in the meantime.
+        let (deferred, token) = defer_read();
+        let client = self.client.clone();
+        blocking::unblock(move || {
+            let mut data = [0; 8];
+            let data = &mut data[..len];
+            if let Err(err) =
+                client
+                    .lock()
+                    .region_read(region_index::BAR0 + bar as u32, offset, data)
+            {
+                tracelimit::error_ratelimited!(
+                    error = &err as &dyn std::error::Error,
+                    bar,
+                    offset,
+                    "vfio-user bar read failed"
+                );
+                data.fill(!0);
+            }
+            deferred.complete(data);
+        })
+        .detach();
+        IoResult::Defer(token)
+    }
+
+    fn mmio_write(&mut self, addr: u64, data: &[u8]) -> IoResult {
+        let Some((bar, offset)) = self.find_bar(addr) else {
+            return IoResult::Err(IoError::InvalidRegister);
+        };
+        if let Some(msix) = &mut self.msix {
+            if let Some(offset) = msix.layout.emulator_offset(bar, offset) {
+                write_as_u32_chunks(offset, data, |offset, ty| match ty {
+                    ReadWriteRequestType::Read => Some(msix.read_u32(offset)),
+                    ReadWriteRequestType::Write(val) => {
+                        msix.write_u32(offset, val);
+                        None
+                    }
+                });
+                return IoResult::Ok;
+            }
+        }
+        let (deferred, token) = defer_write();
+        let client = self.client.clone();
+        let data = data.to_vec();
+        blocking::unblock(move || {
+            if let Err(err) =
+                client
+                    .lock()
+                    .region_write(region_index::BAR0 + bar as u32, offset, &data)
+            {
+                tracelimit::error_ratelimited!(
+                    error = &err as &dyn std::error::Error,
+                    bar,
+                    offset,
+                    "vfio-user bar write failed"
+                );
+            }
+            deferred.complete();
+        })
+        .detach();
+        IoResult::Defer(token)
+    }
+}
+
+impl PciConfigSpace for VfioUserDevice {
+    fn pci_cfg_read(&mut self, offset: u16, value: &mut u32) -> IoResult {
+        *value = match HeaderType00(offset) {
+            HeaderType00::BAR0
+            | HeaderType00::BAR1
+            | HeaderType00::BAR2
+            | HeaderType00::BAR3
+            | HeaderType00::BAR4
+            | HeaderType00::BAR5 => {
+                let index = (offset - HeaderType00::BAR0.0) as usize / 4;
+                let encoding = self.bars[index].as_ref().map_or(0, |bar| bar.encoding);
+                (self.base_addresses[index] & self.bar_masks[index]) | encoding
+            }
+            // Expansion ROMs are not supported.
+            HeaderType00::EXPANSION_ROM_BASE => 0,
+            _ if self
+                .msix
+                .as_ref()
+                .is_some_and(|msix| msix.layout.cap_offset == offset) =>
+            {
+                let remote = self.forward_config_read(offset);
+                let msix = self.msix.as_ref().unwrap();
+                let control = msix
+                    .capability
+                    .read_u32(MsixCapabilityHeader::CONTROL_CAPS.0);
+                (remote & 0x3fff_ffff)
+                    | (control & 0x8000_0000)
+                    | if msix.function_masked() {
+                        0x4000_0000
+                    } else {
+                        0
+                    }
+            }
+            _ => self.forward_config_read(offset),
+        };
+        IoResult::Ok
+    }
+
+    fn pci_cfg_write(&mut self, offset: u16, value: u32) -> IoResult {
+        match HeaderType00(offset) {
+            HeaderType00::STATUS_COMMAND => {
+                self.forward_config_write(offset, value);
+                self.command = cfg_space::Command::from_bits(value as u16);
+                self.update_mappings();
+            }
+            HeaderType00::BAR0
+            | HeaderType00::BAR1
+            | HeaderType00::BAR2
+            | HeaderType00::BAR3
+            | HeaderType00::BAR4
+            | HeaderType00::BAR5 => {
+                let index = (offset - HeaderType00::BAR0.0) as usize / 4;
+                self.base_addresses[index] = value & self.bar_masks[index];
+                self.update_mappings();
+            }
+            HeaderType00::EXPANSION_ROM_BASE => {}
+            _ if self
+                .msix
+                .as_ref()
+                .is_some_and(|msix| msix.layout.cap_offset == offset) =>
+            {
+                // MSI-X is emulated locally, so the server never sees the
+                // enable or function mask bits.
+                let msix = self.msix.as_mut().unwrap();
+                msix.capability
+                    .write_u32(MsixCapabilityHeader::CONTROL_CAPS.0, value);
+                msix.set_function_mask(value & 0x4000_0000 != 0);
+            }
+            _ => self.forward_config_write(offset, value),
+        }
+        IoResult::Ok
+    }
+}
+
+impl SaveRestore for VfioUserDevice {
+    type SavedState = SavedStateNotSupported;
+
+    fn save(&mut self) -> Result<Self::SavedState, SaveError> {
+        Err(SaveError::NotSupported)
+    }
+
+    fn restore(&mut self, state: Self::SavedState) -> Result<(), RestoreError> {
+        match state {}
+    }
+}
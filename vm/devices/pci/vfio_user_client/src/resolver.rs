@@ -0,0 +1,66 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Resource resolver for vfio-user devices.
+
+use crate::DeviceError;
+use crate::VfioUserDevice;
+use async_trait::async_trait;
+use pci_resources::ResolvePciDeviceHandleParams;
+use pci_resources::ResolvedPciDevice;
+use std::os::unix::net::UnixStream;
+use thiserror::Error;
+use vfio_user_resources::VfioUserDeviceHandle;
+use vm_resource::declare_static_async_resolver;
+use vm_resource::kind::PciDeviceHandleKind;
+use vm_resource::AsyncResolveResource;
+use vm_resource::ResourceResolver;
+
+/// Resource resolver for [`VfioUserDeviceHandle`].
+pub struct VfioUserResolver;
+
+declare_static_async_resolver! {
+    VfioUserResolver,
+    (PciDeviceHandleKind, VfioUserDeviceHandle),
+}
+
+/// Error returned by [`VfioUserResolver`].
+#[derive(Debug, Error)]
+#[allow(missing_docs)]
+pub enum ResolveVfioUserError {
+    #[error("failed to connect to vfio-user server at {0}")]
+    Connect(String, #[source] std::io::Error),
+    #[error("guest ram cannot be shared with a vfio-user server (is memory hot-add enabled?)")]
+    NoSharedGuestRam,
+    #[error("failed to create vfio-user device")]
+    Device(#[source] DeviceError),
+}
+
+#[async_trait]
+impl AsyncResolveResource<PciDeviceHandleKind, VfioUserDeviceHandle> for VfioUserResolver {
+    type Output = ResolvedPciDevice;
+    type Error = ResolveVfioUserError;
+
+    async fn resolve(
+        &self,
+        _resolver: &ResourceResolver,
+        resource: VfioUserDeviceHandle,
+        input: ResolvePciDeviceHandleParams<'_>,
+    ) -> Result<Self::Output, Self::Error> {
+        let shared_guest_ram = input
+            .shared_guest_ram
+            .ok_or(ResolveVfioUserError::NoSharedGuestRam)?;
+        let socket = UnixStream::connect(&resource.socket_path)
+            .map_err(|err| ResolveVfioUserError::Connect(resource.socket_path, err))?;
+        let device = VfioUserDevice::new(
+            input.driver_source,
+            socket,
+            input.register_msi,
+            input.register_mmio,
+            input.shared_mem_mapper,
+            shared_guest_ram,
+        )
+        .map_err(ResolveVfioUserError::Device)?;
+        Ok(device.into())
+    }
+}
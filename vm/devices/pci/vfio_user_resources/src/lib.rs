@@ -0,0 +1,23 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Resource definitions for PCI devices emulated by a vfio-user server.
+
+#![forbid(unsafe_code)]
+#![warn(missing_docs)]
+
+use mesh::MeshPayload;
+use vm_resource::kind::PciDeviceHandleKind;
+use vm_resource::ResourceId;
+
+/// A handle to a PCI device emulated in another process, reached via the
+/// vfio-user protocol.
+#[derive(MeshPayload)]
+pub struct VfioUserDeviceHandle {
+    /// The path of the server's Unix socket.
+    pub socket_path: String,
+}
+
+impl ResourceId<PciDeviceHandleKind> for VfioUserDeviceHandle {
+    const ID: &'static str = "vfio-user";
+}
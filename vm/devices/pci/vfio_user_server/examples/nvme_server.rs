@@ -0,0 +1,67 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Hosts an NVMe controller with a RAM-backed namespace on a vfio-user socket.
+//!
+//! Usage: `nvme_server <socket path> [disk size in MiB]`
+//!
+//! Then start OpenVMM with `--vfio-user <socket path>,pcie_port=<port>`.
+
+#[cfg(target_os = "linux")]
+fn main() -> anyhow::Result<()> {
+    use anyhow::Context;
+    use futures::executor::block_on;
+    use guid::Guid;
+    use nvme::NvmeController;
+    use nvme::NvmeControllerCaps;
+    use pal_async::task::Spawn;
+    use pal_async::DefaultPool;
+    use std::os::unix::net::UnixListener;
+    use vfio_user_server::VfioUserServer;
+    use vmcore::vm_task::SingleDriverBackend;
+    use vmcore::vm_task::VmTaskDriverSource;
+
+    let mut args = std::env::args().skip(1);
+    let path = args.next().context("missing socket path")?;
+    let size_mb = args
+        .next()
+        .map(|s| s.parse::<u64>())
+        .transpose()
+        .context("invalid disk size")?
+        .unwrap_or(64);
+
+    let pool = DefaultPool::new();
+    let driver = pool.driver();
+    // Ensure the pool stays alive with no tasks.
+    driver.spawn("leak", std::future::pending::<()>()).detach();
+    std::thread::Builder::new()
+        .name("nvme".to_owned())
+        .spawn(|| pool.run())?;
+    let driver_source = VmTaskDriverSource::new(SingleDriverBackend::new(driver));
+
+    // Allow DMA to any guest address below 1TB.
+    let mut server = VfioUserServer::new(1 << 40, |params| {
+        NvmeController::new(
+            &driver_source,
+            params.guest_memory.clone(),
+            params.register_msi,
+            params.register_mmio,
+            NvmeControllerCaps {
+                msix_count: 64,
+                max_io_queues: 64,
+                subsystem_id: Guid::new_random(),
+            },
+        )
+    })?;
+
+    let disk = disklayer_ram::ram_disk(size_mb << 20, false)?;
+    block_on(server.device().client().add_namespace(1, disk))?;
+
+    let listener = UnixListener::bind(&path).with_context(|| format!("failed to bind {path}"))?;
+    let (socket, _) = listener.accept()?;
+    server.run(&socket)?;
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn main() {}
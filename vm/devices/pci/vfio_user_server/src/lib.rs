@@ -0,0 +1,569 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A vfio-user server hosting an emulated PCI device, so that the device can
+//! run in a separate process from the VMM.
+//!
+//! The server owns the device's BARs: it assigns them private addresses and
+//! keeps memory decoding enabled, translating the client's region accesses to
+//! MMIO at those addresses. The server also programs the device's MSI-X table
+//! itself so that each vector signals the eventfd provided by the client; the
+//! client emulates the guest-visible MSI-X state.
+//!
+//! DMA memory shared by the client via `DMA_MAP` is mapped into a reserved
+//! address range, which backs the device's guest memory.
+
+#![cfg(target_os = "linux")]
+#![warn(missing_docs)]
+
+use chipset_device::io::IoResult;
+use chipset_device::mmio::ExternallyManagedMmioIntercepts;
+use chipset_device::mmio::RegisterMmioIntercept;
+use chipset_device::ChipsetDevice;
+use futures::executor::block_on;
+use guestmem::GuestMemory;
+use nix::errno::Errno;
+use pal_event::Event;
+use parking_lot::Mutex;
+use pci_core::bar_mapping::BarMappings;
+use pci_core::msi::MsiControl;
+use pci_core::msi::MsiInterruptSet;
+use pci_core::msi::MsiInterruptTarget;
+use pci_core::msi::RegisterMsi;
+use pci_core::spec::caps::msix::MsixCapabilityHeader;
+use pci_core::spec::caps::msix::MsixTableEntryIdx;
+use pci_core::spec::caps::CapabilityId;
+use pci_core::spec::cfg_space;
+use pci_core::spec::cfg_space::HeaderType00;
+use sparse_mmap::SparseMapping;
+use std::future::poll_fn;
+use std::io;
+use std::os::fd::OwnedFd;
+use std::os::unix::net::UnixStream;
+use std::sync::Arc;
+use thiserror::Error;
+use vfio_user::protocol;
+use vfio_user::protocol::irq_index;
+use vfio_user::protocol::region_index;
+use vfio_user::protocol::Command;
+use vfio_user::socket::recv_message;
+use vfio_user::socket::send_message;
+use vfio_user::socket::Message;
+use vmcore::device_state::ChangeDeviceState;
+use zerocopy::AsBytes;
+
+/// The capabilities sent to the client during version negotiation.
+const CAPABILITIES: &str = r#"{"capabilities":{"max_msg_fds":16,"max_data_xfer_size":1048576}}"#;
+
+/// The size of the configuration space region.
+const CONFIG_SPACE_SIZE: u64 = 4096;
+
+/// The private address of the first BAR. BARs are allocated upwards from here
+/// in the device's address space, which is never visible to the client.
+const BAR_BASE: u64 = 0x8000_0000;
+
+/// The MSI address programmed into the device's MSI-X table. The data is the
+/// vector index.
+const MSI_ADDRESS: u64 = 0xfee0_0000;
+
+/// Parameters for building the device hosted by a [`VfioUserServer`].
+pub struct DeviceBuildParams<'a> {
+    /// The memory shared by the client for DMA.
+    pub guest_memory: &'a GuestMemory,
+    /// The target for MSI interrupts.
+    pub register_msi: &'a mut dyn RegisterMsi,
+    /// An object with which to register MMIO regions. The server manages the
+    /// device's BARs itself, so this does nothing.
+    pub register_mmio: &'a mut dyn RegisterMmioIntercept,
+}
+
+/// An error creating a [`VfioUserServer`].
+#[derive(Debug, Error)]
+#[allow(missing_docs)]
+pub enum Error {
+    #[error("failed to reserve the dma address space")]
+    DmaReserve(#[source] io::Error),
+    #[error("device does not support pci and mmio")]
+    NotPci,
+}
+
+/// A vfio-user server for a PCI device.
+pub struct VfioUserServer<T> {
+    device: T,
+    dma: Arc<SparseMapping>,
+    bars: [Option<(u64, u64)>; 6],
+    msix: Option<Msix>,
+    irq_events: Arc<Mutex<Vec<Option<Event>>>>,
+}
+
+struct Msix {
+    cap_offset: u16,
+    count: u16,
+    table_bar: u8,
+    table_offset: u64,
+}
+
+/// Signals the client's eventfd for the vector identified by the MSI data.
+struct EventfdTarget(Arc<Mutex<Vec<Option<Event>>>>);
+
+impl MsiInterruptTarget for EventfdTarget {
+    fn new_interrupt(&self) -> Box<dyn MsiControl> {
+        let events = self.0.clone();
+        Box::new(move |_address: u64, data: u32| {
+            if let Some(Some(event)) = events.lock().get(data as usize) {
+                event.signal();
+            }
+        })
+    }
+}
+
+impl<T: ChipsetDevice + ChangeDeviceState> VfioUserServer<T> {
+    /// Creates a new server for the device returned by `build_device`.
+    ///
+    /// `dma_size` is the size of the DMA address space; the client can only
+    /// share memory below this address.
+    pub fn new(
+        dma_size: u64,
+        build_device: impl FnOnce(DeviceBuildParams<'_>) -> T,
+    ) -> Result<Self, Error> {
+        let dma = Arc::new(SparseMapping::new(dma_size as usize).map_err(Error::DmaReserve)?);
+        let guest_memory = GuestMemory::new("vfio-user-dma", dma.clone());
+        let mut msi_set = MsiInterruptSet::new();
+        let mut device = build_device(DeviceBuildParams {
+            guest_memory: &guest_memory,
+            register_msi: &mut msi_set,
+            register_mmio: &mut ExternallyManagedMmioIntercepts,
+        });
+        if device.supports_pci().is_none() || device.supports_mmio().is_none() {
+            return Err(Error::NotPci);
+        }
+
+        let irq_events = Arc::new(Mutex::new(Vec::new()));
+        msi_set.connect(&EventfdTarget(irq_events.clone()));
+
+        let mut server = Self {
+            device,
+            dma,
+            bars: [None; 6],
+            msix: None,
+            irq_events,
+        };
+        server.size_bars();
+        server.msix = server.find_msix();
+        if let Some(msix) = &server.msix {
+            server
+                .irq_events
+                .lock()
+                .resize_with(msix.count.into(), || None);
+        }
+        server.program_device();
+        server.device.start();
+        Ok(server)
+    }
+
+    /// Returns the hosted device.
+    pub fn device(&self) -> &T {
+        &self.device
+    }
+
+    /// Serves requests from the client connected to `socket` until it
+    /// disconnects.
+    pub fn run(&mut self, socket: &UnixStream) -> io::Result<()> {
+        while let Some(mut message) = recv_message(socket)? {
+            let header = message.header;
+            if header.flags.message_type() != protocol::MESSAGE_TYPE_COMMAND {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unexpected message {:?}", header),
+                ));
+            }
+            match self.handle_command(&mut message) {
+                Ok(reply) => {
+                    if !header.flags.no_reply() {
+                        send_message(socket, header.reply(), &[&reply], &[])?;
+                    }
+                }
+                Err(errno) => {
+                    tracing::debug!(command = ?header.command, %errno, "vfio-user command failed");
+                    send_message(socket, header.error_reply(errno as i32), &[], &[])?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_command(&mut self, message: &mut Message) -> Result<Vec<u8>, Errno> {
+        let reply = match message.header.command {
+            Command::VERSION => {
+                let version = protocol::Version {
+                    major: protocol::MAJOR_VERSION,
+                    minor: protocol::MINOR_VERSION,
+                };
+                let mut reply = version.as_bytes().to_vec();
+                reply.extend_from_slice(CAPABILITIES.as_bytes());
+                reply.push(0);
+                reply
+            }
+            Command::DMA_MAP => {
+                let map = read_payload::<protocol::DmaMap>(message)?;
+                let fd = message.fds.first().ok_or(Errno::EINVAL)?;
+                self.dma
+                    .map_file(
+                        map.address as usize,
+                        map.size as usize,
+                        fd,
+                        map.offset,
+                        map.flags.write(),
+                    )
+                    .map_err(errno)?;
+                Vec::new()
+            }
+            Command::DMA_UNMAP => {
+                let unmap = read_payload::<protocol::DmaUnmap>(message)?;
+                self.dma
+                    .unmap(unmap.address as usize, unmap.size as usize)
+                    .map_err(errno)?;
+                unmap.as_bytes().to_vec()
+            }
+            Command::DEVICE_GET_INFO => protocol::DeviceInfo {
+                argsz: size_of::<protocol::DeviceInfo>() as u32,
+                flags: protocol::DeviceFlags::new().with_reset(true).with_pci(true),
+                num_regions: region_index::VGA + 1,
+                num_irqs: irq_index::REQ + 1,
+            }
+            .as_bytes()
+            .to_vec(),
+            Command::DEVICE_GET_REGION_INFO => {
+                let info = read_payload::<protocol::RegionInfo>(message)?;
+                let size = self.region_size(info.index).ok_or(Errno::EINVAL)?;
+                protocol::RegionInfo {
+                    argsz: size_of::<protocol::RegionInfo>() as u32,
+                    flags: protocol::RegionFlags::new()
+                        .with_read(size != 0)
+                        .with_write(size != 0),
+                    index: info.index,
+                    cap_offset: 0,
+                    size,
+                    offset: 0,
+                }
+                .as_bytes()
+                .to_vec()
+            }
+            Command::DEVICE_GET_IRQ_INFO => {
+                let info = read_payload::<protocol::IrqInfo>(message)?;
+                if info.index > irq_index::REQ {
+                    return Err(Errno::EINVAL);
+                }
+                let count = match (info.index, &self.msix) {
+                    (irq_index::MSIX, Some(msix)) => msix.count.into(),
+                    _ => 0,
+                };
+                protocol::IrqInfo {
+                    argsz: size_of::<protocol::IrqInfo>() as u32,
+                    flags: protocol::IrqInfoFlags::new()
+                        .with_eventfd(true)
+                        .with_noresize(true),
+                    index: info.index,
+                    count,
+                }
+                .as_bytes()
+                .to_vec()
+            }
+            Command::DEVICE_SET_IRQS => {
+                let set = read_payload::<protocol::SetIrqs>(message)?;
+                self.set_irqs(set, std::mem::take(&mut message.fds))?;
+                Vec::new()
+            }
+            Command::REGION_READ => {
+                let access = read_payload::<protocol::RegionAccess>(message)?;
+                self.check_access(&access)?;
+                let mut reply = access.as_bytes().to_vec();
+                reply.resize(reply.len() + access.count as usize, 0);
+                self.region_read(
+                    access.region,
+                    access.offset,
+                    &mut reply[size_of::<protocol::RegionAccess>()..],
+                );
+                reply
+            }
+            Command::REGION_WRITE => {
+                let access = read_payload::<protocol::RegionAccess>(message)?;
+                self.check_access(&access)?;
+                let data = message.trailing_payload::<protocol::RegionAccess>();
+                if data.len() != access.count as usize {
+                    return Err(Errno::EINVAL);
+                }
+                self.region_write(access.region, access.offset, data);
+                access.as_bytes().to_vec()
+            }
+            Command::DEVICE_RESET => {
+                block_on(self.device.reset());
+                self.program_device();
+                Vec::new()
+            }
+            _ => return Err(Errno::ENOTSUP),
+        };
+        Ok(reply)
+    }
+
+    fn region_size(&self, index: u32) -> Option<u64> {
+        let size = match index {
+            0..=5 => self.bars[index as usize].map_or(0, |(_, len)| len),
+            region_index::CONFIG => CONFIG_SPACE_SIZE,
+            region_index::ROM | region_index::VGA => 0,
+            _ => return None,
+        };
+        Some(size)
+    }
+
+    fn check_access(&self, access: &protocol::RegionAccess) -> Result<(), Errno> {
+        let size = self.region_size(access.region).ok_or(Errno::EINVAL)?;
+        if access.count > protocol::MAX_DATA_XFER_SIZE
+            || access
+                .offset
+                .checked_add(access.count.into())
+                .map_or(true, |end| end > size)
+        {
+            return Err(Errno::EINVAL);
+        }
+        Ok(())
+    }
+
+    fn set_irqs(&mut self, set: protocol::SetIrqs, fds: Vec<OwnedFd>) -> Result<(), Errno> {
+        if set.index != irq_index::MSIX || !set.flags.action_trigger() {
+            return Err(Errno::EINVAL);
+        }
+        let mut events = self.irq_events.lock();
+        if set.flags.data_none() && set.count == 0 {
+            // Disable all vectors.
+            events.iter_mut().for_each(|event| *event = None);
+            return Ok(());
+        }
+        let start = set.start as usize;
+        let events = events
+            .get_mut(start..start + set.count as usize)
+            .ok_or(Errno::EINVAL)?;
+        if set.flags.data_eventfd() {
+            if fds.len() != events.len() {
+                return Err(Errno::EINVAL);
+            }
+            for (event, fd) in events.iter_mut().zip(fds) {
+                *event = Some(Event::from(fd));
+            }
+        } else if set.flags.data_none() {
+            for event in events.iter().flatten() {
+                event.signal();
+            }
+        } else {
+            return Err(Errno::EINVAL);
+        }
+        Ok(())
+    }
+
+    fn region_read(&mut self, region: u32, offset: u64, data: &mut [u8]) {
+        if region == region_index::CONFIG {
+            let mut pos = 0;
+            while pos < data.len() {
+                let address = offset + pos as u64;
+                let value = self.config_read((address & !3) as u16);
+                let start = (address & 3) as usize;
+                let n = (4 - start).min(data.len() - pos);
+                data[pos..pos + n].copy_from_slice(&value.to_ne_bytes()[start..start + n]);
+                pos += n;
+            }
+        } else {
+            let (base, _) = self.bars[region as usize].unwrap();
+            let mmio = self.device.supports_mmio().unwrap();
+            let result = mmio.mmio_read(base + offset, data);
+            if let Err(err) = complete(result, Some(data)) {
+                tracelimit::warn_ratelimited!(region, offset, err, "bar read failed");
+                data.fill(!0);
+            }
+        }
+    }
+
+    fn region_write(&mut self, region: u32, offset: u64, data: &[u8]) {
+        if region == region_index::CONFIG {
+            let mut pos = 0;
+            while pos < data.len() {
+                let address = offset + pos as u64;
+                let dword_offset = (address & !3) as u16;
+                let start = (address & 3) as usize;
+                let n = (4 - start).min(data.len() - pos);
+                let value = if n == 4 {
+                    u32::from_ne_bytes(data[pos..pos + 4].try_into().unwrap())
+                } else {
+                    let mut value = self.config_read(dword_offset);
+                    if HeaderType00(dword_offset) == HeaderType00::STATUS_COMMAND {
+                        // Don't write back the write-1-to-clear status bits.
+                        value &= 0xffff;
+                    }
+                    let mut bytes = value.to_ne_bytes();
+                    bytes[start..start + n].copy_from_slice(&data[pos..pos + n]);
+                    u32::from_ne_bytes(bytes)
+                };
+                self.client_config_write(dword_offset, value);
+                pos += n;
+            }
+        } else {
+            let (base, _) = self.bars[region as usize].unwrap();
+            let mmio = self.device.supports_mmio().unwrap();
+            let result = mmio.mmio_write(base + offset, data);
+            if let Err(err) = complete(result, None) {
+                tracelimit::warn_ratelimited!(region, offset, err, "bar write failed");
+            }
+        }
+    }
+
+    /// Writes a configuration space register on behalf of the client, leaving
+    /// the state owned by the server unchanged.
+    fn client_config_write(&mut self, offset: u16, value: u32) {
+        match HeaderType00(offset) {
+            HeaderType00::STATUS_COMMAND => {
+                let command = cfg_space::Command::new()
+                    .with_mmio_enabled(true)
+                    .with_bus_master(true);
+                self.config_write(offset, value | u32::from(command.into_bits()));
+            }
+            HeaderType00::BAR0
+            | HeaderType00::BAR1
+            | HeaderType00::BAR2
+            | HeaderType00::BAR3
+            | HeaderType00::BAR4
+            | HeaderType00::BAR5
+            | HeaderType00::EXPANSION_ROM_BASE => {}
+            _ if self
+                .msix
+                .as_ref()
+                .is_some_and(|msix| msix.cap_offset == offset) => {}
+            _ => self.config_write(offset, value),
+        }
+    }
+
+    fn config_read(&mut self, offset: u16) -> u32 {
+        let mut value = 0;
+        let pci = self.device.supports_pci().unwrap();
+        let result = pci.pci_cfg_read(offset, &mut value);
+        if let Err(err) = complete(result, Some(value.as_bytes_mut())) {
+            tracelimit::warn_ratelimited!(offset, err, "config space read failed");
+            value = 0;
+        }
+        value
+    }
+
+    fn config_write(&mut self, offset: u16, value: u32) {
+        let pci = self.device.supports_pci().unwrap();
+        let result = pci.pci_cfg_write(offset, value);
+        if let Err(err) = complete(result, None) {
+            tracelimit::warn_ratelimited!(offset, err, "config space write failed");
+        }
+    }
+
+    /// Determines the BAR sizes and assigns them addresses.
+    fn size_bars(&mut self) {
+        let mut masks = [0; 6];
+        for (index, mask) in masks.iter_mut().enumerate() {
+            let offset = HeaderType00::BAR0.0 + index as u16 * 4;
+            self.config_write(offset, !0);
+            *mask = self.config_read(offset);
+            if cfg_space::BarEncodingBits::from_bits(*mask).use_pio() {
+                tracing::warn!(index, "io port bars are not supported");
+                *mask = 0;
+            }
+        }
+        let mut next = BAR_BASE;
+        for mapping in BarMappings::parse(&[0; 6], &masks).iter() {
+            let base = next.next_multiple_of(mapping.len);
+            self.bars[mapping.index as usize] = Some((base, mapping.len));
+            next = base + mapping.len;
+        }
+    }
+
+    fn find_msix(&mut self) -> Option<Msix> {
+        let mut offset = (self.config_read(HeaderType00::RESERVED_CAP_PTR.0) & 0xfc) as u16;
+        // Bound the walk in case the capabilities form a loop.
+        for _ in 0..48 {
+            if offset == 0 {
+                break;
+            }
+            let header = self.config_read(offset);
+            if header as u8 == CapabilityId::MSIX.0 {
+                let table = self.config_read(offset + MsixCapabilityHeader::OFFSET_TABLE.0);
+                return Some(Msix {
+                    cap_offset: offset,
+                    count: ((header >> 16) as u16 & 0x7ff) + 1,
+                    table_bar: table as u8 & 7,
+                    table_offset: (table & !7).into(),
+                });
+            }
+            offset = ((header >> 8) & 0xfc) as u16;
+        }
+        None
+    }
+
+    /// Programs the BARs, enables decoding, and points each MSI-X vector at
+    /// its eventfd.
+    fn program_device(&mut self) {
+        for (index, bar) in self.bars.into_iter().enumerate() {
+            let Some((base, _)) = bar else { continue };
+            let offset = HeaderType00::BAR0.0 + index as u16 * 4;
+            self.config_write(offset, base as u32);
+            if index < 5 && self.bars[index + 1].is_none() {
+                // The next register is either the upper half of this BAR or
+                // is unimplemented.
+                self.config_write(offset + 4, (base >> 32) as u32);
+            }
+        }
+        let command = self.config_read(HeaderType00::STATUS_COMMAND.0) & 0xffff;
+        self.client_config_write(HeaderType00::STATUS_COMMAND.0, command);
+
+        let Some(msix) = &self.msix else { return };
+        let (cap_offset, count) = (msix.cap_offset, msix.count);
+        let Some((table_base, _)) = self.bars[msix.table_bar as usize] else {
+            tracing::warn!("msix table is in an unimplemented bar");
+            return;
+        };
+        let table_base = table_base + msix.table_offset;
+        let mmio = self.device.supports_mmio().unwrap();
+        for vector in 0..count as u64 {
+            let entry = table_base + vector * 16;
+            for (idx, value) in [
+                (MsixTableEntryIdx::MSG_ADDR_LO, MSI_ADDRESS as u32),
+                (MsixTableEntryIdx::MSG_ADDR_HI, (MSI_ADDRESS >> 32) as u32),
+                (MsixTableEntryIdx::MSG_DATA, vector as u32),
+                (MsixTableEntryIdx::VECTOR_CTL, 0),
+            ] {
+                let result = mmio.mmio_write(entry + idx.0 as u64, value.as_bytes());
+                if let Err(err) = complete(result, None) {
+                    tracing::warn!(vector, err, "failed to program msix table");
+                }
+            }
+        }
+        let control = self.config_read(cap_offset);
+        self.config_write(cap_offset, control | 0x8000_0000);
+    }
+}
+
+fn read_payload<T: zerocopy::FromBytes>(message: &Message) -> Result<T, Errno> {
+    message.read_payload().map_err(|_| Errno::EINVAL)
+}
+
+fn errno(err: io::Error) -> Errno {
+    err.raw_os_error().map_or(Errno::EINVAL, Errno::from_i32)
+}
+
+/// Waits for a possibly deferred IO to complete, copying the result into
+/// `data` for reads.
+fn complete(result: IoResult, data: Option<&mut [u8]>) -> Result<(), &'static str> {
+    match result {
+        IoResult::Ok => Ok(()),
+        IoResult::Err(_) => Err("access error"),
+        IoResult::Defer(mut token) => {
+            let result = match data {
+                Some(data) => block_on(poll_fn(|cx| token.poll_read(cx, data))),
+                None => block_on(poll_fn(|cx| token.poll_write(cx))),
+            };
+            result.map_err(|_| "deferred io cancelled")
+        }
+    }
+}
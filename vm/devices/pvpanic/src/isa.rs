@@ -0,0 +1,164 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! The ISA variant of the pvpanic device.
+
+use crate::PanicRegister;
+use chipset_device::io::IoError;
+use chipset_device::io::IoResult;
+use chipset_device::pio::PortIoIntercept;
+use chipset_device::ChipsetDevice;
+use inspect::InspectMut;
+use power_resources::PowerRequestClient;
+use std::ops::RangeInclusive;
+use vmcore::device_state::ChangeDeviceState;
+
+/// A pvpanic device exposing its register at a fixed I/O port.
+#[derive(InspectMut)]
+pub struct PvPanicIsaDevice {
+    // Fixed configuration
+    #[inspect(hex)]
+    io_port: u16,
+    #[inspect(skip)]
+    io_region: (&'static str, RangeInclusive<u16>),
+
+    // Runtime glue
+    #[inspect(flatten)]
+    register: PanicRegister,
+}
+
+impl PvPanicIsaDevice {
+    /// Returns a new device at `port`, reporting panics to `power`.
+    pub fn new(port: u16, power: PowerRequestClient) -> Self {
+        Self {
+            io_port: port,
+            io_region: ("pvpanic", port..=port),
+            register: PanicRegister::new(power),
+        }
+    }
+}
+
+impl ChangeDeviceState for PvPanicIsaDevice {
+    fn start(&mut self) {}
+
+    async fn stop(&mut self) {}
+
+    async fn reset(&mut self) {}
+}
+
+impl ChipsetDevice for PvPanicIsaDevice {
+    fn supports_pio(&mut self) -> Option<&mut dyn PortIoIntercept> {
+        Some(self)
+    }
+}
+
+impl PortIoIntercept for PvPanicIsaDevice {
+    fn io_read(&mut self, io_port: u16, data: &mut [u8]) -> IoResult {
+        if io_port != self.io_port {
+            return IoResult::Err(IoError::InvalidRegister);
+        }
+        if data.len() != 1 {
+            return IoResult::Err(IoError::InvalidAccessSize);
+        }
+
+        data[0] = self.register.read();
+        IoResult::Ok
+    }
+
+    fn io_write(&mut self, io_port: u16, data: &[u8]) -> IoResult {
+        if io_port != self.io_port {
+            return IoResult::Err(IoError::InvalidRegister);
+        }
+        if data.len() != 1 {
+            return IoResult::Err(IoError::InvalidAccessSize);
+        }
+
+        self.register.write(data[0]);
+        IoResult::Ok
+    }
+
+    fn get_static_regions(&mut self) -> &[(&str, RangeInclusive<u16>)] {
+        std::slice::from_ref(&self.io_region)
+    }
+}
+
+mod save_restore {
+    use super::PvPanicIsaDevice;
+    use vmcore::save_restore::NoSavedState;
+    use vmcore::save_restore::RestoreError;
+    use vmcore::save_restore::SaveError;
+    use vmcore::save_restore::SaveRestore;
+
+    impl SaveRestore for PvPanicIsaDevice {
+        type SavedState = NoSavedState;
+
+        fn save(&mut self) -> Result<Self::SavedState, SaveError> {
+            Ok(NoSavedState)
+        }
+
+        fn restore(&mut self, state: Self::SavedState) -> Result<(), RestoreError> {
+            let NoSavedState = state;
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PVPANIC_CRASH_LOADED;
+    use crate::PVPANIC_PANICKED;
+    use parking_lot::Mutex;
+    use power_resources::PowerRequest;
+    use std::sync::Arc;
+
+    const PORT: u16 = 0x505;
+
+    fn new_device() -> (PvPanicIsaDevice, Arc<Mutex<Vec<PowerRequest>>>) {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let power = PowerRequestClient::from({
+            let requests = requests.clone();
+            move |req| requests.lock().push(req)
+        });
+        (PvPanicIsaDevice::new(PORT, power), requests)
+    }
+
+    #[test]
+    fn reports_supported_events() {
+        let (mut dev, _) = new_device();
+        let mut data = [0];
+        dev.io_read(PORT, &mut data).unwrap();
+        assert_eq!(data[0], PVPANIC_PANICKED | PVPANIC_CRASH_LOADED);
+
+        let mut wide = [0; 2];
+        assert!(matches!(
+            dev.io_read(PORT, &mut wide),
+            IoResult::Err(IoError::InvalidAccessSize)
+        ));
+    }
+
+    #[test]
+    fn events() {
+        let (mut dev, requests) = new_device();
+
+        dev.io_write(PORT, &[0]).unwrap();
+        assert!(requests.lock().is_empty());
+
+        dev.io_write(PORT, &[PVPANIC_PANICKED]).unwrap();
+        dev.io_write(PORT, &[PVPANIC_CRASH_LOADED]).unwrap();
+        dev.io_write(PORT, &[PVPANIC_PANICKED | PVPANIC_CRASH_LOADED])
+            .unwrap();
+        assert_eq!(
+            *requests.lock(),
+            [
+                PowerRequest::GuestPanic {
+                    crash_loaded: false
+                },
+                PowerRequest::GuestPanic { crash_loaded: true },
+                PowerRequest::GuestPanic {
+                    crash_loaded: false
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,80 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Implementation of the QEMU pvpanic device.
+//!
+//! pvpanic is a simple paravirtualized device that the guest kernel writes to
+//! when it panics, giving the host a reliable signal that the guest has
+//! crashed without requiring any guest-side crash reporting infrastructure.
+//!
+//! The device exposes a single byte-wide register. Reads return the set of
+//! events supported by the device, and writes report one or more events.
+//!
+//! Two variants are provided: an ISA device at a fixed I/O port, described to
+//! the guest via ACPI (`QEMU0001`), and a PCI device (`1b36:0011`) exposing the
+//! register in BAR0.
+
+#![forbid(unsafe_code)]
+#![warn(missing_docs)]
+
+pub mod isa;
+pub mod pci;
+pub mod resolver;
+
+use inspect::Inspect;
+use inspect_counters::Counter;
+use power_resources::PowerRequest;
+use power_resources::PowerRequestClient;
+
+/// The guest panicked.
+pub const PVPANIC_PANICKED: u8 = 1 << 0;
+/// The guest panicked, and a crash kernel is loaded to handle it.
+pub const PVPANIC_CRASH_LOADED: u8 = 1 << 1;
+
+/// The events supported by this implementation.
+const SUPPORTED_EVENTS: u8 = PVPANIC_PANICKED | PVPANIC_CRASH_LOADED;
+
+/// The event register shared by both device variants.
+#[derive(Inspect)]
+struct PanicRegister {
+    #[inspect(skip)]
+    power: PowerRequestClient,
+    panicked: Counter,
+    crash_loaded: Counter,
+}
+
+impl PanicRegister {
+    fn new(power: PowerRequestClient) -> Self {
+        Self {
+            power,
+            panicked: Counter::new(),
+            crash_loaded: Counter::new(),
+        }
+    }
+
+    fn read(&self) -> u8 {
+        SUPPORTED_EVENTS
+    }
+
+    fn write(&mut self, events: u8) {
+        if events & !SUPPORTED_EVENTS != 0 {
+            tracelimit::warn_ratelimited!(events, "unsupported pvpanic events");
+        }
+
+        // A guest with a crash kernel loaded will report CRASH_LOADED instead
+        // of PANICKED. If both are somehow set, the panic takes precedence.
+        let crash_loaded = if events & PVPANIC_PANICKED != 0 {
+            self.panicked.increment();
+            false
+        } else if events & PVPANIC_CRASH_LOADED != 0 {
+            self.crash_loaded.increment();
+            true
+        } else {
+            return;
+        };
+
+        tracing::info!(crash_loaded, "guest reported panic");
+        self.power
+            .power_request(PowerRequest::GuestPanic { crash_loaded });
+    }
+}
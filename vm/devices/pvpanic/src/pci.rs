@@ -0,0 +1,132 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! The PCI variant of the pvpanic device.
+
+use crate::PanicRegister;
+use chipset_device::io::IoResult;
+use chipset_device::mmio::MmioIntercept;
+use chipset_device::mmio::RegisterMmioIntercept;
+use chipset_device::pci::PciConfigSpace;
+use chipset_device::ChipsetDevice;
+use inspect::InspectMut;
+use pci_core::cfg_space_emu::BarMemoryKind;
+use pci_core::cfg_space_emu::ConfigSpaceType0Emulator;
+use pci_core::cfg_space_emu::DeviceBars;
+use pci_core::spec::hwid::ClassCode;
+use pci_core::spec::hwid::HardwareIds;
+use pci_core::spec::hwid::ProgrammingInterface;
+use pci_core::spec::hwid::Subclass;
+use power_resources::PowerRequestClient;
+use vmcore::device_state::ChangeDeviceState;
+
+/// The Red Hat PCI vendor ID, used for QEMU's paravirtualized devices.
+const VENDOR_ID: u16 = 0x1b36;
+/// The pvpanic PCI device ID.
+const DEVICE_ID: u16 = 0x0011;
+
+/// The size of BAR0, which holds the event register at offset 0.
+const BAR0_LEN: u64 = 0x10;
+
+/// A pvpanic device exposing its register in BAR0 of a PCI function.
+#[derive(InspectMut)]
+pub struct PvPanicPciDevice {
+    config: ConfigSpaceType0Emulator,
+    #[inspect(flatten)]
+    register: PanicRegister,
+}
+
+impl PvPanicPciDevice {
+    /// Returns a new device reporting panics to `power`.
+    pub fn new(register_mmio: &mut dyn RegisterMmioIntercept, power: PowerRequestClient) -> Self {
+        let hardware_ids = HardwareIds {
+            vendor_id: VENDOR_ID,
+            device_id: DEVICE_ID,
+            revision_id: 1,
+            prog_if: ProgrammingInterface::NONE,
+            sub_class: Subclass::BASE_SYSTEM_PERIPHERAL_OTHER,
+            base_class: ClassCode::BASE_SYSTEM_PERIPHERAL,
+            type0_sub_vendor_id: VENDOR_ID,
+            type0_sub_system_id: 0x1100,
+        };
+
+        let bar0 = register_mmio.new_io_region("pvpanic", BAR0_LEN);
+        let config = ConfigSpaceType0Emulator::new(
+            hardware_ids,
+            Vec::new(),
+            DeviceBars::new().bar0(BAR0_LEN, BarMemoryKind::Intercept(bar0)),
+        );
+
+        Self {
+            config,
+            register: PanicRegister::new(power),
+        }
+    }
+}
+
+impl ChangeDeviceState for PvPanicPciDevice {
+    fn start(&mut self) {}
+
+    async fn stop(&mut self) {}
+
+    async fn reset(&mut self) {
+        self.config.reset();
+    }
+}
+
+impl ChipsetDevice for PvPanicPciDevice {
+    fn supports_mmio(&mut self) -> Option<&mut dyn MmioIntercept> {
+        Some(self)
+    }
+
+    fn supports_pci(&mut self) -> Option<&mut dyn PciConfigSpace> {
+        Some(self)
+    }
+}
+
+impl MmioIntercept for PvPanicPciDevice {
+    fn mmio_read(&mut self, address: u64, data: &mut [u8]) -> IoResult {
+        data.fill(0);
+        if let Some((0, 0)) = self.config.find_bar(address) {
+            data[0] = self.register.read();
+        }
+        IoResult::Ok
+    }
+
+    fn mmio_write(&mut self, address: u64, data: &[u8]) -> IoResult {
+        if let Some((0, 0)) = self.config.find_bar(address) {
+            self.register.write(data[0]);
+        }
+        IoResult::Ok
+    }
+}
+
+impl PciConfigSpace for PvPanicPciDevice {
+    fn pci_cfg_read(&mut self, offset: u16, value: &mut u32) -> IoResult {
+        self.config.read_u32(offset, value)
+    }
+
+    fn pci_cfg_write(&mut self, offset: u16, value: u32) -> IoResult {
+        self.config.write_u32(offset, value)
+    }
+}
+
+mod save_restore {
+    use super::PvPanicPciDevice;
+    use pci_core::cfg_space_emu::ConfigSpaceType0Emulator;
+    use vmcore::save_restore::RestoreError;
+    use vmcore::save_restore::SaveError;
+    use vmcore::save_restore::SaveRestore;
+
+    impl SaveRestore for PvPanicPciDevice {
+        type SavedState = <ConfigSpaceType0Emulator as SaveRestore>::SavedState;
+
+        fn save(&mut self) -> Result<Self::SavedState, SaveError> {
+            self.config.save()
+        }
+
+        fn restore(&mut self, state: Self::SavedState) -> Result<(), RestoreError> {
+            self.config.restore(state)
+        }
+    }
+}
@@ -0,0 +1,82 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Resource resolvers for the pvpanic devices.
+
+use crate::isa::PvPanicIsaDevice;
+use crate::pci::PvPanicPciDevice;
+use async_trait::async_trait;
+use chipset_device_resources::ResolveChipsetDeviceHandleParams;
+use chipset_device_resources::ResolvedChipsetDevice;
+use pci_resources::ResolvePciDeviceHandleParams;
+use pci_resources::ResolvedPciDevice;
+use power_resources::PowerRequestHandleKind;
+use pvpanic_resources::PvPanicIsaDeviceHandle;
+use pvpanic_resources::PvPanicPciDeviceHandle;
+use pvpanic_resources::PVPANIC_ISA_PORT;
+use thiserror::Error;
+use vm_resource::declare_static_async_resolver;
+use vm_resource::kind::ChipsetDeviceHandleKind;
+use vm_resource::kind::PciDeviceHandleKind;
+use vm_resource::AsyncResolveResource;
+use vm_resource::IntoResource;
+use vm_resource::PlatformResource;
+use vm_resource::ResolveError;
+use vm_resource::ResourceResolver;
+
+/// The resource resolver for the pvpanic devices.
+pub struct PvPanicResolver;
+
+declare_static_async_resolver! {
+    PvPanicResolver,
+    (ChipsetDeviceHandleKind, PvPanicIsaDeviceHandle),
+    (PciDeviceHandleKind, PvPanicPciDeviceHandle),
+}
+
+/// An error resolving a pvpanic device handle.
+#[allow(missing_docs)]
+#[derive(Debug, Error)]
+pub enum ResolvePvPanicError {
+    #[error("failed to resolve power request client")]
+    Power(#[source] ResolveError),
+}
+
+#[async_trait]
+impl AsyncResolveResource<ChipsetDeviceHandleKind, PvPanicIsaDeviceHandle> for PvPanicResolver {
+    type Output = ResolvedChipsetDevice;
+    type Error = ResolvePvPanicError;
+
+    async fn resolve(
+        &self,
+        resolver: &ResourceResolver,
+        _resource: PvPanicIsaDeviceHandle,
+        _input: ResolveChipsetDeviceHandleParams<'_>,
+    ) -> Result<Self::Output, Self::Error> {
+        let power = resolver
+            .resolve::<PowerRequestHandleKind, _>(PlatformResource.into_resource(), ())
+            .await
+            .map_err(ResolvePvPanicError::Power)?;
+
+        Ok(PvPanicIsaDevice::new(PVPANIC_ISA_PORT, power).into())
+    }
+}
+
+#[async_trait]
+impl AsyncResolveResource<PciDeviceHandleKind, PvPanicPciDeviceHandle> for PvPanicResolver {
+    type Output = ResolvedPciDevice;
+    type Error = ResolvePvPanicError;
+
+    async fn resolve(
+        &self,
+        resolver: &ResourceResolver,
+        _resource: PvPanicPciDeviceHandle,
+        input: ResolvePciDeviceHandleParams<'_>,
+    ) -> Result<Self::Output, Self::Error> {
+        let power = resolver
+            .resolve::<PowerRequestHandleKind, _>(PlatformResource.into_resource(), ())
+            .await
+            .map_err(ResolvePvPanicError::Power)?;
+
+        Ok(PvPanicPciDevice::new(input.register_mmio, power).into())
+    }
+}
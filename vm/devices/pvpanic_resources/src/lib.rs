@@ -0,0 +1,34 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Resource definitions for the pvpanic device.
+
+#![forbid(unsafe_code)]
+#![warn(missing_docs)]
+
+use mesh::MeshPayload;
+use vm_resource::kind::ChipsetDeviceHandleKind;
+use vm_resource::kind::PciDeviceHandleKind;
+use vm_resource::ResourceId;
+
+/// The I/O port of the ISA pvpanic device.
+///
+/// This matches QEMU's default, which is what guests expect to find described
+/// in the ACPI tables.
+pub const PVPANIC_ISA_PORT: u16 = 0x505;
+
+/// A handle to a pvpanic device on the ISA bus, at [`PVPANIC_ISA_PORT`].
+#[derive(MeshPayload)]
+pub struct PvPanicIsaDeviceHandle;
+
+impl ResourceId<ChipsetDeviceHandleKind> for PvPanicIsaDeviceHandle {
+    const ID: &'static str = "pvpanic-isa";
+}
+
+/// A handle to a pvpanic PCI device.
+#[derive(MeshPayload)]
+pub struct PvPanicPciDeviceHandle;
+
+impl ResourceId<PciDeviceHandleKind> for PvPanicPciDeviceHandle {
+    const ID: &'static str = "pvpanic-pci";
+}
@@ -0,0 +1,314 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! An expect-style engine for scripting interactions with a serial port:
+//! waiting for output that matches a pattern, then sending input.
+
+use futures::AsyncRead;
+use futures::AsyncReadExt;
+use futures::AsyncWrite;
+use futures::AsyncWriteExt;
+use mesh::CancelContext;
+use regex::bytes::Regex;
+use std::io;
+use std::time::Duration;
+use thiserror::Error;
+
+/// The default maximum amount of unmatched output to buffer.
+pub const DEFAULT_MAX_BUFFER: usize = 64 * 1024;
+
+/// The amount of recent output to include in errors.
+const ERROR_CONTEXT: usize = 256;
+
+/// An error waiting for serial output.
+#[derive(Debug, Error)]
+pub enum ExpectError {
+    /// No matching output arrived before the timeout.
+    #[error("timed out waiting for `{pattern}`, last output: {recent:?}")]
+    Timeout {
+        /// The pattern that was being waited for.
+        pattern: String,
+        /// The most recent unmatched output.
+        recent: String,
+    },
+    /// The serial port was closed.
+    #[error("serial port closed while waiting for `{pattern}`")]
+    Closed {
+        /// The pattern that was being waited for.
+        pattern: String,
+    },
+    /// More output than the buffer limit arrived without a match.
+    #[error("more than {limit} bytes of output without matching `{pattern}`")]
+    BufferFull {
+        /// The pattern that was being waited for.
+        pattern: String,
+        /// The buffer limit, from [`Expect::with_max_buffer`].
+        limit: usize,
+    },
+    /// An IO error occurred.
+    #[error("serial port IO error")]
+    Io(#[source] io::Error),
+}
+
+/// Output matched by [`Expect::expect`].
+#[derive(Debug, Clone)]
+pub struct Match {
+    /// The index of the pattern that matched, for
+    /// [`Expect::expect_any`].
+    pub index: usize,
+    /// The output preceding the match.
+    pub before: Vec<u8>,
+    /// The capture groups of the match, starting with the whole match.
+    pub groups: Vec<Option<Vec<u8>>>,
+}
+
+impl Match {
+    /// Returns the matched output.
+    pub fn matched(&self) -> &[u8] {
+        self.groups[0].as_deref().unwrap()
+    }
+}
+
+/// Waits for output from a serial port and sends it input.
+///
+/// Output is consumed as it is matched: each call to [`Self::expect`] only
+/// searches output received after the previous match.
+pub struct Expect<R, W> {
+    read: R,
+    write: W,
+    buffer: Vec<u8>,
+    max_buffer: Option<usize>,
+    timeout: Option<Duration>,
+    transcript: Option<Vec<u8>>,
+}
+
+impl<R: AsyncRead + Unpin, W: AsyncWrite + Unpin> Expect<R, W> {
+    /// Returns a new engine reading serial output from `read` and writing
+    /// serial input to `write`.
+    ///
+    /// To use a single [`SerialIo`](crate::SerialIo), split it with
+    /// [`AsyncReadExt::split`].
+    pub fn new(read: R, write: W) -> Self {
+        Self {
+            read,
+            write,
+            buffer: Vec::new(),
+            max_buffer: Some(DEFAULT_MAX_BUFFER),
+            timeout: None,
+            transcript: None,
+        }
+    }
+
+    /// Sets the maximum amount of unmatched output to buffer, or `None` for
+    /// no limit. Waiting fails with [`ExpectError::BufferFull`] if the limit
+    /// is exceeded. The default is [`DEFAULT_MAX_BUFFER`].
+    pub fn with_max_buffer(mut self, limit: Option<usize>) -> Self {
+        self.max_buffer = limit;
+        self
+    }
+
+    /// Sets the default timeout for [`Self::expect`]. By default, there is no
+    /// timeout.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Records all output received, to be retrieved with
+    /// [`Self::transcript`].
+    pub fn with_transcript(mut self) -> Self {
+        self.transcript = Some(Vec::new());
+        self
+    }
+
+    /// Returns the output received so far, if enabled with
+    /// [`Self::with_transcript`].
+    pub fn transcript(&self) -> Option<&[u8]> {
+        self.transcript.as_deref()
+    }
+
+    /// Returns the reader and writer.
+    pub fn into_inner(self) -> (R, W) {
+        (self.read, self.write)
+    }
+
+    /// Waits for output matching `pattern`, with the default timeout.
+    pub async fn expect(&mut self, pattern: &Regex) -> Result<Match, ExpectError> {
+        self.expect_any(&[pattern], self.timeout).await
+    }
+
+    /// Waits for output matching `pattern`, with the given timeout.
+    pub async fn expect_timeout(
+        &mut self,
+        pattern: &Regex,
+        timeout: Duration,
+    ) -> Result<Match, ExpectError> {
+        self.expect_any(&[pattern], Some(timeout)).await
+    }
+
+    /// Waits for output matching any of `patterns`. If more than one matches,
+    /// the one matching earliest in the output is returned.
+    pub async fn expect_any(
+        &mut self,
+        patterns: &[&Regex],
+        timeout: Option<Duration>,
+    ) -> Result<Match, ExpectError> {
+        let mut ctx = match timeout {
+            Some(timeout) => CancelContext::new().with_timeout(timeout),
+            None => CancelContext::new(),
+        };
+        match ctx.until_cancelled(self.wait(patterns)).await {
+            Ok(r) => r,
+            Err(_) => {
+                let recent = &self.buffer[self.buffer.len().saturating_sub(ERROR_CONTEXT)..];
+                Err(ExpectError::Timeout {
+                    pattern: describe(patterns),
+                    recent: String::from_utf8_lossy(recent).into_owned(),
+                })
+            }
+        }
+    }
+
+    async fn wait(&mut self, patterns: &[&Regex]) -> Result<Match, ExpectError> {
+        let mut buf = [0; 1024];
+        loop {
+            if let Some(m) = self.find(patterns) {
+                return Ok(m);
+            }
+            let n = self.read.read(&mut buf).await.map_err(ExpectError::Io)?;
+            if n == 0 {
+                return Err(ExpectError::Closed {
+                    pattern: describe(patterns),
+                });
+            }
+            if let Some(transcript) = &mut self.transcript {
+                transcript.extend_from_slice(&buf[..n]);
+            }
+            self.buffer.extend_from_slice(&buf[..n]);
+            if let Some(limit) = self.max_buffer {
+                // Fail rather than discard output that may be part of a
+                // match, or that the caller expects in `Match::before`.
+                if self.buffer.len() > limit {
+                    if let Some(m) = self.find(patterns) {
+                        return Ok(m);
+                    }
+                    return Err(ExpectError::BufferFull {
+                        pattern: describe(patterns),
+                        limit,
+                    });
+                }
+            }
+        }
+    }
+
+    fn find(&mut self, patterns: &[&Regex]) -> Option<Match> {
+        let (index, captures) = patterns
+            .iter()
+            .enumerate()
+            .filter_map(|(i, pattern)| Some((i, pattern.captures(&self.buffer)?)))
+            .min_by_key(|(_, captures)| captures.get(0).unwrap().start())?;
+        let whole = captures.get(0).unwrap();
+        let m = Match {
+            index,
+            before: self.buffer[..whole.start()].to_vec(),
+            groups: captures
+                .iter()
+                .map(|group| group.map(|group| group.as_bytes().to_vec()))
+                .collect(),
+        };
+        let end = whole.end();
+        self.buffer.drain(..end);
+        Some(m)
+    }
+
+    /// Sends `data` to the serial port.
+    pub async fn send(&mut self, data: &[u8]) -> io::Result<()> {
+        self.write.write_all(data).await?;
+        self.write.flush().await
+    }
+
+    /// Sends `line` to the serial port, followed by a newline.
+    pub async fn send_line(&mut self, line: &str) -> io::Result<()> {
+        self.send(format!("{line}\n").as_bytes()).await
+    }
+}
+
+fn describe(patterns: &[&Regex]) -> String {
+    patterns
+        .iter()
+        .map(|pattern| pattern.as_str())
+        .collect::<Vec<_>>()
+        .join("` or `")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Expect;
+    use super::ExpectError;
+    use futures::io::Cursor;
+    use futures::TryStreamExt;
+    use pal_async::async_test;
+    use regex::bytes::Regex;
+    use std::time::Duration;
+
+    #[async_test]
+    async fn test_expect() {
+        let output = b"booting...\r\nlogin: root\r\n# ready 42\r\n".to_vec();
+        let mut expect = Expect::new(Cursor::new(output), Vec::new()).with_transcript();
+
+        let m = expect
+            .expect(&Regex::new("login: ").unwrap())
+            .await
+            .unwrap();
+        assert_eq!(m.before, b"booting...\r\n");
+        assert_eq!(m.matched(), b"login: ");
+        expect.send_line("root").await.unwrap();
+
+        // Output consumed by the previous match is not searched again.
+        let booting = Regex::new("booting").unwrap();
+        let ready = Regex::new(r"ready (\d+)").unwrap();
+        let m = expect.expect_any(&[&booting, &ready], None).await.unwrap();
+        assert_eq!(m.index, 1);
+        assert_eq!(m.before, b"root\r\n# ");
+        assert_eq!(m.groups[1].as_deref(), Some(&b"42"[..]));
+
+        assert!(matches!(
+            expect.expect(&booting).await,
+            Err(ExpectError::Closed { .. })
+        ));
+        assert_eq!(
+            expect.transcript().unwrap(),
+            b"booting...\r\nlogin: root\r\n# ready 42\r\n"
+        );
+        let (_, input) = expect.into_inner();
+        assert_eq!(input, b"root\n");
+    }
+
+    #[async_test]
+    async fn test_expect_buffer_limit() {
+        let output = [vec![b'x'; 2000], b"done".to_vec()].concat();
+        let done = Regex::new("done").unwrap();
+
+        let mut expect =
+            Expect::new(Cursor::new(output.clone()), Vec::new()).with_max_buffer(Some(64));
+        assert!(matches!(
+            expect.expect(&done).await,
+            Err(ExpectError::BufferFull { limit: 64, .. })
+        ));
+
+        let mut expect = Expect::new(Cursor::new(output), Vec::new()).with_max_buffer(None);
+        let m = expect.expect(&done).await.unwrap();
+        assert_eq!(m.before, [b'x'; 2000]);
+    }
+
+    #[async_test]
+    async fn test_expect_timeout() {
+        let output = futures::stream::pending::<std::io::Result<Vec<u8>>>().into_async_read();
+        let mut expect = Expect::new(output, futures::io::sink());
+        let err = expect
+            .expect_timeout(&Regex::new("never").unwrap(), Duration::from_millis(10))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, ExpectError::Timeout { .. }), "{err}");
+    }
+}
@@ -6,6 +6,7 @@
 #![warn(missing_docs)]
 
 pub mod disconnected;
+pub mod expect;
 pub mod resources;
 pub mod serial_io;
 
@@ -13,11 +13,16 @@
 #![warn(missing_docs)]
 
 pub mod pr;
+mod range_lock;
 pub mod resolve;
 pub mod sync_wrapper;
 
 use guestmem::AccessError;
+use guestmem::GuestMemory;
+use guestmem::MemoryRead;
 use inspect::Inspect;
+use range_lock::RangeLock;
+use scsi_buffers::OwnedRequestBuffers;
 use scsi_buffers::RequestBuffers;
 use stackfuture::StackFuture;
 use std::fmt::Debug;
@@ -45,6 +50,11 @@ pub enum DiskError {
     /// The request failed due to a reportable medium error.
     #[error("medium error")]
     MediumError(#[source] std::io::Error, MediumErrorDetails),
+    /// The request failed because the compare data did not match the
+    /// contents of the disk. Contains the byte offset of the first
+    /// mismatch.
+    #[error("miscompare at byte offset {0}")]
+    Miscompare(u64),
     /// The request failed due to a failure to access the specified buffers.
     #[error("failed to access guest memory")]
     MemoryAccess(#[from] AccessError),
@@ -170,6 +180,74 @@ fn wait_resize(&self, sector_count: u64) -> impl Future<Output = u64> + Send {
         let _ = sector_count;
         std::future::pending()
     }
+
+    /// Returns true if the backing store implements
+    /// [`DiskIo::compare_and_write`] natively.
+    ///
+    /// If this returns false, [`Disk::compare_and_write`] emulates the
+    /// operation with a read, compare, and write.
+    fn supports_compare_and_write(&self) -> bool {
+        false
+    }
+
+    /// Issues an asynchronous atomic compare-and-write operation to the disk.
+    ///
+    /// This is only called if [`DiskIo::supports_compare_and_write`] returns
+    /// true.
+    ///
+    /// # Arguments
+    /// * `buffers` - The compare data followed by the write data, each half
+    ///   of the buffer.
+    /// * `sector` - The logical sector at which the operation starts.
+    /// * `fua` - A flag indicates if FUA (force unit access) is requested.
+    ///
+    /// Fails with [`DiskError::Miscompare`] if the on-disk contents do not
+    /// match the compare data, in which case nothing is written.
+    fn compare_and_write(
+        &self,
+        buffers: &RequestBuffers<'_>,
+        sector: u64,
+        fua: bool,
+    ) -> impl Future<Output = Result<(), DiskError>> + Send {
+        let _ = (buffers, sector, fua);
+        ready(Err(DiskError::InvalidInput))
+    }
+
+    /// Returns true if the backing store implements
+    /// [`DiskIo::query_allocation`].
+    ///
+    /// This must not change at runtime. If this returns false,
+    /// [`Disk::query_allocation`] reports all sectors as allocated.
+    fn supports_allocation_query(&self) -> bool {
+        false
+    }
+
+    /// Queries whether sectors are allocated in the backing store.
+    ///
+    /// Returns the allocation state of `sector` and the number of consecutive
+    /// sectors, starting at `sector` and up to `count`, that share that state.
+    ///
+    /// This is only called if [`DiskIo::supports_allocation_query`] returns
+    /// true.
+    fn query_allocation(
+        &self,
+        sector: u64,
+        count: u64,
+    ) -> impl Future<Output = Result<AllocationRun, DiskError>> + Send {
+        let _ = (sector, count);
+        ready(Err(DiskError::InvalidInput))
+    }
+}
+
+/// A run of sectors with the same allocation state, returned by
+/// [`DiskIo::query_allocation`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct AllocationRun {
+    /// True if the sectors are allocated in the backing store. Unallocated
+    /// sectors read as zero.
+    pub allocated: bool,
+    /// The number of sectors in the run. This is at least one.
+    pub count: u64,
 }
 
 /// An asynchronous block device.
@@ -205,6 +283,10 @@ struct DiskInner<T: ?Sized = dyn DynDisk> {
     is_read_only: bool,
     unmap_behavior: UnmapBehavior,
     optimal_unmap_sectors: u32,
+    supports_compare_and_write: bool,
+    supports_allocation_query: bool,
+    #[inspect(skip)]
+    compare_and_write_lock: RangeLock,
     disk: T,
 }
 
@@ -243,6 +325,9 @@ pub fn new(disk: impl 'static + DiskIo) -> Result<Self, InvalidDisk> {
             is_read_only: disk.is_read_only(),
             optimal_unmap_sectors: disk.optimal_unmap_sectors(),
             unmap_behavior: disk.unmap_behavior(),
+            supports_compare_and_write: disk.supports_compare_and_write(),
+            supports_allocation_query: disk.supports_allocation_query(),
+            compare_and_write_lock: RangeLock::new(),
             disk,
         })))
     }
@@ -364,6 +449,96 @@ pub fn sync_cache(&self) -> impl use<'_> + Future<Output = Result<(), DiskError>
     pub fn wait_resize(&self, sector_count: u64) -> impl use<'_> + Future<Output = u64> {
         self.0.disk.wait_resize(sector_count)
     }
+
+    /// Issues an asynchronous compare-and-write operation to the disk.
+    ///
+    /// The first half of `buffers` is compared against the disk contents
+    /// starting at `sector`. If they match, the second half is written to the
+    /// same sectors. Otherwise, the operation fails with
+    /// [`DiskError::Miscompare`] and nothing is written.
+    ///
+    /// If the backing store does not support this natively, it is emulated
+    /// with a read, compare, and write while holding a lock on the sector
+    /// range. This makes the operation atomic with respect to other
+    /// compare-and-write operations on this disk, but not with respect to
+    /// ordinary writes.
+    ///
+    /// # Panics
+    ///
+    /// The caller must pass a buffer with an even, integer number of sectors.
+    pub fn compare_and_write<'a>(
+        &'a self,
+        buffers: &'a RequestBuffers<'_>,
+        sector: u64,
+        fua: bool,
+    ) -> impl use<'a> + Future<Output = Result<(), DiskError>> + Send {
+        assert_eq!(buffers.len() % (2 << self.0.sector_shift), 0);
+        async move {
+            if self.0.supports_compare_and_write {
+                return self.0.disk.compare_and_write(buffers, sector, fua).await;
+            }
+
+            let len = buffers.len() / 2;
+            let count = (len >> self.0.sector_shift) as u64;
+            let _guard = self
+                .0
+                .compare_and_write_lock
+                .lock(sector..sector + count)
+                .await;
+
+            let mem = GuestMemory::allocate(len);
+            let current = OwnedRequestBuffers::linear(0, len, true);
+            let current = current.buffer(&mem);
+            self.0.disk.read_vectored(&current, sector).await?;
+
+            let mut actual = vec![0; len];
+            current.reader().read(&mut actual)?;
+            let mut expected = vec![0; len];
+            buffers.subrange(0, len).reader().read(&mut expected)?;
+            if let Some(offset) = actual.iter().zip(&expected).position(|(a, b)| a != b) {
+                return Err(DiskError::Miscompare(offset as u64));
+            }
+
+            self.0
+                .disk
+                .write_vectored(&buffers.subrange(len, len), sector, fua)
+                .await
+        }
+    }
+
+    /// Returns true if the backing store reports which sectors are allocated.
+    pub fn supports_allocation_query(&self) -> bool {
+        self.0.supports_allocation_query
+    }
+
+    /// Queries whether sectors are allocated in the backing store.
+    ///
+    /// Returns the allocation state of `sector` and the number of consecutive
+    /// sectors, starting at `sector` and up to `count`, that share that state.
+    /// If the backing store does not support allocation queries, all sectors
+    /// are reported as allocated.
+    ///
+    /// # Panics
+    ///
+    /// The caller must pass a non-zero `count`.
+    pub fn query_allocation(
+        &self,
+        sector: u64,
+        count: u64,
+    ) -> impl use<'_> + Future<Output = Result<AllocationRun, DiskError>> + Send {
+        assert!(count != 0);
+        async move {
+            if !self.0.supports_allocation_query {
+                return Ok(AllocationRun {
+                    allocated: true,
+                    count,
+                });
+            }
+            let run = self.0.disk.query_allocation(sector, count).await?;
+            debug_assert!(run.count != 0 && run.count <= count);
+            Ok(run)
+        }
+    }
 }
 
 /// The behavior of unmap.
@@ -405,6 +580,19 @@ fn write_vectored<'a>(
 
     fn sync_cache(&self) -> IoFuture<'_>;
 
+    fn compare_and_write<'a>(
+        &'a self,
+        buffers: &'a RequestBuffers<'_>,
+        sector: u64,
+        fua: bool,
+    ) -> IoFuture<'a>;
+
+    fn query_allocation(
+        &self,
+        sector: u64,
+        count: u64,
+    ) -> StackFuture<'_, Result<AllocationRun, DiskError>, { ASYNC_DISK_STACK_SIZE }>;
+
     fn wait_resize<'a>(
         &'a self,
         sector_count: u64,
@@ -456,4 +644,21 @@ fn write_vectored<'a>(
     fn sync_cache(&self) -> IoFuture<'_> {
         StackFuture::from_or_box(self.sync_cache())
     }
+
+    fn compare_and_write<'a>(
+        &'a self,
+        buffers: &'a RequestBuffers<'_>,
+        sector: u64,
+        fua: bool,
+    ) -> IoFuture<'a> {
+        StackFuture::from_or_box(self.compare_and_write(buffers, sector, fua))
+    }
+
+    fn query_allocation(
+        &self,
+        sector: u64,
+        count: u64,
+    ) -> StackFuture<'_, Result<AllocationRun, DiskError>, { ASYNC_DISK_STACK_SIZE }> {
+        StackFuture::from_or_box(self.query_allocation(sector, count))
+    }
 }
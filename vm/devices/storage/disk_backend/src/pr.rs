@@ -56,6 +56,21 @@ async fn preempt(
         reservation_type: ReservationType,
         abort: bool,
     ) -> Result<(), DiskError>;
+
+    /// Saves the reservation state associated with this client, so that it
+    /// can be restored after the VM is restarted or serviced.
+    ///
+    /// Returns `None` if the implementation has no state to save.
+    fn save(&self) -> Result<Option<SavedReservationState>, DiskError> {
+        Ok(None)
+    }
+
+    /// Restores reservation state previously returned by
+    /// [`PersistentReservation::save`].
+    fn restore(&self, state: &SavedReservationState) -> Result<(), DiskError> {
+        let _ = state;
+        Ok(())
+    }
 }
 
 /// Capabilities returned by [`PersistentReservation::capabilities`].
@@ -111,3 +126,18 @@ pub struct ReservationReport {
     /// The registered controllers.
     pub controllers: Vec<RegisteredController>,
 }
+
+/// Reservation state saved by [`PersistentReservation::save`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SavedReservationState {
+    /// The ID identifying this client to other users of the disk.
+    pub host_id: u64,
+    /// The registration generation.
+    pub generation: u32,
+    /// This client's registration key, if it is registered.
+    pub key: Option<u64>,
+    /// The type of the reservation held by this client, if any.
+    pub reservation_type: Option<ReservationType>,
+    /// The persist through power loss state.
+    pub persist_through_power_loss: bool,
+}
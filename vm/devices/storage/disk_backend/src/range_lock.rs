@@ -0,0 +1,75 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! An asynchronous lock over ranges of sectors.
+
+use parking_lot::Mutex;
+use std::ops::Range;
+
+/// A lock that serializes access to overlapping sector ranges.
+///
+/// Non-overlapping ranges can be held concurrently.
+pub(crate) struct RangeLock {
+    held: Mutex<Vec<Range<u64>>>,
+    released: event_listener::Event,
+}
+
+impl RangeLock {
+    pub fn new() -> Self {
+        Self {
+            held: Mutex::new(Vec::new()),
+            released: event_listener::Event::new(),
+        }
+    }
+
+    /// Waits until no held range overlaps `range`, then acquires it.
+    pub async fn lock(&self, range: Range<u64>) -> RangeLockGuard<'_> {
+        loop {
+            let listen = {
+                let mut held = self.held.lock();
+                if !held
+                    .iter()
+                    .any(|r| r.start < range.end && range.start < r.end)
+                {
+                    held.push(range.clone());
+                    break RangeLockGuard { lock: self, range };
+                }
+                self.released.listen()
+            };
+            listen.await;
+        }
+    }
+}
+
+/// A guard that releases its range when dropped.
+pub(crate) struct RangeLockGuard<'a> {
+    lock: &'a RangeLock,
+    range: Range<u64>,
+}
+
+impl Drop for RangeLockGuard<'_> {
+    fn drop(&mut self) {
+        let mut held = self.lock.held.lock();
+        let i = held.iter().position(|r| *r == self.range).unwrap();
+        held.swap_remove(i);
+        drop(held);
+        self.lock.released.notify(usize::MAX);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RangeLock;
+    use futures::FutureExt;
+
+    #[test]
+    fn test_range_lock() {
+        let lock = RangeLock::new();
+        let a = lock.lock(0..4).now_or_never().unwrap();
+        let _b = lock.lock(4..8).now_or_never().unwrap();
+        let mut c = Box::pin(lock.lock(2..6));
+        assert!((&mut c).now_or_never().is_none());
+        drop(a);
+        assert!((&mut c).now_or_never().is_none());
+    }
+}
@@ -28,12 +28,64 @@ impl ResourceId<DiskHandleKind> for FileDiskHandle {
 
 /// Disk handle for a disk that emulates persistent reservation support.
 #[derive(MeshPayload)]
-pub struct DiskWithReservationsHandle(pub Resource<DiskHandleKind>);
+pub struct DiskWithReservationsHandle {
+    /// The underlying disk.
+    pub disk: Resource<DiskHandleKind>,
+    /// An optional file to store the reservation state in. If present, the
+    /// state is shared with any other process using the same file.
+    ///
+    /// Otherwise, the state is kept in memory.
+    pub store: Option<std::fs::File>,
+}
 
 impl ResourceId<DiskHandleKind> for DiskWithReservationsHandle {
     const ID: &'static str = "prwrap";
 }
 
+/// Handle for a read-only disk containing an ISO9660 image built from the
+/// contents of a host directory.
+#[derive(MeshPayload)]
+pub struct IsoDirDiskHandle {
+    /// The path to the host directory.
+    pub path: String,
+    /// The volume identifier. If `None`, the directory name is used.
+    pub volume_id: Option<String>,
+}
+
+impl ResourceId<DiskHandleKind> for IsoDirDiskHandle {
+    const ID: &'static str = "iso_dir";
+}
+
+/// Handle for a read-only disk containing a FAT filesystem built from the
+/// contents of a host directory.
+#[derive(MeshPayload)]
+pub struct FatDirDiskHandle {
+    /// The path to the host directory.
+    pub path: String,
+    /// The volume label. If `None`, the directory name is used.
+    pub volume_label: Option<String>,
+    /// The FAT type. If `None`, it is chosen based on the volume size.
+    pub fat_type: Option<FatType>,
+    /// The volume size in bytes. If `None`, the volume is sized to fit the
+    /// directory contents.
+    pub size: Option<u64>,
+}
+
+impl ResourceId<DiskHandleKind> for FatDirDiskHandle {
+    const ID: &'static str = "fat_dir";
+}
+
+/// The FAT variant of a [`FatDirDiskHandle`].
+#[derive(MeshPayload)]
+pub enum FatType {
+    /// FAT12.
+    Fat12,
+    /// FAT16.
+    Fat16,
+    /// FAT32.
+    Fat32,
+}
+
 /// Disk handle for a fixed VHD1 disk.
 #[derive(MeshPayload)]
 pub struct FixedVhd1DiskHandle(pub std::fs::File);
@@ -4,11 +4,14 @@
 #![forbid(unsafe_code)]
 
 mod readwriteat;
+#[cfg(target_os = "linux")]
+mod seekdata;
 
 use self::readwriteat::ReadWriteAt;
 use blocking::unblock;
 use disk_backend::resolve::ResolveDiskParameters;
 use disk_backend::resolve::ResolvedDisk;
+use disk_backend::AllocationRun;
 use disk_backend::DiskError;
 use disk_backend::DiskIo;
 use disk_backend_resources::FileDiskHandle;
@@ -141,6 +144,42 @@ pub async fn flush(&self) -> Result<(), DiskError> {
             .map_err(DiskError::Io)?;
         Ok(())
     }
+
+    #[cfg(target_os = "linux")]
+    pub async fn query_allocation(
+        &self,
+        sector: u64,
+        count: u64,
+    ) -> Result<AllocationRun, DiskError> {
+        let end_sector = sector + count;
+        if end_sector > self.sector_count() {
+            return Err(DiskError::IllegalBlock);
+        }
+        let file = self.file.clone();
+        let offset = sector << self.sector_shift;
+        let end = end_sector << self.sector_shift;
+        let (allocated, run_end) = unblock(move || seekdata::query_extent(&file, offset, end))
+            .await
+            .map_err(DiskError::Io)?;
+        let run_end_sector = if allocated {
+            // Include any partially allocated sector in the run.
+            (run_end + (1 << self.sector_shift) - 1) >> self.sector_shift
+        } else {
+            run_end >> self.sector_shift
+        };
+        if run_end_sector == sector {
+            // The hole ends partway through the first sector, so the sector
+            // is partially allocated.
+            return Ok(AllocationRun {
+                allocated: true,
+                count: 1,
+            });
+        }
+        Ok(AllocationRun {
+            allocated,
+            count: run_end_sector.min(end_sector) - sector,
+        })
+    }
 }
 
 impl DiskIo for FileDisk {
@@ -205,4 +244,20 @@ async fn unmap(
     fn unmap_behavior(&self) -> disk_backend::UnmapBehavior {
         disk_backend::UnmapBehavior::Ignored
     }
+
+    fn supports_allocation_query(&self) -> bool {
+        cfg!(target_os = "linux")
+    }
+
+    async fn query_allocation(&self, sector: u64, count: u64) -> Result<AllocationRun, DiskError> {
+        #[cfg(target_os = "linux")]
+        {
+            FileDisk::query_allocation(self, sector, count).await
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = (sector, count);
+            Err(DiskError::InvalidInput)
+        }
+    }
 }
@@ -0,0 +1,32 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Helpers for finding the allocated extents of a sparse file.
+
+use nix::errno::Errno;
+use nix::unistd::lseek;
+use nix::unistd::Whence;
+use std::fs;
+use std::io::Result;
+use std::os::unix::prelude::*;
+
+/// Queries whether the file data at `offset` is allocated.
+///
+/// Returns the allocation state of `offset` and the end of the run of bytes
+/// with the same state, which is no larger than `end`.
+///
+/// This updates the file pointer, so it must only be used on files that are
+/// accessed with positioned reads and writes.
+pub fn query_extent(file: &fs::File, offset: u64, end: u64) -> Result<(bool, u64)> {
+    let data = match lseek(file.as_raw_fd(), offset as i64, Whence::SeekData) {
+        Ok(data) => data as u64,
+        // There is no more data in the file.
+        Err(Errno::ENXIO) => return Ok((false, end)),
+        Err(err) => return Err(err.into()),
+    };
+    if data > offset {
+        return Ok((false, data.min(end)));
+    }
+    let hole = lseek(file.as_raw_fd(), offset as i64, Whence::SeekHole)? as u64;
+    Ok((true, hole.min(end)))
+}
@@ -0,0 +1,941 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! ISO9660 image layout, with Joliet and Rock Ridge extensions.
+//!
+//! The image is laid out as follows, in 2048-byte sectors:
+//!
+//! - the system area (sectors 0-15, zeroed)
+//! - the primary volume descriptor, the Joliet supplementary volume
+//!   descriptor, and the volume descriptor set terminator
+//! - the L and M path tables for the primary and Joliet hierarchies
+//! - the Rock Ridge continuation area
+//! - the primary directories, then the Joliet directories
+//! - the file data
+//!
+//! Both hierarchies share the same file extents. Everything before the file
+//! data is generated up front and kept in memory.
+
+use host_dir_image::civil_time;
+use host_dir_image::Dir;
+use host_dir_image::Extent;
+use host_dir_image::File;
+use host_dir_image::FileSource;
+use host_dir_image::Image;
+use host_dir_image::Node;
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::time::SystemTime;
+
+pub(crate) const SECTOR_SIZE: usize = 2048;
+const SYSTEM_AREA_SECTORS: u32 = 16;
+const FIRST_TABLE_SECTOR: u32 = SYSTEM_AREA_SECTORS + 3;
+
+const VD_TYPE_PRIMARY: u8 = 1;
+const VD_TYPE_SUPPLEMENTARY: u8 = 2;
+const VD_TYPE_TERMINATOR: u8 = 255;
+const VD_STANDARD_ID: &[u8; 5] = b"CD001";
+/// The escape sequence identifying UCS-2 level 3 Joliet names.
+const JOLIET_ESCAPE: &[u8; 3] = b"%/E";
+
+const FLAG_DIRECTORY: u8 = 0x02;
+
+/// The maximum length of a Joliet identifier, in UCS-2 characters.
+const JOLIET_MAX_NAME: usize = 64;
+/// The maximum length of the name in a single Rock Ridge NM entry.
+const RR_MAX_NM_NAME: usize = 250;
+const RR_NM_CONTINUE: u8 = 0x01;
+const RR_TF_MODIFY: u8 = 0x02;
+const RR_ER_ID: &[u8] = b"RRIP_1991A";
+const RR_ER_DESCRIPTOR: &[u8] =
+    b"THE ROCK RIDGE INTERCHANGE PROTOCOL PROVIDES SUPPORT FOR POSIX FILE SYSTEM SEMANTICS";
+const RR_ER_SOURCE: &[u8] = b"PLEASE CONTACT DISC PUBLISHER FOR SPECIFICATION SOURCE.  SEE PUBLISHER IDENTIFIER IN PRIMARY VOLUME DESCRIPTOR FOR CONTACT INFORMATION.";
+
+const MODE_DIRECTORY: u32 = 0o040555;
+const MODE_FILE: u32 = 0o100444;
+const MODE_EXECUTABLE: u32 = 0o111;
+
+/// The image is too large to be described by ISO9660's 32-bit sector numbers.
+#[derive(Debug)]
+pub(crate) struct ImageTooLarge;
+
+/// Lays out an image containing the contents of `root`.
+pub(crate) fn build(root: &Dir, volume_id: &str) -> Result<Image, ImageTooLarge> {
+    let tree = Tree::new(root);
+
+    // Serialize once to find the sizes of the tables. Sizes do not depend on
+    // locations, so the second pass with the final locations produces tables
+    // of the same size.
+    let mut loc = Locations {
+        primary_dirs: vec![(0, 0); tree.dirs.len()],
+        joliet_dirs: vec![(0, 0); tree.dirs.len()],
+        files: vec![0; tree.files.len()],
+        path_tables: [0; 4],
+        continuation: 0,
+    };
+    let tables = tree.write(&loc);
+
+    let mut alloc = Allocator(FIRST_TABLE_SECTOR.into());
+    for (loc, table) in loc.path_tables.iter_mut().zip(&tables.path_tables) {
+        *loc = alloc.alloc(table.len() as u64);
+    }
+    loc.continuation = alloc.alloc(tables.continuation.len() as u64);
+    for &i in &tree.primary_order {
+        let len = tables.primary_dirs[i].len();
+        loc.primary_dirs[i] = (alloc.alloc(len as u64), len);
+    }
+    for &i in &tree.joliet_order {
+        let len = tables.joliet_dirs[i].len();
+        loc.joliet_dirs[i] = (alloc.alloc(len as u64), len);
+    }
+    let data_start = alloc.0;
+    for (loc, file) in loc.files.iter_mut().zip(&tree.files) {
+        // Empty files have no extent.
+        if file.len != 0 {
+            *loc = alloc.alloc(file.len.into());
+        }
+    }
+    let volume_sectors: u32 = alloc.0.try_into().map_err(|_| ImageTooLarge)?;
+
+    let tables = tree.write(&loc);
+
+    let mut metadata = vec![0; data_start as usize * SECTOR_SIZE];
+    let mut put = |sector: u32, data: &[u8]| {
+        let offset = sector as usize * SECTOR_SIZE;
+        metadata[offset..offset + data.len()].copy_from_slice(data);
+    };
+
+    let root_mtime = root.mtime;
+    put(
+        SYSTEM_AREA_SECTORS,
+        &volume_descriptor(
+            false,
+            volume_id,
+            volume_sectors,
+            [
+                tables.path_tables[0].len() as u32,
+                loc.path_tables[0],
+                loc.path_tables[1],
+            ],
+            &dir_record(
+                &[0],
+                loc.primary_dirs[0].0,
+                loc.primary_dirs[0].1 as u32,
+                FLAG_DIRECTORY,
+                root_mtime,
+                &[],
+            ),
+            root_mtime,
+        ),
+    );
+    put(
+        SYSTEM_AREA_SECTORS + 1,
+        &volume_descriptor(
+            true,
+            volume_id,
+            volume_sectors,
+            [
+                tables.path_tables[2].len() as u32,
+                loc.path_tables[2],
+                loc.path_tables[3],
+            ],
+            &dir_record(
+                &[0],
+                loc.joliet_dirs[0].0,
+                loc.joliet_dirs[0].1 as u32,
+                FLAG_DIRECTORY,
+                root_mtime,
+                &[],
+            ),
+            root_mtime,
+        ),
+    );
+    put(SYSTEM_AREA_SECTORS + 2, &terminator());
+    for (&sector, table) in loc.path_tables.iter().zip(&tables.path_tables) {
+        put(sector, table);
+    }
+    put(loc.continuation, &tables.continuation);
+    for (i, dir) in tables.primary_dirs.iter().enumerate() {
+        put(loc.primary_dirs[i].0, dir);
+    }
+    for (i, dir) in tables.joliet_dirs.iter().enumerate() {
+        put(loc.joliet_dirs[i].0, dir);
+    }
+
+    let metadata = Extent {
+        offset: 0,
+        len: metadata.len() as u64,
+        source: FileSource::Memory(metadata.into()),
+    };
+    let extents = std::iter::once(metadata)
+        .chain(
+            tree.files
+                .iter()
+                .zip(&loc.files)
+                .filter(|(file, _)| file.len != 0)
+                .map(|(file, &sector)| Extent {
+                    offset: sector as u64 * SECTOR_SIZE as u64,
+                    len: file.len.into(),
+                    source: file.source.clone(),
+                }),
+        )
+        .collect();
+
+    Ok(Image::new(
+        extents,
+        SECTOR_SIZE as u32,
+        volume_sectors.into(),
+    ))
+}
+
+/// Allocates sectors sequentially.
+struct Allocator(u64);
+
+impl Allocator {
+    /// Allocates enough sectors to hold `len` bytes, returning the first
+    /// sector. The returned value is truncated if the image is too large; this
+    /// is detected once allocation is complete.
+    fn alloc(&mut self, len: u64) -> u32 {
+        let sector = self.0;
+        self.0 += len.div_ceil(SECTOR_SIZE as u64);
+        sector as u32
+    }
+}
+
+/// The sector locations of the image's tables.
+struct Locations {
+    /// The location and length of each directory's primary extent.
+    primary_dirs: Vec<(u32, usize)>,
+    /// The location and length of each directory's Joliet extent.
+    joliet_dirs: Vec<(u32, usize)>,
+    files: Vec<u32>,
+    /// The primary L and M path tables, then the Joliet L and M path tables.
+    path_tables: [u32; 4],
+    continuation: u32,
+}
+
+struct Tables {
+    primary_dirs: Vec<Vec<u8>>,
+    joliet_dirs: Vec<Vec<u8>>,
+    path_tables: [Vec<u8>; 4],
+    continuation: Vec<u8>,
+}
+
+/// A flattened view of the directory hierarchy.
+struct Tree<'a> {
+    /// All directories, with the root first.
+    dirs: Vec<FlatDir<'a>>,
+    files: Vec<&'a File>,
+    /// The directory indexes in primary path table order.
+    primary_order: Vec<usize>,
+    /// The directory indexes in Joliet path table order.
+    joliet_order: Vec<usize>,
+}
+
+struct FlatDir<'a> {
+    parent: usize,
+    mtime: Option<SystemTime>,
+    /// The children, sorted by primary identifier.
+    primary: Vec<Child<'a>>,
+    /// The indexes into `primary`, sorted by Joliet identifier.
+    joliet: Vec<usize>,
+}
+
+struct Child<'a> {
+    name: &'a str,
+    primary_id: Vec<u8>,
+    joliet_id: Vec<u8>,
+    kind: ChildKind,
+}
+
+#[derive(Copy, Clone)]
+enum ChildKind {
+    Dir(usize),
+    File(usize),
+}
+
+impl<'a> Tree<'a> {
+    fn new(root: &'a Dir) -> Self {
+        let mut tree = Tree {
+            dirs: Vec::new(),
+            files: Vec::new(),
+            primary_order: Vec::new(),
+            joliet_order: Vec::new(),
+        };
+        tree.add_dir(root, 0);
+        tree.primary_order = tree.path_table_order(|dir| {
+            dir.primary
+                .iter()
+                .filter_map(|c| c.dir_index())
+                .collect::<Vec<_>>()
+        });
+        tree.joliet_order = tree.path_table_order(|dir| {
+            dir.joliet
+                .iter()
+                .filter_map(|&c| dir.primary[c].dir_index())
+                .collect::<Vec<_>>()
+        });
+        tree
+    }
+
+    fn add_dir(&mut self, dir: &'a Dir, parent: usize) -> usize {
+        let index = self.dirs.len();
+        self.dirs.push(FlatDir {
+            parent,
+            mtime: dir.mtime,
+            primary: Vec::new(),
+            joliet: Vec::new(),
+        });
+
+        let mut primary_taken = HashSet::new();
+        let mut joliet_taken = HashSet::new();
+        let mut children = Vec::new();
+        for (name, node) in &dir.entries {
+            let kind = match node {
+                Node::Dir(dir) => ChildKind::Dir(self.add_dir(dir, index)),
+                Node::File(file) => {
+                    self.files.push(file);
+                    ChildKind::File(self.files.len() - 1)
+                }
+            };
+            let is_dir = matches!(kind, ChildKind::Dir(_));
+            children.push(Child {
+                name,
+                primary_id: primary_id(name, is_dir, &mut primary_taken),
+                joliet_id: joliet_id(name, &mut joliet_taken),
+                kind,
+            });
+        }
+
+        children.sort_by(|a, b| compare_ids(&a.primary_id, &b.primary_id, b' '));
+        let mut joliet = (0..children.len()).collect::<Vec<_>>();
+        joliet.sort_by(|&a, &b| compare_ids(&children[a].joliet_id, &children[b].joliet_id, 0));
+        let flat = &mut self.dirs[index];
+        flat.primary = children;
+        flat.joliet = joliet;
+        index
+    }
+
+    /// Returns the directories in path table order: by level, then by parent
+    /// directory number, then by identifier.
+    fn path_table_order(&self, subdirs: impl Fn(&FlatDir<'_>) -> Vec<usize>) -> Vec<usize> {
+        let mut order = vec![0];
+        let mut i = 0;
+        while i < order.len() {
+            order.extend(subdirs(&self.dirs[order[i]]));
+            i += 1;
+        }
+        order
+    }
+
+    fn write(&self, loc: &Locations) -> Tables {
+        let mut continuation = Continuation {
+            sector: loc.continuation,
+            data: Vec::new(),
+        };
+        let primary_dirs = (0..self.dirs.len())
+            .map(|i| self.write_primary_dir(i, loc, &mut continuation))
+            .collect();
+        let joliet_dirs = (0..self.dirs.len())
+            .map(|i| self.write_joliet_dir(i, loc))
+            .collect();
+        let primary_ids = |dir: &FlatDir<'_>, index: usize| {
+            let child = dir
+                .primary
+                .iter()
+                .find(|c| c.dir_index() == Some(index))
+                .unwrap();
+            child.primary_id.clone()
+        };
+        let joliet_ids = |dir: &FlatDir<'_>, index: usize| {
+            let child = dir
+                .primary
+                .iter()
+                .find(|c| c.dir_index() == Some(index))
+                .unwrap();
+            child.joliet_id.clone()
+        };
+        let [primary_l, primary_m] =
+            self.path_tables(&self.primary_order, &loc.primary_dirs, primary_ids);
+        let [joliet_l, joliet_m] =
+            self.path_tables(&self.joliet_order, &loc.joliet_dirs, joliet_ids);
+        Tables {
+            primary_dirs,
+            joliet_dirs,
+            path_tables: [primary_l, primary_m, joliet_l, joliet_m],
+            continuation: continuation.data,
+        }
+    }
+
+    /// Builds the L (little endian) and M (big endian) path tables.
+    fn path_tables(
+        &self,
+        order: &[usize],
+        dirs: &[(u32, usize)],
+        id: impl Fn(&FlatDir<'_>, usize) -> Vec<u8>,
+    ) -> [Vec<u8>; 2] {
+        let mut number = vec![0u16; self.dirs.len()];
+        for (n, &i) in order.iter().enumerate() {
+            // Directory numbers beyond 65535 cannot be represented; they are
+            // only used for parent references, so saturate them.
+            number[i] = (n + 1).try_into().unwrap_or(u16::MAX);
+        }
+        let mut l = Vec::new();
+        let mut m = Vec::new();
+        for &i in order {
+            let dir = &self.dirs[i];
+            let id = if i == 0 {
+                vec![0]
+            } else {
+                id(&self.dirs[dir.parent], i)
+            };
+            let parent = number[dir.parent];
+            let (extent, _) = dirs[i];
+            for (table, big_endian) in [(&mut l, false), (&mut m, true)] {
+                table.push(id.len() as u8);
+                table.push(0);
+                if big_endian {
+                    table.extend(extent.to_be_bytes());
+                    table.extend(parent.to_be_bytes());
+                } else {
+                    table.extend(extent.to_le_bytes());
+                    table.extend(parent.to_le_bytes());
+                }
+                table.extend(&id);
+                if id.len() % 2 != 0 {
+                    table.push(0);
+                }
+            }
+        }
+        [l, m]
+    }
+
+    fn dir_mode(&self, index: usize) -> (u32, u32) {
+        let subdirs = self.dirs[index]
+            .primary
+            .iter()
+            .filter(|c| c.dir_index().is_some())
+            .count();
+        (MODE_DIRECTORY, 2 + subdirs as u32)
+    }
+
+    fn write_primary_dir(
+        &self,
+        index: usize,
+        loc: &Locations,
+        continuation: &mut Continuation,
+    ) -> Vec<u8> {
+        let dir = &self.dirs[index];
+        let mut extent = DirExtent::default();
+
+        // The "." entry. For the root directory, this also holds the SUSP and
+        // Rock Ridge extension indicators.
+        let (location, len) = loc.primary_dirs[index];
+        let mut su = Vec::new();
+        if index == 0 {
+            su.extend(susp_sp());
+        }
+        let (mode, nlink) = self.dir_mode(index);
+        su.extend(rr_px(mode, nlink));
+        su.extend(rr_tf(dir.mtime));
+        if index == 0 {
+            su.extend(continuation.push(&rr_er()));
+        }
+        extent.push(&dir_record(
+            &[0],
+            location,
+            len as u32,
+            FLAG_DIRECTORY,
+            dir.mtime,
+            &su,
+        ));
+
+        // The ".." entry.
+        let (location, len) = loc.primary_dirs[dir.parent];
+        let parent = &self.dirs[dir.parent];
+        let (mode, nlink) = self.dir_mode(dir.parent);
+        let mut su = rr_px(mode, nlink);
+        su.extend(rr_tf(parent.mtime));
+        extent.push(&dir_record(
+            &[1],
+            location,
+            len as u32,
+            FLAG_DIRECTORY,
+            parent.mtime,
+            &su,
+        ));
+
+        for child in &dir.primary {
+            let (location, len, flags, mtime, mode, nlink) = match child.kind {
+                ChildKind::Dir(i) => {
+                    let (location, len) = loc.primary_dirs[i];
+                    let (mode, nlink) = self.dir_mode(i);
+                    (
+                        location,
+                        len as u32,
+                        FLAG_DIRECTORY,
+                        self.dirs[i].mtime,
+                        mode,
+                        nlink,
+                    )
+                }
+                ChildKind::File(i) => {
+                    let file = self.files[i];
+                    let mut mode = MODE_FILE;
+                    if file.executable {
+                        mode |= MODE_EXECUTABLE;
+                    }
+                    (loc.files[i], file.len, 0, file.mtime, mode, 1)
+                }
+            };
+
+            let mut su = rr_px(mode, nlink);
+            su.extend(rr_tf(mtime));
+            let nm = rr_nm(child.name);
+            if record_len(child.primary_id.len(), su.len() + nm.len()) <= u8::MAX as usize {
+                su.extend(nm);
+            } else {
+                // The name does not fit in the record. Move it to the
+                // continuation area.
+                su.extend(continuation.push(&nm));
+            }
+            extent.push(&dir_record(
+                &child.primary_id,
+                location,
+                len,
+                flags,
+                mtime,
+                &su,
+            ));
+        }
+
+        extent.finish()
+    }
+
+    fn write_joliet_dir(&self, index: usize, loc: &Locations) -> Vec<u8> {
+        let dir = &self.dirs[index];
+        let mut extent = DirExtent::default();
+        let (location, len) = loc.joliet_dirs[index];
+        extent.push(&dir_record(
+            &[0],
+            location,
+            len as u32,
+            FLAG_DIRECTORY,
+            dir.mtime,
+            &[],
+        ));
+        let (location, len) = loc.joliet_dirs[dir.parent];
+        extent.push(&dir_record(
+            &[1],
+            location,
+            len as u32,
+            FLAG_DIRECTORY,
+            self.dirs[dir.parent].mtime,
+            &[],
+        ));
+        for &c in &dir.joliet {
+            let child = &dir.primary[c];
+            let (location, len, flags, mtime) = match child.kind {
+                ChildKind::Dir(i) => {
+                    let (location, len) = loc.joliet_dirs[i];
+                    (location, len as u32, FLAG_DIRECTORY, self.dirs[i].mtime)
+                }
+                ChildKind::File(i) => {
+                    let file = self.files[i];
+                    (loc.files[i], file.len, 0, file.mtime)
+                }
+            };
+            extent.push(&dir_record(
+                &child.joliet_id,
+                location,
+                len,
+                flags,
+                mtime,
+                &[],
+            ));
+        }
+        extent.finish()
+    }
+}
+
+impl Child<'_> {
+    fn dir_index(&self) -> Option<usize> {
+        match self.kind {
+            ChildKind::Dir(i) => Some(i),
+            ChildKind::File(_) => None,
+        }
+    }
+}
+
+/// A directory's extent. Records may not span sector boundaries.
+#[derive(Default)]
+struct DirExtent(Vec<u8>);
+
+impl DirExtent {
+    fn push(&mut self, record: &[u8]) {
+        if self.0.len() % SECTOR_SIZE + record.len() > SECTOR_SIZE {
+            self.0.resize(self.0.len().next_multiple_of(SECTOR_SIZE), 0);
+        }
+        self.0.extend(record);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.0.resize(self.0.len().next_multiple_of(SECTOR_SIZE), 0);
+        self.0
+    }
+}
+
+/// The Rock Ridge continuation area, holding system use entries that do not
+/// fit in their directory records.
+struct Continuation {
+    sector: u32,
+    data: Vec<u8>,
+}
+
+impl Continuation {
+    /// Appends `entries` to the area, returning the CE entry that references
+    /// them.
+    fn push(&mut self, entries: &[u8]) -> Vec<u8> {
+        // Continuation areas may not span sector boundaries.
+        if self.data.len() % SECTOR_SIZE + entries.len() > SECTOR_SIZE {
+            self.data
+                .resize(self.data.len().next_multiple_of(SECTOR_SIZE), 0);
+        }
+        let block = self.sector + (self.data.len() / SECTOR_SIZE) as u32;
+        let offset = (self.data.len() % SECTOR_SIZE) as u32;
+        self.data.extend(entries);
+
+        let mut ce = susp_header(b"CE", 28);
+        ce.extend(both32(block));
+        ce.extend(both32(offset));
+        ce.extend(both32(entries.len() as u32));
+        ce
+    }
+}
+
+/// Returns the length of a directory record with the given identifier and
+/// system use lengths.
+fn record_len(id_len: usize, su_len: usize) -> usize {
+    // The identifier is padded to an even length, as is the system use area.
+    33 + id_len + (id_len + 1) % 2 + su_len.next_multiple_of(2)
+}
+
+fn dir_record(
+    id: &[u8],
+    extent: u32,
+    len: u32,
+    flags: u8,
+    mtime: Option<SystemTime>,
+    su: &[u8],
+) -> Vec<u8> {
+    let record_len = record_len(id.len(), su.len());
+    let mut record = Vec::with_capacity(record_len);
+    record.push(record_len as u8);
+    record.push(0); // extended attribute record length
+    record.extend(both32(extent));
+    record.extend(both32(len));
+    record.extend(record_date(mtime));
+    record.push(flags);
+    record.push(0); // file unit size
+    record.push(0); // interleave gap size
+    record.extend(both16(1)); // volume sequence number
+    record.push(id.len() as u8);
+    record.extend(id);
+    if id.len() % 2 == 0 {
+        record.push(0);
+    }
+    record.extend(su);
+    record.resize(record_len, 0);
+    record
+}
+
+fn volume_descriptor(
+    joliet: bool,
+    volume_id: &str,
+    volume_sectors: u32,
+    [path_table_len, l_path_table, m_path_table]: [u32; 3],
+    root_record: &[u8],
+    date: Option<SystemTime>,
+) -> Vec<u8> {
+    let mut vd = vec![0; SECTOR_SIZE];
+    let string = |vd: &mut [u8], s: &[u8]| {
+        if joliet {
+            for (i, b) in vd.iter_mut().enumerate() {
+                // Pad with UCS-2 spaces.
+                *b = s
+                    .get(i)
+                    .copied()
+                    .unwrap_or(if i % 2 == 0 { 0 } else { b' ' });
+            }
+        } else {
+            for (i, b) in vd.iter_mut().enumerate() {
+                *b = s.get(i).copied().unwrap_or(b' ');
+            }
+        }
+    };
+
+    vd[0] = if joliet {
+        VD_TYPE_SUPPLEMENTARY
+    } else {
+        VD_TYPE_PRIMARY
+    };
+    vd[1..6].copy_from_slice(VD_STANDARD_ID);
+    vd[6] = 1;
+    string(&mut vd[8..40], &[]); // system identifier
+    let volume_id = if joliet {
+        ucs2(volume_id, 16)
+    } else {
+        d_characters(volume_id, 32)
+    };
+    string(&mut vd[40..72], &volume_id);
+    vd[80..88].copy_from_slice(&both32(volume_sectors));
+    if joliet {
+        vd[88..91].copy_from_slice(JOLIET_ESCAPE);
+    }
+    vd[120..124].copy_from_slice(&both16(1)); // volume set size
+    vd[124..128].copy_from_slice(&both16(1)); // volume sequence number
+    vd[128..132].copy_from_slice(&both16(SECTOR_SIZE as u16));
+    vd[132..140].copy_from_slice(&both32(path_table_len));
+    vd[140..144].copy_from_slice(&l_path_table.to_le_bytes());
+    vd[148..152].copy_from_slice(&m_path_table.to_be_bytes());
+    vd[156..190].copy_from_slice(root_record);
+    // Volume set, publisher, data preparer, and application identifiers,
+    // then the copyright, abstract, and bibliographic file identifiers.
+    string(&mut vd[190..813], &[]);
+    let date = volume_date(date);
+    vd[813..830].copy_from_slice(&date); // creation
+    vd[830..847].copy_from_slice(&date); // modification
+    vd[847..864].copy_from_slice(&volume_date(None)); // expiration
+    vd[864..881].copy_from_slice(&volume_date(None)); // effective
+    vd[881] = 1; // file structure version
+    vd
+}
+
+fn terminator() -> Vec<u8> {
+    let mut vd = vec![0; SECTOR_SIZE];
+    vd[0] = VD_TYPE_TERMINATOR;
+    vd[1..6].copy_from_slice(VD_STANDARD_ID);
+    vd[6] = 1;
+    vd
+}
+
+fn susp_header(signature: &[u8; 2], len: usize) -> Vec<u8> {
+    let mut entry = Vec::with_capacity(len);
+    entry.extend(signature);
+    entry.push(len as u8);
+    entry.push(1); // version
+    entry
+}
+
+/// The SUSP indicator, which must be the first system use entry of the root
+/// directory's "." record.
+fn susp_sp() -> Vec<u8> {
+    let mut entry = susp_header(b"SP", 7);
+    entry.extend([0xbe, 0xef, 0]);
+    entry
+}
+
+/// The extension reference identifying Rock Ridge.
+fn rr_er() -> Vec<u8> {
+    let len = 8 + RR_ER_ID.len() + RR_ER_DESCRIPTOR.len() + RR_ER_SOURCE.len();
+    let mut entry = susp_header(b"ER", len);
+    entry.push(RR_ER_ID.len() as u8);
+    entry.push(RR_ER_DESCRIPTOR.len() as u8);
+    entry.push(RR_ER_SOURCE.len() as u8);
+    entry.push(1); // extension version
+    entry.extend(RR_ER_ID);
+    entry.extend(RR_ER_DESCRIPTOR);
+    entry.extend(RR_ER_SOURCE);
+    entry
+}
+
+/// POSIX file attributes.
+fn rr_px(mode: u32, nlink: u32) -> Vec<u8> {
+    let mut entry = susp_header(b"PX", 36);
+    entry.extend(both32(mode));
+    entry.extend(both32(nlink));
+    entry.extend(both32(0)); // uid
+    entry.extend(both32(0)); // gid
+    entry
+}
+
+/// POSIX time stamps.
+fn rr_tf(mtime: Option<SystemTime>) -> Vec<u8> {
+    let mut entry = susp_header(b"TF", 12);
+    entry.push(RR_TF_MODIFY);
+    entry.extend(record_date(mtime));
+    entry
+}
+
+/// The alternate (POSIX) name, split across as many NM entries as needed.
+fn rr_nm(name: &str) -> Vec<u8> {
+    let mut entries = Vec::new();
+    let mut chunks = name.as_bytes().chunks(RR_MAX_NM_NAME).peekable();
+    while let Some(chunk) = chunks.next() {
+        entries.extend(susp_header(b"NM", 5 + chunk.len()));
+        entries.push(if chunks.peek().is_some() {
+            RR_NM_CONTINUE
+        } else {
+            0
+        });
+        entries.extend(chunk);
+    }
+    entries
+}
+
+fn both16(v: u16) -> [u8; 4] {
+    let mut b = [0; 4];
+    b[..2].copy_from_slice(&v.to_le_bytes());
+    b[2..].copy_from_slice(&v.to_be_bytes());
+    b
+}
+
+fn both32(v: u32) -> [u8; 8] {
+    let mut b = [0; 8];
+    b[..4].copy_from_slice(&v.to_le_bytes());
+    b[4..].copy_from_slice(&v.to_be_bytes());
+    b
+}
+
+/// Converts `s` to at most `max` ISO9660 d-characters (`A-Z`, `0-9`, `_`).
+fn d_characters(s: &str, max: usize) -> Vec<u8> {
+    s.chars()
+        .map(|c| match c.to_ascii_uppercase() {
+            c @ ('A'..='Z' | '0'..='9' | '_') => c as u8,
+            _ => b'_',
+        })
+        .take(max)
+        .collect()
+}
+
+/// Converts `s` to at most `max` big-endian UCS-2 characters.
+fn ucs2(s: &str, max: usize) -> Vec<u8> {
+    let mut units = s
+        .encode_utf16()
+        .map(|c| match char::from_u32(c.into()) {
+            // Characters not allowed in Joliet identifiers.
+            Some('*' | '/' | ':' | ';' | '?' | '\\') => u16::from(b'_'),
+            _ => c,
+        })
+        .take(max)
+        .collect::<Vec<_>>();
+    // Don't split a surrogate pair.
+    if units.len() == max && (0xd800..0xdc00).contains(&units[max - 1]) {
+        units.pop();
+    }
+    units.iter().flat_map(|c| c.to_be_bytes()).collect()
+}
+
+/// Returns a unique ISO9660 level 1 identifier for `name`.
+fn primary_id(name: &str, is_dir: bool, taken: &mut HashSet<Vec<u8>>) -> Vec<u8> {
+    let (base, ext) = match name.rsplit_once('.') {
+        Some((base, ext)) if !is_dir && !base.is_empty() => (base, ext),
+        _ => (name, ""),
+    };
+    let mut base = d_characters(base, 8);
+    if base.is_empty() {
+        base.push(b'_');
+    }
+    let ext = d_characters(ext, 3);
+
+    let make = |base: &[u8]| {
+        let mut id = base.to_vec();
+        if !is_dir {
+            id.push(b'.');
+            id.extend(&ext);
+        }
+        id
+    };
+    let mut id = make(&base);
+    let mut n = 1;
+    while !taken.insert(id.clone()) {
+        let suffix = format!("_{n}");
+        let mut unique = base.clone();
+        unique.truncate(8 - suffix.len());
+        unique.extend(suffix.as_bytes());
+        id = make(&unique);
+        n += 1;
+    }
+    if !is_dir {
+        id.extend(b";1");
+    }
+    id
+}
+
+/// Returns a unique Joliet identifier for `name`.
+fn joliet_id(name: &str, taken: &mut HashSet<Vec<u8>>) -> Vec<u8> {
+    let mut id = ucs2(name, JOLIET_MAX_NAME);
+    let mut n = 1;
+    while !taken.insert(id.clone()) {
+        let suffix = format!("~{n}");
+        id = ucs2(name, JOLIET_MAX_NAME - suffix.len());
+        id.extend(ucs2(&suffix, suffix.len()));
+        n += 1;
+    }
+    id
+}
+
+/// Compares identifiers as if the shorter one were padded with `pad`.
+fn compare_ids(a: &[u8], b: &[u8], pad: u8) -> Ordering {
+    let len = a.len().max(b.len());
+    let a = a.iter().copied().chain(std::iter::repeat(pad)).take(len);
+    let b = b.iter().copied().chain(std::iter::repeat(pad)).take(len);
+    a.cmp(b)
+}
+
+/// The 7-byte date format used in directory records. All zeroes means the
+/// date is not specified.
+fn record_date(time: Option<SystemTime>) -> [u8; 7] {
+    match time.and_then(civil_time) {
+        Some((year, month, day, hour, minute, second)) if year - 1900 <= u8::MAX.into() => {
+            [(year - 1900) as u8, month, day, hour, minute, second, 0]
+        }
+        _ => [0; 7],
+    }
+}
+
+/// The 17-byte date format used in volume descriptors. All zero digits means
+/// the date is not specified.
+fn volume_date(time: Option<SystemTime>) -> [u8; 17] {
+    let mut date = [b'0'; 17];
+    date[16] = 0;
+    if let Some((year, month, day, hour, minute, second)) = time.and_then(civil_time) {
+        if year <= 9999 {
+            let s = format!("{year:04}{month:02}{day:02}{hour:02}{minute:02}{second:02}00");
+            date[..16].copy_from_slice(s.as_bytes());
+        }
+    }
+    date
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn test_primary_id() {
+        let mut taken = HashSet::new();
+        assert_eq!(primary_id("readme.txt", false, &mut taken), b"README.TXT;1");
+        assert_eq!(
+            primary_id("README.TXT", false, &mut taken),
+            b"README_1.TXT;1"
+        );
+        assert_eq!(
+            primary_id("a long name.json", false, &mut taken),
+            b"A_LONG_N.JSO;1"
+        );
+        assert_eq!(primary_id(".bashrc", false, &mut taken), b"_BASHRC.;1");
+        assert_eq!(primary_id("user-data", false, &mut taken), b"USER_DAT.;1");
+        assert_eq!(primary_id("dir.d", true, &mut taken), b"DIR_D");
+    }
+
+    #[test]
+    fn test_dates() {
+        let time = UNIX_EPOCH + Duration::from_secs(951_827_696); // 2000-02-29 12:34:56
+        assert_eq!(record_date(Some(time)), [100, 2, 29, 12, 34, 56, 0]);
+        assert_eq!(&volume_date(Some(time))[..16], b"2000022912345600");
+        assert_eq!(record_date(None), [0; 7]);
+    }
+}
@@ -0,0 +1,312 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A read-only disk backend that presents an ISO9660 image, with Joliet and
+//! Rock Ridge extensions, synthesized from a host directory or an in-memory
+//! list of files.
+//!
+//! The image metadata is built when the disk is created. File contents are
+//! read from their sources on demand, so no temporary image file is needed.
+//! Host files should not change while the disk is in use: data past a file's
+//! original length is not visible, and data removed from a file reads as
+//! zero.
+
+#![warn(missing_docs)]
+#![forbid(unsafe_code)]
+
+mod layout;
+pub mod resolver;
+
+use disk_backend::DiskError;
+use disk_backend::DiskIo;
+use disk_backend::UnmapBehavior;
+use host_dir_image::Image;
+use host_dir_image::Tree;
+use inspect::Inspect;
+use layout::SECTOR_SIZE;
+use scsi_buffers::RequestBuffers;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use thiserror::Error;
+
+/// An error building an ISO image.
+#[derive(Debug, Error)]
+pub enum Error {
+    /// A host file or directory could not be accessed.
+    #[error("failed to access {}", .0.display())]
+    Io(PathBuf, #[source] io::Error),
+    /// The path within the image is invalid.
+    #[error("invalid image path {0:?}")]
+    InvalidPath(String),
+    /// The path within the image is already in use.
+    #[error("image path {0:?} already exists")]
+    AlreadyExists(String),
+    /// The file is too large to be stored in a single ISO9660 extent.
+    #[error("file {0:?} is too large")]
+    FileTooLarge(String),
+    /// The image is too large.
+    #[error("image is too large")]
+    ImageTooLarge,
+}
+
+impl From<host_dir_image::Error> for Error {
+    fn from(err: host_dir_image::Error) -> Self {
+        match err {
+            host_dir_image::Error::Io(path, err) => Error::Io(path, err),
+            host_dir_image::Error::InvalidPath(path) => Error::InvalidPath(path),
+            host_dir_image::Error::AlreadyExists(path) => Error::AlreadyExists(path),
+            host_dir_image::Error::FileTooLarge(path) => Error::FileTooLarge(path),
+        }
+    }
+}
+
+/// The maximum length of a file or directory name, in bytes.
+const MAX_NAME_LEN: usize = 255;
+
+/// A builder for an [`IsoDisk`].
+///
+/// Paths within the image are `/`-separated and relative to the image root.
+/// Parent directories are created as needed.
+pub struct IsoBuilder {
+    volume_id: String,
+    tree: Tree,
+}
+
+impl IsoBuilder {
+    /// Returns a new builder for an empty image with the given volume
+    /// identifier.
+    ///
+    /// The identifier is stored as-is (up to 16 characters) in the Joliet
+    /// volume descriptor, and converted to upper case in the primary volume
+    /// descriptor.
+    pub fn new(volume_id: impl Into<String>) -> Self {
+        Self {
+            volume_id: volume_id.into(),
+            tree: Tree::new(|name| name.len() <= MAX_NAME_LEN),
+        }
+    }
+
+    /// Adds a file with the contents `data` at `path`.
+    pub fn add_file(&mut self, path: &str, data: impl Into<Arc<[u8]>>) -> Result<(), Error> {
+        Ok(self.tree.add_file(path, data)?)
+    }
+
+    /// Adds the host file `host_path` at `path`.
+    pub fn add_host_file(&mut self, path: &str, host_path: impl AsRef<Path>) -> Result<(), Error> {
+        Ok(self.tree.add_host_file(path, host_path)?)
+    }
+
+    /// Recursively adds the contents of the host directory `host_path` to the
+    /// directory `path`. Pass an empty `path` to add the contents to the image
+    /// root.
+    ///
+    /// Symbolic links to files are followed. Symbolic links to directories,
+    /// and entries that are neither files nor directories, are skipped.
+    pub fn add_host_dir(&mut self, path: &str, host_path: impl AsRef<Path>) -> Result<(), Error> {
+        Ok(self.tree.add_host_dir(path, host_path)?)
+    }
+
+    /// Builds the disk.
+    pub fn build(self) -> Result<IsoDisk, Error> {
+        let image =
+            layout::build(self.tree.root(), &self.volume_id).map_err(|_| Error::ImageTooLarge)?;
+        Ok(IsoDisk {
+            image: Arc::new(image),
+        })
+    }
+}
+
+/// A read-only disk containing a synthesized ISO9660 image.
+#[derive(Inspect)]
+pub struct IsoDisk {
+    #[inspect(flatten)]
+    image: Arc<Image>,
+}
+
+impl IsoDisk {
+    /// Returns a disk containing the contents of the host directory `path`.
+    pub fn from_dir(path: impl AsRef<Path>, volume_id: impl Into<String>) -> Result<Self, Error> {
+        let mut builder = IsoBuilder::new(volume_id);
+        builder.add_host_dir("", path)?;
+        builder.build()
+    }
+}
+
+impl DiskIo for IsoDisk {
+    fn disk_type(&self) -> &str {
+        "iso"
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.image.sector_count()
+    }
+
+    fn sector_size(&self) -> u32 {
+        SECTOR_SIZE as u32
+    }
+
+    fn disk_id(&self) -> Option<[u8; 16]> {
+        None
+    }
+
+    fn physical_sector_size(&self) -> u32 {
+        SECTOR_SIZE as u32
+    }
+
+    fn is_fua_respected(&self) -> bool {
+        false
+    }
+
+    fn is_read_only(&self) -> bool {
+        true
+    }
+
+    async fn read_vectored(
+        &self,
+        buffers: &RequestBuffers<'_>,
+        sector: u64,
+    ) -> Result<(), DiskError> {
+        self.image.read_vectored(buffers, sector).await
+    }
+
+    async fn write_vectored(
+        &self,
+        _buffers: &RequestBuffers<'_>,
+        _sector: u64,
+        _fua: bool,
+    ) -> Result<(), DiskError> {
+        Err(DiskError::ReadOnly)
+    }
+
+    async fn sync_cache(&self) -> Result<(), DiskError> {
+        Err(DiskError::ReadOnly)
+    }
+
+    async fn unmap(
+        &self,
+        _sector: u64,
+        _count: u64,
+        _block_level_only: bool,
+    ) -> Result<(), DiskError> {
+        Err(DiskError::ReadOnly)
+    }
+
+    fn unmap_behavior(&self) -> UnmapBehavior {
+        UnmapBehavior::Ignored
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IsoBuilder;
+    use super::IsoDisk;
+    use super::SECTOR_SIZE;
+    use disk_backend::DiskIo;
+    use guestmem::GuestMemory;
+    use pal_async::async_test;
+    use scsi_buffers::OwnedRequestBuffers;
+
+    async fn read(disk: &IsoDisk, offset: u64, len: usize) -> Vec<u8> {
+        let sector = offset / SECTOR_SIZE as u64;
+        let skip = (offset % SECTOR_SIZE as u64) as usize;
+        let buf_len = (skip + len).next_multiple_of(SECTOR_SIZE);
+        let mem = GuestMemory::allocate(buf_len);
+        disk.read_vectored(
+            &OwnedRequestBuffers::linear(0, buf_len, true).buffer(&mem),
+            sector,
+        )
+        .await
+        .unwrap();
+        let mut data = vec![0; buf_len];
+        mem.read_at(0, &mut data).unwrap();
+        data[skip..skip + len].to_vec()
+    }
+
+    fn u32_le(b: &[u8]) -> u32 {
+        u32::from_le_bytes(b[..4].try_into().unwrap())
+    }
+
+    /// Looks up `name` in the directory extent at `extent`, returning the
+    /// record's extent location, data length, and system use area.
+    async fn lookup(
+        disk: &IsoDisk,
+        (extent, len): (u32, u32),
+        name: &[u8],
+    ) -> Option<(u32, u32, Vec<u8>)> {
+        let dir = read(disk, extent as u64 * SECTOR_SIZE as u64, len as usize).await;
+        let mut offset = 0;
+        while offset < dir.len() {
+            let record_len = dir[offset] as usize;
+            if record_len == 0 {
+                offset = (offset + 1).next_multiple_of(SECTOR_SIZE);
+                continue;
+            }
+            let record = &dir[offset..offset + record_len];
+            let id_len = record[32] as usize;
+            if &record[33..33 + id_len] == name {
+                let su = &record[33 + id_len + (id_len + 1) % 2..];
+                return Some((u32_le(&record[2..]), u32_le(&record[10..]), su.to_vec()));
+            }
+            offset += record_len;
+        }
+        None
+    }
+
+    fn joliet(name: &str) -> Vec<u8> {
+        name.encode_utf16().flat_map(|c| c.to_be_bytes()).collect()
+    }
+
+    #[async_test]
+    async fn test_iso() {
+        let mut builder = IsoBuilder::new("cidata");
+        builder
+            .add_file("meta-data", &b"instance-id: test\n"[..])
+            .unwrap();
+        let large = (0..5000u32).map(|i| i as u8).collect::<Vec<_>>();
+        builder
+            .add_file("nested/directory/a long file name.bin", large.clone())
+            .unwrap();
+        builder.add_file("empty", Vec::new()).unwrap();
+        assert!(builder.add_file("meta-data", Vec::new()).is_err());
+        let disk = builder.build().unwrap();
+
+        // Primary and Joliet volume descriptors.
+        let pvd = read(&disk, 16 * SECTOR_SIZE as u64, SECTOR_SIZE).await;
+        assert_eq!(&pvd[..7], b"\x01CD001\x01");
+        assert_eq!(&pvd[40..48], b"CIDATA  ");
+        assert_eq!(u32_le(&pvd[80..]) as u64, disk.sector_count());
+        let svd = read(&disk, 17 * SECTOR_SIZE as u64, SECTOR_SIZE).await;
+        assert_eq!(&svd[..7], b"\x02CD001\x01");
+        assert_eq!(&svd[88..91], b"%/E");
+        assert_eq!(&svd[40..52], &joliet("cidata")[..]);
+        let term = read(&disk, 18 * SECTOR_SIZE as u64, SECTOR_SIZE).await;
+        assert_eq!(&term[..7], b"\xffCD001\x01");
+
+        // Primary hierarchy, with Rock Ridge names.
+        let root = (u32_le(&pvd[158..]), u32_le(&pvd[166..]));
+        let (extent, len, su) = lookup(&disk, root, b"META_DAT.;1").await.unwrap();
+        assert_eq!(
+            read(&disk, extent as u64 * SECTOR_SIZE as u64, len as usize).await,
+            b"instance-id: test\n"
+        );
+        assert!(su.windows(11).any(|w| w == b"NM\x0e\x01\x00meta-d"));
+        let (_, len, _) = lookup(&disk, root, b"EMPTY.;1").await.unwrap();
+        assert_eq!(len, 0);
+
+        // Joliet hierarchy.
+        let root = (u32_le(&svd[158..]), u32_le(&svd[166..]));
+        let (extent, len, _) = lookup(&disk, root, &joliet("nested")).await.unwrap();
+        let (extent, len, _) = lookup(&disk, (extent, len), &joliet("directory"))
+            .await
+            .unwrap();
+        let (extent, len, _) = lookup(&disk, (extent, len), &joliet("a long file name.bin"))
+            .await
+            .unwrap();
+        assert_eq!(
+            read(&disk, extent as u64 * SECTOR_SIZE as u64, len as usize).await,
+            large
+        );
+    }
+}
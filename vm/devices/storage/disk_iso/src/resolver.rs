@@ -0,0 +1,55 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Resolver implementation for [`IsoDisk`].
+
+use crate::IsoDisk;
+use disk_backend::resolve::ResolveDiskParameters;
+use disk_backend::resolve::ResolvedDisk;
+use disk_backend_resources::IsoDirDiskHandle;
+use std::path::Path;
+use thiserror::Error;
+use vm_resource::declare_static_resolver;
+use vm_resource::kind::DiskHandleKind;
+use vm_resource::ResolveResource;
+
+/// A resolver for ISO disks built from a host directory.
+pub struct IsoDirDiskResolver;
+
+declare_static_resolver!(IsoDirDiskResolver, (DiskHandleKind, IsoDirDiskHandle));
+
+/// An error resolving an [`IsoDirDiskHandle`].
+#[derive(Debug, Error)]
+pub enum ResolveIsoDiskError {
+    /// The disk was requested as writable.
+    #[error("writable iso disks are not supported")]
+    Writable,
+    /// The image could not be built.
+    #[error("failed to build iso image")]
+    Build(#[source] crate::Error),
+    /// The disk is invalid.
+    #[error("invalid disk")]
+    InvalidDisk(#[source] disk_backend::InvalidDisk),
+}
+
+impl ResolveResource<DiskHandleKind, IsoDirDiskHandle> for IsoDirDiskResolver {
+    type Output = ResolvedDisk;
+    type Error = ResolveIsoDiskError;
+
+    fn resolve(
+        &self,
+        rsrc: IsoDirDiskHandle,
+        input: ResolveDiskParameters<'_>,
+    ) -> Result<Self::Output, Self::Error> {
+        if !input.read_only {
+            return Err(ResolveIsoDiskError::Writable);
+        }
+        let volume_id = rsrc.volume_id.unwrap_or_else(|| {
+            Path::new(&rsrc.path)
+                .file_name()
+                .map_or_else(|| "CDROM".into(), |name| name.to_string_lossy().into())
+        });
+        let disk = IsoDisk::from_dir(&rsrc.path, volume_id).map_err(ResolveIsoDiskError::Build)?;
+        ResolvedDisk::new(disk).map_err(ResolveIsoDiskError::InvalidDisk)
+    }
+}
@@ -31,6 +31,7 @@
 pub use bitmap::SectorMarker;
 
 use bitmap::Bitmap;
+use disk_backend::AllocationRun;
 use disk_backend::Disk;
 use disk_backend::DiskError;
 use disk_backend::DiskIo;
@@ -55,6 +56,7 @@ pub struct LayeredDisk {
     physical_sector_size: u32,
     unmap_behavior: UnmapBehavior,
     optimal_unmap_sectors: u32,
+    supports_allocation_query: bool,
 }
 
 #[derive(Inspect)]
@@ -94,6 +96,7 @@ pub struct DiskLayerMetadata {
     pub read_only: bool,
     pub can_read_cache: bool,
     pub is_fua_respected: bool,
+    pub supports_allocation_query: bool,
 }
 
 // DEVNOTE: this is a transient object, used solely in LayeredDisk::new.
@@ -234,6 +237,7 @@ pub async fn new(
         let mut unmap_must_zero = false;
         let mut disk_id = None;
         let mut unmap_behavior = UnmapBehavior::Zeroes;
+        let mut supports_allocation_query = false;
         for (
             i,
             &LayerConfiguration {
@@ -276,6 +280,10 @@ pub async fn new(
             if disk_id.is_none() {
                 disk_id = layer.meta.disk_id;
             }
+            // Layers that don't support allocation queries are reported as
+            // fully allocated, so one layer is enough to provide useful
+            // information.
+            supports_allocation_query |= layer.meta.supports_allocation_query;
         }
 
         if last_write_through {
@@ -323,6 +331,7 @@ pub async fn new(
             physical_sector_size,
             unmap_behavior,
             optimal_unmap_sectors,
+            supports_allocation_query,
             layers,
         })
     }
@@ -357,6 +366,12 @@ fn unmap(
     ) -> Pin<Box<dyn '_ + Future<Output = Result<(), DiskError>> + Send>>;
 
     fn wait_resize(&self, sector_count: u64) -> Pin<Box<dyn '_ + Future<Output = u64> + Send>>;
+
+    fn query_allocation(
+        &self,
+        sector: u64,
+        count: u64,
+    ) -> Pin<Box<dyn '_ + Future<Output = Result<LayerAllocationRun, DiskError>> + Send>>;
 }
 
 impl<T: LayerIo> DynLayerIo for T {
@@ -409,6 +424,24 @@ fn unmap(
     fn wait_resize(&self, sector_count: u64) -> Pin<Box<dyn '_ + Future<Output = u64> + Send>> {
         Box::pin(self.wait_resize(sector_count))
     }
+
+    fn query_allocation(
+        &self,
+        sector: u64,
+        count: u64,
+    ) -> Pin<Box<dyn '_ + Future<Output = Result<LayerAllocationRun, DiskError>> + Send>> {
+        Box::pin(async move {
+            if self.supports_allocation_query() {
+                self.query_allocation(sector, count).await
+            } else {
+                // Assume the layer is fully present and allocated.
+                Ok(LayerAllocationRun {
+                    state: LayerAllocation::Allocated,
+                    count,
+                })
+            }
+        })
+    }
 }
 
 trait DynLayerAttach: Send + Sync + Inspect {
@@ -441,6 +474,7 @@ fn attach(
                         optimal_unmap_sectors: backing.optimal_unmap_sectors(),
                         read_only: backing.is_read_only(),
                         can_read_cache,
+                        supports_allocation_query: backing.supports_allocation_query(),
                     },
                     backing: Box::new(backing),
                 }
@@ -580,6 +614,49 @@ fn wait_resize(&self, sector_count: u64) -> impl Future<Output = u64> + Send {
         let _ = sector_count;
         std::future::pending()
     }
+
+    /// Returns true if the layer implements [`LayerIo::query_allocation`].
+    ///
+    /// This must not change at runtime. Layers that don't support this are
+    /// treated as fully present and allocated.
+    fn supports_allocation_query(&self) -> bool {
+        false
+    }
+
+    /// Queries which sectors are present and allocated in the layer.
+    ///
+    /// Returns the state of `sector` and the number of consecutive sectors,
+    /// starting at `sector` and up to `count`, that share that state.
+    fn query_allocation(
+        &self,
+        sector: u64,
+        count: u64,
+    ) -> impl Future<Output = Result<LayerAllocationRun, DiskError>> + Send {
+        let _ = (sector, count);
+        std::future::ready(Err(DiskError::InvalidInput))
+    }
+}
+
+/// The state of sectors in a layer, as returned by [`LayerIo::query_allocation`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LayerAllocation {
+    /// The sectors are present and backed by storage in this layer.
+    Allocated,
+    /// The sectors are present in this layer but are not backed by storage.
+    /// They read as zero.
+    Zero,
+    /// The sectors are not present in this layer. Their contents come from
+    /// the next layer.
+    NotPresent,
+}
+
+/// A run of sectors with the same [`LayerAllocation`] state.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct LayerAllocationRun {
+    /// The state of the sectors.
+    pub state: LayerAllocation,
+    /// The number of sectors in the run. This is at least one.
+    pub count: u64,
 }
 
 enum NoIdet {}
@@ -780,6 +857,46 @@ fn unmap_behavior(&self) -> UnmapBehavior {
     fn optimal_unmap_sectors(&self) -> u32 {
         self.optimal_unmap_sectors
     }
+
+    fn supports_allocation_query(&self) -> bool {
+        self.supports_allocation_query
+    }
+
+    async fn query_allocation(&self, sector: u64, count: u64) -> Result<AllocationRun, DiskError> {
+        // Walk down the layers until a layer has the sector present, limiting
+        // the run to the sectors that are not present in any layer above.
+        let mut count = count;
+        for (i, layer) in self.layers.iter().enumerate() {
+            if i > 0 {
+                // Sectors beyond the layer's visible sector count are logically
+                // zero.
+                if sector >= layer.visible_sector_count {
+                    break;
+                }
+                count = count.min(layer.visible_sector_count - sector);
+            }
+            let run = layer.backing.query_allocation(sector, count).await?;
+            match run.state {
+                LayerAllocation::Allocated => {
+                    return Ok(AllocationRun {
+                        allocated: true,
+                        count: run.count,
+                    });
+                }
+                LayerAllocation::Zero => {
+                    return Ok(AllocationRun {
+                        allocated: false,
+                        count: run.count,
+                    });
+                }
+                LayerAllocation::NotPresent => count = run.count,
+            }
+        }
+        Ok(AllocationRun {
+            allocated: false,
+            count,
+        })
+    }
 }
 
 /// A disk layer wrapping a full disk.
@@ -853,4 +970,25 @@ fn unmap(
     fn unmap_behavior(&self) -> UnmapBehavior {
         self.0.unmap_behavior()
     }
+
+    fn supports_allocation_query(&self) -> bool {
+        self.0.supports_allocation_query()
+    }
+
+    async fn query_allocation(
+        &self,
+        sector: u64,
+        count: u64,
+    ) -> Result<LayerAllocationRun, DiskError> {
+        // The disk is fully present.
+        let run = self.0.query_allocation(sector, count).await?;
+        Ok(LayerAllocationRun {
+            state: if run.allocated {
+                LayerAllocation::Allocated
+            } else {
+                LayerAllocation::Zero
+            },
+            count: run.count,
+        })
+    }
 }
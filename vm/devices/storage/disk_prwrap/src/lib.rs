@@ -4,8 +4,21 @@
 //! Provides a basic implementation of SCSI persistent reservations on top of
 //! any other disk type.
 //!
-//! Since these reservations are stored locally in memory, this is not useful
-//! for actually sharing a disk between VMs. This is just useful for testing.
+//! By default, these reservations are stored locally in memory, which is just
+//! useful for testing. Alternatively, the reservations can be kept in a store
+//! file shared by multiple processes, so that several local VMs can exercise
+//! cluster failover against one shared disk.
+//!
+//! PREEMPT AND ABORT is not supported, since commands already issued by the
+//! preempted host cannot be aborted.
+//!
+//! Local reservations are saved and restored with the SCSI disk's saved
+//! state. The NVMe controller does not support save/restore, so NVMe
+//! namespaces only keep their reservations across a restart when they use a
+//! shared store.
+
+mod state;
+mod store;
 
 use async_trait::async_trait;
 use disk_backend::pr;
@@ -19,9 +32,13 @@
 use inspect::Inspect;
 use parking_lot::Mutex;
 use scsi_buffers::RequestBuffers;
+use state::ReservationState;
+use std::fs::File;
 use std::future::Future;
-use std::num::NonZeroU64;
-use std::num::Wrapping;
+use std::io;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use store::SharedStore;
 use thiserror::Error;
 use vm_resource::declare_static_async_resolver;
 use vm_resource::kind::DiskHandleKind;
@@ -41,6 +58,8 @@ pub enum ResolvePrDiskError {
     Resolve(#[source] ResolveError),
     #[error("invalid disk")]
     InvalidDisk(#[source] disk_backend::InvalidDisk),
+    #[error("failed to open reservation store")]
+    Store(#[source] std::io::Error),
 }
 
 #[async_trait]
@@ -57,32 +76,76 @@ async fn resolve(
         input: ResolveDiskParameters<'_>,
     ) -> Result<Self::Output, Self::Error> {
         let inner = resolver
-            .resolve(rsrc.0, input)
+            .resolve(rsrc.disk, input)
             .await
             .map_err(ResolvePrDiskError::Resolve)?;
 
-        ResolvedDisk::new(DiskWithReservations::new(inner.0))
-            .map_err(ResolvePrDiskError::InvalidDisk)
+        let disk = match rsrc.store {
+            Some(store) => DiskWithReservations::with_shared_store(inner.0, store)
+                .map_err(ResolvePrDiskError::Store)?,
+            None => DiskWithReservations::new(inner.0),
+        };
+        ResolvedDisk::new(disk).map_err(ResolvePrDiskError::InvalidDisk)
     }
 }
 
 /// A disk wrapper that adds persistent reservations support to any disk type.
 ///
-/// The reservations are handled locally in memory, so they cannot be used to
-/// actually share a disk.
+/// By default, the reservations are handled locally in memory, so they cannot
+/// be used to actually share a disk. Use
+/// [`DiskWithReservations::with_shared_store`] to keep the reservations in a
+/// file shared with other processes opening the same disk.
 #[derive(Inspect)]
 pub struct DiskWithReservations {
     inner: Disk,
+    #[inspect(with = "|x| inspect::AsHex(x.load(Ordering::Relaxed))")]
+    host_id: AtomicU64,
     #[inspect(flatten)]
-    state: Mutex<ReservationState>,
+    backing: Backing,
 }
 
-#[derive(Default, Debug, Inspect)]
-struct ReservationState {
-    generation: Wrapping<u32>,
-    registered_key: Option<NonZeroU64>,
-    reservation_type: Option<ReservationType>,
-    persist_through_power_loss: bool,
+enum Backing {
+    Local(Mutex<ReservationState>),
+    Shared(SharedStore),
+}
+
+impl Inspect for Backing {
+    fn inspect(&self, req: inspect::Request<'_>) {
+        match self {
+            Backing::Local(state) => state.lock().inspect(req),
+            Backing::Shared(store) => store.with_cached(|state| {
+                req.respond().field("shared", true).merge(state);
+            }),
+        }
+    }
+}
+
+impl Backing {
+    /// Runs a reservation command.
+    async fn update<R: 'static + Send>(
+        &self,
+        f: impl 'static + Send + FnOnce(&mut ReservationState) -> Result<R, DiskError>,
+    ) -> Result<R, DiskError> {
+        match self {
+            Backing::Local(state) => {
+                let mut state = state.lock();
+                f(&mut state)
+            }
+            Backing::Shared(store) => store.update(f).await,
+        }
+    }
+
+    /// Checks whether `host_id` may access the disk. For a shared store, reads
+    /// are checked against cached state, which may be slightly out of date.
+    async fn check_access(&self, host_id: u64, write: bool) -> Result<(), DiskError> {
+        match self {
+            Backing::Local(state) => {
+                let state = state.lock();
+                state.check_access(host_id, write)
+            }
+            Backing::Shared(store) => store.check_access(host_id, write).await,
+        }
+    }
 }
 
 impl DiskWithReservations {
@@ -90,9 +153,38 @@ impl DiskWithReservations {
     pub fn new(inner: Disk) -> Self {
         Self {
             inner,
-            state: Default::default(),
+            // There is only one host, so there's no need for a unique ID.
+            host_id: AtomicU64::new(0),
+            backing: Backing::Local(Default::default()),
         }
     }
+
+    /// Wraps `inner` with persistent reservations support, storing the
+    /// reservation state in `store`.
+    ///
+    /// Multiple instances, in this process or others, can share the same store
+    /// file to emulate a disk shared by multiple hosts. Each instance gets a
+    /// random host ID, which is preserved across save/restore.
+    ///
+    /// Reservation changes made by other instances are enforced on this
+    /// instance's writes immediately, and on its reads within 100ms.
+    pub fn with_shared_store(inner: Disk, store: File) -> io::Result<Self> {
+        let mut host_id = [0; 8];
+        getrandom::getrandom(&mut host_id).map_err(io::Error::from)?;
+        Ok(Self {
+            inner,
+            host_id: AtomicU64::new(u64::from_ne_bytes(host_id) | 1),
+            backing: Backing::Shared(SharedStore::new(store)?),
+        })
+    }
+
+    fn host_id(&self) -> u64 {
+        self.host_id.load(Ordering::Relaxed)
+    }
+
+    async fn check_access(&self, write: bool) -> Result<(), DiskError> {
+        self.backing.check_access(self.host_id(), write).await
+    }
 }
 
 impl DiskIo for DiskWithReservations {
@@ -124,13 +216,14 @@ fn is_read_only(&self) -> bool {
         self.inner.is_read_only()
     }
 
-    fn unmap(
+    async fn unmap(
         &self,
         sector: u64,
         count: u64,
         block_level_only: bool,
-    ) -> impl Future<Output = Result<(), DiskError>> + Send {
-        self.inner.unmap(sector, count, block_level_only)
+    ) -> Result<(), DiskError> {
+        self.check_access(true).await?;
+        self.inner.unmap(sector, count, block_level_only).await
     }
 
     fn unmap_behavior(&self) -> disk_backend::UnmapBehavior {
@@ -141,6 +234,18 @@ fn optimal_unmap_sectors(&self) -> u32 {
         self.inner.optimal_unmap_sectors()
     }
 
+    fn supports_allocation_query(&self) -> bool {
+        self.inner.supports_allocation_query()
+    }
+
+    fn query_allocation(
+        &self,
+        sector: u64,
+        count: u64,
+    ) -> impl Future<Output = Result<disk_backend::AllocationRun, DiskError>> + Send {
+        self.inner.query_allocation(sector, count)
+    }
+
     fn pr(&self) -> Option<&dyn pr::PersistentReservation> {
         Some(self)
     }
@@ -150,6 +255,7 @@ async fn read_vectored(
         buffers: &RequestBuffers<'_>,
         sector: u64,
     ) -> Result<(), DiskError> {
+        self.check_access(false).await?;
         self.inner.read_vectored(buffers, sector).await
     }
 
@@ -159,6 +265,7 @@ async fn write_vectored(
         sector: u64,
         fua: bool,
     ) -> Result<(), DiskError> {
+        self.check_access(true).await?;
         self.inner.write_vectored(buffers, sector, fua).await
     }
 
@@ -183,23 +290,7 @@ fn capabilities(&self) -> pr::ReservationCapabilities {
 
     async fn report(&self) -> Result<pr::ReservationReport, DiskError> {
         tracing::info!("reading full status");
-        let state = self.state.lock();
-        let report = pr::ReservationReport {
-            generation: state.generation.0,
-            reservation_type: state.reservation_type,
-            persist_through_power_loss: state.persist_through_power_loss,
-            controllers: state
-                .registered_key
-                .iter()
-                .map(|&key| pr::RegisteredController {
-                    key: key.get(),
-                    host_id: vec![0; 8],
-                    controller_id: 0,
-                    holds_reservation: state.reservation_type.is_some(),
-                })
-                .collect(),
-        };
-        Ok(report)
+        self.backing.update(|state| Ok(state.report())).await
     }
 
     async fn register(
@@ -208,61 +299,31 @@ async fn register(
         new_key: u64,
         ptpl: Option<bool>,
     ) -> Result<(), DiskError> {
-        let mut state = self.state.lock();
-        if let Some(current_key) = current_key {
-            if state.registered_key != NonZeroU64::new(current_key) {
-                return Err(DiskError::ReservationConflict);
-            }
-        }
-        let new_key = NonZeroU64::new(new_key);
-        state.registered_key = new_key;
-        if new_key.is_none() {
-            state.reservation_type = None;
-        }
-        if let Some(ptpl) = ptpl {
-            state.persist_through_power_loss = ptpl;
-        }
-        state.generation += 1;
-        Ok(())
+        let host_id = self.host_id();
+        self.backing
+            .update(move |state| state.register(host_id, current_key, new_key, ptpl))
+            .await
     }
 
     async fn reserve(&self, key: u64, reservation_type: ReservationType) -> Result<(), DiskError> {
-        let mut state = self.state.lock();
-        if state.registered_key.is_none()
-            || state.registered_key != NonZeroU64::new(key)
-            || (state.reservation_type.is_some()
-                && state.reservation_type != Some(reservation_type))
-        {
-            return Err(DiskError::ReservationConflict);
-        }
-        state.reservation_type = Some(reservation_type);
-        Ok(())
+        let host_id = self.host_id();
+        self.backing
+            .update(move |state| state.reserve(host_id, key, reservation_type))
+            .await
     }
 
     async fn release(&self, key: u64, reservation_type: ReservationType) -> Result<(), DiskError> {
-        let mut state = self.state.lock();
-        if state.registered_key.is_none() || state.registered_key != NonZeroU64::new(key) {
-            return Err(DiskError::ReservationConflict);
-        }
-
-        if state.reservation_type.is_some() {
-            if state.reservation_type != Some(reservation_type) {
-                return Err(DiskError::InvalidInput);
-            }
-            state.reservation_type = None;
-        }
-        Ok(())
+        let host_id = self.host_id();
+        self.backing
+            .update(move |state| state.release(host_id, key, reservation_type))
+            .await
     }
 
     async fn clear(&self, key: u64) -> Result<(), DiskError> {
-        let mut state = self.state.lock();
-        if state.registered_key.is_none() || state.registered_key != NonZeroU64::new(key) {
-            return Err(DiskError::ReservationConflict);
-        }
-        state.registered_key = None;
-        state.reservation_type = None;
-        state.generation += 1;
-        Ok(())
+        let host_id = self.host_id();
+        self.backing
+            .update(move |state| state.clear(host_id, key))
+            .await
     }
 
     async fn preempt(
@@ -270,21 +331,213 @@ async fn preempt(
         current_key: u64,
         preempt_key: u64,
         reservation_type: ReservationType,
-        _abort: bool,
+        abort: bool,
     ) -> Result<(), DiskError> {
-        let mut state = self.state.lock();
-        if state.registered_key.is_none() || state.registered_key != NonZeroU64::new(current_key) {
-            return Err(DiskError::ReservationConflict);
-        }
-        if state.registered_key != NonZeroU64::new(preempt_key)
-            || (state.reservation_type.is_some()
-                && state.reservation_type != Some(reservation_type))
-        {
+        if abort {
             return Err(DiskError::InvalidInput);
         }
+        let host_id = self.host_id();
+        self.backing
+            .update(move |state| state.preempt(host_id, current_key, preempt_key, reservation_type))
+            .await
+    }
+
+    fn save(&self) -> Result<Option<pr::SavedReservationState>, DiskError> {
+        let host_id = self.host_id();
+        let saved = match &self.backing {
+            Backing::Local(state) => state.lock().save(host_id),
+            // The shared store is authoritative and is not restored, so the
+            // cached state is good enough.
+            Backing::Shared(store) => store.with_cached(|state| state.save(host_id)),
+        };
+        Ok(Some(saved))
+    }
 
-        state.reservation_type = None;
-        state.generation += 1;
+    fn restore(&self, saved: &pr::SavedReservationState) -> Result<(), DiskError> {
+        self.host_id.store(saved.host_id, Ordering::Relaxed);
+        match &self.backing {
+            Backing::Local(state) => {
+                *state.lock() = ReservationState::from_saved(saved);
+            }
+            Backing::Shared(_) => {
+                // The shared store is authoritative. Just resume using the
+                // saved host ID, so that the registrations in the store still
+                // belong to this instance.
+            }
+        }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use disk_backend::pr::PersistentReservation;
+    use guestmem::GuestMemory;
+    use pal_async::async_test;
+    use pal_async::timer::PolledTimer;
+    use pal_async::DefaultDriver;
+    use scsi_buffers::OwnedRequestBuffers;
+
+    async fn access(disk: &DiskWithReservations, write: bool) -> Result<(), DiskError> {
+        let mem = GuestMemory::allocate(512);
+        let buffers = OwnedRequestBuffers::linear(0, 512, !write);
+        if write {
+            disk.write_vectored(&buffers.buffer(&mem), 0, false).await
+        } else {
+            disk.read_vectored(&buffers.buffer(&mem), 0).await
+        }
+    }
+
+    fn shared_pair() -> (
+        DiskWithReservations,
+        DiskWithReservations,
+        tempfile::NamedTempFile,
+    ) {
+        let inner = disklayer_ram::ram_disk(0x10000, false).unwrap();
+        let store = tempfile::NamedTempFile::new().unwrap();
+        let a = DiskWithReservations::with_shared_store(inner.clone(), store.reopen().unwrap())
+            .unwrap();
+        let b = DiskWithReservations::with_shared_store(inner, store.reopen().unwrap()).unwrap();
+        (a, b, store)
+    }
+
+    #[async_test]
+    async fn test_shared_failover() {
+        let (a, b, _store) = shared_pair();
+
+        a.register(None, 0xa, None).await.unwrap();
+        a.reserve(0xa, ReservationType::WriteExclusive)
+            .await
+            .unwrap();
+        b.register(None, 0xb, None).await.unwrap();
+
+        // Only the holder may write, but anyone may read.
+        access(&a, true).await.unwrap();
+        access(&b, false).await.unwrap();
+        assert!(matches!(
+            access(&b, true).await,
+            Err(DiskError::ReservationConflict)
+        ));
+        assert!(matches!(
+            b.reserve(0xb, ReservationType::WriteExclusive).await,
+            Err(DiskError::ReservationConflict)
+        ));
+
+        // Take over the reservation from the failed host.
+        assert!(matches!(
+            b.preempt(0xb, 0xa, ReservationType::ExclusiveAccess, true)
+                .await,
+            Err(DiskError::InvalidInput)
+        ));
+        b.preempt(0xb, 0xa, ReservationType::ExclusiveAccess, false)
+            .await
+            .unwrap();
+        access(&b, true).await.unwrap();
+        // The failed host is fenced as soon as the preempt completes.
+        assert!(matches!(
+            access(&a, true).await,
+            Err(DiskError::ReservationConflict)
+        ));
+        assert!(matches!(
+            access(&a, false).await,
+            Err(DiskError::ReservationConflict)
+        ));
+        assert!(matches!(
+            a.register(Some(0xa), 0xa, None).await,
+            Err(DiskError::ReservationConflict)
+        ));
+
+        let report = a.report().await.unwrap();
+        assert_eq!(report.generation, 3);
+        assert_eq!(
+            report.reservation_type,
+            Some(ReservationType::ExclusiveAccess)
+        );
+        assert_eq!(report.controllers.len(), 1);
+        assert_eq!(report.controllers[0].key, 0xb);
+        assert!(report.controllers[0].holds_reservation);
+    }
+
+    #[async_test]
+    async fn test_shared_read_refresh(driver: DefaultDriver) {
+        let (a, b, _store) = shared_pair();
+        access(&b, false).await.unwrap();
+        a.register(None, 0xa, None).await.unwrap();
+        a.reserve(0xa, ReservationType::ExclusiveAccess)
+            .await
+            .unwrap();
+        // Reads may use the cached state for a while, but not past the
+        // refresh interval.
+        PolledTimer::new(&driver)
+            .sleep(store::REFRESH_INTERVAL)
+            .await;
+        assert!(matches!(
+            access(&b, false).await,
+            Err(DiskError::ReservationConflict)
+        ));
+    }
+
+    #[async_test]
+    async fn test_save_restore_local() {
+        let inner = disklayer_ram::ram_disk(0x10000, false).unwrap();
+        let disk = DiskWithReservations::new(inner.clone());
+        disk.register(None, 0x1234, Some(true)).await.unwrap();
+        disk.reserve(0x1234, ReservationType::ExclusiveAccessRegistrantsOnly)
+            .await
+            .unwrap();
+        let saved = disk.save().unwrap().unwrap();
+
+        let disk = DiskWithReservations::new(inner);
+        disk.restore(&saved).unwrap();
+        let report = disk.report().await.unwrap();
+        assert_eq!(report.generation, 1);
+        assert!(report.persist_through_power_loss);
+        assert_eq!(
+            report.reservation_type,
+            Some(ReservationType::ExclusiveAccessRegistrantsOnly)
+        );
+        assert_eq!(report.controllers.len(), 1);
+        assert_eq!(report.controllers[0].key, 0x1234);
+        assert!(report.controllers[0].holds_reservation);
+        access(&disk, true).await.unwrap();
+        assert_eq!(disk.save().unwrap().unwrap(), saved);
+    }
+
+    #[async_test]
+    async fn test_save_restore_shared() {
+        let (a, b, store) = shared_pair();
+        a.register(None, 0xa, None).await.unwrap();
+        a.reserve(0xa, ReservationType::WriteExclusive)
+            .await
+            .unwrap();
+        let saved = a.save().unwrap().unwrap();
+        drop(a);
+
+        // A new instance picks up the registration after restoring the host
+        // ID, while the other host still sees the reservation.
+        let inner = disklayer_ram::ram_disk(0x10000, false).unwrap();
+        let a = DiskWithReservations::with_shared_store(inner, store.reopen().unwrap()).unwrap();
+        assert!(matches!(
+            access(&a, true).await,
+            Err(DiskError::ReservationConflict)
+        ));
+        a.restore(&saved).unwrap();
+        access(&a, true).await.unwrap();
+        a.release(0xa, ReservationType::WriteExclusive)
+            .await
+            .unwrap();
+        let report = b.report().await.unwrap();
+        assert_eq!(report.reservation_type, None);
+        assert_eq!(report.controllers.len(), 1);
+        assert_eq!(report.controllers[0].host_id, saved.host_id.to_le_bytes());
+    }
+
+    #[test]
+    fn test_invalid_store() {
+        let inner = disklayer_ram::ram_disk(0x10000, false).unwrap();
+        let mut store = tempfile::tempfile().unwrap();
+        std::io::Write::write_all(&mut store, b"not a reservation store").unwrap();
+        assert!(DiskWithReservations::with_shared_store(inner, store).is_err());
+    }
+}
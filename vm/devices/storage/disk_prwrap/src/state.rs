@@ -0,0 +1,275 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Registration and reservation state, shared by all hosts accessing a disk.
+
+use disk_backend::pr;
+use disk_backend::pr::ReservationType;
+use disk_backend::DiskError;
+use inspect::Inspect;
+use std::num::NonZeroU64;
+use std::num::Wrapping;
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Inspect)]
+pub(crate) struct ReservationState {
+    pub generation: Wrapping<u32>,
+    #[inspect(iter_by_index)]
+    pub registrations: Vec<Registration>,
+    #[inspect(hex)]
+    pub holder: Option<u64>,
+    pub reservation_type: Option<ReservationType>,
+    pub persist_through_power_loss: bool,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Inspect)]
+pub(crate) struct Registration {
+    #[inspect(hex)]
+    pub host_id: u64,
+    #[inspect(hex)]
+    pub key: NonZeroU64,
+}
+
+impl ReservationState {
+    /// Returns the registration key for `host_id`.
+    pub fn key(&self, host_id: u64) -> Option<NonZeroU64> {
+        self.registrations
+            .iter()
+            .find(|r| r.host_id == host_id)
+            .map(|r| r.key)
+    }
+
+    /// Returns whether `host_id` holds the current reservation.
+    ///
+    /// For the all registrants reservation types, every registrant is a
+    /// reservation holder.
+    pub fn holds(&self, host_id: u64) -> bool {
+        if self.reservation_type.is_none() {
+            false
+        } else if self.is_all_registrants() {
+            self.key(host_id).is_some()
+        } else {
+            self.holder == Some(host_id)
+        }
+    }
+
+    fn is_all_registrants(&self) -> bool {
+        matches!(
+            self.reservation_type,
+            Some(
+                ReservationType::WriteExclusiveAllRegistrants
+                    | ReservationType::ExclusiveAccessAllRegistrants
+            )
+        )
+    }
+
+    fn check_key(&self, host_id: u64, key: u64) -> Result<NonZeroU64, DiskError> {
+        match self.key(host_id) {
+            Some(registered) if registered.get() == key => Ok(registered),
+            _ => Err(DiskError::ReservationConflict),
+        }
+    }
+
+    fn clear_reservation(&mut self) {
+        self.holder = None;
+        self.reservation_type = None;
+    }
+
+    /// Checks whether `host_id` may access the disk under the current
+    /// reservation.
+    pub fn check_access(&self, host_id: u64, write: bool) -> Result<(), DiskError> {
+        let Some(reservation_type) = self.reservation_type else {
+            return Ok(());
+        };
+        let (exclusive_access, registrants) = match reservation_type {
+            ReservationType::WriteExclusive => (false, false),
+            ReservationType::ExclusiveAccess => (true, false),
+            ReservationType::WriteExclusiveRegistrantsOnly
+            | ReservationType::WriteExclusiveAllRegistrants => (false, true),
+            ReservationType::ExclusiveAccessRegistrantsOnly
+            | ReservationType::ExclusiveAccessAllRegistrants => (true, true),
+        };
+        if !write && !exclusive_access {
+            return Ok(());
+        }
+        let allowed = if registrants {
+            self.key(host_id).is_some()
+        } else {
+            self.holds(host_id)
+        };
+        if allowed {
+            Ok(())
+        } else {
+            Err(DiskError::ReservationConflict)
+        }
+    }
+
+    pub fn report(&self) -> pr::ReservationReport {
+        pr::ReservationReport {
+            generation: self.generation.0,
+            reservation_type: self.reservation_type,
+            persist_through_power_loss: self.persist_through_power_loss,
+            controllers: self
+                .registrations
+                .iter()
+                .map(|r| pr::RegisteredController {
+                    key: r.key.get(),
+                    host_id: r.host_id.to_le_bytes().to_vec(),
+                    controller_id: 0,
+                    holds_reservation: self.holds(r.host_id),
+                })
+                .collect(),
+        }
+    }
+
+    pub fn register(
+        &mut self,
+        host_id: u64,
+        current_key: Option<u64>,
+        new_key: u64,
+        ptpl: Option<bool>,
+    ) -> Result<(), DiskError> {
+        if let Some(current_key) = current_key {
+            if self.key(host_id) != NonZeroU64::new(current_key) {
+                return Err(DiskError::ReservationConflict);
+            }
+        }
+        let held = self.holds(host_id);
+        self.registrations.retain(|r| r.host_id != host_id);
+        if let Some(key) = NonZeroU64::new(new_key) {
+            self.registrations.push(Registration { host_id, key });
+        } else if held && (!self.is_all_registrants() || self.registrations.is_empty()) {
+            // Unregistering the holder releases the reservation, unless it is
+            // an all registrants reservation with other registrants left.
+            self.clear_reservation();
+        }
+        if let Some(ptpl) = ptpl {
+            self.persist_through_power_loss = ptpl;
+        }
+        self.generation += 1;
+        Ok(())
+    }
+
+    pub fn reserve(
+        &mut self,
+        host_id: u64,
+        key: u64,
+        reservation_type: ReservationType,
+    ) -> Result<(), DiskError> {
+        self.check_key(host_id, key)?;
+        match self.reservation_type {
+            None => {
+                self.holder = Some(host_id);
+                self.reservation_type = Some(reservation_type);
+            }
+            Some(current) => {
+                if current != reservation_type || !self.holds(host_id) {
+                    return Err(DiskError::ReservationConflict);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn release(
+        &mut self,
+        host_id: u64,
+        key: u64,
+        reservation_type: ReservationType,
+    ) -> Result<(), DiskError> {
+        self.check_key(host_id, key)?;
+        // Releasing a reservation held by someone else is a no-op.
+        if self.holds(host_id) {
+            if self.reservation_type != Some(reservation_type) {
+                return Err(DiskError::InvalidInput);
+            }
+            self.clear_reservation();
+        }
+        Ok(())
+    }
+
+    pub fn clear(&mut self, host_id: u64, key: u64) -> Result<(), DiskError> {
+        self.check_key(host_id, key)?;
+        self.registrations.clear();
+        self.clear_reservation();
+        self.generation += 1;
+        Ok(())
+    }
+
+    pub fn preempt(
+        &mut self,
+        host_id: u64,
+        current_key: u64,
+        preempt_key: u64,
+        reservation_type: ReservationType,
+    ) -> Result<(), DiskError> {
+        let key = self.check_key(host_id, current_key)?;
+        let preempt_key = NonZeroU64::new(preempt_key).ok_or(DiskError::InvalidInput)?;
+
+        if preempt_key == key {
+            // Preempting our own key just drops our reservation.
+            if self.holds(host_id) {
+                if self.reservation_type != Some(reservation_type) {
+                    return Err(DiskError::InvalidInput);
+                }
+                self.clear_reservation();
+            }
+            self.generation += 1;
+            return Ok(());
+        }
+
+        let holder_key = self.holder.and_then(|holder| self.key(holder));
+        let count = self.registrations.len();
+        self.registrations
+            .retain(|r| r.key != preempt_key || r.host_id == host_id);
+        if self.registrations.len() == count {
+            return Err(DiskError::InvalidInput);
+        }
+
+        // If the holder's registration was removed, the reservation moves to
+        // the preempting host with the requested type.
+        if self.reservation_type.is_some() && holder_key == Some(preempt_key) {
+            self.holder = Some(host_id);
+            self.reservation_type = Some(reservation_type);
+        }
+        self.generation += 1;
+        Ok(())
+    }
+
+    /// Returns the state saved for `host_id`.
+    pub fn save(&self, host_id: u64) -> pr::SavedReservationState {
+        pr::SavedReservationState {
+            host_id,
+            generation: self.generation.0,
+            key: self.key(host_id).map(NonZeroU64::get),
+            reservation_type: self
+                .holds(host_id)
+                .then_some(self.reservation_type)
+                .flatten(),
+            persist_through_power_loss: self.persist_through_power_loss,
+        }
+    }
+
+    /// Rebuilds a single host's state from a saved state.
+    pub fn from_saved(state: &pr::SavedReservationState) -> Self {
+        let &pr::SavedReservationState {
+            host_id,
+            generation,
+            key,
+            reservation_type,
+            persist_through_power_loss,
+        } = state;
+        let registrations = key
+            .and_then(NonZeroU64::new)
+            .map(|key| Registration { host_id, key })
+            .into_iter()
+            .collect::<Vec<_>>();
+        let reservation_type = reservation_type.filter(|_| !registrations.is_empty());
+        Self {
+            generation: Wrapping(generation),
+            registrations,
+            holder: reservation_type.map(|_| host_id),
+            reservation_type,
+            persist_through_power_loss,
+        }
+    }
+}
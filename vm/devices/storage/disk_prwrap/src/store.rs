@@ -0,0 +1,370 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A reservation store kept in a file, so that multiple processes opening the
+//! same backing disk can share registrations and reservations.
+//!
+//! Reservation commands lock the file and reread and rewrite the state, so
+//! they are coherent across processes without any other coordination. Writes
+//! are checked against the current state: before each one, the store's
+//! sequence number is checked, and the state is reread if another instance
+//! has changed it. This fences a host as soon as another host preempts it.
+//! Reads are checked against a cached copy of the state, which is refreshed
+//! the same way after at most [`REFRESH_INTERVAL`].
+//!
+//! All file I/O is done on a blocking thread.
+
+use crate::state::Registration;
+use crate::state::ReservationState;
+use blocking::unblock;
+use disk_backend::pr::ReservationType;
+use disk_backend::DiskError;
+use parking_lot::Mutex;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::num::NonZeroU64;
+use std::num::Wrapping;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+use zerocopy::AsBytes;
+use zerocopy::FromBytes;
+use zerocopy::FromZeroes;
+
+const MAGIC: [u8; 8] = *b"PRWRAPv1";
+const VERSION: u32 = 1;
+
+/// The longest time that disk reads are checked against the cached state
+/// without looking for changes made by other instances.
+pub(crate) const REFRESH_INTERVAL: Duration = Duration::from_millis(100);
+
+#[repr(C)]
+#[derive(AsBytes, FromBytes, FromZeroes)]
+struct Header {
+    magic: [u8; 8],
+    version: u32,
+    generation: u32,
+    holder: u64,
+    /// 0 if there is no reservation, otherwise [`type_to_u32`].
+    reservation_type: u32,
+    persist_through_power_loss: u32,
+    registration_count: u32,
+    /// Incremented on every change to the store, so that other instances can
+    /// detect changes without rereading the whole store. Unlike
+    /// `generation`, this also changes on reserve and release.
+    sequence: u32,
+}
+
+#[repr(C)]
+#[derive(AsBytes, FromBytes, FromZeroes)]
+struct StoredRegistration {
+    host_id: u64,
+    key: u64,
+}
+
+/// The maximum number of registrations in the store, to bound the file size.
+const MAX_REGISTRATIONS: u32 = 1024;
+
+fn type_to_u32(reservation_type: ReservationType) -> u32 {
+    match reservation_type {
+        ReservationType::WriteExclusive => 1,
+        ReservationType::ExclusiveAccess => 2,
+        ReservationType::WriteExclusiveRegistrantsOnly => 3,
+        ReservationType::ExclusiveAccessRegistrantsOnly => 4,
+        ReservationType::WriteExclusiveAllRegistrants => 5,
+        ReservationType::ExclusiveAccessAllRegistrants => 6,
+    }
+}
+
+fn type_from_u32(value: u32) -> io::Result<Option<ReservationType>> {
+    let reservation_type = match value {
+        0 => return Ok(None),
+        1 => ReservationType::WriteExclusive,
+        2 => ReservationType::ExclusiveAccess,
+        3 => ReservationType::WriteExclusiveRegistrantsOnly,
+        4 => ReservationType::ExclusiveAccessRegistrantsOnly,
+        5 => ReservationType::WriteExclusiveAllRegistrants,
+        6 => ReservationType::ExclusiveAccessAllRegistrants,
+        _ => return Err(invalid_data("invalid reservation type")),
+    };
+    Ok(Some(reservation_type))
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// A reservation store backed by a file shared between processes.
+pub(crate) struct SharedStore {
+    file: Arc<Mutex<File>>,
+    cache: Mutex<Cache>,
+}
+
+/// The most recently read state of the store.
+struct Cache {
+    state: ReservationState,
+    sequence: u32,
+    checked: Instant,
+}
+
+impl Cache {
+    fn set(&mut self, state: ReservationState, sequence: u32) {
+        // Ignore results that raced with a newer read or update.
+        if sequence.wrapping_sub(self.sequence) as i32 >= 0 {
+            self.state = state;
+            self.sequence = sequence;
+        }
+        self.checked = Instant::now();
+    }
+}
+
+impl SharedStore {
+    /// Uses `file` as the store. An empty file is treated as a store with no
+    /// registrations.
+    pub fn new(file: File) -> io::Result<Self> {
+        // Validate the existing contents.
+        let (state, sequence) = {
+            let _lock = sys::FileLock::new(&file)?;
+            read_state(&file)?
+        };
+        Ok(Self {
+            file: Arc::new(Mutex::new(file)),
+            cache: Mutex::new(Cache {
+                state,
+                sequence,
+                checked: Instant::now(),
+            }),
+        })
+    }
+
+    /// Runs `f` on the cached state.
+    pub fn with_cached<R>(&self, f: impl FnOnce(&ReservationState) -> R) -> R {
+        f(&self.cache.lock().state)
+    }
+
+    /// Runs `f` on the current state with the store locked, writing back the
+    /// state if `f` succeeds and changes it.
+    pub async fn update<R: 'static + Send>(
+        &self,
+        f: impl 'static + Send + FnOnce(&mut ReservationState) -> Result<R, DiskError>,
+    ) -> Result<R, DiskError> {
+        let file = self.file.clone();
+        let (r, state, sequence) = unblock(move || {
+            let file = file.lock();
+            let _lock = sys::FileLock::new(&file)?;
+            let (original, mut sequence) = read_state(&file)?;
+            let mut state = original.clone();
+            let r = f(&mut state);
+            let state = if r.is_ok() && state != original {
+                sequence = sequence.wrapping_add(1);
+                write_state(&file, &state, sequence)?;
+                state
+            } else {
+                original
+            };
+            io::Result::Ok((r, state, sequence))
+        })
+        .await
+        .map_err(DiskError::Io)?;
+        self.cache.lock().set(state, sequence);
+        r
+    }
+
+    /// Checks whether `host_id` may access the disk.
+    ///
+    /// Writes always refresh the cached state from the store first. Reads
+    /// only refresh it if it is stale.
+    pub async fn check_access(&self, host_id: u64, write: bool) -> Result<(), DiskError> {
+        let known_sequence = {
+            let mut cache = self.cache.lock();
+            if !write && cache.checked.elapsed() < REFRESH_INTERVAL {
+                return cache.state.check_access(host_id, write);
+            }
+            // Claim the refresh, so that concurrent reads keep using the
+            // cache instead of also refreshing it.
+            cache.checked = Instant::now();
+            cache.sequence
+        };
+        let file = self.file.clone();
+        let new = unblock(move || {
+            let file = file.lock();
+            let _lock = sys::FileLock::new(&file)?;
+            if read_sequence(&file)? == known_sequence {
+                return Ok(None);
+            }
+            read_state(&file).map(Some)
+        })
+        .await
+        .map_err(DiskError::Io)?;
+        let mut cache = self.cache.lock();
+        if let Some((state, sequence)) = new {
+            cache.set(state, sequence);
+        }
+        cache.state.check_access(host_id, write)
+    }
+}
+
+fn read_sequence(mut file: &File) -> io::Result<u32> {
+    let mut header = Header::new_zeroed();
+    file.seek(SeekFrom::Start(0))?;
+    match file.read_exact(header.as_bytes_mut()) {
+        Ok(()) => Ok(header.sequence),
+        // An empty or truncated store. Let `read_state` sort it out.
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(!0),
+        Err(err) => Err(err),
+    }
+}
+
+fn read_state(mut file: &File) -> io::Result<(ReservationState, u32)> {
+    let mut data = Vec::new();
+    file.seek(SeekFrom::Start(0))?;
+    file.read_to_end(&mut data)?;
+    if data.is_empty() {
+        return Ok((ReservationState::default(), 0));
+    }
+    let (header, rest) = Header::read_from_prefix(&data)
+        .map(|header| (header, &data[size_of::<Header>()..]))
+        .ok_or_else(|| invalid_data("reservation store is truncated"))?;
+    if header.magic != MAGIC || header.version != VERSION {
+        return Err(invalid_data("not a reservation store"));
+    }
+    if header.registration_count > MAX_REGISTRATIONS
+        || rest.len() < header.registration_count as usize * size_of::<StoredRegistration>()
+    {
+        return Err(invalid_data("invalid registration count"));
+    }
+    let registrations = rest
+        .chunks_exact(size_of::<StoredRegistration>())
+        .take(header.registration_count as usize)
+        .map(|data| {
+            let r = StoredRegistration::read_from(data).unwrap();
+            Ok(Registration {
+                host_id: r.host_id,
+                key: NonZeroU64::new(r.key).ok_or_else(|| invalid_data("invalid key"))?,
+            })
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+    let reservation_type = type_from_u32(header.reservation_type)?;
+    let state = ReservationState {
+        generation: Wrapping(header.generation),
+        registrations,
+        holder: reservation_type.map(|_| header.holder),
+        reservation_type,
+        persist_through_power_loss: header.persist_through_power_loss != 0,
+    };
+    Ok((state, header.sequence))
+}
+
+fn write_state(mut file: &File, state: &ReservationState, sequence: u32) -> io::Result<()> {
+    if state.registrations.len() > MAX_REGISTRATIONS as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::OutOfMemory,
+            "too many registrations",
+        ));
+    }
+    let header = Header {
+        magic: MAGIC,
+        version: VERSION,
+        generation: state.generation.0,
+        holder: state.holder.unwrap_or(0),
+        reservation_type: state.reservation_type.map_or(0, type_to_u32),
+        persist_through_power_loss: state.persist_through_power_loss.into(),
+        registration_count: state.registrations.len() as u32,
+        sequence,
+    };
+    let mut data = header.as_bytes().to_vec();
+    for r in &state.registrations {
+        data.extend_from_slice(
+            StoredRegistration {
+                host_id: r.host_id,
+                key: r.key.get(),
+            }
+            .as_bytes(),
+        );
+    }
+    file.seek(SeekFrom::Start(0))?;
+    file.write_all(&data)?;
+    file.set_len(data.len() as u64)?;
+    file.sync_data()?;
+    Ok(())
+}
+
+#[cfg(unix)]
+mod sys {
+    use nix::fcntl::flock;
+    use nix::fcntl::FlockArg;
+    use std::fs::File;
+    use std::io;
+    use std::os::unix::prelude::*;
+
+    /// An exclusive advisory lock on a file, held until dropped.
+    pub struct FileLock<'a>(&'a File);
+
+    impl<'a> FileLock<'a> {
+        pub fn new(file: &'a File) -> io::Result<Self> {
+            flock(file.as_raw_fd(), FlockArg::LockExclusive)?;
+            Ok(Self(file))
+        }
+    }
+
+    impl Drop for FileLock<'_> {
+        fn drop(&mut self) {
+            let _ = flock(self.0.as_raw_fd(), FlockArg::Unlock);
+        }
+    }
+}
+
+#[cfg(windows)]
+mod sys {
+    // UNSAFETY: calling LockFileEx and UnlockFileEx.
+    #![expect(unsafe_code)]
+
+    use std::fs::File;
+    use std::io;
+    use std::os::windows::prelude::*;
+    use windows_sys::Win32::Storage::FileSystem::LockFileEx;
+    use windows_sys::Win32::Storage::FileSystem::UnlockFileEx;
+    use windows_sys::Win32::Storage::FileSystem::LOCKFILE_EXCLUSIVE_LOCK;
+    use windows_sys::Win32::System::IO::OVERLAPPED;
+
+    /// An exclusive lock on a file, held until dropped.
+    pub struct FileLock<'a>(&'a File);
+
+    impl<'a> FileLock<'a> {
+        pub fn new(file: &'a File) -> io::Result<Self> {
+            // SAFETY: the handle is valid for the lifetime of `file`, and the
+            // overlapped structure is only used for the synchronous lock
+            // call.
+            let r = unsafe {
+                let mut overlapped: OVERLAPPED = std::mem::zeroed();
+                LockFileEx(
+                    file.as_raw_handle() as _,
+                    LOCKFILE_EXCLUSIVE_LOCK,
+                    0,
+                    !0,
+                    !0,
+                    &mut overlapped,
+                )
+            };
+            if r == 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Self(file))
+        }
+    }
+
+    impl Drop for FileLock<'_> {
+        fn drop(&mut self) {
+            // SAFETY: the handle is valid, and the range matches the one
+            // locked in `new`.
+            unsafe {
+                let mut overlapped: OVERLAPPED = std::mem::zeroed();
+                UnlockFileEx(self.0.as_raw_handle() as _, 0, !0, !0, &mut overlapped);
+            }
+        }
+    }
+}
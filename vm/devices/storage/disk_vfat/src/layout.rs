@@ -0,0 +1,1051 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! FAT12/16/32 image layout, with VFAT long file names.
+//!
+//! The image is laid out as follows, in 512-byte sectors:
+//!
+//! - the reserved sectors: the boot sector and, for FAT32, the FS
+//!   information sector and a backup of both
+//! - two copies of the file allocation table
+//! - for FAT12 and FAT16, the fixed-size root directory
+//! - the data area: the directories (including the FAT32 root directory),
+//!   then the file data
+//!
+//! Every directory and file is stored in a single run of consecutive
+//! clusters. Only the used part of each structure is kept in memory; the
+//! rest of the image, including all free clusters, reads as zero.
+
+use crate::Error;
+use crate::FatType;
+use host_dir_image::civil_time;
+use host_dir_image::Dir;
+use host_dir_image::Extent;
+use host_dir_image::File;
+use host_dir_image::FileSource;
+use host_dir_image::Node;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+pub(crate) const SECTOR_SIZE: usize = 512;
+const DIR_ENTRY_SIZE: usize = 32;
+/// The maximum number of 32-byte entries in a directory.
+const MAX_DIR_ENTRIES: usize = 65536;
+/// The maximum number of root directory entries for FAT12 and FAT16.
+const MAX_ROOT_ENTRIES: u32 = 65520;
+/// The default number of root directory entries for FAT12 and FAT16 hard
+/// disk volumes.
+const DEFAULT_ROOT_ENTRIES: u32 = 512;
+
+const MAX_CLUSTERS_FAT12: u32 = 4084;
+const MAX_CLUSTERS_FAT16: u32 = 65524;
+const MAX_CLUSTERS_FAT32: u32 = 0x0fff_fff5;
+/// The largest cluster size that is widely supported, in sectors.
+const MAX_SECTORS_PER_CLUSTER: u32 = 64;
+/// Volumes larger than this use 4KiB clusters for FAT32, as on Windows.
+const FAT32_SMALL_CLUSTER_LIMIT: u64 = 260 * 1024 * 1024;
+
+const FAT32_RESERVED_SECTORS: u32 = 32;
+const FAT32_ROOT_CLUSTER: u32 = 2;
+const FAT32_FSINFO_SECTOR: u16 = 1;
+const FAT32_BACKUP_BOOT_SECTOR: u16 = 6;
+
+const MEDIA_FIXED: u8 = 0xf8;
+const OEM_NAME: &[u8; 8] = b"MSWIN4.1";
+const NO_NAME: &[u8; 11] = b"NO NAME    ";
+const EXTENDED_BOOT_SIGNATURE: u8 = 0x29;
+/// `int 0x18` (boot failure), then `hlt` in a loop.
+const BOOT_CODE: &[u8] = &[0xcd, 0x18, 0xf4, 0xeb, 0xfd];
+
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_ARCHIVE: u8 = 0x20;
+const ATTR_LONG_NAME: u8 = 0x0f;
+/// Set in the short name entry's reserved byte when the base name is lower
+/// case. Honored by Windows and Linux.
+const CASE_LOWER_BASE: u8 = 0x08;
+/// Set in the short name entry's reserved byte when the extension is lower
+/// case.
+const CASE_LOWER_EXT: u8 = 0x10;
+const LFN_LAST: u8 = 0x40;
+/// The number of UTF-16 code units in each long name entry.
+const LFN_CHARS: usize = 13;
+/// The maximum length of a long name, in UTF-16 code units.
+pub(crate) const MAX_LFN_LEN: usize = 255;
+
+/// A built image.
+pub(crate) struct Image {
+    pub fat_type: FatType,
+    pub cluster_size: u32,
+    pub image: host_dir_image::Image,
+}
+
+/// A standard floppy disk format.
+struct FloppyFormat {
+    sectors: u32,
+    sectors_per_track: u16,
+    heads: u16,
+    sectors_per_cluster: u32,
+    root_entries: u32,
+    media: u8,
+}
+
+const FLOPPY_FORMATS: &[FloppyFormat] = &[
+    // 360KiB, 3.5" single sided
+    FloppyFormat {
+        sectors: 720,
+        sectors_per_track: 9,
+        heads: 1,
+        sectors_per_cluster: 2,
+        root_entries: 112,
+        media: 0xf9,
+    },
+    // 720KiB
+    FloppyFormat {
+        sectors: 1440,
+        sectors_per_track: 9,
+        heads: 2,
+        sectors_per_cluster: 2,
+        root_entries: 112,
+        media: 0xf9,
+    },
+    // 1.2MiB
+    FloppyFormat {
+        sectors: 2400,
+        sectors_per_track: 15,
+        heads: 2,
+        sectors_per_cluster: 1,
+        root_entries: 224,
+        media: 0xf9,
+    },
+    // 1.44MiB
+    FloppyFormat {
+        sectors: 2880,
+        sectors_per_track: 18,
+        heads: 2,
+        sectors_per_cluster: 1,
+        root_entries: 224,
+        media: 0xf0,
+    },
+    // 1.68MiB DMF
+    FloppyFormat {
+        sectors: 3360,
+        sectors_per_track: 21,
+        heads: 2,
+        sectors_per_cluster: 4,
+        root_entries: 16,
+        media: 0xf0,
+    },
+    // 1.84MiB
+    FloppyFormat {
+        sectors: 3680,
+        sectors_per_track: 23,
+        heads: 2,
+        sectors_per_cluster: 1,
+        root_entries: 224,
+        media: 0xf0,
+    },
+    // 2.88MiB
+    FloppyFormat {
+        sectors: 5760,
+        sectors_per_track: 36,
+        heads: 2,
+        sectors_per_cluster: 2,
+        root_entries: 240,
+        media: 0xf0,
+    },
+];
+
+impl FatType {
+    fn min_clusters(self) -> u32 {
+        match self {
+            FatType::Fat12 => 1,
+            FatType::Fat16 => MAX_CLUSTERS_FAT12 + 1,
+            FatType::Fat32 => MAX_CLUSTERS_FAT16 + 1,
+        }
+    }
+
+    fn max_clusters(self) -> u32 {
+        match self {
+            FatType::Fat12 => MAX_CLUSTERS_FAT12,
+            FatType::Fat16 => MAX_CLUSTERS_FAT16,
+            FatType::Fat32 => MAX_CLUSTERS_FAT32,
+        }
+    }
+
+    /// The size of a FAT with `entries` entries, in bytes.
+    fn fat_bytes(self, entries: u64) -> u64 {
+        match self {
+            FatType::Fat12 => (entries * 3).div_ceil(2),
+            FatType::Fat16 => entries * 2,
+            FatType::Fat32 => entries * 4,
+        }
+    }
+
+    /// The end-of-chain marker.
+    fn eoc(self) -> u32 {
+        match self {
+            FatType::Fat12 => 0xfff,
+            FatType::Fat16 => 0xffff,
+            FatType::Fat32 => 0x0fff_ffff,
+        }
+    }
+
+    fn fs_type(self) -> &'static [u8; 8] {
+        match self {
+            FatType::Fat12 => b"FAT12   ",
+            FatType::Fat16 => b"FAT16   ",
+            FatType::Fat32 => b"FAT32   ",
+        }
+    }
+}
+
+/// The volume geometry.
+#[derive(Debug)]
+struct Geometry {
+    fat_type: FatType,
+    total_sectors: u32,
+    sectors_per_cluster: u32,
+    reserved_sectors: u32,
+    fat_sectors: u32,
+    root_entries: u32,
+    cluster_count: u32,
+    media: u8,
+    sectors_per_track: u16,
+    heads: u16,
+}
+
+impl Geometry {
+    /// Computes the geometry of a volume of `total_sectors` sectors. Returns
+    /// `None` if the resulting cluster count is not valid for `fat_type`.
+    fn new(
+        fat_type: FatType,
+        total_sectors: u32,
+        sectors_per_cluster: u32,
+        root_entries: u32,
+        floppy: Option<&FloppyFormat>,
+    ) -> Option<Self> {
+        let reserved_sectors = match fat_type {
+            FatType::Fat12 | FatType::Fat16 => 1,
+            FatType::Fat32 => FAT32_RESERVED_SECTORS,
+        };
+        let root_sectors = root_sectors(root_entries);
+        let cluster_count = |fat_sectors: u32| {
+            let data_sectors =
+                total_sectors.checked_sub(reserved_sectors + 2 * fat_sectors + root_sectors)?;
+            Some(data_sectors / sectors_per_cluster)
+        };
+        let fat_fits = |fat_sectors: u32, cluster_count: u32| {
+            fat_type.fat_bytes(cluster_count as u64 + 2) <= fat_sectors as u64 * SECTOR_SIZE as u64
+        };
+        // Growing the FAT shrinks the data area, so this converges, but it may
+        // overshoot. Then shrink the FAT to the smallest size that fits.
+        let mut fat_sectors = 1;
+        loop {
+            let cluster_count = cluster_count(fat_sectors)?;
+            if fat_fits(fat_sectors, cluster_count) {
+                break;
+            }
+            fat_sectors = fat_type
+                .fat_bytes(cluster_count as u64 + 2)
+                .div_ceil(SECTOR_SIZE as u64) as u32;
+        }
+        while fat_sectors > 1
+            && cluster_count(fat_sectors - 1).is_some_and(|n| fat_fits(fat_sectors - 1, n))
+        {
+            fat_sectors -= 1;
+        }
+        let cluster_count = cluster_count(fat_sectors)?;
+        if cluster_count < fat_type.min_clusters() || cluster_count > fat_type.max_clusters() {
+            return None;
+        }
+        Some(Self {
+            fat_type,
+            total_sectors,
+            sectors_per_cluster,
+            reserved_sectors,
+            fat_sectors,
+            root_entries,
+            cluster_count,
+            media: floppy.map_or(MEDIA_FIXED, |f| f.media),
+            sectors_per_track: floppy.map_or(63, |f| f.sectors_per_track),
+            heads: floppy.map_or(255, |f| f.heads),
+        })
+    }
+
+    fn cluster_size(&self) -> u64 {
+        (self.sectors_per_cluster as usize * SECTOR_SIZE) as u64
+    }
+
+    fn fat_offset(&self, index: u32) -> u64 {
+        (self.reserved_sectors + index * self.fat_sectors) as u64 * SECTOR_SIZE as u64
+    }
+
+    fn root_offset(&self) -> u64 {
+        self.fat_offset(2)
+    }
+
+    fn cluster_offset(&self, cluster: u32) -> u64 {
+        let data_start =
+            self.reserved_sectors + 2 * self.fat_sectors + root_sectors(self.root_entries);
+        (data_start as u64 + (cluster - 2) as u64 * self.sectors_per_cluster as u64)
+            * SECTOR_SIZE as u64
+    }
+}
+
+fn root_sectors(root_entries: u32) -> u32 {
+    (root_entries * DIR_ENTRY_SIZE as u32).div_ceil(SECTOR_SIZE as u32)
+}
+
+/// A directory, flattened for layout.
+struct FlatDir<'a> {
+    path: String,
+    dir: &'a Dir,
+    parent: usize,
+    entries: Vec<FlatEntry<'a>>,
+    /// The number of 32-byte entries in the directory.
+    entry_count: usize,
+    cluster: u32,
+}
+
+struct FlatEntry<'a> {
+    name: &'a str,
+    short_name: ShortName,
+    target: Target,
+}
+
+#[derive(Copy, Clone)]
+enum Target {
+    Dir(usize),
+    File(usize),
+}
+
+struct FlatFile<'a> {
+    file: &'a File,
+    cluster: u32,
+}
+
+/// The short (8.3) name of an entry.
+#[derive(Copy, Clone)]
+struct ShortName {
+    name: [u8; 11],
+    /// The `CASE_LOWER_*` flags.
+    case: u8,
+    /// Whether the entry also needs a long name.
+    long_name: bool,
+}
+
+/// Builds the image for `root`.
+///
+/// If `size` is `None`, the image is made just large enough to hold the
+/// contents. If `fat_type` is `None`, the smallest suitable FAT type is
+/// used.
+pub(crate) fn build(
+    root: &Dir,
+    volume_label: &str,
+    fat_type: Option<FatType>,
+    size: Option<u64>,
+) -> Result<Image, Error> {
+    let label = volume_label_bytes(volume_label);
+    let (mut dirs, mut files) = flatten(root, label.is_some())?;
+    let geometry = choose_geometry(&dirs, &files, fat_type, size)?;
+    let fat_type = geometry.fat_type;
+    let cluster_size = geometry.cluster_size();
+
+    // Allocate clusters: the directories first, in breadth-first order, then
+    // the files.
+    let mut fat = vec![0xffff_ff00 | geometry.media as u32, fat_type.eoc()];
+    let mut alloc = |len: u64| {
+        let count = len.div_ceil(cluster_size).max(1) as u32;
+        let first = fat.len() as u32;
+        fat.extend(first + 1..first + count);
+        fat.push(fat_type.eoc());
+        first
+    };
+    for (i, dir) in dirs.iter_mut().enumerate() {
+        if i != 0 || fat_type == FatType::Fat32 {
+            dir.cluster = alloc((dir.entry_count * DIR_ENTRY_SIZE) as u64);
+        }
+    }
+    for file in &mut files {
+        if file.file.len != 0 {
+            file.cluster = alloc(file.file.len.into());
+        }
+    }
+    let used_clusters = fat.len() as u32 - 2;
+    if used_clusters > geometry.cluster_count {
+        return Err(Error::VolumeTooSmall);
+    }
+    let fat = encode_fat(fat_type, &fat);
+
+    let serial = volume_serial(label.as_ref().unwrap_or(NO_NAME), &geometry);
+    let mut reserved = boot_sector(&geometry, label.as_ref().unwrap_or(NO_NAME), serial);
+    if fat_type == FatType::Fat32 {
+        reserved.extend_from_slice(&fsinfo_sector(
+            geometry.cluster_count - used_clusters,
+            used_clusters + 2,
+        ));
+        let backup = reserved.clone();
+        reserved.resize(FAT32_BACKUP_BOOT_SECTOR as usize * SECTOR_SIZE, 0);
+        reserved.extend_from_slice(&backup);
+    }
+
+    let memory = |offset, data: Arc<[u8]>| Extent {
+        offset,
+        len: data.len() as u64,
+        source: FileSource::Memory(data),
+    };
+    let mut extents = vec![memory(0, reserved.into())];
+    let fat: Arc<[u8]> = fat.into();
+    for index in 0..2 {
+        extents.push(memory(geometry.fat_offset(index), fat.clone()));
+    }
+    for (i, dir) in dirs.iter().enumerate() {
+        let offset = if dir.cluster == 0 {
+            geometry.root_offset()
+        } else {
+            geometry.cluster_offset(dir.cluster)
+        };
+        let label = if i == 0 { label.as_ref() } else { None };
+        let data = dir_contents(i, &dirs, &files, label);
+        if !data.is_empty() {
+            extents.push(memory(offset, data.into()));
+        }
+    }
+    for file in &files {
+        if file.cluster != 0 {
+            extents.push(Extent {
+                offset: geometry.cluster_offset(file.cluster),
+                len: file.file.len.into(),
+                source: file.file.source.clone(),
+            });
+        }
+    }
+
+    Ok(Image {
+        fat_type,
+        cluster_size: cluster_size as u32,
+        image: host_dir_image::Image::new(
+            extents,
+            SECTOR_SIZE as u32,
+            geometry.total_sectors.into(),
+        ),
+    })
+}
+
+/// Flattens the tree into directories in breadth-first order, with the root
+/// first, and files in allocation order.
+fn flatten(root: &Dir, has_label: bool) -> Result<(Vec<FlatDir<'_>>, Vec<FlatFile<'_>>), Error> {
+    let mut dirs = vec![FlatDir {
+        path: "/".into(),
+        dir: root,
+        parent: 0,
+        entries: Vec::new(),
+        entry_count: 0,
+        cluster: 0,
+    }];
+    let mut files = Vec::new();
+    let mut i = 0;
+    while i < dirs.len() {
+        let dir = dirs[i].dir;
+        let short_names = short_names(dir, &dirs[i].path)?;
+        // The root holds the volume label. Other directories hold the "."
+        // and ".." entries.
+        let mut entry_count = if i == 0 { has_label.into() } else { 2 };
+        let mut entries = Vec::with_capacity(dir.entries.len());
+        for ((name, node), short_name) in dir.entries.iter().zip(short_names) {
+            entry_count += 1;
+            if short_name.long_name {
+                entry_count += name.encode_utf16().count().div_ceil(LFN_CHARS);
+            }
+            let target = match node {
+                Node::Dir(sub) => {
+                    dirs.push(FlatDir {
+                        path: format!("{}{}/", dirs[i].path, name),
+                        dir: sub,
+                        parent: i,
+                        entries: Vec::new(),
+                        entry_count: 0,
+                        cluster: 0,
+                    });
+                    Target::Dir(dirs.len() - 1)
+                }
+                Node::File(file) => {
+                    files.push(FlatFile { file, cluster: 0 });
+                    Target::File(files.len() - 1)
+                }
+            };
+            entries.push(FlatEntry {
+                name,
+                short_name,
+                target,
+            });
+        }
+        if entry_count > MAX_DIR_ENTRIES {
+            return Err(Error::DirectoryTooLarge(dirs[i].path.clone()));
+        }
+        dirs[i].entries = entries;
+        dirs[i].entry_count = entry_count;
+        i += 1;
+    }
+    Ok((dirs, files))
+}
+
+/// Chooses the volume geometry for the flattened tree.
+fn choose_geometry(
+    dirs: &[FlatDir<'_>],
+    files: &[FlatFile<'_>],
+    fat_type: Option<FatType>,
+    size: Option<u64>,
+) -> Result<Geometry, Error> {
+    let total_sectors = size
+        .map(|size| {
+            if size % SECTOR_SIZE as u64 != 0 {
+                return Err(Error::InvalidSize(size));
+            }
+            (size / SECTOR_SIZE as u64)
+                .try_into()
+                .map_err(|_| Error::InvalidSize(size))
+        })
+        .transpose()?;
+
+    let root_entry_count = dirs[0].entry_count as u32;
+    let fits = |geometry: &Geometry| {
+        let root_fits =
+            geometry.fat_type == FatType::Fat32 || root_entry_count <= geometry.root_entries;
+        root_fits
+            && data_clusters(dirs, files, geometry.fat_type, geometry.cluster_size())
+                <= geometry.cluster_count.into()
+    };
+
+    // Use the standard layout for floppy disk sizes.
+    if let Some(total_sectors) = total_sectors {
+        if let Some(format) = FLOPPY_FORMATS.iter().find(|f| f.sectors == total_sectors) {
+            if matches!(fat_type, None | Some(FatType::Fat12)) {
+                let geometry = Geometry::new(
+                    FatType::Fat12,
+                    total_sectors,
+                    format.sectors_per_cluster,
+                    format.root_entries,
+                    Some(format),
+                )
+                .expect("floppy format is valid");
+                return if fits(&geometry) {
+                    Ok(geometry)
+                } else {
+                    Err(Error::VolumeTooSmall)
+                };
+            }
+        }
+    }
+
+    let requested;
+    let fat_types: &[FatType] = match fat_type {
+        Some(fat_type) => {
+            requested = [fat_type];
+            &requested
+        }
+        None => &[FatType::Fat12, FatType::Fat16, FatType::Fat32],
+    };
+    let approx_size =
+        size.unwrap_or_else(|| files.iter().map(|file| u64::from(file.file.len)).sum());
+    for &fat_type in fat_types {
+        let root_entries = match fat_type {
+            FatType::Fat12 | FatType::Fat16 => {
+                let entries = root_entry_count
+                    .next_multiple_of(16)
+                    .max(DEFAULT_ROOT_ENTRIES);
+                if entries > MAX_ROOT_ENTRIES {
+                    continue;
+                }
+                entries
+            }
+            FatType::Fat32 => 0,
+        };
+        // Unless the FAT type was requested explicitly, prefer a larger FAT
+        // type over very large clusters, much like the Windows formatter.
+        let (min_spc, max_spc) = match fat_type {
+            _ if fat_types.len() == 1 && fat_type != FatType::Fat32 => (1, MAX_SECTORS_PER_CLUSTER),
+            FatType::Fat12 => (1, 8),
+            FatType::Fat16 => (1, 16),
+            FatType::Fat32 if approx_size > FAT32_SMALL_CLUSTER_LIMIT => {
+                (8, MAX_SECTORS_PER_CLUSTER)
+            }
+            FatType::Fat32 => (1, MAX_SECTORS_PER_CLUSTER),
+        };
+        let mut spc = min_spc;
+        while spc <= max_spc {
+            let total_sectors = match total_sectors {
+                Some(total_sectors) => Some(total_sectors),
+                None => {
+                    // Size the volume to fit the contents exactly.
+                    let cluster_size = (spc as usize * SECTOR_SIZE) as u64;
+                    let clusters = data_clusters(dirs, files, fat_type, cluster_size)
+                        .max(fat_type.min_clusters().into());
+                    let fat_sectors = fat_type
+                        .fat_bytes(clusters + 2)
+                        .div_ceil(SECTOR_SIZE as u64);
+                    let reserved_sectors = match fat_type {
+                        FatType::Fat12 | FatType::Fat16 => 1,
+                        FatType::Fat32 => FAT32_RESERVED_SECTORS,
+                    };
+                    (reserved_sectors as u64
+                        + 2 * fat_sectors
+                        + root_sectors(root_entries) as u64
+                        + clusters * spc as u64)
+                        .try_into()
+                        .ok()
+                }
+            };
+            if let Some(geometry) = total_sectors
+                .and_then(|total| Geometry::new(fat_type, total, spc, root_entries, None))
+            {
+                if fits(&geometry) {
+                    return Ok(geometry);
+                }
+            }
+            spc *= 2;
+        }
+    }
+    Err(Error::VolumeTooSmall)
+}
+
+/// Returns the number of clusters needed to hold the directories and files.
+fn data_clusters(
+    dirs: &[FlatDir<'_>],
+    files: &[FlatFile<'_>],
+    fat_type: FatType,
+    cluster_size: u64,
+) -> u64 {
+    let skip_root = fat_type != FatType::Fat32;
+    let dirs = dirs.iter().skip(skip_root.into()).map(|dir| {
+        ((dir.entry_count * DIR_ENTRY_SIZE) as u64)
+            .div_ceil(cluster_size)
+            .max(1)
+    });
+    let files = files
+        .iter()
+        .map(|file| u64::from(file.file.len).div_ceil(cluster_size));
+    dirs.chain(files).sum()
+}
+
+/// Encodes the FAT entries.
+fn encode_fat(fat_type: FatType, entries: &[u32]) -> Vec<u8> {
+    let mut fat = vec![0; fat_type.fat_bytes(entries.len() as u64) as usize];
+    for (n, &entry) in entries.iter().enumerate() {
+        match fat_type {
+            FatType::Fat12 => {
+                let offset = n * 3 / 2;
+                let entry = entry & 0xfff;
+                if n % 2 == 0 {
+                    fat[offset] = entry as u8;
+                    fat[offset + 1] |= (entry >> 8) as u8;
+                } else {
+                    fat[offset] |= (entry << 4) as u8;
+                    fat[offset + 1] = (entry >> 4) as u8;
+                }
+            }
+            FatType::Fat16 => {
+                fat[n * 2..n * 2 + 2].copy_from_slice(&(entry as u16).to_le_bytes());
+            }
+            FatType::Fat32 => {
+                fat[n * 4..n * 4 + 4].copy_from_slice(&(entry & 0x0fff_ffff).to_le_bytes());
+            }
+        }
+    }
+    fat
+}
+
+fn boot_sector(geometry: &Geometry, label: &[u8; 11], serial: u32) -> Vec<u8> {
+    let mut sector = vec![0; SECTOR_SIZE];
+    let fat32 = geometry.fat_type == FatType::Fat32;
+    let floppy = geometry.media != MEDIA_FIXED;
+    let ext_offset = if fat32 { 64 } else { 36 };
+    let code_offset = ext_offset + 26;
+
+    sector[..3].copy_from_slice(&[0xeb, code_offset as u8 - 2, 0x90]);
+    sector[3..11].copy_from_slice(OEM_NAME);
+    sector[11..13].copy_from_slice(&(SECTOR_SIZE as u16).to_le_bytes());
+    sector[13] = geometry.sectors_per_cluster as u8;
+    sector[14..16].copy_from_slice(&(geometry.reserved_sectors as u16).to_le_bytes());
+    sector[16] = 2;
+    sector[17..19].copy_from_slice(&(geometry.root_entries as u16).to_le_bytes());
+    if let (false, Ok(total)) = (fat32, u16::try_from(geometry.total_sectors)) {
+        sector[19..21].copy_from_slice(&total.to_le_bytes());
+    } else {
+        sector[32..36].copy_from_slice(&geometry.total_sectors.to_le_bytes());
+    }
+    sector[21] = geometry.media;
+    if !fat32 {
+        sector[22..24].copy_from_slice(&(geometry.fat_sectors as u16).to_le_bytes());
+    }
+    sector[24..26].copy_from_slice(&geometry.sectors_per_track.to_le_bytes());
+    sector[26..28].copy_from_slice(&geometry.heads.to_le_bytes());
+    if fat32 {
+        sector[36..40].copy_from_slice(&geometry.fat_sectors.to_le_bytes());
+        sector[44..48].copy_from_slice(&FAT32_ROOT_CLUSTER.to_le_bytes());
+        sector[48..50].copy_from_slice(&FAT32_FSINFO_SECTOR.to_le_bytes());
+        sector[50..52].copy_from_slice(&FAT32_BACKUP_BOOT_SECTOR.to_le_bytes());
+    }
+
+    let ext = &mut sector[ext_offset..code_offset];
+    ext[0] = if floppy { 0x00 } else { 0x80 };
+    ext[2] = EXTENDED_BOOT_SIGNATURE;
+    ext[3..7].copy_from_slice(&serial.to_le_bytes());
+    ext[7..18].copy_from_slice(label);
+    ext[18..26].copy_from_slice(geometry.fat_type.fs_type());
+
+    sector[code_offset..code_offset + BOOT_CODE.len()].copy_from_slice(BOOT_CODE);
+    sector[510..].copy_from_slice(&[0x55, 0xaa]);
+    sector
+}
+
+fn fsinfo_sector(free_count: u32, next_free: u32) -> Vec<u8> {
+    let mut sector = vec![0; SECTOR_SIZE];
+    sector[..4].copy_from_slice(&0x4161_5252u32.to_le_bytes());
+    sector[484..488].copy_from_slice(&0x6141_7272u32.to_le_bytes());
+    sector[488..492].copy_from_slice(&free_count.to_le_bytes());
+    sector[492..496].copy_from_slice(&next_free.to_le_bytes());
+    sector[508..].copy_from_slice(&0xaa55_0000u32.to_le_bytes());
+    sector
+}
+
+/// Derives a stable volume serial number from the label and geometry, so
+/// that the same inputs always produce the same image.
+fn volume_serial(label: &[u8; 11], geometry: &Geometry) -> u32 {
+    // FNV-1a.
+    let mut hash = 0x811c_9dc5u32;
+    for &b in label
+        .iter()
+        .chain(&geometry.total_sectors.to_le_bytes())
+        .chain(&[geometry.fat_type as u8])
+    {
+        hash = (hash ^ b as u32).wrapping_mul(0x0100_0193);
+    }
+    hash
+}
+
+/// Generates the contents of directory `index`.
+fn dir_contents(
+    index: usize,
+    dirs: &[FlatDir<'_>],
+    files: &[FlatFile<'_>],
+    label: Option<&[u8; 11]>,
+) -> Vec<u8> {
+    let dir = &dirs[index];
+    let mut data = Vec::with_capacity(dir.entry_count * DIR_ENTRY_SIZE);
+    if index == 0 {
+        if let Some(label) = label {
+            data.extend_from_slice(&short_entry(label, ATTR_VOLUME_ID, 0, 0, 0, dir.dir.mtime));
+        }
+    } else {
+        // ".." refers to the root as cluster 0, even on FAT32.
+        let parent_cluster = if dir.parent == 0 {
+            0
+        } else {
+            dirs[dir.parent].cluster
+        };
+        for (name, cluster) in [
+            (b".          ", dir.cluster),
+            (b"..         ", parent_cluster),
+        ] {
+            data.extend_from_slice(&short_entry(
+                name,
+                ATTR_DIRECTORY,
+                0,
+                cluster,
+                0,
+                dir.dir.mtime,
+            ));
+        }
+    }
+    for entry in &dir.entries {
+        let short_name = &entry.short_name;
+        if short_name.long_name {
+            push_long_entries(&mut data, entry.name, checksum(&short_name.name));
+        }
+        let (attr, cluster, len, mtime) = match entry.target {
+            Target::Dir(i) => (ATTR_DIRECTORY, dirs[i].cluster, 0, dirs[i].dir.mtime),
+            Target::File(i) => (
+                ATTR_ARCHIVE,
+                files[i].cluster,
+                files[i].file.len,
+                files[i].file.mtime,
+            ),
+        };
+        data.extend_from_slice(&short_entry(
+            &short_name.name,
+            attr,
+            short_name.case,
+            cluster,
+            len,
+            mtime,
+        ));
+    }
+    debug_assert_eq!(data.len(), dir.entry_count * DIR_ENTRY_SIZE);
+    data
+}
+
+fn short_entry(
+    name: &[u8; 11],
+    attr: u8,
+    case: u8,
+    cluster: u32,
+    len: u32,
+    mtime: Option<SystemTime>,
+) -> [u8; DIR_ENTRY_SIZE] {
+    let (date, time) = dos_date_time(mtime);
+    let mut entry = [0; DIR_ENTRY_SIZE];
+    entry[..11].copy_from_slice(name);
+    entry[11] = attr;
+    entry[12] = case;
+    entry[14..16].copy_from_slice(&time.to_le_bytes());
+    entry[16..18].copy_from_slice(&date.to_le_bytes());
+    entry[18..20].copy_from_slice(&date.to_le_bytes());
+    entry[20..22].copy_from_slice(&((cluster >> 16) as u16).to_le_bytes());
+    entry[22..24].copy_from_slice(&time.to_le_bytes());
+    entry[24..26].copy_from_slice(&date.to_le_bytes());
+    entry[26..28].copy_from_slice(&(cluster as u16).to_le_bytes());
+    entry[28..32].copy_from_slice(&len.to_le_bytes());
+    entry
+}
+
+/// Appends the long name entries for `name`, which precede its short name
+/// entry in reverse order.
+fn push_long_entries(data: &mut Vec<u8>, name: &str, checksum: u8) {
+    let mut chars = name.encode_utf16().collect::<Vec<_>>();
+    let count = chars.len().div_ceil(LFN_CHARS);
+    // The name is terminated with a NUL if there is room, then padded with
+    // 0xffff.
+    if chars.len() % LFN_CHARS != 0 {
+        chars.push(0);
+    }
+    chars.resize(count * LFN_CHARS, 0xffff);
+    for (i, part) in chars.chunks(LFN_CHARS).enumerate().rev() {
+        let mut entry = [0; DIR_ENTRY_SIZE];
+        entry[0] = (i + 1) as u8 | if i + 1 == count { LFN_LAST } else { 0 };
+        entry[11] = ATTR_LONG_NAME;
+        entry[13] = checksum;
+        let offsets = (1..11)
+            .step_by(2)
+            .chain((14..26).step_by(2))
+            .chain((28..32).step_by(2));
+        for (offset, c) in offsets.zip(part) {
+            entry[offset..offset + 2].copy_from_slice(&c.to_le_bytes());
+        }
+        data.extend_from_slice(&entry);
+    }
+}
+
+/// The checksum of a short name, stored in its long name entries.
+fn checksum(name: &[u8; 11]) -> u8 {
+    name.iter()
+        .fold(0u8, |sum, &c| sum.rotate_right(1).wrapping_add(c))
+}
+
+/// Assigns short names to the entries of `dir`.
+///
+/// Names that are already valid short names, apart from case, are used
+/// directly. Other names get a generated short name with a numeric tail and a
+/// long name.
+fn short_names(dir: &Dir, path: &str) -> Result<Vec<ShortName>, Error> {
+    let mut used = HashSet::new();
+    let mut lower_names = HashSet::new();
+    let mut names = Vec::with_capacity(dir.entries.len());
+    for name in dir.entries.keys() {
+        // FAT names are case insensitive.
+        if !lower_names.insert(name.to_lowercase()) {
+            return Err(Error::AlreadyExists(format!("{path}{name}")));
+        }
+        let short_name = exact_short_name(name);
+        if let Some(short_name) = &short_name {
+            used.insert(short_name.name);
+        }
+        names.push(short_name);
+    }
+    dir.entries
+        .keys()
+        .zip(names)
+        .map(|(name, short_name)| match short_name {
+            Some(short_name) => Ok(short_name),
+            None => {
+                let short_name = generated_short_name(name, &used)
+                    .ok_or_else(|| Error::DirectoryTooLarge(path.to_owned()))?;
+                used.insert(short_name.name);
+                Ok(short_name)
+            }
+        })
+        .collect()
+}
+
+fn is_short_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || b"!#$%&'()-@^_`{}~".contains(&c)
+}
+
+/// Returns the short name for `name` if it is a valid 8.3 name, with each
+/// part in a single case.
+fn exact_short_name(name: &str) -> Option<ShortName> {
+    let (base, ext) = name.split_once('.').unwrap_or((name, ""));
+    if base.is_empty()
+        || base.len() > 8
+        || ext.len() > 3
+        || !base.bytes().chain(ext.bytes()).all(is_short_char)
+    {
+        return None;
+    }
+    let lower = |s: &str| {
+        let lower = s.bytes().any(|c| c.is_ascii_lowercase());
+        let upper = s.bytes().any(|c| c.is_ascii_uppercase());
+        (!(lower && upper)).then_some(lower)
+    };
+    let case = match (lower(base)?, lower(ext)?) {
+        (false, false) => 0,
+        (true, false) => CASE_LOWER_BASE,
+        (false, true) => CASE_LOWER_EXT,
+        (true, true) => CASE_LOWER_BASE | CASE_LOWER_EXT,
+    };
+    let mut short = [b' '; 11];
+    short[..base.len()].copy_from_slice(base.as_bytes());
+    short[8..8 + ext.len()].copy_from_slice(ext.as_bytes());
+    short.make_ascii_uppercase();
+    Some(ShortName {
+        name: short,
+        case,
+        long_name: false,
+    })
+}
+
+/// Generates a unique short name for `name` with a `~N` numeric tail, as
+/// Windows does.
+fn generated_short_name(name: &str, used: &HashSet<[u8; 11]>) -> Option<ShortName> {
+    let name = name.trim_start_matches('.');
+    let (base, ext) = name.rsplit_once('.').unwrap_or((name, ""));
+    let convert = |s: &str, max| {
+        s.chars()
+            .filter(|&c| c != ' ' && c != '.')
+            .map(|c| match u8::try_from(c) {
+                Ok(c) if is_short_char(c) => c.to_ascii_uppercase(),
+                _ => b'_',
+            })
+            .take(max)
+            .collect::<Vec<_>>()
+    };
+    let base = convert(base, 8);
+    let ext = convert(ext, 3);
+    (1..1000000).find_map(|n| {
+        let tail = format!("~{n}");
+        let keep = base.len().min(8 - tail.len());
+        let mut short = [b' '; 11];
+        short[..keep].copy_from_slice(&base[..keep]);
+        short[keep..keep + tail.len()].copy_from_slice(tail.as_bytes());
+        short[8..8 + ext.len()].copy_from_slice(&ext);
+        (!used.contains(&short)).then_some(ShortName {
+            name: short,
+            case: 0,
+            long_name: true,
+        })
+    })
+}
+
+/// Converts the volume label to the 11-byte on-disk form. Returns `None` for
+/// an empty label.
+fn volume_label_bytes(label: &str) -> Option<[u8; 11]> {
+    let label = label.trim();
+    if label.is_empty() {
+        return None;
+    }
+    let mut bytes = [b' '; 11];
+    for (b, c) in bytes.iter_mut().zip(label.chars()) {
+        *b = match u8::try_from(c) {
+            Ok(c) if c == b' ' || is_short_char(c) => c.to_ascii_uppercase(),
+            _ => b'_',
+        };
+    }
+    Some(bytes)
+}
+
+/// Returns the DOS date and time for `time`. Times that can't be
+/// represented are stored as 1980-01-01 00:00:00.
+fn dos_date_time(time: Option<SystemTime>) -> (u16, u16) {
+    match time.and_then(civil_time) {
+        Some((year, month, day, hour, minute, second)) if (1980..=2107).contains(&year) => (
+            ((year - 1980) as u16) << 9 | (month as u16) << 5 | day as u16,
+            (hour as u16) << 11 | (minute as u16) << 5 | (second / 2) as u16,
+        ),
+        _ => (1 << 5 | 1, 0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::choose_geometry;
+    use super::exact_short_name;
+    use super::flatten;
+    use super::generated_short_name;
+    use super::Dir;
+    use super::CASE_LOWER_BASE;
+    use super::CASE_LOWER_EXT;
+    use crate::FatType;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_short_names() {
+        let short = exact_short_name("bootx64.efi").unwrap();
+        assert_eq!(&short.name, b"BOOTX64 EFI");
+        assert_eq!(short.case, CASE_LOWER_BASE | CASE_LOWER_EXT);
+        let short = exact_short_name("EFI").unwrap();
+        assert_eq!(&short.name, b"EFI        ");
+        assert_eq!(short.case, 0);
+        assert!(exact_short_name("Boot.efi").is_none());
+        assert!(exact_short_name("a.b.c").is_none());
+        assert!(exact_short_name("longername.txt").is_none());
+        assert!(exact_short_name("a b").is_none());
+
+        let mut used = HashSet::new();
+        let short = generated_short_name("longername.text", &used).unwrap();
+        assert_eq!(&short.name, b"LONGER~1TEX");
+        used.insert(short.name);
+        let short = generated_short_name("longername.texture", &used).unwrap();
+        assert_eq!(&short.name, b"LONGER~2TEX");
+        let short = generated_short_name(".config", &used).unwrap();
+        assert_eq!(&short.name, b"CONFIG~1   ");
+        let short = generated_short_name("na\u{ef}ve file", &used).unwrap();
+        assert_eq!(&short.name, b"NA_VEF~1   ");
+    }
+
+    #[test]
+    fn test_geometry() {
+        let root = Dir::default();
+        let (dirs, files) = flatten(&root, true).unwrap();
+
+        // Standard floppy layout.
+        let g = choose_geometry(&dirs, &files, None, Some(1474560)).unwrap();
+        assert_eq!(g.fat_type, FatType::Fat12);
+        assert_eq!(
+            (
+                g.sectors_per_track,
+                g.heads,
+                g.root_entries,
+                g.fat_sectors,
+                g.media
+            ),
+            (18, 2, 224, 9, 0xf0)
+        );
+
+        // Size chosen to fit.
+        let g = choose_geometry(&dirs, &files, None, None).unwrap();
+        assert_eq!(g.fat_type, FatType::Fat12);
+        let g = choose_geometry(&dirs, &files, Some(FatType::Fat32), None).unwrap();
+        assert_eq!(g.fat_type, FatType::Fat32);
+        assert_eq!(g.cluster_count, 65525);
+
+        // FAT type chosen by size.
+        let g = choose_geometry(&dirs, &files, None, Some(64 << 20)).unwrap();
+        assert_eq!(g.fat_type, FatType::Fat16);
+        let g = choose_geometry(&dirs, &files, None, Some(1 << 30)).unwrap();
+        assert_eq!((g.fat_type, g.sectors_per_cluster), (FatType::Fat32, 8));
+        assert!(choose_geometry(&dirs, &files, Some(FatType::Fat32), Some(1 << 20)).is_err());
+        assert!(choose_geometry(&dirs, &files, None, Some(1000)).is_err());
+    }
+}
@@ -0,0 +1,580 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A read-only disk backend that presents a FAT12, FAT16, or FAT32
+//! filesystem, with VFAT long file names, synthesized from a host directory
+//! or an in-memory list of files.
+//!
+//! This is useful for passing files to a guest on a floppy disk, or for
+//! booting a UEFI application from a directory laid out as an EFI system
+//! partition. The filesystem occupies the whole disk, with no partition
+//! table.
+//!
+//! The filesystem metadata is built when the disk is created. File contents
+//! are read from their sources on demand, so no temporary image file is
+//! needed. Host files should not change while the disk is in use.
+//!
+//! The disk is read-only. To let the guest write to it, use it as the lower
+//! layer of a layered disk with a RAM or sqlite layer on top.
+
+#![warn(missing_docs)]
+#![forbid(unsafe_code)]
+
+mod layout;
+pub mod resolver;
+
+use disk_backend::DiskError;
+use disk_backend::DiskIo;
+use disk_backend::UnmapBehavior;
+use host_dir_image::Image;
+use host_dir_image::Tree;
+use inspect::Inspect;
+use layout::SECTOR_SIZE;
+use scsi_buffers::RequestBuffers;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use thiserror::Error;
+
+/// An error building a FAT image.
+#[derive(Debug, Error)]
+pub enum Error {
+    /// A host file or directory could not be accessed.
+    #[error("failed to access {}", .0.display())]
+    Io(PathBuf, #[source] io::Error),
+    /// The path within the image is invalid.
+    #[error("invalid image path {0:?}")]
+    InvalidPath(String),
+    /// The path within the image is already in use. FAT names are case
+    /// insensitive.
+    #[error("image path {0:?} already exists")]
+    AlreadyExists(String),
+    /// The file is larger than the 4GiB FAT limit.
+    #[error("file {0:?} is too large")]
+    FileTooLarge(String),
+    /// The directory has too many entries.
+    #[error("directory {0:?} has too many entries")]
+    DirectoryTooLarge(String),
+    /// The requested volume size is invalid.
+    #[error("invalid volume size {0:#x}")]
+    InvalidSize(u64),
+    /// The contents do not fit in a volume of the requested size and FAT
+    /// type.
+    #[error("the contents do not fit in the volume")]
+    VolumeTooSmall,
+}
+
+impl From<host_dir_image::Error> for Error {
+    fn from(err: host_dir_image::Error) -> Self {
+        match err {
+            host_dir_image::Error::Io(path, err) => Error::Io(path, err),
+            host_dir_image::Error::InvalidPath(path) => Error::InvalidPath(path),
+            host_dir_image::Error::AlreadyExists(path) => Error::AlreadyExists(path),
+            host_dir_image::Error::FileTooLarge(path) => Error::FileTooLarge(path),
+        }
+    }
+}
+
+/// The FAT variant.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FatType {
+    /// FAT12, for floppy disks and volumes up to 16MiB.
+    Fat12,
+    /// FAT16, for volumes from 2MiB to 4GiB.
+    Fat16,
+    /// FAT32, for volumes of 32MiB and larger.
+    Fat32,
+}
+
+/// A builder for a [`VfatDisk`].
+///
+/// Paths within the image are `/`-separated and relative to the image root.
+/// Parent directories are created as needed.
+pub struct VfatBuilder {
+    volume_label: String,
+    fat_type: Option<FatType>,
+    size: Option<u64>,
+    tree: Tree,
+}
+
+impl VfatBuilder {
+    /// Returns a new builder for an empty image with the given volume label.
+    ///
+    /// The label is converted to upper case and truncated to 11 characters.
+    /// Pass an empty label for an unlabeled volume.
+    pub fn new(volume_label: impl Into<String>) -> Self {
+        Self {
+            volume_label: volume_label.into(),
+            fat_type: None,
+            size: None,
+            tree: Tree::new(is_valid_name),
+        }
+    }
+
+    /// Sets the FAT type. By default, the smallest type suitable for the
+    /// volume size is used.
+    pub fn fat_type(mut self, fat_type: FatType) -> Self {
+        self.fat_type = Some(fat_type);
+        self
+    }
+
+    /// Sets the volume size in bytes, which must be a multiple of 512. By
+    /// default, the volume is made just large enough to hold its contents.
+    ///
+    /// Standard floppy disk sizes (such as 1474560 bytes) get the matching
+    /// floppy disk layout.
+    pub fn size(mut self, size: u64) -> Self {
+        self.size = Some(size);
+        self
+    }
+
+    /// Adds a file with the contents `data` at `path`.
+    pub fn add_file(&mut self, path: &str, data: impl Into<Arc<[u8]>>) -> Result<(), Error> {
+        Ok(self.tree.add_file(path, data)?)
+    }
+
+    /// Adds the host file `host_path` at `path`.
+    pub fn add_host_file(&mut self, path: &str, host_path: impl AsRef<Path>) -> Result<(), Error> {
+        Ok(self.tree.add_host_file(path, host_path)?)
+    }
+
+    /// Recursively adds the contents of the host directory `host_path` to the
+    /// directory `path`. Pass an empty `path` to add the contents to the image
+    /// root.
+    ///
+    /// Symbolic links to files are followed. Symbolic links to directories,
+    /// and entries that are neither files nor directories, are skipped.
+    pub fn add_host_dir(&mut self, path: &str, host_path: impl AsRef<Path>) -> Result<(), Error> {
+        Ok(self.tree.add_host_dir(path, host_path)?)
+    }
+
+    /// Builds the disk.
+    pub fn build(self) -> Result<VfatDisk, Error> {
+        let image = layout::build(
+            self.tree.root(),
+            &self.volume_label,
+            self.fat_type,
+            self.size,
+        )?;
+        Ok(VfatDisk {
+            fat_type: image.fat_type,
+            cluster_size: image.cluster_size,
+            image: Arc::new(image.image),
+        })
+    }
+}
+
+/// Validates a long file name.
+fn is_valid_name(name: &str) -> bool {
+    !name.ends_with(['.', ' '])
+        && name.encode_utf16().count() <= layout::MAX_LFN_LEN
+        && !name
+            .chars()
+            .any(|c| c.is_ascii_control() || "\"*/:<>?\\|".contains(c))
+}
+
+/// A read-only disk containing a synthesized FAT filesystem.
+#[derive(Inspect)]
+pub struct VfatDisk {
+    #[inspect(flatten)]
+    image: Arc<Image>,
+    #[inspect(debug)]
+    fat_type: FatType,
+    cluster_size: u32,
+}
+
+impl VfatDisk {
+    /// Returns a disk containing the contents of the host directory `path`,
+    /// with the default FAT type and size.
+    pub fn from_dir(
+        path: impl AsRef<Path>,
+        volume_label: impl Into<String>,
+    ) -> Result<Self, Error> {
+        let mut builder = VfatBuilder::new(volume_label);
+        builder.add_host_dir("", path)?;
+        builder.build()
+    }
+
+    /// Returns the FAT type of the filesystem.
+    pub fn fat_type(&self) -> FatType {
+        self.fat_type
+    }
+}
+
+impl DiskIo for VfatDisk {
+    fn disk_type(&self) -> &str {
+        "vfat"
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.image.sector_count()
+    }
+
+    fn sector_size(&self) -> u32 {
+        SECTOR_SIZE as u32
+    }
+
+    fn disk_id(&self) -> Option<[u8; 16]> {
+        None
+    }
+
+    fn physical_sector_size(&self) -> u32 {
+        SECTOR_SIZE as u32
+    }
+
+    fn is_fua_respected(&self) -> bool {
+        false
+    }
+
+    fn is_read_only(&self) -> bool {
+        true
+    }
+
+    async fn read_vectored(
+        &self,
+        buffers: &RequestBuffers<'_>,
+        sector: u64,
+    ) -> Result<(), DiskError> {
+        self.image.read_vectored(buffers, sector).await
+    }
+
+    async fn write_vectored(
+        &self,
+        _buffers: &RequestBuffers<'_>,
+        _sector: u64,
+        _fua: bool,
+    ) -> Result<(), DiskError> {
+        Err(DiskError::ReadOnly)
+    }
+
+    async fn sync_cache(&self) -> Result<(), DiskError> {
+        Err(DiskError::ReadOnly)
+    }
+
+    async fn unmap(
+        &self,
+        _sector: u64,
+        _count: u64,
+        _block_level_only: bool,
+    ) -> Result<(), DiskError> {
+        Err(DiskError::ReadOnly)
+    }
+
+    fn unmap_behavior(&self) -> UnmapBehavior {
+        UnmapBehavior::Ignored
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FatType;
+    use super::VfatBuilder;
+    use super::VfatDisk;
+    use super::SECTOR_SIZE;
+    use disk_backend::Disk;
+    use disk_backend::DiskIo;
+    use disk_layered::DiskLayer;
+    use disk_layered::LayerConfiguration;
+    use disk_layered::LayeredDisk;
+    use disklayer_ram::RamDiskLayer;
+    use guestmem::GuestMemory;
+    use pal_async::async_test;
+    use scsi_buffers::OwnedRequestBuffers;
+
+    async fn read(disk: &impl DiskIo, offset: u64, len: usize) -> Vec<u8> {
+        let sector = offset / SECTOR_SIZE as u64;
+        let skip = (offset % SECTOR_SIZE as u64) as usize;
+        let buf_len = (skip + len).next_multiple_of(SECTOR_SIZE);
+        let mem = GuestMemory::allocate(buf_len);
+        disk.read_vectored(
+            &OwnedRequestBuffers::linear(0, buf_len, true).buffer(&mem),
+            sector,
+        )
+        .await
+        .unwrap();
+        let mut data = vec![0; buf_len];
+        mem.read_at(0, &mut data).unwrap();
+        data[skip..skip + len].to_vec()
+    }
+
+    fn u16_le(b: &[u8]) -> u16 {
+        u16::from_le_bytes(b[..2].try_into().unwrap())
+    }
+
+    fn u32_le(b: &[u8]) -> u32 {
+        u32::from_le_bytes(b[..4].try_into().unwrap())
+    }
+
+    /// A minimal FAT reader, independent of the layout code.
+    struct Fat {
+        fat_type: FatType,
+        cluster_size: u64,
+        fat_offset: u64,
+        root_offset: u64,
+        root_len: usize,
+        data_offset: u64,
+        root_cluster: u32,
+    }
+
+    impl Fat {
+        async fn new(disk: &impl DiskIo) -> Self {
+            let bpb = read(disk, 0, SECTOR_SIZE).await;
+            assert_eq!(&bpb[510..], &[0x55, 0xaa]);
+            let sector_size = u16_le(&bpb[11..]) as u64;
+            let reserved = u16_le(&bpb[14..]) as u64;
+            let root_entries = u16_le(&bpb[17..]) as u64;
+            let fat_sectors = match u16_le(&bpb[22..]) {
+                0 => u32_le(&bpb[36..]) as u64,
+                n => n as u64,
+            };
+            let fat_type = match &bpb[54..62] {
+                b"FAT12   " => FatType::Fat12,
+                b"FAT16   " => FatType::Fat16,
+                _ => {
+                    assert_eq!(&bpb[82..90], b"FAT32   ");
+                    FatType::Fat32
+                }
+            };
+            let root_offset = (reserved + bpb[16] as u64 * fat_sectors) * sector_size;
+            let root_len = root_entries as usize * 32;
+            Self {
+                fat_type,
+                cluster_size: bpb[13] as u64 * sector_size,
+                fat_offset: reserved * sector_size,
+                root_offset,
+                root_len,
+                data_offset: root_offset + root_len as u64,
+                root_cluster: if fat_type == FatType::Fat32 {
+                    u32_le(&bpb[44..])
+                } else {
+                    0
+                },
+            }
+        }
+
+        async fn next_cluster(&self, disk: &impl DiskIo, cluster: u32) -> u32 {
+            let (offset, bits) = match self.fat_type {
+                FatType::Fat12 => (cluster as u64 * 3 / 2, 12),
+                FatType::Fat16 => (cluster as u64 * 2, 16),
+                FatType::Fat32 => (cluster as u64 * 4, 28),
+            };
+            let raw = u32_le(&read(disk, self.fat_offset + offset, 4).await);
+            let next = match self.fat_type {
+                FatType::Fat12 if cluster % 2 == 1 => raw >> 4,
+                _ => raw,
+            } & ((1 << bits) - 1);
+            if next >= (1 << bits) - 8 {
+                0
+            } else {
+                next
+            }
+        }
+
+        async fn read_chain(&self, disk: &impl DiskIo, mut cluster: u32) -> Vec<u8> {
+            let mut data = Vec::new();
+            while cluster != 0 {
+                let offset = self.data_offset + (cluster - 2) as u64 * self.cluster_size;
+                data.extend(read(disk, offset, self.cluster_size as usize).await);
+                cluster = self.next_cluster(disk, cluster).await;
+            }
+            data
+        }
+
+        async fn read_dir(&self, disk: &impl DiskIo, cluster: u32) -> Vec<u8> {
+            if cluster == 0 {
+                read(disk, self.root_offset, self.root_len).await
+            } else {
+                self.read_chain(disk, cluster).await
+            }
+        }
+
+        /// Looks up `name` in the directory at `cluster`, returning the short
+        /// name entry.
+        async fn lookup(&self, disk: &impl DiskIo, cluster: u32, name: &str) -> Option<[u8; 32]> {
+            let dir = self.read_dir(disk, cluster).await;
+            let mut long_name = Vec::new();
+            for entry in dir.chunks_exact(32) {
+                match entry[0] {
+                    0 => break,
+                    _ if entry[11] == 0x0f => {
+                        let chars = [1..11, 14..26, 28..32]
+                            .into_iter()
+                            .flat_map(|r| entry[r].chunks(2).map(u16_le).collect::<Vec<_>>());
+                        let mut part = chars.take_while(|&c| c != 0).collect::<Vec<_>>();
+                        part.append(&mut long_name);
+                        long_name = part;
+                    }
+                    _ => {
+                        let found = if long_name.is_empty() {
+                            let mut short = name.to_ascii_uppercase().into_bytes();
+                            if let Some(dot) = short.iter().position(|&c| c == b'.') {
+                                short.splice(dot..dot + 1, vec![b' '; 8 - dot]);
+                            }
+                            short.resize(11, b' ');
+                            entry[..11] == short
+                        } else {
+                            String::from_utf16(&long_name).unwrap() == name
+                        };
+                        long_name.clear();
+                        if found {
+                            return Some(entry.try_into().unwrap());
+                        }
+                    }
+                }
+            }
+            None
+        }
+
+        async fn read_file(&self, disk: &impl DiskIo, path: &str) -> Option<Vec<u8>> {
+            let mut cluster = self.root_cluster;
+            let mut entry = None;
+            for name in path.split('/') {
+                let e = self.lookup(disk, cluster, name).await?;
+                cluster = (u16_le(&e[20..]) as u32) << 16 | u16_le(&e[26..]) as u32;
+                entry = Some(e);
+            }
+            let len = u32_le(&entry?[28..]) as usize;
+            let mut data = self.read_chain(disk, cluster).await;
+            data.truncate(len);
+            Some(data)
+        }
+    }
+
+    async fn check(fat_type: FatType) {
+        let mut builder = VfatBuilder::new("test").fat_type(fat_type);
+        builder
+            .add_file("EFI/BOOT/bootx64.efi", &b"MZ"[..])
+            .unwrap();
+        let large = (0..100000u32).map(|i| i as u8).collect::<Vec<_>>();
+        builder
+            .add_file(
+                "a long directory name/another long file name.bin",
+                large.clone(),
+            )
+            .unwrap();
+        for i in 0..100 {
+            builder
+                .add_file(
+                    &format!("many/file number {i}.txt"),
+                    format!("{i}").into_bytes(),
+                )
+                .unwrap();
+        }
+        builder.add_file("empty", Vec::new()).unwrap();
+        let disk = builder.build().unwrap();
+        assert_eq!(disk.fat_type(), fat_type);
+
+        let fat = Fat::new(&disk).await;
+        assert_eq!(fat.fat_type, fat_type);
+        assert_eq!(
+            fat.read_file(&disk, "EFI/BOOT/bootx64.efi").await.unwrap(),
+            b"MZ"
+        );
+        assert_eq!(
+            fat.read_file(&disk, "a long directory name/another long file name.bin")
+                .await
+                .unwrap(),
+            large
+        );
+        for i in [0, 42, 99] {
+            assert_eq!(
+                fat.read_file(&disk, &format!("many/file number {i}.txt"))
+                    .await
+                    .unwrap(),
+                format!("{i}").as_bytes()
+            );
+        }
+        assert!(fat.read_file(&disk, "empty").await.unwrap().is_empty());
+        assert!(fat.read_file(&disk, "missing").await.is_none());
+    }
+
+    #[async_test]
+    async fn test_fat12() {
+        check(FatType::Fat12).await;
+    }
+
+    #[async_test]
+    async fn test_fat16() {
+        check(FatType::Fat16).await;
+    }
+
+    #[async_test]
+    async fn test_fat32() {
+        check(FatType::Fat32).await;
+    }
+
+    #[async_test]
+    async fn test_floppy() {
+        let mut builder = VfatBuilder::new("floppy").size(1474560);
+        builder
+            .add_file("autoexec.bat", &b"@echo off\r\n"[..])
+            .unwrap();
+        let disk = builder.build().unwrap();
+        assert_eq!(disk.sector_count() * SECTOR_SIZE as u64, 1474560);
+        let fat = Fat::new(&disk).await;
+        assert_eq!(
+            fat.read_file(&disk, "autoexec.bat").await.unwrap(),
+            b"@echo off\r\n"
+        );
+
+        let mut builder = VfatBuilder::new("floppy").size(1474560);
+        builder.add_file("big", vec![0; 2 << 20]).unwrap();
+        assert!(builder.build().is_err());
+    }
+
+    #[async_test]
+    async fn test_overlay() {
+        let mut builder = VfatBuilder::new("");
+        builder.add_file("data", vec![0xcc; 4096]).unwrap();
+        let disk = builder.build().unwrap();
+        let size = disk.sector_count() * SECTOR_SIZE as u64;
+        let disk = LayeredDisk::new(
+            false,
+            vec![
+                LayerConfiguration {
+                    layer: DiskLayer::new(RamDiskLayer::new(size).unwrap()),
+                    write_through: false,
+                    read_cache: false,
+                },
+                LayerConfiguration {
+                    layer: DiskLayer::from_disk(Disk::new(disk).unwrap()),
+                    write_through: false,
+                    read_cache: false,
+                },
+            ],
+        )
+        .await
+        .unwrap();
+
+        let mem = GuestMemory::allocate(SECTOR_SIZE);
+        mem.write_at(0, &[0x55; SECTOR_SIZE]).unwrap();
+        let sector = disk.sector_count() - 1;
+        disk.write_vectored(
+            &OwnedRequestBuffers::linear(0, SECTOR_SIZE, false).buffer(&mem),
+            sector,
+            false,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            read(&disk, sector * SECTOR_SIZE as u64, SECTOR_SIZE).await,
+            [0x55; SECTOR_SIZE]
+        );
+        let fat = Fat::new(&disk).await;
+        assert_eq!(fat.read_file(&disk, "data").await.unwrap(), [0xcc; 4096]);
+    }
+
+    #[test]
+    fn test_invalid_names() {
+        let mut builder = VfatBuilder::new("test");
+        assert!(builder.add_file("a:b", Vec::new()).is_err());
+        assert!(builder.add_file("trailing.", Vec::new()).is_err());
+        assert!(builder.add_file(&"x".repeat(256), Vec::new()).is_err());
+        assert!(builder.add_file("ok name.txt", Vec::new()).is_ok());
+        // FAT names are case insensitive.
+        builder.add_file("OK NAME.TXT", Vec::new()).unwrap();
+        assert!(builder.build().is_err());
+        assert!(VfatDisk::from_dir("/nonexistent/path", "test").is_err());
+    }
+}
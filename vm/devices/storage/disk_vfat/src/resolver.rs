@@ -0,0 +1,70 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Resolver implementation for [`VfatDisk`](crate::VfatDisk).
+
+use crate::FatType;
+use crate::VfatBuilder;
+use disk_backend::resolve::ResolveDiskParameters;
+use disk_backend::resolve::ResolvedDisk;
+use disk_backend_resources::FatDirDiskHandle;
+use std::path::Path;
+use thiserror::Error;
+use vm_resource::declare_static_resolver;
+use vm_resource::kind::DiskHandleKind;
+use vm_resource::ResolveResource;
+
+/// A resolver for FAT disks built from a host directory.
+pub struct FatDirDiskResolver;
+
+declare_static_resolver!(FatDirDiskResolver, (DiskHandleKind, FatDirDiskHandle));
+
+/// An error resolving a [`FatDirDiskHandle`].
+#[derive(Debug, Error)]
+pub enum ResolveFatDiskError {
+    /// The disk was requested as writable.
+    #[error("writable fat disks are not supported, use a diff disk on top instead")]
+    Writable,
+    /// The image could not be built.
+    #[error("failed to build fat image")]
+    Build(#[source] crate::Error),
+    /// The disk is invalid.
+    #[error("invalid disk")]
+    InvalidDisk(#[source] disk_backend::InvalidDisk),
+}
+
+impl ResolveResource<DiskHandleKind, FatDirDiskHandle> for FatDirDiskResolver {
+    type Output = ResolvedDisk;
+    type Error = ResolveFatDiskError;
+
+    fn resolve(
+        &self,
+        rsrc: FatDirDiskHandle,
+        input: ResolveDiskParameters<'_>,
+    ) -> Result<Self::Output, Self::Error> {
+        if !input.read_only {
+            return Err(ResolveFatDiskError::Writable);
+        }
+        let volume_label = rsrc.volume_label.unwrap_or_else(|| {
+            Path::new(&rsrc.path)
+                .file_name()
+                .map_or_else(String::new, |name| name.to_string_lossy().into())
+        });
+        let mut builder = VfatBuilder::new(volume_label);
+        if let Some(fat_type) = rsrc.fat_type {
+            builder = builder.fat_type(match fat_type {
+                disk_backend_resources::FatType::Fat12 => FatType::Fat12,
+                disk_backend_resources::FatType::Fat16 => FatType::Fat16,
+                disk_backend_resources::FatType::Fat32 => FatType::Fat32,
+            });
+        }
+        if let Some(size) = rsrc.size {
+            builder = builder.size(size);
+        }
+        builder
+            .add_host_dir("", &rsrc.path)
+            .map_err(ResolveFatDiskError::Build)?;
+        let disk = builder.build().map_err(ResolveFatDiskError::Build)?;
+        ResolvedDisk::new(disk).map_err(ResolveFatDiskError::InvalidDisk)
+    }
+}
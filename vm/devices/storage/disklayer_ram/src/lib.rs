@@ -13,6 +13,8 @@
 use disk_backend::DiskError;
 use disk_backend::UnmapBehavior;
 use disk_layered::DiskLayer;
+use disk_layered::LayerAllocation;
+use disk_layered::LayerAllocationRun;
 use disk_layered::LayerAttach;
 use disk_layered::LayerConfiguration;
 use disk_layered::LayerIo;
@@ -350,6 +352,50 @@ fn unmap_behavior(&self) -> UnmapBehavior {
     fn optimal_unmap_sectors(&self) -> u32 {
         1
     }
+
+    fn supports_allocation_query(&self) -> bool {
+        true
+    }
+
+    async fn query_allocation(
+        &self,
+        sector: u64,
+        count: u64,
+    ) -> Result<LayerAllocationRun, DiskError> {
+        let end = sector + count;
+        let state = self.state.read();
+        if end > state.sector_count {
+            return Err(DiskError::IllegalBlock);
+        }
+        let mut present = state.data.range(sector..end).map(|(&s, _)| s);
+        let first = present.next().unwrap_or(end);
+        let run = if first == sector {
+            // Count the consecutive present sectors.
+            let mut next = sector + 1;
+            for s in present {
+                if s != next {
+                    break;
+                }
+                next += 1;
+            }
+            LayerAllocationRun {
+                state: LayerAllocation::Allocated,
+                count: next - sector,
+            }
+        } else if sector >= state.zero_after {
+            // Non-present sectors after the zero-after point read as zero.
+            LayerAllocationRun {
+                state: LayerAllocation::Zero,
+                count: first - sector,
+            }
+        } else {
+            LayerAllocationRun {
+                state: LayerAllocation::NotPresent,
+                count: first.min(state.zero_after) - sector,
+            }
+        };
+        Ok(run)
+    }
 }
 
 impl WriteNoOverwrite for RamDiskLayer {
@@ -390,6 +436,7 @@ pub fn ram_disk(size: u64, read_only: bool) -> anyhow::Result<Disk> {
 mod tests {
     use super::RamDiskLayer;
     use super::SECTOR_SIZE;
+    use disk_backend::AllocationRun;
     use disk_backend::DiskIo;
     use disk_layered::DiskLayer;
     use disk_layered::LayerConfiguration;
@@ -554,4 +601,56 @@ async fn test_unmap() {
             assert_eq!(buf, [0u8; SECTOR_USIZE]);
         }
     }
+
+    #[async_test]
+    async fn test_query_allocation() {
+        const SIZE: usize = 1024 * 1024;
+        const SECTORS: u64 = (SIZE / SECTOR_USIZE) as u64;
+
+        let (guest_mem, mut upper) = prep_disk(SIZE).await;
+        assert!(upper.supports_allocation_query());
+        // The lower layer is fully written.
+        assert_eq!(
+            upper.query_allocation(0, SECTORS).await.unwrap(),
+            AllocationRun {
+                allocated: true,
+                count: SECTORS,
+            }
+        );
+        // Shrinking and growing the upper layer hides the lower layer's data.
+        resize(&upper, SECTORS / 2).await;
+        resize(&upper, SECTORS).await;
+        write(&guest_mem, &mut upper, SECTORS - 4, 2, 1).await;
+        assert_eq!(
+            upper.query_allocation(0, SECTORS).await.unwrap(),
+            AllocationRun {
+                allocated: true,
+                count: SECTORS / 2,
+            }
+        );
+        assert_eq!(
+            upper
+                .query_allocation(SECTORS / 2, SECTORS / 2)
+                .await
+                .unwrap(),
+            AllocationRun {
+                allocated: false,
+                count: SECTORS / 2 - 4,
+            }
+        );
+        assert_eq!(
+            upper.query_allocation(SECTORS - 4, 4).await.unwrap(),
+            AllocationRun {
+                allocated: true,
+                count: 2,
+            }
+        );
+        assert_eq!(
+            upper.query_allocation(SECTORS - 2, 2).await.unwrap(),
+            AllocationRun {
+                allocated: false,
+                count: 2,
+            }
+        );
+    }
 }
@@ -68,6 +68,8 @@
 use blocking::unblock;
 use disk_backend::DiskError;
 use disk_backend::UnmapBehavior;
+use disk_layered::LayerAllocation;
+use disk_layered::LayerAllocationRun;
 use disk_layered::LayerAttach;
 use disk_layered::LayerIo;
 use disk_layered::SectorMarker;
@@ -420,6 +422,28 @@ fn unmap_behavior(&self) -> UnmapBehavior {
     fn optimal_unmap_sectors(&self) -> u32 {
         1
     }
+
+    fn supports_allocation_query(&self) -> bool {
+        true
+    }
+
+    async fn query_allocation(
+        &self,
+        sector: u64,
+        count: u64,
+    ) -> Result<LayerAllocationRun, DiskError> {
+        tracing::trace!(sector, count, "query_allocation");
+        if sector + count > self.meta.sector_count {
+            return Err(DiskError::IllegalBlock);
+        }
+
+        unblock({
+            let conn = self.conn.clone().lock_owned().await;
+            move || query_allocation(conn, sector, sector + count)
+        })
+        .await
+        .map_err(|e| DiskError::Io(std::io::Error::new(std::io::ErrorKind::Other, e)))
+    }
 }
 
 impl WriteNoOverwrite for SqliteDiskLayer {
@@ -475,6 +499,49 @@ fn read_sectors(
     Ok(res)
 }
 
+fn query_allocation(
+    conn: OwnedMutexGuard<Connection>,
+    start_sector: u64,
+    end_sector: u64,
+) -> Result<LayerAllocationRun, rusqlite::Error> {
+    let mut select_stmt = conn.prepare_cached(
+        "SELECT sector, data IS NULL
+        FROM sectors
+        WHERE sector >= ? AND sector < ?
+        ORDER BY sector ASC",
+    )?;
+    let mut rows = select_stmt.query(rusqlite::params![start_sector, end_sector])?;
+
+    let mut run: Option<LayerAllocationRun> = None;
+    while let Some(row) = rows.next()? {
+        let sector: u64 = row.get(0)?;
+        let state = if row.get::<_, bool>(1)? {
+            LayerAllocation::Zero
+        } else {
+            LayerAllocation::Allocated
+        };
+        if let Some(run) = &mut run {
+            if run.state != state || start_sector + run.count != sector {
+                break;
+            }
+            run.count += 1;
+        } else if sector != start_sector {
+            // The run starts with sectors that are not in the table.
+            return Ok(LayerAllocationRun {
+                state: LayerAllocation::NotPresent,
+                count: sector - start_sector,
+            });
+        } else {
+            run = Some(LayerAllocationRun { state, count: 1 });
+        }
+    }
+
+    Ok(run.unwrap_or(LayerAllocationRun {
+        state: LayerAllocation::NotPresent,
+        count: end_sector - start_sector,
+    }))
+}
+
 // FUTURE: write into sqlite directly from `RequestBuffers`.
 fn write_sectors(
     mut conn: OwnedMutexGuard<Connection>,
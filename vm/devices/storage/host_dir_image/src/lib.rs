@@ -0,0 +1,479 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Shared support for read-only disks that present a filesystem image
+//! synthesized from a host directory or an in-memory list of files.
+//!
+//! A [`Tree`] collects the files to include. The format-specific layout code
+//! then describes the image as a list of [`Extent`]s, each backed by
+//! generated metadata or by a file's contents, and wraps them in an
+//! [`Image`]. File contents are read from their sources on demand, so no
+//! temporary image file is needed.
+
+#![warn(missing_docs)]
+#![forbid(unsafe_code)]
+
+use blocking::unblock;
+use disk_backend::DiskError;
+use guestmem::MemoryWrite;
+use inspect::Inspect;
+use scsi_buffers::RequestBuffers;
+use std::collections::btree_map;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::io::Read;
+use std::io::Seek;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+use thiserror::Error;
+
+/// An error adding files to a [`Tree`].
+#[derive(Debug, Error)]
+pub enum Error {
+    /// A host file or directory could not be accessed.
+    #[error("failed to access {}", .0.display())]
+    Io(PathBuf, #[source] io::Error),
+    /// The path within the image is invalid.
+    #[error("invalid image path {0:?}")]
+    InvalidPath(String),
+    /// The path within the image is already in use.
+    #[error("image path {0:?} already exists")]
+    AlreadyExists(String),
+    /// The file is larger than 4GiB.
+    #[error("file {0:?} is too large")]
+    FileTooLarge(String),
+}
+
+/// The source of a file's contents.
+#[derive(Clone)]
+pub enum FileSource {
+    /// The contents are in memory.
+    Memory(Arc<[u8]>),
+    /// The contents are read from a host file.
+    Host(Arc<Path>),
+}
+
+impl FileSource {
+    /// Reads from the file at `offset`. Reads past the end of the file are
+    /// zero-filled.
+    pub fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        match self {
+            FileSource::Memory(data) => {
+                let data = data.get(offset as usize..).unwrap_or_default();
+                let n = buf.len().min(data.len());
+                buf[..n].copy_from_slice(&data[..n]);
+                buf[n..].fill(0);
+            }
+            FileSource::Host(path) => {
+                let mut file = fs::File::open(path)?;
+                file.seek(io::SeekFrom::Start(offset))?;
+                let mut n = 0;
+                while n < buf.len() {
+                    match file.read(&mut buf[n..]) {
+                        Ok(0) => break,
+                        Ok(read) => n += read,
+                        Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                        Err(err) => return Err(err),
+                    }
+                }
+                buf[n..].fill(0);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A directory in the tree.
+#[derive(Default)]
+pub struct Dir {
+    /// The directory's entries, by name.
+    pub entries: BTreeMap<String, Node>,
+    /// The modification time, if known.
+    pub mtime: Option<SystemTime>,
+}
+
+/// An entry in a directory.
+pub enum Node {
+    /// A subdirectory.
+    Dir(Dir),
+    /// A file.
+    File(File),
+}
+
+/// A file in the tree.
+pub struct File {
+    /// The file's contents.
+    pub source: FileSource,
+    /// The file's length in bytes.
+    pub len: u32,
+    /// The modification time, if known.
+    pub mtime: Option<SystemTime>,
+    /// Whether the host file is executable.
+    pub executable: bool,
+}
+
+/// A tree of files to include in an image.
+///
+/// Paths within the tree are `/`-separated and relative to the root. Parent
+/// directories are created as needed.
+pub struct Tree {
+    root: Dir,
+    is_valid_name: fn(&str) -> bool,
+}
+
+impl Tree {
+    /// Returns a new, empty tree.
+    ///
+    /// Empty names, `.`, and `..` are always rejected. `is_valid_name`
+    /// applies any additional restrictions of the image format.
+    pub fn new(is_valid_name: fn(&str) -> bool) -> Self {
+        Self {
+            root: Dir::default(),
+            is_valid_name,
+        }
+    }
+
+    /// Returns the root directory.
+    pub fn root(&self) -> &Dir {
+        &self.root
+    }
+
+    /// Adds a file with the contents `data` at `path`.
+    pub fn add_file(&mut self, path: &str, data: impl Into<Arc<[u8]>>) -> Result<(), Error> {
+        let data = data.into();
+        let len = data
+            .len()
+            .try_into()
+            .map_err(|_| Error::FileTooLarge(path.to_owned()))?;
+        self.insert(
+            path,
+            Node::File(File {
+                source: FileSource::Memory(data),
+                len,
+                mtime: None,
+                executable: false,
+            }),
+        )
+    }
+
+    /// Adds the host file `host_path` at `path`.
+    pub fn add_host_file(&mut self, path: &str, host_path: impl AsRef<Path>) -> Result<(), Error> {
+        let host_path = host_path.as_ref();
+        let metadata = fs::metadata(host_path).map_err(|err| Error::Io(host_path.into(), err))?;
+        let file = host_file(path, host_path, &metadata)?;
+        self.insert(path, Node::File(file))
+    }
+
+    /// Recursively adds the contents of the host directory `host_path` to the
+    /// directory `path`. Pass an empty `path` to add the contents to the root.
+    ///
+    /// Symbolic links to files are followed. Symbolic links to directories,
+    /// and entries that are neither files nor directories, are skipped.
+    pub fn add_host_dir(&mut self, path: &str, host_path: impl AsRef<Path>) -> Result<(), Error> {
+        let host_path = host_path.as_ref();
+        let metadata = fs::metadata(host_path).map_err(|err| Error::Io(host_path.into(), err))?;
+        let is_valid_name = self.is_valid_name;
+        let dir = self.dir_mut(path)?;
+        dir.mtime = metadata.modified().ok();
+        add_host_dir(dir, host_path, is_valid_name)
+    }
+
+    fn insert(&mut self, path: &str, node: Node) -> Result<(), Error> {
+        let (parent, name) = path.rsplit_once('/').unwrap_or(("", path));
+        validate_name(path, name, self.is_valid_name)?;
+        let dir = self.dir_mut(parent)?;
+        match dir.entries.entry(name.to_owned()) {
+            btree_map::Entry::Vacant(entry) => {
+                entry.insert(node);
+                Ok(())
+            }
+            btree_map::Entry::Occupied(_) => Err(Error::AlreadyExists(path.to_owned())),
+        }
+    }
+
+    /// Returns the directory at `path`, creating it and any missing parents.
+    fn dir_mut(&mut self, path: &str) -> Result<&mut Dir, Error> {
+        let mut dir = &mut self.root;
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            validate_name(path, name, self.is_valid_name)?;
+            let node = dir
+                .entries
+                .entry(name.to_owned())
+                .or_insert_with(|| Node::Dir(Dir::default()));
+            dir = match node {
+                Node::Dir(dir) => dir,
+                Node::File(_) => return Err(Error::AlreadyExists(path.to_owned())),
+            };
+        }
+        Ok(dir)
+    }
+}
+
+fn validate_name(path: &str, name: &str, is_valid_name: fn(&str) -> bool) -> Result<(), Error> {
+    if name.is_empty() || name == "." || name == ".." || !is_valid_name(name) {
+        return Err(Error::InvalidPath(path.to_owned()));
+    }
+    Ok(())
+}
+
+fn host_file(path: &str, host_path: &Path, metadata: &fs::Metadata) -> Result<File, Error> {
+    #[cfg(unix)]
+    let executable = {
+        use std::os::unix::fs::PermissionsExt;
+        metadata.permissions().mode() & 0o111 != 0
+    };
+    #[cfg(not(unix))]
+    let executable = false;
+
+    Ok(File {
+        source: FileSource::Host(host_path.into()),
+        len: metadata
+            .len()
+            .try_into()
+            .map_err(|_| Error::FileTooLarge(path.to_owned()))?,
+        mtime: metadata.modified().ok(),
+        executable,
+    })
+}
+
+fn add_host_dir(
+    dir: &mut Dir,
+    host_path: &Path,
+    is_valid_name: fn(&str) -> bool,
+) -> Result<(), Error> {
+    let io_err = |err| Error::Io(host_path.into(), err);
+    for entry in fs::read_dir(host_path).map_err(io_err)? {
+        let entry = entry.map_err(io_err)?;
+        let path = entry.path();
+        let Some(name) = entry.file_name().to_str().map(str::to_owned) else {
+            return Err(Error::InvalidPath(path.display().to_string()));
+        };
+        validate_name(&path.display().to_string(), &name, is_valid_name)?;
+        let file_type = entry
+            .file_type()
+            .map_err(|err| Error::Io(path.clone(), err))?;
+        let node = if file_type.is_dir() {
+            let mut sub = Dir {
+                entries: Default::default(),
+                mtime: entry.metadata().ok().and_then(|m| m.modified().ok()),
+            };
+            add_host_dir(&mut sub, &path, is_valid_name)?;
+            Node::Dir(sub)
+        } else {
+            // Follow symlinks to files.
+            let metadata = fs::metadata(&path).map_err(|err| Error::Io(path.clone(), err))?;
+            if !metadata.is_file() {
+                continue;
+            }
+            Node::File(host_file(&name, &path, &metadata)?)
+        };
+        if dir.entries.insert(name.clone(), node).is_some() {
+            return Err(Error::AlreadyExists(name));
+        }
+    }
+    Ok(())
+}
+
+/// A region of an image backed by a file or by generated metadata.
+pub struct Extent {
+    /// The byte offset of the region in the image.
+    pub offset: u64,
+    /// The length of the region in bytes.
+    pub len: u64,
+    /// The region's contents.
+    pub source: FileSource,
+}
+
+/// A laid-out image, read on demand.
+#[derive(Inspect)]
+pub struct Image {
+    #[inspect(with = "Vec::len")]
+    extents: Vec<Extent>,
+    sector_size: u32,
+    sector_count: u64,
+}
+
+impl Image {
+    /// Returns an image of `sector_count` sectors of `sector_size` bytes.
+    ///
+    /// `extents` must be in increasing offset order and must not overlap.
+    /// Regions of the image not covered by an extent read as zero.
+    pub fn new(extents: Vec<Extent>, sector_size: u32, sector_count: u64) -> Self {
+        assert!(extents
+            .windows(2)
+            .all(|w| w[0].offset + w[0].len <= w[1].offset));
+        Self {
+            extents,
+            sector_size,
+            sector_count,
+        }
+    }
+
+    /// Returns the sector size in bytes.
+    pub fn sector_size(&self) -> u32 {
+        self.sector_size
+    }
+
+    /// Returns the number of sectors in the image.
+    pub fn sector_count(&self) -> u64 {
+        self.sector_count
+    }
+
+    /// Reads `buf.len()` bytes from the image at byte offset `offset`.
+    pub fn read(&self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        let end = offset + buf.len() as u64;
+        buf.fill(0);
+        let first = self
+            .extents
+            .partition_point(|extent| extent.offset + extent.len <= offset);
+        for extent in &self.extents[first..] {
+            if extent.offset >= end {
+                break;
+            }
+            let start = offset.max(extent.offset);
+            let stop = end.min(extent.offset + extent.len);
+            extent.source.read_at(
+                start - extent.offset,
+                &mut buf[(start - offset) as usize..(stop - offset) as usize],
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Reads from the image at `sector` into `buffers`, as for
+    /// [`disk_backend::DiskIo::read_vectored`].
+    ///
+    /// Host files are read on a blocking thread.
+    pub async fn read_vectored(
+        self: &Arc<Self>,
+        buffers: &RequestBuffers<'_>,
+        sector: u64,
+    ) -> Result<(), DiskError> {
+        let len = buffers.len();
+        if sector + (len / self.sector_size as usize) as u64 > self.sector_count {
+            return Err(DiskError::IllegalBlock);
+        }
+        let image = self.clone();
+        let buf = unblock(move || {
+            let mut buf = vec![0; len];
+            image.read(sector * image.sector_size as u64, &mut buf)?;
+            io::Result::Ok(buf)
+        })
+        .await
+        .map_err(DiskError::Io)?;
+        buffers.writer().write(&buf)?;
+        Ok(())
+    }
+}
+
+/// Converts `time` to UTC `(year, month, day, hour, minute, second)`, for
+/// the on-disk date formats. Returns `None` for times before the Unix epoch.
+pub fn civil_time(time: SystemTime) -> Option<(u64, u8, u8, u8, u8, u8)> {
+    let secs = time.duration_since(UNIX_EPOCH).ok()?.as_secs();
+    let (days, secs) = (secs / 86400, secs % 86400);
+    // Convert days since the epoch to a proleptic Gregorian date. See
+    // <http://howardhinnant.github.io/date_algorithms.html#civil_from_days>.
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    Some((
+        year,
+        month as u8,
+        day as u8,
+        (secs / 3600) as u8,
+        (secs / 60 % 60) as u8,
+        (secs % 60) as u8,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::civil_time;
+    use super::Error;
+    use super::Extent;
+    use super::FileSource;
+    use super::Image;
+    use super::Node;
+    use super::Tree;
+    use std::time::Duration;
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn test_civil_time() {
+        assert_eq!(civil_time(UNIX_EPOCH), Some((1970, 1, 1, 0, 0, 0)));
+        assert_eq!(
+            civil_time(UNIX_EPOCH + Duration::from_secs(951_827_696)),
+            Some((2000, 2, 29, 12, 34, 56))
+        );
+        assert_eq!(civil_time(UNIX_EPOCH - Duration::from_secs(1)), None);
+    }
+
+    #[test]
+    fn test_tree() {
+        let mut tree = Tree::new(|name| !name.contains(':'));
+        tree.add_file("a/b/c", &b"data"[..]).unwrap();
+        assert!(matches!(
+            tree.add_file("a/b/c", Vec::new()),
+            Err(Error::AlreadyExists(_))
+        ));
+        assert!(matches!(
+            tree.add_file("a/b/c/d", Vec::new()),
+            Err(Error::AlreadyExists(_))
+        ));
+        assert!(matches!(
+            tree.add_file("a/../b", Vec::new()),
+            Err(Error::InvalidPath(_))
+        ));
+        assert!(matches!(
+            tree.add_file("a:b", Vec::new()),
+            Err(Error::InvalidPath(_))
+        ));
+        assert!(matches!(
+            tree.add_host_dir("", "/nonexistent/path"),
+            Err(Error::Io(..))
+        ));
+        let Some(Node::Dir(a)) = tree.root().entries.get("a") else {
+            panic!()
+        };
+        assert!(matches!(a.entries.get("b"), Some(Node::Dir(_))));
+    }
+
+    #[test]
+    fn test_image_read() {
+        let memory = |offset, data: &[u8]| Extent {
+            offset,
+            len: data.len() as u64,
+            source: FileSource::Memory(data.into()),
+        };
+        let image = Image::new(vec![memory(2, b"abc"), memory(8, b"defg")], 4, 4);
+        let mut buf = [0xff; 16];
+        image.read(0, &mut buf).unwrap();
+        assert_eq!(&buf, b"\0\0abc\0\0\0defg\0\0\0\0");
+        let mut buf = [0xff; 4];
+        image.read(3, &mut buf).unwrap();
+        assert_eq!(&buf, b"bc\0\0");
+        // An extent longer than its source reads as zero past the source.
+        let image = Image::new(
+            vec![Extent {
+                offset: 0,
+                len: 4,
+                source: FileSource::Memory(b"xy"[..].into()),
+            }],
+            4,
+            1,
+        );
+        let mut buf = [0xff; 4];
+        image.read(0, &mut buf).unwrap();
+        assert_eq!(&buf, b"xy\0\0");
+    }
+}
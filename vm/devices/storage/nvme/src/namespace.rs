@@ -246,6 +246,7 @@ fn map_disk_error(err: disk_backend::DiskError) -> NvmeError {
             }
             disk_backend::MediumErrorDetails::WriteFault => spec::Status::MEDIA_WRITE_FAULT.into(),
         },
+        disk_backend::DiskError::Miscompare(_) => spec::Status::MEDIA_COMPARE_FAILURE.into(),
         disk_backend::DiskError::ReadOnly => {
             spec::Status::ATTEMPTED_WRITE_TO_READ_ONLY_RANGE.into()
         }
@@ -0,0 +1,80 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Support for copy offload between SCSI devices.
+
+use disk_backend::Disk;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Weak;
+
+/// A set of disks that can be the source or destination of each other's copy
+/// offload (EXTENDED COPY) requests.
+///
+/// Disks are identified by the 16-byte NAA designator that they report in
+/// the device identification VPD page. Typically, all the disks attached to a
+/// single SCSI controller share one domain.
+#[derive(Clone, Default)]
+pub struct CopyDomain(Arc<Mutex<CopyDomainInner>>);
+
+#[derive(Default)]
+struct CopyDomainInner {
+    disks: HashMap<[u8; 16], (u64, Disk)>,
+    next_registration: u64,
+}
+
+impl CopyDomain {
+    /// Returns a new, empty copy domain.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `disk` with designator `id`.
+    ///
+    /// The disk remains in the domain until the returned registration is
+    /// dropped.
+    pub fn register(&self, id: [u8; 16], disk: Disk) -> CopyDomainRegistration {
+        let mut inner = self.0.lock();
+        let registration = inner.next_registration;
+        inner.next_registration += 1;
+        if inner.disks.insert(id, (registration, disk)).is_some() {
+            tracing::warn!(?id, "duplicate designator in copy domain");
+        }
+        CopyDomainRegistration {
+            domain: Arc::downgrade(&self.0),
+            id,
+            registration,
+        }
+    }
+
+    /// Looks up the disk with designator `id`.
+    pub fn get(&self, id: &[u8; 16]) -> Option<Disk> {
+        self.0.lock().disks.get(id).map(|(_, disk)| disk.clone())
+    }
+}
+
+/// A registration of a disk in a [`CopyDomain`], which removes the disk from
+/// the domain when dropped.
+pub struct CopyDomainRegistration {
+    domain: Weak<Mutex<CopyDomainInner>>,
+    id: [u8; 16],
+    registration: u64,
+}
+
+impl Drop for CopyDomainRegistration {
+    fn drop(&mut self) {
+        if let Some(domain) = self.domain.upgrade() {
+            let mut domain = domain.lock();
+            // Only remove the entry if it has not been replaced by a later
+            // registration with the same designator.
+            if domain
+                .disks
+                .get(&self.id)
+                .is_some_and(|(registration, _)| *registration == self.registration)
+            {
+                domain.disks.remove(&self.id);
+            }
+        }
+    }
+}
@@ -6,6 +6,9 @@
 #![forbid(unsafe_code)]
 #![warn(missing_docs)]
 
+pub mod copy_domain;
+
+use copy_domain::CopyDomain;
 use inspect::Inspect;
 use scsi_buffers::RequestBuffers;
 use scsi_defs::srb::SrbStatus;
@@ -36,6 +39,9 @@ fn from(value: T) -> Self {
 pub struct ResolveScsiDeviceHandleParams<'a> {
     /// The VM task driver source.
     pub driver_source: &'a VmTaskDriverSource,
+    /// The copy domain shared with the other devices on the same controller,
+    /// if copy offload between devices is supported.
+    pub copy_domain: Option<&'a CopyDomain>,
 }
 
 /// The amount of space reserved for an AsyncScsiDisk-returned future
@@ -120,6 +126,25 @@ pub struct ScsiDiskSavedState {
         pub sector_count: u64,
         #[mesh(2)]
         pub sense_data: Option<SavedSenseData>,
+        #[mesh(3)]
+        pub reservation: Option<SavedReservationState>,
+    }
+
+    /// Persistent reservation state of the backing disk.
+    #[derive(Debug, Copy, Clone, Eq, PartialEq, Protobuf)]
+    #[mesh(package = "storage.scsi.disk")]
+    pub struct SavedReservationState {
+        #[mesh(1)]
+        pub host_id: u64,
+        #[mesh(2)]
+        pub generation: u32,
+        #[mesh(3)]
+        pub key: Option<u64>,
+        /// The SCSI reservation type code.
+        #[mesh(4)]
+        pub reservation_type: Option<u8>,
+        #[mesh(5)]
+        pub persist_through_power_loss: bool,
     }
 
     #[derive(Debug, Protobuf, Copy, Clone)]
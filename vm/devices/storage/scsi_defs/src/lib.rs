@@ -254,6 +254,7 @@ pub struct InquiryFlags {
 pub const INQUIRY_ENABLE_VPD: u8 = 0x1;
 pub const INQUIRY_COMMAND_SUPPORT_DATA: u8 = 0x2;
 pub const T10_VERSION_SPC3: u8 = 0x05;
+pub const INQUIRY_THIRD_PARTY_COPY: u8 = 0x08;
 pub const T10_RESPONSE_DATA_SPC3: u8 = 0x02;
 
 /*
@@ -506,6 +507,9 @@ pub struct VpdMsftPagingExtentPropertiesPage {
 pub const PROVISIONING_TYPE_RESOURCE: u8 = 0x1;
 pub const PROVISIONING_TYPE_THIN: u8 = 0x2;
 
+pub const VPD_LBP_LBPRZ: u8 = 0x04;
+pub const VPD_LBP_LBPU: u8 = 0x80;
+
 #[repr(C)]
 #[derive(Debug, AsBytes, FromBytes, FromZeroes)]
 pub struct VpdLogicalBlockProvisioningPage {
@@ -591,8 +595,18 @@ pub const fn new(
             sense_key_specific: [0; 3],
         }
     }
+
+    /// Sets the information field and marks it valid.
+    pub fn with_information(mut self, information: u32) -> Self {
+        self.header.error_code =
+            SenseDataErrorCode(self.header.error_code.0 | SENSE_DATA_INFORMATION_VALID);
+        self.header.information = information.to_be_bytes();
+        self
+    }
 }
 
+pub const SENSE_DATA_INFORMATION_VALID: u8 = 0x80;
+
 open_enum! {
     #[derive(AsBytes, FromBytes, FromZeroes)]
     pub enum SenseKey: u8 {
@@ -1184,6 +1198,204 @@ pub struct UnmapListHeader {
     pub block_descriptor_data_length: U16BE,
     pub reserved: [u8; 4],
 }
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, AsBytes, FromBytes, FromZeroes)]
+pub struct CompareAndWrite {
+    pub operation_code: ScsiOp,
+    pub flags: Cdb16Flags,
+    pub logical_block: U64BE,
+    pub reserved: [u8; 3],
+    pub number_of_logical_blocks: u8,
+    /*
+    UCHAR GroupNumber   : 5;
+    UCHAR Reserved1     : 3;
+    */
+    pub group_number: u8,
+    pub control: u8,
+}
+
+// EXTENDED COPY (0x83) service actions
+pub const SERVICE_ACTION_EXTENDED_COPY_LID1: u8 = 0x00;
+pub const SERVICE_ACTION_EXTENDED_COPY_LID4: u8 = 0x01;
+pub const SERVICE_ACTION_POPULATE_TOKEN: u8 = 0x10;
+pub const SERVICE_ACTION_WRITE_USING_TOKEN: u8 = 0x11;
+
+// RECEIVE COPY RESULTS (0x84) service actions
+pub const SERVICE_ACTION_RECEIVE_COPY_STATUS_LID1: u8 = 0x00;
+pub const SERVICE_ACTION_RECEIVE_COPY_DATA_LID1: u8 = 0x01;
+pub const SERVICE_ACTION_RECEIVE_COPY_OPERATING_PARAMETERS: u8 = 0x03;
+pub const SERVICE_ACTION_RECEIVE_COPY_FAILURE_DETAILS_LID1: u8 = 0x04;
+pub const SERVICE_ACTION_RECEIVE_COPY_STATUS_LID4: u8 = 0x05;
+pub const SERVICE_ACTION_RECEIVE_ROD_TOKEN_INFORMATION: u8 = 0x07;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, AsBytes, FromBytes, FromZeroes)]
+pub struct ExtendedCopy {
+    pub operation_code: ScsiOp,
+    /*
+    UCHAR ServiceAction : 5;
+    UCHAR Reserved1     : 3;
+    */
+    pub service_action: u8,
+    pub reserved: [u8; 8],
+    pub parameter_list_length: U32BE,
+    pub reserved2: u8,
+    pub control: u8,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, AsBytes, FromBytes, FromZeroes)]
+pub struct ReceiveCopyResults {
+    pub operation_code: ScsiOp,
+    /*
+    UCHAR ServiceAction : 5;
+    UCHAR Reserved1     : 3;
+    */
+    pub service_action: u8,
+    pub list_identifier: u8,
+    pub reserved: [u8; 7],
+    pub allocation_length: U32BE,
+    pub reserved2: u8,
+    pub control: u8,
+}
+
+/// EXTENDED COPY (LID1) parameter list header.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, AsBytes, FromBytes, FromZeroes)]
+pub struct ExtendedCopyParameterListHeader {
+    pub list_identifier: u8,
+    /*
+    UCHAR Priority      : 3;
+    UCHAR ListIdUsage   : 2;
+    UCHAR Str           : 1;
+    UCHAR Reserved1     : 2;
+    */
+    pub flags: u8,
+    pub cscd_descriptor_list_length: U16BE,
+    pub reserved: [u8; 4],
+    pub segment_descriptor_list_length: U32BE,
+    pub inline_data_length: U32BE,
+}
+
+pub const EXTENDED_COPY_LIST_ID_USAGE_MASK: u8 = 0x18;
+pub const EXTENDED_COPY_LIST_ID_USAGE_NONE: u8 = 0x18;
+
+pub const CSCD_DESCRIPTOR_LENGTH: usize = 32;
+pub const CSCD_DESCRIPTOR_TYPE_IDENTIFICATION: u8 = 0xE4;
+pub const SEGMENT_DESCRIPTOR_TYPE_BLOCK_TO_BLOCK: u8 = 0x02;
+
+/// Identification descriptor CSCD descriptor (type 0xE4).
+#[repr(C)]
+#[derive(Debug, AsBytes, FromBytes, FromZeroes)]
+pub struct CscdIdentificationDescriptor {
+    pub descriptor_type_code: u8,
+    /*
+    UCHAR PeripheralDeviceType  : 5;
+    UCHAR Nul                   : 1;
+    UCHAR LuIdType              : 2;
+    */
+    pub flags: u8,
+    pub relative_initiator_port_identifier: U16BE,
+    pub designator: VpdIdentificationDescriptor,
+    pub designator_data: [u8; 16],
+    pub reserved: [u8; 4],
+    /*
+    UCHAR Reserved2     : 2;
+    UCHAR Pad           : 1;
+    UCHAR Reserved3     : 5;
+    */
+    pub device_type_specific: u8,
+    pub disk_block_length: [u8; 3],
+}
+
+/// Block device to block device segment descriptor (type 0x02).
+#[repr(C)]
+#[derive(Debug, Copy, Clone, AsBytes, FromBytes, FromZeroes)]
+pub struct BlockToBlockSegmentDescriptor {
+    pub descriptor_type_code: u8,
+    /*
+    UCHAR Cat           : 1;
+    UCHAR Dc            : 1;
+    UCHAR Reserved1     : 6;
+    */
+    pub flags: u8,
+    pub descriptor_length: U16BE,
+    pub source_cscd_index: U16BE,
+    pub destination_cscd_index: U16BE,
+    pub reserved: [u8; 2],
+    pub block_device_number_of_blocks: U16BE,
+    pub source_lba: U64BE,
+    pub destination_lba: U64BE,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, AsBytes, FromBytes, FromZeroes)]
+pub struct ReceiveCopyOperatingParameters {
+    pub available_data: U32BE,
+    /*
+    UCHAR Snlid         : 1;
+    UCHAR Reserved1     : 7;
+    */
+    pub flags: u8,
+    pub reserved: [u8; 3],
+    pub maximum_cscd_descriptor_count: U16BE,
+    pub maximum_segment_descriptor_count: U16BE,
+    pub maximum_descriptor_list_length: U32BE,
+    pub maximum_segment_length: U32BE,
+    pub maximum_inline_data_length: U32BE,
+    pub held_data_limit: U32BE,
+    pub maximum_stream_device_transfer_size: U32BE,
+    pub reserved2: [u8; 2],
+    pub total_concurrent_copies: U16BE,
+    pub maximum_concurrent_copies: u8,
+    pub data_segment_granularity: u8,
+    pub inline_data_granularity: u8,
+    pub held_data_granularity: u8,
+    pub reserved3: [u8; 3],
+    pub implemented_descriptor_list_length: u8,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, AsBytes, FromBytes, FromZeroes)]
+pub struct ReceiveCopyStatusLid1 {
+    pub available_data: U32BE,
+    /*
+    UCHAR CopyManagerStatus : 7;
+    UCHAR Hdd               : 1;
+    */
+    pub copy_manager_status: u8,
+    pub segments_processed: U16BE,
+    pub transfer_count_units: u8,
+    pub transfer_count: U32BE,
+}
+
+pub const COPY_STATUS_IN_PROGRESS: u8 = 0x00;
+pub const COPY_STATUS_COMPLETED_GOOD: u8 = 0x01;
+pub const COPY_STATUS_COMPLETED_WITH_ERRORS: u8 = 0x02;
+
+pub const COPY_TRANSFER_COUNT_UNITS_BYTES: u8 = 0x00;
+pub const COPY_TRANSFER_COUNT_UNITS_KIBIBYTES: u8 = 0xF1;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, AsBytes, FromBytes, FromZeroes)]
+pub struct ReceiveCopyFailureDetailsHeader {
+    pub available_data: U32BE,
+    pub reserved: [u8; 12],
+    pub copy_command_status: u8,
+    pub reserved2: u8,
+    pub sense_data_length: U16BE,
+}
+
+// VPD Page 0x8F, Third Party Copy descriptor types
+pub const TPC_DESCRIPTOR_TYPE_SUPPORTED_COMMANDS: u16 = 0x0001;
+pub const TPC_DESCRIPTOR_TYPE_PARAMETER_DATA: u16 = 0x0008;
+pub const TPC_DESCRIPTOR_TYPE_SUPPORTED_DESCRIPTORS: u16 = 0x000C;
+pub const TPC_DESCRIPTOR_TYPE_GENERAL_COPY_OPERATIONS: u16 = 0x8001;
+
+// SCSI_ADSENSE_COPY_TARGET_DEVICE_ERROR (0x0D) qualifiers
+pub const SCSI_SENSEQ_COPY_TARGET_DEVICE_NOT_REACHABLE: u8 = 0x02;
+
 #[repr(C)]
 #[derive(Debug, Copy, Clone, AsBytes, FromBytes, FromZeroes)]
 pub struct StartStop {
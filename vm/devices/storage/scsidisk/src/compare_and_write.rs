@@ -0,0 +1,70 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Support for the SCSI "Compare and Write" command.
+
+use super::ScsiError;
+use super::SimpleScsiDisk;
+use crate::scsi;
+use crate::unmap::validate_lba_range;
+use scsi::AdditionalSenseCode;
+use scsi_buffers::RequestBuffers;
+use scsi_core::Request;
+use zerocopy::FromBytes;
+
+/// The maximum number of blocks in a single compare and write request.
+pub(crate) const MAX_COMPARE_AND_WRITE_LENGTH: u8 = 16;
+
+impl SimpleScsiDisk {
+    pub(crate) async fn handle_compare_and_write(
+        &self,
+        external_data: &RequestBuffers<'_>,
+        request: &Request,
+        sector_count: u64,
+    ) -> Result<usize, ScsiError> {
+        let cdb = scsi::CompareAndWrite::read_from_prefix(&request.cdb[..]).unwrap();
+        let start_lba = cdb.logical_block.get();
+        let lba_count = cdb.number_of_logical_blocks;
+
+        // A zero-length request is not an error, and no data is transferred.
+        if lba_count == 0 {
+            return Ok(0);
+        }
+
+        if lba_count > MAX_COMPARE_AND_WRITE_LENGTH {
+            tracelimit::error_ratelimited!(lba_count, "compare and write length too big");
+            return Err(ScsiError::IllegalRequest(AdditionalSenseCode::INVALID_CDB));
+        }
+
+        if !validate_lba_range(sector_count, start_lba, lba_count.into()) {
+            //valiate_lba_range trace errors
+            return Err(ScsiError::IllegalRequest(
+                AdditionalSenseCode::ILLEGAL_BLOCK,
+            ));
+        }
+
+        if self.disk.is_read_only() {
+            return Err(ScsiError::WriteProtected);
+        }
+
+        // The data-out buffer contains the verify data followed by the write
+        // data.
+        let tx = (usize::from(lba_count) << self.sector_shift) * 2;
+        if external_data.len() < tx {
+            tracelimit::error_ratelimited!(
+                external_data = external_data.len(),
+                tx,
+                "provided transfer length too small"
+            );
+            return Err(ScsiError::IllegalRequest(AdditionalSenseCode::INVALID_CDB));
+        }
+
+        let external_data = external_data.subrange(0, tx);
+        self.disk
+            .compare_and_write(&external_data, start_lba, cdb.flags.fua())
+            .await
+            .map_err(ScsiError::Disk)?;
+
+        Ok(tx)
+    }
+}
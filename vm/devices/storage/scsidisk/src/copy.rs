@@ -0,0 +1,501 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Support for the SCSI "Extended Copy" (LID1) and "Receive Copy Results"
+//! commands, which offload copies between disks in the same
+//! [`CopyDomain`].
+
+use super::ScsiError;
+use super::SimpleScsiDisk;
+use crate::scsi;
+use disk_backend::Disk;
+use guestmem::GuestMemory;
+use guestmem::MemoryRead;
+use guestmem::MemoryWrite;
+use parking_lot::Mutex;
+use scsi::AdditionalSenseCode;
+use scsi_buffers::OwnedRequestBuffers;
+use scsi_buffers::RequestBuffers;
+use scsi_core::copy_domain::CopyDomain;
+use scsi_core::copy_domain::CopyDomainRegistration;
+use scsi_core::Request;
+use std::collections::HashMap;
+use zerocopy::AsBytes;
+use zerocopy::FromBytes;
+use zerocopy::FromZeroes;
+
+const MAX_CSCD_DESCRIPTORS: u16 = 8;
+const MAX_SEGMENT_DESCRIPTORS: u16 = 64;
+const MAX_DESCRIPTOR_LIST_LENGTH: usize = MAX_CSCD_DESCRIPTORS as usize
+    * scsi::CSCD_DESCRIPTOR_LENGTH
+    + MAX_SEGMENT_DESCRIPTORS as usize * size_of::<scsi::BlockToBlockSegmentDescriptor>();
+const MAX_PARAMETER_LIST_LENGTH: usize =
+    size_of::<scsi::ExtendedCopyParameterListHeader>() + MAX_DESCRIPTOR_LIST_LENGTH;
+
+/// The size of the bounce buffer used to copy data between disks.
+const COPY_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// The descriptor type codes supported by this copy manager, in ascending
+/// order.
+const IMPLEMENTED_DESCRIPTORS: [u8; 2] = [
+    scsi::SEGMENT_DESCRIPTOR_TYPE_BLOCK_TO_BLOCK,
+    scsi::CSCD_DESCRIPTOR_TYPE_IDENTIFICATION,
+];
+
+/// State for disks that participate in a copy domain.
+pub(crate) struct ExtendedCopyState {
+    domain: CopyDomain,
+    _registration: CopyDomainRegistration,
+    results: Mutex<HashMap<u8, CopyResult>>,
+}
+
+/// The outcome of an EXTENDED COPY command with a list identifier, retained
+/// for RECEIVE COPY RESULTS.
+#[derive(Debug, Copy, Clone, Default)]
+struct CopyResult {
+    failed: bool,
+    segments_processed: u16,
+    bytes_transferred: u64,
+}
+
+impl ExtendedCopyState {
+    pub(crate) fn new(domain: &CopyDomain, designator: [u8; 16], disk: Disk) -> Self {
+        Self {
+            domain: domain.clone(),
+            _registration: domain.register(designator, disk),
+            results: Default::default(),
+        }
+    }
+
+    /// Resolves a CSCD descriptor to a disk in the copy domain.
+    fn resolve_cscd(&self, descriptor: &[u8]) -> Result<Disk, ScsiError> {
+        let descriptor = scsi::CscdIdentificationDescriptor::read_from(descriptor).unwrap();
+        if descriptor.descriptor_type_code != scsi::CSCD_DESCRIPTOR_TYPE_IDENTIFICATION {
+            tracelimit::warn_ratelimited!(
+                descriptor_type = descriptor.descriptor_type_code,
+                "unsupported cscd descriptor type"
+            );
+            return Err(ScsiError::IllegalRequest(
+                AdditionalSenseCode::INVALID_FIELD_PARAMETER_LIST,
+            ));
+        }
+
+        // Only block devices identified by their logical unit are supported.
+        if descriptor.flags != scsi::DIRECT_ACCESS_DEVICE {
+            return Err(ScsiError::IllegalRequest(
+                AdditionalSenseCode::INVALID_FIELD_PARAMETER_LIST,
+            ));
+        }
+
+        // Only the NAA designator reported by this device type can be resolved.
+        let designator = &descriptor.designator;
+        if designator.code_set & 0xf != scsi::VPD_CODE_SET_BINARY
+            || designator.identifiertype != scsi::VPD_IDENTIFIER_TYPE_FCPH_NAME
+            || designator.identifier_length as usize != descriptor.designator_data.len()
+        {
+            return Err(ScsiError::CopyAborted(
+                AdditionalSenseCode::COPY_TARGET_DEVICE_ERROR,
+                scsi::SCSI_SENSEQ_COPY_TARGET_DEVICE_NOT_REACHABLE,
+            ));
+        }
+
+        let disk = self
+            .domain
+            .get(&descriptor.designator_data)
+            .ok_or(ScsiError::CopyAborted(
+                AdditionalSenseCode::COPY_TARGET_DEVICE_ERROR,
+                scsi::SCSI_SENSEQ_COPY_TARGET_DEVICE_NOT_REACHABLE,
+            ))?;
+
+        let [a, b, c] = descriptor.disk_block_length;
+        let block_length = u32::from_be_bytes([0, a, b, c]);
+        if block_length != disk.sector_size() {
+            return Err(ScsiError::IllegalRequest(
+                AdditionalSenseCode::INVALID_FIELD_PARAMETER_LIST,
+            ));
+        }
+
+        Ok(disk)
+    }
+
+    /// Appends the EXTENDED COPY descriptors for the third party copy VPD
+    /// page.
+    pub(crate) fn write_third_party_copy_descriptors(&self, data: &mut Vec<u8>, sector_shift: u8) {
+        fn push_descriptor(data: &mut Vec<u8>, descriptor_type: u16, body: &[u8]) {
+            // Descriptors are padded to a multiple of four bytes.
+            let length = body.len().next_multiple_of(4);
+            data.extend_from_slice(&descriptor_type.to_be_bytes());
+            data.extend_from_slice(&(length as u16).to_be_bytes());
+            data.extend_from_slice(body);
+            data.resize(data.len() + length - body.len(), 0);
+        }
+
+        // Supported commands: each entry is the operation code, the length of
+        // the service action list, and the service actions.
+        let commands: [(scsi::ScsiOp, &[u8]); 2] = [
+            (
+                scsi::ScsiOp::EXTENDED_COPY,
+                &[scsi::SERVICE_ACTION_EXTENDED_COPY_LID1],
+            ),
+            (
+                scsi::ScsiOp::RECEIVE_COPY_RESULTS,
+                &[
+                    scsi::SERVICE_ACTION_RECEIVE_COPY_STATUS_LID1,
+                    scsi::SERVICE_ACTION_RECEIVE_COPY_DATA_LID1,
+                    scsi::SERVICE_ACTION_RECEIVE_COPY_OPERATING_PARAMETERS,
+                    scsi::SERVICE_ACTION_RECEIVE_COPY_FAILURE_DETAILS_LID1,
+                ],
+            ),
+        ];
+        let mut list = Vec::new();
+        for (op, service_actions) in commands {
+            list.push(op.0);
+            list.push(service_actions.len() as u8);
+            list.extend_from_slice(service_actions);
+        }
+        let mut body = vec![list.len() as u8];
+        body.extend(list);
+        push_descriptor(data, scsi::TPC_DESCRIPTOR_TYPE_SUPPORTED_COMMANDS, &body);
+
+        // Parameter data.
+        let mut body = [0; 0x1c];
+        body[4..6].copy_from_slice(&MAX_CSCD_DESCRIPTORS.to_be_bytes());
+        body[6..8].copy_from_slice(&MAX_SEGMENT_DESCRIPTORS.to_be_bytes());
+        body[8..12].copy_from_slice(&(MAX_DESCRIPTOR_LIST_LENGTH as u32).to_be_bytes());
+        push_descriptor(data, scsi::TPC_DESCRIPTOR_TYPE_PARAMETER_DATA, &body);
+
+        // Supported descriptors.
+        let mut body = vec![IMPLEMENTED_DESCRIPTORS.len() as u8];
+        body.extend_from_slice(&IMPLEMENTED_DESCRIPTORS);
+        push_descriptor(data, scsi::TPC_DESCRIPTOR_TYPE_SUPPORTED_DESCRIPTORS, &body);
+
+        // General copy operations.
+        let mut body = [0; 0x1c];
+        body[0..4].copy_from_slice(&1u32.to_be_bytes());
+        body[4..8].copy_from_slice(&1u32.to_be_bytes());
+        body[8..12].copy_from_slice(&max_segment_length(sector_shift).to_be_bytes());
+        push_descriptor(
+            data,
+            scsi::TPC_DESCRIPTOR_TYPE_GENERAL_COPY_OPERATIONS,
+            &body,
+        );
+    }
+}
+
+/// The maximum number of bytes a single block to block segment descriptor can
+/// describe.
+fn max_segment_length(sector_shift: u8) -> u32 {
+    u32::from(u16::MAX) << sector_shift
+}
+
+impl SimpleScsiDisk {
+    pub(crate) async fn handle_extended_copy(
+        &self,
+        external_data: &RequestBuffers<'_>,
+        request: &Request,
+    ) -> Result<usize, ScsiError> {
+        let Some(state) = &self.extended_copy else {
+            return Err(ScsiError::IllegalRequest(
+                AdditionalSenseCode::ILLEGAL_COMMAND,
+            ));
+        };
+
+        let cdb = scsi::ExtendedCopy::read_from_prefix(&request.cdb[..]).unwrap();
+        let service_action = cdb.service_action & 0x1f;
+        if service_action != scsi::SERVICE_ACTION_EXTENDED_COPY_LID1 {
+            return Err(ScsiError::UnsupportedServiceAction(service_action));
+        }
+
+        // A zero-length parameter list is not an error, and no copy is done.
+        let parameter_list_length = cdb.parameter_list_length.get() as usize;
+        if parameter_list_length == 0 {
+            return Ok(0);
+        }
+
+        if external_data.len() < parameter_list_length {
+            return Err(ScsiError::SrbError);
+        }
+
+        const HEADER_SIZE: usize = size_of::<scsi::ExtendedCopyParameterListHeader>();
+        if !(HEADER_SIZE..=MAX_PARAMETER_LIST_LENGTH).contains(&parameter_list_length) {
+            tracelimit::error_ratelimited!(parameter_list_length, "invalid parameter list length");
+            return Err(ScsiError::IllegalRequest(
+                AdditionalSenseCode::PARAMETER_LIST_LENGTH,
+            ));
+        }
+
+        let mut data = vec![0; parameter_list_length];
+        external_data
+            .reader()
+            .read(&mut data)
+            .map_err(ScsiError::MemoryAccess)?;
+
+        let header = scsi::ExtendedCopyParameterListHeader::read_from_prefix(&data[..]).unwrap();
+        let cscd_length = header.cscd_descriptor_list_length.get() as usize;
+        let segment_length = header.segment_descriptor_list_length.get() as usize;
+        let inline_length = header.inline_data_length.get() as usize;
+        if HEADER_SIZE + cscd_length + segment_length + inline_length != parameter_list_length
+            || cscd_length % scsi::CSCD_DESCRIPTOR_LENGTH != 0
+            || cscd_length / scsi::CSCD_DESCRIPTOR_LENGTH > MAX_CSCD_DESCRIPTORS.into()
+        {
+            return Err(ScsiError::IllegalRequest(
+                AdditionalSenseCode::PARAMETER_LIST_LENGTH,
+            ));
+        }
+
+        // Inline data is only used by segment descriptor types that are not
+        // supported.
+        if inline_length != 0 {
+            return Err(ScsiError::IllegalRequest(
+                AdditionalSenseCode::INVALID_FIELD_PARAMETER_LIST,
+            ));
+        }
+
+        let list_identifier = (header.flags & scsi::EXTENDED_COPY_LIST_ID_USAGE_MASK
+            != scsi::EXTENDED_COPY_LIST_ID_USAGE_NONE)
+            .then_some(header.list_identifier);
+
+        let (cscds, segments) = data[HEADER_SIZE..].split_at(cscd_length);
+        let cscds = cscds
+            .chunks_exact(scsi::CSCD_DESCRIPTOR_LENGTH)
+            .map(|descriptor| state.resolve_cscd(descriptor))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut result = CopyResult::default();
+        let r = self.process_segments(&cscds, segments, &mut result).await;
+        if let Some(list_identifier) = list_identifier {
+            result.failed = r.is_err();
+            state.results.lock().insert(list_identifier, result);
+        }
+        r?;
+        Ok(0)
+    }
+
+    async fn process_segments(
+        &self,
+        cscds: &[Disk],
+        mut segments: &[u8],
+        result: &mut CopyResult,
+    ) -> Result<(), ScsiError> {
+        const SEGMENT_SIZE: usize = size_of::<scsi::BlockToBlockSegmentDescriptor>();
+        while !segments.is_empty() {
+            if result.segments_processed == MAX_SEGMENT_DESCRIPTORS {
+                return Err(ScsiError::IllegalRequest(
+                    AdditionalSenseCode::PARAMETER_LIST_LENGTH,
+                ));
+            }
+
+            let Some(segment) = scsi::BlockToBlockSegmentDescriptor::read_from_prefix(segments)
+            else {
+                return Err(ScsiError::IllegalRequest(
+                    AdditionalSenseCode::PARAMETER_LIST_LENGTH,
+                ));
+            };
+
+            if segment.descriptor_type_code != scsi::SEGMENT_DESCRIPTOR_TYPE_BLOCK_TO_BLOCK
+                || segment.descriptor_length.get() as usize != SEGMENT_SIZE - 4
+            {
+                tracelimit::warn_ratelimited!(
+                    descriptor_type = segment.descriptor_type_code,
+                    "unsupported segment descriptor"
+                );
+                return Err(ScsiError::IllegalRequest(
+                    AdditionalSenseCode::INVALID_FIELD_PARAMETER_LIST,
+                ));
+            }
+
+            let (Some(source), Some(destination)) = (
+                cscds.get(segment.source_cscd_index.get() as usize),
+                cscds.get(segment.destination_cscd_index.get() as usize),
+            ) else {
+                return Err(ScsiError::IllegalRequest(
+                    AdditionalSenseCode::INVALID_FIELD_PARAMETER_LIST,
+                ));
+            };
+
+            self.copy_blocks(
+                source,
+                segment.source_lba.get(),
+                destination,
+                segment.destination_lba.get(),
+                segment.block_device_number_of_blocks.get().into(),
+                result,
+            )
+            .await?;
+
+            result.segments_processed += 1;
+            segments = &segments[SEGMENT_SIZE..];
+        }
+        Ok(())
+    }
+
+    async fn copy_blocks(
+        &self,
+        source: &Disk,
+        source_lba: u64,
+        destination: &Disk,
+        destination_lba: u64,
+        block_count: u64,
+        result: &mut CopyResult,
+    ) -> Result<(), ScsiError> {
+        if block_count == 0 {
+            return Ok(());
+        }
+
+        if source.sector_size() != destination.sector_size() {
+            return Err(ScsiError::IllegalRequest(
+                AdditionalSenseCode::INVALID_FIELD_PARAMETER_LIST,
+            ));
+        }
+
+        if source_lba
+            .checked_add(block_count)
+            .is_none_or(|end| end > source.sector_count())
+            || destination_lba
+                .checked_add(block_count)
+                .is_none_or(|end| end > destination.sector_count())
+        {
+            return Err(ScsiError::IllegalRequest(
+                AdditionalSenseCode::ILLEGAL_BLOCK,
+            ));
+        }
+
+        if destination.is_read_only() {
+            return Err(ScsiError::WriteProtected);
+        }
+
+        let sector_shift = source.sector_shift();
+        let chunk_blocks = (COPY_CHUNK_SIZE >> sector_shift) as u64;
+        let mem = GuestMemory::allocate(COPY_CHUNK_SIZE);
+        let mut copied = 0;
+        while copied < block_count {
+            let count = chunk_blocks.min(block_count - copied);
+            let len = (count as usize) << sector_shift;
+            let buffers = OwnedRequestBuffers::linear(0, len, true);
+            let buffers = buffers.buffer(&mem);
+            source
+                .read_vectored(&buffers, source_lba + copied)
+                .await
+                .map_err(ScsiError::Disk)?;
+            destination
+                .write_vectored(&buffers, destination_lba + copied, false)
+                .await
+                .map_err(ScsiError::Disk)?;
+            copied += count;
+            result.bytes_transferred += len as u64;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn handle_receive_copy_results(
+        &self,
+        external_data: &RequestBuffers<'_>,
+        request: &Request,
+    ) -> Result<usize, ScsiError> {
+        let Some(state) = &self.extended_copy else {
+            return Err(ScsiError::IllegalRequest(
+                AdditionalSenseCode::ILLEGAL_COMMAND,
+            ));
+        };
+
+        let cdb = scsi::ReceiveCopyResults::read_from_prefix(&request.cdb[..]).unwrap();
+        let allocation_length = cdb.allocation_length.get() as usize;
+        if external_data.len() < allocation_length {
+            return Err(ScsiError::SrbError);
+        }
+
+        let lookup = || {
+            state
+                .results
+                .lock()
+                .get(&cdb.list_identifier)
+                .copied()
+                .ok_or(ScsiError::IllegalRequest(AdditionalSenseCode::INVALID_CDB))
+        };
+
+        let data = match cdb.service_action & 0x1f {
+            scsi::SERVICE_ACTION_RECEIVE_COPY_OPERATING_PARAMETERS => {
+                let page = scsi::ReceiveCopyOperatingParameters {
+                    available_data: ((size_of::<scsi::ReceiveCopyOperatingParameters>() - 4
+                        + IMPLEMENTED_DESCRIPTORS.len())
+                        as u32)
+                        .into(),
+                    maximum_cscd_descriptor_count: MAX_CSCD_DESCRIPTORS.into(),
+                    maximum_segment_descriptor_count: MAX_SEGMENT_DESCRIPTORS.into(),
+                    maximum_descriptor_list_length: (MAX_DESCRIPTOR_LIST_LENGTH as u32).into(),
+                    maximum_segment_length: max_segment_length(self.sector_shift).into(),
+                    total_concurrent_copies: 1.into(),
+                    maximum_concurrent_copies: 1,
+                    implemented_descriptor_list_length: IMPLEMENTED_DESCRIPTORS.len() as u8,
+                    ..FromZeroes::new_zeroed()
+                };
+                let mut data = page.as_bytes().to_vec();
+                data.extend_from_slice(&IMPLEMENTED_DESCRIPTORS);
+                data
+            }
+            scsi::SERVICE_ACTION_RECEIVE_COPY_STATUS_LID1 => {
+                let result = lookup()?;
+                let (transfer_count_units, transfer_count) =
+                    match u32::try_from(result.bytes_transferred) {
+                        Ok(n) => (scsi::COPY_TRANSFER_COUNT_UNITS_BYTES, n),
+                        Err(_) => (
+                            scsi::COPY_TRANSFER_COUNT_UNITS_KIBIBYTES,
+                            (result.bytes_transferred >> 10) as u32,
+                        ),
+                    };
+                scsi::ReceiveCopyStatusLid1 {
+                    available_data: ((size_of::<scsi::ReceiveCopyStatusLid1>() - 4) as u32).into(),
+                    copy_manager_status: if result.failed {
+                        scsi::COPY_STATUS_COMPLETED_WITH_ERRORS
+                    } else {
+                        scsi::COPY_STATUS_COMPLETED_GOOD
+                    },
+                    segments_processed: result.segments_processed.into(),
+                    transfer_count_units,
+                    transfer_count: transfer_count.into(),
+                }
+                .as_bytes()
+                .to_vec()
+            }
+            scsi::SERVICE_ACTION_RECEIVE_COPY_DATA_LID1 => {
+                // No supported segment descriptor holds data.
+                lookup()?;
+                vec![0; 4]
+            }
+            scsi::SERVICE_ACTION_RECEIVE_COPY_FAILURE_DETAILS_LID1 => {
+                // Failure details are discarded once reported.
+                let result = lookup()?;
+                state.results.lock().remove(&cdb.list_identifier);
+                if result.failed {
+                    let sense = scsi::SenseData::new(
+                        scsi::SenseKey::COPY_ABORTED,
+                        AdditionalSenseCode::NO_SENSE,
+                        0,
+                    );
+                    let header = scsi::ReceiveCopyFailureDetailsHeader {
+                        available_data: ((size_of::<scsi::ReceiveCopyFailureDetailsHeader>() - 4
+                            + size_of_val(&sense)) as u32)
+                            .into(),
+                        copy_command_status: scsi::ScsiStatus::CHECK_CONDITION.0,
+                        sense_data_length: (size_of_val(&sense) as u16).into(),
+                        ..FromZeroes::new_zeroed()
+                    };
+                    [header.as_bytes(), sense.as_bytes()].concat()
+                } else {
+                    scsi::ReceiveCopyFailureDetailsHeader::new_zeroed()
+                        .as_bytes()
+                        .to_vec()
+                }
+            }
+            service_action => return Err(ScsiError::UnsupportedServiceAction(service_action)),
+        };
+
+        let tx = allocation_length.min(data.len());
+        external_data
+            .writer()
+            .write(&data[..tx])
+            .map_err(ScsiError::MemoryAccess)?;
+
+        Ok(tx)
+    }
+}
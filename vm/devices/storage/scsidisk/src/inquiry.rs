@@ -3,6 +3,7 @@
 
 use super::ScsiError;
 use super::SimpleScsiDisk;
+use crate::compare_and_write::MAX_COMPARE_AND_WRITE_LENGTH;
 use crate::scsi;
 use crate::UNMAP_RANGE_DESCRIPTOR_COUNT_MAX;
 use crate::VHDMP_MAX_WRITE_SAME_LENGTH_BYTES;
@@ -111,6 +112,37 @@ fn write_vpd_page<T: ?Sized + AsBytes>(
     Ok(tx)
 }
 
+/// Returns the NAA designator for a disk, as reported in the device
+/// identifiers VPD page.
+pub(crate) fn naa_id(disk_id: &[u8; 16]) -> scsi::VpdNaaId {
+    let mut naa_id = scsi::VpdNaaId {
+        header: scsi::VpdIdentificationDescriptor {
+            code_set: scsi::VPD_CODE_SET_BINARY,
+            identifiertype: scsi::VPD_IDENTIFIER_TYPE_FCPH_NAME, //VpdAssocDevice = 0
+            reserved3: 0x00,
+            identifier_length: (size_of::<scsi::VpdNaaId>()
+                - size_of::<scsi::VpdIdentificationDescriptor>())
+                as u8,
+        },
+        ouid_msb: 0x60, // 6(NAA), 0 (OuidMSB MSFT OUID used = 00-22-48 (hex))
+        ouid_middle: [0x02, 0x24],
+        ouid_lsb: 0x80,
+        vendor_specific_id: [0; 12],
+    };
+
+    // Best effort uniqueness:
+    // Where possible we use version 4 UUID for the T10Id guid,
+    // which has the format: xxxxxxxx-xxxx-4xxx-yxxx-xxxxxxxxxxxx
+    // where x is any hexadecimal digit and y is one of 8, 9, a or b
+    //
+    // Use the first and last 6 bytes of the T10Id's ContextGuid
+    // which are the most random bytes.
+    let id_split_size = naa_id.vendor_specific_id.len() / 2;
+    naa_id.vendor_specific_id[..id_split_size].copy_from_slice(&disk_id[..id_split_size]);
+    naa_id.vendor_specific_id[id_split_size..].copy_from_slice(&disk_id[10..]);
+    naa_id
+}
+
 impl SimpleScsiDisk {
     fn handle_vpd_supported_pages(
         &self,
@@ -124,7 +156,7 @@ fn handle_vpd_supported_pages(
             scsi::VPD_MSFT_VIRTUAL_DEVICE_PROPERTIES, // Microsoft Virtual Device Properties
         ];
 
-        if self.scsi_parameters.support_odx {
+        if self.scsi_parameters.support_odx || self.extended_copy.is_some() {
             supported_pages.push(scsi::VPD_THIRD_PARTY_COPY);
         }
 
@@ -180,7 +212,7 @@ struct Ids {
         // Construct a full local copy and transfer as much as possible.
         // Start with the template and initialize the T10 and NAA
         // Ids appropriately.
-        let mut page = Ids {
+        let page = Ids {
             t10_id: scsi::VpdT10Id {
                 header: scsi::VpdIdentificationDescriptor {
                     code_set: scsi::VPD_CODE_SET_BINARY,
@@ -193,35 +225,9 @@ struct Ids {
                 vendor_id: self.scsi_parameters.identity.vendor_id.into(),
                 context_guid: self.scsi_parameters.disk_id,
             },
-            naa_id: scsi::VpdNaaId {
-                header: scsi::VpdIdentificationDescriptor {
-                    code_set: scsi::VPD_CODE_SET_BINARY,
-                    identifiertype: scsi::VPD_IDENTIFIER_TYPE_FCPH_NAME, //VpdAssocDevice = 0
-                    reserved3: 0x00,
-                    identifier_length: (size_of::<scsi::VpdNaaId>()
-                        - size_of::<scsi::VpdIdentificationDescriptor>())
-                        as u8,
-                },
-                ouid_msb: 0x60, // 6(NAA), 0 (OuidMSB MSFT OUID used = 00-22-48 (hex))
-                ouid_middle: [0x02, 0x24],
-                ouid_lsb: 0x80,
-                vendor_specific_id: [0; 12],
-            },
+            naa_id: naa_id(&self.scsi_parameters.disk_id),
         };
 
-        // Best effort uniqueness:
-        // Where possible we use version 4 UUID for the T10Id guid,
-        // which has the format: xxxxxxxx-xxxx-4xxx-yxxx-xxxxxxxxxxxx
-        // where x is any hexadecimal digit and y is one of 8, 9, a or b
-        //
-        // Use the first and last 6 bytes of the T10Id's ContextGuid
-        // which are the most random bytes.
-        let id_split_size = page.naa_id.vendor_specific_id.len() / 2;
-        page.naa_id.vendor_specific_id[..id_split_size]
-            .copy_from_slice(&self.scsi_parameters.disk_id.as_bytes()[..id_split_size]);
-        page.naa_id.vendor_specific_id[id_split_size..]
-            .copy_from_slice(&page.t10_id.context_guid[10..]);
-
         write_vpd_page(
             external_data,
             allocation_length,
@@ -246,7 +252,7 @@ fn handle_vpd_block_limits(
 
         let page = scsi::VpdBlockLimitsDescriptor {
             reserved0: 0x00,
-            max_compare_and_write_length: 0, // don't support compare_and_write
+            max_compare_and_write_length: MAX_COMPARE_AND_WRITE_LENGTH,
             max_unmap_lba_count: u32::MAX.into(),
             max_unmap_block_descriptor_count: u32::from(UNMAP_RANGE_DESCRIPTOR_COUNT_MAX).into(),
             optimal_unmap_granularity: optimal_unmap_granularity.into(),
@@ -344,11 +350,19 @@ fn handle_vpd_third_party_copy(
             optimal_transfer_count: (VHDMP_MAX_BYTES_PER_OFFLOAD >> self.sector_shift).into(),
         };
 
+        let mut descriptors = Vec::new();
+        if self.scsi_parameters.support_odx {
+            descriptors.extend_from_slice(page.as_bytes());
+        }
+        if let Some(extended_copy) = &self.extended_copy {
+            extended_copy.write_third_party_copy_descriptors(&mut descriptors, self.sector_shift);
+        }
+
         write_vpd_page(
             external_data,
             allocation_length,
             scsi::VPD_THIRD_PARTY_COPY,
-            &page,
+            descriptors.as_slice(),
         )
     }
 
@@ -420,7 +434,11 @@ fn handle_vpd_logical_block_provisioning(
         };
 
         if self.scsi_parameters.support_unmap {
-            page.flags = 0x80;
+            page.flags |= scsi::VPD_LBP_LBPU;
+        }
+        if self.disk.supports_allocation_query() {
+            // Deallocated blocks read as zero.
+            page.flags |= scsi::VPD_LBP_LBPRZ;
         }
 
         write_vpd_page(
@@ -445,6 +463,14 @@ fn handle_no_vpd_page(
         }
 
         let page = scsi::InquiryData {
+            reserved: [
+                if self.extended_copy.is_some() {
+                    scsi::INQUIRY_THIRD_PARTY_COPY
+                } else {
+                    0
+                },
+                0,
+            ],
             misc: 0x02, // CommandQueue = 1
             vendor_id: self.scsi_parameters.identity.vendor_id.into(),
             product_id: self.scsi_parameters.identity.product_id.into(),
@@ -512,7 +538,9 @@ pub(crate) fn handle_inquiry(
                         allocation_length,
                         sector_count,
                     ),
-                scsi::VPD_THIRD_PARTY_COPY if self.scsi_parameters.support_odx => {
+                scsi::VPD_THIRD_PARTY_COPY
+                    if self.scsi_parameters.support_odx || self.extended_copy.is_some() =>
+                {
                     self.handle_vpd_third_party_copy(external_data, allocation_length)
                 }
                 scsi::VPD_MSFT_VIRTUAL_DEVICE_PROPERTIES => {
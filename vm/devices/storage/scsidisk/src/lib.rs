@@ -4,6 +4,8 @@
 #![forbid(unsafe_code)]
 
 pub mod atapi_scsi;
+mod compare_and_write;
+mod copy;
 mod getlbastatus;
 mod inquiry;
 mod reservation;
@@ -16,6 +18,7 @@
 
 pub use inquiry::INQUIRY_DATA_TEMPLATE;
 
+use copy::ExtendedCopyState;
 use disk_backend::Disk;
 use disk_backend::DiskError;
 use disk_backend::UnmapBehavior;
@@ -31,6 +34,7 @@
 use scsi::ScsiStatus;
 use scsi::SenseKey;
 use scsi_buffers::RequestBuffers;
+use scsi_core::copy_domain::CopyDomain;
 use scsi_core::save_restore::SavedSenseData;
 use scsi_core::save_restore::ScsiDiskSavedState;
 use scsi_core::save_restore::ScsiSavedState;
@@ -71,6 +75,7 @@ fn save(&self) -> Result<Option<ScsiSavedState>, SaveError> {
         Ok(Some(ScsiSavedState::ScsiDisk(ScsiDiskSavedState {
             sector_count: self.last_sector_count.load(Ordering::Relaxed),
             sense_data,
+            reservation: self.save_reservation()?,
         })))
     }
 
@@ -79,6 +84,7 @@ fn restore(&self, state: &ScsiSavedState) -> Result<(), RestoreError> {
             let ScsiDiskSavedState {
                 sector_count,
                 sense_data,
+                reservation,
             } = *disk_state;
 
             // restore sense data
@@ -96,6 +102,10 @@ fn restore(&self, state: &ScsiSavedState) -> Result<(), RestoreError> {
 
             self.last_sector_count
                 .store(sector_count, Ordering::Relaxed);
+
+            if let Some(reservation) = &reservation {
+                self.restore_reservation(reservation)?;
+            }
             Ok(())
         } else {
             Err(RestoreError::InvalidSavedState(anyhow::anyhow!(
@@ -114,6 +124,7 @@ pub struct SimpleScsiDisk {
     scsi_parameters: ScsiParameters,
     support_pr: bool,
     last_sector_count: AtomicU64,
+    extended_copy: Option<ExtendedCopyState>,
 }
 
 #[derive(Debug, Clone, Inspect)]
@@ -207,8 +218,24 @@ pub fn new(disk: Disk, disk_parameters: DiskParameters) -> Self {
             scsi_parameters,
             support_pr,
             last_sector_count: AtomicU64::new(sector_count),
+            extended_copy: None,
         }
     }
+
+    /// Enables EXTENDED COPY offload between this disk and the other disks in
+    /// `domain`.
+    pub fn with_copy_domain(mut self, domain: &CopyDomain) -> Self {
+        let designator = inquiry::naa_id(&self.scsi_parameters.disk_id).as_bytes()
+            [size_of::<scsi::VpdIdentificationDescriptor>()..]
+            .try_into()
+            .unwrap();
+        self.extended_copy = Some(ExtendedCopyState::new(
+            domain,
+            designator,
+            self.disk.clone(),
+        ));
+        self
+    }
 }
 
 #[derive(Error, Debug)]
@@ -233,6 +260,8 @@ enum ScsiError {
     UnsupportedVpdPageCode(u8),
     #[error("unsupported service action: {0}")]
     UnsupportedServiceAction(u8),
+    #[error("copy aborted, asc: {0:?}, ascq: {1}")]
+    CopyAborted(AdditionalSenseCode, u8),
 }
 
 struct RequestParameters {
@@ -560,9 +589,13 @@ fn handle_service_action_in16(
                 if self.scsi_parameters.support_unmap {
                     // report trim capabilities:
                     //  - trim is supported
-                    //  - read zero after trim is not supported
                     data.lowest_aligned_block_msb |= scsi::READ_CAPACITY16_LBPME;
                 }
+                if self.disk.supports_allocation_query() {
+                    // Blocks reported as deallocated by GET LBA STATUS read
+                    // as zero.
+                    data.lowest_aligned_block_msb |= scsi::READ_CAPACITY16_LBPRZ;
+                }
 
                 let tx = std::cmp::min(external_data.len(), size_of::<scsi::ReadCapacity16Data>());
                 external_data
@@ -572,16 +605,6 @@ fn handle_service_action_in16(
 
                 Ok(tx)
             }
-            scsi::SERVICE_ACTION_GET_LBA_STATUS => {
-                if !self.scsi_parameters.support_get_lba_status {
-                    tracing::debug!("doesn't support get lba status");
-                    Err(ScsiError::IllegalRequest(
-                        AdditionalSenseCode::ILLEGAL_COMMAND,
-                    ))
-                } else {
-                    self.handle_get_lba_status(external_data, request, sector_count)
-                }
-            }
             _ => Err(ScsiError::UnsupportedServiceAction(cdb.service_action)),
         }
     }
@@ -708,6 +731,9 @@ fn handle_control_cdb(
             | ScsiOp::WRITE_VERIFY
             | ScsiOp::WRITE_VERIFY12
             | ScsiOp::WRITE_VERIFY16 => self.handle_verify_validation(request, sector_count),
+            ScsiOp::RECEIVE_COPY_RESULTS => {
+                self.handle_receive_copy_results(external_data, request)
+            }
             _ => {
                 tracing::debug!(?op, "illegal command");
                 Err(ScsiError::IllegalRequest(
@@ -975,6 +1001,16 @@ fn process_result(&self, result: Result<usize, ScsiError>, op: ScsiOp) -> ScsiRe
                             scsi::SCSI_SENSEQ_CAPACITY_DATA_CHANGED,
                         )),
                     },
+                    ScsiError::CopyAborted(sense_code, qualifier) => ScsiResult {
+                        scsi_status: ScsiStatus::CHECK_CONDITION,
+                        srb_status: SrbStatus::ERROR,
+                        tx: 0,
+                        sense_data: Some(scsi::SenseData::new(
+                            SenseKey::COPY_ABORTED,
+                            sense_code,
+                            qualifier,
+                        )),
+                    },
                     ScsiError::WriteProtected | ScsiError::Disk(DiskError::ReadOnly) => {
                         ScsiResult {
                             scsi_status: ScsiStatus::CHECK_CONDITION,
@@ -1059,6 +1095,19 @@ fn process_result(&self, result: Result<usize, ScsiError>, op: ScsiOp) -> ScsiRe
                                 tx: 0,
                                 sense_data: None,
                             },
+                            DiskError::Miscompare(offset) => ScsiResult {
+                                scsi_status: ScsiStatus::CHECK_CONDITION,
+                                srb_status: SrbStatus::ERROR,
+                                tx: 0,
+                                sense_data: Some(
+                                    scsi::SenseData::new(
+                                        SenseKey::MISCOMPARE,
+                                        AdditionalSenseCode::MISCOMPARE_DURING_VERIFY_OPERATION,
+                                        0,
+                                    )
+                                    .with_information(offset as u32),
+                                ),
+                            },
                             DiskError::UnsupportedEject => ScsiResult {
                                 scsi_status: ScsiStatus::CHECK_CONDITION,
                                 srb_status: SrbStatus::INVALID_REQUEST,
@@ -1274,6 +1323,25 @@ fn execute_scsi<'a>(
                         .instrument(tracing::trace_span!("handle_persistent_reserve_async", ?op,))
                         .await
                 }
+                // These are infrequent, so box them to keep the common path's
+                // future small.
+                ScsiOp::SERVICE_ACTION_IN16
+                    if request.cdb[1] & 0x1f == scsi::SERVICE_ACTION_GET_LBA_STATUS =>
+                {
+                    Box::pin(self.handle_get_lba_status(external_data, request, sector_count))
+                        .instrument(tracing::trace_span!("handle_get_lba_status_async"))
+                        .await
+                }
+                ScsiOp::COMPARE_AND_WRITE => {
+                    Box::pin(self.handle_compare_and_write(external_data, request, sector_count))
+                        .instrument(tracing::trace_span!("handle_compare_and_write_async"))
+                        .await
+                }
+                ScsiOp::EXTENDED_COPY => {
+                    Box::pin(self.handle_extended_copy(external_data, request))
+                        .instrument(tracing::debug_span!("handle_extended_copy_async"))
+                        .await
+                }
                 _ => {
                     let _span = tracing::trace_span!("handle_control_cdb", ?op,).entered();
                     self.handle_control_cdb(external_data, request, sector_count)
@@ -1300,6 +1368,7 @@ fn inspect(&self, req: inspect::Request<'_>) {
             )
             .field("scsi_parameters", &self.scsi_parameters)
             .field("pr", self.support_pr)
+            .field("extended_copy", self.extended_copy.is_some())
             .field("backend", &self.disk);
     }
 }
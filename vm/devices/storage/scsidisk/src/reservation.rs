@@ -17,7 +17,10 @@
 use guestmem::MemoryWrite;
 use scsi::AdditionalSenseCode;
 use scsi::ScsiOp;
+use scsi_core::save_restore::SavedReservationState;
 use scsi_core::Request;
+use vmcore::save_restore::RestoreError;
+use vmcore::save_restore::SaveError;
 use zerocopy::AsBytes;
 use zerocopy::FromBytes;
 use zerocopy::FromZeroes;
@@ -66,6 +69,70 @@ fn to_scsi_reservation_type(
     }
 }
 
+impl SimpleScsiDisk {
+    /// Saves the persistent reservation state of the backing disk.
+    pub(crate) fn save_reservation(&self) -> Result<Option<SavedReservationState>, SaveError> {
+        let Some(pr) = self.disk.pr() else {
+            return Ok(None);
+        };
+        let state = pr
+            .save()
+            .map_err(|err| SaveError::Other(anyhow::Error::new(err)))?;
+        Ok(state.map(|state| {
+            let disk_backend::pr::SavedReservationState {
+                host_id,
+                generation,
+                key,
+                reservation_type,
+                persist_through_power_loss,
+            } = state;
+            SavedReservationState {
+                host_id,
+                generation,
+                key,
+                reservation_type: reservation_type.map(|ty| to_scsi_reservation_type(ty).0),
+                persist_through_power_loss,
+            }
+        }))
+    }
+
+    /// Restores the persistent reservation state of the backing disk.
+    pub(crate) fn restore_reservation(
+        &self,
+        state: &SavedReservationState,
+    ) -> Result<(), RestoreError> {
+        let Some(pr) = self.disk.pr() else {
+            return Err(RestoreError::InvalidSavedState(anyhow::anyhow!(
+                "saved reservation state for a disk without reservation support"
+            )));
+        };
+        let &SavedReservationState {
+            host_id,
+            generation,
+            key,
+            reservation_type,
+            persist_through_power_loss,
+        } = state;
+        let reservation_type = reservation_type
+            .map(|ty| {
+                from_scsi_reservation_type(scsi::ReservationType(ty)).ok_or_else(|| {
+                    RestoreError::InvalidSavedState(anyhow::anyhow!(
+                        "invalid reservation type {ty:#x}"
+                    ))
+                })
+            })
+            .transpose()?;
+        pr.restore(&disk_backend::pr::SavedReservationState {
+            host_id,
+            generation,
+            key,
+            reservation_type,
+            persist_through_power_loss,
+        })
+        .map_err(|err| RestoreError::Other(anyhow::Error::new(err)))
+    }
+}
+
 impl SimpleScsiDisk {
     async fn run_persistent_reservation_report_capabilities(
         &self,
@@ -48,7 +48,7 @@ async fn resolve(
         &self,
         resolver: &ResourceResolver,
         resource: SimpleScsiDiskHandle,
-        _: ResolveScsiDeviceHandleParams<'_>,
+        input: ResolveScsiDeviceHandleParams<'_>,
     ) -> Result<Self::Output, Self::Error> {
         let disk = resolver
             .resolve(
@@ -61,7 +61,10 @@ async fn resolve(
             .await
             .map_err(Error::Disk)?;
 
-        let disk = SimpleScsiDisk::new(disk.0, resource.parameters);
+        let mut disk = SimpleScsiDisk::new(disk.0, resource.parameters);
+        if let Some(copy_domain) = input.copy_domain {
+            disk = disk.with_copy_domain(copy_domain);
+        }
         Ok(disk.into())
     }
 }
@@ -21,15 +21,19 @@
 use scsi::ScsiStatus;
 use scsi::SenseKey;
 use scsi_buffers::OwnedRequestBuffers;
+use scsi_core::copy_domain::CopyDomain;
 use scsi_core::save_restore::SavedSenseData;
 use scsi_core::save_restore::ScsiDiskSavedState;
 use scsi_core::save_restore::ScsiSavedState;
 use scsi_core::AsyncScsiDisk;
 use scsi_core::Request;
 use scsi_core::ScsiSaveRestore;
+use scsidisk_resources::DiskParameters;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use zerocopy::AsBytes;
+use zerocopy::FromBytes;
+use zerocopy::FromZeroes;
 
 fn save_scsi_disk(scsi_disk: &SimpleScsiDisk) -> ScsiDiskSavedState {
     let saved_state = if let Some(ScsiSavedState::ScsiDisk(saved_state)) = scsi_disk.save().unwrap()
@@ -253,3 +257,243 @@ async fn validate_atapi_disk_read() {
     let state = state.lock();
     check_guest_memory(&guest_mem, 0, &state.storage[..sector_size * 4].to_vec());
 }
+
+fn make_request(cdb: &[u8]) -> Request {
+    let mut data = [0u8; 16];
+    data[..cdb.len()].copy_from_slice(cdb);
+    Request {
+        cdb: data,
+        srb_flags: 0,
+    }
+}
+
+#[async_test]
+async fn validate_compare_and_write() {
+    let sector_size = 512;
+    let (disk, state) = new_scsi_disk(sector_size as u32, 4096, 1024, false, true, false);
+    let cdb = scsi::CompareAndWrite {
+        operation_code: ScsiOp::COMPARE_AND_WRITE,
+        logical_block: 2.into(),
+        number_of_logical_blocks: 1,
+        ..FromZeroes::new_zeroed()
+    };
+    let request = make_request(cdb.as_bytes());
+
+    // Compare against the zeroed disk and write a pattern.
+    let mut data = vec![0; sector_size * 2];
+    data[sector_size..].fill(0xab);
+    let guest_mem = make_guest_memory(&data);
+    let external_data = OwnedRequestBuffers::linear(0, data.len(), false);
+    check_execute_scsi_pass(&disk, &external_data.buffer(&guest_mem), &request).await;
+    assert!(state.lock().storage[sector_size * 2..sector_size * 3]
+        .iter()
+        .all(|&b| b == 0xab));
+
+    // The same request now miscompares at the first byte and writes nothing.
+    data[sector_size..].fill(0xcd);
+    guest_mem.write_at(0, &data).unwrap();
+    let result = disk
+        .execute_scsi(&external_data.buffer(&guest_mem), &request)
+        .await;
+    assert_eq!(result.scsi_status, ScsiStatus::CHECK_CONDITION);
+    let sense = result.sense_data.unwrap();
+    assert_eq!(sense.header.sense_key, SenseKey::MISCOMPARE);
+    assert_eq!(
+        sense.additional_sense_code,
+        AdditionalSenseCode::MISCOMPARE_DURING_VERIFY_OPERATION
+    );
+    assert_eq!(sense.header.information, 0u32.to_be_bytes());
+    assert!(state.lock().storage[sector_size * 2..sector_size * 3]
+        .iter()
+        .all(|&b| b == 0xab));
+}
+
+#[async_test]
+async fn validate_extended_copy() {
+    let sector_size = 512;
+    let domain = CopyDomain::new();
+    let (source, source_state) = new_scsi_disk(sector_size as u32, 4096, 1024, false, true, false);
+    let source = source.with_copy_domain(&domain);
+    let (destination, destination_state) =
+        new_scsi_disk(sector_size as u32, 4096, 1024, false, true, false);
+    let destination = destination.with_copy_domain(&domain);
+
+    for (i, b) in source_state.lock().storage.iter_mut().enumerate() {
+        *b = i as u8;
+    }
+
+    let cscd = |disk: &SimpleScsiDisk| {
+        let naa_id = crate::inquiry::naa_id(&disk.scsi_parameters.disk_id);
+        scsi::CscdIdentificationDescriptor {
+            descriptor_type_code: scsi::CSCD_DESCRIPTOR_TYPE_IDENTIFICATION,
+            flags: scsi::DIRECT_ACCESS_DEVICE,
+            designator_data: naa_id.as_bytes()[size_of_val(&naa_id.header)..]
+                .try_into()
+                .unwrap(),
+            designator: naa_id.header,
+            disk_block_length: (sector_size as u32).to_be_bytes()[1..].try_into().unwrap(),
+            ..FromZeroes::new_zeroed()
+        }
+    };
+    let segment = scsi::BlockToBlockSegmentDescriptor {
+        descriptor_type_code: scsi::SEGMENT_DESCRIPTOR_TYPE_BLOCK_TO_BLOCK,
+        descriptor_length: ((size_of::<scsi::BlockToBlockSegmentDescriptor>() - 4) as u16).into(),
+        source_cscd_index: 0.into(),
+        destination_cscd_index: 1.into(),
+        block_device_number_of_blocks: 4.into(),
+        source_lba: 1.into(),
+        destination_lba: 8.into(),
+        ..FromZeroes::new_zeroed()
+    };
+    let header = scsi::ExtendedCopyParameterListHeader {
+        list_identifier: 7,
+        cscd_descriptor_list_length: (2 * scsi::CSCD_DESCRIPTOR_LENGTH as u16).into(),
+        segment_descriptor_list_length: (size_of_val(&segment) as u32).into(),
+        ..FromZeroes::new_zeroed()
+    };
+    let parameters = [
+        header.as_bytes(),
+        cscd(&source).as_bytes(),
+        cscd(&destination).as_bytes(),
+        segment.as_bytes(),
+    ]
+    .concat();
+
+    let cdb = scsi::ExtendedCopy {
+        operation_code: ScsiOp::EXTENDED_COPY,
+        service_action: scsi::SERVICE_ACTION_EXTENDED_COPY_LID1,
+        parameter_list_length: (parameters.len() as u32).into(),
+        ..FromZeroes::new_zeroed()
+    };
+    let guest_mem = make_guest_memory(&parameters);
+    let external_data = OwnedRequestBuffers::linear(0, parameters.len(), false);
+    check_execute_scsi_pass(
+        &source,
+        &external_data.buffer(&guest_mem),
+        &make_request(cdb.as_bytes()),
+    )
+    .await;
+
+    assert_eq!(
+        destination_state.lock().storage[sector_size * 8..sector_size * 12],
+        source_state.lock().storage[sector_size..sector_size * 5]
+    );
+
+    // Retrieve the copy status.
+    let cdb = scsi::ReceiveCopyResults {
+        operation_code: ScsiOp::RECEIVE_COPY_RESULTS,
+        service_action: scsi::SERVICE_ACTION_RECEIVE_COPY_STATUS_LID1,
+        list_identifier: 7,
+        allocation_length: (size_of::<scsi::ReceiveCopyStatusLid1>() as u32).into(),
+        ..FromZeroes::new_zeroed()
+    };
+    let guest_mem = GuestMemory::allocate(4096);
+    let external_data =
+        OwnedRequestBuffers::linear(0, size_of::<scsi::ReceiveCopyStatusLid1>(), true);
+    check_execute_scsi_pass(
+        &source,
+        &external_data.buffer(&guest_mem),
+        &make_request(cdb.as_bytes()),
+    )
+    .await;
+    let mut status = scsi::ReceiveCopyStatusLid1::new_zeroed();
+    guest_mem.read_at(0, status.as_bytes_mut()).unwrap();
+    assert_eq!(status.copy_manager_status, scsi::COPY_STATUS_COMPLETED_GOOD);
+    assert_eq!(status.segments_processed.get(), 1);
+    assert_eq!(status.transfer_count.get(), 4 * sector_size as u32);
+
+    // Disks outside the domain cannot be reached.
+    let (other, _) = new_scsi_disk(sector_size as u32, 4096, 1024, false, true, false);
+    let parameters = [
+        header.as_bytes(),
+        cscd(&source).as_bytes(),
+        cscd(&other).as_bytes(),
+        segment.as_bytes(),
+    ]
+    .concat();
+    let guest_mem = make_guest_memory(&parameters);
+    let external_data = OwnedRequestBuffers::linear(0, parameters.len(), false);
+    let cdb = scsi::ExtendedCopy {
+        operation_code: ScsiOp::EXTENDED_COPY,
+        service_action: scsi::SERVICE_ACTION_EXTENDED_COPY_LID1,
+        parameter_list_length: (parameters.len() as u32).into(),
+        ..FromZeroes::new_zeroed()
+    };
+    let result = source
+        .execute_scsi(
+            &external_data.buffer(&guest_mem),
+            &make_request(cdb.as_bytes()),
+        )
+        .await;
+    assert_eq!(result.scsi_status, ScsiStatus::CHECK_CONDITION);
+    assert_eq!(
+        result.sense_data.unwrap().header.sense_key,
+        SenseKey::COPY_ABORTED
+    );
+}
+
+#[async_test]
+async fn validate_get_lba_status() {
+    let sector_size = 512;
+    let disk = disklayer_ram::ram_disk(1024 * sector_size as u64, false).unwrap();
+    let scsi_disk = SimpleScsiDisk::new(
+        disk,
+        DiskParameters {
+            get_lba_status: true,
+            ..Default::default()
+        },
+    );
+
+    // Write sectors 4 through 7.
+    let data = vec![0xab; 4 * sector_size];
+    let guest_mem = make_guest_memory(&data);
+    let external_data = OwnedRequestBuffers::linear(0, data.len(), false);
+    scsi_disk
+        .disk
+        .write_vectored(&external_data.buffer(&guest_mem), 4, false)
+        .await
+        .unwrap();
+
+    let header_len = size_of::<scsi::LbaStatusListHeader>();
+    let descriptor_len = size_of::<scsi::LbaStatusDescriptor>();
+    let allocation_length = header_len + descriptor_len * 4;
+    let cdb = scsi::GetLbaStatus {
+        operation_code: ScsiOp::SERVICE_ACTION_IN16,
+        service_action: scsi::SERVICE_ACTION_GET_LBA_STATUS,
+        start_lba: 2.into(),
+        allocation_length: (allocation_length as u32).into(),
+        ..FromZeroes::new_zeroed()
+    };
+    let request = make_request(cdb.as_bytes());
+    let guest_mem = GuestMemory::allocate(allocation_length);
+    let external_data = OwnedRequestBuffers::linear(0, allocation_length, true);
+    check_execute_scsi_pass(&scsi_disk, &external_data.buffer(&guest_mem), &request).await;
+
+    let mut buffer = vec![0; allocation_length];
+    guest_mem.read_at(0, &mut buffer).unwrap();
+    let header = scsi::LbaStatusListHeader::read_from_prefix(&buffer).unwrap();
+    assert_eq!(
+        header.parameter_length.get() as usize,
+        4 + descriptor_len * 3
+    );
+    let descriptors: Vec<_> = buffer[header_len..]
+        .chunks_exact(descriptor_len)
+        .take(3)
+        .map(|d| {
+            let d = scsi::LbaStatusDescriptor::read_from(d).unwrap();
+            (
+                d.start_lba.get(),
+                d.logical_block_count.get(),
+                d.provisioning_status,
+            )
+        })
+        .collect();
+    assert_eq!(
+        descriptors,
+        [
+            (2, 2, scsi::LBA_STATUS_DEALLOCATED),
+            (4, 4, scsi::LBA_STATUS_MAPPED),
+            (8, 1016, scsi::LBA_STATUS_DEALLOCATED),
+        ]
+    );
+}
@@ -12,6 +12,7 @@
 use async_trait::async_trait;
 use futures::StreamExt;
 use pal_async::task::Spawn;
+use scsi_core::copy_domain::CopyDomain;
 use scsi_core::ResolveScsiDeviceHandleParams;
 use std::sync::Arc;
 use std::sync::Weak;
@@ -70,12 +71,16 @@ async fn resolve(
             resource.io_queue_depth.unwrap_or(256),
         );
 
+        // Allow copy offload between all the disks on this controller.
+        let copy_domain = CopyDomain::new();
+
         for ScsiDeviceAndPath { path, device } in resource.devices {
             let device = resolver
                 .resolve(
                     device,
                     ResolveScsiDeviceHandleParams {
                         driver_source: input.driver_source,
+                        copy_domain: Some(&copy_domain),
                     },
                 )
                 .await
@@ -93,6 +98,7 @@ async fn resolve(
                     "storvsp-requests",
                     handle_requests(
                         input.driver_source.clone(),
+                        copy_domain,
                         Arc::downgrade(&controller.state),
                         resolver.clone(),
                         requests,
@@ -107,6 +113,7 @@ async fn resolve(
 
 async fn handle_requests(
     driver_source: VmTaskDriverSource,
+    copy_domain: CopyDomain,
     state: Weak<ScsiControllerState>,
     resolver: ResourceResolver,
     mut requests: mesh::Receiver<ScsiControllerRequest>,
@@ -117,10 +124,17 @@ async fn handle_requests(
                 rpc.handle_failable(|ScsiDeviceAndPath { path, device }| {
                     let resolver = &resolver;
                     let driver_source = &driver_source;
+                    let copy_domain = &copy_domain;
                     let state = &state;
                     async move {
                         let device = resolver
-                            .resolve(device, ResolveScsiDeviceHandleParams { driver_source })
+                            .resolve(
+                                device,
+                                ResolveScsiDeviceHandleParams {
+                                    driver_source,
+                                    copy_domain: Some(copy_domain),
+                                },
+                            )
                             .await
                             .context("failed to resolve media")?;
 
@@ -6,9 +6,11 @@
 #![forbid(unsafe_code)]
 #![warn(missing_docs)]
 
+use guestmem::GuestMemory;
 use inspect::Inspect;
 use mesh::payload::Protobuf;
 use mesh::MeshPayload;
+use std::io;
 use vm_resource::kind::FramebufferHandleKind;
 use vm_resource::CanResolveTo;
 use vm_resource::ResourceId;
@@ -66,4 +68,8 @@ pub trait FramebufferControl: Send {
     async fn unmap(&mut self);
     /// Updates the framebuffer format.
     async fn set_format(&mut self, format: FramebufferFormat);
+    /// Gets a `GuestMemory` object that can be used to access the framebuffer
+    /// memory, for devices that render into the framebuffer from the host
+    /// instead of mapping it into the guest.
+    fn memory(&self) -> io::Result<GuestMemory>;
 }
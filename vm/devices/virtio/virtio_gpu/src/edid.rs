@@ -0,0 +1,154 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! EDID generation for the virtual display.
+
+/// The size of a base EDID block.
+pub const EDID_SIZE: usize = 128;
+
+const HEADER: [u8; 8] = [0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00];
+
+/// sRGB chromaticity coordinates, in EDID encoding.
+const SRGB_CHROMATICITY: [u8; 10] = [0xee, 0x91, 0xa3, 0x54, 0x4c, 0x99, 0x26, 0x0f, 0x50, 0x54];
+
+const ASPECT_16_10: u8 = 0;
+const ASPECT_4_3: u8 = 1;
+const ASPECT_5_4: u8 = 2;
+const ASPECT_16_9: u8 = 3;
+
+/// Modes advertised in the standard timings, in addition to the established
+/// 640x480, 800x600, and 1024x768 modes.
+const STANDARD_MODES: &[(u32, u32, u8)] = &[
+    (1280, 720, ASPECT_16_9),
+    (1280, 800, ASPECT_16_10),
+    (1280, 1024, ASPECT_5_4),
+    (1440, 900, ASPECT_16_10),
+    (1600, 900, ASPECT_16_9),
+    (1600, 1200, ASPECT_4_3),
+    (1680, 1050, ASPECT_16_10),
+    (1920, 1080, ASPECT_16_9),
+];
+
+const REFRESH_HZ: u32 = 60;
+
+/// Builds an EDID 1.4 block for a display with the given preferred
+/// resolution, advertising the common modes that fit in `max_bytes` of 32bpp
+/// framebuffer.
+pub fn edid(width: u32, height: u32, max_bytes: usize) -> [u8; EDID_SIZE] {
+    let mut edid = [0; EDID_SIZE];
+    edid[..8].copy_from_slice(&HEADER);
+    edid[8..10].copy_from_slice(&manufacturer_id(*b"OVM").to_be_bytes());
+    edid[10..12].copy_from_slice(&1u16.to_le_bytes()); // product code
+    edid[17] = 34; // model year 2024
+    edid[18] = 1; // version 1.4
+    edid[19] = 4;
+    edid[20] = 0xa0; // digital, 8 bits per color
+    edid[21] = 48; // 48cm x 27cm
+    edid[22] = 27;
+    edid[23] = 120; // gamma 2.2
+    edid[24] = 0x06; // sRGB default, preferred timing is native
+    edid[25..35].copy_from_slice(&SRGB_CHROMATICITY);
+    edid[35] = 0x21; // 640x480@60, 800x600@60
+    edid[36] = 0x08; // 1024x768@60
+
+    let mut standard = STANDARD_MODES
+        .iter()
+        .filter(|&&(w, h, _)| (w * h * 4) as usize <= max_bytes)
+        .map(|&(w, _, aspect)| [(w / 8 - 31) as u8, aspect << 6 | (REFRESH_HZ - 60) as u8])
+        .chain(std::iter::repeat([0x01, 0x01]));
+    for timing in edid[38..54].chunks_exact_mut(2) {
+        timing.copy_from_slice(&standard.next().unwrap());
+    }
+
+    edid[54..72].copy_from_slice(&detailed_timing(width, height));
+    edid[72..90].copy_from_slice(&range_limits());
+    edid[90..108].copy_from_slice(&text_descriptor(0xfc, b"OpenVMM"));
+    // Dummy descriptor.
+    edid[111] = 0x10;
+
+    let sum = edid[..127].iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+    edid[127] = sum.wrapping_neg();
+    edid
+}
+
+fn manufacturer_id(id: [u8; 3]) -> u16 {
+    id.iter()
+        .fold(0, |acc, &c| acc << 5 | u16::from(c - b'A' + 1))
+}
+
+/// Builds a detailed timing descriptor using reduced blanking.
+fn detailed_timing(width: u32, height: u32) -> [u8; 18] {
+    const H_BLANK: u32 = 160;
+    const H_FRONT: u32 = 48;
+    const H_SYNC: u32 = 32;
+    const V_BLANK: u32 = 30;
+    const V_FRONT: u32 = 3;
+    const V_SYNC: u32 = 6;
+
+    let clock_10khz = (width + H_BLANK) * (height + V_BLANK) * REFRESH_HZ / 10000;
+    let mut d = [0; 18];
+    d[0..2].copy_from_slice(&(clock_10khz as u16).to_le_bytes());
+    d[2] = width as u8;
+    d[3] = H_BLANK as u8;
+    d[4] = ((width >> 8) << 4 | H_BLANK >> 8) as u8;
+    d[5] = height as u8;
+    d[6] = V_BLANK as u8;
+    d[7] = ((height >> 8) << 4 | V_BLANK >> 8) as u8;
+    d[8] = H_FRONT as u8;
+    d[9] = H_SYNC as u8;
+    d[10] = ((V_FRONT & 0xf) << 4 | V_SYNC & 0xf) as u8;
+    d[11] = ((H_FRONT >> 8) << 6 | (H_SYNC >> 8) << 4 | (V_FRONT >> 4) << 2 | V_SYNC >> 4) as u8;
+    // Digital separate sync, positive horizontal and negative vertical
+    // polarity.
+    d[17] = 0x1a;
+    d
+}
+
+/// Builds a display range limits descriptor covering the advertised modes.
+fn range_limits() -> [u8; 18] {
+    let mut d = [0; 18];
+    d[3] = 0xfd;
+    d[5] = 50; // min vertical Hz
+    d[6] = 75; // max vertical Hz
+    d[7] = 30; // min horizontal kHz
+    d[8] = 160; // max horizontal kHz
+    d[9] = 30; // max pixel clock, in units of 10 MHz
+    d[11] = 0x0a;
+    d[12..].fill(b' ');
+    d
+}
+
+fn text_descriptor(tag: u8, text: &[u8]) -> [u8; 18] {
+    let mut d = [0; 18];
+    d[3] = tag;
+    d[5..].fill(b' ');
+    d[5..5 + text.len()].copy_from_slice(text);
+    d[5 + text.len()] = b'\n';
+    d
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_edid() {
+        let edid = edid(1024, 768, 8 << 20);
+        assert_eq!(edid[..8], HEADER);
+        assert_eq!(edid.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)), 0);
+
+        // Preferred mode.
+        let d = &edid[54..72];
+        assert_eq!(u32::from(d[2]) | u32::from(d[4] >> 4) << 8, 1024);
+        assert_eq!(u32::from(d[5]) | u32::from(d[7] >> 4) << 8, 768);
+
+        // All of the standard modes fit in 8MB.
+        assert_eq!(edid[38..40], [(1280 / 8 - 31) as u8, ASPECT_16_9 << 6]);
+        assert_eq!(edid[52..54], [(1920 / 8 - 31) as u8, ASPECT_16_9 << 6]);
+
+        // Only some of them fit in 6MB.
+        let edid = super::edid(1024, 768, 6 << 20);
+        assert_eq!(edid[46..48], [(1600 / 8 - 31) as u8, ASPECT_16_9 << 6]);
+        assert_eq!(edid[48..50], [0x01, 0x01]);
+    }
+}
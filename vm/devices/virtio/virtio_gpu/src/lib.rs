@@ -0,0 +1,868 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A virtio-gpu device supporting 2D resources, rendering into the shared
+//! framebuffer.
+//!
+//! The guest renders into resources backed by guest memory. On flush, the
+//! device copies the scanout resource into the framebuffer, where it can be
+//! read by the VNC server like any other framebuffer-backed display.
+
+#![forbid(unsafe_code)]
+
+mod edid;
+pub mod resolver;
+mod spec;
+
+use async_trait::async_trait;
+use guestmem::GuestMemory;
+use pal_async::task::Spawn;
+use pal_async::task::Task;
+use spec::*;
+use std::collections::HashMap;
+use std::io;
+use task_control::TaskControl;
+use video_core::FramebufferControl;
+use video_core::FramebufferFormat;
+use virtio::DeviceTraits;
+use virtio::DeviceTraitsSharedMemory;
+use virtio::Resources;
+use virtio::VirtioDevice;
+use virtio::VirtioQueueCallbackWork;
+use virtio::VirtioQueueState;
+use virtio::VirtioQueueWorker;
+use virtio::VirtioQueueWorkerContext;
+use vmcore::vm_task::VmTaskDriver;
+use vmcore::vm_task::VmTaskDriverSource;
+use zerocopy::AsBytes;
+use zerocopy::FromBytes;
+use zerocopy::FromZeroes;
+
+/// The resolution reported to the guest before it picks a mode.
+const DEFAULT_WIDTH: u32 = 1024;
+const DEFAULT_HEIGHT: u32 = 768;
+
+/// The maximum host memory used for the contents of guest resources.
+const MAX_RESOURCE_BYTES: usize = 256 * 1024 * 1024;
+
+/// The maximum number of backing entries for a single resource.
+const MAX_BACKING_ENTRIES: u32 = 16384;
+
+/// The size of the largest valid command, `RESOURCE_ATTACH_BACKING` with the
+/// maximum number of entries. Any more of a request is ignored.
+const MAX_REQUEST_SIZE: usize = size_of::<CtrlHeader>()
+    + size_of::<ResourceAttachBacking>()
+    + MAX_BACKING_ENTRIES as usize * size_of::<MemEntry>();
+
+/// The bytes per pixel of all supported formats and of the framebuffer.
+const BYTES_PER_PIXEL: usize = 4;
+
+/// A virtio-gpu device.
+pub struct Device {
+    driver: VmTaskDriver,
+    memory: GuestMemory,
+    vram: GuestMemory,
+    vram_len: usize,
+    format_send: mesh::Sender<FramebufferFormat>,
+    _display_task: Task<()>,
+    workers: Vec<TaskControl<VirtioQueueWorker, VirtioQueueState>>,
+    exit_event: event_listener::Event,
+}
+
+impl Device {
+    /// Creates a new virtio-gpu device rendering into `framebuffer`.
+    pub fn new(
+        driver_source: &VmTaskDriverSource,
+        memory: GuestMemory,
+        mut framebuffer: Box<dyn FramebufferControl>,
+    ) -> io::Result<Self> {
+        let driver = driver_source.simple();
+        let vram = framebuffer.memory()?;
+        let vram_len = vram
+            .full_mapping()
+            .map(|(_, len)| len)
+            .ok_or_else(|| io::Error::other("framebuffer memory is not mapped"))?;
+
+        // Forward format changes to the framebuffer from a separate task,
+        // since the queue workers cannot wait on the framebuffer control.
+        let (format_send, mut format_recv) = mesh::channel();
+        let display_task = driver.spawn("virtio-gpu-display", async move {
+            while let Ok(format) = format_recv.recv().await {
+                framebuffer.set_format(format).await;
+            }
+        });
+
+        Ok(Self {
+            driver,
+            memory,
+            vram,
+            vram_len,
+            format_send,
+            _display_task: display_task,
+            workers: Vec::new(),
+            exit_event: event_listener::Event::new(),
+        })
+    }
+}
+
+impl VirtioDevice for Device {
+    fn traits(&self) -> DeviceTraits {
+        DeviceTraits {
+            device_id: VIRTIO_GPU_DEVICE_ID,
+            device_features: VIRTIO_GPU_F_EDID,
+            max_queues: 2,
+            device_register_length: size_of::<Config>() as u32,
+            shared_memory: DeviceTraitsSharedMemory { id: 0, size: 0 },
+        }
+    }
+
+    fn read_registers_u32(&self, offset: u16) -> u32 {
+        // The display configuration never changes, so there are never any
+        // events to report.
+        let config = Config {
+            events_read: 0,
+            events_clear: 0,
+            num_scanouts: 1,
+            num_capsets: 0,
+        };
+        u32::read_from_prefix(config.as_bytes().get(offset as usize..).unwrap_or(&[])).unwrap_or(0)
+    }
+
+    fn write_registers_u32(&mut self, _offset: u16, _val: u32) {}
+
+    fn enable(&mut self, mut resources: Resources) {
+        assert!(self.workers.is_empty());
+        let control_resources = resources.queues.remove(0);
+        let cursor_resources = resources.queues.remove(0);
+        if control_resources.params.enable {
+            let worker = GpuWorker {
+                mem: self.memory.clone(),
+                vram: self.vram.clone(),
+                vram_len: self.vram_len,
+                format_send: self.format_send.clone(),
+                resources: HashMap::new(),
+                resource_bytes: 0,
+                scanout: None,
+            };
+            let worker = VirtioQueueWorker::new(self.driver.clone(), Box::new(worker));
+            self.workers.push(worker.into_running_task(
+                "virtio-gpu-control",
+                self.memory.clone(),
+                resources.features,
+                control_resources,
+                self.exit_event.listen(),
+            ));
+        }
+        if cursor_resources.params.enable {
+            let worker = VirtioQueueWorker::new(self.driver.clone(), Box::new(CursorWorker));
+            self.workers.push(worker.into_running_task(
+                "virtio-gpu-cursor",
+                self.memory.clone(),
+                resources.features,
+                cursor_resources,
+                self.exit_event.listen(),
+            ));
+        }
+    }
+
+    fn disable(&mut self) {
+        self.exit_event.notify(usize::MAX);
+        for mut worker in self.workers.drain(..) {
+            self.driver
+                .spawn("shutdown-virtio-gpu-queue".to_owned(), async move {
+                    worker.stop().await;
+                })
+                .detach();
+        }
+    }
+}
+
+/// A 2D resource created by the guest.
+struct Resource2d {
+    format: Format,
+    width: u32,
+    height: u32,
+    /// The resource contents, in `format`.
+    data: Vec<u8>,
+    /// The guest memory backing the resource.
+    backing: Vec<MemEntry>,
+}
+
+impl Resource2d {
+    fn stride(&self) -> usize {
+        self.width as usize * BYTES_PER_PIXEL
+    }
+
+    fn contains(&self, r: &Rect) -> bool {
+        r.x.checked_add(r.width).is_some_and(|x| x <= self.width)
+            && r.y.checked_add(r.height).is_some_and(|y| y <= self.height)
+    }
+
+    /// Reads from the backing memory, treated as a single contiguous buffer.
+    fn read_backing(&self, mem: &GuestMemory, mut offset: u64, mut buf: &mut [u8]) -> bool {
+        for entry in &self.backing {
+            let len = u64::from(entry.length);
+            if offset >= len {
+                offset -= len;
+                continue;
+            }
+            let n = buf.len().min((len - offset) as usize);
+            let (this, rest) = buf.split_at_mut(n);
+            // The address is guest controlled, so it may overflow.
+            let Some(addr) = entry.addr.checked_add(offset) else {
+                return false;
+            };
+            if mem.read_at(addr, this).is_err() {
+                return false;
+            }
+            buf = rest;
+            offset = 0;
+            if buf.is_empty() {
+                break;
+            }
+        }
+        buf.is_empty()
+    }
+}
+
+/// The resource being displayed.
+struct Scanout {
+    resource_id: u32,
+    rect: Rect,
+}
+
+/// Returns the byte offsets of the blue, green, and red components in a pixel
+/// of `format`, or `None` if the format is not supported.
+fn bgr_offsets(format: Format) -> Option<[usize; 3]> {
+    let offsets = match format {
+        Format::B8G8R8A8_UNORM | Format::B8G8R8X8_UNORM => [0, 1, 2],
+        Format::A8R8G8B8_UNORM | Format::X8R8G8B8_UNORM => [3, 2, 1],
+        Format::R8G8B8A8_UNORM | Format::R8G8B8X8_UNORM => [2, 1, 0],
+        Format::A8B8G8R8_UNORM | Format::X8B8G8R8_UNORM => [1, 2, 3],
+        _ => return None,
+    };
+    Some(offsets)
+}
+
+/// Converts pixels from `format` to the framebuffer's BGRX format.
+fn convert_to_bgrx(format: Format, src: &[u8], dst: &mut [u8]) {
+    let offsets = bgr_offsets(format).expect("format was validated");
+    if offsets == [0, 1, 2] {
+        dst.copy_from_slice(src);
+        return;
+    }
+    for (s, d) in src
+        .chunks_exact(BYTES_PER_PIXEL)
+        .zip(dst.chunks_exact_mut(BYTES_PER_PIXEL))
+    {
+        d[0] = s[offsets[0]];
+        d[1] = s[offsets[1]];
+        d[2] = s[offsets[2]];
+        d[3] = 0;
+    }
+}
+
+/// The processor for the control queue.
+struct GpuWorker {
+    mem: GuestMemory,
+    vram: GuestMemory,
+    vram_len: usize,
+    format_send: mesh::Sender<FramebufferFormat>,
+    resources: HashMap<u32, Resource2d>,
+    resource_bytes: usize,
+    scanout: Option<Scanout>,
+}
+
+type CommandResult = Result<(CtrlType, Vec<u8>), CtrlType>;
+
+fn read_request<T: FromBytes>(body: &[u8]) -> Result<T, CtrlType> {
+    T::read_from_prefix(body).ok_or(CtrlType::RESP_ERR_INVALID_PARAMETER)
+}
+
+fn no_data() -> CommandResult {
+    Ok((CtrlType::RESP_OK_NODATA, Vec::new()))
+}
+
+impl GpuWorker {
+    fn handle_command(&mut self, ty: CtrlType, body: &[u8]) -> CommandResult {
+        match ty {
+            CtrlType::GET_DISPLAY_INFO => self.get_display_info(),
+            CtrlType::GET_EDID => self.get_edid(read_request(body)?),
+            CtrlType::RESOURCE_CREATE_2D => self.resource_create_2d(read_request(body)?),
+            CtrlType::RESOURCE_UNREF => self.resource_unref(read_request(body)?),
+            CtrlType::RESOURCE_ATTACH_BACKING => self.resource_attach_backing(body),
+            CtrlType::RESOURCE_DETACH_BACKING => self.resource_detach_backing(read_request(body)?),
+            CtrlType::TRANSFER_TO_HOST_2D => self.transfer_to_host_2d(read_request(body)?),
+            CtrlType::SET_SCANOUT => self.set_scanout(read_request(body)?),
+            CtrlType::RESOURCE_FLUSH => self.resource_flush(read_request(body)?),
+            // No 3D capability sets are supported.
+            CtrlType::GET_CAPSET_INFO | CtrlType::GET_CAPSET => {
+                Err(CtrlType::RESP_ERR_INVALID_PARAMETER)
+            }
+            ty => {
+                tracing::debug!(ty = ty.0, "unsupported virtio-gpu command");
+                Err(CtrlType::RESP_ERR_UNSPEC)
+            }
+        }
+    }
+
+    fn resource(&mut self, resource_id: u32) -> Result<&mut Resource2d, CtrlType> {
+        self.resources
+            .get_mut(&resource_id)
+            .ok_or(CtrlType::RESP_ERR_INVALID_RESOURCE_ID)
+    }
+
+    fn get_display_info(&mut self) -> CommandResult {
+        let mut info = RespDisplayInfo::new_zeroed();
+        info.pmodes[0] = DisplayOne {
+            r: Rect {
+                x: 0,
+                y: 0,
+                width: DEFAULT_WIDTH,
+                height: DEFAULT_HEIGHT,
+            },
+            enabled: 1,
+            flags: 0,
+        };
+        Ok((
+            CtrlType::RESP_OK_DISPLAY_INFO,
+            info.as_bytes()[size_of::<CtrlHeader>()..].to_vec(),
+        ))
+    }
+
+    fn get_edid(&mut self, req: GetEdid) -> CommandResult {
+        if req.scanout != 0 {
+            return Err(CtrlType::RESP_ERR_INVALID_SCANOUT_ID);
+        }
+        let mut resp = RespEdid::new_zeroed();
+        resp.size = edid::EDID_SIZE as u32;
+        resp.edid[..edid::EDID_SIZE].copy_from_slice(&edid::edid(
+            DEFAULT_WIDTH,
+            DEFAULT_HEIGHT,
+            self.vram_len,
+        ));
+        Ok((
+            CtrlType::RESP_OK_EDID,
+            resp.as_bytes()[size_of::<CtrlHeader>()..].to_vec(),
+        ))
+    }
+
+    fn resource_create_2d(&mut self, req: ResourceCreate2d) -> CommandResult {
+        if req.resource_id == 0 || self.resources.contains_key(&req.resource_id) {
+            return Err(CtrlType::RESP_ERR_INVALID_RESOURCE_ID);
+        }
+        if bgr_offsets(req.format).is_none() || req.width == 0 || req.height == 0 {
+            return Err(CtrlType::RESP_ERR_INVALID_PARAMETER);
+        }
+        let len = (req.width as usize)
+            .checked_mul(req.height as usize)
+            .and_then(|n| n.checked_mul(BYTES_PER_PIXEL))
+            .filter(|&len| self.resource_bytes + len <= MAX_RESOURCE_BYTES)
+            .ok_or(CtrlType::RESP_ERR_OUT_OF_MEMORY)?;
+        self.resource_bytes += len;
+        self.resources.insert(
+            req.resource_id,
+            Resource2d {
+                format: req.format,
+                width: req.width,
+                height: req.height,
+                data: vec![0; len],
+                backing: Vec::new(),
+            },
+        );
+        no_data()
+    }
+
+    fn resource_unref(&mut self, req: ResourceUnref) -> CommandResult {
+        let resource = self
+            .resources
+            .remove(&req.resource_id)
+            .ok_or(CtrlType::RESP_ERR_INVALID_RESOURCE_ID)?;
+        self.resource_bytes -= resource.data.len();
+        if self
+            .scanout
+            .as_ref()
+            .is_some_and(|scanout| scanout.resource_id == req.resource_id)
+        {
+            self.scanout = None;
+        }
+        no_data()
+    }
+
+    fn resource_attach_backing(&mut self, body: &[u8]) -> CommandResult {
+        let req: ResourceAttachBacking = read_request(body)?;
+        if req.nr_entries > MAX_BACKING_ENTRIES {
+            return Err(CtrlType::RESP_ERR_INVALID_PARAMETER);
+        }
+        let entries = body
+            .get(size_of::<ResourceAttachBacking>()..)
+            .and_then(|entries| {
+                MemEntry::slice_from_prefix(entries, req.nr_entries as usize)
+                    .map(|(entries, _)| entries.to_vec())
+            })
+            .ok_or(CtrlType::RESP_ERR_INVALID_PARAMETER)?;
+        if entries
+            .iter()
+            .any(|entry| entry.addr.checked_add(entry.length.into()).is_none())
+        {
+            return Err(CtrlType::RESP_ERR_INVALID_PARAMETER);
+        }
+        self.resource(req.resource_id)?.backing = entries;
+        no_data()
+    }
+
+    fn resource_detach_backing(&mut self, req: ResourceDetachBacking) -> CommandResult {
+        self.resource(req.resource_id)?.backing.clear();
+        no_data()
+    }
+
+    fn transfer_to_host_2d(&mut self, req: TransferToHost2d) -> CommandResult {
+        let mem = self.mem.clone();
+        let resource = self.resource(req.resource_id)?;
+        if !resource.contains(&req.r) {
+            return Err(CtrlType::RESP_ERR_INVALID_PARAMETER);
+        }
+        let stride = resource.stride();
+        let row_len = req.r.width as usize * BYTES_PER_PIXEL;
+        let mut data = std::mem::take(&mut resource.data);
+        let mut result = no_data();
+        for row in 0..req.r.height as usize {
+            // The offset is guest controlled, so it may overflow.
+            let Some(src) = (stride as u64)
+                .checked_mul(row as u64)
+                .and_then(|n| n.checked_add(req.offset))
+            else {
+                result = Err(CtrlType::RESP_ERR_INVALID_PARAMETER);
+                break;
+            };
+            let dst = (req.r.y as usize + row) * stride + req.r.x as usize * BYTES_PER_PIXEL;
+            if !resource.read_backing(&mem, src, &mut data[dst..dst + row_len]) {
+                result = Err(CtrlType::RESP_ERR_UNSPEC);
+            }
+        }
+        resource.data = data;
+        result
+    }
+
+    fn set_scanout(&mut self, req: SetScanout) -> CommandResult {
+        if req.scanout_id != 0 {
+            return Err(CtrlType::RESP_ERR_INVALID_SCANOUT_ID);
+        }
+        if req.resource_id == 0 {
+            // The guest has disabled the display.
+            self.scanout = None;
+            return no_data();
+        }
+        let vram_len = self.vram_len;
+        let resource = self.resource(req.resource_id)?;
+        if !resource.contains(&req.r)
+            || req.r.width == 0
+            || req.r.height == 0
+            || req.r.width as usize * req.r.height as usize * BYTES_PER_PIXEL > vram_len
+        {
+            return Err(CtrlType::RESP_ERR_INVALID_PARAMETER);
+        }
+        if self.scanout.as_ref().map(|scanout| scanout.rect) != Some(req.r) {
+            self.format_send.send(FramebufferFormat {
+                width: req.r.width as usize,
+                height: req.r.height as usize,
+                bytes_per_line: req.r.width as usize * BYTES_PER_PIXEL,
+                offset: 0,
+            });
+        }
+        self.scanout = Some(Scanout {
+            resource_id: req.resource_id,
+            rect: req.r,
+        });
+        self.flush(req.r);
+        no_data()
+    }
+
+    fn resource_flush(&mut self, req: ResourceFlush) -> CommandResult {
+        let resource = self.resource(req.resource_id)?;
+        if !resource.contains(&req.r) {
+            return Err(CtrlType::RESP_ERR_INVALID_PARAMETER);
+        }
+        if self
+            .scanout
+            .as_ref()
+            .is_some_and(|scanout| scanout.resource_id == req.resource_id)
+        {
+            self.flush(req.r);
+        }
+        no_data()
+    }
+
+    /// Copies the part of `rect` that is within the scanout to the
+    /// framebuffer.
+    fn flush(&mut self, rect: Rect) {
+        let Some(scanout) = &self.scanout else {
+            return;
+        };
+        let resource = &self.resources[&scanout.resource_id];
+        let s = &scanout.rect;
+        let x0 = rect.x.max(s.x);
+        let y0 = rect.y.max(s.y);
+        let x1 = (rect.x + rect.width).min(s.x + s.width);
+        let y1 = (rect.y + rect.height).min(s.y + s.height);
+        if x0 >= x1 || y0 >= y1 {
+            return;
+        }
+        let stride = resource.stride();
+        let row_len = (x1 - x0) as usize * BYTES_PER_PIXEL;
+        let mut line = vec![0; row_len];
+        for y in y0..y1 {
+            let src = y as usize * stride + x0 as usize * BYTES_PER_PIXEL;
+            convert_to_bgrx(
+                resource.format,
+                &resource.data[src..src + row_len],
+                &mut line,
+            );
+            let dst = (y - s.y) as usize * s.width as usize * BYTES_PER_PIXEL
+                + (x0 - s.x) as usize * BYTES_PER_PIXEL;
+            if let Err(err) = self.vram.write_at(dst as u64, &line) {
+                tracing::warn!(
+                    error = &err as &dyn std::error::Error,
+                    "failed to write framebuffer"
+                );
+                return;
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl VirtioQueueWorkerContext for GpuWorker {
+    async fn process_work(&mut self, work: anyhow::Result<VirtioQueueCallbackWork>) -> bool {
+        let mut work = match work {
+            Ok(work) => work,
+            Err(err) => {
+                tracing::error!(err = err.as_ref() as &dyn std::error::Error, "queue error");
+                return false;
+            }
+        };
+
+        let len = work.get_payload_length(false).min(MAX_REQUEST_SIZE as u64);
+        let mut request = vec![0; len as usize];
+        let hdr = match work.read(&self.mem, &mut request) {
+            Ok(_) => CtrlHeader::read_from_prefix(&request[..]),
+            Err(err) => {
+                tracing::error!(error = &err as &dyn std::error::Error, "invalid descriptor");
+                None
+            }
+        };
+        let Some(hdr) = hdr else {
+            work.complete(0);
+            return true;
+        };
+
+        let (ty, body) = match self.handle_command(hdr.ty, &request[size_of::<CtrlHeader>()..]) {
+            Ok((ty, body)) => (ty, body),
+            Err(ty) => {
+                tracing::debug!(
+                    command = hdr.ty.0,
+                    response = ty.0,
+                    "virtio-gpu command failed"
+                );
+                (ty, Vec::new())
+            }
+        };
+
+        // Commands complete synchronously, so fences are signaled
+        // immediately by echoing them back.
+        let mut resp_hdr = CtrlHeader {
+            ty,
+            flags: 0,
+            fence_id: 0,
+            ctx_id: 0,
+            ring_idx: 0,
+            padding: [0; 3],
+        };
+        if hdr.flags & VIRTIO_GPU_FLAG_FENCE != 0 {
+            resp_hdr.flags = hdr.flags;
+            resp_hdr.fence_id = hdr.fence_id;
+            resp_hdr.ctx_id = hdr.ctx_id;
+            resp_hdr.ring_idx = hdr.ring_idx;
+        }
+        let mut response = resp_hdr.as_bytes().to_vec();
+        response.extend_from_slice(&body);
+        match work.write(&self.mem, &response) {
+            Ok(()) => work.complete(response.len() as u32),
+            Err(err) => {
+                tracing::error!(
+                    error = &err as &dyn std::error::Error,
+                    "failed to write response"
+                );
+                work.complete(0);
+            }
+        }
+        true
+    }
+}
+
+/// The processor for the cursor queue. Hardware cursors are not supported, so
+/// cursor updates are ignored and the guest falls back to software cursors.
+struct CursorWorker;
+
+#[async_trait]
+impl VirtioQueueWorkerContext for CursorWorker {
+    async fn process_work(&mut self, work: anyhow::Result<VirtioQueueCallbackWork>) -> bool {
+        if let Err(err) = work {
+            tracing::error!(err = err.as_ref() as &dyn std::error::Error, "queue error");
+            return false;
+        }
+        // Dropping the work completes it.
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn worker(vram_len: usize) -> (GpuWorker, mesh::Receiver<FramebufferFormat>) {
+        let (format_send, format_recv) = mesh::channel();
+        let worker = GpuWorker {
+            mem: GuestMemory::allocate(0x10000),
+            vram: GuestMemory::allocate(vram_len),
+            vram_len,
+            format_send,
+            resources: HashMap::new(),
+            resource_bytes: 0,
+            scanout: None,
+        };
+        (worker, format_recv)
+    }
+
+    fn command<T: AsBytes>(worker: &mut GpuWorker, ty: CtrlType, req: T) -> CommandResult {
+        worker.handle_command(ty, req.as_bytes())
+    }
+
+    #[test]
+    fn test_render() {
+        let (mut worker, mut format_recv) = worker(0x1000);
+        let rect = Rect {
+            x: 0,
+            y: 0,
+            width: 4,
+            height: 2,
+        };
+
+        // A 4x2 R8G8B8A8 image in guest memory at 0x1000, split across two
+        // backing entries.
+        let pixels: Vec<u8> = (0..8u8)
+            .flat_map(|i| [i, 0x10 + i, 0x20 + i, 0xff])
+            .collect();
+        worker.mem.write_at(0x1000, &pixels[..20]).unwrap();
+        worker.mem.write_at(0x3000, &pixels[20..]).unwrap();
+
+        command(
+            &mut worker,
+            CtrlType::RESOURCE_CREATE_2D,
+            ResourceCreate2d {
+                resource_id: 1,
+                format: Format::R8G8B8A8_UNORM,
+                width: 4,
+                height: 2,
+            },
+        )
+        .unwrap();
+
+        let mut attach = ResourceAttachBacking {
+            resource_id: 1,
+            nr_entries: 2,
+        }
+        .as_bytes()
+        .to_vec();
+        for (addr, length) in [(0x1000, 20), (0x3000, 12)] {
+            attach.extend_from_slice(
+                MemEntry {
+                    addr,
+                    length,
+                    padding: 0,
+                }
+                .as_bytes(),
+            );
+        }
+        worker
+            .handle_command(CtrlType::RESOURCE_ATTACH_BACKING, &attach)
+            .unwrap();
+
+        command(
+            &mut worker,
+            CtrlType::TRANSFER_TO_HOST_2D,
+            TransferToHost2d {
+                r: rect,
+                offset: 0,
+                resource_id: 1,
+                padding: 0,
+            },
+        )
+        .unwrap();
+
+        command(
+            &mut worker,
+            CtrlType::SET_SCANOUT,
+            SetScanout {
+                r: rect,
+                scanout_id: 0,
+                resource_id: 1,
+            },
+        )
+        .unwrap();
+
+        let format = format_recv.try_recv().unwrap();
+        assert_eq!(
+            (format.width, format.height, format.bytes_per_line),
+            (4, 2, 16)
+        );
+
+        let mut vram = [0; 32];
+        worker.vram.read_at(0, &mut vram).unwrap();
+        let expected: Vec<u8> = (0..8u8).flat_map(|i| [0x20 + i, 0x10 + i, i, 0]).collect();
+        assert_eq!(vram[..], expected[..]);
+
+        // The scanout must fit in the framebuffer.
+        let (mut small, _) = worker_with_resource(16);
+        assert_eq!(
+            command(
+                &mut small,
+                CtrlType::SET_SCANOUT,
+                SetScanout {
+                    r: rect,
+                    scanout_id: 0,
+                    resource_id: 1,
+                },
+            )
+            .unwrap_err(),
+            CtrlType::RESP_ERR_INVALID_PARAMETER
+        );
+    }
+
+    fn worker_with_resource(vram_len: usize) -> (GpuWorker, mesh::Receiver<FramebufferFormat>) {
+        let (mut worker, format_recv) = worker(vram_len);
+        command(
+            &mut worker,
+            CtrlType::RESOURCE_CREATE_2D,
+            ResourceCreate2d {
+                resource_id: 1,
+                format: Format::B8G8R8X8_UNORM,
+                width: 4,
+                height: 2,
+            },
+        )
+        .unwrap();
+        (worker, format_recv)
+    }
+
+    #[test]
+    fn test_resource_errors() {
+        let (mut worker, _) = worker_with_resource(0x1000);
+        let create = ResourceCreate2d {
+            resource_id: 1,
+            format: Format::B8G8R8X8_UNORM,
+            width: 4,
+            height: 2,
+        };
+        assert_eq!(
+            command(&mut worker, CtrlType::RESOURCE_CREATE_2D, create).unwrap_err(),
+            CtrlType::RESP_ERR_INVALID_RESOURCE_ID
+        );
+        assert_eq!(
+            command(
+                &mut worker,
+                CtrlType::RESOURCE_CREATE_2D,
+                ResourceCreate2d {
+                    resource_id: 2,
+                    width: 0x10000,
+                    height: 0x10000,
+                    ..create
+                }
+            )
+            .unwrap_err(),
+            CtrlType::RESP_ERR_OUT_OF_MEMORY
+        );
+        assert_eq!(
+            command(
+                &mut worker,
+                CtrlType::TRANSFER_TO_HOST_2D,
+                TransferToHost2d {
+                    r: Rect {
+                        x: 2,
+                        y: 0,
+                        width: 4,
+                        height: 1,
+                    },
+                    offset: 0,
+                    resource_id: 1,
+                    padding: 0,
+                },
+            )
+            .unwrap_err(),
+            CtrlType::RESP_ERR_INVALID_PARAMETER
+        );
+        // The transfer offset must not overflow.
+        assert_eq!(
+            command(
+                &mut worker,
+                CtrlType::TRANSFER_TO_HOST_2D,
+                TransferToHost2d {
+                    r: Rect {
+                        x: 0,
+                        y: 0,
+                        width: 4,
+                        height: 2,
+                    },
+                    offset: u64::MAX - 8,
+                    resource_id: 1,
+                    padding: 0,
+                },
+            )
+            .unwrap_err(),
+            CtrlType::RESP_ERR_INVALID_PARAMETER
+        );
+        // Backing entries must not wrap around the address space.
+        let mut attach = ResourceAttachBacking {
+            resource_id: 1,
+            nr_entries: 1,
+        }
+        .as_bytes()
+        .to_vec();
+        attach.extend_from_slice(
+            MemEntry {
+                addr: u64::MAX - 8,
+                length: 16,
+                padding: 0,
+            }
+            .as_bytes(),
+        );
+        assert_eq!(
+            worker
+                .handle_command(CtrlType::RESOURCE_ATTACH_BACKING, &attach)
+                .unwrap_err(),
+            CtrlType::RESP_ERR_INVALID_PARAMETER
+        );
+        command(
+            &mut worker,
+            CtrlType::RESOURCE_UNREF,
+            ResourceUnref {
+                resource_id: 1,
+                padding: 0,
+            },
+        )
+        .unwrap();
+        assert_eq!(worker.resource_bytes, 0);
+        assert_eq!(
+            command(
+                &mut worker,
+                CtrlType::RESOURCE_UNREF,
+                ResourceUnref {
+                    resource_id: 1,
+                    padding: 0,
+                },
+            )
+            .unwrap_err(),
+            CtrlType::RESP_ERR_INVALID_RESOURCE_ID
+        );
+    }
+}
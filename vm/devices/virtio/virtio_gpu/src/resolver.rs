@@ -0,0 +1,60 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Defines the resource resolver for virtio-gpu devices.
+
+use crate::Device;
+use async_trait::async_trait;
+use thiserror::Error;
+use virtio::resolve::ResolvedVirtioDevice;
+use virtio::resolve::VirtioResolveInput;
+use virtio_resources::gpu::VirtioGpuHandle;
+use vm_resource::declare_static_async_resolver;
+use vm_resource::kind::VirtioDeviceHandle;
+use vm_resource::AsyncResolveResource;
+use vm_resource::ResolveError;
+use vm_resource::ResourceResolver;
+
+/// Resolver for virtio-gpu devices.
+pub struct VirtioGpuResolver;
+
+declare_static_async_resolver! {
+    VirtioGpuResolver,
+    (VirtioDeviceHandle, VirtioGpuHandle),
+}
+
+/// Error returned when resolving virtio-gpu device handles.
+#[derive(Debug, Error)]
+pub enum ResolveVirtioGpuError {
+    /// The framebuffer could not be resolved.
+    #[error("failed to resolve framebuffer")]
+    Framebuffer(#[source] ResolveError),
+    /// The framebuffer memory could not be accessed.
+    #[error("failed to access framebuffer memory")]
+    FramebufferMemory(#[source] std::io::Error),
+}
+
+#[async_trait]
+impl AsyncResolveResource<VirtioDeviceHandle, VirtioGpuHandle> for VirtioGpuResolver {
+    type Output = ResolvedVirtioDevice;
+    type Error = ResolveVirtioGpuError;
+
+    async fn resolve(
+        &self,
+        resolver: &ResourceResolver,
+        resource: VirtioGpuHandle,
+        input: VirtioResolveInput<'_>,
+    ) -> Result<Self::Output, Self::Error> {
+        let framebuffer = resolver
+            .resolve(resource.framebuffer, ())
+            .await
+            .map_err(ResolveVirtioGpuError::Framebuffer)?;
+        let device = Device::new(
+            input.driver_source,
+            input.guest_memory.clone(),
+            framebuffer.0,
+        )
+        .map_err(ResolveVirtioGpuError::FramebufferMemory)?;
+        Ok(device.into())
+    }
+}
@@ -0,0 +1,189 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Definitions from the virtio-gpu specification, limited to 2D operation.
+
+use zerocopy::AsBytes;
+use zerocopy::FromBytes;
+use zerocopy::FromZeroes;
+
+pub const VIRTIO_GPU_DEVICE_ID: u16 = 16;
+
+pub const VIRTIO_GPU_F_EDID: u64 = 1 << 1;
+
+pub const VIRTIO_GPU_MAX_SCANOUTS: usize = 16;
+
+pub const VIRTIO_GPU_FLAG_FENCE: u32 = 1 << 0;
+
+open_enum::open_enum! {
+    #[derive(AsBytes, FromBytes, FromZeroes)]
+    pub enum CtrlType: u32 {
+        // 2D commands
+        GET_DISPLAY_INFO = 0x0100,
+        RESOURCE_CREATE_2D = 0x0101,
+        RESOURCE_UNREF = 0x0102,
+        SET_SCANOUT = 0x0103,
+        RESOURCE_FLUSH = 0x0104,
+        TRANSFER_TO_HOST_2D = 0x0105,
+        RESOURCE_ATTACH_BACKING = 0x0106,
+        RESOURCE_DETACH_BACKING = 0x0107,
+        GET_CAPSET_INFO = 0x0108,
+        GET_CAPSET = 0x0109,
+        GET_EDID = 0x010a,
+
+        // Cursor commands
+        UPDATE_CURSOR = 0x0300,
+        MOVE_CURSOR = 0x0301,
+
+        // Success responses
+        RESP_OK_NODATA = 0x1100,
+        RESP_OK_DISPLAY_INFO = 0x1101,
+        RESP_OK_EDID = 0x1104,
+
+        // Error responses
+        RESP_ERR_UNSPEC = 0x1200,
+        RESP_ERR_OUT_OF_MEMORY = 0x1201,
+        RESP_ERR_INVALID_SCANOUT_ID = 0x1202,
+        RESP_ERR_INVALID_RESOURCE_ID = 0x1203,
+        RESP_ERR_INVALID_CONTEXT_ID = 0x1204,
+        RESP_ERR_INVALID_PARAMETER = 0x1205,
+    }
+}
+
+open_enum::open_enum! {
+    /// Pixel formats, named by their byte order in memory.
+    #[derive(AsBytes, FromBytes, FromZeroes)]
+    pub enum Format: u32 {
+        B8G8R8A8_UNORM = 1,
+        B8G8R8X8_UNORM = 2,
+        A8R8G8B8_UNORM = 3,
+        X8R8G8B8_UNORM = 4,
+        R8G8B8A8_UNORM = 67,
+        X8B8G8R8_UNORM = 68,
+        A8B8G8R8_UNORM = 121,
+        R8G8B8X8_UNORM = 134,
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, AsBytes, FromBytes, FromZeroes)]
+pub struct Config {
+    pub events_read: u32,
+    pub events_clear: u32,
+    pub num_scanouts: u32,
+    pub num_capsets: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, AsBytes, FromBytes, FromZeroes)]
+pub struct CtrlHeader {
+    pub ty: CtrlType,
+    pub flags: u32,
+    pub fence_id: u64,
+    pub ctx_id: u32,
+    pub ring_idx: u8,
+    pub padding: [u8; 3],
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, AsBytes, FromBytes, FromZeroes, PartialEq, Eq)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, AsBytes, FromBytes, FromZeroes)]
+pub struct DisplayOne {
+    pub r: Rect,
+    pub enabled: u32,
+    pub flags: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, AsBytes, FromBytes, FromZeroes)]
+pub struct RespDisplayInfo {
+    pub hdr: CtrlHeader,
+    pub pmodes: [DisplayOne; VIRTIO_GPU_MAX_SCANOUTS],
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, AsBytes, FromBytes, FromZeroes)]
+pub struct GetEdid {
+    pub scanout: u32,
+    pub padding: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, AsBytes, FromBytes, FromZeroes)]
+pub struct RespEdid {
+    pub hdr: CtrlHeader,
+    pub size: u32,
+    pub padding: u32,
+    pub edid: [u8; 1024],
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, AsBytes, FromBytes, FromZeroes)]
+pub struct ResourceCreate2d {
+    pub resource_id: u32,
+    pub format: Format,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, AsBytes, FromBytes, FromZeroes)]
+pub struct ResourceUnref {
+    pub resource_id: u32,
+    pub padding: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, AsBytes, FromBytes, FromZeroes)]
+pub struct SetScanout {
+    pub r: Rect,
+    pub scanout_id: u32,
+    pub resource_id: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, AsBytes, FromBytes, FromZeroes)]
+pub struct ResourceFlush {
+    pub r: Rect,
+    pub resource_id: u32,
+    pub padding: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, AsBytes, FromBytes, FromZeroes)]
+pub struct TransferToHost2d {
+    pub r: Rect,
+    pub offset: u64,
+    pub resource_id: u32,
+    pub padding: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, AsBytes, FromBytes, FromZeroes)]
+pub struct ResourceAttachBacking {
+    pub resource_id: u32,
+    pub nr_entries: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, AsBytes, FromBytes, FromZeroes)]
+pub struct MemEntry {
+    pub addr: u64,
+    pub length: u32,
+    pub padding: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, AsBytes, FromBytes, FromZeroes)]
+pub struct ResourceDetachBacking {
+    pub resource_id: u32,
+    pub padding: u32,
+}
@@ -0,0 +1,671 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Virtio input devices: a keyboard, a relative mouse, and an absolute tablet,
+//! each fed by an [`InputSource`].
+
+#![forbid(unsafe_code)]
+
+pub mod resolver;
+mod spec;
+
+use futures::StreamExt;
+use futures_concurrency::future::Race;
+use guestmem::GuestMemory;
+use input_core::InputSource;
+use input_core::KeyboardData;
+use input_core::MouseData;
+use pal_async::task::Spawn;
+use pal_async::task::Task;
+use pal_async::wait::PolledWait;
+use spec::*;
+use std::pin::pin;
+use virtio::DeviceTraits;
+use virtio::DeviceTraitsSharedMemory;
+use virtio::QueueResources;
+use virtio::Resources;
+use virtio::VirtioDevice;
+use virtio::VirtioQueue;
+use vmcore::vm_task::VmTaskDriver;
+use vmcore::vm_task::VmTaskDriverSource;
+use zerocopy::AsBytes;
+
+/// The maximum absolute coordinate reported by [`MouseData`].
+const ABS_MAX: u32 = 0x7fff;
+
+/// The divisor used to convert absolute coordinate deltas into relative mouse
+/// motion, so that the full width of the screen is 1024 counts.
+const REL_DIVISOR: i32 = (ABS_MAX as i32 + 1) / 1024;
+
+/// The kind of virtio input device.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum InputKind {
+    /// A keyboard.
+    Keyboard,
+    /// A mouse reporting relative motion.
+    Mouse,
+    /// A tablet reporting absolute positions.
+    Tablet,
+}
+
+impl InputKind {
+    fn name(&self) -> &'static str {
+        match self {
+            InputKind::Keyboard => "OpenVMM Virtio Keyboard",
+            InputKind::Mouse => "OpenVMM Virtio Mouse",
+            InputKind::Tablet => "OpenVMM Virtio Tablet",
+        }
+    }
+
+    fn product(&self) -> u16 {
+        match self {
+            InputKind::Keyboard => 1,
+            InputKind::Mouse => 2,
+            InputKind::Tablet => 3,
+        }
+    }
+
+    /// Returns the event codes the device reports for event type `ty`.
+    fn event_codes(&self, ty: u16) -> Vec<u16> {
+        match (self, ty) {
+            (InputKind::Keyboard, EV_KEY) => (1..=KEY_F12)
+                .filter(|&code| keyboard_code(code) == Some(code))
+                .chain(EXTENDED_KEYS.iter().map(|&(_, code)| code))
+                .collect(),
+            (InputKind::Mouse | InputKind::Tablet, EV_KEY) => {
+                BUTTONS.iter().map(|&(_, code)| code).collect()
+            }
+            (InputKind::Mouse, EV_REL) => vec![REL_X, REL_Y, REL_WHEEL],
+            (InputKind::Tablet, EV_REL) => vec![REL_WHEEL],
+            (InputKind::Tablet, EV_ABS) => vec![ABS_X, ABS_Y],
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Mapping from extended (0xE0- or 0xE1-prefixed) set 1 scancodes to Linux
+/// key codes.
+const EXTENDED_KEYS: &[(u16, u16)] = &[
+    (0xe01c, KEY_KPENTER),
+    (0xe01d, KEY_RIGHTCTRL),
+    (0xe020, KEY_MUTE),
+    (0xe02e, KEY_VOLUMEDOWN),
+    (0xe030, KEY_VOLUMEUP),
+    (0xe035, KEY_KPSLASH),
+    (0xe037, KEY_SYSRQ),
+    (0xe038, KEY_RIGHTALT),
+    (0xe047, KEY_HOME),
+    (0xe048, KEY_UP),
+    (0xe049, KEY_PAGEUP),
+    (0xe04b, KEY_LEFT),
+    (0xe04d, KEY_RIGHT),
+    (0xe04f, KEY_END),
+    (0xe050, KEY_DOWN),
+    (0xe051, KEY_PAGEDOWN),
+    (0xe052, KEY_INSERT),
+    (0xe053, KEY_DELETE),
+    (0xe05b, KEY_LEFTMETA),
+    (0xe05c, KEY_RIGHTMETA),
+    (0xe05d, KEY_COMPOSE),
+    (0xe05e, KEY_POWER),
+    (0xe05f, KEY_SLEEP),
+    (0xe063, KEY_WAKEUP),
+    (0xe11d, KEY_PAUSE),
+];
+
+/// Mapping from [`MouseData::button_mask`] bits to Linux button codes.
+const BUTTONS: &[(u8, u16)] = &[(0x1, BTN_LEFT), (0x2, BTN_MIDDLE), (0x4, BTN_RIGHT)];
+
+const WHEEL_UP: u8 = 0x8;
+const WHEEL_DOWN: u8 = 0x10;
+
+/// Converts a set 1 scancode to a Linux key code.
+fn keyboard_code(scancode: u16) -> Option<u16> {
+    match scancode {
+        // Linux key codes match the set 1 scancodes for the base keys, with
+        // the exception of the unassigned codes 0x54 and 0x55.
+        0x54 | 0x55 => None,
+        1..=KEY_F12 => Some(scancode),
+        _ => EXTENDED_KEYS
+            .iter()
+            .find(|&&(extended, _)| extended == scancode)
+            .map(|&(_, code)| code),
+    }
+}
+
+/// An input source for a virtio input device.
+enum Source {
+    Keyboard(Box<dyn InputSource<KeyboardData>>),
+    Mouse(Box<dyn InputSource<MouseData>>),
+}
+
+/// Translates input into virtio input events.
+struct Translator {
+    kind: InputKind,
+    last_mouse: Option<MouseData>,
+}
+
+impl Translator {
+    fn keyboard(&mut self, data: KeyboardData, events: &mut Vec<VirtioInputEvent>) {
+        let Some(code) = keyboard_code(data.code) else {
+            tracing::debug!(code = data.code, "unsupported scancode");
+            return;
+        };
+        events.push(event(EV_KEY, code, data.make.into()));
+    }
+
+    fn mouse(&mut self, data: MouseData, events: &mut Vec<VirtioInputEvent>) {
+        let last = self.last_mouse.replace(data);
+        let last_mask = last.map_or(0, |last| last.button_mask);
+        match self.kind {
+            InputKind::Tablet => {
+                if last.map(|last| last.x) != Some(data.x) {
+                    events.push(event(EV_ABS, ABS_X, data.x.into()));
+                }
+                if last.map(|last| last.y) != Some(data.y) {
+                    events.push(event(EV_ABS, ABS_Y, data.y.into()));
+                }
+            }
+            InputKind::Mouse => {
+                if let Some(last) = last {
+                    let dx = (i32::from(data.x) - i32::from(last.x)) / REL_DIVISOR;
+                    let dy = (i32::from(data.y) - i32::from(last.y)) / REL_DIVISOR;
+                    if dx != 0 {
+                        events.push(event(EV_REL, REL_X, dx as u32));
+                    }
+                    if dy != 0 {
+                        events.push(event(EV_REL, REL_Y, dy as u32));
+                    }
+                    // Keep the remainder for the next movement.
+                    self.last_mouse = Some(MouseData {
+                        x: (i32::from(last.x) + dx * REL_DIVISOR) as u16,
+                        y: (i32::from(last.y) + dy * REL_DIVISOR) as u16,
+                        ..data
+                    });
+                }
+            }
+            InputKind::Keyboard => unreachable!(),
+        }
+        for &(mask, code) in BUTTONS {
+            if (data.button_mask ^ last_mask) & mask != 0 {
+                events.push(event(EV_KEY, code, (data.button_mask & mask != 0).into()));
+            }
+        }
+        // Wheel "buttons" are reported as a press and release per notch.
+        let pressed = data.button_mask & !last_mask;
+        if pressed & WHEEL_UP != 0 {
+            events.push(event(EV_REL, REL_WHEEL, 1));
+        }
+        if pressed & WHEEL_DOWN != 0 {
+            events.push(event(EV_REL, REL_WHEEL, -1i32 as u32));
+        }
+    }
+}
+
+fn event(event_type: u16, code: u16, value: u32) -> VirtioInputEvent {
+    VirtioInputEvent {
+        event_type,
+        code,
+        value,
+    }
+}
+
+/// The device configuration space, `virtio_input_config`.
+struct InputConfig {
+    kind: InputKind,
+    select: u8,
+    subsel: u8,
+    data: Vec<u8>,
+}
+
+impl InputConfig {
+    fn new(kind: InputKind) -> Self {
+        Self {
+            kind,
+            select: VIRTIO_INPUT_CFG_UNSET,
+            subsel: 0,
+            data: Vec::new(),
+        }
+    }
+
+    fn read_u32(&self, offset: u16) -> u32 {
+        match offset {
+            0 => {
+                u32::from(self.select)
+                    | u32::from(self.subsel) << 8
+                    | (self.data.len() as u32) << 16
+            }
+            offset if offset >= CONFIG_DATA_OFFSET => {
+                let offset = usize::from(offset - CONFIG_DATA_OFFSET);
+                let mut value = [0; 4];
+                for (i, v) in value.iter_mut().enumerate() {
+                    *v = self.data.get(offset + i).copied().unwrap_or(0);
+                }
+                u32::from_le_bytes(value)
+            }
+            _ => 0,
+        }
+    }
+
+    fn write_u32(&mut self, offset: u16, val: u32) {
+        // The size field is read only, and the rest of the first dword is
+        // reserved.
+        if offset == 0 {
+            self.select = val as u8;
+            self.subsel = (val >> 8) as u8;
+            self.data = self.data();
+            self.data.truncate(CONFIG_DATA_SIZE);
+        }
+    }
+
+    /// Computes the configuration data for the current `select` and `subsel`
+    /// values.
+    fn data(&self) -> Vec<u8> {
+        match self.select {
+            VIRTIO_INPUT_CFG_ID_NAME if self.subsel == 0 => self.kind.name().as_bytes().to_vec(),
+            VIRTIO_INPUT_CFG_ID_DEVIDS if self.subsel == 0 => VirtioInputDevIds {
+                bustype: BUS_VIRTUAL,
+                vendor: 0,
+                product: self.kind.product(),
+                version: 1,
+            }
+            .as_bytes()
+            .to_vec(),
+            VIRTIO_INPUT_CFG_EV_BITS => {
+                let mut bitmap = Vec::new();
+                for code in self.kind.event_codes(self.subsel.into()) {
+                    let byte = usize::from(code / 8);
+                    if bitmap.len() <= byte {
+                        bitmap.resize(byte + 1, 0);
+                    }
+                    bitmap[byte] |= 1 << (code % 8);
+                }
+                bitmap
+            }
+            VIRTIO_INPUT_CFG_ABS_INFO if self.kind == InputKind::Tablet => {
+                match u16::from(self.subsel) {
+                    ABS_X | ABS_Y => VirtioInputAbsInfo {
+                        min: 0,
+                        max: ABS_MAX,
+                        fuzz: 0,
+                        flat: 0,
+                        res: 0,
+                    }
+                    .as_bytes()
+                    .to_vec(),
+                    _ => Vec::new(),
+                }
+            }
+            VIRTIO_INPUT_CFG_UNSET
+            | VIRTIO_INPUT_CFG_ID_SERIAL
+            | VIRTIO_INPUT_CFG_PROP_BITS
+            | VIRTIO_INPUT_CFG_ID_NAME
+            | VIRTIO_INPUT_CFG_ID_DEVIDS
+            | VIRTIO_INPUT_CFG_ABS_INFO => Vec::new(),
+            select => {
+                tracing::debug!(select, "unsupported config select");
+                Vec::new()
+            }
+        }
+    }
+}
+
+/// The state of the input source across device enables and disables.
+enum SourceState {
+    Idle(Source),
+    Running {
+        stop: mesh::OneshotSender<()>,
+        task: Task<Source>,
+    },
+    Stopping(Task<Source>),
+    Invalid,
+}
+
+/// A virtio input device.
+pub struct Device {
+    driver: VmTaskDriver,
+    memory: GuestMemory,
+    kind: InputKind,
+    config: InputConfig,
+    source: SourceState,
+}
+
+impl Device {
+    /// Returns a new keyboard device.
+    pub fn keyboard(
+        driver_source: &VmTaskDriverSource,
+        memory: GuestMemory,
+        source: Box<dyn InputSource<KeyboardData>>,
+    ) -> Self {
+        Self::new(
+            driver_source,
+            memory,
+            InputKind::Keyboard,
+            Source::Keyboard(source),
+        )
+    }
+
+    /// Returns a new mouse or tablet device.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `kind` is [`InputKind::Keyboard`].
+    pub fn mouse(
+        driver_source: &VmTaskDriverSource,
+        memory: GuestMemory,
+        kind: InputKind,
+        source: Box<dyn InputSource<MouseData>>,
+    ) -> Self {
+        assert_ne!(kind, InputKind::Keyboard);
+        Self::new(driver_source, memory, kind, Source::Mouse(source))
+    }
+
+    fn new(
+        driver_source: &VmTaskDriverSource,
+        memory: GuestMemory,
+        kind: InputKind,
+        source: Source,
+    ) -> Self {
+        Self {
+            driver: driver_source.simple(),
+            memory,
+            kind,
+            config: InputConfig::new(kind),
+            source: SourceState::Idle(source),
+        }
+    }
+
+    fn start(&mut self, resources: Resources) -> anyhow::Result<()> {
+        self.disable();
+        let mut queues = resources.queues.into_iter();
+        let (Some(event_queue), Some(status_queue)) = (queues.next(), queues.next()) else {
+            anyhow::bail!("missing queues");
+        };
+        if !event_queue.params.enable {
+            return Ok(());
+        }
+        let event_queue = self.queue(resources.features, event_queue)?;
+        let status_queue = if status_queue.params.enable {
+            Some(self.queue(resources.features, status_queue)?)
+        } else {
+            None
+        };
+
+        let (stop_send, stop_recv) = mesh::oneshot();
+        let previous = std::mem::replace(&mut self.source, SourceState::Invalid);
+        let mut worker = Worker {
+            memory: self.memory.clone(),
+            translator: Translator {
+                kind: self.kind,
+                last_mouse: None,
+            },
+            event_queue,
+            status_queue,
+        };
+        let task = self.driver.spawn("virtio-input", async move {
+            let source = match previous {
+                SourceState::Idle(source) => source,
+                SourceState::Stopping(task) => task.await,
+                SourceState::Running { .. } | SourceState::Invalid => unreachable!(),
+            };
+            worker.run(source, stop_recv).await
+        });
+        self.source = SourceState::Running {
+            stop: stop_send,
+            task,
+        };
+        Ok(())
+    }
+
+    fn queue(&self, features: u64, resources: QueueResources) -> anyhow::Result<VirtioQueue> {
+        let queue_event = PolledWait::new(&self.driver, resources.event)?;
+        let queue = VirtioQueue::new(
+            features,
+            resources.params,
+            self.memory.clone(),
+            resources.notify,
+            queue_event,
+        )?;
+        Ok(queue)
+    }
+}
+
+impl VirtioDevice for Device {
+    fn traits(&self) -> DeviceTraits {
+        DeviceTraits {
+            device_id: VIRTIO_INPUT_DEVICE_ID,
+            device_features: 0,
+            max_queues: 2,
+            device_register_length: u32::from(CONFIG_DATA_OFFSET) + CONFIG_DATA_SIZE as u32,
+            shared_memory: DeviceTraitsSharedMemory { id: 0, size: 0 },
+        }
+    }
+
+    fn read_registers_u32(&self, offset: u16) -> u32 {
+        self.config.read_u32(offset)
+    }
+
+    fn write_registers_u32(&mut self, offset: u16, val: u32) {
+        self.config.write_u32(offset, val)
+    }
+
+    fn enable(&mut self, resources: Resources) {
+        if let Err(err) = self.start(resources) {
+            tracing::error!(
+                error = err.as_ref() as &dyn std::error::Error,
+                "failed to start virtio-input queues"
+            );
+        }
+    }
+
+    fn disable(&mut self) {
+        self.source = match std::mem::replace(&mut self.source, SourceState::Invalid) {
+            SourceState::Running { stop, task } => {
+                stop.send(());
+                SourceState::Stopping(task)
+            }
+            state => state,
+        };
+    }
+}
+
+struct Worker {
+    memory: GuestMemory,
+    translator: Translator,
+    event_queue: VirtioQueue,
+    status_queue: Option<VirtioQueue>,
+}
+
+enum WorkerEvent {
+    Stop,
+    Keyboard(KeyboardData),
+    Mouse(MouseData),
+    Status,
+}
+
+impl Worker {
+    /// Runs the device until `stop` is signaled, returning the input source
+    /// so that it can be used again after the device is re-enabled.
+    async fn run(&mut self, mut source: Source, stop: mesh::OneshotReceiver<()>) -> Source {
+        let mut stop = pin!(stop);
+        set_active(&mut source, true).await;
+        if let Err(err) = self.process(&mut source, stop.as_mut()).await {
+            tracing::error!(
+                error = &err as &dyn std::error::Error,
+                "virtio-input queue failure"
+            );
+            let _ = stop.await;
+        }
+        set_active(&mut source, false).await;
+        source
+    }
+
+    async fn process(
+        &mut self,
+        source: &mut Source,
+        mut stop: std::pin::Pin<&mut mesh::OneshotReceiver<()>>,
+    ) -> Result<(), std::io::Error> {
+        let mut events = Vec::new();
+        loop {
+            let stop_fut = async {
+                let _ = stop.as_mut().await;
+                WorkerEvent::Stop
+            };
+            let input = async {
+                match source {
+                    Source::Keyboard(source) => match source.next().await {
+                        Some(data) => WorkerEvent::Keyboard(data),
+                        None => std::future::pending().await,
+                    },
+                    Source::Mouse(source) => match source.next().await {
+                        Some(data) => WorkerEvent::Mouse(data),
+                        None => std::future::pending().await,
+                    },
+                }
+            };
+            let status = async {
+                match &mut self.status_queue {
+                    Some(queue) => match queue.next().await {
+                        // The device reports no LEDs, so there is no status
+                        // to act on. Drop the buffer to return it to the
+                        // guest.
+                        Some(Ok(_work)) => WorkerEvent::Status,
+                        Some(Err(err)) => {
+                            tracing::warn!(
+                                error = &err as &dyn std::error::Error,
+                                "virtio-input status queue failure"
+                            );
+                            std::future::pending().await
+                        }
+                        None => std::future::pending().await,
+                    },
+                    None => std::future::pending().await,
+                }
+            };
+            match (stop_fut, input, status).race().await {
+                WorkerEvent::Stop => break,
+                WorkerEvent::Keyboard(data) => self.translator.keyboard(data, &mut events),
+                WorkerEvent::Mouse(data) => self.translator.mouse(data, &mut events),
+                WorkerEvent::Status => {}
+            }
+            if events.is_empty() {
+                continue;
+            }
+            events.push(event(EV_SYN, SYN_REPORT, 0));
+            for event in events.drain(..) {
+                let work = (
+                    async {
+                        let _ = stop.as_mut().await;
+                        None
+                    },
+                    async { Some(self.event_queue.next().await) },
+                )
+                    .race()
+                    .await;
+                let Some(work) = work else {
+                    return Ok(());
+                };
+                let mut work = work.expect("queue will never complete")?;
+                match work.write(&self.memory, event.as_bytes()) {
+                    Ok(()) => work.complete(size_of::<VirtioInputEvent>() as u32),
+                    Err(err) => {
+                        tracing::warn!(
+                            error = &err as &dyn std::error::Error,
+                            "failed to write virtio-input event"
+                        );
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+async fn set_active(source: &mut Source, active: bool) {
+    match source {
+        Source::Keyboard(source) => source.set_active(active).await,
+        Source::Mouse(source) => source.set_active(active).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mouse(kind: InputKind, data: &[(u8, u16, u16)]) -> Vec<VirtioInputEvent> {
+        let mut translator = Translator {
+            kind,
+            last_mouse: None,
+        };
+        let mut events = Vec::new();
+        for &(button_mask, x, y) in data {
+            translator.mouse(MouseData { button_mask, x, y }, &mut events);
+        }
+        events
+    }
+
+    #[test]
+    fn test_keyboard_codes() {
+        assert_eq!(keyboard_code(0x1e), Some(30)); // KEY_A
+        assert_eq!(keyboard_code(0x58), Some(KEY_F12));
+        assert_eq!(keyboard_code(0xe048), Some(KEY_UP));
+        assert_eq!(keyboard_code(0x54), None);
+        assert_eq!(keyboard_code(0xe0ff), None);
+    }
+
+    #[test]
+    fn test_tablet() {
+        let events = mouse(
+            InputKind::Tablet,
+            &[(0, 100, 200), (1, 100, 300), (8, 100, 300)],
+        );
+        assert_eq!(
+            events,
+            [
+                event(EV_ABS, ABS_X, 100),
+                event(EV_ABS, ABS_Y, 200),
+                event(EV_ABS, ABS_Y, 300),
+                event(EV_KEY, BTN_LEFT, 1),
+                event(EV_KEY, BTN_LEFT, 0),
+                event(EV_REL, REL_WHEEL, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_mouse() {
+        let events = mouse(
+            InputKind::Mouse,
+            &[
+                (0, 1000, 1000),
+                (0, 1000 + REL_DIVISOR as u16 * 3 / 2, 1000),
+                (0, 1000 + REL_DIVISOR as u16 * 2, 1000 - REL_DIVISOR as u16),
+            ],
+        );
+        assert_eq!(
+            events,
+            [
+                event(EV_REL, REL_X, 1),
+                event(EV_REL, REL_X, 1),
+                event(EV_REL, REL_Y, -1i32 as u32),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_config() {
+        let device_bits = |kind: InputKind, ty: u16| {
+            let mut config = InputConfig::new(kind);
+            config.write_u32(0, u32::from(VIRTIO_INPUT_CFG_EV_BITS) | u32::from(ty) << 8);
+            let size = config.read_u32(0) >> 16;
+            (0..size as u16)
+                .step_by(4)
+                .flat_map(|i| config.read_u32(CONFIG_DATA_OFFSET + i).to_le_bytes())
+                .take(size as usize)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(device_bits(InputKind::Mouse, EV_REL), [0x03, 0x01]);
+        assert_eq!(device_bits(InputKind::Tablet, EV_ABS), [0x03]);
+        assert!(device_bits(InputKind::Keyboard, EV_ABS).is_empty());
+    }
+}
@@ -0,0 +1,107 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Defines the resource resolver for virtio-input devices.
+
+use crate::Device;
+use crate::InputKind;
+use async_trait::async_trait;
+use thiserror::Error;
+use virtio::resolve::ResolvedVirtioDevice;
+use virtio::resolve::VirtioResolveInput;
+use virtio_resources::input::VirtioKeyboardHandle;
+use virtio_resources::input::VirtioMouseHandle;
+use virtio_resources::input::VirtioTabletHandle;
+use vm_resource::declare_static_async_resolver;
+use vm_resource::kind::VirtioDeviceHandle;
+use vm_resource::AsyncResolveResource;
+use vm_resource::ResolveError;
+use vm_resource::ResourceResolver;
+
+/// Resolver for virtio-input devices.
+pub struct VirtioInputResolver;
+
+declare_static_async_resolver! {
+    VirtioInputResolver,
+    (VirtioDeviceHandle, VirtioKeyboardHandle),
+    (VirtioDeviceHandle, VirtioMouseHandle),
+    (VirtioDeviceHandle, VirtioTabletHandle),
+}
+
+/// Error returned when resolving virtio-input device handles.
+#[derive(Debug, Error)]
+pub enum ResolveVirtioInputError {
+    /// The input source could not be resolved.
+    #[error("failed to resolve input source")]
+    InputSource(#[source] ResolveError),
+}
+
+#[async_trait]
+impl AsyncResolveResource<VirtioDeviceHandle, VirtioKeyboardHandle> for VirtioInputResolver {
+    type Output = ResolvedVirtioDevice;
+    type Error = ResolveVirtioInputError;
+
+    async fn resolve(
+        &self,
+        resolver: &ResourceResolver,
+        resource: VirtioKeyboardHandle,
+        input: VirtioResolveInput<'_>,
+    ) -> Result<Self::Output, Self::Error> {
+        let source = resolver
+            .resolve(resource.source, "virtio-keyboard")
+            .await
+            .map_err(ResolveVirtioInputError::InputSource)?;
+        let device = Device::keyboard(input.driver_source, input.guest_memory.clone(), source.0);
+        Ok(device.into())
+    }
+}
+
+#[async_trait]
+impl AsyncResolveResource<VirtioDeviceHandle, VirtioMouseHandle> for VirtioInputResolver {
+    type Output = ResolvedVirtioDevice;
+    type Error = ResolveVirtioInputError;
+
+    async fn resolve(
+        &self,
+        resolver: &ResourceResolver,
+        resource: VirtioMouseHandle,
+        input: VirtioResolveInput<'_>,
+    ) -> Result<Self::Output, Self::Error> {
+        let source = resolver
+            .resolve(resource.source, "virtio-mouse")
+            .await
+            .map_err(ResolveVirtioInputError::InputSource)?;
+        let device = Device::mouse(
+            input.driver_source,
+            input.guest_memory.clone(),
+            InputKind::Mouse,
+            source.0,
+        );
+        Ok(device.into())
+    }
+}
+
+#[async_trait]
+impl AsyncResolveResource<VirtioDeviceHandle, VirtioTabletHandle> for VirtioInputResolver {
+    type Output = ResolvedVirtioDevice;
+    type Error = ResolveVirtioInputError;
+
+    async fn resolve(
+        &self,
+        resolver: &ResourceResolver,
+        resource: VirtioTabletHandle,
+        input: VirtioResolveInput<'_>,
+    ) -> Result<Self::Output, Self::Error> {
+        let source = resolver
+            .resolve(resource.source, "virtio-tablet")
+            .await
+            .map_err(ResolveVirtioInputError::InputSource)?;
+        let device = Device::mouse(
+            input.driver_source,
+            input.guest_memory.clone(),
+            InputKind::Tablet,
+            source.0,
+        );
+        Ok(device.into())
+    }
+}
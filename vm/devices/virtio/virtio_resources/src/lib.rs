@@ -81,6 +81,59 @@ impl ResourceId<VirtioDeviceHandle> for VirtioPmemHandle {
     }
 }
 
+pub mod gpu {
+    use mesh::MeshPayload;
+    use vm_resource::kind::FramebufferHandleKind;
+    use vm_resource::kind::VirtioDeviceHandle;
+    use vm_resource::Resource;
+    use vm_resource::ResourceId;
+
+    #[derive(MeshPayload)]
+    pub struct VirtioGpuHandle {
+        pub framebuffer: Resource<FramebufferHandleKind>,
+    }
+
+    impl ResourceId<VirtioDeviceHandle> for VirtioGpuHandle {
+        const ID: &'static str = "virtio-gpu";
+    }
+}
+
+pub mod input {
+    use mesh::MeshPayload;
+    use vm_resource::kind::KeyboardInputHandleKind;
+    use vm_resource::kind::MouseInputHandleKind;
+    use vm_resource::kind::VirtioDeviceHandle;
+    use vm_resource::Resource;
+    use vm_resource::ResourceId;
+
+    #[derive(MeshPayload)]
+    pub struct VirtioKeyboardHandle {
+        pub source: Resource<KeyboardInputHandleKind>,
+    }
+
+    impl ResourceId<VirtioDeviceHandle> for VirtioKeyboardHandle {
+        const ID: &'static str = "virtio-keyboard";
+    }
+
+    #[derive(MeshPayload)]
+    pub struct VirtioMouseHandle {
+        pub source: Resource<MouseInputHandleKind>,
+    }
+
+    impl ResourceId<VirtioDeviceHandle> for VirtioMouseHandle {
+        const ID: &'static str = "virtio-mouse";
+    }
+
+    #[derive(MeshPayload)]
+    pub struct VirtioTabletHandle {
+        pub source: Resource<MouseInputHandleKind>,
+    }
+
+    impl ResourceId<VirtioDeviceHandle> for VirtioTabletHandle {
+        const ID: &'static str = "virtio-tablet";
+    }
+}
+
 pub mod net {
     use mesh::MeshPayload;
     use net_backend_resources::mac_address::MacAddress;
@@ -1099,6 +1099,24 @@ pub fn translate_gva(&self, gva: u64) -> Result<kvm_translation> {
         Ok(())
     }
 
+    /// Sets the guest debugging state: `control` bits `KVM_GUESTDBG_*` and the
+    /// breakpoint and watchpoint value and control registers.
+    #[cfg(target_arch = "aarch64")]
+    pub fn set_guest_debug(&self, control: u32, arch: &kvm_guest_debug_arch) -> Result<()> {
+        let debug = kvm_guest_debug {
+            control,
+            pad: 0,
+            arch: *arch,
+        };
+
+        // SAFETY: Calling IOCTL as documented, with no special requirements.
+        unsafe {
+            ioctl::kvm_set_guest_debug(self.get().vcpu.as_raw_fd(), &debug)
+                .map_err(Error::GetRegs)?;
+        }
+        Ok(())
+    }
+
     /// # Safety
     ///
     /// `addr` must point to the appropriate input for the attribute being
@@ -1245,10 +1263,12 @@ pub fn run(&mut self) -> Result<Exit<'_>, Error> {
                 // SAFETY: no other references to this data.
                 let debug = unsafe { &self.run_data().__bindgen_anon_1.debug };
 
-                #[cfg(not(target_arch = "x86_64"))]
+                #[cfg(target_arch = "aarch64")]
                 {
-                    _ = debug;
-                    todo!("debug exit on non-x86_64")
+                    Exit::Debug {
+                        hsr: debug.arch.hsr,
+                        far: debug.arch.far,
+                    }
                 }
 
                 #[cfg(target_arch = "x86_64")]
@@ -1468,12 +1488,20 @@ pub enum Exit<'a> {
         result: &'a mut u64,
         params: [u64; 2],
     },
+    #[cfg(target_arch = "x86_64")]
     Debug {
         exception: u32,
         pc: u64,
         dr6: u64,
         dr7: u64,
     },
+    #[cfg(target_arch = "aarch64")]
+    Debug {
+        /// The low 32 bits of ESR_EL2.
+        hsr: u32,
+        /// The faulting virtual address, for watchpoints.
+        far: u64,
+    },
     Eoi {
         irq: u8,
     },
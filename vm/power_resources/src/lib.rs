@@ -53,4 +53,10 @@ pub enum PowerRequest {
         /// The VP that caused the triple fault.
         vp: u32,
     },
+    /// The guest OS reported that it panicked.
+    GuestPanic {
+        /// The guest has a crash kernel loaded and will try to collect a
+        /// crash dump itself.
+        crash_loaded: bool,
+    },
 }
@@ -0,0 +1,591 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Builder for SMBIOS 3.x tables, for loaders that must provide DMI
+//! information to the guest without firmware to generate it.
+
+#![forbid(unsafe_code)]
+
+pub mod spec;
+
+use guid::Guid;
+use memory_range::MemoryRange;
+use spec::structure_type;
+use spec::Header;
+use zerocopy::AsBytes;
+
+/// Type 0 (BIOS information) fields.
+#[derive(Debug, Default, Clone)]
+pub struct BiosInfo<'a> {
+    pub vendor: &'a str,
+    pub version: &'a str,
+    pub release_date: &'a str,
+}
+
+/// Type 1 (system information) fields.
+#[derive(Debug, Default, Clone)]
+pub struct SystemInfo<'a> {
+    pub manufacturer: &'a str,
+    pub product_name: &'a str,
+    pub version: &'a str,
+    pub serial_number: &'a str,
+    pub uuid: Guid,
+    pub sku_number: &'a str,
+    pub family: &'a str,
+}
+
+/// Type 2 (baseboard information) fields.
+#[derive(Debug, Default, Clone)]
+pub struct BaseboardInfo<'a> {
+    pub manufacturer: &'a str,
+    pub product: &'a str,
+    pub version: &'a str,
+    pub serial_number: &'a str,
+    pub asset_tag: &'a str,
+}
+
+/// Type 3 (system enclosure) fields.
+#[derive(Debug, Default, Clone)]
+pub struct ChassisInfo<'a> {
+    pub manufacturer: &'a str,
+    pub version: &'a str,
+    pub serial_number: &'a str,
+    pub asset_tag: &'a str,
+    pub sku_number: &'a str,
+}
+
+/// Type 4 (processor information) fields, for one socket.
+#[derive(Debug, Default, Clone)]
+pub struct ProcessorInfo<'a> {
+    pub socket_designation: &'a str,
+    pub manufacturer: &'a str,
+    pub version: &'a str,
+    /// The processor family, from the `processor_family2` encoding.
+    pub family: u16,
+    /// The raw processor ID (on x86, CPUID leaf 1 EAX and EDX).
+    pub processor_id: u64,
+    pub core_count: u16,
+    pub thread_count: u16,
+}
+
+/// The strings of a single structure.
+#[derive(Default)]
+struct StringSet {
+    data: Vec<u8>,
+    count: u8,
+}
+
+impl StringSet {
+    /// Adds `s`, returning its string number. Empty strings are not stored
+    /// and return 0. Strings are truncated at any embedded NUL.
+    fn add(&mut self, s: &str) -> u8 {
+        let s = s.as_bytes();
+        let s = &s[..s.iter().position(|&c| c == 0).unwrap_or(s.len())];
+        if s.is_empty() || self.count == u8::MAX {
+            return 0;
+        }
+        self.data.extend_from_slice(s);
+        self.data.push(0);
+        self.count += 1;
+        self.count
+    }
+}
+
+/// Builds an SMBIOS structure table.
+///
+/// Structures are assigned sequential handles as they are added, and the
+/// `add_*` methods return the handle so that later structures can refer to
+/// it.
+pub struct SmbiosBuilder {
+    data: Vec<u8>,
+    next_handle: u16,
+}
+
+impl Default for SmbiosBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SmbiosBuilder {
+    /// Returns a new builder with an empty table.
+    pub fn new() -> Self {
+        Self {
+            data: Vec::new(),
+            next_handle: 0,
+        }
+    }
+
+    fn header<T>(&mut self, typ: u8) -> Header {
+        let handle = self.next_handle;
+        self.next_handle += 1;
+        Header {
+            typ,
+            length: size_of::<T>() as u8,
+            handle,
+        }
+    }
+
+    fn push<T: AsBytes>(&mut self, structure: &T, strings: StringSet) {
+        self.data.extend_from_slice(structure.as_bytes());
+        if strings.count == 0 {
+            // An empty string set is still terminated by two NULs.
+            self.data.extend_from_slice(&[0, 0]);
+        } else {
+            self.data.extend_from_slice(&strings.data);
+            self.data.push(0);
+        }
+    }
+
+    /// Adds a type 0 (BIOS information) structure.
+    pub fn add_bios(&mut self, info: &BiosInfo<'_>) -> u16 {
+        let mut strings = StringSet::default();
+        let structure = spec::BiosInformation {
+            header: self.header::<spec::BiosInformation>(structure_type::BIOS_INFORMATION),
+            vendor: strings.add(info.vendor),
+            version: strings.add(info.version),
+            starting_segment: 0xe800,
+            release_date: strings.add(info.release_date),
+            rom_size: 0,
+            characteristics: spec::BIOS_CHARACTERISTICS_NOT_SUPPORTED,
+            characteristics_ext1: spec::BIOS_CHARACTERISTICS_EXT1_ACPI,
+            characteristics_ext2: spec::BIOS_CHARACTERISTICS_EXT2_TCD
+                | spec::BIOS_CHARACTERISTICS_EXT2_VM,
+            major_release: 0xff,
+            minor_release: 0xff,
+            ec_major_release: 0xff,
+            ec_minor_release: 0xff,
+            extended_rom_size: 0,
+        };
+        self.push(&structure, strings);
+        structure.header.handle
+    }
+
+    /// Adds a type 1 (system information) structure.
+    pub fn add_system(&mut self, info: &SystemInfo<'_>) -> u16 {
+        let mut strings = StringSet::default();
+        let structure = spec::SystemInformation {
+            header: self.header::<spec::SystemInformation>(structure_type::SYSTEM_INFORMATION),
+            manufacturer: strings.add(info.manufacturer),
+            product_name: strings.add(info.product_name),
+            version: strings.add(info.version),
+            serial_number: strings.add(info.serial_number),
+            uuid: info.uuid.as_bytes().try_into().unwrap(),
+            wake_up_type: spec::WAKE_UP_TYPE_POWER_SWITCH,
+            sku_number: strings.add(info.sku_number),
+            family: strings.add(info.family),
+        };
+        self.push(&structure, strings);
+        structure.header.handle
+    }
+
+    /// Adds a type 2 (baseboard information) structure, contained in the
+    /// chassis with handle `chassis`.
+    pub fn add_baseboard(&mut self, info: &BaseboardInfo<'_>, chassis: u16) -> u16 {
+        let mut strings = StringSet::default();
+        let structure = spec::BaseboardInformation {
+            header: self
+                .header::<spec::BaseboardInformation>(structure_type::BASEBOARD_INFORMATION),
+            manufacturer: strings.add(info.manufacturer),
+            product: strings.add(info.product),
+            version: strings.add(info.version),
+            serial_number: strings.add(info.serial_number),
+            asset_tag: strings.add(info.asset_tag),
+            feature_flags: spec::BASEBOARD_FEATURE_HOSTING_BOARD,
+            location_in_chassis: 0,
+            chassis_handle: chassis,
+            board_type: spec::BASEBOARD_TYPE_MOTHERBOARD,
+            contained_object_handles: 0,
+        };
+        self.push(&structure, strings);
+        structure.header.handle
+    }
+
+    /// Adds a type 3 (system enclosure) structure.
+    pub fn add_chassis(&mut self, info: &ChassisInfo<'_>) -> u16 {
+        let mut strings = StringSet::default();
+        let structure = spec::SystemEnclosure {
+            header: self.header::<spec::SystemEnclosure>(structure_type::SYSTEM_ENCLOSURE),
+            manufacturer: strings.add(info.manufacturer),
+            typ: spec::ENCLOSURE_TYPE_OTHER,
+            version: strings.add(info.version),
+            serial_number: strings.add(info.serial_number),
+            asset_tag: strings.add(info.asset_tag),
+            boot_up_state: spec::ENCLOSURE_STATE_SAFE,
+            power_supply_state: spec::ENCLOSURE_STATE_SAFE,
+            thermal_state: spec::ENCLOSURE_STATE_SAFE,
+            security_status: spec::ENCLOSURE_SECURITY_UNKNOWN,
+            oem_defined: 0,
+            height: 0,
+            power_cords: 0,
+            contained_element_count: 0,
+            contained_element_record_length: 0,
+            sku_number: strings.add(info.sku_number),
+        };
+        self.push(&structure, strings);
+        structure.header.handle
+    }
+
+    /// Adds a type 4 (processor information) structure.
+    pub fn add_processor(&mut self, info: &ProcessorInfo<'_>) -> u16 {
+        let mut strings = StringSet::default();
+        // The 8-bit counts saturate, with the real values in the 16-bit
+        // fields.
+        let count8 = |n: u16| n.min(0xff) as u8;
+        let mut characteristics = spec::PROCESSOR_CHARACTERISTICS_64BIT;
+        if info.core_count > 1 {
+            characteristics |= spec::PROCESSOR_CHARACTERISTICS_MULTI_CORE;
+        }
+        let structure = spec::ProcessorInformation {
+            header: self
+                .header::<spec::ProcessorInformation>(structure_type::PROCESSOR_INFORMATION),
+            socket_designation: strings.add(info.socket_designation),
+            processor_type: spec::PROCESSOR_TYPE_CENTRAL,
+            processor_family: if info.family < spec::PROCESSOR_FAMILY_USE_FAMILY2.into() {
+                info.family as u8
+            } else {
+                spec::PROCESSOR_FAMILY_USE_FAMILY2
+            },
+            processor_manufacturer: strings.add(info.manufacturer),
+            processor_id: info.processor_id,
+            processor_version: strings.add(info.version),
+            voltage: 0,
+            external_clock: 0,
+            max_speed: 0,
+            current_speed: 0,
+            status: spec::PROCESSOR_STATUS_ENABLED,
+            processor_upgrade: spec::PROCESSOR_UPGRADE_OTHER,
+            l1_cache_handle: 0xffff,
+            l2_cache_handle: 0xffff,
+            l3_cache_handle: 0xffff,
+            serial_number: 0,
+            asset_tag: 0,
+            part_number: 0,
+            core_count: count8(info.core_count),
+            core_enabled: count8(info.core_count),
+            thread_count: count8(info.thread_count),
+            processor_characteristics: characteristics,
+            processor_family2: info.family,
+            core_count2: info.core_count,
+            core_enabled2: info.core_count,
+            thread_count2: info.thread_count,
+        };
+        self.push(&structure, strings);
+        structure.header.handle
+    }
+
+    /// Adds a type 11 (OEM strings) structure.
+    pub fn add_oem_strings(&mut self, oem_strings: &[impl AsRef<str>]) -> u16 {
+        let mut strings = StringSet::default();
+        for s in oem_strings {
+            strings.add(s.as_ref());
+        }
+        let structure = spec::OemStrings {
+            header: self.header::<spec::OemStrings>(structure_type::OEM_STRINGS),
+            count: strings.count,
+        };
+        self.push(&structure, strings);
+        structure.header.handle
+    }
+
+    /// Adds a type 16 (physical memory array) structure describing the RAM
+    /// in `ram`, followed by a type 17 (memory device) and a type 19 (memory
+    /// array mapped address) structure per range.
+    ///
+    /// Returns the handle of the memory array.
+    pub fn add_memory(&mut self, ram: &[MemoryRange]) -> u16 {
+        let total: u64 = ram.iter().map(|range| range.len()).sum();
+        let total_kib = total >> 10;
+        let array = spec::PhysicalMemoryArray {
+            header: self.header::<spec::PhysicalMemoryArray>(structure_type::PHYSICAL_MEMORY_ARRAY),
+            location: spec::MEMORY_ARRAY_LOCATION_OTHER,
+            array_use: spec::MEMORY_ARRAY_USE_SYSTEM,
+            error_correction: spec::MEMORY_ARRAY_ECC_NONE,
+            maximum_capacity: if total_kib < spec::CAPACITY_USE_EXTENDED.into() {
+                total_kib as u32
+            } else {
+                spec::CAPACITY_USE_EXTENDED
+            },
+            error_information_handle: spec::HANDLE_NOT_PROVIDED,
+            number_of_devices: ram.len() as u16,
+            extended_maximum_capacity: if total_kib < spec::CAPACITY_USE_EXTENDED.into() {
+                0
+            } else {
+                total
+            },
+        };
+        self.push(&array, StringSet::default());
+        let array_handle = array.header.handle;
+
+        for (i, range) in ram.iter().enumerate() {
+            let mut strings = StringSet::default();
+            let (size, extended_size) = memory_device_size(range.len());
+            let device = spec::MemoryDevice {
+                header: self.header::<spec::MemoryDevice>(structure_type::MEMORY_DEVICE),
+                physical_memory_array_handle: array_handle,
+                error_information_handle: spec::HANDLE_NOT_PROVIDED,
+                total_width: 64,
+                data_width: 64,
+                size,
+                form_factor: spec::MEMORY_FORM_FACTOR_DIMM,
+                device_set: 0,
+                device_locator: strings.add(&format!("DIMM {i}")),
+                bank_locator: 0,
+                memory_type: spec::MEMORY_TYPE_RAM,
+                type_detail: spec::MEMORY_TYPE_DETAIL_OTHER,
+                speed: 0,
+                manufacturer: 0,
+                serial_number: 0,
+                asset_tag: 0,
+                part_number: 0,
+                attributes: 0,
+                extended_size,
+                configured_memory_speed: 0,
+                minimum_voltage: 0,
+                maximum_voltage: 0,
+                configured_voltage: 0,
+            };
+            self.push(&device, strings);
+        }
+
+        for range in ram {
+            let start_kib = range.start() >> 10;
+            let end_kib = (range.end() - 1) >> 10;
+            let extended = end_kib >= spec::ADDRESS_USE_EXTENDED.into();
+            let mapped = spec::MemoryArrayMappedAddress {
+                header: self.header::<spec::MemoryArrayMappedAddress>(
+                    structure_type::MEMORY_ARRAY_MAPPED_ADDRESS,
+                ),
+                starting_address: if extended {
+                    spec::ADDRESS_USE_EXTENDED
+                } else {
+                    start_kib as u32
+                },
+                ending_address: if extended {
+                    spec::ADDRESS_USE_EXTENDED
+                } else {
+                    end_kib as u32
+                },
+                memory_array_handle: array_handle,
+                partition_width: 1,
+                extended_starting_address: if extended { range.start() } else { 0 },
+                extended_ending_address: if extended { range.end() - 1 } else { 0 },
+            };
+            self.push(&mapped, StringSet::default());
+        }
+
+        array_handle
+    }
+
+    /// Adds a type 32 (system boot information) structure reporting no
+    /// errors.
+    pub fn add_system_boot(&mut self) -> u16 {
+        let structure = spec::SystemBootInformation {
+            header: self
+                .header::<spec::SystemBootInformation>(structure_type::SYSTEM_BOOT_INFORMATION),
+            reserved: [0; 6],
+            boot_status: spec::BOOT_STATUS_NO_ERRORS,
+        };
+        self.push(&structure, StringSet::default());
+        structure.header.handle
+    }
+
+    /// Terminates the table with a type 127 (end-of-table) structure.
+    pub fn build(mut self) -> SmbiosTables {
+        let header = self.header::<Header>(structure_type::END_OF_TABLE);
+        self.push(&header, StringSet::default());
+        SmbiosTables { data: self.data }
+    }
+}
+
+/// Encodes a memory device size into the `size` and `extended_size` fields of
+/// a type 17 structure.
+fn memory_device_size(len: u64) -> (u16, u32) {
+    const MIB: u64 = 1 << 20;
+    if len % MIB != 0 {
+        let kib = len >> 10;
+        if kib < spec::MEMORY_SIZE_KIB.into() {
+            return (spec::MEMORY_SIZE_KIB | kib as u16, 0);
+        }
+    }
+    let mib = len / MIB;
+    if mib < spec::MEMORY_SIZE_USE_EXTENDED.into() {
+        (mib as u16, 0)
+    } else {
+        (
+            spec::MEMORY_SIZE_USE_EXTENDED,
+            mib.try_into().unwrap_or(u32::MAX),
+        )
+    }
+}
+
+/// A built SMBIOS structure table.
+pub struct SmbiosTables {
+    data: Vec<u8>,
+}
+
+impl SmbiosTables {
+    /// The structure table, to be placed in guest memory.
+    pub fn tables(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Returns the 64-bit entry point for the table placed at guest physical
+    /// address `table_address`.
+    pub fn entry_point(&self, table_address: u64) -> spec::EntryPoint64 {
+        let mut entry_point = spec::EntryPoint64 {
+            anchor: *spec::ENTRY_POINT_ANCHOR,
+            checksum: 0,
+            length: size_of::<spec::EntryPoint64>() as u8,
+            major_version: 3,
+            minor_version: 0,
+            docrev: 0,
+            revision: 1,
+            reserved: 0,
+            max_table_size: self.data.len() as u32,
+            table_address,
+        };
+        entry_point.checksum = entry_point
+            .as_bytes()
+            .iter()
+            .fold(0u8, |sum, &b| sum.wrapping_sub(b));
+        entry_point
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zerocopy::FromBytes;
+
+    struct Structure<'a> {
+        header: Header,
+        formatted: &'a [u8],
+        strings: Vec<&'a [u8]>,
+    }
+
+    impl Structure<'_> {
+        fn string(&self, index: u8) -> Option<&[u8]> {
+            (index != 0).then(|| self.strings[index as usize - 1])
+        }
+    }
+
+    fn parse(mut data: &[u8]) -> Vec<Structure<'_>> {
+        let mut structures = Vec::new();
+        loop {
+            let header = Header::read_from_prefix(data).unwrap();
+            let (formatted, rest) = data.split_at(header.length.into());
+            let end = rest.windows(2).position(|w| w == [0, 0]).unwrap();
+            let strings = if end == 0 {
+                Vec::new()
+            } else {
+                rest[..end].split(|&c| c == 0).collect()
+            };
+            data = &rest[end + 2..];
+            structures.push(Structure {
+                header,
+                formatted,
+                strings,
+            });
+            if header.typ == structure_type::END_OF_TABLE {
+                assert!(data.is_empty());
+                break;
+            }
+        }
+        structures
+    }
+
+    #[test]
+    fn test_tables() {
+        let mut builder = SmbiosBuilder::new();
+        builder.add_bios(&BiosInfo {
+            vendor: "vendor",
+            version: "",
+            release_date: "01/01/2024",
+        });
+        builder.add_system(&SystemInfo {
+            serial_number: "ds=nocloud",
+            uuid: Guid::from_static_str("12345678-9abc-def0-1234-56789abcdef0"),
+            ..Default::default()
+        });
+        let chassis = builder.add_chassis(&ChassisInfo::default());
+        builder.add_baseboard(&BaseboardInfo::default(), chassis);
+        builder.add_processor(&ProcessorInfo {
+            core_count: 300,
+            thread_count: 600,
+            family: spec::PROCESSOR_FAMILY_ARMV8,
+            ..Default::default()
+        });
+        builder.add_oem_strings(&["a", "b\0c"]);
+        let array = builder.add_memory(&[
+            MemoryRange::new(0..0xa0000),
+            MemoryRange::new(0x1_0000_0000..0x401_0000_0000),
+        ]);
+        builder.add_system_boot();
+        let tables = builder.build();
+
+        let structures = parse(tables.tables());
+        let types: Vec<_> = structures.iter().map(|s| s.header.typ).collect();
+        assert_eq!(types, [0, 1, 3, 2, 4, 11, 16, 17, 17, 19, 19, 32, 127]);
+        for (i, s) in structures.iter().enumerate() {
+            assert_eq!({ s.header.handle }, i as u16);
+        }
+
+        let bios = spec::BiosInformation::read_from(structures[0].formatted).unwrap();
+        assert_eq!(structures[0].string(bios.vendor), Some(&b"vendor"[..]));
+        assert_eq!(bios.version, 0);
+
+        let system = spec::SystemInformation::read_from(structures[1].formatted).unwrap();
+        assert_eq!(
+            structures[1].string(system.serial_number),
+            Some(&b"ds=nocloud"[..])
+        );
+        assert_eq!(system.uuid[..4], [0x78, 0x56, 0x34, 0x12]);
+
+        let baseboard = spec::BaseboardInformation::read_from(structures[3].formatted).unwrap();
+        assert_eq!({ baseboard.chassis_handle }, chassis);
+
+        let processor = spec::ProcessorInformation::read_from(structures[4].formatted).unwrap();
+        assert_eq!(processor.core_count, 0xff);
+        assert_eq!({ processor.core_count2 }, 300);
+        assert_eq!({ processor.thread_count2 }, 600);
+        assert_eq!(
+            processor.processor_family,
+            spec::PROCESSOR_FAMILY_USE_FAMILY2
+        );
+
+        let oem = spec::OemStrings::read_from(structures[5].formatted).unwrap();
+        assert_eq!(oem.count, 2);
+        assert_eq!(structures[5].strings, [&b"a"[..], &b"b"[..]]);
+
+        let low = spec::MemoryDevice::read_from(structures[7].formatted).unwrap();
+        assert_eq!({ low.physical_memory_array_handle }, array);
+        assert_eq!({ low.size }, spec::MEMORY_SIZE_KIB | 640);
+        let high = spec::MemoryDevice::read_from(structures[8].formatted).unwrap();
+        assert_eq!({ high.size }, spec::MEMORY_SIZE_USE_EXTENDED);
+        assert_eq!({ high.extended_size }, 4 << 20);
+
+        let low = spec::MemoryArrayMappedAddress::read_from(structures[9].formatted).unwrap();
+        assert_eq!({ low.starting_address }, 0);
+        assert_eq!({ low.ending_address }, 639);
+        let high = spec::MemoryArrayMappedAddress::read_from(structures[10].formatted).unwrap();
+        assert_eq!({ high.starting_address }, spec::ADDRESS_USE_EXTENDED);
+        assert_eq!({ high.extended_ending_address }, 0x400_ffff_ffff);
+    }
+
+    #[test]
+    fn test_entry_point() {
+        let tables = SmbiosBuilder::new().build();
+        assert_eq!(tables.tables(), [127, 4, 0, 0, 0, 0]);
+        let entry_point = tables.entry_point(0xf1000);
+        assert_eq!(
+            entry_point
+                .as_bytes()
+                .iter()
+                .fold(0u8, |sum, &b| sum.wrapping_add(b)),
+            0
+        );
+        assert_eq!({ entry_point.max_table_size }, 6);
+        assert_eq!({ entry_point.table_address }, 0xf1000);
+    }
+}
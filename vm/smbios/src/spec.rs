@@ -0,0 +1,343 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Structure definitions from the DMTF SMBIOS reference specification
+//! (DSP0134), version 3.x.
+//!
+//! String fields are one-based indexes into the string set that follows each
+//! structure's formatted area, with zero meaning "no string".
+
+use core::mem::size_of;
+use static_assertions::const_assert_eq;
+use zerocopy::AsBytes;
+use zerocopy::FromBytes;
+use zerocopy::FromZeroes;
+use zerocopy::Unaligned;
+
+/// The anchor string of the 64-bit entry point.
+pub const ENTRY_POINT_ANCHOR: &[u8; 5] = b"_SM3_";
+
+/// The SMBIOS 3.0 (64-bit) entry point structure.
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, AsBytes, FromBytes, FromZeroes, Unaligned)]
+pub struct EntryPoint64 {
+    pub anchor: [u8; 5],
+    pub checksum: u8,
+    pub length: u8,
+    pub major_version: u8,
+    pub minor_version: u8,
+    pub docrev: u8,
+    pub revision: u8,
+    pub reserved: u8,
+    pub max_table_size: u32,
+    pub table_address: u64,
+}
+
+const_assert_eq!(size_of::<EntryPoint64>(), 24);
+
+/// Structure type numbers.
+pub mod structure_type {
+    pub const BIOS_INFORMATION: u8 = 0;
+    pub const SYSTEM_INFORMATION: u8 = 1;
+    pub const BASEBOARD_INFORMATION: u8 = 2;
+    pub const SYSTEM_ENCLOSURE: u8 = 3;
+    pub const PROCESSOR_INFORMATION: u8 = 4;
+    pub const OEM_STRINGS: u8 = 11;
+    pub const PHYSICAL_MEMORY_ARRAY: u8 = 16;
+    pub const MEMORY_DEVICE: u8 = 17;
+    pub const MEMORY_ARRAY_MAPPED_ADDRESS: u8 = 19;
+    pub const SYSTEM_BOOT_INFORMATION: u8 = 32;
+    pub const END_OF_TABLE: u8 = 127;
+}
+
+/// The handle value used for "not provided" handle references.
+pub const HANDLE_NOT_PROVIDED: u16 = 0xfffe;
+
+/// The header common to all structures.
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, AsBytes, FromBytes, FromZeroes, Unaligned)]
+pub struct Header {
+    pub typ: u8,
+    pub length: u8,
+    pub handle: u16,
+}
+
+/// Type 0.
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, AsBytes, FromBytes, FromZeroes, Unaligned)]
+pub struct BiosInformation {
+    pub header: Header,
+    pub vendor: u8,
+    pub version: u8,
+    pub starting_segment: u16,
+    pub release_date: u8,
+    pub rom_size: u8,
+    pub characteristics: u64,
+    pub characteristics_ext1: u8,
+    pub characteristics_ext2: u8,
+    pub major_release: u8,
+    pub minor_release: u8,
+    pub ec_major_release: u8,
+    pub ec_minor_release: u8,
+    pub extended_rom_size: u16,
+}
+
+const_assert_eq!(size_of::<BiosInformation>(), 0x1a);
+
+/// BIOS characteristics: characteristics are not supported.
+pub const BIOS_CHARACTERISTICS_NOT_SUPPORTED: u64 = 1 << 3;
+/// BIOS characteristics extension byte 1: ACPI is supported.
+pub const BIOS_CHARACTERISTICS_EXT1_ACPI: u8 = 1 << 0;
+/// BIOS characteristics extension byte 2: targeted content distribution is
+/// enabled.
+pub const BIOS_CHARACTERISTICS_EXT2_TCD: u8 = 1 << 2;
+/// BIOS characteristics extension byte 2: the system is a virtual machine.
+pub const BIOS_CHARACTERISTICS_EXT2_VM: u8 = 1 << 4;
+
+/// Type 1.
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, AsBytes, FromBytes, FromZeroes, Unaligned)]
+pub struct SystemInformation {
+    pub header: Header,
+    pub manufacturer: u8,
+    pub product_name: u8,
+    pub version: u8,
+    pub serial_number: u8,
+    /// Encoded with the first three fields little-endian, matching the
+    /// in-memory layout of a GUID.
+    pub uuid: [u8; 16],
+    pub wake_up_type: u8,
+    pub sku_number: u8,
+    pub family: u8,
+}
+
+const_assert_eq!(size_of::<SystemInformation>(), 0x1b);
+
+/// Wake-up type: power switch.
+pub const WAKE_UP_TYPE_POWER_SWITCH: u8 = 0x06;
+
+/// Type 2.
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, AsBytes, FromBytes, FromZeroes, Unaligned)]
+pub struct BaseboardInformation {
+    pub header: Header,
+    pub manufacturer: u8,
+    pub product: u8,
+    pub version: u8,
+    pub serial_number: u8,
+    pub asset_tag: u8,
+    pub feature_flags: u8,
+    pub location_in_chassis: u8,
+    pub chassis_handle: u16,
+    pub board_type: u8,
+    pub contained_object_handles: u8,
+}
+
+const_assert_eq!(size_of::<BaseboardInformation>(), 0x0f);
+
+/// Baseboard feature flags: the board is a hosting board.
+pub const BASEBOARD_FEATURE_HOSTING_BOARD: u8 = 1 << 0;
+/// Baseboard type: motherboard.
+pub const BASEBOARD_TYPE_MOTHERBOARD: u8 = 0x0a;
+
+/// Type 3.
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, AsBytes, FromBytes, FromZeroes, Unaligned)]
+pub struct SystemEnclosure {
+    pub header: Header,
+    pub manufacturer: u8,
+    pub typ: u8,
+    pub version: u8,
+    pub serial_number: u8,
+    pub asset_tag: u8,
+    pub boot_up_state: u8,
+    pub power_supply_state: u8,
+    pub thermal_state: u8,
+    pub security_status: u8,
+    pub oem_defined: u32,
+    pub height: u8,
+    pub power_cords: u8,
+    pub contained_element_count: u8,
+    pub contained_element_record_length: u8,
+    pub sku_number: u8,
+}
+
+const_assert_eq!(size_of::<SystemEnclosure>(), 0x16);
+
+/// Enclosure type: other.
+pub const ENCLOSURE_TYPE_OTHER: u8 = 0x01;
+/// Enclosure state: safe.
+pub const ENCLOSURE_STATE_SAFE: u8 = 0x03;
+/// Enclosure security status: unknown.
+pub const ENCLOSURE_SECURITY_UNKNOWN: u8 = 0x02;
+
+/// Type 4.
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, AsBytes, FromBytes, FromZeroes, Unaligned)]
+pub struct ProcessorInformation {
+    pub header: Header,
+    pub socket_designation: u8,
+    pub processor_type: u8,
+    pub processor_family: u8,
+    pub processor_manufacturer: u8,
+    pub processor_id: u64,
+    pub processor_version: u8,
+    pub voltage: u8,
+    pub external_clock: u16,
+    pub max_speed: u16,
+    pub current_speed: u16,
+    pub status: u8,
+    pub processor_upgrade: u8,
+    pub l1_cache_handle: u16,
+    pub l2_cache_handle: u16,
+    pub l3_cache_handle: u16,
+    pub serial_number: u8,
+    pub asset_tag: u8,
+    pub part_number: u8,
+    pub core_count: u8,
+    pub core_enabled: u8,
+    pub thread_count: u8,
+    pub processor_characteristics: u16,
+    pub processor_family2: u16,
+    pub core_count2: u16,
+    pub core_enabled2: u16,
+    pub thread_count2: u16,
+}
+
+const_assert_eq!(size_of::<ProcessorInformation>(), 0x30);
+
+/// Processor type: central processor.
+pub const PROCESSOR_TYPE_CENTRAL: u8 = 0x03;
+/// Processor family: other.
+pub const PROCESSOR_FAMILY_OTHER: u16 = 0x01;
+/// Processor family: ARMv8.
+pub const PROCESSOR_FAMILY_ARMV8: u16 = 0x101;
+/// Processor family value indicating that `processor_family2` holds the
+/// family.
+pub const PROCESSOR_FAMILY_USE_FAMILY2: u8 = 0xfe;
+/// Processor status: socket populated, CPU enabled.
+pub const PROCESSOR_STATUS_ENABLED: u8 = 0x41;
+/// Processor upgrade: other.
+pub const PROCESSOR_UPGRADE_OTHER: u8 = 0x01;
+/// Processor characteristics: 64-bit capable.
+pub const PROCESSOR_CHARACTERISTICS_64BIT: u16 = 1 << 2;
+/// Processor characteristics: multi-core.
+pub const PROCESSOR_CHARACTERISTICS_MULTI_CORE: u16 = 1 << 3;
+
+/// Type 11.
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, AsBytes, FromBytes, FromZeroes, Unaligned)]
+pub struct OemStrings {
+    pub header: Header,
+    pub count: u8,
+}
+
+const_assert_eq!(size_of::<OemStrings>(), 0x05);
+
+/// Type 16.
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, AsBytes, FromBytes, FromZeroes, Unaligned)]
+pub struct PhysicalMemoryArray {
+    pub header: Header,
+    pub location: u8,
+    pub array_use: u8,
+    pub error_correction: u8,
+    /// In KiB, or [`CAPACITY_USE_EXTENDED`].
+    pub maximum_capacity: u32,
+    pub error_information_handle: u16,
+    pub number_of_devices: u16,
+    /// In bytes.
+    pub extended_maximum_capacity: u64,
+}
+
+const_assert_eq!(size_of::<PhysicalMemoryArray>(), 0x17);
+
+/// Memory array location: other.
+pub const MEMORY_ARRAY_LOCATION_OTHER: u8 = 0x01;
+/// Memory array use: system memory.
+pub const MEMORY_ARRAY_USE_SYSTEM: u8 = 0x03;
+/// Memory array error correction: none.
+pub const MEMORY_ARRAY_ECC_NONE: u8 = 0x03;
+/// The maximum capacity value indicating that the extended field is used.
+pub const CAPACITY_USE_EXTENDED: u32 = 0x8000_0000;
+
+/// Type 17, in its SMBIOS 2.8 length.
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, AsBytes, FromBytes, FromZeroes, Unaligned)]
+pub struct MemoryDevice {
+    pub header: Header,
+    pub physical_memory_array_handle: u16,
+    pub error_information_handle: u16,
+    pub total_width: u16,
+    pub data_width: u16,
+    /// In MiB, or in KiB if [`MEMORY_SIZE_KIB`] is set, or
+    /// [`MEMORY_SIZE_USE_EXTENDED`].
+    pub size: u16,
+    pub form_factor: u8,
+    pub device_set: u8,
+    pub device_locator: u8,
+    pub bank_locator: u8,
+    pub memory_type: u8,
+    pub type_detail: u16,
+    pub speed: u16,
+    pub manufacturer: u8,
+    pub serial_number: u8,
+    pub asset_tag: u8,
+    pub part_number: u8,
+    pub attributes: u8,
+    /// In MiB.
+    pub extended_size: u32,
+    pub configured_memory_speed: u16,
+    pub minimum_voltage: u16,
+    pub maximum_voltage: u16,
+    pub configured_voltage: u16,
+}
+
+const_assert_eq!(size_of::<MemoryDevice>(), 0x28);
+
+/// Memory device size bit indicating the size is in KiB.
+pub const MEMORY_SIZE_KIB: u16 = 0x8000;
+/// The memory device size value indicating that the extended field is used.
+pub const MEMORY_SIZE_USE_EXTENDED: u16 = 0x7fff;
+/// Memory device form factor: DIMM.
+pub const MEMORY_FORM_FACTOR_DIMM: u8 = 0x09;
+/// Memory type: RAM.
+pub const MEMORY_TYPE_RAM: u8 = 0x07;
+/// Memory type detail: other.
+pub const MEMORY_TYPE_DETAIL_OTHER: u16 = 1 << 1;
+
+/// Type 19.
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, AsBytes, FromBytes, FromZeroes, Unaligned)]
+pub struct MemoryArrayMappedAddress {
+    pub header: Header,
+    /// In KiB, or [`ADDRESS_USE_EXTENDED`].
+    pub starting_address: u32,
+    /// In KiB, inclusive, or [`ADDRESS_USE_EXTENDED`].
+    pub ending_address: u32,
+    pub memory_array_handle: u16,
+    pub partition_width: u8,
+    /// In bytes.
+    pub extended_starting_address: u64,
+    /// In bytes, inclusive.
+    pub extended_ending_address: u64,
+}
+
+const_assert_eq!(size_of::<MemoryArrayMappedAddress>(), 0x1f);
+
+/// The address value indicating that the extended fields are used.
+pub const ADDRESS_USE_EXTENDED: u32 = 0xffff_ffff;
+
+/// Type 32.
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, AsBytes, FromBytes, FromZeroes, Unaligned)]
+pub struct SystemBootInformation {
+    pub header: Header,
+    pub reserved: [u8; 6],
+    pub boot_status: u8,
+}
+
+const_assert_eq!(size_of::<SystemBootInformation>(), 0x0b);
+
+/// Boot status: no errors detected.
+pub const BOOT_STATUS_NO_ERRORS: u8 = 0;
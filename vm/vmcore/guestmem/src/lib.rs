@@ -2071,6 +2071,26 @@ fn new_region(
     ) -> io::Result<(Box<dyn MappableGuestMemory>, Arc<dyn MappedMemoryRegion>)>;
 }
 
+/// A range of guest RAM and the shareable OS object backing it.
+#[derive(Debug)]
+pub struct SharedRamRange {
+    /// The guest physical address of the range.
+    pub gpa: u64,
+    /// The length of the range in bytes.
+    pub len: u64,
+    /// The object backing the range.
+    pub mappable: sparse_mmap::Mappable,
+    /// The offset of the range within `mappable`.
+    pub file_offset: u64,
+}
+
+/// Trait implemented to allow guest RAM to be mapped into another process,
+/// e.g. to allow an out-of-process device to perform DMA.
+pub trait SharedGuestRam: Send + Sync {
+    /// Returns the guest RAM ranges, in address order.
+    fn shared_ram_ranges(&self) -> io::Result<Vec<SharedRamRange>>;
+}
+
 /// Doorbell provides a mechanism to register for notifications on writes to specific addresses in guest memory.
 pub trait DoorbellRegistration: Send + Sync {
     /// Register a doorbell event.
@@ -32,6 +32,9 @@ pub struct MemoryLayout {
     /// The RAM range used by VTL2. This is not present in any of the stats
     /// above.
     vtl2_range: Option<MemoryRange>,
+    /// The range reserved for hot-added RAM. This is not present in any of
+    /// the stats above.
+    hotplug_range: Option<MemoryRange>,
 }
 
 #[cfg(feature = "inspect")]
@@ -75,6 +78,9 @@ pub enum Error {
     /// VTL2 range is below the end of ram, and overlaps.
     #[error("vtl2 range is below end of ram")]
     Vtl2RangeBeforeEndOfRam,
+    /// Invalid hotplug range size or alignment.
+    #[error("invalid memory hotplug size or alignment")]
+    BadHotplugRange,
     /// An address doesn't fit into the physical address space.
     #[error("range {range} is outside of physical address space ({width} bits)")]
     PhysicalAddressExceeded {
@@ -126,7 +132,28 @@ pub fn new(
         gaps: &[MemoryRange],
         vtl2_range: Option<MemoryRange>,
     ) -> Result<Self, Error> {
-        if ram_size == 0 || ram_size & (PAGE_SIZE - 1) != 0 {
+        Self::new_numa(physical_address_size, &[ram_size], gaps, vtl2_range)
+    }
+
+    /// Makes a new memory layout for a guest with NUMA nodes with
+    /// `node_sizes[n]` bytes of memory in node `n`, and MMIO gaps at the
+    /// locations specified by `gaps`.
+    ///
+    /// RAM is allocated to nodes in order, starting at address 0 and skipping
+    /// the gaps, so a node's RAM may be split across several ranges. Each
+    /// node size must be a multiple of the page size, and the total must be
+    /// non-zero. Nodes may be empty.
+    ///
+    /// `gaps` and `vtl2_range` are as in [`MemoryLayout::new`].
+    pub fn new_numa(
+        physical_address_size: u8,
+        node_sizes: &[u64],
+        gaps: &[MemoryRange],
+        vtl2_range: Option<MemoryRange>,
+    ) -> Result<Self, Error> {
+        if node_sizes.iter().all(|&size| size == 0)
+            || node_sizes.iter().any(|&size| size & (PAGE_SIZE - 1) != 0)
+        {
             return Err(Error::BadSize);
         }
         if gaps.len() < 2 {
@@ -135,23 +162,26 @@ pub fn new(
 
         validate_ranges(gaps)?;
         let mut ram = Vec::new();
-        let mut remaining = ram_size;
-        let mut remaining_gaps = gaps.iter().cloned();
-        let mut last_end = 0;
-
-        while remaining > 0 {
-            let (this, next_end) = if let Some(gap) = remaining_gaps.next() {
-                (remaining.min(gap.start() - last_end), gap.end())
-            } else {
-                (remaining, 0)
-            };
-
-            ram.push(MemoryRangeWithNode {
-                range: MemoryRange::new(last_end..last_end + this),
-                vnode: 0,
-            });
-            remaining -= this;
-            last_end = next_end;
+        let mut remaining_gaps = gaps.iter().peekable();
+        let mut next_start = 0;
+
+        for (vnode, &size) in node_sizes.iter().enumerate() {
+            let mut remaining = size;
+            while remaining > 0 {
+                let gap_start = remaining_gaps.peek().map_or(u64::MAX, |gap| gap.start());
+                let this = remaining.min(gap_start.saturating_sub(next_start));
+                if this > 0 {
+                    ram.push(MemoryRangeWithNode {
+                        range: MemoryRange::new(next_start..next_start + this),
+                        vnode: vnode as u32,
+                    });
+                }
+                remaining -= this;
+                next_start += this;
+                if next_start >= gap_start {
+                    next_start = remaining_gaps.next().unwrap().end();
+                }
+            }
         }
 
         Self::build(physical_address_size, ram, gaps.to_vec(), vtl2_range)
@@ -224,9 +254,37 @@ fn build(
             ram,
             mmio,
             vtl2_range,
+            hotplug_range: None,
         })
     }
 
+    /// Reserves `len` bytes of guest physical address space for hot-added
+    /// RAM, aligned to `align` bytes and located above all RAM, MMIO and VTL2
+    /// ranges.
+    ///
+    /// `len` must be a non-zero multiple of `align`, which must be a power of
+    /// two and at least the page size.
+    pub fn with_hotplug_range(mut self, len: u64, align: u64) -> Result<Self, Error> {
+        if len == 0 || !align.is_power_of_two() || align < PAGE_SIZE || len % align != 0 {
+            return Err(Error::BadHotplugRange);
+        }
+        let start = self
+            .end_of_ram_or_mmio()
+            .max(self.vtl2_range.map_or(0, |r| r.end()))
+            .checked_next_multiple_of(align)
+            .ok_or(Error::BadHotplugRange)?;
+        let end = start.checked_add(len).ok_or(Error::BadHotplugRange)?;
+        let range = MemoryRange::new(start..end);
+        if end > 1 << self.physical_address_size {
+            return Err(Error::PhysicalAddressExceeded {
+                range,
+                width: self.physical_address_size,
+            });
+        }
+        self.hotplug_range = Some(range);
+        Ok(self)
+    }
+
     /// The MMIO gap ranges.
     pub fn mmio(&self) -> &[MemoryRange] {
         &self.mmio
@@ -244,6 +302,12 @@ pub fn vtl2_range(&self) -> Option<MemoryRange> {
         self.vtl2_range
     }
 
+    /// The range reserved for hot-added RAM, if any. This is located above
+    /// all other ranges, and RAM is only present in it once hot-added.
+    pub fn hotplug_range(&self) -> Option<MemoryRange> {
+        self.hotplug_range
+    }
+
     /// The bit width of a physical address for the VM.
     pub fn physical_address_size(&self) -> u8 {
         self.physical_address_size
@@ -423,4 +487,67 @@ fn bad_layout() {
         ];
         MemoryLayout::new(36, TB, mmio, None).unwrap_err();
     }
+
+    #[test]
+    fn numa_layout() {
+        let mmio = &[
+            MemoryRange::new(GB..2 * GB),
+            MemoryRange::new(3 * GB..4 * GB),
+        ];
+        let layout = MemoryLayout::new_numa(42, &[GB + GB / 2, 0, 2 * GB], mmio, None).unwrap();
+        assert_eq!(
+            layout.ram(),
+            &[
+                MemoryRangeWithNode {
+                    range: MemoryRange::new(0..GB),
+                    vnode: 0
+                },
+                MemoryRangeWithNode {
+                    range: MemoryRange::new(2 * GB..2 * GB + GB / 2),
+                    vnode: 0
+                },
+                MemoryRangeWithNode {
+                    range: MemoryRange::new(2 * GB + GB / 2..3 * GB),
+                    vnode: 2
+                },
+                MemoryRangeWithNode {
+                    range: MemoryRange::new(4 * GB..5 * GB + GB / 2),
+                    vnode: 2
+                },
+            ]
+        );
+        assert_eq!(layout.ram_size(), 3 * GB + GB / 2);
+
+        MemoryLayout::new_numa(42, &[0, 0], mmio, None).unwrap_err();
+        MemoryLayout::new_numa(42, &[GB, MB + 1], mmio, None).unwrap_err();
+    }
+
+    #[test]
+    fn hotplug_range() {
+        let mmio = &[
+            MemoryRange::new(GB..2 * GB),
+            MemoryRange::new(3 * GB..4 * GB),
+        ];
+        let layout = MemoryLayout::new(42, 2 * GB, mmio, None).unwrap();
+        assert_eq!(layout.hotplug_range(), None);
+
+        let layout = layout.with_hotplug_range(4 * GB, GB).unwrap();
+        assert_eq!(
+            layout.hotplug_range(),
+            Some(MemoryRange::new(4 * GB..8 * GB))
+        );
+        assert_eq!(layout.ram_size(), 2 * GB);
+
+        // Unaligned.
+        MemoryLayout::new(42, 2 * GB, mmio, None)
+            .unwrap()
+            .with_hotplug_range(GB + MB, GB)
+            .unwrap_err();
+
+        // Too large for the physical address space.
+        MemoryLayout::new(36, 2 * GB, mmio, None)
+            .unwrap()
+            .with_hotplug_range(64 * GB, GB)
+            .unwrap_err();
+    }
 }
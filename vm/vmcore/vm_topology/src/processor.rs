@@ -57,6 +57,7 @@ pub trait ArchTopology: Sized {
 pub struct TopologyBuilder<T: ArchTopology> {
     vps_per_socket: u32,
     smt_enabled: bool,
+    vnodes: Option<Vec<u32>>,
     arch: T::BuilderState,
 }
 
@@ -91,6 +92,9 @@ pub enum InvalidTopology {
     /// VpInfo indices must be linear and start at 0
     #[error("vp indices don't start at 0 or don't count up")]
     InvalidVpIndices,
+    /// A VP was not assigned a NUMA node.
+    #[error("no numa node specified for vp {0}")]
+    MissingVnode(u32),
     /// Failed to query the topology information from Device Tree.
     #[error("failed to query memory topology from device tree")]
     StdIoError(#[source] std::io::Error),
@@ -116,6 +120,25 @@ pub fn smt_enabled(&mut self, enabled: bool) -> &mut Self {
         self.smt_enabled = enabled;
         self
     }
+
+    /// Sets the virtual NUMA node of each VP, indexed by VP index.
+    ///
+    /// If this is not set, then the VP's socket determines its NUMA node on
+    /// x86, and all VPs are in NUMA node 0 on ARM64.
+    pub fn vnodes(&mut self, vnodes: Vec<u32>) -> &mut Self {
+        self.vnodes = Some(vnodes);
+        self
+    }
+
+    fn vnode(&self, vp_index: u32, default: u32) -> Result<u32, InvalidTopology> {
+        match &self.vnodes {
+            Some(vnodes) => vnodes
+                .get(vp_index as usize)
+                .copied()
+                .ok_or(InvalidTopology::MissingVnode(vp_index)),
+            None => Ok(default),
+        }
+    }
 }
 
 impl<
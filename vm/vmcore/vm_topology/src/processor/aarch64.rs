@@ -80,6 +80,7 @@ pub fn new_aarch64(gic: GicInfo) -> Self {
         Self {
             vps_per_socket: 1,
             smt_enabled: false,
+            vnodes: None,
             arch: Aarch64TopologyBuilderState { gic },
         }
     }
@@ -108,15 +109,21 @@ pub fn build(
                 .with_aff2(aff.next().unwrap())
                 .with_aff3(aff.next().unwrap())
         });
-        self.build_with_vp_info(mpidrs.enumerate().map(|(id, mpidr)| Aarch64VpInfo {
-            base: VpInfo {
-                vp_index: VpIndex::new(id as u32),
-                vnode: 0,
-            },
-            mpidr,
-            gicr: self.arch.gic.gic_redistributors_base
-                + id as u64 * aarch64defs::GIC_REDISTRIBUTOR_SIZE,
-        }))
+        let vps = mpidrs
+            .enumerate()
+            .map(|(id, mpidr)| {
+                Ok(Aarch64VpInfo {
+                    base: VpInfo {
+                        vp_index: VpIndex::new(id as u32),
+                        vnode: self.vnode(id as u32, 0)?,
+                    },
+                    mpidr,
+                    gicr: self.arch.gic.gic_redistributors_base
+                        + id as u64 * aarch64defs::GIC_REDISTRIBUTOR_SIZE,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        self.build_with_vp_info(vps)
     }
 
     /// Builds a processor topology with processors with the specified information.
@@ -11,6 +11,7 @@
 use acpi_spec::madt::InterruptPolarity;
 use acpi_spec::madt::InterruptTriggerMode;
 use cache_topology::CacheTopology;
+use chipset::hpet;
 use chipset::ioapic;
 use chipset::psp;
 use inspect::Inspect;
@@ -22,6 +23,7 @@
 use vm_topology::processor::ProcessorTopology;
 use x86defs::apic::APIC_BASE_ADDRESS;
 use zerocopy::AsBytes;
+use zerocopy::FromBytes;
 
 /// Binary ACPI tables constructed by [`AcpiTablesBuilder`].
 pub struct BuiltAcpiTables {
@@ -31,6 +33,103 @@ pub struct BuiltAcpiTables {
     pub tables: Vec<u8>,
 }
 
+/// ACPI tables and the QEMU fw_cfg ACPI table loader script to install them,
+/// constructed by [`AcpiTablesBuilder::build_acpi_table_loader`].
+pub struct AcpiTableLoaderFiles {
+    /// The RSDP.
+    pub rsdp: Vec<u8>,
+    /// The remaining tables pointed to by the RSDP.
+    pub tables: Vec<u8>,
+    /// The loader script.
+    pub loader: Vec<u8>,
+}
+
+impl AcpiTableLoaderFiles {
+    /// The fw_cfg file name for [`Self::rsdp`].
+    pub const RSDP_FILE: &'static str = "etc/acpi/rsdp";
+    /// The fw_cfg file name for [`Self::tables`].
+    pub const TABLES_FILE: &'static str = "etc/acpi/tables";
+    /// The fw_cfg file name for [`Self::loader`].
+    pub const LOADER_FILE: &'static str = "etc/table-loader";
+}
+
+/// Writer for QEMU's ACPI table loader script (`hw/acpi/bios-linker-loader.c`
+/// in the QEMU tree), a sequence of fixed-size commands for the firmware.
+#[derive(Default)]
+struct TableLoader {
+    script: Vec<u8>,
+}
+
+impl TableLoader {
+    const ENTRY_SIZE: usize = 128;
+    const FILE_NAME_SIZE: usize = 56;
+    const COMMAND_ALLOCATE: u32 = 1;
+    const COMMAND_ADD_POINTER: u32 = 2;
+    const COMMAND_ADD_CHECKSUM: u32 = 3;
+    const ZONE_HIGH: u8 = 1;
+    const ZONE_FSEG: u8 = 2;
+
+    fn push(&mut self, command: u32, fields: &[&[u8]]) {
+        let start = self.script.len();
+        self.script.extend_from_slice(&command.to_le_bytes());
+        for field in fields {
+            self.script.extend_from_slice(field);
+        }
+        self.script.resize(start + Self::ENTRY_SIZE, 0);
+    }
+
+    fn file_name(name: &str) -> [u8; Self::FILE_NAME_SIZE] {
+        let mut v = [0; Self::FILE_NAME_SIZE];
+        v[..name.len()].copy_from_slice(name.as_bytes());
+        v
+    }
+
+    /// Allocates memory for `file` and loads it there. Files that must be
+    /// found by scanning low memory (the RSDP) go in the F segment.
+    fn allocate(&mut self, file: &str, align: u32, fseg: bool) {
+        self.push(
+            Self::COMMAND_ALLOCATE,
+            &[
+                &Self::file_name(file),
+                &align.to_le_bytes(),
+                &[if fseg {
+                    Self::ZONE_FSEG
+                } else {
+                    Self::ZONE_HIGH
+                }],
+            ],
+        );
+    }
+
+    /// Adds the address of `src_file` to the `size`-byte pointer at `offset`
+    /// in `dest_file`.
+    fn add_pointer(&mut self, dest_file: &str, src_file: &str, offset: usize, size: u8) {
+        self.push(
+            Self::COMMAND_ADD_POINTER,
+            &[
+                &Self::file_name(dest_file),
+                &Self::file_name(src_file),
+                &(offset as u32).to_le_bytes(),
+                &[size],
+            ],
+        );
+    }
+
+    /// Sets the byte at `offset` in `file` so that the `len` bytes at `start`
+    /// sum to zero.
+    fn add_checksum(&mut self, file: &str, offset: usize, start: usize, len: usize) {
+        self.push(
+            Self::COMMAND_ADD_CHECKSUM,
+            &[
+                &Self::file_name(file),
+                &(offset as u32).to_le_bytes(),
+                &(start as u32).to_le_bytes(),
+                &(len as u32).to_le_bytes(),
+            ],
+        );
+    }
+}
+
 /// Builder to construct a set of [`BuiltAcpiTables`]
 pub struct AcpiTablesBuilder<'a, T: AcpiTopology> {
     /// The processor topology.
@@ -52,10 +151,45 @@ pub struct AcpiTablesBuilder<'a, T: AcpiTopology> {
     pub with_pit: bool,
     /// If a psp is present.
     pub with_psp: bool,
+    /// If an HPET is present.
+    ///
+    /// If and only if this is set, then the HPET table will be generated.
+    pub with_hpet: bool,
     /// base address of dynamic power management device registers
     pub pm_base: u16,
     /// ACPI IRQ number
     pub acpi_irq: u32,
+    /// The ECAM region of the PCI Express root complex, if any.
+    ///
+    /// If and only if this is set, then the MCFG table will be generated.
+    pub pcie_ecam: Option<PcieEcamRange>,
+    /// Whether each VP is present, indexed by VP index, if processor hotplug
+    /// is enabled.
+    ///
+    /// VPs that are not present are reported as online capable in the MADT,
+    /// so that they can be hot-added later. If this is not set, then all VPs
+    /// are present.
+    pub present_vps: Option<&'a [bool]>,
+    /// The distances between NUMA nodes, where `numa_distances[i][j]` is the
+    /// distance from node `i` to node `j`.
+    ///
+    /// If and only if this is set, then the SLIT table will be generated.
+    pub numa_distances: Option<&'a [Vec<u8>]>,
+}
+
+/// An ECAM (Enhanced Configuration Access Mechanism) region decoding a range
+/// of buses in a single PCI segment group.
+#[derive(Debug, Copy, Clone)]
+pub struct PcieEcamRange {
+    /// Base address of the ECAM region, corresponding to bus 0 (even if
+    /// `start_bus` is not 0).
+    pub ecam_base: u64,
+    /// The PCI segment group number.
+    pub segment: u16,
+    /// The first bus number decoded by the region.
+    pub start_bus: u8,
+    /// The last bus number decoded by the region.
+    pub end_bus: u8,
 }
 
 pub const OEM_INFO: acpi::builder::OemInfo = acpi::builder::OemInfo {
@@ -68,7 +202,11 @@ pub struct AcpiTablesBuilder<'a, T: AcpiTopology> {
 
 pub trait AcpiTopology: ArchTopology + Inspect + Sized {
     fn extend_srat(topology: &ProcessorTopology<Self>, srat: &mut Vec<u8>);
-    fn extend_madt(topology: &ProcessorTopology<Self>, madt: &mut Vec<u8>);
+    fn extend_madt(
+        topology: &ProcessorTopology<Self>,
+        present_vps: Option<&[bool]>,
+        madt: &mut Vec<u8>,
+    );
 }
 
 /// The maximum ID that can be used for a legacy APIC ID in an ACPI table.
@@ -92,15 +230,26 @@ fn extend_srat(topology: &ProcessorTopology<Self>, srat: &mut Vec<u8>) {
         }
     }
 
-    fn extend_madt(topology: &ProcessorTopology<Self>, madt: &mut Vec<u8>) {
+    fn extend_madt(
+        topology: &ProcessorTopology<Self>,
+        present_vps: Option<&[bool]>,
+        madt: &mut Vec<u8>,
+    ) {
         for vp in topology.vps_arch() {
             let uid = vp.base.vp_index.index() + 1;
+            let present =
+                present_vps.map_or(true, |present| present[vp.base.vp_index.index() as usize]);
+            let flags = if present {
+                acpi_spec::madt::MADT_APIC_ENABLED
+            } else {
+                acpi_spec::madt::MADT_APIC_ONLINE_CAPABLE
+            };
             if vp.apic_id <= MAX_LEGACY_APIC_ID && uid <= u8::MAX.into() {
                 madt.extend_from_slice(
                     acpi_spec::madt::MadtApic {
                         apic_id: vp.apic_id as u8,
                         acpi_processor_uid: uid as u8,
-                        flags: acpi_spec::madt::MADT_APIC_ENABLED,
+                        flags,
                         ..acpi_spec::madt::MadtApic::new()
                     }
                     .as_bytes(),
@@ -110,7 +259,7 @@ fn extend_madt(topology: &ProcessorTopology<Self>, madt: &mut Vec<u8>) {
                     acpi_spec::madt::MadtX2Apic {
                         x2_apic_id: vp.apic_id,
                         acpi_processor_uid: uid,
-                        flags: acpi_spec::madt::MADT_APIC_ENABLED,
+                        flags,
                         ..acpi_spec::madt::MadtX2Apic::new()
                     }
                     .as_bytes(),
@@ -130,7 +279,11 @@ fn extend_srat(topology: &ProcessorTopology<Self>, srat: &mut Vec<u8>) {
         }
     }
 
-    fn extend_madt(topology: &ProcessorTopology<Self>, madt: &mut Vec<u8>) {
+    fn extend_madt(
+        topology: &ProcessorTopology<Self>,
+        _present_vps: Option<&[bool]>,
+        madt: &mut Vec<u8>,
+    ) {
         // GIC version 3.
         madt.extend_from_slice(
             acpi_spec::madt::MadtGicd::new(0, topology.gic_distributor_base(), 3).as_bytes(),
@@ -164,6 +317,18 @@ fn with_srat<F, R>(&self, f: F) -> R
                 .as_bytes(),
             );
         }
+        if let Some(range) = self.mem_layout.hotplug_range() {
+            srat_extra.extend_from_slice(
+                acpi_spec::srat::SratMemory {
+                    flags: (acpi_spec::srat::SratMemoryFlags::ENABLED
+                        | acpi_spec::srat::SratMemoryFlags::HOT_PLUGGABLE)
+                        .0
+                        .into(),
+                    ..acpi_spec::srat::SratMemory::new(range.start(), range.len(), 0)
+                }
+                .as_bytes(),
+            );
+        }
 
         (f)(&acpi::builder::Table::new_dyn(
             acpi_spec::srat::SRAT_REVISION,
@@ -207,7 +372,7 @@ fn with_madt<F, R>(&self, f: F) -> R
             );
         }
 
-        T::extend_madt(self.processor_topology, &mut madt_extra);
+        T::extend_madt(self.processor_topology, self.present_vps, &mut madt_extra);
 
         let flags = if self.with_pic {
             acpi_spec::madt::MADT_PCAT_COMPAT
@@ -365,12 +530,167 @@ fn with_pptt<F, R>(&self, f: F) -> R
         ))
     }
 
+    fn with_mcfg<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&acpi::builder::Table<'_>) -> R,
+    {
+        let ecam = self.pcie_ecam.expect("pcie ecam is required");
+
+        (f)(&acpi::builder::Table::new_dyn(
+            acpi_spec::mcfg::MCFG_REVISION,
+            None,
+            &acpi_spec::mcfg::McfgHeader::new(),
+            &[acpi_spec::mcfg::McfgSegmentBusRange::new(
+                ecam.ecam_base,
+                ecam.segment,
+                ecam.start_bus,
+                ecam.end_bus,
+            )
+            .as_bytes()],
+        ))
+    }
+
+    fn with_slit<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&acpi::builder::Table<'_>) -> R,
+    {
+        let distances = self.numa_distances.expect("numa distances are required");
+        let matrix = distances.concat();
+        assert_eq!(
+            matrix.len(),
+            distances.len() * distances.len(),
+            "numa distances must be a square matrix"
+        );
+
+        (f)(&acpi::builder::Table::new_dyn(
+            acpi_spec::slit::SLIT_REVISION,
+            None,
+            &acpi_spec::slit::SlitHeader::new(distances.len() as u64),
+            &[matrix.as_slice()],
+        ))
+    }
+
+    fn with_hpet<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&acpi::builder::Table<'_>) -> R,
+    {
+        (f)(&acpi::builder::Table::new(
+            acpi_spec::hpet::HPET_REVISION,
+            None,
+            &acpi_spec::hpet::Hpet {
+                event_timer_block_id: hpet::event_timer_block_id(),
+                base_address: GenericAddress {
+                    addr_space_id: AddressSpaceId::SystemMemory,
+                    register_bit_width: 64,
+                    register_bit_offset: 0,
+                    access_size: AddressWidth::Undefined,
+                    address: hpet::HPET_MMIO_BASE,
+                },
+                hpet_number: 0,
+                min_clock_tick: hpet::MIN_PERIODIC_TICKS,
+                page_protection: acpi_spec::hpet::HPET_PAGE_PROTECTION_4K,
+            },
+        ))
+    }
+
     /// Build ACPI tables based on the supplied closure that adds devices to the DSDT.
     ///
     /// The RDSP is assumed to take one whole page.
     ///
     /// Returns tables that should be loaded at the supplied gpa.
     pub fn build_acpi_tables<F>(&self, gpa: u64, add_devices_to_dsdt: F) -> BuiltAcpiTables
+    where
+        F: FnOnce(&MemoryLayout, &mut dsdt::Dsdt),
+    {
+        let dsdt = self.build_dsdt(add_devices_to_dsdt);
+        self.build_acpi_tables_inner(gpa + 0x1000, &dsdt)
+    }
+
+    /// Build ACPI tables for firmware to install with the QEMU fw_cfg ACPI
+    /// table loader, based on the supplied closure that adds devices to the
+    /// DSDT.
+    ///
+    /// The tables are built as if loaded at address zero. The loader script
+    /// has the firmware allocate them, relocate the pointers between them,
+    /// and compute their checksums.
+    pub fn build_acpi_table_loader<F>(&self, add_devices_to_dsdt: F) -> AcpiTableLoaderFiles
+    where
+        F: FnOnce(&MemoryLayout, &mut dsdt::Dsdt),
+    {
+        let dsdt = self.build_dsdt(add_devices_to_dsdt);
+        let BuiltAcpiTables {
+            rdsp: mut rsdp,
+            mut tables,
+        } = self.build_acpi_tables_inner(0, &dsdt);
+
+        let mut loader = TableLoader::default();
+        loader.allocate(AcpiTableLoaderFiles::TABLES_FILE, 64, false);
+        loader.allocate(AcpiTableLoaderFiles::RSDP_FILE, 16, true);
+
+        // Find the pointers and checksums in each table. The checksums are
+        // cleared, since the firmware computes them over the whole table
+        // after relocating it.
+        let mut checksums = Vec::new();
+        let mut offset = 0;
+        while offset < tables.len() {
+            let header = acpi_spec::Header::read_from_prefix(&tables[offset..]).unwrap();
+            let len = header.length.get() as usize;
+            let body = offset + size_of::<acpi_spec::Header>();
+            match &header.signature {
+                b"FACP" => loader.add_pointer(
+                    AcpiTableLoaderFiles::TABLES_FILE,
+                    AcpiTableLoaderFiles::TABLES_FILE,
+                    body + std::mem::offset_of!(acpi_spec::fadt::Fadt, x_dsdt),
+                    8,
+                ),
+                b"XSDT" => {
+                    for entry in (body..offset + len).step_by(8) {
+                        loader.add_pointer(
+                            AcpiTableLoaderFiles::TABLES_FILE,
+                            AcpiTableLoaderFiles::TABLES_FILE,
+                            entry,
+                            8,
+                        );
+                    }
+                }
+                _ => {}
+            }
+            tables[offset + std::mem::offset_of!(acpi_spec::Header, checksum)] = 0;
+            checksums.push((offset, len));
+            offset += len.next_multiple_of(8);
+        }
+        for (offset, len) in checksums {
+            loader.add_checksum(
+                AcpiTableLoaderFiles::TABLES_FILE,
+                offset + std::mem::offset_of!(acpi_spec::Header, checksum),
+                offset,
+                len,
+            );
+        }
+
+        loader.add_pointer(
+            AcpiTableLoaderFiles::RSDP_FILE,
+            AcpiTableLoaderFiles::TABLES_FILE,
+            std::mem::offset_of!(acpi_spec::Rsdp, xsdt),
+            8,
+        );
+        let checksum = std::mem::offset_of!(acpi_spec::Rsdp, checksum);
+        let xchecksum = std::mem::offset_of!(acpi_spec::Rsdp, xchecksum);
+        rsdp[checksum] = 0;
+        rsdp[xchecksum] = 0;
+        // The legacy checksum covers the ACPI 1.0 part of the RSDP, and is
+        // itself covered by the extended checksum.
+        loader.add_checksum(AcpiTableLoaderFiles::RSDP_FILE, checksum, 0, 20);
+        loader.add_checksum(AcpiTableLoaderFiles::RSDP_FILE, xchecksum, 0, rsdp.len());
+
+        AcpiTableLoaderFiles {
+            rsdp,
+            tables,
+            loader: loader.script,
+        }
+    }
+
+    fn build_dsdt<F>(&self, add_devices_to_dsdt: F) -> Vec<u8>
     where
         F: FnOnce(&MemoryLayout, &mut dsdt::Dsdt),
     {
@@ -405,7 +725,7 @@ pub fn build_acpi_tables<F>(&self, gpa: u64, add_devices_to_dsdt: F) -> BuiltAcp
             dsdt_data.add_object(&proc);
         }
 
-        self.build_acpi_tables_inner(gpa, &dsdt_data.to_bytes())
+        dsdt_data.to_bytes()
     }
 
     /// Build ACPI tables based on the supplied custom DSDT.
@@ -414,11 +734,12 @@ pub fn build_acpi_tables<F>(&self, gpa: u64, add_devices_to_dsdt: F) -> BuiltAcp
     ///
     /// Returns tables that should be loaded at the supplied gpa.
     pub fn build_acpi_tables_custom_dsdt(&self, gpa: u64, dsdt: &[u8]) -> BuiltAcpiTables {
-        self.build_acpi_tables_inner(gpa, dsdt)
+        self.build_acpi_tables_inner(gpa + 0x1000, dsdt)
     }
 
-    fn build_acpi_tables_inner(&self, gpa: u64, dsdt: &[u8]) -> BuiltAcpiTables {
-        let mut b = acpi::builder::Builder::new(gpa + 0x1000, OEM_INFO);
+    /// Builds the tables with the first one (the DSDT) at `tables_gpa`.
+    fn build_acpi_tables_inner(&self, tables_gpa: u64, dsdt: &[u8]) -> BuiltAcpiTables {
+        let mut b = acpi::builder::Builder::new(tables_gpa, OEM_INFO);
 
         let dsdt = b.append_raw(dsdt);
 
@@ -532,6 +853,15 @@ fn build_acpi_tables_inner(&self, gpa: u64, dsdt: &[u8]) -> BuiltAcpiTables {
         if self.cache_topology.is_some() {
             self.with_pptt(|t| b.append(t));
         }
+        if self.numa_distances.is_some() {
+            self.with_slit(|t| b.append(t));
+        }
+        if self.pcie_ecam.is_some() {
+            self.with_mcfg(|t| b.append(t));
+        }
+        if self.with_hpet {
+            self.with_hpet(|t| b.append(t));
+        }
 
         let (rdsp, tables) = b.build();
 
@@ -558,6 +888,30 @@ pub fn build_srat(&self) -> Vec<u8> {
     pub fn build_pptt(&self) -> Vec<u8> {
         self.with_pptt(|t| t.to_vec(&OEM_INFO))
     }
+
+    /// Helper method to construct an MCFG without constructing the rest of the
+    /// ACPI tables.
+    ///
+    /// # Panics
+    /// Panics if `self.pcie_ecam` is not set.
+    pub fn build_mcfg(&self) -> Vec<u8> {
+        self.with_mcfg(|t| t.to_vec(&OEM_INFO))
+    }
+
+    /// Helper method to construct a SLIT without constructing the rest of the
+    /// ACPI tables.
+    ///
+    /// # Panics
+    /// Panics if `self.numa_distances` is not set.
+    pub fn build_slit(&self) -> Vec<u8> {
+        self.with_slit(|t| t.to_vec(&OEM_INFO))
+    }
+
+    /// Helper method to construct an HPET table without constructing the rest
+    /// of the ACPI tables.
+    pub fn build_hpet(&self) -> Vec<u8> {
+        self.with_hpet(|t| t.to_vec(&OEM_INFO))
+    }
 }
 
 #[cfg(test)]
@@ -596,8 +950,12 @@ fn new_builder<'a>(
             with_pic: false,
             with_pit: false,
             with_psp: false,
+            with_hpet: false,
             pm_base: 1234,
             acpi_irq: 2,
+            pcie_ecam: None,
+            present_vps: None,
+            numa_distances: None,
         }
     }
 
@@ -641,4 +999,180 @@ fn test_basic_madt_cpu() {
             apic_ids.iter().map(|e| Some(*e)).collect::<Vec<_>>()
         );
     }
+
+    #[test]
+    fn test_madt_present_vps() {
+        let mem = new_mem();
+        let topology = TopologyBuilder::new_x86().build(4).unwrap();
+        let present = [true, true, false, false];
+        let builder = AcpiTablesBuilder {
+            present_vps: Some(&present),
+            ..new_builder(&mem, &topology)
+        };
+        let madt = builder.build_madt();
+
+        // Only the present VPs are reported as enabled.
+        let entries = MadtParser::new(&madt).unwrap().parse_apic_ids().unwrap();
+        assert_eq!(entries, vec![Some(0), Some(1)]);
+    }
+
+    #[test]
+    fn test_mcfg() {
+        let mem = new_mem();
+        let topology = TopologyBuilder::new_x86().build(1).unwrap();
+        let builder = AcpiTablesBuilder {
+            pcie_ecam: Some(PcieEcamRange {
+                ecam_base: 0xe000_0000,
+                segment: 1,
+                start_bus: 0,
+                end_bus: 3,
+            }),
+            ..new_builder(&mem, &topology)
+        };
+        let mcfg = builder.build_mcfg();
+
+        // header + reserved + one allocation structure
+        assert_eq!(mcfg.len(), 36 + 8 + 16);
+        assert_eq!(&mcfg[..4], b"MCFG");
+        assert_eq!(&mcfg[44..52], &0xe000_0000u64.to_ne_bytes());
+        assert_eq!(&mcfg[52..56], &[1, 0, 0, 3]);
+    }
+
+    #[test]
+    fn test_slit() {
+        let mem = new_mem();
+        let topology = TopologyBuilder::new_x86().build(1).unwrap();
+        let distances = [vec![10, 21], vec![21, 10]];
+        let builder = AcpiTablesBuilder {
+            numa_distances: Some(&distances),
+            ..new_builder(&mem, &topology)
+        };
+        let slit = builder.build_slit();
+
+        // header + locality count + 2x2 matrix
+        assert_eq!(slit.len(), 36 + 8 + 4);
+        assert_eq!(&slit[..4], b"SLIT");
+        assert_eq!(&slit[36..44], &2u64.to_ne_bytes());
+        assert_eq!(&slit[44..48], &[10, 21, 21, 10]);
+    }
+
+    #[test]
+    fn test_srat_vnodes() {
+        let mem = MemoryLayout::new_numa(42, &[2 * GB, 2 * GB], &MMIO, None).unwrap();
+        let topology = TopologyBuilder::new_x86()
+            .vnodes(vec![0, 1])
+            .build(2)
+            .unwrap();
+        let srat = new_builder(&mem, &topology).build_srat();
+
+        // The second VP and the memory above 4GB are in node 1.
+        let find = |pattern: &[u8]| srat.windows(pattern.len()).any(|w| w == pattern);
+        assert!(find(acpi_spec::srat::SratApic::new(1, 1).as_bytes()));
+        assert!(find(
+            acpi_spec::srat::SratMemory::new(4 * GB, 2 * GB, 1).as_bytes()
+        ));
+    }
+
+    #[test]
+    fn test_hpet() {
+        let mem = new_mem();
+        let topology = TopologyBuilder::new_x86().build(1).unwrap();
+        let builder = AcpiTablesBuilder {
+            with_hpet: true,
+            ..new_builder(&mem, &topology)
+        };
+        let hpet = builder.build_hpet();
+
+        assert_eq!(hpet.len(), 36 + 20);
+        assert_eq!(&hpet[..4], b"HPET");
+        assert_eq!(
+            &hpet[36..40],
+            &chipset::hpet::event_timer_block_id().to_ne_bytes()
+        );
+        assert_eq!(&hpet[44..52], &0xfed0_0000u64.to_ne_bytes());
+    }
+
+    #[test]
+    fn test_table_loader() {
+        let mem = new_mem();
+        let topology = TopologyBuilder::new_x86().build(2).unwrap();
+        let builder = new_builder(&mem, &topology);
+        let files = builder.build_acpi_table_loader(|_, _| {});
+
+        // Run the loader script the way firmware would.
+        let file_name = |v: &[u8]| {
+            let len = v.iter().position(|&c| c == 0).unwrap();
+            String::from_utf8(v[..len].to_vec()).unwrap()
+        };
+        let u32_at = |v: &[u8], offset: usize| {
+            u32::from_le_bytes(v[offset..offset + 4].try_into().unwrap()) as usize
+        };
+        let mut blobs = BTreeMap::from([
+            (AcpiTableLoaderFiles::RSDP_FILE.to_string(), files.rsdp),
+            (AcpiTableLoaderFiles::TABLES_FILE.to_string(), files.tables),
+        ]);
+        let mut bases = BTreeMap::new();
+        for entry in files.loader.chunks_exact(TableLoader::ENTRY_SIZE) {
+            match u32_at(entry, 0) as u32 {
+                TableLoader::COMMAND_ALLOCATE => {
+                    let base = if entry[64] == TableLoader::ZONE_FSEG {
+                        0xf0000
+                    } else {
+                        0x100000
+                    };
+                    bases.insert(file_name(&entry[4..60]), base);
+                }
+                TableLoader::COMMAND_ADD_POINTER => {
+                    let src = bases[&file_name(&entry[60..116])];
+                    let dest = blobs.get_mut(&file_name(&entry[4..60])).unwrap();
+                    let offset = u32_at(entry, 116);
+                    assert_eq!(entry[120], 8);
+                    let pointer = &mut dest[offset..offset + 8];
+                    let value = u64::from_le_bytes(pointer.try_into().unwrap()) + src;
+                    pointer.copy_from_slice(&value.to_le_bytes());
+                }
+                TableLoader::COMMAND_ADD_CHECKSUM => {
+                    let blob = blobs.get_mut(&file_name(&entry[4..60])).unwrap();
+                    let (offset, start, len) =
+                        (u32_at(entry, 60), u32_at(entry, 64), u32_at(entry, 68));
+                    let sum = blob[start..start + len]
+                        .iter()
+                        .fold(0u8, |sum, &b| sum.wrapping_add(b));
+                    blob[offset] = sum.wrapping_neg();
+                }
+                command => panic!("unexpected command {command}"),
+            }
+        }
+
+        let sum = |v: &[u8]| v.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+        let rsdp = acpi_spec::Rsdp::read_from(&blobs[AcpiTableLoaderFiles::RSDP_FILE][..]).unwrap();
+        assert_eq!(sum(&rsdp.as_bytes()[..20]), 0);
+        assert_eq!(sum(rsdp.as_bytes()), 0);
+
+        // The RSDP points to the XSDT, which points to each table but the
+        // DSDT, which the FADT points to.
+        let tables = &blobs[AcpiTableLoaderFiles::TABLES_FILE];
+        let table = |address: u64| {
+            let offset = (address - 0x100000) as usize;
+            let header = acpi_spec::Header::read_from_prefix(&tables[offset..]).unwrap();
+            let table = &tables[offset..offset + header.length.get() as usize];
+            assert_eq!(sum(table), 0);
+            (header.signature, table)
+        };
+        let (signature, xsdt) = table(rsdp.xsdt);
+        assert_eq!(&signature, b"XSDT");
+        let signatures = xsdt[36..]
+            .chunks_exact(8)
+            .map(|entry| {
+                let (signature, t) = table(u64::from_le_bytes(entry.try_into().unwrap()));
+                if &signature == b"FACP" {
+                    let x_dsdt = 36 + std::mem::offset_of!(acpi_spec::fadt::Fadt, x_dsdt);
+                    let dsdt = u64::from_le_bytes(t[x_dsdt..x_dsdt + 8].try_into().unwrap());
+                    assert_eq!(&table(dsdt).0, b"DSDT");
+                }
+                signature
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(signatures, [*b"FACP", *b"APIC", *b"SRAT"]);
+    }
 }
@@ -4,10 +4,12 @@
 //! Functions for resolving and building devices.
 
 use anyhow::Context as _;
+use closeable_mutex::CloseableMutex;
 use guestmem::DoorbellRegistration;
 use guestmem::GuestMemory;
 use pci_core::msi::MsiInterruptSet;
 use pci_core::msi::MsiInterruptTarget;
+use pcie::GenericPcieRootComplex;
 use std::sync::Arc;
 use vm_resource::kind::PciDeviceHandleKind;
 use vm_resource::Resource;
@@ -16,6 +18,7 @@
 use vmbus_server::VmbusServerControl;
 use vmcore::vm_task::VmTaskDriverSource;
 use vmcore::vpci_msi::VpciInterruptMapper;
+use vmotherboard::BusIdPci;
 use vmotherboard::ChipsetBuilder;
 
 /// Resolves a PCI device resource, builds the corresponding device, and builds
@@ -30,6 +33,7 @@ pub async fn build_vpci_device(
     chipset_builder: &mut ChipsetBuilder<'_>,
     doorbell_registration: Option<Arc<dyn DoorbellRegistration>>,
     mapper: Option<&dyn guestmem::MemoryMapper>,
+    shared_guest_ram: Option<&dyn guestmem::SharedGuestRam>,
     new_virtual_device: impl FnOnce(
         u64,
     ) -> anyhow::Result<(
@@ -57,6 +61,7 @@ pub async fn build_vpci_device(
                             guest_memory,
                             doorbell_registration,
                             shared_mem_mapper: mapper,
+                            shared_guest_ram,
                         },
                     )
                     .await
@@ -94,3 +99,100 @@ pub async fn build_vpci_device(
 
     Ok(())
 }
+
+/// Resolves a PCI device resource and builds the corresponding device,
+/// attaching it to the PCIe root port `port_name`.
+///
+/// The device's MSIs are delivered to `msi_target`.
+pub async fn build_pcie_device(
+    driver_source: &VmTaskDriverSource,
+    resolver: &ResourceResolver,
+    guest_memory: &GuestMemory,
+    port_name: &str,
+    resource: Resource<PciDeviceHandleKind>,
+    chipset_builder: &mut ChipsetBuilder<'_>,
+    doorbell_registration: Option<Arc<dyn DoorbellRegistration>>,
+    mapper: Option<&dyn guestmem::MemoryMapper>,
+    shared_guest_ram: Option<&dyn guestmem::SharedGuestRam>,
+    msi_target: &dyn MsiInterruptTarget,
+) -> anyhow::Result<()> {
+    let device_name = format!("{}:pcie-{port_name}", resource.id());
+
+    let mut msi_set = MsiInterruptSet::new();
+
+    {
+        let mut builder = chipset_builder.arc_mutex_device(device_name);
+        let mut register_mmio = builder.services().register_mmio();
+        builder
+            .on_pci_bus(BusIdPci::new(port_name))
+            .with_pci_addr(0, 0, 0)
+            .try_add_async(|_services| async {
+                resolver
+                    .resolve(
+                        resource,
+                        pci_resources::ResolvePciDeviceHandleParams {
+                            register_msi: &mut msi_set,
+                            register_mmio: &mut register_mmio,
+                            driver_source,
+                            guest_memory,
+                            doorbell_registration,
+                            shared_mem_mapper: mapper,
+                            shared_guest_ram,
+                        },
+                    )
+                    .await
+                    .map(|r| r.0)
+            })
+            .await
+            .with_context(|| format!("failed to add device to pcie port {port_name}"))?;
+    }
+
+    msi_set.connect(msi_target);
+
+    Ok(())
+}
+
+/// Resolves a PCI device resource and hot-adds the corresponding device to
+/// the PCIe root port `port_name` of `root_complex`.
+///
+/// The device's MSIs are delivered to `msi_target`.
+pub async fn hot_add_pcie_device(
+    driver_source: &VmTaskDriverSource,
+    resolver: &ResourceResolver,
+    guest_memory: &GuestMemory,
+    root_complex: &CloseableMutex<GenericPcieRootComplex>,
+    port_name: &str,
+    resource: Resource<PciDeviceHandleKind>,
+    doorbell_registration: Option<Arc<dyn DoorbellRegistration>>,
+    mapper: Option<&dyn guestmem::MemoryMapper>,
+    shared_guest_ram: Option<&dyn guestmem::SharedGuestRam>,
+    msi_target: &dyn MsiInterruptTarget,
+) -> anyhow::Result<()> {
+    let device_name = format!("{}:pcie-{port_name}", resource.id());
+
+    let mut msi_set = MsiInterruptSet::new();
+    let mut register_mmio = root_complex.lock().hot_plug_mmio();
+    let device = resolver
+        .resolve(
+            resource,
+            pci_resources::ResolvePciDeviceHandleParams {
+                register_msi: &mut msi_set,
+                register_mmio: &mut register_mmio,
+                driver_source,
+                guest_memory,
+                doorbell_registration,
+                shared_mem_mapper: mapper,
+                shared_guest_ram,
+            },
+        )
+        .await
+        .with_context(|| format!("failed to resolve device for pcie port {port_name}"))?;
+
+    msi_set.connect(msi_target);
+
+    root_complex
+        .lock()
+        .hot_add_device(port_name, device_name, device.0, register_mmio)?;
+
+    Ok(())
+}
@@ -35,6 +35,7 @@
 use thiserror::Error;
 use virt::InitialRegs;
 use virt::PageVisibility;
+use virt::VpIndex;
 use vm_topology::processor::ProcessorTopology;
 use vmcore::save_restore::ProtobufSaveRestore;
 use vmcore::save_restore::RestoreError;
@@ -75,6 +76,7 @@ struct PartitionUnitRunner {
     halt_request_recv: Receiver<InternalHaltReason>,
     client_notify_send: mesh::Sender<HaltReason>,
     req_recv: Receiver<PartitionRequest>,
+    vp_presence_recv: Receiver<(VpIndex, bool)>,
     topology: ProcessorTopology,
     initial_regs: Option<Arc<InitialRegs>>,
 
@@ -118,6 +120,8 @@ enum PartitionRequest {
     SetInitialPageVisibility(
         Rpc<Vec<(MemoryRange, PageVisibility)>, Result<(), InitialVisibilityError>>,
     ),
+    #[cfg(feature = "gdb")]
+    GetVpState(Rpc<VpIndex, anyhow::Result<Box<vmm_core_defs::debug_rpc::DebuggerVpState>>>),
 }
 
 pub struct PartitionUnitParams<'a> {
@@ -131,11 +135,40 @@ pub struct PartitionUnitParams<'a> {
     /// other reason).
     pub client_notify_send: mesh::Sender<HaltReason>,
     pub debugger_rpc: Option<Receiver<vmm_core_defs::debug_rpc::DebugRequest>>,
+    /// The receiver returned from `VpPresence::new()`, if VPs can be added
+    /// and removed at runtime.
+    pub vp_presence_recv: Option<VpPresenceReceiver>,
 }
 
 /// The halt reason receiver to pass to put in [`PartitionUnitParams`].
 pub struct HaltReasonReceiver(Receiver<InternalHaltReason>);
 
+/// An object used to add and remove VPs from the partition at runtime.
+///
+/// All VPs start out present. A VP that is not present is never run, and a
+/// running VP is stopped when it is removed.
+#[derive(Clone)]
+pub struct VpPresence(mesh::Sender<(VpIndex, bool)>);
+
+/// The VP presence receiver to put in [`PartitionUnitParams`].
+pub struct VpPresenceReceiver(Receiver<(VpIndex, bool)>);
+
+impl VpPresence {
+    /// Returns a new presence object, plus the receiver to pass to the
+    /// partition unit.
+    pub fn new() -> (Self, VpPresenceReceiver) {
+        let (send, recv) = mesh::channel();
+        (Self(send), VpPresenceReceiver(recv))
+    }
+
+    /// Sets whether VP `vp` is present.
+    ///
+    /// This takes effect asynchronously, but before the VPs are next started.
+    pub fn set_present(&self, vp: VpIndex, present: bool) {
+        self.0.send((vp, present));
+    }
+}
+
 enum InternalHaltReason {
     Halt(HaltReason),
     ReplayMtrrs,
@@ -202,6 +235,11 @@ pub fn new(
             halt_request_recv: params.halt_request_recv.0,
             client_notify_send: params.client_notify_send,
             req_recv,
+            // Without a presence object, use a closed channel so that all VPs
+            // stay present.
+            vp_presence_recv: params
+                .vp_presence_recv
+                .map_or_else(|| mesh::channel().1, |recv| recv.0),
             topology: params.processor_topology.clone(),
             initial_regs: None,
             #[cfg(feature = "gdb")]
@@ -270,6 +308,18 @@ pub async fn set_initial_page_visibility(
             .await
             .unwrap()
     }
+
+    /// Gets the register state of a VP, as seen by a debugger.
+    #[cfg(feature = "gdb")]
+    pub async fn get_vp_state(
+        &self,
+        vp: VpIndex,
+    ) -> anyhow::Result<Box<vmm_core_defs::debug_rpc::DebuggerVpState>> {
+        self.req_send
+            .call(PartitionRequest::GetVpState, vp)
+            .await
+            .unwrap()
+    }
 }
 
 impl PartitionUnitRunner {
@@ -280,6 +330,7 @@ enum Event {
                 State(Option<StateRequest>),
                 Halt(InternalHaltReason),
                 Request(PartitionRequest),
+                SetPresent((VpIndex, bool)),
                 #[cfg(feature = "gdb")]
                 Debug(vmm_core_defs::debug_rpc::DebugRequest),
             }
@@ -293,6 +344,7 @@ enum Event {
                 request = recv.next() => Event::State(request),
                 request = self.halt_request_recv.select_next_some() => Event::Halt(request),
                 request = self.req_recv.select_next_some() => Event::Request(request),
+                request = self.vp_presence_recv.select_next_some() => Event::SetPresent(request),
                 request = debug.fuse() => {
                     #[cfg(feature = "gdb")]
                     {
@@ -333,7 +385,12 @@ enum Event {
                         rpc.handle(|vis| self.set_initial_page_visibility(vis))
                             .await
                     }
+                    #[cfg(feature = "gdb")]
+                    PartitionRequest::GetVpState(rpc) => {
+                        rpc.handle(|vp| self.vp_set.get_vp_state(vp)).await
+                    }
                 },
+                Event::SetPresent((vp, present)) => self.vp_set.set_present(vp, present),
                 #[cfg(feature = "gdb")]
                 Event::Debug(request) => {
                     self.handle_gdb(request).await;
@@ -447,6 +504,11 @@ async fn set_initial_page_visibility(
 
     fn try_start(&mut self) {
         if self.started && self.halt_reason.is_none() {
+            // Apply any pending presence changes first so that VPs that have
+            // been removed do not start.
+            while let Ok((vp, present)) = self.vp_presence_recv.try_recv() {
+                self.vp_set.set_present(vp, present);
+            }
             self.vp_set.start();
         }
     }
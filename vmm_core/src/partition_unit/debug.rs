@@ -58,7 +58,9 @@ pub fn report_halt_to_debugger(&mut self, reason: &HaltReason) -> bool {
                 HaltReason::TripleFault { vp, .. }
                 | HaltReason::InvalidVmState { vp }
                 | HaltReason::VpError { vp } => DebugStopReason::TripleFault { vp: *vp },
-                HaltReason::DebugBreak { .. } => DebugStopReason::Break,
+                HaltReason::DebugBreak { .. } | HaltReason::GuestPanic { .. } => {
+                    DebugStopReason::Break
+                }
                 HaltReason::SingleStep { vp } => DebugStopReason::SingleStep { vp: *vp },
                 HaltReason::HwBreakpoint { vp, breakpoint } => DebugStopReason::HwBreakpoint {
                     vp: *vp,
@@ -127,9 +129,9 @@ pub async fn handle_gdb(&mut self, req: DebugRequest) {
             DebugRequest::ReadMemory(rpc) => {
                 rpc.handle_failable(|(addr, len)| async move {
                     match addr {
-                        GuestAddress::Gva { vp, gva } => {
+                        GuestAddress::Gva { vp, gva, cr3 } => {
                             self.vp_set
-                                .read_virtual_memory(VpIndex::new(vp), gva, len)
+                                .read_virtual_memory(VpIndex::new(vp), gva, cr3, len)
                                 .await
                         }
                         GuestAddress::Gpa(gpa) => {
@@ -147,9 +149,9 @@ pub async fn handle_gdb(&mut self, req: DebugRequest) {
             DebugRequest::WriteMemory(rpc) => {
                 rpc.handle_failable(|(addr, data)| async move {
                     match addr {
-                        GuestAddress::Gva { vp, gva } => {
+                        GuestAddress::Gva { vp, gva, cr3 } => {
                             self.vp_set
-                                .write_virtual_memory(VpIndex::new(vp), gva, data)
+                                .write_virtual_memory(VpIndex::new(vp), gva, cr3, data)
                                 .await
                         }
                         GuestAddress::Gpa(gpa) => self
@@ -161,6 +163,17 @@ pub async fn handle_gdb(&mut self, req: DebugRequest) {
                 })
                 .await
             }
+            // These need the rest of the VM. The VMM handles them before they
+            // get here if it supports them.
+            DebugRequest::GetRamRanges(rpc) => rpc
+                .handle_failable_sync(|()| Err(anyhow::anyhow!("memory layout is not available"))),
+            DebugRequest::Nmi(rpc) => {
+                rpc.handle_failable_sync(|_vp| Err(anyhow::anyhow!("nmi is not supported")))
+            }
+            DebugRequest::Reset(rpc) => {
+                rpc.handle_failable_sync(|()| Err(anyhow::anyhow!("reset is not supported")))
+            }
+            DebugRequest::Inspect(deferred) => deferred.inspect(&mut *self),
         }
     }
 }
@@ -471,6 +471,10 @@ fn get_vp_state(&mut self, vtl: Vtl) -> anyhow::Result<Box<DebuggerVpState>> {
                 ds: regs.ds,
                 fs: regs.fs,
                 gs: regs.gs,
+                tr: regs.tr,
+                ldtr: regs.ldtr,
+                gdtr: regs.gdtr,
+                idtr: regs.idtr,
             },
         )))
     }
@@ -749,6 +753,7 @@ pub fn add(&mut self, vp: TargetVpInfo) -> VpRunner {
                 vp: vp.as_ref().vp_index,
                 inner: self.inner.clone(),
                 state: VpState::Stopped,
+                present: true,
             },
         }
     }
@@ -763,6 +768,18 @@ pub fn start(&mut self) {
         }
     }
 
+    /// Sets whether VP `vp` is present in the VM.
+    ///
+    /// A VP that is not present does not run, even while the VPs are started.
+    /// A running VP that is made not present is stopped. When it is made
+    /// present again, it resumes from the state it was in, and the guest is
+    /// expected to reset it with INIT before using it.
+    pub fn set_present(&mut self, vp: VpIndex, present: bool) {
+        if let Some(vp) = self.vps.get(vp.index() as usize) {
+            vp.send.send(VpEvent::SetPresent(present));
+        }
+    }
+
     /// Initiates a halt to the VPs.
     #[cfg_attr(not(feature = "gdb"), allow(dead_code))]
     pub fn halt(&mut self, reason: HaltReason) {
@@ -954,13 +971,14 @@ pub async fn read_virtual_memory(
         &self,
         vp: VpIndex,
         gva: u64,
+        cr3: Option<u64>,
         len: usize,
     ) -> anyhow::Result<Vec<u8>> {
         self.vps[vp.index() as usize]
             .send
             .call(
                 |x| VpEvent::State(StateEvent::Debug(DebugEvent::ReadVirtualMemory(x))),
-                (gva, len),
+                (gva, cr3, len),
             )
             .await
             .map_err(RunnerGoneError)?
@@ -970,13 +988,14 @@ pub async fn write_virtual_memory(
         &self,
         vp: VpIndex,
         gva: u64,
+        cr3: Option<u64>,
         data: Vec<u8>,
     ) -> anyhow::Result<()> {
         self.vps[vp.index() as usize]
             .send
             .call(
                 |x| VpEvent::State(StateEvent::Debug(DebugEvent::WriteVirtualMemory(x))),
-                (gva, data),
+                (gva, cr3, data),
             )
             .await
             .map_err(RunnerGoneError)?
@@ -987,6 +1006,7 @@ pub async fn write_virtual_memory(
 enum VpEvent {
     Start,
     Stop(mesh::OneshotSender<()>),
+    SetPresent(bool),
     State(StateEvent),
 }
 
@@ -1006,8 +1026,8 @@ enum DebugEvent {
     SetDebugState(Rpc<Option<virt::x86::DebugState>, anyhow::Result<()>>),
     SetVpState(Rpc<Box<DebuggerVpState>, anyhow::Result<()>>),
     GetVpState(Rpc<(), anyhow::Result<Box<DebuggerVpState>>>),
-    ReadVirtualMemory(Rpc<(u64, usize), anyhow::Result<Vec<u8>>>),
-    WriteVirtualMemory(Rpc<(u64, Vec<u8>), anyhow::Result<()>>),
+    ReadVirtualMemory(Rpc<(u64, Option<u64>, usize), anyhow::Result<Vec<u8>>>),
+    WriteVirtualMemory(Rpc<(u64, Option<u64>, Vec<u8>), anyhow::Result<()>>),
 }
 
 /// An object used to dispatch a virtual processor.
@@ -1039,6 +1059,7 @@ struct RunnerInner {
     vp: VpIndex,
     inner: Arc<Inner>,
     state: VpState,
+    present: bool,
 }
 
 #[derive(Copy, Clone, Debug, Inspect, PartialEq, Eq)]
@@ -1075,8 +1096,8 @@ pub fn canceller(&self) -> RunnerCanceller {
     #[instrument(level = "debug", name = "run_vp", skip_all, fields(vp_index = self.inner.vp.index()))]
     async fn run_inner(&mut self, vp: &mut dyn ControlVp) -> Result<(), RunCancelled> {
         loop {
-            // Wait for start.
-            while self.inner.state != VpState::Running {
+            // Wait for start, and for the VP to be present.
+            while self.inner.state != VpState::Running || !self.inner.present {
                 let r = (self.recv.next().map(Ok), self.cancel_recv.next().map(Err))
                     .race()
                     .await
@@ -1087,10 +1108,11 @@ async fn run_inner(&mut self, vp: &mut dyn ControlVp) -> Result<(), RunCancelled
                         self.inner.state = VpState::Running;
                     }
                     Some(VpEvent::Stop(send)) => {
-                        assert_eq!(self.inner.state, VpState::Halted);
+                        assert_ne!(self.inner.state, VpState::Stopped);
                         self.inner.state = VpState::Stopped;
                         send.send(());
                     }
+                    Some(VpEvent::SetPresent(present)) => self.inner.present = present,
                     Some(VpEvent::State(event)) => self.inner.state_event(vp, event),
                     None => return Ok(()),
                 }
@@ -1154,6 +1176,13 @@ enum Event {
                             stop.stop();
                             stop_complete = Some(send);
                         }
+                        Event::Vp(VpEvent::SetPresent(present)) => {
+                            if !present {
+                                tracing::debug!("stopping VP due to removal");
+                                stop.stop();
+                            }
+                            self.inner.present = present;
+                        }
                         Event::Vp(VpEvent::State(event)) => {
                             // Stop the VP so that we can drop the run_vp future
                             // before manipulating state.
@@ -1219,7 +1248,8 @@ fn state_event(&mut self, vp: &mut dyn ControlVp, event: StateEvent) {
         match event {
             StateEvent::Inspect(deferred) => {
                 deferred.respond(|resp| {
-                    resp.field("state", self.state);
+                    resp.field("state", self.state)
+                        .field("present", self.present);
                     vp.inspect_vp(&self.inner.vtl_guest_memory, resp.request());
                 });
             }
@@ -1239,7 +1269,7 @@ fn state_event(&mut self, vp: &mut dyn ControlVp, event: StateEvent) {
                 DebugEvent::GetVpState(rpc) => {
                     rpc.handle_sync(|()| vp.debug().get_vp_state(Vtl::Vtl0))
                 }
-                DebugEvent::ReadVirtualMemory(rpc) => rpc.handle_sync(|(gva, len)| {
+                DebugEvent::ReadVirtualMemory(rpc) => rpc.handle_sync(|(gva, cr3, len)| {
                     let mut buf = vec![0; len];
                     vp_state::read_virtual_memory(
                         self.inner.vtl_guest_memory[0]
@@ -1248,11 +1278,12 @@ fn state_event(&mut self, vp: &mut dyn ControlVp, event: StateEvent) {
                         vp.debug(),
                         Vtl::Vtl0,
                         gva,
+                        cr3,
                         &mut buf,
                     )?;
                     Ok(buf)
                 }),
-                DebugEvent::WriteVirtualMemory(rpc) => rpc.handle_sync(|(gva, buf)| {
+                DebugEvent::WriteVirtualMemory(rpc) => rpc.handle_sync(|(gva, cr3, buf)| {
                     vp_state::write_virtual_memory(
                         self.inner.vtl_guest_memory[0]
                             .as_ref()
@@ -1260,6 +1291,7 @@ fn state_event(&mut self, vp: &mut dyn ControlVp, event: StateEvent) {
                         vp.debug(),
                         Vtl::Vtl0,
                         gva,
+                        cr3,
                         &buf,
                     )?;
                     Ok(())
@@ -1277,11 +1309,14 @@ mod vp_state {
     use hvdef::Vtl;
     use vmm_core_defs::debug_rpc::DebuggerVpState;
 
+    /// Translates `gva`, using the page tables rooted at `cr3` if specified
+    /// instead of the VP's current ones.
     fn translate_gva(
         guest_memory: &GuestMemory,
         debug: &mut dyn DebugVp,
         vtl: Vtl,
         gva: u64,
+        cr3: Option<u64>,
     ) -> anyhow::Result<u64> {
         let state = debug.get_vp_state(vtl).context("failed to get vp state")?;
 
@@ -1291,7 +1326,7 @@ fn translate_gva(
                     cr0: state.cr0,
                     cr4: state.cr4,
                     efer: state.efer,
-                    cr3: state.cr3,
+                    cr3: cr3.unwrap_or(state.cr3),
                     rflags: state.rflags,
                     ss: state.ss.into(),
                     // For debug translation, don't worry about accidentally reading
@@ -1316,6 +1351,9 @@ fn translate_gva(
                 .gpa)
             }
             DebuggerVpState::Aarch64(state) => {
+                if cr3.is_some() {
+                    anyhow::bail!("page table override is not supported on aarch64");
+                }
                 let registers = virt_support_aarch64emu::translate::TranslationRegisters {
                     cpsr: state.cpsr.into(),
                     sctlr: state.sctlr_el1.into(),
@@ -1350,11 +1388,12 @@ pub(super) fn read_virtual_memory(
         debug: &mut dyn DebugVp,
         vtl: Vtl,
         gva: u64,
+        cr3: Option<u64>,
         buf: &mut [u8],
     ) -> Result<(), anyhow::Error> {
         let mut offset = 0;
         while offset < buf.len() {
-            let gpa = translate_gva(guest_memory, debug, vtl, gva + offset as u64)
+            let gpa = translate_gva(guest_memory, debug, vtl, gva + offset as u64, cr3)
                 .context("failed to translate gva")?;
             let this_len = (buf.len() - offset).min(4096 - (gpa & 4095) as usize);
             guest_memory.read_at(gpa, &mut buf[offset..offset + this_len])?;
@@ -1368,11 +1407,12 @@ pub(super) fn write_virtual_memory(
         debug: &mut dyn DebugVp,
         vtl: Vtl,
         gva: u64,
+        cr3: Option<u64>,
         buf: &[u8],
     ) -> Result<(), anyhow::Error> {
         let mut offset = 0;
         while offset < buf.len() {
-            let gpa = translate_gva(guest_memory, debug, vtl, gva + offset as u64)
+            let gpa = translate_gva(guest_memory, debug, vtl, gva + offset as u64, cr3)
                 .context("failed to translate gva")?;
             let this_len = (buf.len() - offset).min(4096 - (gpa & 4095) as usize);
             guest_memory.write_at(gpa, &buf[offset..offset + this_len])?;
@@ -1415,8 +1455,15 @@ pub(super) fn previous_instruction(
         let mut bytes = [0u8; 16];
         // Read 16 bytes before RIP.
         let rip = regs.rip.wrapping_sub(16);
-        read_virtual_memory(guest_memory, debug, vtl, linear_ip(regs, rip), &mut bytes)
-            .context("failed to read memory")?;
+        read_virtual_memory(
+            guest_memory,
+            debug,
+            vtl,
+            linear_ip(regs, rip),
+            None,
+            &mut bytes,
+        )
+        .context("failed to read memory")?;
         let mut decoder = iced_x86::Decoder::new(bits(regs), &bytes, 0);
 
         // Try decoding at each byte until we find the instruction right before the current one.
@@ -1445,6 +1492,7 @@ pub(super) fn next_instruction(
             debug,
             vtl,
             linear_ip(regs, regs.rip),
+            None,
             &mut bytes,
         )
         .context("failed to read memory")?;
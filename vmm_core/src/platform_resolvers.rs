@@ -32,6 +32,9 @@ fn resolve(
                 vp,
                 registers: None,
             }),
+            PowerRequest::GuestPanic { crash_loaded } => {
+                halt.halt(HaltReason::GuestPanic { crash_loaded })
+            }
         })
         .into())
     }
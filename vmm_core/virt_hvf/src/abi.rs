@@ -96,6 +96,7 @@ pub fn hv_vcpu_set_pending_interrupt(
     #[allow(dead_code)]
     pub fn hv_vcpu_get_vtimer_mask(vcpu: u64, vtimer_is_masked: *mut bool) -> HvfResult;
     pub fn hv_vcpu_set_vtimer_mask(vcpu: u64, vtimer_is_masked: bool) -> HvfResult;
+    pub fn hv_vcpu_set_trap_debug_exceptions(vcpu: u64, value: bool) -> HvfResult;
 }
 
 open_enum! {
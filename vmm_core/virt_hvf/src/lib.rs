@@ -18,6 +18,7 @@
 use aarch64defs::psci::PsciError;
 use aarch64defs::psci::PSCI;
 use aarch64defs::Cpsr64;
+use aarch64defs::EsrEl2;
 use aarch64defs::ExceptionClass;
 use aarch64defs::IssDataAbort;
 use aarch64defs::IssSystem;
@@ -68,6 +69,11 @@
 
 const PPI_VTIMER: u32 = 20;
 
+/// MDSCR_EL1.SS: software step enable.
+const MDSCR_SS: u64 = 1 << 0;
+/// MDSCR_EL1.MDE: monitor debug events (hardware breakpoints/watchpoints).
+const MDSCR_MDE: u64 = 1 << 15;
+
 const HV_ARM64_HVC_SMCCC_IDENTIFIER: u32 = (1 << 30) | (6 << 24) | 1;
 
 #[derive(Debug)]
@@ -515,6 +521,7 @@ fn bind(&mut self) -> Result<Self::Processor<'_>, Self::Error> {
             vcpu,
             wfi: false,
             on: inner.vp_info.base.vp_index.is_bsp(),
+            debug: None,
             gicr: state.gicr,
             hv1: state.hv1,
             vmtime: state.vmtime,
@@ -546,6 +553,8 @@ pub struct HvfProcessor<'a> {
     vcpu: HvfVcpu,
     wfi: bool,
     on: bool,
+    #[inspect(debug)]
+    debug: Option<virt::x86::DebugState>,
 }
 
 #[derive(Debug, Inspect)]
@@ -643,6 +652,37 @@ fn set_sys_reg(&mut self, reg: abi::HvSysReg, value: u64) -> Result<(), HvfError
         }
         Ok(())
     }
+
+    /// Delivers a synchronous exception taken from EL0 or EL1 to the guest's
+    /// EL1 vector table, as the hardware would have without the trap to EL2.
+    fn inject_sync_exception(&mut self, esr: EsrEl2, far: u64) -> Result<(), HvfError> {
+        let cpsr = Cpsr64::from(self.reg(abi::HvReg::CPSR)?);
+        let pc = self.reg(abi::HvReg::PC)?;
+        let vbar = self.sys_reg(abi::HvSysReg::VBAR_EL1)?;
+        // The syndrome reports the exception as taken from a lower EL, which
+        // from the guest's view is only true when it was running at EL0.
+        let (ec, offset) = match (cpsr.el(), cpsr.sp()) {
+            (0, _) => (esr.ec(), 0x400),
+            (_, false) => (esr.ec() | 1, 0),
+            (_, true) => (esr.ec() | 1, 0x200),
+        };
+        self.set_sys_reg(abi::HvSysReg::ESR_EL1, esr.with_ec(ec).into())?;
+        self.set_sys_reg(abi::HvSysReg::FAR_EL1, far)?;
+        self.set_sys_reg(abi::HvSysReg::ELR_EL1, pc)?;
+        self.set_sys_reg(abi::HvSysReg::SPSR_EL1, cpsr.into())?;
+        self.set_reg(
+            abi::HvReg::CPSR,
+            Cpsr64::new()
+                .with_el(1)
+                .with_sp(true)
+                .with_d(true)
+                .with_a(true)
+                .with_i(true)
+                .with_f(true)
+                .into(),
+        )?;
+        self.set_reg(abi::HvReg::PC, vbar + offset)
+    }
 }
 
 impl Drop for HvfVcpu {
@@ -752,8 +792,70 @@ impl<'p> Processor for HvfProcessor<'p> {
     fn set_debug_state(
         &mut self,
         _vtl: Vtl,
-        _state: Option<&virt::x86::DebugState>,
+        state: Option<&virt::x86::DebugState>,
     ) -> Result<(), Self::Error> {
+        const BREAKPOINT_REGS: [(abi::HvSysReg, abi::HvSysReg); 4] = [
+            (abi::HvSysReg::DBGBVR0_EL1, abi::HvSysReg::DBGBCR0_EL1),
+            (abi::HvSysReg::DBGBVR1_EL1, abi::HvSysReg::DBGBCR1_EL1),
+            (abi::HvSysReg::DBGBVR2_EL1, abi::HvSysReg::DBGBCR2_EL1),
+            (abi::HvSysReg::DBGBVR3_EL1, abi::HvSysReg::DBGBCR3_EL1),
+        ];
+        const WATCHPOINT_REGS: [(abi::HvSysReg, abi::HvSysReg); 4] = [
+            (abi::HvSysReg::DBGWVR0_EL1, abi::HvSysReg::DBGWCR0_EL1),
+            (abi::HvSysReg::DBGWVR1_EL1, abi::HvSysReg::DBGWCR1_EL1),
+            (abi::HvSysReg::DBGWVR2_EL1, abi::HvSysReg::DBGWCR2_EL1),
+            (abi::HvSysReg::DBGWVR3_EL1, abi::HvSysReg::DBGWCR3_EL1),
+        ];
+
+        let mut mdscr = self.vcpu.sys_reg(abi::HvSysReg::MDSCR_EL1)?;
+        mdscr &= !(MDSCR_SS | MDSCR_MDE);
+        let breakpoints = state.map_or([None; 4], |state| state.breakpoints);
+        for (i, bp) in breakpoints.into_iter().enumerate() {
+            let (bvr, bcr) = BREAKPOINT_REGS[i];
+            let (wvr, wcr) = WATCHPOINT_REGS[i];
+            let (b, w) = match bp {
+                None => ((0, 0), (0, 0)),
+                Some(bp) => match bp.ty {
+                    virt::x86::BreakpointType::Execute => {
+                        // E, PMC = EL0 and EL1, BAS = all four bytes.
+                        ((bp.address & !3, 1 | (0b11 << 1) | (0b1111 << 5)), (0, 0))
+                    }
+                    ty => {
+                        let len = match bp.size {
+                            virt::x86::BreakpointSize::Byte => 1,
+                            virt::x86::BreakpointSize::Word => 2,
+                            virt::x86::BreakpointSize::DWord => 4,
+                            virt::x86::BreakpointSize::QWord => 8,
+                        };
+                        let lsc = if ty == virt::x86::BreakpointType::Write {
+                            0b10
+                        } else {
+                            0b11
+                        };
+                        let bas = ((1u64 << len) - 1) << (bp.address & 7);
+                        // E, PAC = EL0 and EL1, LSC, BAS.
+                        (
+                            (0, 0),
+                            (bp.address & !7, 1 | (0b11 << 1) | (lsc << 3) | (bas << 5)),
+                        )
+                    }
+                },
+            };
+            if bp.is_some() {
+                mdscr |= MDSCR_MDE;
+            }
+            self.vcpu.set_sys_reg(bvr, b.0)?;
+            self.vcpu.set_sys_reg(bcr, b.1)?;
+            self.vcpu.set_sys_reg(wvr, w.0)?;
+            self.vcpu.set_sys_reg(wcr, w.1)?;
+        }
+        if state.is_some_and(|state| state.single_step) {
+            mdscr |= MDSCR_SS;
+        }
+        self.vcpu.set_sys_reg(abi::HvSysReg::MDSCR_EL1, mdscr)?;
+        // SAFETY: no special requirements.
+        unsafe { abi::hv_vcpu_set_trap_debug_exceptions(self.vcpu.vcpu, state.is_some()) }.chk()?;
+        self.debug = state.copied();
         Ok(())
     }
 
@@ -860,6 +962,18 @@ async fn run_vp(
                 }
             }
 
+            if self.debug.is_some_and(|debug| debug.single_step) {
+                // PSTATE.SS is cleared each time a step completes, so set it
+                // again before each entry.
+                self.vcpu
+                    .reg(abi::HvReg::CPSR)
+                    .and_then(|cpsr| {
+                        self.vcpu
+                            .set_reg(abi::HvReg::CPSR, Cpsr64::from(cpsr).with_ss(true).into())
+                    })
+                    .map_err(|err| VpHaltReason::Hypervisor(err.into()))?;
+            }
+
             // SAFETY: we are not concurrently accessing `exit`.
             unsafe { abi::hv_vcpu_run(self.vcpu.vcpu) }
                 .chk()
@@ -1033,6 +1147,58 @@ async fn run_vp(
                             self.wfi = true;
                             advance(&mut self.vcpu);
                         }
+                        ExceptionClass::STEP_LOWER
+                            if self.debug.is_some_and(|debug| debug.single_step) =>
+                        {
+                            return Err(VpHaltReason::SingleStep);
+                        }
+                        ec @ (ExceptionClass::STEP_LOWER
+                        | ExceptionClass::BREAKPOINT_LOWER
+                        | ExceptionClass::WATCHPOINT_LOWER) => {
+                            let pc = self
+                                .vcpu
+                                .reg(abi::HvReg::PC)
+                                .map_err(|err| VpHaltReason::Hypervisor(err.into()))?;
+                            let bp = self
+                                .debug
+                                .iter()
+                                .flat_map(|debug| debug.breakpoints)
+                                .flatten()
+                                .find(|bp| {
+                                    if ec == ExceptionClass::STEP_LOWER {
+                                        false
+                                    } else if ec == ExceptionClass::BREAKPOINT_LOWER {
+                                        bp.ty == virt::x86::BreakpointType::Execute
+                                            && bp.address & !3 == pc
+                                    } else {
+                                        // The reported address may be anywhere
+                                        // in the 8-byte watched granule.
+                                        bp.ty != virt::x86::BreakpointType::Execute
+                                            && bp.address & !7 == exception.virtual_address & !7
+                                    }
+                                });
+                            match bp {
+                                Some(bp) => return Err(VpHaltReason::HwBreak(bp)),
+                                None => {
+                                    // Not one of ours. Reflect it to the
+                                    // guest rather than re-entering at the
+                                    // same instruction, which would just take
+                                    // the exception again.
+                                    tracelimit::warn_ratelimited!(
+                                        ?ec,
+                                        pc,
+                                        va = exception.virtual_address,
+                                        "reflecting unexpected debug exception to guest"
+                                    );
+                                    self.vcpu
+                                        .inject_sync_exception(
+                                            exception.syndrome,
+                                            exception.virtual_address,
+                                        )
+                                        .map_err(|err| VpHaltReason::Hypervisor(err.into()))?;
+                                }
+                            }
+                        }
                         class => {
                             return Err(VpHaltReason::Hypervisor(
                                 anyhow::anyhow!(
@@ -14,6 +14,8 @@
 use crate::KvmPartition;
 use crate::KvmPartitionInner;
 use crate::KvmRunVpError;
+use aarch64defs::EsrEl2;
+use aarch64defs::ExceptionClass;
 use aarch64defs::SystemReg;
 use bitfield_struct::bitfield;
 use core::panic;
@@ -37,6 +39,8 @@
 use virt::io::CpuIo;
 use virt::vp::Registers;
 use virt::vp::SystemRegisters;
+use virt::x86::BreakpointSize;
+use virt::x86::BreakpointType;
 use virt::x86::DebugState;
 use virt::NeedsYield;
 use virt::PartitionCapabilities;
@@ -219,6 +223,9 @@ pub struct KvmProcessor<'a> {
     kvm: kvm::Processor<'a>,
     vpindex: VpIndex,
     vmtime: &'a mut VmTimeAccess,
+    /// The debug state last set, to map debug exits back to breakpoints.
+    #[inspect(skip)]
+    debug: Option<DebugState>,
 }
 
 impl virt::vp::AccessVpState for &'_ mut KvmProcessor<'_> {
@@ -386,9 +393,47 @@ impl virt::Processor for KvmProcessor<'_> {
     fn set_debug_state(
         &mut self,
         _vtl: Vtl,
-        _state: Option<&DebugState>,
+        state: Option<&DebugState>,
     ) -> Result<(), Self::Error> {
-        unimplemented!()
+        let mut control = 0;
+        let mut arch = kvm::kvm_guest_debug_arch::default();
+        if let Some(state) = state {
+            control |= kvm::KVM_GUESTDBG_ENABLE;
+            if state.single_step {
+                control |= kvm::KVM_GUESTDBG_SINGLESTEP;
+            }
+            for (i, bp) in state.breakpoints.iter().enumerate() {
+                let Some(bp) = bp else { continue };
+                control |= kvm::KVM_GUESTDBG_USE_HW;
+                match bp.ty {
+                    BreakpointType::Execute => {
+                        // E, PMC = EL0 and EL1, BAS = all four bytes.
+                        arch.dbg_bvr[i] = bp.address & !3;
+                        arch.dbg_bcr[i] = 1 | (0b11 << 1) | (0b1111 << 5);
+                    }
+                    ty => {
+                        let len = match bp.size {
+                            BreakpointSize::Byte => 1,
+                            BreakpointSize::Word => 2,
+                            BreakpointSize::DWord => 4,
+                            BreakpointSize::QWord => 8,
+                        };
+                        let lsc = if ty == BreakpointType::Write {
+                            0b10
+                        } else {
+                            0b11
+                        };
+                        let bas = ((1u64 << len) - 1) << (bp.address & 7);
+                        // E, PAC = EL0 and EL1, LSC, BAS.
+                        arch.dbg_wvr[i] = bp.address & !7;
+                        arch.dbg_wcr[i] = 1 | (0b11 << 1) | (lsc << 3) | (bas << 5);
+                    }
+                }
+            }
+        }
+        self.kvm.set_guest_debug(control, &arch)?;
+        self.debug = state.copied();
+        Ok(())
     }
 
     async fn run_vp(
@@ -438,6 +483,47 @@ async fn run_vp(
                     kvm::Exit::Eoi { irq } => {
                         dev.handle_eoi(irq.into());
                     }
+                    kvm::Exit::Debug { hsr, far } => {
+                        let ec = ExceptionClass(EsrEl2::from(hsr as u64).ec());
+                        if ec == ExceptionClass::STEP_LOWER {
+                            return Err(VpHaltReason::SingleStep);
+                        }
+                        let pc = self
+                            .kvm
+                            .get_reg64(KvmRegisterId::PC.into())
+                            .map_err(|err| {
+                                VpHaltReason::Hypervisor(KvmRunVpError::Registers(err))
+                            })?;
+                        let bp = self
+                            .debug
+                            .iter()
+                            .flat_map(|debug| debug.breakpoints)
+                            .flatten()
+                            .find(|bp| match ec {
+                                ExceptionClass::BREAKPOINT_LOWER => {
+                                    bp.ty == BreakpointType::Execute && bp.address & !3 == pc
+                                }
+                                ExceptionClass::WATCHPOINT_LOWER => {
+                                    // The reported address may be anywhere in
+                                    // the 8-byte watched granule.
+                                    bp.ty != BreakpointType::Execute && bp.address & !7 == far & !7
+                                }
+                                _ => false,
+                            });
+                        match bp {
+                            Some(bp) => return Err(VpHaltReason::HwBreak(bp)),
+                            None => {
+                                // KVM owns the debug registers while guest
+                                // debugging is enabled, so this is stale.
+                                tracelimit::warn_ratelimited!(
+                                    ?ec,
+                                    pc,
+                                    far,
+                                    "debug exit with no matching breakpoint"
+                                );
+                            }
+                        }
+                    }
                     kvm::Exit::InternalError { error, .. } => {
                         return Err(VpHaltReason::Hypervisor(KvmRunVpError::InternalError(
                             error,
@@ -487,6 +573,7 @@ fn bind(&mut self) -> Result<Self::Processor<'_>, Self::Error> {
             kvm,
             vpindex: self.vpindex,
             vmtime: &mut self.vmtime,
+            debug: None,
         };
 
         Ok(vp)
@@ -96,6 +96,8 @@ pub enum KvmRunVpError {
     InvalidVpState,
     #[error("failed to run VP")]
     Run(#[source] kvm::Error),
+    #[error("failed to read VP registers")]
+    Registers(#[source] kvm::Error),
     #[error("failed to inject an extint interrupt")]
     ExtintInterrupt(#[source] kvm::Error),
 }
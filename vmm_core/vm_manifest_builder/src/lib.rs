@@ -17,9 +17,11 @@
 use chipset_resources::battery::BatteryDeviceHandleAArch64;
 use chipset_resources::battery::BatteryDeviceHandleX64;
 use chipset_resources::battery::HostBatteryUpdate;
+use chipset_resources::hpet::HpetDeviceHandle;
 use chipset_resources::i8042::I8042DeviceHandle;
 use input_core::MultiplexedInputHandle;
 use missing_dev_resources::MissingDevHandle;
+use pvpanic_resources::PvPanicIsaDeviceHandle;
 use serial_16550_resources::Serial16550DeviceHandle;
 use serial_core::resources::DisconnectedSerialBackendHandle;
 use serial_debugcon_resources::SerialDebugconDeviceHandle;
@@ -44,6 +46,9 @@ pub struct VmManifestBuilder {
     framebuffer: bool,
     guest_watchdog: bool,
     psp: bool,
+    pcie_root_complex: bool,
+    hpet: bool,
+    pvpanic: bool,
     debugcon: Option<(Resource<SerialBackendHandle>, u16)>,
 }
 
@@ -99,6 +104,12 @@ enum ErrorInner {
     UnsupportedDebugconArch,
     #[error("wait for RTS not supported with this serial type")]
     WaitForRtsNotSupported,
+    #[error("PCIe root complex only supported with Linux direct boot")]
+    PcieRootComplexNotSupported,
+    #[error("HPET only supported with x86_64 Linux direct boot")]
+    HpetNotSupported,
+    #[error("ISA pvpanic device only supported with x86_64 Linux direct boot")]
+    PvPanicNotSupported,
 }
 
 impl VmManifestBuilder {
@@ -116,6 +127,9 @@ pub fn new(ty: BaseChipsetType, arch: MachineArch) -> Self {
             framebuffer: false,
             guest_watchdog: false,
             psp: false,
+            pcie_root_complex: false,
+            hpet: false,
+            pvpanic: false,
             debugcon: None,
         }
     }
@@ -203,6 +217,33 @@ pub fn with_psp(mut self) -> Self {
         self
     }
 
+    /// Enable the generic PCIe root complex.
+    ///
+    /// This is currently only supported for VMs booting Linux directly, since
+    /// the firmware does not know how to enumerate the ECAM.
+    pub fn with_pcie_root_complex(mut self) -> Self {
+        self.pcie_root_complex = true;
+        self
+    }
+
+    /// Enable the HPET (High Precision Event Timer).
+    ///
+    /// This is currently only supported for x86_64 VMs booting Linux directly,
+    /// since the firmware does not describe the HPET to the guest.
+    pub fn with_hpet(mut self) -> Self {
+        self.hpet = true;
+        self
+    }
+
+    /// Enable the ISA pvpanic device, which the guest uses to report panics.
+    ///
+    /// This is currently only supported for x86_64 VMs booting Linux directly,
+    /// since the device is only described to the guest via the DSDT.
+    pub fn with_pvpanic(mut self) -> Self {
+        self.pvpanic = true;
+        self
+    }
+
     /// Build the VM manifest.
     pub fn build(self) -> Result<VmChipsetResult, Error> {
         let mut result = VmChipsetResult {
@@ -210,6 +251,41 @@ pub fn build(self) -> Result<VmChipsetResult, Error> {
             chipset: BaseChipsetManifest::empty(),
         };
 
+        if self.pcie_root_complex
+            && !matches!(
+                self.ty,
+                BaseChipsetType::UnenlightenedLinuxDirect | BaseChipsetType::HyperVGen2LinuxDirect
+            )
+        {
+            return Err(ErrorInner::PcieRootComplexNotSupported.into());
+        }
+
+        if self.hpet {
+            if self.arch != MachineArch::X86_64
+                || !matches!(
+                    self.ty,
+                    BaseChipsetType::UnenlightenedLinuxDirect
+                        | BaseChipsetType::HyperVGen2LinuxDirect
+                )
+            {
+                return Err(ErrorInner::HpetNotSupported.into());
+            }
+            result.attach_hpet();
+        }
+
+        if self.pvpanic {
+            if self.arch != MachineArch::X86_64
+                || !matches!(
+                    self.ty,
+                    BaseChipsetType::UnenlightenedLinuxDirect
+                        | BaseChipsetType::HyperVGen2LinuxDirect
+                )
+            {
+                return Err(ErrorInner::PvPanicNotSupported.into());
+            }
+            result.attach_pvpanic();
+        }
+
         if let Some((backend, port)) = self.debugcon {
             if matches!(self.arch, MachineArch::X86_64) {
                 result.attach_debugcon(port, backend);
@@ -235,6 +311,7 @@ pub fn build(self) -> Result<VmChipsetResult, Error> {
                     with_generic_isa_dma: true,
                     with_generic_isa_floppy: false,
                     with_generic_pci_bus: false,
+                    with_generic_pcie_root_complex: false,
                     with_generic_pic: true,
                     with_generic_pit: true,
                     with_generic_psp: false,
@@ -268,6 +345,7 @@ pub fn build(self) -> Result<VmChipsetResult, Error> {
                     with_generic_isa_dma: false,
                     with_generic_isa_floppy: false,
                     with_generic_pci_bus: is_x86,
+                    with_generic_pcie_root_complex: self.pcie_root_complex,
                     with_generic_pic: is_x86,
                     with_generic_pit: is_x86,
                     with_generic_psp: self.psp,
@@ -308,6 +386,7 @@ pub fn build(self) -> Result<VmChipsetResult, Error> {
                     with_generic_isa_dma: false,
                     with_generic_isa_floppy: false,
                     with_generic_pci_bus: false,
+                    with_generic_pcie_root_complex: self.pcie_root_complex,
                     with_generic_pic: false,
                     with_generic_pit: false,
                     with_generic_psp: self.psp,
@@ -372,6 +451,22 @@ fn attach_i8042(&mut self) -> &mut Self {
         self
     }
 
+    fn attach_hpet(&mut self) -> &mut Self {
+        self.chipset_devices.push(ChipsetDeviceHandle {
+            name: "hpet".to_owned(),
+            resource: HpetDeviceHandle.into_resource(),
+        });
+        self
+    }
+
+    fn attach_pvpanic(&mut self) -> &mut Self {
+        self.chipset_devices.push(ChipsetDeviceHandle {
+            name: "pvpanic".to_owned(),
+            resource: PvPanicIsaDeviceHandle.into_resource(),
+        });
+        self
+    }
+
     fn attach_battery(
         &mut self,
         arch: MachineArch,
@@ -4,6 +4,7 @@
 //! The message definitions used to process debugging requests from
 //! `debug_worker`.
 
+use memory_range::MemoryRange;
 use mesh::payload::Protobuf;
 pub use virt::x86::BreakpointSize;
 pub use virt::x86::BreakpointType;
@@ -13,6 +14,7 @@
 use mesh::rpc::FailableRpc;
 use mesh::MeshPayload;
 use virt::x86::SegmentRegister;
+use virt::x86::TableRegister;
 
 #[derive(Debug, MeshPayload)]
 pub enum DebugRequest {
@@ -36,6 +38,14 @@ pub enum DebugRequest {
     ReadMemory(FailableRpc<(GuestAddress, usize), Vec<u8>>),
     /// Write to the specified GPA from the guest.
     WriteMemory(FailableRpc<(GuestAddress, Vec<u8>), ()>),
+    /// Get the guest RAM ranges.
+    GetRamRanges(FailableRpc<(), Vec<MemoryRange>>),
+    /// Inject an NMI into the specified VP.
+    Nmi(FailableRpc<u32, ()>),
+    /// Reset the VM.
+    Reset(FailableRpc<(), ()>),
+    /// Inspect the VM.
+    Inspect(inspect::Deferred),
 }
 
 /// Register state for a VP.
@@ -67,6 +77,10 @@ pub struct X86VpState {
     pub ds: SegmentRegister,
     pub fs: SegmentRegister,
     pub gs: SegmentRegister,
+    pub tr: SegmentRegister,
+    pub ldtr: SegmentRegister,
+    pub gdtr: TableRegister,
+    pub idtr: TableRegister,
 }
 
 #[derive(Debug, PartialEq, Eq, Protobuf)]
@@ -86,7 +100,13 @@ pub struct Aarch64VpState {
 #[derive(Debug, MeshPayload)]
 pub enum GuestAddress {
     /// Guest Virtual Address
-    Gva { vp: u32, gva: u64 },
+    Gva {
+        vp: u32,
+        gva: u64,
+        /// Translate using this page table root instead of the VP's current
+        /// CR3. Only supported on x86_64.
+        cr3: Option<u64>,
+    },
     /// Guest Physical Address
     Gpa(u64),
 }
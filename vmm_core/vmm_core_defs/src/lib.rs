@@ -47,4 +47,10 @@ pub enum HaltReason {
         #[inspect(skip)]
         breakpoint: virt::x86::HardwareBreakpoint,
     },
+    /// The guest OS reported a panic via a paravirtualized panic device.
+    GuestPanic {
+        /// The guest has a crash kernel loaded and will try to collect a
+        /// crash dump itself.
+        crash_loaded: bool,
+    },
 }
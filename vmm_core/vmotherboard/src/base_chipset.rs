@@ -55,6 +55,7 @@ pub enum BaseChipsetBuilderError {
 #[allow(missing_docs)] // self explanatory field names
 pub struct BaseChipsetDeviceInterfaces {
     pub framebuffer_local_control: Option<FramebufferLocalControl>,
+    pub pcie_root_complex: Option<Arc<CloseableMutex<pcie::GenericPcieRootComplex>>>,
 }
 
 /// A bundle of goodies the base chipset builder returns.
@@ -186,6 +187,7 @@ pub async fn build(
 
         let mut device_interfaces = BaseChipsetDeviceInterfaces {
             framebuffer_local_control: None,
+            pcie_root_complex: None,
         };
 
         let mut builder = ChipsetBuilder::new(
@@ -206,6 +208,7 @@ pub async fn build(
             deps_generic_isa_dma,
             deps_generic_isa_floppy,
             deps_generic_pci_bus,
+            deps_generic_pcie_root_complex,
             deps_generic_pic,
             deps_generic_pit,
             deps_generic_psp: _, // not actually a device... yet
@@ -269,6 +272,49 @@ pub async fn build(
             builder.register_weak_mutex_pci_bus(bus_id, Box::new(pci));
         }
 
+        if let Some(options::dev::GenericPcieRootComplexDeps {
+            ecam_base,
+            start_bus,
+            end_bus,
+            root_ports,
+            msi_target,
+        }) = deps_generic_pcie_root_complex
+        {
+            let ports = root_ports
+                .iter()
+                .map(|port| pcie::GenericPcieRootPortDefinition {
+                    name: port.name.as_str().into(),
+                })
+                .collect();
+
+            let mut msi_set = pci_core::msi::MsiInterruptSet::new();
+            let root_complex = builder
+                .arc_mutex_device("pcie_root_complex")
+                .add(|services| {
+                    pcie::GenericPcieRootComplex::new(
+                        ecam_base,
+                        start_bus,
+                        end_bus,
+                        ports,
+                        &mut msi_set,
+                        Box::new(services.register_mmio()),
+                    )
+                })?;
+            msi_set.connect(msi_target.as_ref());
+
+            for options::dev::GenericPcieRootPortDeps { name, bus_id } in root_ports {
+                builder.register_weak_mutex_pci_bus(
+                    bus_id,
+                    Box::new(weak_mutex_pci::PcieRootPort {
+                        root_complex: root_complex.clone(),
+                        port: name.into(),
+                    }),
+                );
+            }
+
+            device_interfaces.pcie_root_complex = Some(root_complex);
+        }
+
         if let Some(options::dev::Piix4PciBusDeps { bus_id }) = deps_piix4_pci_bus {
             // TODO: use PowerRequestHandleKind
             let reset = {
@@ -746,6 +792,7 @@ macro_rules! feature_gate_check {
         for device in device_handles {
             let mut builder = builder.arc_mutex_device(device.name.as_ref());
             let services = builder.services();
+            let mut msi_set = pci_core::msi::MsiInterruptSet::new();
             let dev = resolver
                 .resolve(
                     device.resource,
@@ -758,10 +805,14 @@ macro_rules! feature_gate_check {
                         task_driver_source: driver_source,
                         register_mmio: &mut services.register_mmio(),
                         register_pio: &mut services.register_pio(),
+                        register_msi: foundation.msi_target.is_some().then_some(&mut msi_set as _),
                         configure: services,
                     },
                 )
                 .await;
+            if let Some(msi_target) = &foundation.msi_target {
+                msi_set.connect(msi_target.as_ref());
+            }
             builder.try_add(|_| dev.map(|d| d.0))?;
         }
 
@@ -861,6 +912,42 @@ fn add_pci_device(
         }
     }
 
+    /// A single root port of a [`pcie::GenericPcieRootComplex`], which accepts
+    /// a single device at address 0:0.0 on its downstream link.
+    pub struct PcieRootPort {
+        pub root_complex: Arc<CloseableMutex<pcie::GenericPcieRootComplex>>,
+        pub port: Arc<str>,
+    }
+
+    // wiring to enable using PCIe root ports alongside the Arc+CloseableMutex device infra
+    impl RegisterWeakMutexPci for PcieRootPort {
+        fn add_pci_device(
+            &mut self,
+            bus: u8,
+            device: u8,
+            function: u8,
+            name: Arc<str>,
+            dev: Weak<CloseableMutex<dyn ChipsetDevice>>,
+        ) -> Result<(), PciConflict> {
+            if (bus, device, function) != (0, 0, 0) {
+                return Err(PciConflict {
+                    bdf: (bus, device, function),
+                    reason: PciConflictReason::InvalidAddress,
+                    conflict_dev: name,
+                });
+            }
+
+            self.root_complex
+                .lock()
+                .add_pcie_device(&self.port, name.clone(), WeakMutexPciDeviceWrapper(dev))
+                .map_err(|(_, existing_dev)| PciConflict {
+                    bdf: (bus, device, function),
+                    reason: PciConflictReason::ExistingDev(existing_dev),
+                    conflict_dev: name,
+                })
+        }
+    }
+
     // wiring to enable using the PIIX4 PCI bus alongside the Arc+CloseableMutex device infra
     impl RegisterWeakMutexPci for Arc<CloseableMutex<chipset_legacy::piix4_pci_bus::Piix4PciBus>> {
         fn add_pci_device(
@@ -957,6 +1044,9 @@ pub struct BaseChipsetFoundation<'a> {
         pub vmtime: &'a VmTimeSource,
         pub vmtime_unit: &'a UnitHandle,
         pub doorbell_registration: Option<Arc<dyn DoorbellRegistration>>,
+        /// Target for MSIs signaled by chipset devices (such as HPET FSB
+        /// interrupts), if supported by the platform.
+        pub msi_target: Option<Arc<dyn pci_core::msi::MsiInterruptTarget>>,
     }
 
     macro_rules! base_chipset_devices_and_manifest {
@@ -1051,6 +1141,7 @@ pub struct BaseChipsetManifest {
             generic_isa_dma:             dev::GenericIsaDmaDeps,
             generic_isa_floppy:          dev::GenericIsaFloppyDeps,
             generic_pci_bus:             dev::GenericPciBusDeps,
+            generic_pcie_root_complex:   dev::GenericPcieRootComplexDeps,
             generic_pic:                 dev::GenericPicDeps,
             generic_pit:                 dev::GenericPitDeps,
             generic_psp:                 dev::GenericPspDeps,
@@ -1214,6 +1305,31 @@ pub struct GenericPciBusDeps {
             pub pio_data: u16,
         }
 
+        /// Generic PCI Express root complex, with ECAM based configuration
+        /// space access
+        pub struct GenericPcieRootComplexDeps {
+            /// Base address of the ECAM region
+            pub ecam_base: u64,
+            /// First bus number decoded by the ECAM region
+            pub start_bus: u8,
+            /// Last bus number decoded by the ECAM region
+            pub end_bus: u8,
+            /// Root ports, placed at consecutive device numbers on `start_bus`
+            pub root_ports: Vec<GenericPcieRootPortDeps>,
+            /// Target for the root ports' hot-plug MSIs
+            pub msi_target: Box<dyn pci_core::msi::MsiInterruptTarget>,
+        }
+
+        /// A root port on a [`GenericPcieRootComplexDeps`]
+        pub struct GenericPcieRootPortDeps {
+            /// Name of the root port
+            pub name: String,
+            /// `vmotherboard` bus identifier for the port's downstream link.
+            ///
+            /// Only a single device, at address 0:0.0, may be attached.
+            pub bus_id: BusIdPci,
+        }
+
         /// PIIX4 PCI Bus
         pub struct Piix4PciBusDeps {
             /// `vmotherboard` bus identifier
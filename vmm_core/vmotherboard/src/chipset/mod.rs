@@ -287,6 +287,7 @@ pub fn handle_eoi(&self, irq: u32) {
 pub enum PciConflictReason {
     ExistingDev(Arc<str>),
     MissingBus,
+    InvalidAddress,
 }
 
 #[derive(Debug)]
@@ -315,6 +316,14 @@ fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
                     self.conflict_dev, b, d, f
                 )
             }
+            PciConflictReason::InvalidAddress => {
+                let (b, d, f) = self.bdf;
+                write!(
+                    fmt,
+                    "cannot attach {} to {:02x}:{:02x}:{}, address not supported by the bus",
+                    self.conflict_dev, b, d, f
+                )
+            }
         }
     }
 }
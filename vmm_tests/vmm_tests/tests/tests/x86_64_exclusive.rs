@@ -39,6 +39,7 @@ async fn legacy_xapic(config: PetriVmConfig) -> Result<(), anyhow::Error> {
                     x2apic: X2ApicConfig::Unsupported,
                     apic_id_offset: 253,
                 },
+                max_proc_count: None,
             }
         })
         .run()
@@ -27,6 +27,11 @@ pub struct VmProxy {
 
     pub vps: Box<[Vp]>,
     pub breakpoints: [Option<HardwareBreakpoint>; 4],
+    /// Treat memory addresses as guest physical addresses.
+    pub physical: bool,
+    /// Translate virtual addresses using this page table root instead of the
+    /// VP's current one.
+    pub cr3: Option<u64>,
 }
 
 impl VmProxy {
@@ -36,6 +41,8 @@ pub fn new(req_chan: mesh::Sender<DebugRequest>, vp_count: u32) -> Self {
             vps: vec![Vp::default(); vp_count as usize].into(),
             stop_chan: None,
             breakpoints: [None; 4],
+            physical: false,
+            cr3: None,
         }
     }
 
@@ -64,31 +71,31 @@ pub fn vp_to_tid(&self, vp: u32) -> Tid {
         NonZeroUsize::new(vp as usize + 1).unwrap()
     }
 
-    #[allow(dead_code)] // TODO: add monitor command to inspect physical memory?
-    fn read_guest_physical_memory(&mut self, gpa: u64, data: &mut [u8]) -> anyhow::Result<()> {
-        let buf = block_on(self.req_chan.call(
-            DebugRequest::ReadMemory,
-            (GuestAddress::Gpa(gpa), data.len()),
-        ))
-        .flatten()
-        .context("failed to read memory")?;
-        data.copy_from_slice(
-            buf.get(..data.len())
-                .context("invalid return buffer length")?,
-        );
-        Ok(())
+    /// Returns the guest address for debugger address `addr` on VP
+    /// `vp_index`, according to the current addressing mode.
+    fn guest_address(&self, vp_index: u32, addr: u64) -> GuestAddress {
+        if self.physical {
+            GuestAddress::Gpa(addr)
+        } else {
+            GuestAddress::Gva {
+                vp: vp_index,
+                gva: addr,
+                cr3: self.cr3,
+            }
+        }
     }
 
-    /// Reads `len` bytes from guest VP `vp_index`'s virtual address `gva`.
-    fn read_guest_virtual_memory(
+    /// Reads `data.len()` bytes from debugger address `addr` on guest VP
+    /// `vp_index`.
+    fn read_guest_memory(
         &mut self,
         vp_index: u32,
-        gva: u64,
+        addr: u64,
         data: &mut [u8],
     ) -> anyhow::Result<()> {
         let buf = block_on(self.req_chan.call(
             DebugRequest::ReadMemory,
-            (GuestAddress::Gva { vp: vp_index, gva }, data.len()),
+            (self.guest_address(vp_index, addr), data.len()),
         ))
         .flatten()
         .context("failed to read memory")?;
@@ -99,16 +106,11 @@ fn read_guest_virtual_memory(
         Ok(())
     }
 
-    /// Writes `data` to guest VP `vp_index`'s virtual address `gva`.
-    fn write_guest_virtual_memory(
-        &mut self,
-        vp_index: u32,
-        gva: u64,
-        data: &[u8],
-    ) -> anyhow::Result<()> {
+    /// Writes `data` to debugger address `addr` on guest VP `vp_index`.
+    fn write_guest_memory(&mut self, vp_index: u32, addr: u64, data: &[u8]) -> anyhow::Result<()> {
         block_on(self.req_chan.call(
             DebugRequest::WriteMemory,
-            (GuestAddress::Gva { vp: vp_index, gva }, data.to_vec()),
+            (self.guest_address(vp_index, addr), data.to_vec()),
         ))
         .flatten()
         .context("failed to write memory")?;
@@ -58,7 +58,7 @@ fn read_addrs(
         tid: Tid,
     ) -> TargetResult<(), Self> {
         self.0
-            .read_guest_virtual_memory(self.0.tid_to_vp(tid).fatal()?, start_addr.into(), data)
+            .read_guest_memory(self.0.tid_to_vp(tid).fatal()?, start_addr.into(), data)
             .nonfatal()?;
         Ok(())
     }
@@ -70,7 +70,7 @@ fn write_addrs(
         tid: Tid,
     ) -> TargetResult<(), Self> {
         self.0
-            .write_guest_virtual_memory(self.0.tid_to_vp(tid).fatal()?, start_addr.into(), data)
+            .write_guest_memory(self.0.tid_to_vp(tid).fatal()?, start_addr.into(), data)
             .nonfatal()?;
         Ok(())
     }
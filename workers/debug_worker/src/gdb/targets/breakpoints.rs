@@ -120,13 +120,18 @@ fn add_hw_watchpoint(
         len: T::Usize,
         kind: WatchKind,
     ) -> TargetResult<bool, Self> {
-        Ok(self.add_breakpoint(HardwareBreakpoint {
-            address: addr.into(),
-            ty: type_from_watch_kind(kind),
-            size: (len.into() as usize)
-                .try_into()
-                .map_err(|_| TargetError::NonFatal)?,
-        }))
+        let Some(bps) = watchpoints(addr.into(), len.into(), kind) else {
+            return Ok(false);
+        };
+        // Add all or none of the breakpoints.
+        let free = self.breakpoints.iter().filter(|x| x.is_none()).count();
+        if bps.len() > free {
+            return Ok(false);
+        }
+        for bp in bps {
+            self.add_breakpoint(bp);
+        }
+        Ok(true)
     }
 
     fn remove_hw_watchpoint(
@@ -135,13 +140,14 @@ fn remove_hw_watchpoint(
         len: T::Usize,
         kind: WatchKind,
     ) -> TargetResult<bool, Self> {
-        Ok(self.remove_breakpoint(HardwareBreakpoint {
-            address: addr.into(),
-            ty: type_from_watch_kind(kind),
-            size: (len.into() as usize)
-                .try_into()
-                .map_err(|_| TargetError::NonFatal)?,
-        }))
+        let Some(bps) = watchpoints(addr.into(), len.into(), kind) else {
+            return Ok(false);
+        };
+        let mut removed = true;
+        for bp in bps {
+            removed &= self.remove_breakpoint(bp);
+        }
+        Ok(removed)
     }
 }
 
@@ -151,3 +157,67 @@ fn type_from_watch_kind(kind: WatchKind) -> BreakpointType {
         WatchKind::Read | WatchKind::ReadWrite => BreakpointType::ReadOrWrite,
     }
 }
+
+/// Splits a watched range into naturally aligned breakpoints of up to 8 bytes,
+/// since that is all a single debug register can cover. Returns `None` if this
+/// takes more breakpoints than there are debug registers.
+fn watchpoints(addr: u64, len: u64, kind: WatchKind) -> Option<Vec<HardwareBreakpoint>> {
+    let end = addr.checked_add(len)?;
+    let mut bps = Vec::new();
+    let mut addr = addr;
+    while addr < end {
+        let size = [8, 4, 2, 1]
+            .into_iter()
+            .find(|&size| addr & (size - 1) == 0 && end - addr >= size)
+            .unwrap();
+        if bps.len() == 4 {
+            return None;
+        }
+        bps.push(HardwareBreakpoint {
+            address: addr,
+            ty: type_from_watch_kind(kind),
+            size: (size as usize).try_into().unwrap(),
+        });
+        addr += size;
+    }
+    (!bps.is_empty()).then_some(bps)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::watchpoints;
+    use gdbstub::target::ext::breakpoints::WatchKind;
+    use vmm_core_defs::debug_rpc::BreakpointSize;
+
+    #[test]
+    fn test_watchpoints() {
+        let split = |addr, len| {
+            watchpoints(addr, len, WatchKind::Write).map(|bps| {
+                bps.iter()
+                    .map(|bp| (bp.address, bp.size))
+                    .collect::<Vec<_>>()
+            })
+        };
+        assert_eq!(
+            split(0x1000, 8),
+            Some(vec![(0x1000, BreakpointSize::QWord)])
+        );
+        assert_eq!(
+            split(0x1002, 8),
+            Some(vec![
+                (0x1002, BreakpointSize::Word),
+                (0x1004, BreakpointSize::DWord),
+                (0x1008, BreakpointSize::Word),
+            ])
+        );
+        assert_eq!(
+            split(0x1000, 16),
+            Some(vec![
+                (0x1000, BreakpointSize::QWord),
+                (0x1008, BreakpointSize::QWord),
+            ])
+        );
+        assert_eq!(split(0x1001, 32), None);
+        assert_eq!(split(0x1000, 0), None);
+    }
+}
@@ -0,0 +1,76 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Memory map support, so that the debugger can avoid accessing physical
+//! addresses outside of RAM.
+
+use super::copy_range_to_buf;
+use super::TargetArch;
+use super::ToTargetResult;
+use super::VmTarget;
+use futures::executor::block_on;
+use gdbstub::target::ext::memory_map::MemoryMap;
+use gdbstub::target::TargetResult;
+use memory_range::MemoryRange;
+use mesh::error::RemoteResultExt;
+use mesh::rpc::RpcSend;
+use std::fmt::Write;
+use vmm_core_defs::debug_rpc::DebugRequest;
+
+/// Builds the memory map XML for `ram`. Everything outside of `ram` is
+/// treated as inaccessible by the debugger.
+///
+/// ExdiGdbSrv doesn't parse XML with newlines in it, so this is a single line.
+fn memory_map_xml(ram: &[MemoryRange]) -> String {
+    let mut xml = String::from(
+        r#"<?xml version="1.0"?><!DOCTYPE memory-map PUBLIC "+//IDN gnu.org//DTD GDB Memory Map V1.0//EN" "http://sourceware.org/gdb/gdb-memory-map.dtd"><memory-map>"#,
+    );
+    for range in ram {
+        write!(
+            xml,
+            r#"<memory type="ram" start="{:#x}" length="{:#x}"/>"#,
+            range.start(),
+            range.len()
+        )
+        .unwrap();
+    }
+    xml.push_str("</memory-map>");
+    xml
+}
+
+impl<T: TargetArch> MemoryMap for VmTarget<'_, T> {
+    fn memory_map_xml(
+        &self,
+        offset: u64,
+        length: usize,
+        buf: &mut [u8],
+    ) -> TargetResult<usize, Self> {
+        let xml = if self.physical {
+            let ram = block_on(self.req_chan.call(DebugRequest::GetRamRanges, ()))
+                .flatten()
+                .nonfatal()?;
+            memory_map_xml(&ram)
+        } else {
+            // Virtual addresses can be anywhere.
+            memory_map_xml(&[MemoryRange::new(0..MemoryRange::MAX_ADDRESS)])
+        };
+        Ok(copy_range_to_buf(xml.as_bytes(), offset, length, buf))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::memory_map_xml;
+    use memory_range::MemoryRange;
+
+    #[test]
+    fn test_memory_map_xml() {
+        let xml = memory_map_xml(&[
+            MemoryRange::new(0..0xc000_0000),
+            MemoryRange::new(0x1_0000_0000..0x1_4000_0000),
+        ]);
+        assert!(xml.ends_with(
+            r#"<memory-map><memory type="ram" start="0x0" length="0xc0000000"/><memory type="ram" start="0x100000000" length="0x40000000"/></memory-map>"#
+        ));
+    }
+}
@@ -12,6 +12,8 @@
 
 mod base;
 mod breakpoints;
+mod memory_map;
+mod monitor;
 mod target_aarch64;
 mod target_i8086;
 mod target_x86_64_qemu;
@@ -48,6 +50,29 @@ fn nonfatal(self) -> Result<T, TargetError<anyhow::Error>> {
     }
 }
 
+/// Copy all bytes of `data` to `buf`.
+/// Return the size of data copied.
+fn copy_to_buf(data: &[u8], buf: &mut [u8]) -> usize {
+    let len = buf.len().min(data.len());
+    buf[..len].copy_from_slice(&data[..len]);
+    len
+}
+
+/// Copy a range of `data` (start at `offset` with a size of `length`) to `buf`.
+/// Return the size of data copied. Returns 0 if `offset >= buf.len()`.
+///
+/// Mainly used by qXfer:_object_:read commands.
+pub fn copy_range_to_buf(data: &[u8], offset: u64, length: usize, buf: &mut [u8]) -> usize {
+    let offset = offset as usize;
+    if offset > data.len() {
+        return 0;
+    }
+
+    let start = offset;
+    let end = (offset + length).min(data.len());
+    copy_to_buf(&data[start..end], buf)
+}
+
 pub struct ArchError;
 
 impl<E> From<ArchError> for TargetError<E> {
@@ -140,6 +165,20 @@ fn support_target_description_xml_override(
         T::support_target_description_xml_override(self)
     }
 
+    #[inline(always)]
+    fn support_monitor_cmd(
+        &mut self,
+    ) -> Option<gdbstub::target::ext::monitor_cmd::MonitorCmdOps<'_, Self>> {
+        Some(self)
+    }
+
+    #[inline(always)]
+    fn support_memory_map(
+        &mut self,
+    ) -> Option<gdbstub::target::ext::memory_map::MemoryMapOps<'_, Self>> {
+        Some(self)
+    }
+
     #[inline(always)]
     fn support_breakpoints(
         &mut self,
@@ -0,0 +1,186 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Custom commands, sent with `monitor` (gdb) or `.exdicmd` (WinDbg).
+
+use super::TargetArch;
+use super::VmTarget;
+use futures::executor::block_on;
+use gdbstub::outputln;
+use gdbstub::target::ext::monitor_cmd::ConsoleOutput;
+use gdbstub::target::ext::monitor_cmd::MonitorCmd;
+use inspect::InspectionBuilder;
+use mesh::error::RemoteResultExt;
+use mesh::rpc::RpcSend;
+use vmm_core_defs::debug_rpc::DebugRequest;
+
+const HELP: &str = "\
+phys [on|off]        treat addresses as guest physical addresses
+cr3 [<value>|off]    translate virtual addresses with the given page table root
+inspect [-r] [path]  inspect the VM, recursively with -r
+nmi [vp]             inject an NMI into a VP (default 0)
+reset                reset the VM
+help                 show this help";
+
+#[derive(Debug, PartialEq, Eq)]
+enum Command<'a> {
+    Help,
+    Phys(Option<bool>),
+    Cr3(Option<Option<u64>>),
+    Inspect { recursive: bool, path: &'a str },
+    Nmi(u32),
+    Reset,
+}
+
+fn parse_number(s: &str) -> Result<u64, String> {
+    let r = if let Some(hex) = s.strip_prefix("0x") {
+        u64::from_str_radix(hex, 16)
+    } else {
+        s.parse()
+    };
+    r.map_err(|_| format!("invalid number: {s}"))
+}
+
+fn parse_command(cmd: &str) -> Result<Command<'_>, String> {
+    let mut args = cmd.split_whitespace();
+    let command = match args.next().unwrap_or("help") {
+        "help" => Command::Help,
+        "phys" => Command::Phys(match args.next() {
+            None => None,
+            Some("on") => Some(true),
+            Some("off") => Some(false),
+            Some(arg) => return Err(format!("invalid argument: {arg}")),
+        }),
+        "cr3" => Command::Cr3(match args.next() {
+            None => None,
+            Some("off") => Some(None),
+            Some(arg) => Some(Some(parse_number(arg)?)),
+        }),
+        "inspect" => {
+            let mut path = args.next().unwrap_or("");
+            let recursive = path == "-r";
+            if recursive {
+                path = args.next().unwrap_or("");
+            }
+            Command::Inspect { recursive, path }
+        }
+        "nmi" => Command::Nmi(match args.next() {
+            None => 0,
+            Some(arg) => parse_number(arg)?
+                .try_into()
+                .map_err(|_| format!("invalid vp: {arg}"))?,
+        }),
+        "reset" => Command::Reset,
+        command => return Err(format!("unknown command: {command}")),
+    };
+    if let Some(arg) = args.next() {
+        return Err(format!("unexpected argument: {arg}"));
+    }
+    Ok(command)
+}
+
+impl<T: TargetArch> VmTarget<'_, T> {
+    fn run_command(&mut self, command: Command<'_>, out: &mut ConsoleOutput<'_>) {
+        match command {
+            Command::Help => outputln!(out, "{HELP}"),
+            Command::Phys(physical) => {
+                if let Some(physical) = physical {
+                    self.0.physical = physical;
+                }
+                outputln!(
+                    out,
+                    "addresses are {}",
+                    if self.0.physical {
+                        "guest physical"
+                    } else {
+                        "guest virtual"
+                    }
+                );
+            }
+            Command::Cr3(cr3) => {
+                if let Some(cr3) = cr3 {
+                    self.0.cr3 = cr3;
+                }
+                match self.0.cr3 {
+                    Some(cr3) => outputln!(out, "translating with cr3 {cr3:#x}"),
+                    None => outputln!(out, "translating with the vp's cr3"),
+                }
+            }
+            Command::Inspect { recursive, path } => {
+                let req_chan = &self.0.req_chan;
+                let mut inspection = InspectionBuilder::new(path)
+                    .depth(if recursive { None } else { Some(0) })
+                    .inspect(inspect::adhoc_mut(|req| {
+                        req_chan.send(DebugRequest::Inspect(req.defer()))
+                    }));
+                block_on(inspection.resolve());
+                outputln!(out, "{:#}", inspection.results());
+            }
+            Command::Nmi(vp) => {
+                if let Err(err) = block_on(self.0.req_chan.call(DebugRequest::Nmi, vp)).flatten() {
+                    outputln!(out, "error: {:#}", anyhow::Error::from(err));
+                }
+            }
+            Command::Reset => {
+                if let Err(err) = block_on(self.0.req_chan.call(DebugRequest::Reset, ())).flatten()
+                {
+                    outputln!(out, "error: {:#}", anyhow::Error::from(err));
+                }
+            }
+        }
+    }
+}
+
+impl<T: TargetArch> MonitorCmd for VmTarget<'_, T> {
+    fn handle_monitor_cmd(
+        &mut self,
+        cmd: &[u8],
+        mut out: ConsoleOutput<'_>,
+    ) -> Result<(), Self::Error> {
+        match std::str::from_utf8(cmd)
+            .map_err(|_| "invalid command".to_string())
+            .and_then(parse_command)
+        {
+            Ok(command) => self.run_command(command, &mut out),
+            Err(err) => outputln!(out, "{err}\n{HELP}"),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_command;
+    use super::Command;
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(parse_command(""), Ok(Command::Help));
+        assert_eq!(parse_command("phys"), Ok(Command::Phys(None)));
+        assert_eq!(parse_command("phys on"), Ok(Command::Phys(Some(true))));
+        assert_eq!(
+            parse_command("cr3 0x1ad000"),
+            Ok(Command::Cr3(Some(Some(0x1ad000))))
+        );
+        assert_eq!(parse_command("cr3 off"), Ok(Command::Cr3(Some(None))));
+        assert_eq!(
+            parse_command("inspect -r vm/chipset"),
+            Ok(Command::Inspect {
+                recursive: true,
+                path: "vm/chipset"
+            })
+        );
+        assert_eq!(
+            parse_command("inspect"),
+            Ok(Command::Inspect {
+                recursive: false,
+                path: ""
+            })
+        );
+        assert_eq!(parse_command("nmi 2"), Ok(Command::Nmi(2)));
+        assert!(parse_command("phys maybe").is_err());
+        assert!(parse_command("reset now").is_err());
+        assert!(parse_command("cr3 xyz").is_err());
+        assert!(parse_command("frobnicate").is_err());
+    }
+}
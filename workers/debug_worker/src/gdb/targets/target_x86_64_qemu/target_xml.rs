@@ -2,34 +2,12 @@
 // Licensed under the MIT License.
 
 use crate::gdb::arch::x86::X86_64_QEMU;
+use crate::gdb::targets::copy_range_to_buf;
 use crate::gdb::targets::VmTarget;
 use gdbstub::target;
 use gdbstub::target::TargetError;
 use gdbstub::target::TargetResult;
 
-/// Copy all bytes of `data` to `buf`.
-/// Return the size of data copied.
-fn copy_to_buf(data: &[u8], buf: &mut [u8]) -> usize {
-    let len = buf.len().min(data.len());
-    buf[..len].copy_from_slice(&data[..len]);
-    len
-}
-
-/// Copy a range of `data` (start at `offset` with a size of `length`) to `buf`.
-/// Return the size of data copied. Returns 0 if `offset >= buf.len()`.
-///
-/// Mainly used by qXfer:_object_:read commands.
-fn copy_range_to_buf(data: &[u8], offset: u64, length: usize, buf: &mut [u8]) -> usize {
-    let offset = offset as usize;
-    if offset > data.len() {
-        return 0;
-    }
-
-    let start = offset;
-    let end = (offset + length).min(data.len());
-    copy_to_buf(&data[start..end], buf)
-}
-
 impl target::ext::target_description_xml_override::TargetDescriptionXmlOverride
     for VmTarget<'_, X86_64_QEMU>
 {
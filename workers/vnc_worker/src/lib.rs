@@ -23,12 +23,16 @@
 use std::pin::Pin;
 use std::time::Duration;
 use tracing_helpers::AnyhowValueExt;
+use vnc_worker_defs::VncAuth;
 use vnc_worker_defs::VncParameters;
 
 /// A worker for running a VNC server.
 pub struct VncWorker<T: Listener> {
     listener: T,
+    websocket_listener: Option<T>,
     state: State<T>,
+    auth_params: VncAuth,
+    auth: vnc::Auth,
 }
 
 /// The current server state.
@@ -39,6 +43,7 @@ enum State<T: Listener> {
     },
     Connected {
         remote_addr: T::Address,
+        transport: vnc::Transport,
         task: Pin<Box<dyn Future<Output = (ViewWrapper, VncInput)>>>,
         abort: mesh::OneshotSender<()>,
     },
@@ -84,8 +89,29 @@ fn run(self, rpc_recv: mesh::Receiver<WorkerRpc<Self::State>>) -> anyhow::Result
 
 impl<T: 'static + Listener + MeshField + Send> VncWorker<T> {
     fn new_inner(params: VncParameters<T>) -> anyhow::Result<Self> {
+        let auth_params = params.auth;
+        if auth_params.username.is_some()
+            && (auth_params.password.is_none() || auth_params.tls.is_none())
+        {
+            anyhow::bail!("VNC username authentication requires a password and TLS");
+        }
+        let tls = auth_params
+            .tls
+            .as_ref()
+            .map(|tls| vnc::TlsAcceptor::new(&tls.cert_chain_pem, &tls.key_pem))
+            .transpose()
+            .context("failed to load VNC TLS certificate")?;
+        let auth = vnc::Auth {
+            password: auth_params.password.clone(),
+            username: auth_params.username.clone(),
+            tls,
+            allowed_origins: auth_params.allowed_origins.clone(),
+        };
         Ok(Self {
             listener: params.listener,
+            websocket_listener: params.websocket_listener,
+            auth_params,
+            auth,
             state: State::Listening {
                 view: ViewWrapper(
                     params
@@ -110,10 +136,23 @@ fn run_inner(
                 "VNC server listening",
             );
 
+            if let Some(listener) = &self.websocket_listener {
+                tracing::info!(
+                    address = ?listener.local_addr().unwrap(),
+                    "VNC server listening for WebSocket connections",
+                );
+            }
+
             let listener = PolledSocket::new(&driver, self.listener)?;
+            let websocket_listener = self
+                .websocket_listener
+                .map(|listener| PolledSocket::new(&driver, listener))
+                .transpose()?;
             let mut server = Server {
                 listener,
+                websocket_listener,
                 state: self.state,
+                auth: self.auth,
             };
 
             let response = loop {
@@ -141,8 +180,10 @@ fn run_inner(
                 };
                 let state = VncParameters {
                     listener: server.listener.into_inner(),
+                    websocket_listener: server.websocket_listener.map(|l| l.into_inner()),
                     framebuffer: view.0.access(),
                     input_send: input.send,
+                    auth: self.auth_params,
                 };
                 response.send(Ok(state));
             }
@@ -153,7 +194,9 @@ fn run_inner(
 
 struct Server<T: Listener> {
     listener: PolledSocket<T>,
+    websocket_listener: Option<PolledSocket<T>>,
     state: State<T>,
+    auth: vnc::Auth,
 }
 
 impl<T: Listener> Server<T> {
@@ -167,10 +210,26 @@ async fn process(&mut self, driver: &LocalDriver) -> anyhow::Result<()> {
             match &mut self.state {
                 State::Listening { .. } => {
                     // Accept the connection if one is really ready.
-                    let (socket, remote_addr) = self.listener.accept().await?;
+                    let websocket_listener = &mut self.websocket_listener;
+                    let websocket_accept = async {
+                        match websocket_listener {
+                            Some(listener) => listener.accept().await,
+                            None => std::future::pending().await,
+                        }
+                    };
+                    let (socket, remote_addr, transport) = futures::select! { // race semantics
+                        r = self.listener.accept().fuse() => {
+                            let (socket, remote_addr) = r?;
+                            (socket, remote_addr, vnc::Transport::Raw)
+                        }
+                        r = websocket_accept.fuse() => {
+                            let (socket, remote_addr) = r?;
+                            (socket, remote_addr, vnc::Transport::WebSocket)
+                        }
+                    };
                     let socket = PolledSocket::new(driver, socket.into())?;
 
-                    tracing::info!(address = ?remote_addr, "VNC client connected");
+                    tracing::info!(address = ?remote_addr, ?transport, "VNC client connected");
 
                     let (view, input) = if let State::Listening { view, input } =
                         std::mem::replace(&mut self.state, State::Invalid)
@@ -180,7 +239,9 @@ async fn process(&mut self, driver: &LocalDriver) -> anyhow::Result<()> {
                         unreachable!()
                     };
 
-                    let mut vncserver = vnc::Server::new("HvLite VM".into(), socket, view, input);
+                    let mut vncserver = vnc::Server::new("HvLite VM".into(), socket, view, input)
+                        .with_auth(self.auth.clone())
+                        .with_transport(transport);
                     let mut timer = PolledTimer::new(driver);
 
                     let (abort_send, abort_recv) = mesh::oneshot();
@@ -211,6 +272,7 @@ async fn process(&mut self, driver: &LocalDriver) -> anyhow::Result<()> {
                     });
                     self.state = State::Connected {
                         remote_addr,
+                        transport,
                         task: connection,
                         abort: abort_send,
                     };
@@ -229,15 +291,25 @@ impl<T: Listener> inspect::Inspect for Server<T> {
     fn inspect(&self, req: inspect::Request<'_>) {
         let mut resp = req.respond();
         resp.display_debug("local_addr", &self.listener.get().local_addr().unwrap());
+        if let Some(listener) = &self.websocket_listener {
+            resp.display_debug("websocket_addr", &listener.get().local_addr().unwrap());
+        }
         let state = match &self.state {
             State::Listening { .. } => "listening",
-            State::Connected { remote_addr, .. } => {
+            State::Connected {
+                remote_addr,
+                transport,
+                ..
+            } => {
                 resp.display_debug("remote_addr", &remote_addr);
+                resp.display_debug("transport", transport);
                 "connected"
             }
             State::Invalid => unreachable!(),
         };
         resp.field("state", state);
+        resp.field("tls", self.auth.tls.is_some());
+        resp.field("password", self.auth.password.is_some());
     }
 }
 
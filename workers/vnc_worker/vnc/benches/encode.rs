@@ -0,0 +1,169 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Benchmarks for change detection and encoding, replaying synthetic frame
+//! sequences.
+
+use criterion::black_box;
+use criterion::criterion_group;
+use criterion::criterion_main;
+use criterion::BenchmarkId;
+use criterion::Criterion;
+use criterion::Throughput;
+use vnc::DirtyTracker;
+use vnc::Encoder;
+use vnc::Encoding;
+use zerocopy::AsBytes;
+
+criterion_main!(benches);
+
+criterion_group!(benches, replay);
+
+const WIDTH: usize = 1024;
+const HEIGHT: usize = 768;
+const FRAME_COUNT: usize = 30;
+
+const BACKGROUND: u32 = 0x204060;
+const TERMINAL: u32 = 0x101010;
+const TEXT: u32 = 0xc0c0c0;
+
+/// Replays a sequence of frames.
+struct Replay<'a> {
+    frame: &'a [u32],
+}
+
+impl vnc::Framebuffer for Replay<'_> {
+    fn resolution(&mut self) -> (u16, u16) {
+        (WIDTH as u16, HEIGHT as u16)
+    }
+
+    fn read_line(&mut self, line: u16, data: &mut [u8]) {
+        let start = line as usize * WIDTH;
+        data.copy_from_slice(self.frame[start..start + WIDTH].as_bytes());
+    }
+}
+
+fn fill(frame: &mut [u32], x: usize, y: usize, width: usize, height: usize, color: u32) {
+    for row in frame[y * WIDTH..].chunks_exact_mut(WIDTH).take(height) {
+        row[x..x + width].fill(color);
+    }
+}
+
+/// Draws an 8x16 pseudo-glyph for `c`.
+fn glyph(frame: &mut [u32], x: usize, y: usize, c: u32) {
+    for gy in 2..14 {
+        for gx in 1..7 {
+            let bit = (c.wrapping_mul(2654435761) >> ((gy * 3 + gx) % 29)) & 1;
+            frame[(y + gy) * WIDTH + x + gx] = if bit != 0 { TEXT } else { TERMINAL };
+        }
+    }
+}
+
+/// A desktop with a terminal window showing `lines` of text, starting at
+/// text line `first`.
+fn desktop(first: usize, lines: usize, columns: usize) -> Vec<u32> {
+    let mut frame = vec![BACKGROUND; WIDTH * HEIGHT];
+    fill(&mut frame, 64, 64, 640, 480, TERMINAL);
+    for line in 0..lines {
+        for column in 0..columns {
+            let c = ((first + line) * 80 + column) as u32;
+            glyph(&mut frame, 64 + column * 8, 64 + line * 16, c);
+        }
+    }
+    frame
+}
+
+/// Returns named frame sequences, each starting with a full frame.
+fn scenarios() -> Vec<(&'static str, Vec<Vec<u32>>)> {
+    let idle = vec![desktop(0, 30, 80); FRAME_COUNT];
+
+    // Characters appearing one at a time.
+    let typing = (0..FRAME_COUNT)
+        .map(|i| {
+            let mut frame = desktop(0, 29, 80);
+            for column in 0..i {
+                glyph(&mut frame, 64 + column * 8, 64 + 29 * 16, column as u32);
+            }
+            frame
+        })
+        .collect();
+
+    // The terminal scrolling by a line every frame.
+    let scrolling = (0..FRAME_COUNT).map(|i| desktop(i, 30, 80)).collect();
+
+    // A window being dragged across a patterned background.
+    let dragging = (0..FRAME_COUNT)
+        .map(|i| {
+            let mut frame = desktop(0, 30, 80);
+            fill(&mut frame, 100 + i * 10, 200 + i * 5, 300, 200, 0xe0e0e0);
+            fill(&mut frame, 100 + i * 10, 200 + i * 5, 300, 20, 0x0050a0);
+            frame
+        })
+        .collect();
+
+    // Video playing in a region of the screen.
+    let mut seed = 1u32;
+    let video = (0..FRAME_COUNT)
+        .map(|_| {
+            let mut frame = desktop(0, 30, 80);
+            for row in frame[300 * WIDTH..].chunks_exact_mut(WIDTH).take(240) {
+                for p in &mut row[600..920] {
+                    seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                    *p = seed >> 8;
+                }
+            }
+            frame
+        })
+        .collect();
+
+    vec![
+        ("idle", idle),
+        ("typing", typing),
+        ("scrolling", scrolling),
+        ("dragging", dragging),
+        ("video", video),
+    ]
+}
+
+/// Runs change detection and encoding over `frames`, returning the number of
+/// bytes sent to the client.
+fn run(frames: &[Vec<u32>], encoding: Encoding) -> usize {
+    let mut tracker = DirtyTracker::new();
+    let mut encoder = Encoder::new(encoding);
+    let mut out = Vec::new();
+    let mut total = 0;
+    for frame in frames {
+        let rects = tracker.update(&mut Replay { frame }, WIDTH as u16, HEIGHT as u16);
+        out.clear();
+        for rect in rects {
+            encoder.encode(tracker.frame(), WIDTH, rect, &mut out);
+        }
+        total += out.len();
+    }
+    total
+}
+
+fn replay(c: &mut Criterion) {
+    for (name, frames) in scenarios() {
+        let mut group = c.benchmark_group(name);
+        group.throughput(Throughput::Elements(frames.len() as u64));
+        for encoding in [
+            Encoding::Raw,
+            Encoding::Hextile,
+            Encoding::Zlib,
+            Encoding::Zrle,
+        ] {
+            println!(
+                "{name}/{encoding:?}: {} bytes for {} frames",
+                run(&frames, encoding),
+                frames.len()
+            );
+            group.bench_with_input(
+                BenchmarkId::from_parameter(format!("{encoding:?}")),
+                &frames,
+                |b, frames| b.iter(|| black_box(run(frames, encoding))),
+            );
+        }
+        group.finish();
+    }
+}
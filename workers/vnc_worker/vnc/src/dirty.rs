@@ -0,0 +1,223 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Change detection between framebuffer updates.
+
+use crate::Framebuffer;
+use zerocopy::AsBytes;
+
+/// The size of the square tiles used to detect changes.
+const TILE_SIZE: u16 = 64;
+
+/// A rectangle within the framebuffer.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Rect {
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+}
+
+/// Tracks the framebuffer contents last sent to the client, to find the
+/// regions that have changed since.
+pub struct DirtyTracker {
+    width: u16,
+    height: u16,
+    frame: Vec<u32>,
+    previous: Vec<u32>,
+    valid: bool,
+}
+
+impl DirtyTracker {
+    pub fn new() -> Self {
+        Self {
+            width: 0,
+            height: 0,
+            frame: Vec::new(),
+            previous: Vec::new(),
+            valid: false,
+        }
+    }
+
+    /// Forgets the last sent frame, so that the next update includes the
+    /// whole framebuffer.
+    pub fn invalidate(&mut self) {
+        self.valid = false;
+    }
+
+    /// Reads the current contents of `fb`, which has resolution `width` by
+    /// `height`, and returns the regions that differ from the frame read by
+    /// the previous call.
+    pub fn update(&mut self, fb: &mut impl Framebuffer, width: u16, height: u16) -> Vec<Rect> {
+        if width != self.width || height != self.height {
+            self.width = width;
+            self.height = height;
+            self.valid = false;
+        }
+        if width == 0 || height == 0 {
+            return Vec::new();
+        }
+
+        std::mem::swap(&mut self.frame, &mut self.previous);
+        let stride = width as usize;
+        self.frame.resize(stride * height as usize, 0);
+        for (y, line) in self.frame.chunks_exact_mut(stride).enumerate() {
+            fb.read_line(y as u16, line.as_bytes_mut());
+        }
+
+        if !std::mem::replace(&mut self.valid, true) {
+            return vec![Rect {
+                x: 0,
+                y: 0,
+                width,
+                height,
+            }];
+        }
+
+        let mut rects = Vec::<Rect>::new();
+        for y in (0..height).step_by(TILE_SIZE.into()) {
+            let tile_height = TILE_SIZE.min(height - y);
+            let mut run_start = None;
+            for x in (0..width).step_by(TILE_SIZE.into()).chain([width]) {
+                let dirty = x < width && self.tile_dirty(x, y, tile_height);
+                match (run_start, dirty) {
+                    (None, true) => run_start = Some(x),
+                    (Some(start), false) => {
+                        run_start = None;
+                        // Extend a rectangle from the previous row of tiles
+                        // if it spans the same columns.
+                        let run = Rect {
+                            x: start,
+                            y,
+                            width: x - start,
+                            height: tile_height,
+                        };
+                        if let Some(rect) = rects.iter_mut().find(|r| {
+                            r.x == run.x && r.width == run.width && r.y + r.height == run.y
+                        }) {
+                            rect.height += run.height;
+                        } else {
+                            rects.push(run);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        rects
+    }
+
+    fn tile_dirty(&self, x: u16, y: u16, tile_height: u16) -> bool {
+        let stride = self.width as usize;
+        let x0 = x as usize;
+        let x1 = (x + TILE_SIZE.min(self.width - x)) as usize;
+        (y..y + tile_height).any(|y| {
+            let offset = y as usize * stride;
+            self.frame[offset + x0..offset + x1] != self.previous[offset + x0..offset + x1]
+        })
+    }
+
+    /// The current frame, in rows of `width` pixels in `0x00RRGGBB` format.
+    pub fn frame(&self) -> &[u32] {
+        &self.frame
+    }
+
+    /// The width of the current frame.
+    pub fn width(&self) -> u16 {
+        self.width
+    }
+}
+
+impl Default for DirtyTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DirtyTracker;
+    use super::Rect;
+    use crate::Framebuffer;
+    use zerocopy::AsBytes;
+
+    struct TestFramebuffer {
+        width: u16,
+        pixels: Vec<u32>,
+    }
+
+    impl Framebuffer for TestFramebuffer {
+        fn resolution(&mut self) -> (u16, u16) {
+            unreachable!()
+        }
+
+        fn read_line(&mut self, line: u16, data: &mut [u8]) {
+            let start = line as usize * self.width as usize;
+            data.copy_from_slice(self.pixels[start..start + self.width as usize].as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_dirty_tiles() {
+        let (width, height) = (300, 200);
+        let mut fb = TestFramebuffer {
+            width,
+            pixels: vec![0; width as usize * height as usize],
+        };
+        let mut tracker = DirtyTracker::new();
+        let full = Rect {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        };
+        assert_eq!(tracker.update(&mut fb, width, height), [full]);
+        assert!(tracker.update(&mut fb, width, height).is_empty());
+
+        // A change in the last, partial tile.
+        fb.pixels[199 * 300 + 299] = 1;
+        assert_eq!(
+            tracker.update(&mut fb, width, height),
+            [Rect {
+                x: 256,
+                y: 192,
+                width: 44,
+                height: 8
+            }]
+        );
+
+        // Changes spanning tiles vertically and horizontally are merged.
+        for y in 10..150 {
+            fb.pixels[y * 300 + 70] = 2;
+            fb.pixels[y * 300 + 130] = 2;
+        }
+        assert_eq!(
+            tracker.update(&mut fb, width, height),
+            [Rect {
+                x: 64,
+                y: 0,
+                width: 128,
+                height: 192
+            }]
+        );
+
+        // The tracker compares against the previous frame.
+        assert!(tracker.update(&mut fb, width, height).is_empty());
+
+        tracker.invalidate();
+        assert_eq!(tracker.update(&mut fb, width, height), [full]);
+
+        // A resolution change resets the tracker.
+        fb.width = 100;
+        fb.pixels.truncate(100 * 100);
+        assert_eq!(
+            tracker.update(&mut fb, 100, 100),
+            [Rect {
+                x: 0,
+                y: 0,
+                width: 100,
+                height: 100
+            }]
+        );
+    }
+}
@@ -0,0 +1,907 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Framebuffer update encodings.
+
+use crate::dirty::Rect;
+use crate::rfb;
+use flate2::Compress;
+use flate2::Compression;
+use flate2::FlushCompress;
+use std::ops::Range;
+use zerocopy::AsBytes;
+
+/// The pixel format used by the server until the client requests another.
+pub(crate) const SERVER_PIXEL_FORMAT: rfb::PixelFormat = rfb::PixelFormat {
+    bits_per_pixel: 32,
+    depth: 24,
+    big_endian_flag: 0,
+    true_color_flag: 1,
+    red_max: zerocopy::U16::new(255),
+    green_max: zerocopy::U16::new(255),
+    blue_max: zerocopy::U16::new(255),
+    red_shift: 16,
+    green_shift: 8,
+    blue_shift: 0,
+    padding: [0; 3],
+};
+
+const HEXTILE_SIZE: u16 = 16;
+const HEXTILE_RAW: u8 = 1;
+const HEXTILE_BACKGROUND_SPECIFIED: u8 = 2;
+const HEXTILE_FOREGROUND_SPECIFIED: u8 = 4;
+const HEXTILE_ANY_SUBRECTS: u8 = 8;
+const HEXTILE_SUBRECTS_COLOURED: u8 = 16;
+
+const ZRLE_TILE_SIZE: u16 = 64;
+const ZRLE_RAW: u8 = 0;
+const ZRLE_SOLID: u8 = 1;
+/// Set in the subencoding for run-length encoded tiles. The low bits are the
+/// palette size, or zero for plain RLE.
+const ZRLE_RLE: u8 = 128;
+const ZRLE_MAX_PALETTE: usize = 127;
+const ZRLE_MAX_PACKED_PALETTE: usize = 16;
+
+/// An encoding for framebuffer rectangles.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Encoding {
+    Raw,
+    Hextile,
+    Zlib,
+    Zrle,
+}
+
+impl Encoding {
+    /// Returns the first supported encoding in `encodings`, which the client
+    /// lists in order of preference.
+    pub(crate) fn choose(encodings: &[u32]) -> Self {
+        encodings
+            .iter()
+            .find_map(|&encoding| match encoding {
+                rfb::ENCODING_TYPE_RAW => Some(Self::Raw),
+                rfb::ENCODING_TYPE_HEXTILE => Some(Self::Hextile),
+                rfb::ENCODING_TYPE_ZLIB => Some(Self::Zlib),
+                rfb::ENCODING_TYPE_ZRLE => Some(Self::Zrle),
+                _ => None,
+            })
+            .unwrap_or(Self::Raw)
+    }
+
+    fn encoding_type(&self) -> u32 {
+        match self {
+            Self::Raw => rfb::ENCODING_TYPE_RAW,
+            Self::Hextile => rfb::ENCODING_TYPE_HEXTILE,
+            Self::Zlib => rfb::ENCODING_TYPE_ZLIB,
+            Self::Zrle => rfb::ENCODING_TYPE_ZRLE,
+        }
+    }
+}
+
+/// Converts pixels from the framebuffer's `0x00RRGGBB` format to the client's
+/// pixel format.
+#[derive(Debug, Clone)]
+struct PixelConverter {
+    big_endian: bool,
+    /// The number of bits in each of the red, green, and blue channels.
+    bits: [u32; 3],
+    shifts: [u32; 3],
+    /// The bytes of the big or little endian `u32` that make up a pixel.
+    pixel: Range<usize>,
+    /// The bytes that make up a ZRLE compressed pixel.
+    cpixel: Range<usize>,
+    /// The conversion is the identity on this host.
+    identity: bool,
+}
+
+impl PixelConverter {
+    fn new(format: &rfb::PixelFormat) -> Self {
+        let bytes = (format.bits_per_pixel / 8).clamp(1, 4) as usize;
+        let big_endian = format.big_endian_flag != 0;
+        let maxes = [
+            format.red_max.get(),
+            format.green_max.get(),
+            format.blue_max.get(),
+        ];
+        let bits = maxes.map(|max| 16 - max.leading_zeros());
+        let shifts = [format.red_shift, format.green_shift, format.blue_shift].map(u32::from);
+        let pixel = if big_endian { 4 - bytes..4 } else { 0..bytes };
+
+        // Compressed pixels drop an unused byte from 32-bit pixels.
+        let used = bits.iter().zip(&shifts).fold(0u64, |acc, (&bits, &shift)| {
+            acc | (((1 << bits) - 1) << shift.min(32))
+        });
+        let cpixel = if bytes == 4 && format.depth <= 24 && used < 1 << 24 {
+            if big_endian {
+                1..4
+            } else {
+                0..3
+            }
+        } else if bytes == 4 && format.depth <= 24 && used & 0xff == 0 {
+            if big_endian {
+                0..3
+            } else {
+                1..4
+            }
+        } else {
+            pixel.clone()
+        };
+
+        let identity = bytes == 4
+            && big_endian == cfg!(target_endian = "big")
+            && bits == [8; 3]
+            && shifts == [16, 8, 0];
+
+        Self {
+            big_endian,
+            bits,
+            shifts,
+            pixel,
+            cpixel,
+            identity,
+        }
+    }
+
+    fn convert(&self, p: u32) -> [u8; 4] {
+        let mut v = 0;
+        for ((bits, shift), channel_shift) in self.bits.iter().zip(&self.shifts).zip([16, 8, 0]) {
+            let c = (p >> channel_shift) & 0xff;
+            let c = if *bits <= 8 {
+                c >> (8 - bits)
+            } else {
+                c << (bits - 8)
+            };
+            v |= c << shift;
+        }
+        if self.big_endian {
+            v.to_be_bytes()
+        } else {
+            v.to_le_bytes()
+        }
+    }
+
+    fn bytes_per_pixel(&self) -> usize {
+        self.pixel.len()
+    }
+
+    fn bytes_per_cpixel(&self) -> usize {
+        self.cpixel.len()
+    }
+
+    fn write_pixel(&self, p: u32, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.convert(p)[self.pixel.clone()]);
+    }
+
+    fn write_cpixel(&self, p: u32, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.convert(p)[self.cpixel.clone()]);
+    }
+
+    fn write_pixels(&self, pixels: &[u32], out: &mut Vec<u8>) {
+        if self.identity {
+            out.extend_from_slice(pixels.as_bytes());
+        } else {
+            for &p in pixels {
+                self.write_pixel(p, out);
+            }
+        }
+    }
+}
+
+/// Encodes framebuffer rectangles for a single client connection.
+///
+/// The zlib-based encodings maintain compression state across the whole
+/// connection, so every encoded rectangle must be sent to the client.
+pub struct Encoder {
+    encoding: Encoding,
+    converter: PixelConverter,
+    zlib: Compress,
+    zrle: Compress,
+    buf: Vec<u8>,
+    tile: Vec<u32>,
+}
+
+impl Encoder {
+    /// Returns a new encoder using `encoding` and the server's default
+    /// pixel format.
+    pub fn new(encoding: Encoding) -> Self {
+        Self {
+            encoding,
+            converter: PixelConverter::new(&SERVER_PIXEL_FORMAT),
+            zlib: Compress::new(Compression::default(), true),
+            zrle: Compress::new(Compression::default(), true),
+            buf: Vec::new(),
+            tile: Vec::new(),
+        }
+    }
+
+    /// Returns the current encoding.
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    /// Sets the encoding for subsequent rectangles.
+    pub fn set_encoding(&mut self, encoding: Encoding) {
+        self.encoding = encoding;
+    }
+
+    pub(crate) fn set_pixel_format(&mut self, format: &rfb::PixelFormat) {
+        self.converter = PixelConverter::new(format);
+    }
+
+    /// Appends the rectangle header and encoded contents of `rect` to `out`.
+    ///
+    /// `frame` contains rows of `stride` pixels in `0x00RRGGBB` format.
+    pub fn encode(&mut self, frame: &[u32], stride: usize, rect: Rect, out: &mut Vec<u8>) {
+        out.extend_from_slice(
+            rfb::Rectangle {
+                x: rect.x.into(),
+                y: rect.y.into(),
+                width: rect.width.into(),
+                height: rect.height.into(),
+                encoding_type: self.encoding.encoding_type().into(),
+            }
+            .as_bytes(),
+        );
+        match self.encoding {
+            Encoding::Raw => self.raw(frame, stride, rect, out),
+            Encoding::Hextile => self.hextile(frame, stride, rect, out),
+            Encoding::Zlib => {
+                let mut buf = std::mem::take(&mut self.buf);
+                buf.clear();
+                self.raw(frame, stride, rect, &mut buf);
+                compress(&mut self.zlib, &buf, out);
+                self.buf = buf;
+            }
+            Encoding::Zrle => {
+                let mut buf = std::mem::take(&mut self.buf);
+                buf.clear();
+                self.zrle(frame, stride, rect, &mut buf);
+                compress(&mut self.zrle, &buf, out);
+                self.buf = buf;
+            }
+        }
+    }
+
+    fn raw(&self, frame: &[u32], stride: usize, rect: Rect, out: &mut Vec<u8>) {
+        for row in rows(frame, stride, rect) {
+            self.converter.write_pixels(row, out);
+        }
+    }
+
+    /// Copies the pixels of `rect` into `self.tile`.
+    fn load_tile(&mut self, frame: &[u32], stride: usize, rect: Rect) {
+        self.tile.clear();
+        for row in rows(frame, stride, rect) {
+            self.tile.extend_from_slice(row);
+        }
+    }
+
+    fn hextile(&mut self, frame: &[u32], stride: usize, rect: Rect, out: &mut Vec<u8>) {
+        let mut background = None;
+        let mut foreground = None;
+        let bpp = self.converter.bytes_per_pixel();
+        for tile in tiles(rect, HEXTILE_SIZE) {
+            self.load_tile(frame, stride, tile);
+            let (width, height) = (tile.width as usize, tile.height as usize);
+            let pixels = &self.tile;
+
+            let Some(colors) = palette(pixels, 16) else {
+                out.push(HEXTILE_RAW);
+                self.converter.write_pixels(pixels, out);
+                background = None;
+                foreground = None;
+                continue;
+            };
+
+            // Use the most common color as the background.
+            let bg = *colors
+                .iter()
+                .max_by_key(|&&c| pixels.iter().filter(|&&p| p == c).count())
+                .unwrap();
+
+            let mut flags = 0;
+            let mut header = Vec::new();
+            if background != Some(bg) {
+                flags |= HEXTILE_BACKGROUND_SPECIFIED;
+                self.converter.write_pixel(bg, &mut header);
+            }
+
+            let mut subrect_data = Vec::new();
+            if colors.len() > 1 {
+                let subrects = subrects(pixels, width, height, bg);
+                let coloured = colors.len() > 2;
+                let fg = (!coloured).then(|| subrects[0].0);
+                let size = header.len()
+                    + 1
+                    + if coloured {
+                        subrects.len() * (bpp + 2)
+                    } else {
+                        bpp + subrects.len() * 2
+                    };
+                if subrects.len() > 255 || size >= width * height * bpp {
+                    out.push(HEXTILE_RAW);
+                    self.converter.write_pixels(pixels, out);
+                    background = None;
+                    foreground = None;
+                    continue;
+                }
+
+                flags |= HEXTILE_ANY_SUBRECTS;
+                if coloured {
+                    flags |= HEXTILE_SUBRECTS_COLOURED;
+                    foreground = None;
+                } else if foreground != fg {
+                    flags |= HEXTILE_FOREGROUND_SPECIFIED;
+                    self.converter.write_pixel(fg.unwrap(), &mut header);
+                    foreground = fg;
+                }
+                subrect_data.push(subrects.len() as u8);
+                for (color, x, y, w, h) in subrects {
+                    if coloured {
+                        self.converter.write_pixel(color, &mut subrect_data);
+                    }
+                    subrect_data.push((x << 4) | y);
+                    subrect_data.push(((w - 1) << 4) | (h - 1));
+                }
+            }
+
+            background = Some(bg);
+            out.push(flags);
+            out.extend_from_slice(&header);
+            out.extend_from_slice(&subrect_data);
+        }
+    }
+
+    fn zrle(&mut self, frame: &[u32], stride: usize, rect: Rect, out: &mut Vec<u8>) {
+        for tile in tiles(rect, ZRLE_TILE_SIZE) {
+            self.load_tile(frame, stride, tile);
+            self.zrle_tile(tile.width.into(), out);
+        }
+    }
+
+    fn zrle_tile(&self, width: usize, out: &mut Vec<u8>) {
+        let pixels = &self.tile[..];
+        let cpp = self.converter.bytes_per_cpixel();
+        let colors = palette(pixels, ZRLE_MAX_PALETTE);
+        if let Some([color]) = colors.as_deref() {
+            out.push(ZRLE_SOLID);
+            self.converter.write_cpixel(*color, out);
+            return;
+        }
+
+        // Estimate the size of each subencoding and use the smallest.
+        let mut run_count = 0;
+        let mut run_length_bytes = 0;
+        for (_, len) in runs(pixels) {
+            run_count += 1;
+            run_length_bytes += run_length_size(len);
+        }
+        let raw_size = pixels.len() * cpp;
+        let plain_rle_size = run_count * cpp + run_length_bytes;
+        let (palette_rle_size, packed_size) = match &colors {
+            Some(colors) => {
+                let palette_size = colors.len() * cpp;
+                let single_runs = runs(pixels).filter(|&(_, len)| len == 1).count();
+                let palette_rle =
+                    palette_size + run_count + run_length_bytes - single_runs * run_length_size(1);
+                let packed = (colors.len() <= ZRLE_MAX_PACKED_PALETTE).then(|| {
+                    let bits = packed_bits(colors.len());
+                    let height = pixels.len() / width;
+                    palette_size + height * (width * bits).div_ceil(8)
+                });
+                (Some(palette_rle), packed)
+            }
+            None => (None, None),
+        };
+
+        let best = [
+            Some(raw_size),
+            Some(plain_rle_size),
+            palette_rle_size,
+            packed_size,
+        ]
+        .into_iter()
+        .flatten()
+        .min()
+        .unwrap();
+
+        if Some(best) == packed_size {
+            let colors = colors.unwrap();
+            out.push(colors.len() as u8);
+            for &c in &colors {
+                self.converter.write_cpixel(c, out);
+            }
+            let bits = packed_bits(colors.len());
+            for row in pixels.chunks_exact(width) {
+                let mut byte = 0u8;
+                let mut used = 0;
+                for &p in row {
+                    let index = colors.iter().position(|&c| c == p).unwrap() as u8;
+                    byte |= index << (8 - bits - used);
+                    used += bits;
+                    if used == 8 {
+                        out.push(byte);
+                        byte = 0;
+                        used = 0;
+                    }
+                }
+                if used != 0 {
+                    out.push(byte);
+                }
+            }
+        } else if Some(best) == palette_rle_size {
+            let colors = colors.unwrap();
+            out.push(ZRLE_RLE | colors.len() as u8);
+            for &c in &colors {
+                self.converter.write_cpixel(c, out);
+            }
+            for (p, len) in runs(pixels) {
+                let index = colors.iter().position(|&c| c == p).unwrap() as u8;
+                if len == 1 {
+                    out.push(index);
+                } else {
+                    out.push(0x80 | index);
+                    write_run_length(len, out);
+                }
+            }
+        } else if best == plain_rle_size {
+            out.push(ZRLE_RLE);
+            for (p, len) in runs(pixels) {
+                self.converter.write_cpixel(p, out);
+                write_run_length(len, out);
+            }
+        } else {
+            out.push(ZRLE_RAW);
+            for &p in pixels {
+                self.converter.write_cpixel(p, out);
+            }
+        }
+    }
+}
+
+/// Returns the rows of `rect` within `frame`.
+fn rows(frame: &[u32], stride: usize, rect: Rect) -> impl Iterator<Item = &[u32]> {
+    let x = rect.x as usize;
+    let width = rect.width as usize;
+    (rect.y..rect.y + rect.height).map(move |y| {
+        let offset = y as usize * stride + x;
+        &frame[offset..offset + width]
+    })
+}
+
+/// Splits `rect` into tiles of at most `size` by `size` pixels, in row-major
+/// order.
+fn tiles(rect: Rect, size: u16) -> impl Iterator<Item = Rect> {
+    (0..rect.height).step_by(size.into()).flat_map(move |y| {
+        (0..rect.width).step_by(size.into()).map(move |x| Rect {
+            x: rect.x + x,
+            y: rect.y + y,
+            width: size.min(rect.width - x),
+            height: size.min(rect.height - y),
+        })
+    })
+}
+
+/// Returns the distinct colors in `pixels`, or `None` if there are more than
+/// `max`.
+fn palette(pixels: &[u32], max: usize) -> Option<Vec<u32>> {
+    let mut colors = Vec::new();
+    let mut last = None;
+    for &p in pixels {
+        if last == Some(p) {
+            continue;
+        }
+        last = Some(p);
+        if !colors.contains(&p) {
+            if colors.len() == max {
+                return None;
+            }
+            colors.push(p);
+        }
+    }
+    Some(colors)
+}
+
+/// Returns the runs of identical pixels in `pixels` as `(pixel, length)`.
+fn runs(pixels: &[u32]) -> impl '_ + Iterator<Item = (u32, usize)> {
+    pixels
+        .chunk_by(|a, b| a == b)
+        .map(|run| (run[0], run.len()))
+}
+
+/// Covers the pixels of a hextile tile that differ from `background` with
+/// single-color rectangles, returned as `(color, x, y, width, height)`.
+fn subrects(
+    pixels: &[u32],
+    width: usize,
+    height: usize,
+    background: u32,
+) -> Vec<(u32, u8, u8, u8, u8)> {
+    let mut covered = [false; (HEXTILE_SIZE * HEXTILE_SIZE) as usize];
+    let mut subrects = Vec::new();
+    for y in 0..height {
+        for x in 0..width {
+            let i = y * width + x;
+            let color = pixels[i];
+            if covered[i] || color == background {
+                continue;
+            }
+            let mut w = 1;
+            while x + w < width && !covered[i + w] && pixels[i + w] == color {
+                w += 1;
+            }
+            let mut h = 1;
+            while y + h < height
+                && (0..w).all(|dx| {
+                    let j = (y + h) * width + x + dx;
+                    !covered[j] && pixels[j] == color
+                })
+            {
+                h += 1;
+            }
+            for dy in 0..h {
+                let j = (y + dy) * width + x;
+                covered[j..j + w].fill(true);
+            }
+            subrects.push((color, x as u8, y as u8, w as u8, h as u8));
+        }
+    }
+    subrects
+}
+
+/// The number of bits per index for a ZRLE packed palette of `len` colors.
+fn packed_bits(len: usize) -> usize {
+    match len {
+        ..=2 => 1,
+        3..=4 => 2,
+        _ => 4,
+    }
+}
+
+fn run_length_size(len: usize) -> usize {
+    (len - 1) / 255 + 1
+}
+
+fn write_run_length(len: usize, out: &mut Vec<u8>) {
+    let mut n = len - 1;
+    while n >= 255 {
+        out.push(255);
+        n -= 255;
+    }
+    out.push(n as u8);
+}
+
+/// Appends the zlib-compressed `input` to `out`, preceded by its compressed
+/// length, flushing the stream so that the client can decode it immediately.
+fn compress(stream: &mut Compress, input: &[u8], out: &mut Vec<u8>) {
+    let len_offset = out.len();
+    out.extend_from_slice(&[0; 4]);
+    let start = out.len();
+    let total_in = stream.total_in();
+    loop {
+        out.reserve(input.len() / 2 + 64);
+        let consumed = (stream.total_in() - total_in) as usize;
+        stream
+            .compress_vec(&input[consumed..], out, FlushCompress::Sync)
+            .expect("compression should not fail");
+        // The flush is complete once all the input is consumed without
+        // filling the output buffer.
+        if stream.total_in() - total_in == input.len() as u64 && out.len() < out.capacity() {
+            break;
+        }
+    }
+    let len = (out.len() - start) as u32;
+    out[len_offset..start].copy_from_slice(&len.to_be_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::Decompress;
+    use flate2::FlushDecompress;
+    use zerocopy::FromBytes;
+
+    /// A test frame with a solid region, text-like two-color and
+    /// three-color regions, noise, and a gradient with short runs.
+    fn test_frame(width: usize, height: usize) -> Vec<u32> {
+        let mut seed = 1u32;
+        (0..width * height)
+            .map(|i| {
+                let (x, y) = (i % width, i / width);
+                let text = (x / 3 + y / 5) % 2 == 0;
+                match x * 4 / width {
+                    0 => 0x203040,
+                    1 if text => 0xffffff,
+                    1 => 0x000000,
+                    2 if text => 0xffffff,
+                    2 => [0x000000, 0xff0000, 0x00ff00][y % 3],
+                    _ if y < height / 2 => {
+                        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                        seed >> 8
+                    }
+                    _ => (y * 64 + x / 8) as u32,
+                }
+            })
+            .collect()
+    }
+
+    fn full_rect(width: usize, height: usize) -> Rect {
+        Rect {
+            x: 0,
+            y: 0,
+            width: width as u16,
+            height: height as u16,
+        }
+    }
+
+    struct Reader<'a>(&'a [u8]);
+
+    impl Reader<'_> {
+        fn u8(&mut self) -> u8 {
+            let (&b, rest) = self.0.split_first().unwrap();
+            self.0 = rest;
+            b
+        }
+
+        fn bytes(&mut self, n: usize) -> &[u8] {
+            let (b, rest) = self.0.split_at(n);
+            self.0 = rest;
+            b
+        }
+
+        fn pixel(&mut self, n: usize) -> u32 {
+            let mut v = [0; 4];
+            v[..n].copy_from_slice(self.bytes(n));
+            u32::from_le_bytes(v)
+        }
+
+        fn header(&mut self, encoding: Encoding) -> Rect {
+            let header = rfb::Rectangle::read_from_prefix(self.0).unwrap();
+            self.bytes(size_of::<rfb::Rectangle>());
+            assert_eq!(header.encoding_type.get(), encoding.encoding_type());
+            Rect {
+                x: header.x.get(),
+                y: header.y.get(),
+                width: header.width.get(),
+                height: header.height.get(),
+            }
+        }
+
+        fn compressed(&mut self, stream: &mut Decompress) -> Vec<u8> {
+            let len = u32::from_be_bytes(self.bytes(4).try_into().unwrap()) as usize;
+            let input = self.bytes(len);
+            let mut out = Vec::with_capacity(1 << 20);
+            stream
+                .decompress_vec(input, &mut out, FlushDecompress::Sync)
+                .unwrap();
+            out
+        }
+    }
+
+    fn decode_hextile(r: &mut Reader<'_>, rect: Rect) -> Vec<u32> {
+        let mut pixels = vec![0; rect.width as usize * rect.height as usize];
+        let (mut bg, mut fg) = (0, 0);
+        for tile in tiles(rect, HEXTILE_SIZE) {
+            let mut put = |x: usize, y: usize, p: u32| {
+                let x = tile.x as usize - rect.x as usize + x;
+                let y = tile.y as usize - rect.y as usize + y;
+                pixels[y * rect.width as usize + x] = p;
+            };
+            let flags = r.u8();
+            if flags & HEXTILE_RAW != 0 {
+                for y in 0..tile.height as usize {
+                    for x in 0..tile.width as usize {
+                        put(x, y, r.pixel(4));
+                    }
+                }
+                continue;
+            }
+            if flags & HEXTILE_BACKGROUND_SPECIFIED != 0 {
+                bg = r.pixel(4);
+            }
+            if flags & HEXTILE_FOREGROUND_SPECIFIED != 0 {
+                fg = r.pixel(4);
+            }
+            for y in 0..tile.height as usize {
+                for x in 0..tile.width as usize {
+                    put(x, y, bg);
+                }
+            }
+            if flags & HEXTILE_ANY_SUBRECTS != 0 {
+                for _ in 0..r.u8() {
+                    let color = if flags & HEXTILE_SUBRECTS_COLOURED != 0 {
+                        r.pixel(4)
+                    } else {
+                        fg
+                    };
+                    let (xy, wh) = (r.u8() as usize, r.u8() as usize);
+                    for y in (xy & 15)..(xy & 15) + (wh & 15) + 1 {
+                        for x in (xy >> 4)..(xy >> 4) + (wh >> 4) + 1 {
+                            put(x, y, color);
+                        }
+                    }
+                }
+            }
+        }
+        pixels
+    }
+
+    fn decode_zrle(data: &[u8], rect: Rect) -> Vec<u32> {
+        let mut r = Reader(data);
+        let mut pixels = vec![0; rect.width as usize * rect.height as usize];
+        for tile in tiles(rect, ZRLE_TILE_SIZE) {
+            let n = tile.width as usize * tile.height as usize;
+            let mut tile_pixels = Vec::with_capacity(n);
+            let run_length = |r: &mut Reader<'_>| {
+                let mut len = 1;
+                loop {
+                    let b = r.u8();
+                    len += b as usize;
+                    if b != 255 {
+                        break len;
+                    }
+                }
+            };
+            match r.u8() {
+                ZRLE_RAW => {
+                    for _ in 0..n {
+                        tile_pixels.push(r.pixel(3));
+                    }
+                }
+                ZRLE_SOLID => {
+                    let p = r.pixel(3);
+                    tile_pixels.resize(n, p);
+                }
+                count @ 2..=16 => {
+                    let colors: Vec<_> = (0..count).map(|_| r.pixel(3)).collect();
+                    let bits = packed_bits(count.into());
+                    for _ in 0..tile.height {
+                        let row = r.bytes((tile.width as usize * bits).div_ceil(8));
+                        for x in 0..tile.width as usize {
+                            let bit = x * bits;
+                            let index = (row[bit / 8] >> (8 - bits - bit % 8)) & ((1 << bits) - 1);
+                            tile_pixels.push(colors[index as usize]);
+                        }
+                    }
+                }
+                ZRLE_RLE => {
+                    while tile_pixels.len() < n {
+                        let p = r.pixel(3);
+                        let len = run_length(&mut r);
+                        tile_pixels.extend(std::iter::repeat(p).take(len));
+                    }
+                }
+                subencoding if subencoding & ZRLE_RLE != 0 => {
+                    let colors: Vec<_> = (0..subencoding & !ZRLE_RLE).map(|_| r.pixel(3)).collect();
+                    while tile_pixels.len() < n {
+                        let index = r.u8();
+                        let len = if index & 0x80 != 0 {
+                            run_length(&mut r)
+                        } else {
+                            1
+                        };
+                        tile_pixels
+                            .extend(std::iter::repeat(colors[(index & 0x7f) as usize]).take(len));
+                    }
+                }
+                subencoding => panic!("unexpected subencoding {subencoding}"),
+            }
+            assert_eq!(tile_pixels.len(), n);
+            for (y, row) in tile_pixels.chunks_exact(tile.width as usize).enumerate() {
+                let offset = (tile.y - rect.y) as usize + y;
+                let offset = offset * rect.width as usize + (tile.x - rect.x) as usize;
+                pixels[offset..offset + row.len()].copy_from_slice(row);
+            }
+        }
+        assert!(r.0.is_empty());
+        pixels
+    }
+
+    #[test]
+    fn test_encodings_round_trip() {
+        let (width, height) = (400, 200);
+        let frame = test_frame(width, height);
+        let rects = [
+            full_rect(width, height),
+            Rect {
+                x: 30,
+                y: 17,
+                width: 101,
+                height: 67,
+            },
+        ];
+        let expected =
+            |rect: Rect| -> Vec<u32> { rows(&frame, width, rect).flatten().copied().collect() };
+
+        for encoding in [
+            Encoding::Raw,
+            Encoding::Hextile,
+            Encoding::Zlib,
+            Encoding::Zrle,
+        ] {
+            let mut encoder = Encoder::new(encoding);
+            let mut out = Vec::new();
+            for rect in rects {
+                encoder.encode(&frame, width, rect, &mut out);
+            }
+
+            let mut zlib = Decompress::new(true);
+            let mut r = Reader(&out);
+            for rect in rects {
+                assert_eq!(r.header(encoding), rect);
+                let n = rect.width as usize * rect.height as usize;
+                let pixels = match encoding {
+                    Encoding::Raw => (0..n).map(|_| r.pixel(4)).collect(),
+                    Encoding::Hextile => decode_hextile(&mut r, rect),
+                    Encoding::Zlib => {
+                        let data = r.compressed(&mut zlib);
+                        let mut r = Reader(&data);
+                        let pixels = (0..n).map(|_| r.pixel(4)).collect();
+                        assert!(r.0.is_empty());
+                        pixels
+                    }
+                    Encoding::Zrle => decode_zrle(&r.compressed(&mut zlib), rect),
+                };
+                assert_eq!(pixels, expected(rect), "{encoding:?} {rect:?}");
+            }
+            assert!(r.0.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_pixel_format() {
+        let format = rfb::PixelFormat {
+            bits_per_pixel: 16,
+            depth: 16,
+            big_endian_flag: 1,
+            true_color_flag: 1,
+            red_max: 31.into(),
+            green_max: 63.into(),
+            blue_max: 31.into(),
+            red_shift: 11,
+            green_shift: 5,
+            blue_shift: 0,
+            padding: [0; 3],
+        };
+        let converter = PixelConverter::new(&format);
+        let mut out = Vec::new();
+        converter.write_pixel(0xff8040, &mut out);
+        assert_eq!(
+            u16::from_be_bytes(out.try_into().unwrap()),
+            0b11111_100000_01000
+        );
+
+        // 32-bit pixels with the colors in the high bytes drop the low byte
+        // as compressed pixels.
+        let format = rfb::PixelFormat {
+            bits_per_pixel: 32,
+            depth: 24,
+            big_endian_flag: 0,
+            true_color_flag: 1,
+            red_max: 255.into(),
+            green_max: 255.into(),
+            blue_max: 255.into(),
+            red_shift: 24,
+            green_shift: 16,
+            blue_shift: 8,
+            padding: [0; 3],
+        };
+        let converter = PixelConverter::new(&format);
+        let mut out = Vec::new();
+        converter.write_cpixel(0x123456, &mut out);
+        assert_eq!(out, [0x56, 0x34, 0x12]);
+    }
+
+    #[test]
+    fn test_choose_encoding() {
+        assert_eq!(Encoding::choose(&[]), Encoding::Raw);
+        assert_eq!(
+            Encoding::choose(&[
+                rfb::ENCODING_TYPE_DESKTOP_SIZE,
+                rfb::ENCODING_TYPE_TIGHT,
+                rfb::ENCODING_TYPE_ZRLE,
+                rfb::ENCODING_TYPE_HEXTILE
+            ]),
+            Encoding::Zrle
+        );
+    }
+}
@@ -3,8 +3,23 @@
 
 //! A VNC server implementation.
 
+mod des;
+mod dirty;
+mod encoding;
 mod rfb;
 mod scancode;
+mod security;
+mod stream;
+mod tls;
+mod websocket;
+
+pub use dirty::DirtyTracker;
+pub use dirty::Rect;
+pub use encoding::Encoder;
+pub use encoding::Encoding;
+pub use tls::TlsAcceptor;
+pub use tls::TlsError;
+
 use futures::channel::mpsc;
 use futures::future::OptionFuture;
 use futures::AsyncReadExt;
@@ -28,6 +43,54 @@ pub enum Error {
     Io(#[from] std::io::Error),
     #[error("client does not support desktop resize extension")]
     DesktopResizeNotSupported,
+    #[error("unsupported security type: {0}")]
+    UnsupportedSecurityType(u8),
+    #[error("client does not support TLS")]
+    TlsRequired,
+    #[error("unsupported VeNCrypt version {0}.{1}")]
+    UnsupportedVencryptVersion(u8, u8),
+    #[error("unsupported VeNCrypt subtype: {0}")]
+    UnsupportedVencryptSubtype(u32),
+    #[error("TLS handshake failed")]
+    Tls(#[source] std::io::Error),
+    #[error("client authentication failed")]
+    AuthenticationFailed,
+    #[error("unsupported pixel format")]
+    UnsupportedPixelFormat,
+    #[error("websocket handshake failed")]
+    WebSocket(#[source] std::io::Error),
+}
+
+/// The authentication requirements for clients.
+#[derive(Clone, Default)]
+pub struct Auth {
+    /// The password clients must provide.
+    ///
+    /// Without `username`, this is used for VNC authentication, which only
+    /// uses the first 8 bytes of the password.
+    pub password: Option<String>,
+    /// The username for VeNCrypt plain authentication, which also requires
+    /// `password` and `tls`.
+    pub username: Option<String>,
+    /// If set, clients must use VeNCrypt with X.509/TLS.
+    pub tls: Option<TlsAcceptor>,
+    /// The origins (e.g. `http://localhost:6080`) of the web pages allowed to
+    /// connect over WebSocket. Browsers always send the page's origin;
+    /// connections from any other origin are rejected.
+    pub allowed_origins: Vec<String>,
+}
+
+/// The transport carrying the RFB protocol.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Transport {
+    /// RFB directly over the socket.
+    #[default]
+    Raw,
+    /// RFB in WebSocket binary messages, for browser clients such as noVNC.
+    ///
+    /// If TLS is configured in [`Auth`], the WebSocket connection itself uses
+    /// TLS (`wss://`) instead of VeNCrypt.
+    WebSocket,
 }
 
 /// A trait used to retrieve data from a framebuffer.
@@ -40,7 +103,9 @@ pub trait Framebuffer: Send + Sync {
 
 /// A VNC server handling a single connection.
 pub struct Server<F, I> {
-    socket: PolledSocket<socket2::Socket>,
+    socket: stream::Stream,
+    auth: Auth,
+    transport: Transport,
     fb: F,
     input: I,
     update_recv: mpsc::Receiver<()>,
@@ -78,7 +143,9 @@ pub fn new(
         #[allow(clippy::disallowed_methods)] // TODO
         let (update_send, update_recv) = mpsc::channel(1);
         Self {
-            socket,
+            socket: stream::Stream::Plain(socket),
+            auth: Auth::default(),
+            transport: Transport::Raw,
             fb,
             input,
             update_recv,
@@ -91,6 +158,18 @@ pub fn new(
         }
     }
 
+    /// Requires clients to authenticate according to `auth`.
+    pub fn with_auth(mut self, auth: Auth) -> Self {
+        self.auth = auth;
+        self
+    }
+
+    /// Sets the transport used by the client connection.
+    pub fn with_transport(mut self, transport: Transport) -> Self {
+        self.transport = transport;
+        self
+    }
+
     pub fn updater(&mut self) -> Updater {
         Updater(self.update_send.clone())
     }
@@ -109,45 +188,20 @@ pub async fn run(&mut self) -> Result<(), Error> {
     }
 
     async fn run_internal(&mut self) -> Result<(), Error> {
-        let socket = &mut self.socket;
-        socket
-            .write_all(rfb::ProtocolVersion(rfb::PROTOCOL_VERSION_33).as_bytes())
-            .await?;
-
-        let mut version = rfb::ProtocolVersion::new_zeroed();
-        socket.read_exact(version.as_bytes_mut()).await?;
-
-        if version.0 != rfb::PROTOCOL_VERSION_33 {
-            return Err(Error::UnsupportedVersion(version));
+        if self.transport == Transport::WebSocket {
+            if let Some(tls) = &self.auth.tls {
+                self.socket.upgrade_tls(tls).await?;
+            }
+            self.socket
+                .upgrade_websocket(&self.auth.allowed_origins)
+                .await?;
         }
+        security::handshake(&mut self.socket, &self.auth).await?;
 
-        socket
-            .write_all(
-                rfb::Security33 {
-                    padding: [0; 3],
-                    security_type: rfb::SECURITY_TYPE_NONE,
-                }
-                .as_bytes(),
-            )
-            .await?;
-
+        let socket = &mut self.socket;
         let mut init = rfb::ClientInit::new_zeroed();
         socket.read_exact(init.as_bytes_mut()).await?;
 
-        let mut fmt = rfb::PixelFormat {
-            bits_per_pixel: 32,
-            depth: 24,
-            big_endian_flag: 0,
-            true_color_flag: 1,
-            red_max: 255.into(),
-            green_max: 255.into(),
-            blue_max: 255.into(),
-            red_shift: 16,
-            green_shift: 8,
-            blue_shift: 0,
-            padding: [0; 3],
-        };
-
         let name = self.name.as_bytes();
         let (mut width, mut height) = self.fb.resolution();
         socket
@@ -155,7 +209,7 @@ async fn run_internal(&mut self) -> Result<(), Error> {
                 rfb::ServerInit {
                     framebuffer_width: width.into(),
                     framebuffer_height: height.into(),
-                    server_pixel_format: fmt,
+                    server_pixel_format: encoding::SERVER_PIXEL_FORMAT,
                     name_length: (name.len() as u32).into(),
                 }
                 .as_bytes(),
@@ -165,6 +219,8 @@ async fn run_internal(&mut self) -> Result<(), Error> {
 
         let mut ready_for_update = false;
         let mut scancode_state = scancode::State::new();
+        let mut tracker = DirtyTracker::new();
+        let mut encoder = Encoder::new(Encoding::Raw);
         loop {
             let mut socket_ready = false;
             let mut update_ready = false;
@@ -184,12 +240,11 @@ async fn run_internal(&mut self) -> Result<(), Error> {
             }
 
             if ready_for_update && update_ready {
-                ready_for_update = false;
-
                 // Ensure the desktop size has not changed.
                 let (new_width, new_height) = self.fb.resolution();
                 if new_width != width || new_height != height {
                     // Send the new desktop size.
+                    ready_for_update = false;
                     width = new_width;
                     height = new_height;
                     socket
@@ -215,90 +270,23 @@ async fn run_internal(&mut self) -> Result<(), Error> {
                         )
                         .await?;
                 } else {
-                    // Send the update. Just update the whole framebuffer for now.
-                    socket
-                        .write_all(
-                            rfb::FramebufferUpdate {
-                                message_type: rfb::SC_MESSAGE_TYPE_FRAMEBUFFER_UPDATE,
-                                padding: 0,
-                                rectangle_count: 1.into(),
-                            }
-                            .as_bytes(),
-                        )
-                        .await?;
-                    socket
-                        .write_all(
-                            rfb::Rectangle {
-                                x: 0.into(),
-                                y: 0.into(),
-                                width: width.into(),
-                                height: height.into(),
-                                encoding_type: rfb::ENCODING_TYPE_RAW.into(),
-                            }
-                            .as_bytes(),
-                        )
-                        .await?;
-                    let mut src_line = vec![0u32; width as usize];
-                    let dest_depth = fmt.bits_per_pixel as usize / 8;
-                    let shift_r = 24 - fmt.red_max.get().count_ones();
-                    let shift_g = 16 - fmt.green_max.get().count_ones();
-                    let shift_b = 8 - fmt.red_max.get().count_ones();
-                    match dest_depth {
-                        1 => {
-                            let mut line = vec![0u8; width as usize];
-                            for y in 0..height {
-                                self.fb.read_line(y, src_line.as_bytes_mut());
-                                for x in 0..width as usize {
-                                    let p = src_line[x];
-                                    let (r, g, b) = (p & 0xff0000, p & 0xff00, p & 0xff);
-                                    let p2 = r >> shift_r << fmt.red_shift
-                                        | g >> shift_g << fmt.green_shift
-                                        | b >> shift_b << fmt.blue_shift;
-                                    line[x] = p2 as u8;
-                                }
-                                socket.write_all(&line).await?;
-                            }
-                        }
-                        2 => {
-                            let mut line = vec![0u16; width as usize];
-                            for y in 0..height {
-                                self.fb.read_line(y, src_line.as_bytes_mut());
-                                for x in 0..width as usize {
-                                    let p = src_line[x];
-                                    let (r, g, b) = (p & 0xff0000, p & 0xff00, p & 0xff);
-                                    let p2 = r >> shift_r << fmt.red_shift
-                                        | g >> shift_g << fmt.green_shift
-                                        | b >> shift_b << fmt.blue_shift;
-                                    line[x] = p2 as u16;
-                                }
-                                socket.write_all(line.as_bytes()).await?;
-                            }
-                        }
-                        4 if shift_r == fmt.red_shift as u32
-                            && shift_g == fmt.green_shift as u32
-                            && shift_b == fmt.blue_shift as u32 =>
-                        {
-                            for y in 0..height {
-                                self.fb.read_line(y, src_line.as_bytes_mut());
-                                socket.write_all(src_line.as_bytes()).await?;
-                            }
+                    // Send the regions that changed since the last update. If
+                    // nothing changed, keep the request pending until
+                    // something does.
+                    let rects = tracker.update(&mut self.fb, width, height);
+                    if !rects.is_empty() {
+                        ready_for_update = false;
+                        let mut msg = rfb::FramebufferUpdate {
+                            message_type: rfb::SC_MESSAGE_TYPE_FRAMEBUFFER_UPDATE,
+                            padding: 0,
+                            rectangle_count: (rects.len() as u16).into(),
                         }
-                        4 => {
-                            let mut line = vec![0u32; width as usize];
-                            for y in 0..height {
-                                self.fb.read_line(y, src_line.as_bytes_mut());
-                                for x in (width as usize & !3)..width as usize {
-                                    let p = src_line[x];
-                                    let (r, g, b) = (p & 0xff0000, p & 0xff00, p & 0xff);
-                                    let p2 = r >> shift_r << fmt.red_shift
-                                        | g >> shift_g << fmt.green_shift
-                                        | b >> shift_b << fmt.blue_shift;
-                                    line[x] = p2;
-                                }
-                                socket.write_all(line.as_bytes()).await?;
-                            }
+                        .as_bytes()
+                        .to_vec();
+                        for rect in rects {
+                            encoder.encode(tracker.frame(), width.into(), rect, &mut msg);
                         }
-                        _ => unreachable!(),
+                        socket.write_all(&msg).await?;
                     }
                 }
             }
@@ -308,7 +296,19 @@ async fn run_internal(&mut self) -> Result<(), Error> {
                     rfb::CS_MESSAGE_SET_PIXEL_FORMAT => {
                         let mut input = rfb::SetPixelFormat::new_zeroed();
                         socket.read_exact(&mut input.as_bytes_mut()[1..]).await?;
-                        fmt = input.pixel_format;
+                        let format = input.pixel_format;
+                        if !matches!(format.bits_per_pixel, 8 | 16 | 32)
+                            || format.true_color_flag == 0
+                            || [format.red_shift, format.green_shift, format.blue_shift]
+                                .iter()
+                                .any(|&shift| shift >= format.bits_per_pixel)
+                        {
+                            return Err(Error::UnsupportedPixelFormat);
+                        }
+                        encoder.set_pixel_format(&format);
+                        // The client needs the whole framebuffer in the new
+                        // format.
+                        tracker.invalidate();
                     }
                     rfb::CS_MESSAGE_SET_ENCODINGS => {
                         let mut input = rfb::SetEncodings::new_zeroed();
@@ -316,6 +316,9 @@ async fn run_internal(&mut self) -> Result<(), Error> {
                         let mut encodings: Vec<zerocopy::U32<zerocopy::BE>> =
                             vec![0.into(); input.encoding_count.get().into()];
                         socket.read_exact(encodings.as_bytes_mut()).await?;
+                        encoder.set_encoding(Encoding::choose(
+                            &encodings.iter().map(|e| e.get()).collect::<Vec<_>>(),
+                        ));
                         if !encodings.contains(&rfb::ENCODING_TYPE_DESKTOP_SIZE.into()) {
                             // Can't really operate without being able to change the desktop size dynamically.
                             return Err(Error::DesktopResizeNotSupported);
@@ -348,6 +351,9 @@ async fn run_internal(&mut self) -> Result<(), Error> {
                         let mut input = rfb::FramebufferUpdateRequest::new_zeroed();
                         socket.read_exact(&mut input.as_bytes_mut()[1..]).await?;
                         ready_for_update = true;
+                        if input.incremental == 0 {
+                            tracker.invalidate();
+                        }
                     }
                     rfb::CS_MESSAGE_KEY_EVENT => {
                         let mut input = rfb::KeyEvent::new_zeroed();
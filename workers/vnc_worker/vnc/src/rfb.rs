@@ -54,6 +54,32 @@ pub struct SecurityResult {
 pub const SECURITY_RESULT_STATUS_FAILED: u32 = 1;
 pub const SECURITY_RESULT_STATUS_FAILED_TOO_MANY_ATTEMPTS: u32 = 2;
 
+// As defined in https://github.com/rfbproto/rfbproto/blob/master/rfbproto.rst#vencrypt
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, AsBytes, FromBytes, FromZeroes)]
+pub struct VencryptVersion {
+    pub major: u8,
+    pub minor: u8,
+}
+
+pub const VENCRYPT_SUBTYPE_PLAIN: u32 = 256;
+pub const VENCRYPT_SUBTYPE_TLS_NONE: u32 = 257;
+pub const VENCRYPT_SUBTYPE_TLS_VNC: u32 = 258;
+pub const VENCRYPT_SUBTYPE_TLS_PLAIN: u32 = 259;
+pub const VENCRYPT_SUBTYPE_X509_NONE: u32 = 260;
+pub const VENCRYPT_SUBTYPE_X509_VNC: u32 = 261;
+pub const VENCRYPT_SUBTYPE_X509_PLAIN: u32 = 262;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, AsBytes, FromBytes, FromZeroes)]
+pub struct VencryptPlain {
+    pub username_length: u32_be,
+    pub password_length: u32_be,
+    // username: [u8; N],
+    // password: [u8; N],
+}
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug, AsBytes, FromBytes, FromZeroes)]
 pub struct ClientInit {
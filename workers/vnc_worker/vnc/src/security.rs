@@ -0,0 +1,530 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Protocol version and security negotiation, including VNC authentication
+//! and VeNCrypt.
+
+use crate::des;
+use crate::rfb;
+use crate::stream::Stream;
+use crate::Auth;
+use crate::Error;
+use futures::AsyncReadExt;
+use futures::AsyncWriteExt;
+use zerocopy::AsBytes;
+use zerocopy::FromZeroes;
+
+/// The maximum accepted length of a username or password for VeNCrypt plain
+/// authentication.
+const MAX_CREDENTIAL_LENGTH: u32 = 1024;
+
+/// Performs the handshake up to the point where the client sends its
+/// `ClientInit` message, authenticating the client as required by `auth`.
+pub(crate) async fn handshake(stream: &mut Stream, auth: &Auth) -> Result<(), Error> {
+    stream
+        .write_all(rfb::ProtocolVersion(rfb::PROTOCOL_VERSION_38).as_bytes())
+        .await?;
+
+    let mut version = rfb::ProtocolVersion::new_zeroed();
+    stream.read_exact(version.as_bytes_mut()).await?;
+    let minor = match version.0 {
+        rfb::PROTOCOL_VERSION_33 => 3,
+        rfb::PROTOCOL_VERSION_37 => 7,
+        rfb::PROTOCOL_VERSION_38 => 8,
+        _ => return Err(Error::UnsupportedVersion(version)),
+    };
+    // A connection that is already encrypted, such as a secure WebSocket,
+    // does not need VeNCrypt's TLS, but VeNCrypt is still needed to carry a
+    // username.
+    let encrypted = stream.is_encrypted();
+    let mut negotiation = Negotiation { stream, minor };
+
+    let security_type =
+        if (auth.tls.is_some() && !encrypted) || (auth.username.is_some() && encrypted) {
+            rfb::SECURITY_TYPE_VENCRYPT
+        } else if auth.password.is_some() {
+            rfb::SECURITY_TYPE_VNC_AUTHENTICATION
+        } else {
+            rfb::SECURITY_TYPE_NONE
+        };
+
+    if minor == 3 {
+        // The server chooses the security type in 3.3, and VeNCrypt requires
+        // 3.7 or later.
+        if security_type == rfb::SECURITY_TYPE_VENCRYPT {
+            negotiation
+                .write(
+                    rfb::Security33 {
+                        padding: [0; 3],
+                        security_type: rfb::SECURITY_TYPE_INVALID,
+                    }
+                    .as_bytes(),
+                )
+                .await?;
+            negotiation.write_reason("VeNCrypt is required").await?;
+            return Err(Error::TlsRequired);
+        }
+        negotiation
+            .write(
+                rfb::Security33 {
+                    padding: [0; 3],
+                    security_type,
+                }
+                .as_bytes(),
+            )
+            .await?;
+    } else {
+        negotiation.write(&[1, security_type]).await?;
+        let mut requested = 0u8;
+        negotiation.read(requested.as_bytes_mut()).await?;
+        if requested != security_type {
+            negotiation.fail("unsupported security type").await?;
+            return Err(Error::UnsupportedSecurityType(requested));
+        }
+    }
+
+    match security_type {
+        rfb::SECURITY_TYPE_NONE => {
+            // Only 3.8 sends a result for no authentication.
+            if minor >= 8 {
+                negotiation.succeed().await?;
+            }
+        }
+        rfb::SECURITY_TYPE_VNC_AUTHENTICATION => {
+            negotiation
+                .vnc_authentication(auth.password.as_deref().unwrap())
+                .await?
+        }
+        rfb::SECURITY_TYPE_VENCRYPT => negotiation.vencrypt(auth, encrypted).await?,
+        _ => unreachable!(),
+    }
+    Ok(())
+}
+
+struct Negotiation<'a> {
+    stream: &'a mut Stream,
+    minor: u8,
+}
+
+impl Negotiation<'_> {
+    async fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.stream.write_all(data).await?;
+        Ok(())
+    }
+
+    async fn read(&mut self, data: &mut [u8]) -> Result<(), Error> {
+        self.stream.read_exact(data).await?;
+        Ok(())
+    }
+
+    async fn write_reason(&mut self, reason: &str) -> Result<(), Error> {
+        self.write(&(reason.len() as u32).to_be_bytes()).await?;
+        self.write(reason.as_bytes()).await
+    }
+
+    async fn succeed(&mut self) -> Result<(), Error> {
+        self.write(
+            rfb::SecurityResult {
+                status: rfb::SECURITY_RESULT_STATUS_OK.into(),
+            }
+            .as_bytes(),
+        )
+        .await
+    }
+
+    /// Sends a failed security result, with a reason if the protocol version
+    /// supports it.
+    async fn fail(&mut self, reason: &str) -> Result<(), Error> {
+        self.write(
+            rfb::SecurityResult {
+                status: rfb::SECURITY_RESULT_STATUS_FAILED.into(),
+            }
+            .as_bytes(),
+        )
+        .await?;
+        if self.minor >= 8 {
+            self.write_reason(reason).await?;
+        }
+        self.stream.flush().await?;
+        Ok(())
+    }
+
+    async fn check(&mut self, ok: bool) -> Result<(), Error> {
+        if ok {
+            self.succeed().await
+        } else {
+            self.fail("authentication failed").await?;
+            Err(Error::AuthenticationFailed)
+        }
+    }
+
+    async fn vnc_authentication(&mut self, password: &str) -> Result<(), Error> {
+        let mut challenge = [0; 16];
+        getrandom::getrandom(&mut challenge).map_err(std::io::Error::from)?;
+        self.write(&challenge).await?;
+        let mut response = [0; 16];
+        self.read(&mut response).await?;
+        let expected = des::vnc_auth_response(password.as_bytes(), &challenge);
+        self.check(constant_time_eq(&response, &expected)).await
+    }
+
+    async fn plain_authentication(&mut self, username: &str, password: &str) -> Result<(), Error> {
+        let mut header = rfb::VencryptPlain::new_zeroed();
+        self.read(header.as_bytes_mut()).await?;
+        let (username_length, password_length) =
+            (header.username_length.get(), header.password_length.get());
+        if username_length > MAX_CREDENTIAL_LENGTH || password_length > MAX_CREDENTIAL_LENGTH {
+            self.fail("credentials too long").await?;
+            return Err(Error::AuthenticationFailed);
+        }
+        let mut client_username = vec![0; username_length as usize];
+        self.read(&mut client_username).await?;
+        let mut client_password = vec![0; password_length as usize];
+        self.read(&mut client_password).await?;
+        let ok = constant_time_eq(&client_username, username.as_bytes())
+            & constant_time_eq(&client_password, password.as_bytes());
+        self.check(ok).await
+    }
+
+    async fn vencrypt(&mut self, auth: &Auth, encrypted: bool) -> Result<(), Error> {
+        self.write(rfb::VencryptVersion { major: 0, minor: 2 }.as_bytes())
+            .await?;
+        let mut version = rfb::VencryptVersion::new_zeroed();
+        self.read(version.as_bytes_mut()).await?;
+        if (version.major, version.minor) != (0, 2) {
+            self.write(&[1]).await?;
+            return Err(Error::UnsupportedVencryptVersion(
+                version.major,
+                version.minor,
+            ));
+        }
+        self.write(&[0]).await?;
+
+        let subtype = match (&auth.username, &auth.password) {
+            _ if encrypted => rfb::VENCRYPT_SUBTYPE_PLAIN,
+            (Some(_), Some(_)) => rfb::VENCRYPT_SUBTYPE_X509_PLAIN,
+            (None, Some(_)) => rfb::VENCRYPT_SUBTYPE_X509_VNC,
+            _ => rfb::VENCRYPT_SUBTYPE_X509_NONE,
+        };
+        self.write(&[1]).await?;
+        self.write(&subtype.to_be_bytes()).await?;
+        let mut requested = [0; 4];
+        self.read(&mut requested).await?;
+        let requested = u32::from_be_bytes(requested);
+        if requested != subtype {
+            self.write(&[0]).await?;
+            return Err(Error::UnsupportedVencryptSubtype(requested));
+        }
+        self.write(&[1]).await?;
+
+        // Upgrade the connection to TLS. The remaining negotiation is
+        // encrypted.
+        if !encrypted {
+            self.stream.upgrade_tls(auth.tls.as_ref().unwrap()).await?;
+        }
+
+        match subtype {
+            rfb::VENCRYPT_SUBTYPE_X509_NONE => self.succeed().await,
+            rfb::VENCRYPT_SUBTYPE_X509_VNC => {
+                self.vnc_authentication(auth.password.as_deref().unwrap())
+                    .await
+            }
+            rfb::VENCRYPT_SUBTYPE_X509_PLAIN | rfb::VENCRYPT_SUBTYPE_PLAIN => {
+                self.plain_authentication(
+                    auth.username.as_deref().unwrap(),
+                    auth.password.as_deref().unwrap(),
+                )
+                .await
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// Compares two byte strings without short circuiting on the first mismatch.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::handshake;
+    use crate::des;
+    use crate::rfb;
+    use crate::stream::Stream;
+    use crate::Auth;
+    use crate::Error;
+    use futures::AsyncReadExt;
+    use futures::AsyncWriteExt;
+    use pal_async::async_test;
+    use pal_async::socket::PolledSocket;
+    use pal_async::DefaultDriver;
+
+    type Client = PolledSocket<socket2::Socket>;
+
+    /// Returns the server and client ends of a loopback connection.
+    fn connect(driver: &DefaultDriver) -> (Stream, Client) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        (
+            Stream::Plain(PolledSocket::new(driver, server.into()).unwrap()),
+            PolledSocket::new(driver, client.into()).unwrap(),
+        )
+    }
+
+    /// Runs the server handshake against `client_fn`.
+    async fn run<F: std::future::Future<Output = ()>>(
+        driver: &DefaultDriver,
+        auth: Auth,
+        client_fn: impl FnOnce(Client) -> F,
+    ) -> Result<(), Error> {
+        let (mut server, client) = connect(driver);
+        let (result, ()) = futures::join!(handshake(&mut server, &auth), client_fn(client));
+        result
+    }
+
+    async fn read<const N: usize>(client: &mut Client) -> [u8; N] {
+        let mut buf = [0; N];
+        client.read_exact(&mut buf).await.unwrap();
+        buf
+    }
+
+    async fn write(client: &mut Client, data: &[u8]) {
+        client.write_all(data).await.unwrap();
+    }
+
+    async fn read_reason(client: &mut Client) -> String {
+        let len = u32::from_be_bytes(read(client).await);
+        let mut reason = vec![0; len as usize];
+        client.read_exact(&mut reason).await.unwrap();
+        String::from_utf8(reason).unwrap()
+    }
+
+    async fn version(client: &mut Client, version: [u8; 12]) {
+        assert_eq!(read::<12>(client).await, rfb::PROTOCOL_VERSION_38);
+        write(client, &version).await;
+    }
+
+    fn password_auth() -> Auth {
+        Auth {
+            password: Some("secret".into()),
+            ..Default::default()
+        }
+    }
+
+    #[async_test]
+    async fn test_no_auth(driver: DefaultDriver) {
+        run(&driver, Auth::default(), |mut client| async move {
+            let client = &mut client;
+            version(client, rfb::PROTOCOL_VERSION_33).await;
+            assert_eq!(read::<4>(client).await, [0, 0, 0, rfb::SECURITY_TYPE_NONE]);
+        })
+        .await
+        .unwrap();
+
+        run(&driver, Auth::default(), |mut client| async move {
+            let client = &mut client;
+            version(client, rfb::PROTOCOL_VERSION_37).await;
+            assert_eq!(read::<2>(client).await, [1, rfb::SECURITY_TYPE_NONE]);
+            write(client, &[rfb::SECURITY_TYPE_NONE]).await;
+        })
+        .await
+        .unwrap();
+
+        run(&driver, Auth::default(), |mut client| async move {
+            let client = &mut client;
+            version(client, rfb::PROTOCOL_VERSION_38).await;
+            assert_eq!(read::<2>(client).await, [1, rfb::SECURITY_TYPE_NONE]);
+            write(client, &[rfb::SECURITY_TYPE_NONE]).await;
+            assert_eq!(read::<4>(client).await, [0; 4]);
+        })
+        .await
+        .unwrap();
+    }
+
+    #[async_test]
+    async fn test_vnc_auth(driver: DefaultDriver) {
+        run(&driver, password_auth(), |mut client| async move {
+            let client = &mut client;
+            version(client, rfb::PROTOCOL_VERSION_38).await;
+            assert_eq!(
+                read::<2>(client).await,
+                [1, rfb::SECURITY_TYPE_VNC_AUTHENTICATION]
+            );
+            write(client, &[rfb::SECURITY_TYPE_VNC_AUTHENTICATION]).await;
+            let challenge = read::<16>(client).await;
+            write(client, &des::vnc_auth_response(b"secret", &challenge)).await;
+            assert_eq!(read::<4>(client).await, [0; 4]);
+        })
+        .await
+        .unwrap();
+
+        // The server chooses the type in 3.3.
+        run(&driver, password_auth(), |mut client| async move {
+            let client = &mut client;
+            version(client, rfb::PROTOCOL_VERSION_33).await;
+            assert_eq!(
+                read::<4>(client).await,
+                [0, 0, 0, rfb::SECURITY_TYPE_VNC_AUTHENTICATION]
+            );
+            let challenge = read::<16>(client).await;
+            write(client, &des::vnc_auth_response(b"secret", &challenge)).await;
+            assert_eq!(read::<4>(client).await, [0; 4]);
+        })
+        .await
+        .unwrap();
+    }
+
+    #[async_test]
+    async fn test_vnc_auth_rejected(driver: DefaultDriver) {
+        let result = run(&driver, password_auth(), |mut client| async move {
+            let client = &mut client;
+            version(client, rfb::PROTOCOL_VERSION_38).await;
+            read::<2>(client).await;
+            write(client, &[rfb::SECURITY_TYPE_VNC_AUTHENTICATION]).await;
+            let challenge = read::<16>(client).await;
+            write(client, &des::vnc_auth_response(b"wrong", &challenge)).await;
+            assert_eq!(read::<4>(client).await, [0, 0, 0, 1]);
+            assert_eq!(read_reason(client).await, "authentication failed");
+        })
+        .await;
+        assert!(matches!(result, Err(Error::AuthenticationFailed)));
+
+        // 3.7 does not send a reason.
+        let result = run(&driver, password_auth(), |mut client| async move {
+            let client = &mut client;
+            version(client, rfb::PROTOCOL_VERSION_37).await;
+            read::<2>(client).await;
+            write(client, &[rfb::SECURITY_TYPE_VNC_AUTHENTICATION]).await;
+            read::<16>(client).await;
+            write(client, &[0; 16]).await;
+            assert_eq!(read::<4>(client).await, [0, 0, 0, 1]);
+        })
+        .await;
+        assert!(matches!(result, Err(Error::AuthenticationFailed)));
+    }
+
+    #[async_test]
+    async fn test_unsupported(driver: DefaultDriver) {
+        let result = run(&driver, Auth::default(), |mut client| async move {
+            let client = &mut client;
+            version(client, *b"RFB 004.000\n").await;
+        })
+        .await;
+        assert!(matches!(result, Err(Error::UnsupportedVersion(_))));
+
+        // The client may not downgrade from the offered type.
+        let result = run(&driver, password_auth(), |mut client| async move {
+            let client = &mut client;
+            version(client, rfb::PROTOCOL_VERSION_38).await;
+            read::<2>(client).await;
+            write(client, &[rfb::SECURITY_TYPE_NONE]).await;
+            assert_eq!(read::<4>(client).await, [0, 0, 0, 1]);
+            assert_eq!(read_reason(client).await, "unsupported security type");
+        })
+        .await;
+        assert!(matches!(
+            result,
+            Err(Error::UnsupportedSecurityType(rfb::SECURITY_TYPE_NONE))
+        ));
+
+        let result = run(&driver, Auth::default(), |mut client| async move {
+            let client = &mut client;
+            version(client, rfb::PROTOCOL_VERSION_38).await;
+            read::<2>(client).await;
+            write(client, &[rfb::SECURITY_TYPE_TIGHT]).await;
+            assert_eq!(read::<4>(client).await, [0, 0, 0, 1]);
+            read_reason(client).await;
+        })
+        .await;
+        assert!(matches!(
+            result,
+            Err(Error::UnsupportedSecurityType(rfb::SECURITY_TYPE_TIGHT))
+        ));
+    }
+
+    #[cfg(unix)]
+    fn tls_auth() -> Auth {
+        use openssl::asn1::Asn1Time;
+        use openssl::ec::EcGroup;
+        use openssl::ec::EcKey;
+        use openssl::hash::MessageDigest;
+        use openssl::nid::Nid;
+        use openssl::pkey::PKey;
+        use openssl::x509::X509;
+
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+        let mut cert = X509::builder().unwrap();
+        cert.set_version(2).unwrap();
+        cert.set_pubkey(&key).unwrap();
+        cert.set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        cert.set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        cert.sign(&key, MessageDigest::sha256()).unwrap();
+        let tls = crate::TlsAcceptor::new(
+            &cert.build().to_pem().unwrap(),
+            &key.private_key_to_pem_pkcs8().unwrap(),
+        )
+        .unwrap();
+        Auth {
+            tls: Some(tls),
+            ..Default::default()
+        }
+    }
+
+    #[cfg(unix)]
+    #[async_test]
+    async fn test_vencrypt_rejected(driver: DefaultDriver) {
+        // VeNCrypt requires 3.7 or later.
+        let result = run(&driver, tls_auth(), |mut client| async move {
+            let client = &mut client;
+            version(client, rfb::PROTOCOL_VERSION_33).await;
+            assert_eq!(read::<4>(client).await, [0; 4]);
+            assert_eq!(read_reason(client).await, "VeNCrypt is required");
+        })
+        .await;
+        assert!(matches!(result, Err(Error::TlsRequired)));
+
+        let result = run(&driver, tls_auth(), |mut client| async move {
+            let client = &mut client;
+            version(client, rfb::PROTOCOL_VERSION_38).await;
+            assert_eq!(read::<2>(client).await, [1, rfb::SECURITY_TYPE_VENCRYPT]);
+            write(client, &[rfb::SECURITY_TYPE_VENCRYPT]).await;
+            assert_eq!(read::<2>(client).await, [0, 2]);
+            write(client, &[0, 1]).await;
+            assert_eq!(read::<1>(client).await, [1]);
+        })
+        .await;
+        assert!(matches!(
+            result,
+            Err(Error::UnsupportedVencryptVersion(0, 1))
+        ));
+
+        let result = run(&driver, tls_auth(), |mut client| async move {
+            let client = &mut client;
+            version(client, rfb::PROTOCOL_VERSION_38).await;
+            read::<2>(client).await;
+            write(client, &[rfb::SECURITY_TYPE_VENCRYPT]).await;
+            read::<2>(client).await;
+            write(client, &[0, 2]).await;
+            assert_eq!(read::<1>(client).await, [0]);
+            assert_eq!(read::<1>(client).await, [1]);
+            assert_eq!(
+                read::<4>(client).await,
+                rfb::VENCRYPT_SUBTYPE_X509_NONE.to_be_bytes()
+            );
+            write(client, &rfb::VENCRYPT_SUBTYPE_PLAIN.to_be_bytes()).await;
+            assert_eq!(read::<1>(client).await, [0]);
+        })
+        .await;
+        assert!(matches!(
+            result,
+            Err(Error::UnsupportedVencryptSubtype(
+                rfb::VENCRYPT_SUBTYPE_PLAIN
+            ))
+        ));
+    }
+}
@@ -0,0 +1,105 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! The transport for a VNC connection.
+
+use crate::tls::TlsAcceptor;
+use crate::tls::TlsStream;
+use crate::websocket;
+use crate::websocket::WebSocketStream;
+use crate::Error;
+use futures::AsyncRead;
+use futures::AsyncWrite;
+use pal_async::socket::PolledSocket;
+use std::io;
+use std::pin::Pin;
+use std::task::Context;
+use std::task::Poll;
+
+/// The connection to the client, which may be layered in TLS and WebSocket
+/// framing before or during the security handshake.
+pub(crate) enum Stream {
+    Plain(PolledSocket<socket2::Socket>),
+    Tls(TlsStream<Box<Stream>>),
+    WebSocket(WebSocketStream<Box<Stream>>),
+    /// Temporary state while upgrading the connection.
+    Invalid,
+}
+
+impl Stream {
+    /// Returns whether the connection is already encrypted.
+    pub fn is_encrypted(&self) -> bool {
+        match self {
+            Stream::Plain(_) => false,
+            Stream::Tls(_) => true,
+            Stream::WebSocket(s) => s.get_ref().is_encrypted(),
+            Stream::Invalid => unreachable!(),
+        }
+    }
+
+    /// Upgrades the connection to TLS.
+    pub async fn upgrade_tls(&mut self, tls: &TlsAcceptor) -> Result<(), Error> {
+        let stream = Box::new(std::mem::replace(self, Stream::Invalid));
+        *self = Stream::Tls(tls.accept(stream).await.map_err(Error::Tls)?);
+        Ok(())
+    }
+
+    /// Performs the WebSocket handshake and switches to WebSocket framing.
+    pub async fn upgrade_websocket(&mut self, allowed_origins: &[String]) -> Result<(), Error> {
+        let stream = Box::new(std::mem::replace(self, Stream::Invalid));
+        *self = Stream::WebSocket(
+            websocket::accept(stream, allowed_origins)
+                .await
+                .map_err(Error::WebSocket)?,
+        );
+        Ok(())
+    }
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Stream::Plain(s) => Pin::new(s).poll_read(cx, buf),
+            Stream::Tls(s) => Pin::new(s).poll_read(cx, buf),
+            Stream::WebSocket(s) => Pin::new(s).poll_read(cx, buf),
+            Stream::Invalid => unreachable!(),
+        }
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Stream::Plain(s) => Pin::new(s).poll_write(cx, buf),
+            Stream::Tls(s) => Pin::new(s).poll_write(cx, buf),
+            Stream::WebSocket(s) => Pin::new(s).poll_write(cx, buf),
+            Stream::Invalid => unreachable!(),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Plain(s) => Pin::new(s).poll_flush(cx),
+            Stream::Tls(s) => Pin::new(s).poll_flush(cx),
+            Stream::WebSocket(s) => Pin::new(s).poll_flush(cx),
+            Stream::Invalid => unreachable!(),
+        }
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Plain(s) => Pin::new(s).poll_close(cx),
+            Stream::Tls(s) => Pin::new(s).poll_close(cx),
+            Stream::WebSocket(s) => Pin::new(s).poll_close(cx),
+            Stream::Invalid => unreachable!(),
+        }
+    }
+}
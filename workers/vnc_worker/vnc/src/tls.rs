@@ -0,0 +1,308 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! TLS support for VeNCrypt.
+//!
+//! This uses OpenSSL on Unix platforms. TLS is not currently supported on
+//! other platforms.
+
+#[cfg(unix)]
+pub use ossl::*;
+#[cfg(not(unix))]
+pub use unsupported::*;
+
+#[cfg(unix)]
+mod ossl {
+    use futures::AsyncRead;
+    use futures::AsyncReadExt;
+    use futures::AsyncWrite;
+    use futures::AsyncWriteExt;
+    use openssl::pkey::PKey;
+    use openssl::ssl::ErrorCode;
+    use openssl::ssl::HandshakeError;
+    use openssl::ssl::SslAcceptor;
+    use openssl::ssl::SslMethod;
+    use openssl::ssl::SslStream;
+    use openssl::x509::X509;
+    use std::io;
+    use std::io::Read;
+    use std::io::Write;
+    use std::pin::Pin;
+    use std::task::ready;
+    use std::task::Context;
+    use std::task::Poll;
+    use thiserror::Error;
+
+    /// A TLS error.
+    #[derive(Debug, Error)]
+    pub enum TlsError {
+        #[error("no certificate found")]
+        NoCertificate,
+        #[error("openssl error")]
+        Ssl(#[from] openssl::error::ErrorStack),
+    }
+
+    /// A server-side TLS configuration, holding the server's certificate
+    /// chain and private key.
+    #[derive(Clone)]
+    pub struct TlsAcceptor(SslAcceptor);
+
+    impl TlsAcceptor {
+        /// Creates a new acceptor from a PEM-encoded certificate chain (leaf
+        /// first) and a PEM-encoded private key.
+        pub fn new(cert_chain_pem: &[u8], key_pem: &[u8]) -> Result<Self, TlsError> {
+            let mut builder = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls_server())?;
+            let mut certs = X509::stack_from_pem(cert_chain_pem)?.into_iter();
+            let leaf = certs.next().ok_or(TlsError::NoCertificate)?;
+            builder.set_certificate(&leaf)?;
+            for cert in certs {
+                builder.add_extra_chain_cert(cert)?;
+            }
+            builder.set_private_key(&PKey::private_key_from_pem(key_pem)?)?;
+            builder.check_private_key()?;
+            Ok(Self(builder.build()))
+        }
+
+        /// Performs the server side of a TLS handshake over `socket`.
+        pub async fn accept<S: AsyncRead + AsyncWrite + Unpin>(
+            &self,
+            mut socket: S,
+        ) -> io::Result<TlsStream<S>> {
+            let mut buf = vec![0; 4096];
+            let mut result = self.0.accept(Buffers::default());
+            let ssl = loop {
+                match result {
+                    Ok(ssl) => break ssl,
+                    Err(HandshakeError::WouldBlock(mut mid)) => {
+                        // OpenSSL needs more data from the client, possibly
+                        // after sending its own.
+                        let buffers = mid.get_mut();
+                        socket.write_all(&buffers.outgoing).await?;
+                        buffers.outgoing.clear();
+                        let n = socket.read(&mut buf).await?;
+                        if n == 0 {
+                            return Err(io::ErrorKind::UnexpectedEof.into());
+                        }
+                        mid.get_mut().incoming.extend_from_slice(&buf[..n]);
+                        result = mid.handshake();
+                    }
+                    Err(HandshakeError::SetupFailure(err)) => return Err(io::Error::other(err)),
+                    Err(HandshakeError::Failure(mut mid)) => {
+                        // Try to send the alert to the client.
+                        let _ = socket.write_all(&mid.get_mut().outgoing).await;
+                        return Err(io::Error::other(mid.into_error()));
+                    }
+                }
+            };
+            let mut stream = TlsStream { socket, ssl, buf };
+            stream.flush().await?;
+            Ok(stream)
+        }
+    }
+
+    /// In-memory transport for OpenSSL, so that the TLS state machine can be
+    /// driven by an asynchronous socket.
+    #[derive(Default)]
+    struct Buffers {
+        incoming: Vec<u8>,
+        outgoing: Vec<u8>,
+    }
+
+    impl Read for Buffers {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.incoming.is_empty() {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            let n = buf.len().min(self.incoming.len());
+            buf[..n].copy_from_slice(&self.incoming[..n]);
+            self.incoming.drain(..n);
+            Ok(n)
+        }
+    }
+
+    impl Write for Buffers {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.outgoing.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// An established TLS session over `S`.
+    pub struct TlsStream<S> {
+        socket: S,
+        ssl: SslStream<Buffers>,
+        buf: Vec<u8>,
+    }
+
+    impl<S: AsyncRead + AsyncWrite + Unpin> TlsStream<S> {
+        /// Writes any buffered ciphertext to the socket.
+        fn poll_write_outgoing(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            let outgoing = &mut self.ssl.get_mut().outgoing;
+            while !outgoing.is_empty() {
+                let n = ready!(Pin::new(&mut self.socket).poll_write(cx, outgoing))?;
+                if n == 0 {
+                    return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+                }
+                outgoing.drain(..n);
+            }
+            Poll::Ready(Ok(()))
+        }
+
+        /// Reads more ciphertext from the socket. Returns false on EOF.
+        fn poll_read_incoming(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<bool>> {
+            let n = ready!(Pin::new(&mut self.socket).poll_read(cx, &mut self.buf))?;
+            self.ssl
+                .get_mut()
+                .incoming
+                .extend_from_slice(&self.buf[..n]);
+            Poll::Ready(Ok(n != 0))
+        }
+    }
+
+    impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for TlsStream<S> {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            let this = self.get_mut();
+            loop {
+                // Writes may be left buffered, so flush them here to ensure
+                // progress while the caller is waiting for a response.
+                ready!(this.poll_write_outgoing(cx))?;
+                match this.ssl.ssl_read(buf) {
+                    Ok(n) => return Poll::Ready(Ok(n)),
+                    Err(err) if err.code() == ErrorCode::ZERO_RETURN => return Poll::Ready(Ok(0)),
+                    Err(err) if err.code() == ErrorCode::WANT_READ => {
+                        if !this.ssl.get_ref().outgoing.is_empty() {
+                            continue;
+                        }
+                        if !ready!(this.poll_read_incoming(cx))? {
+                            return Poll::Ready(Ok(0));
+                        }
+                    }
+                    Err(err) => {
+                        return Poll::Ready(Err(err
+                            .into_io_error()
+                            .unwrap_or_else(io::Error::other)))
+                    }
+                }
+            }
+        }
+    }
+
+    impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for TlsStream<S> {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            let this = self.get_mut();
+            // Only accept more data once the previous data has been sent, to
+            // provide backpressure.
+            ready!(this.poll_write_outgoing(cx))?;
+            loop {
+                match this.ssl.ssl_write(buf) {
+                    Ok(n) => {
+                        // Start sending the data, but don't wait for it.
+                        if let Poll::Ready(Err(err)) = this.poll_write_outgoing(cx) {
+                            return Poll::Ready(Err(err));
+                        }
+                        return Poll::Ready(Ok(n));
+                    }
+                    Err(err) if err.code() == ErrorCode::WANT_READ => {
+                        if !ready!(this.poll_read_incoming(cx))? {
+                            return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+                        }
+                    }
+                    Err(err) => {
+                        return Poll::Ready(Err(err
+                            .into_io_error()
+                            .unwrap_or_else(io::Error::other)))
+                    }
+                }
+            }
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            let this = self.get_mut();
+            ready!(this.poll_write_outgoing(cx))?;
+            Pin::new(&mut this.socket).poll_flush(cx)
+        }
+
+        fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            let this = self.get_mut();
+            ready!(this.poll_write_outgoing(cx))?;
+            Pin::new(&mut this.socket).poll_close(cx)
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod unsupported {
+    use futures::AsyncRead;
+    use futures::AsyncWrite;
+    use std::io;
+    use std::marker::PhantomData;
+    use std::pin::Pin;
+    use std::task::Context;
+    use std::task::Poll;
+    use thiserror::Error;
+
+    /// A TLS error.
+    #[derive(Debug, Error)]
+    pub enum TlsError {
+        #[error("TLS is not supported on this platform")]
+        NotSupported,
+    }
+
+    /// A server-side TLS configuration.
+    #[derive(Clone)]
+    pub struct TlsAcceptor(std::convert::Infallible);
+
+    impl TlsAcceptor {
+        /// Fails, since TLS is not supported on this platform.
+        pub fn new(_cert_chain_pem: &[u8], _key_pem: &[u8]) -> Result<Self, TlsError> {
+            Err(TlsError::NotSupported)
+        }
+
+        pub async fn accept<S>(&self, _socket: S) -> io::Result<TlsStream<S>> {
+            match self.0 {}
+        }
+    }
+
+    /// An established TLS session over `S`.
+    pub struct TlsStream<S>(std::convert::Infallible, PhantomData<S>);
+
+    impl<S> AsyncRead for TlsStream<S> {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            _buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            match self.0 {}
+        }
+    }
+
+    impl<S> AsyncWrite for TlsStream<S> {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            _buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            match self.0 {}
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            match self.0 {}
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            match self.0 {}
+        }
+    }
+}
@@ -0,0 +1,546 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A minimal WebSocket server transport (RFC 6455), carrying RFB in binary
+//! messages as expected by browser clients such as noVNC.
+
+use base64::Engine;
+use futures::AsyncRead;
+use futures::AsyncReadExt;
+use futures::AsyncWrite;
+use futures::AsyncWriteExt;
+use sha1::Digest;
+use sha1::Sha1;
+use std::io;
+use std::pin::Pin;
+use std::task::ready;
+use std::task::Context;
+use std::task::Poll;
+
+/// The GUID appended to the client's key to compute the accept key.
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// The maximum size of the client's HTTP upgrade request.
+const MAX_REQUEST_SIZE: usize = 8192;
+
+/// The maximum payload in each frame sent to the client.
+const MAX_SEND_PAYLOAD: usize = 0x10000;
+
+const OPCODE_CONTINUATION: u8 = 0;
+const OPCODE_TEXT: u8 = 1;
+const OPCODE_BINARY: u8 = 2;
+const OPCODE_CLOSE: u8 = 8;
+const OPCODE_PING: u8 = 9;
+const OPCODE_PONG: u8 = 10;
+
+const FIN: u8 = 0x80;
+const MASK: u8 = 0x80;
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Performs the server side of the WebSocket opening handshake over `socket`.
+///
+/// Browsers send an `Origin` header identifying the page that opened the
+/// connection. Requests with an `Origin` not in `allowed_origins` are
+/// rejected, so that arbitrary web pages cannot connect to the server from
+/// the user's browser. Non-browser clients do not send the header.
+pub async fn accept<S: AsyncRead + AsyncWrite + Unpin>(
+    mut socket: S,
+    allowed_origins: &[String],
+) -> io::Result<WebSocketStream<S>> {
+    // Read one byte at a time so as not to consume any frames sent after the
+    // request. The request is small, so this is not a performance concern.
+    let mut request = Vec::new();
+    while !request.ends_with(b"\r\n\r\n") {
+        if request.len() == MAX_REQUEST_SIZE {
+            return Err(invalid_data("websocket request too large"));
+        }
+        let mut b = 0;
+        socket.read_exact(std::slice::from_mut(&mut b)).await?;
+        request.push(b);
+    }
+
+    let request = parse_request(&request).and_then(|request| match request.origin {
+        Some(origin)
+            if !allowed_origins
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(origin)) =>
+        {
+            Err(RequestError::Forbidden)
+        }
+        _ => Ok(request),
+    });
+    match request {
+        Ok(request) => {
+            let mut response = format!(
+                "HTTP/1.1 101 Switching Protocols\r\n\
+                Upgrade: websocket\r\n\
+                Connection: Upgrade\r\n\
+                Sec-WebSocket-Accept: {}\r\n",
+                accept_key(request.key)
+            );
+            if request.binary_protocol {
+                response += "Sec-WebSocket-Protocol: binary\r\n";
+            }
+            response += "\r\n";
+            socket.write_all(response.as_bytes()).await?;
+            Ok(WebSocketStream {
+                socket,
+                header: Vec::new(),
+                payload_remaining: 0,
+                mask: [0; 4],
+                mask_offset: 0,
+                outgoing: Vec::new(),
+                closed: false,
+            })
+        }
+        Err(err) => {
+            let status = match err {
+                RequestError::Invalid(_) => "400 Bad Request",
+                RequestError::Forbidden => "403 Forbidden",
+            };
+            let _ = socket
+                .write_all(format!("HTTP/1.1 {status}\r\nConnection: close\r\n\r\n").as_bytes())
+                .await;
+            Err(match err {
+                RequestError::Invalid(msg) => invalid_data(msg),
+                RequestError::Forbidden => io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    "websocket origin not allowed",
+                ),
+            })
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum RequestError {
+    /// The request is malformed.
+    Invalid(&'static str),
+    /// The request's origin is not allowed.
+    Forbidden,
+}
+
+impl From<&'static str> for RequestError {
+    fn from(msg: &'static str) -> Self {
+        Self::Invalid(msg)
+    }
+}
+
+struct UpgradeRequest<'a> {
+    key: &'a str,
+    binary_protocol: bool,
+    origin: Option<&'a str>,
+}
+
+fn parse_request(request: &[u8]) -> Result<UpgradeRequest<'_>, RequestError> {
+    let request = std::str::from_utf8(request).map_err(|_| "request is not utf-8")?;
+    let mut lines = request.split("\r\n");
+    let request_line = lines.next().unwrap();
+    if !request_line.starts_with("GET ") {
+        return Err("not a GET request".into());
+    }
+    let mut upgrade = false;
+    let mut connection = false;
+    let mut version = false;
+    let mut key = None;
+    let mut binary_protocol = false;
+    let mut origin = None;
+    for line in lines.take_while(|line| !line.is_empty()) {
+        let (name, value) = line.split_once(':').ok_or("invalid header")?;
+        let value = value.trim();
+        let mut tokens = value.split(',').map(str::trim);
+        match name.trim().to_ascii_lowercase().as_str() {
+            "upgrade" => upgrade = tokens.any(|t| t.eq_ignore_ascii_case("websocket")),
+            "connection" => connection = tokens.any(|t| t.eq_ignore_ascii_case("upgrade")),
+            "sec-websocket-version" => version = value == "13",
+            "sec-websocket-key" => key = Some(value),
+            "sec-websocket-protocol" => binary_protocol = tokens.any(|t| t == "binary"),
+            "origin" => origin = Some(value),
+            _ => {}
+        }
+    }
+    if !upgrade || !connection {
+        return Err("not a websocket upgrade request".into());
+    }
+    if !version {
+        return Err("unsupported websocket version".into());
+    }
+    Ok(UpgradeRequest {
+        key: key.ok_or("missing websocket key")?,
+        binary_protocol,
+        origin,
+    })
+}
+
+fn accept_key(key: &str) -> String {
+    let digest = Sha1::new()
+        .chain_update(key)
+        .chain_update(ACCEPT_GUID)
+        .finalize();
+    base64::engine::general_purpose::STANDARD.encode(digest)
+}
+
+/// A parsed frame header.
+struct FrameHeader {
+    opcode: u8,
+    mask: Option<[u8; 4]>,
+    payload_len: u64,
+    header_len: usize,
+}
+
+/// Parses a frame header from the start of `data`, returning `None` if more
+/// data is needed.
+fn parse_header(data: &[u8]) -> io::Result<Option<FrameHeader>> {
+    let [b0, b1, rest @ ..] = data else {
+        return Ok(None);
+    };
+    if b0 & 0x70 != 0 {
+        return Err(invalid_data("unexpected websocket extension bits"));
+    }
+    let (payload_len, len_bytes) = match b1 & 0x7f {
+        126 => {
+            let Some(len) = rest.get(..2) else {
+                return Ok(None);
+            };
+            (u16::from_be_bytes(len.try_into().unwrap()).into(), 2)
+        }
+        127 => {
+            let Some(len) = rest.get(..8) else {
+                return Ok(None);
+            };
+            (u64::from_be_bytes(len.try_into().unwrap()), 8)
+        }
+        len => (len.into(), 0),
+    };
+    let mut header_len = 2 + len_bytes;
+    let mask = if b1 & MASK != 0 {
+        let Some(mask) = data.get(header_len..header_len + 4) else {
+            return Ok(None);
+        };
+        header_len += 4;
+        Some(mask.try_into().unwrap())
+    } else {
+        None
+    };
+    Ok(Some(FrameHeader {
+        opcode: b0 & 0xf,
+        mask,
+        payload_len,
+        header_len,
+    }))
+}
+
+fn write_frame(opcode: u8, payload: &[u8], out: &mut Vec<u8>) {
+    out.push(FIN | opcode);
+    match payload.len() {
+        len @ 0..=125 => out.push(len as u8),
+        len @ 126..=0xffff => {
+            out.push(126);
+            out.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            out.push(127);
+            out.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    out.extend_from_slice(payload);
+}
+
+fn unmask(data: &mut [u8], mask: [u8; 4], offset: usize) {
+    for (i, b) in data.iter_mut().enumerate() {
+        *b ^= mask[(offset + i) % 4];
+    }
+}
+
+/// A WebSocket connection, exposing the payload of the binary messages as a
+/// byte stream.
+pub struct WebSocketStream<S> {
+    socket: S,
+    /// Received bytes not yet consumed, starting at a frame header.
+    header: Vec<u8>,
+    /// The remaining payload bytes in the current data frame.
+    payload_remaining: u64,
+    mask: [u8; 4],
+    mask_offset: usize,
+    outgoing: Vec<u8>,
+    closed: bool,
+}
+
+impl<S> WebSocketStream<S> {
+    /// Returns the underlying socket.
+    pub fn get_ref(&self) -> &S {
+        &self.socket
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> WebSocketStream<S> {
+    fn poll_write_outgoing(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while !self.outgoing.is_empty() {
+            let n = ready!(Pin::new(&mut self.socket).poll_write(cx, &self.outgoing))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.outgoing.drain(..n);
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    /// Handles the frame at the start of `self.header`, if it is complete.
+    /// Returns false if more data is needed.
+    fn process_frame(&mut self) -> io::Result<bool> {
+        let Some(header) = parse_header(&self.header)? else {
+            return Ok(false);
+        };
+        let mask = header
+            .mask
+            .ok_or_else(|| invalid_data("unmasked websocket frame from client"))?;
+        match header.opcode {
+            OPCODE_BINARY | OPCODE_CONTINUATION => {
+                self.header.drain(..header.header_len);
+                self.payload_remaining = header.payload_len;
+                self.mask = mask;
+                self.mask_offset = 0;
+            }
+            OPCODE_TEXT => return Err(invalid_data("unsupported websocket text frame")),
+            OPCODE_CLOSE | OPCODE_PING | OPCODE_PONG => {
+                if header.payload_len > 125 {
+                    return Err(invalid_data("websocket control frame too large"));
+                }
+                let end = header.header_len + header.payload_len as usize;
+                if self.header.len() < end {
+                    return Ok(false);
+                }
+                let mut payload = self.header[header.header_len..end].to_vec();
+                self.header.drain(..end);
+                unmask(&mut payload, mask, 0);
+                match header.opcode {
+                    OPCODE_CLOSE => {
+                        // Echo the status code back to complete the close.
+                        payload.truncate(2);
+                        write_frame(OPCODE_CLOSE, &payload, &mut self.outgoing);
+                        self.closed = true;
+                    }
+                    OPCODE_PING => write_frame(OPCODE_PONG, &payload, &mut self.outgoing),
+                    _ => {}
+                }
+            }
+            opcode => {
+                return Err(invalid_data(&format!(
+                    "unknown websocket opcode {opcode:#x}"
+                )))
+            }
+        }
+        Ok(true)
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for WebSocketStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        loop {
+            // Send any control frame responses.
+            ready!(this.poll_write_outgoing(cx))?;
+            if this.closed {
+                return Poll::Ready(Ok(0));
+            }
+            if this.payload_remaining > 0 {
+                let len = (buf.len() as u64).min(this.payload_remaining) as usize;
+                let n = if !this.header.is_empty() {
+                    // Consume payload that was read along with the header.
+                    let n = len.min(this.header.len());
+                    buf[..n].copy_from_slice(&this.header[..n]);
+                    this.header.drain(..n);
+                    n
+                } else {
+                    let n = ready!(Pin::new(&mut this.socket).poll_read(cx, &mut buf[..len]))?;
+                    if n == 0 {
+                        return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+                    }
+                    n
+                };
+                unmask(&mut buf[..n], this.mask, this.mask_offset);
+                this.mask_offset = (this.mask_offset + n) % 4;
+                this.payload_remaining -= n as u64;
+                return Poll::Ready(Ok(n));
+            }
+            if this.process_frame()? {
+                continue;
+            }
+            let mut data = [0; 1024];
+            let n = ready!(Pin::new(&mut this.socket).poll_read(cx, &mut data))?;
+            if n == 0 {
+                return Poll::Ready(Ok(0));
+            }
+            this.header.extend_from_slice(&data[..n]);
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for WebSocketStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        // Only accept more data once the previous frame has been sent, to
+        // provide backpressure.
+        ready!(this.poll_write_outgoing(cx))?;
+        let n = buf.len().min(MAX_SEND_PAYLOAD);
+        write_frame(OPCODE_BINARY, &buf[..n], &mut this.outgoing);
+        // Start sending the frame, but don't wait for it.
+        if let Poll::Ready(Err(err)) = this.poll_write_outgoing(cx) {
+            return Poll::Ready(Err(err));
+        }
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_outgoing(cx))?;
+        Pin::new(&mut this.socket).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_outgoing(cx))?;
+        Pin::new(&mut this.socket).poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pal_async::async_test;
+    use pal_async::socket::PolledSocket;
+    use pal_async::DefaultDriver;
+
+    fn socket_pair(
+        driver: &DefaultDriver,
+    ) -> (PolledSocket<socket2::Socket>, PolledSocket<socket2::Socket>) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        (
+            PolledSocket::new(driver, server.into()).unwrap(),
+            PolledSocket::new(driver, client.into()).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_accept_key() {
+        // From RFC 6455.
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn test_parse_request() {
+        let request = b"GET /websockify HTTP/1.1\r\n\
+            Host: localhost:5700\r\n\
+            Upgrade: websocket\r\n\
+            Connection: keep-alive, Upgrade\r\n\
+            Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+            Sec-WebSocket-Protocol: binary\r\n\
+            Sec-WebSocket-Version: 13\r\n\r\n";
+        let parsed = parse_request(request).unwrap();
+        assert_eq!(parsed.key, "dGhlIHNhbXBsZSBub25jZQ==");
+        assert!(parsed.binary_protocol);
+        assert!(parsed.origin.is_none());
+
+        let request = b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        assert!(parse_request(request).is_err());
+    }
+
+    /// Sends `request` to [`accept`], returning the result and the start of
+    /// the response status line.
+    async fn upgrade(
+        driver: &DefaultDriver,
+        request: String,
+        allowed_origins: &[String],
+    ) -> (io::Result<()>, [u8; 12]) {
+        let (server, mut client) = socket_pair(driver);
+        let (result, response) = futures::join!(accept(server, allowed_origins), async {
+            client.write_all(request.as_bytes()).await.unwrap();
+            let mut response = [0; 12];
+            client.read_exact(&mut response).await.unwrap();
+            response
+        });
+        (result.map(drop), response)
+    }
+
+    #[async_test]
+    async fn test_origin(driver: DefaultDriver) {
+        let allowed = ["http://localhost:6080".to_owned()];
+        let request = |origin: &str| {
+            format!(
+                "GET / HTTP/1.1\r\n\
+                Upgrade: websocket\r\n\
+                Connection: Upgrade\r\n\
+                Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+                Sec-WebSocket-Version: 13\r\n\
+                {origin}\r\n"
+            )
+        };
+
+        // Non-browser clients send no origin.
+        let (result, response) = upgrade(&driver, request(""), &[]).await;
+        result.unwrap();
+        assert_eq!(&response, b"HTTP/1.1 101");
+
+        let (result, response) = upgrade(
+            &driver,
+            request("Origin: HTTP://LOCALHOST:6080\r\n"),
+            &allowed,
+        )
+        .await;
+        result.unwrap();
+        assert_eq!(&response, b"HTTP/1.1 101");
+
+        let (result, response) =
+            upgrade(&driver, request("Origin: http://localhost:6080\r\n"), &[]).await;
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(&response, b"HTTP/1.1 403");
+
+        let (result, response) = upgrade(
+            &driver,
+            request("Origin: https://example.com\r\n"),
+            &allowed,
+        )
+        .await;
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(&response, b"HTTP/1.1 403");
+    }
+
+    #[test]
+    fn test_frames() {
+        let mut out = Vec::new();
+        write_frame(OPCODE_BINARY, &[1, 2, 3], &mut out);
+        assert_eq!(out, [0x82, 3, 1, 2, 3]);
+
+        let mut out = Vec::new();
+        write_frame(OPCODE_BINARY, &[0; 300], &mut out);
+        assert_eq!(out[..4], [0x82, 126, 1, 44]);
+
+        // A masked client frame with "Hello", from RFC 6455.
+        let frame = [
+            0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58,
+        ];
+        assert!(parse_header(&frame[..5]).unwrap().is_none());
+        let header = parse_header(&frame).unwrap().unwrap();
+        assert_eq!(header.opcode, OPCODE_TEXT);
+        assert_eq!(header.payload_len, 5);
+        let mut payload = frame[header.header_len..].to_vec();
+        unmask(&mut payload, header.mask.unwrap(), 0);
+        assert_eq!(payload, b"Hello");
+    }
+}
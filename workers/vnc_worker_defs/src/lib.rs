@@ -10,10 +10,41 @@
 pub struct VncParameters<T> {
     /// The socket the VNC server will listen on
     pub listener: T,
+    /// An optional socket to listen on for WebSocket connections, for
+    /// browser clients such as noVNC.
+    pub websocket_listener: Option<T>,
     /// The framebuffer memory.
     pub framebuffer: framebuffer::FramebufferAccess,
     /// A channel to send input to.
     pub input_send: mesh::MpscSender<input_core::InputData>,
+    /// Client authentication settings.
+    pub auth: VncAuth,
+}
+
+/// Client authentication settings for the VNC server.
+///
+/// With no settings, any client may connect.
+#[derive(MeshPayload, Clone, Default)]
+pub struct VncAuth {
+    /// The password for VNC authentication, or for VeNCrypt plain
+    /// authentication if `username` is set.
+    pub password: Option<String>,
+    /// The username for VeNCrypt plain authentication. Requires `password`
+    /// and `tls`.
+    pub username: Option<String>,
+    /// The TLS configuration. If set, clients must use VeNCrypt.
+    pub tls: Option<VncTls>,
+    /// The origins of the web pages allowed to connect over WebSocket.
+    pub allowed_origins: Vec<String>,
+}
+
+/// The TLS configuration for VeNCrypt.
+#[derive(MeshPayload, Clone)]
+pub struct VncTls {
+    /// The PEM-encoded certificate chain, leaf first.
+    pub cert_chain_pem: Vec<u8>,
+    /// The PEM-encoded private key.
+    pub key_pem: Vec<u8>,
 }
 
 pub const VNC_WORKER_TCP: WorkerId<VncParameters<TcpListener>> = WorkerId::new("VncWorkerTcp");